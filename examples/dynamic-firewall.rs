@@ -175,14 +175,14 @@ async fn on_inbound_request(
     let ReceiveRequest {
         peer,
         request,
-        response_tx,
+        responder,
         ..
     } = request;
     match request {
         Request::Ping => {
             println!("Received Ping from peer {}.", peer);
             // Send Pong back.
-            match response_tx.send(Response::Pong) {
+            match responder.respond(Response::Pong) {
                 Ok(()) => println!("Sent Pong back."),
                 Err(_) => println!("Sending Pong back failed."),
             }
@@ -193,7 +193,7 @@ async fn on_inbound_request(
                 stdin_input = stdin.next_line().fuse() => {
                     let line = stdin_input?.unwrap_or_default();
                     // Send response message back.
-                    match response_tx.send(Response::Message(line)) {
+                    match responder.respond(Response::Message(line)) {
                         Ok(()) => println!("Sent message back."),
                         Err(_) => println!("Sending message back failed.")
                     }
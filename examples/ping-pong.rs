@@ -59,11 +59,11 @@ async fn main() {
     // Bob receives the request and sends a response.
     let bob_recv_req = async {
         let ReceiveRequest {
-            response_tx: bob_response_tx,
+            responder: bob_responder,
             ..
         } = bob_request_rx.next().await.unwrap();
         println!("[Bob] Received Ping from Alice.");
-        bob_response_tx.send(Pong).unwrap();
+        bob_responder.respond(Pong).unwrap();
         println!("[Bob] Sending Pong back to Alice.");
     };
 
@@ -15,13 +15,59 @@
 
 #[doc(hidden)]
 mod addresses;
+#[doc(hidden)]
+mod backoff;
+#[doc(hidden)]
+mod blocks;
+#[doc(hidden)]
+mod event_log;
 pub mod firewall;
 #[doc(hidden)]
 mod handler;
 #[doc(hidden)]
+mod hello;
+#[doc(hidden)]
+mod idempotency;
+#[doc(hidden)]
+mod journal;
+#[doc(hidden)]
+mod limits;
+#[doc(hidden)]
+mod mailbox;
+#[doc(hidden)]
+mod middleware;
+#[doc(hidden)]
+mod oneshot;
+#[doc(hidden)]
+mod presence;
+#[doc(hidden)]
+mod queue;
+#[doc(hidden)]
+mod request_id;
+#[doc(hidden)]
 mod request_manager;
-pub use addresses::{assemble_relayed_addr, AddressInfo, PeerAddress};
-use firewall::{FirewallRequest, FirewallRules, FwRequest, Rule};
+mod responder;
+#[doc(hidden)]
+mod rotation;
+#[doc(hidden)]
+mod stats;
+#[doc(hidden)]
+mod transfer;
+pub use addresses::{
+    assemble_relay_chain_addr, assemble_relayed_addr, dialable_addr, AddressInfo, AddressScore, PeerAddress,
+    RelayPolicy,
+};
+use addresses::{relay_chain, relay_of};
+pub use backoff::DialBackoff;
+use backoff::DialBackoffs;
+pub use blocks::{block_hash, BlockErr, BlockExchangeConfig, BlockStore, MemoryBlockStore, MAX_BLOCK_SIZE};
+use blocks::{BlockExchange, BlockHandler, BlockHandlerEvent, BlockMessage};
+use event_log::EventLog;
+pub use event_log::{EventSource, RecentEvent};
+use firewall::{
+    capability::{CapabilityToken, CapabilityVerifier},
+    FirewallRequest, FirewallRules, FwRequest, Rule,
+};
 use futures::{
     channel::{
         mpsc::{self, SendError},
@@ -33,49 +79,124 @@ use futures::{
     task::{Context, Poll},
     FutureExt, StreamExt, TryFutureExt,
 };
-pub use handler::MessageProtocol;
-use handler::{Handler, HandlerInEvent, HandlerOutEvent};
+#[cfg(feature = "fuzzing")]
+pub use handler::fuzzing;
+use handler::DecodePool;
+pub use handler::{Codec, MessageMigrations, MessageProtocol, UnknownRequestType};
+pub(crate) use handler::{Handler, HandlerInEvent, HandlerOutEvent, RequestHeaders, SerializedRequest};
+pub use hello::Hello;
+use hello::{HelloHandler, HelloHandlerEvent, Hellos};
+use idempotency::IdempotencyCache;
+pub use idempotency::IdempotencyConfig;
+use journal::Journal;
+pub use journal::{FileJournal, JournalRecord, RequestJournal, ResponseStatus, UnansweredRequest};
 use libp2p::{
     core::{
         connection::{ConnectionId, ListenerId},
         either::EitherOutput,
+        upgrade::ProtocolName,
         ConnectedPoint, Multiaddr, PeerId,
     },
-    mdns::Mdns,
-    relay::v1::Relay,
+    identify::{Identify, IdentifyEvent},
+    ping::{Ping, PingEvent, PingFailure, PingSuccess},
     swarm::{
         behaviour::toggle::Toggle,
         dial_opts::{DialOpts, PeerCondition},
-        ConnectionHandler, IntoConnectionHandler, IntoConnectionHandlerSelect,
-        NetworkBehaviour as Libp2pNetworkBehaviour, NetworkBehaviourAction, NotifyHandler, PollParameters,
+        CloseConnection, ConnectionError, ConnectionHandler, DummyBehaviour, IntoConnectionHandler,
+        IntoConnectionHandlerSelect, NetworkBehaviour as Libp2pNetworkBehaviour, NetworkBehaviourAction, NotifyHandler,
+        PollParameters,
     },
 };
-use request_manager::{ApprovalStatus, BehaviourAction, RequestManager};
-use serde::{de::DeserializeOwned, Serialize};
+use limits::EvictionCandidate;
+pub use limits::{EstablishedLimits, ExcessConnectionPolicy, LimitPressurePolicy};
+
+#[cfg(feature = "mdns")]
+use libp2p::mdns::Mdns;
+#[cfg(feature = "metrics")]
+use libp2p::metrics::{Metrics, Recorder};
+#[cfg(feature = "relay")]
+use libp2p::relay::v1::Relay;
+
+// Placeholders if the protocols are not compiled in. They are never constructed, so the respective toggle is always
+// disabled.
+#[cfg(not(feature = "mdns"))]
+type Mdns = DummyBehaviour;
+#[cfg(not(feature = "relay"))]
+type Relay = DummyBehaviour;
+use crate::timer::{Delay, Instant};
+use mailbox::{unix_now, Mailbox, MailboxHandler, MailboxHandlerEvent, MailboxMessage};
+pub use mailbox::{MailboxConfig, MailboxDelivery};
+use middleware::MiddlewareChain;
+pub use middleware::{RequestMiddleware, ResponseHook};
+pub use presence::PresenceConfig;
+use presence::{Presence, PresenceChange, PresenceHandler, PresenceHandlerEvent};
+use queue::OutboundQueue;
+pub use queue::{EnqueueErr, FileQueueStore, OutboundQueueConfig, QueueStore, QueuedRequest};
+pub use request_id::{CounterIds, GlobalRequestId, NamespacedIds, RequestIdGenerator};
+pub(crate) use request_manager::{ApprovalStatus, BehaviourAction, RequestManager};
+pub use request_manager::{AwaitingFirewall, PendingRequests};
+pub use responder::Responder;
+use rotation::{Announcement, RotationHandler, RotationHandlerEvent, RotationMessage};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use smallvec::{smallvec, SmallVec};
+use stats::{ChurnTracker, TrafficStats};
+pub use stats::{FlapDetection, InboundFailureCounts, NetworkStats, OutboundFailureCounts, PeerStats, RelayStats};
 use std::{
-    collections::HashMap,
-    fmt,
-    sync::{
-        atomic::{AtomicU64, Ordering},
-        Arc,
-    },
+    collections::{HashMap, HashSet, VecDeque},
+    fmt, io, iter,
+    num::NonZeroU32,
+    sync::Arc,
     time::Duration,
 };
-use wasm_timer::Delay;
+use transfer::{InboundTransfer, OutboundTransfer, TransferHandler, TransferHandlerEvent, TransferMessage};
+pub use transfer::{TransferConfig, TransferFailure};
 
-type ProtoHandler<Rq, Rs> = IntoConnectionHandlerSelect<
+type ProtoHandler<Rq, Rs, TCustom> = IntoConnectionHandlerSelect<
     Handler<Rq, Rs>,
     IntoConnectionHandlerSelect<
         <Toggle<Mdns> as Libp2pNetworkBehaviour>::ConnectionHandler,
-        <Toggle<Relay> as Libp2pNetworkBehaviour>::ConnectionHandler,
+        IntoConnectionHandlerSelect<
+            <Toggle<Relay> as Libp2pNetworkBehaviour>::ConnectionHandler,
+            IntoConnectionHandlerSelect<InfoHandlers, MessageHandlers<TCustom>>,
+        >,
+    >,
+>;
+
+// Handlers for the protocols that exchange information about the connection and the remote peer.
+type InfoHandlers = IntoConnectionHandlerSelect<
+    <Toggle<Ping> as Libp2pNetworkBehaviour>::ConnectionHandler,
+    <Toggle<Identify> as Libp2pNetworkBehaviour>::ConnectionHandler,
+>;
+
+// Handlers for the protocols that exchange one-shot messages, and the handler of the custom behaviour.
+type MessageHandlers<TCustom> = IntoConnectionHandlerSelect<
+    MailboxHandler,
+    IntoConnectionHandlerSelect<
+        TransferHandler,
+        IntoConnectionHandlerSelect<
+            PresenceHandler,
+            IntoConnectionHandlerSelect<
+                BlockHandler,
+                IntoConnectionHandlerSelect<
+                    HelloHandler,
+                    IntoConnectionHandlerSelect<
+                        RotationHandler,
+                        <Toggle<TCustom> as Libp2pNetworkBehaviour>::ConnectionHandler,
+                    >,
+                >,
+            >,
+        >,
     >,
 >;
 
 // Future for a pending response to a sent `FirewallRequest::PeerSpecificRule`.
-type PendingPeerRuleRequest<TRq> = BoxFuture<'static, (PeerId, Option<Rule<TRq>>)>;
+pub(crate) type PendingPeerRuleRequest<TRq> = BoxFuture<'static, (PeerId, Option<Rule<TRq>>)>;
 // Future for a pending responses to a sent `FirewallRequest::RequestApproval`.
-type PendingApprovalRequest = BoxFuture<'static, (RequestId, bool)>;
+pub(crate) type PendingApprovalRequest = BoxFuture<'static, (RequestId, bool)>;
+// Future for the response of the application to a request with an idempotency key, together with the remote peer,
+// the key and the channel for sending the response to the remote.
+type PendingIdempotentResponse<Rs> =
+    BoxFuture<'static, (PeerId, String, Result<Rs, oneshot::Canceled>, oneshot::Sender<Rs>)>;
 
 const EMPTY_QUEUE_SHRINK_THRESHOLD: usize = 100;
 
@@ -86,11 +207,15 @@ const EMPTY_QUEUE_SHRINK_THRESHOLD: usize = 100;
 ///
 /// This allows sending request messages to remote peers, handling of inbound requests and failures, and additionally
 /// the configuration of a firewall to set permissions individually for different peers and request types.
-pub struct NetworkBehaviour<Rq, Rs, TRq = Rq>
+///
+/// An additional custom behaviour `TCustom` of the application can be composed into it, whose events are emitted as
+/// [`BehaviourEvent::Custom`].
+pub struct NetworkBehaviour<Rq, Rs, TRq = Rq, TCustom = DummyBehaviour>
 where
     Rq: RqRsMessage,
     Rs: RqRsMessage,
     TRq: FwRequest<Rq>,
+    TCustom: Libp2pNetworkBehaviour,
 {
     // Integrate Mdns protocol.
     mdns: Toggle<Mdns>,
@@ -98,20 +223,128 @@ where
     // Integrate Relay protocol.
     relay: Toggle<Relay>,
 
+    // Custom behaviour of the application.
+    custom: Toggle<TCustom>,
+
+    // Integrate Ping protocol.
+    ping: Toggle<Ping>,
+    // Round-trip time of the latest successful ping for each connected peer, and when it was measured.
+    latest_rtt: HashMap<PeerId, RttSample>,
+    // Consecutive missed keep-alive probes of each connected peer.
+    missed_probes: HashMap<PeerId, u32>,
+
+    // Integrate Identify protocol.
+    identify: Toggle<Identify>,
+    // Latest address that each connected peer observed for the local peer.
+    observed_addrs: HashMap<PeerId, Multiaddr>,
+    // Protocols that each connected peer advertised in its identify info.
+    remote_protocols: HashMap<PeerId, Vec<String>>,
+    // Connected peers that rejected a queued request in an envelope, to which it was sent again without envelope.
+    envelope_unsupported: HashSet<PeerId>,
+    // Observed address of the latest identify info, until the swarm processed it.
+    observed_addr_report: Option<ObservedAddrReport>,
+    // Recorder for the events of the ping and identify protocol, shared with the `EventLoop`.
+    #[cfg(feature = "metrics")]
+    metrics: Option<Arc<Metrics>>,
+    // Offset for the next relay in case of `RelaySelection::RoundRobin`.
+    next_relay: usize,
+    // Health of the dialing relays, used for ranking them.
+    relay_health: HashMap<PeerId, RelayHealth>,
+
     // Timeout and protocol configurations.
     config: ConfigConfig,
 
-    // ID assigned to the next request.
-    next_request_id: Arc<AtomicU64>,
+    // Generator for the ids of new requests.
+    next_request_id: Arc<dyn RequestIdGenerator>,
 
     // Manager for pending requests, their state and necessary actions.
     request_manager: RequestManager<Rq, Rs>,
+    // Limits for established connections.
+    connection_limits: EstablishedLimits,
+    // Connections that exceeded the limits and are being closed.
+    excess_connections: HashSet<ConnectionId>,
+    // Established connections that were evicted in favor of a new connection and are being closed.
+    evicted_connections: HashSet<ConnectionId>,
+    // Whether the number of inbound requests that await the firewall reached the high watermark, and did not fall to
+    // the low watermark since.
+    inbound_queue_high: bool,
+    // Reasons for connections that are being closed by the local peer.
+    close_reasons: HashMap<ConnectionId, CloseReason>,
+    // Connection that closed last. The failures for its pending requests are held back until the reason is resolved
+    // with the cause of the swarm event.
+    closed_connection: Option<ClosedConnection>,
+    // Priorities of peers for keeping their connections if the connection limits are exceeded.
+    peer_priorities: HashMap<PeerId, u8>,
+    // Priorities of peers with a tag, if the peer has no higher priority.
+    tag_priorities: HashMap<String, u8>,
+    // Reject approved inbound requests with `InboundFailure::Overloaded` instead of forwarding them.
+    inbound_paused: bool,
+    // Optional pool for decoding large messages, shared by all handlers.
+    decode_pool: Option<DecodePool>,
+    // Converters for messages of earlier protocol versions.
+    migrations: Option<Arc<MessageMigrations<Rq, Rs>>>,
+    // Codec for requests and responses, `None` if they are serialized as JSON.
+    codec: Option<Arc<dyn Codec<Rq, Rs>>>,
     // Address information and relay settings for known peers.
     addresses: AddressInfo,
+    // Counters for the exchanged messages with each peer.
+    traffic: TrafficStats,
+    // Recent connects of each peer to detect flapping peers.
+    churn: ChurnTracker,
+    // Backoff for dialing peers after failed dial attempts.
+    dial_backoffs: DialBackoffs,
+    // Peers that should be dialed once their backoff elapsed.
+    delayed_dials: HashSet<PeerId>,
+    // Expiry of the backoffs of the delayed dials.
+    dial_timeouts: FuturesUnordered<BoxFuture<'static, PeerId>>,
+    // Log of the most recent actions and swarm events, for debugging.
+    event_log: EventLog,
+    // Announcement of an identity rotation that is being sent to the connected peers.
+    rotation: Option<Announcement>,
     // Configuration of the firewall.
     // Each inbound request is checked, and only forwarded if the firewall configuration approves the request
     // for this peer.
     firewall: FirewallRules<TRq>,
+    // Verifier for capability tokens attached to inbound requests, evaluated before the firewall rules.
+    capability_verifier: Option<Arc<dyn CapabilityVerifier<TRq>>>,
+    // Middleware that is invoked on outbound and inbound requests.
+    middleware: MiddlewareChain<Rq, Rs>,
+
+    // Messages that are stored for offline peers, if the local peer acts as mailbox.
+    mailbox: Option<Mailbox>,
+    // Mailbox messages that are sent once a connection to the peer was established.
+    mailbox_outbox: HashMap<PeerId, Vec<MailboxMessage>>,
+    // Recipients of requests that were deposited in a mailbox and await a receipt.
+    pending_deposits: HashMap<RequestId, PeerId>,
+    // Expiry of the TTL of pending deposits.
+    deposit_timeouts: FuturesUnordered<BoxFuture<'static, RequestId>>,
+    // Outbound blob transfers that did not finish yet.
+    outbound_transfers: HashMap<RequestId, OutboundTransfer>,
+    // Inbound blob transfers that did not finish yet, with the peer and the id assigned by it.
+    inbound_transfers: HashMap<(PeerId, u64), InboundTransfer<Rq>>,
+    // Transfer messages that are sent once a connection to the peer was established.
+    transfer_outbox: HashMap<PeerId, Vec<TransferMessage>>,
+    // Status of the local peer and of the online peers, if heartbeats are exchanged.
+    presence: Option<Presence>,
+    // Local hello and the hellos of the connected peers, if hellos are exchanged.
+    hellos: Option<Hellos>,
+    // Blocks that are wanted from and provided to other peers, if the block exchange is enabled.
+    blocks: Option<BlockExchange>,
+    // Expiry of the wanted blocks.
+    block_timeouts: FuturesUnordered<BoxFuture<'static, Vec<u8>>>,
+    // Persistent queue for outbound requests to unreachable peers.
+    outbound_queue: Option<OutboundQueue>,
+    // Expiry of the TTL of queued requests.
+    queue_timeouts: FuturesUnordered<BoxFuture<'static, RequestId>>,
+    // Write-ahead journal of the inbound requests that were forwarded to the application.
+    journal: Option<Journal>,
+    // Cached responses for detecting retransmitted requests with the same idempotency key.
+    idempotency: Option<IdempotencyCache<Rs>>,
+    // Pending responses of the application to requests with an idempotency key.
+    idempotent_responses: FuturesUnordered<PendingIdempotentResponse<Rs>>,
+    // Actions for sending mailbox and transfer messages, and emitting the related events.
+    pending_actions:
+        VecDeque<NetworkBehaviourAction<BehaviourEvent<Rq, Rs, TCustom::OutEvent>, ProtoHandler<Rq, Rs, TCustom>>>,
 
     // Channel for firewall requests.
     // The channel is used if there is no rule set for a peer, or if the configuration demands individual approval for
@@ -127,19 +360,38 @@ where
 
     // Handles to pending approval requests. If the handle is dropped, the future is aborted.
     approval_rq_handles: HashMap<RequestId, oneshot::Sender<()>>,
+    // Channels for sending the reason to the remote if a received request is not answered, and the time at which
+    // the inbound substream times out.
+    remote_error_txs: HashMap<RequestId, (oneshot::Sender<RemoteError>, Instant)>,
+    // Inbound requests that were forwarded to the application and were neither answered nor failed yet.
+    awaiting_response: HashSet<RequestId>,
+    // Metadata, priority class and global id that the remote attached to received requests, until they are forwarded
+    // to the application.
+    request_metadata: HashMap<RequestId, RequestHeaders>,
+    // Local peer id and random id of the running instance, for scoping the ids of outbound requests.
+    session: Option<(PeerId, u64)>,
+    // Outbound requests whose status changes are reported, with the remote peer and the current status.
+    tracked_requests: HashMap<RequestId, (PeerId, RequestStatus, mpsc::UnboundedSender<RequestStatus>)>,
+    // Protocols that the local peer supports on inbound substreams, as reported by the swarm on the first poll.
+    supported_protocols: Vec<String>,
 }
 
-impl<Rq, Rs, TRq> NetworkBehaviour<Rq, Rs, TRq>
+impl<Rq, Rs, TRq, TCustom> NetworkBehaviour<Rq, Rs, TRq, TCustom>
 where
     Rq: RqRsMessage,
     Rs: RqRsMessage,
     TRq: FwRequest<Rq>,
+    TCustom: Libp2pNetworkBehaviour,
 {
     /// Create a new NetworkBehaviour for the libp2p swarm.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         config: ConfigConfig,
         mdns: Option<Mdns>,
         relay: Option<Relay>,
+        ping: Option<Ping>,
+        identify: Option<Identify>,
+        custom: Option<TCustom>,
         permission_req_channel: mpsc::Sender<FirewallRequest<TRq>>,
         firewall: FirewallRules<TRq>,
         address_info: Option<AddressInfo>,
@@ -147,26 +399,592 @@ where
         NetworkBehaviour {
             mdns: mdns.into(),
             relay: relay.into(),
+            custom: custom.into(),
+            ping: ping.into(),
+            latest_rtt: HashMap::new(),
+            missed_probes: HashMap::new(),
+            identify: identify.into(),
+            observed_addrs: HashMap::new(),
+            remote_protocols: HashMap::new(),
+            envelope_unsupported: HashSet::new(),
+            observed_addr_report: None,
+            #[cfg(feature = "metrics")]
+            metrics: None,
+            next_relay: 0,
+            relay_health: HashMap::new(),
+            inbound_paused: false,
+            decode_pool: config.decode_workers.map(DecodePool::new),
+            migrations: None,
+            codec: None,
+            event_log: EventLog::new(config.event_log_size),
+            churn: ChurnTracker::new(config.flap_detection),
+            dial_backoffs: DialBackoffs::new(config.dial_backoff),
+            delayed_dials: HashSet::new(),
+            dial_timeouts: FuturesUnordered::default(),
+            mailbox: config.mailbox.map(Mailbox::new),
+            mailbox_outbox: HashMap::new(),
+            pending_deposits: HashMap::new(),
+            deposit_timeouts: FuturesUnordered::default(),
+            outbound_transfers: HashMap::new(),
+            inbound_transfers: HashMap::new(),
+            transfer_outbox: HashMap::new(),
+            presence: config
+                .presence
+                .map(|c| Presence::new(c, config.presence_status.clone())),
+            hellos: config.hello.clone().map(Hellos::new),
+            blocks: None,
+            block_timeouts: FuturesUnordered::default(),
+            outbound_queue: None,
+            queue_timeouts: FuturesUnordered::default(),
+            journal: None,
+            idempotency: config.idempotency.map(IdempotencyCache::new),
+            idempotent_responses: FuturesUnordered::default(),
+            pending_actions: VecDeque::new(),
+            request_manager: RequestManager::new(&config),
+            connection_limits: EstablishedLimits::default(),
+            excess_connections: HashSet::new(),
+            evicted_connections: HashSet::new(),
+            inbound_queue_high: false,
+            close_reasons: HashMap::new(),
+            closed_connection: None,
+            peer_priorities: HashMap::new(),
+            tag_priorities: HashMap::new(),
             config,
-            next_request_id: Arc::new(AtomicU64::new(1)),
-            request_manager: RequestManager::new(),
+            next_request_id: Arc::new(CounterIds::default()),
             addresses: address_info.unwrap_or_default(),
+            traffic: TrafficStats::default(),
+            rotation: None,
             firewall,
+            capability_verifier: None,
+            middleware: MiddlewareChain::default(),
             permission_req_channel,
             pending_rule_rqs: FuturesUnordered::default(),
             rule_rq_handles: HashMap::new(),
             pending_approval_rqs: FuturesUnordered::default(),
             approval_rq_handles: HashMap::new(),
+            remote_error_txs: HashMap::new(),
+            awaiting_response: HashSet::new(),
+            request_metadata: HashMap::new(),
+            session: None,
+            tracked_requests: HashMap::new(),
+            supported_protocols: Vec::new(),
         }
     }
 
     /// Send a new request to a remote peer.
     pub fn send_request(&mut self, peer: PeerId, request: Rq) -> RequestId {
+        let request = self
+            .intercept_outbound(&peer, request)
+            .and_then(|r| self.serialize_request(&r));
+        self.send_serialized_request(peer, request)
+    }
+
+    // Encode a request that is sent without envelope, with the codec if one is set.
+    fn serialize_request(&self, request: &Rq) -> Result<SerializedRequest, OutboundFailure> {
+        match self.codec.as_ref() {
+            Some(codec) => codec
+                .encode_request(request)
+                .map(SerializedRequest::encoded)
+                .map_err(|_| OutboundFailure::InvalidRequest),
+            None => SerializedRequest::new(request).map_err(|_| OutboundFailure::InvalidRequest),
+        }
+    }
+
+    /// Send the same request to multiple remote peers.
+    /// The request is only serialized once, and the bytes are shared between the connections.
+    /// If middleware is registered, each peer gets a copy of the request instead, so that the middleware can modify
+    /// it per peer.
+    ///
+    /// Returns the ids of the requests in the order of the peers.
+    pub fn send_request_to_peers(&mut self, peers: Vec<PeerId>, request: Rq) -> Vec<RequestId> {
+        if !self.middleware.is_empty() {
+            let bytes = serde_json::to_vec(&request).ok();
+            return peers
+                .into_iter()
+                .map(
+                    |peer| match bytes.as_ref().and_then(|b| serde_json::from_slice(b).ok()) {
+                        Some(request) => self.send_request(peer, request),
+                        None => self.send_serialized_request(peer, Err(OutboundFailure::InvalidRequest)),
+                    },
+                )
+                .collect();
+        }
+        let request = self.serialize_request(&request);
+        peers
+            .into_iter()
+            .map(|peer| self.send_serialized_request(peer, request.clone()))
+            .collect()
+    }
+
+    /// Send a new request to a remote peer, together with a capability token that authorizes the request.
+    pub fn send_request_with_capability(
+        &mut self,
+        peer: PeerId,
+        request: Rq,
+        capability: &CapabilityToken,
+    ) -> RequestId {
+        let request = self.intercept_outbound(&peer, request).and_then(|r| {
+            SerializedRequest::with_envelope(&r, Some(capability), None, None, self.codec.as_deref())
+                .map_err(|_| OutboundFailure::InvalidRequest)
+        });
+        self.send_serialized_request(peer, request)
+    }
+
+    /// Send a new request to a remote peer, together with an idempotency key.
+    ///
+    /// If the remote receives multiple requests with the same key from the local peer, e.g. because a request is
+    /// retried after a failure, only the first one is forwarded to its application. The others are answered with the
+    /// same response.
+    pub fn send_request_with_idempotency_key(&mut self, peer: PeerId, request: Rq, key: &str) -> RequestId {
+        let request = self.intercept_outbound(&peer, request).and_then(|r| {
+            SerializedRequest::with_envelope(&r, None, Some(key), None, self.codec.as_deref())
+                .map_err(|_| OutboundFailure::InvalidRequest)
+        });
+        self.send_serialized_request(peer, request)
+    }
+
+    /// Send a new request to a remote peer, together with metadata that is exposed to the remote application
+    /// alongside the request, e.g. tracing ids, tenancy information or auth material.
+    ///
+    /// The remote has to support the envelope variant of the protocol, otherwise the request fails with
+    /// [`OutboundFailure::UnsupportedProtocols`].
+    ///
+    /// Requests with a higher `priority` are sent before requests of a lower priority class that are queued for the
+    /// same connection. The priority is also exposed to the remote, together with the [`GlobalRequestId`] of the
+    /// request if the session was set.
+    pub fn send_request_with_metadata(
+        &mut self,
+        peer: PeerId,
+        request: Rq,
+        metadata: &RequestMetadata,
+        priority: RequestPriority,
+    ) -> RequestId {
+        let request_id = RequestId::next(&self.next_request_id);
+        let origin = self.global_request_id(request_id);
+        let request = self.intercept_outbound(&peer, request).and_then(|r| {
+            let headers = Some((metadata, priority, origin));
+            SerializedRequest::with_envelope(&r, None, None, headers, self.codec.as_deref())
+                .map_err(|_| OutboundFailure::InvalidRequest)
+        });
+        self.send_serialized_request_with_id(peer, request_id, request);
+        request_id
+    }
+
+    /// Set the local peer id and the random id of the running instance, which scope the ids of outbound requests
+    /// that are sent with metadata.
+    pub fn set_session(&mut self, local_peer: PeerId, session: u64) {
+        self.session = Some((local_peer, session));
+    }
+
+    // Scope the id of an outbound request to the local peer and running instance.
+    fn global_request_id(&self, id: RequestId) -> Option<GlobalRequestId> {
+        self.session
+            .map(|(origin, session)| GlobalRequestId { origin, session, id })
+    }
+
+    /// Deposit a request for the recipient in a mailbox, from where it is forwarded once the recipient connects to
+    /// the mailbox.
+    ///
+    /// The delivery status is reported with a [`BehaviourEvent::MailboxReceipt`]. If no receipt is received within
+    /// the `ttl`, the request is considered as [`MailboxDelivery::Expired`].
+    pub fn send_via_mailbox(&mut self, mailbox: PeerId, recipient: PeerId, request: Rq, ttl: Duration) -> RequestId {
+        let request_id = RequestId::next(&self.next_request_id);
+        let request = match serde_json::to_string(&request) {
+            Ok(r) => r,
+            Err(_) => {
+                let event = BehaviourEvent::MailboxReceipt {
+                    request_id,
+                    recipient,
+                    status: MailboxDelivery::Rejected,
+                };
+                self.pending_actions
+                    .push_back(NetworkBehaviourAction::GenerateEvent(event));
+                return request_id;
+            }
+        };
+        let message = MailboxMessage::Deposit {
+            id: request_id.value(),
+            recipient,
+            expires_at: unix_now() + ttl.as_secs(),
+            request,
+        };
+        self.pending_deposits.insert(request_id, recipient);
+        self.deposit_timeouts
+            .push(Delay::new(ttl).map(move |_| request_id).boxed());
+        let _ = self.send_mailbox_message(mailbox, message, true);
+        request_id
+    }
+
+    /// Send a blob to a remote peer in chunks. The `metadata` describes the blob, and is checked by the firewall of
+    /// the recipient like any request.
+    ///
+    /// The progress and outcome of the transfer are reported with [`BehaviourEvent::TransferProgress`] and
+    /// [`BehaviourEvent::TransferFinished`].
+    pub fn send_blob(&mut self, peer: PeerId, metadata: Rq, data: Vec<u8>) -> RequestId {
+        let transfer_id = RequestId::next(&self.next_request_id);
+        match serde_json::to_string(&metadata) {
+            Ok(metadata) => {
+                let transfer = OutboundTransfer::new(peer, metadata, data);
+                let offer = transfer.offer(transfer_id.value());
+                self.outbound_transfers.insert(transfer_id, transfer);
+                self.send_transfer_message(peer, offer);
+            }
+            Err(_) => self.finish_transfer(peer, transfer_id, Err(TransferFailure::InvalidRequest)),
+        }
+        transfer_id
+    }
+
+    /// Resume an outbound transfer that failed with [`TransferFailure::Interrupted`].
+    /// The recipient continues from the data that it already received.
+    ///
+    /// Returns `false` if there is no interrupted transfer with this id.
+    pub fn resume_transfer(&mut self, transfer_id: RequestId) -> bool {
+        let transfer = match self.outbound_transfers.get_mut(&transfer_id) {
+            Some(t) if t.is_interrupted => t,
+            _ => return false,
+        };
+        transfer.is_interrupted = false;
+        let peer = transfer.peer;
+        let offer = transfer.offer(transfer_id.value());
+        self.send_transfer_message(peer, offer);
+        true
+    }
+
+    /// Abort an outbound transfer. The recipient drops the data that it received so far.
+    pub fn cancel_transfer(&mut self, transfer_id: RequestId) {
+        if let Some(transfer) = self.outbound_transfers.remove(&transfer_id) {
+            if self.request_manager.connected_peers().contains(&transfer.peer) {
+                let cancel = TransferMessage::Cancel {
+                    id: transfer_id.value(),
+                };
+                self.send_transfer_message(transfer.peer, cancel);
+            }
+        }
+    }
+
+    /// Enable the exchange of blocks with other peers. The blocks in the `store` are provided to connected peers that
+    /// want them.
+    pub fn set_block_exchange(&mut self, config: BlockExchangeConfig, store: Box<dyn BlockStore>) {
+        self.blocks = Some(BlockExchange::new(config, store));
+    }
+
+    /// Store a block and provide it to other peers, including those that already asked for it.
+    ///
+    /// Returns the hash of the block.
+    pub fn put_block(&mut self, data: Vec<u8>) -> Result<Vec<u8>, BlockErr> {
+        let blocks = self.blocks.as_mut().ok_or(BlockErr::NotEnabled)?;
+        if data.len() > MAX_BLOCK_SIZE {
+            return Err(BlockErr::TooLarge);
+        }
+        let message = BlockMessage::Block {
+            data: base64::encode(&data),
+        };
+        let (hash, peers) = blocks.insert(data);
+        for peer in peers {
+            self.send_block_message(peer, message.clone());
+        }
+        Ok(hash)
+    }
+
+    /// Get a block from the local store, or ask the connected peers for it.
+    ///
+    /// Returns `None` if the block is not stored locally. Once a peer provided the block, it is stored and a
+    /// [`BehaviourEvent::BlockReceived`] is emitted. If no peer provided it within the want timeout, a
+    /// [`BehaviourEvent::BlockWantExpired`] is emitted.
+    pub fn want_block(&mut self, hash: Vec<u8>) -> Result<Option<Vec<u8>>, BlockErr> {
+        let blocks = self.blocks.as_mut().ok_or(BlockErr::NotEnabled)?;
+        if let Some(data) = blocks.get(&hash) {
+            return Ok(Some(data));
+        }
+        if blocks.want(hash.clone()) {
+            let timeout = blocks.want_timeout();
+            self.block_timeouts.push(Delay::new(timeout).map(move |_| hash).boxed());
+            for peer in self.request_manager.connected_peers() {
+                self.send_want_list(peer);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Enable the outbound queue, and send the requests that were loaded from the `store`.
+    pub fn set_outbound_queue(&mut self, config: OutboundQueueConfig, store: Box<dyn QueueStore>) -> io::Result<()> {
+        let mut queue = OutboundQueue::new(config, store);
+        let requests = queue.load()?;
+        // Don't reuse the ids of the loaded requests for new requests.
+        if let Some(max_id) = requests.iter().map(|r| r.id.value()).max() {
+            self.next_request_id.skip_to(max_id);
+        }
+        self.outbound_queue = Some(queue);
+        for request in requests {
+            self.schedule_queue_expiry(request.id, request.expires_at);
+            self.send_queued_request(request.id);
+        }
+        Ok(())
+    }
+
+    /// Add a request to the outbound queue. The request is persisted, and sent again each time the peer connects,
+    /// until a response was received or the TTL expired.
+    pub fn enqueue_request(&mut self, peer: PeerId, request: Rq, ttl: Duration) -> Result<RequestId, EnqueueErr> {
+        let queue = self.outbound_queue.as_mut().ok_or(EnqueueErr::NotEnabled)?;
+        let request = serde_json::to_string(&request).map_err(|_| EnqueueErr::InvalidRequest)?;
+        let ttl = ttl.min(queue.config().max_ttl);
+        let id = RequestId::next(&self.next_request_id);
+        let expires_at = unix_now() + ttl.as_secs();
+        let queued = QueuedRequest {
+            id,
+            peer,
+            request,
+            expires_at,
+        };
+        queue.insert(queued)?;
+        self.schedule_queue_expiry(id, expires_at);
+        self.send_queued_request(id);
+        Ok(id)
+    }
+
+    fn schedule_queue_expiry(&mut self, id: RequestId, expires_at: u64) {
+        let ttl = Duration::from_secs(expires_at.saturating_sub(unix_now()));
+        self.queue_timeouts.push(Delay::new(ttl).map(move |_| id).boxed());
+    }
+
+    // Send a queued request. If the peer is not connected, it is dialed.
+    fn send_queued_request(&mut self, id: RequestId) {
+        let queued = match self.outbound_queue.as_ref().and_then(|q| q.get(&id)) {
+            Some(queued) => queued,
+            None => return,
+        };
+        let peer = queued.peer;
+        let key = format!("queued-{}-{}", id, queued.expires_at);
+        let request = match serde_json::from_str::<Rq>(&queued.request) {
+            Ok(request) => request,
+            Err(_) => return self.fail_queued_request(id, OutboundFailure::InvalidRequest),
+        };
+        // The request is retried, so that the remote has to detect duplicates with the idempotency key. Remotes that
+        // don't support the envelope for the key get the plain request.
+        let sent_id = match self.supports_envelope(&peer) {
+            true => self.send_request_with_idempotency_key(peer, request, &key),
+            false => self.send_request(peer, request),
+        };
+        if let Some(queue) = self.outbound_queue.as_mut() {
+            queue.set_in_flight(sent_id, id);
+        }
+    }
+
+    // Whether the remote supports the envelope variant of a local protocol. Assumed if the remote did not advertise
+    // its protocols, until it rejected a request in an envelope.
+    fn supports_envelope(&self, peer: &PeerId) -> bool {
+        if self.envelope_unsupported.contains(peer) {
+            return false;
+        }
+        let remote_protocols = match self.remote_protocols.get(peer) {
+            Some(protocols) => protocols,
+            None => return true,
+        };
+        self.config.supported_protocols.iter().any(|p| {
+            let protocol = match self.codec.as_ref() {
+                Some(codec) => p.with_codec(codec.name()).with_envelope(),
+                None => p.with_envelope(),
+            };
+            remote_protocols
+                .iter()
+                .any(|r| r.as_bytes() == protocol.protocol_name())
+        })
+    }
+
+    fn fail_queued_request(&mut self, id: RequestId, failure: OutboundFailure) {
+        if let Some(queued) = self.outbound_queue.as_mut().and_then(|q| q.remove(&id)) {
+            let event = BehaviourEvent::QueuedResponse {
+                request_id: id,
+                peer: queued.peer,
+                result: Err(failure),
+            };
+            self.pending_actions
+                .push_back(NetworkBehaviourAction::GenerateEvent(event));
+        }
+    }
+
+    // Handle the failure of a sent queued request.
+    // Unless the failure is permanent or the TTL expired, the request stays in the queue and is sent again once the
+    // peer connects.
+    fn on_queued_request_failure(&mut self, id: RequestId, failure: OutboundFailure) {
+        let (peer, is_expired) = match self.outbound_queue.as_ref().and_then(|q| q.get(&id)) {
+            Some(queued) => (queued.peer, queued.expires_at <= unix_now()),
+            None => return,
+        };
+        let failure = match failure {
+            _ if is_expired => OutboundFailure::Expired,
+            // The remote rejected the envelope with the idempotency key, send the request again without it.
+            OutboundFailure::UnsupportedProtocols { .. } if self.supports_envelope(&peer) => {
+                self.envelope_unsupported.insert(peer);
+                return self.send_queued_request(id);
+            }
+            OutboundFailure::UnsupportedProtocols { .. }
+            | OutboundFailure::InvalidRequest
+            | OutboundFailure::Intercepted(_)
+            | OutboundFailure::Aborted
+            | OutboundFailure::Remote(RemoteError::NotPermitted)
+            | OutboundFailure::Remote(RemoteError::UpgradeRequired { .. })
+            | OutboundFailure::Remote(RemoteError::UnknownRequest) => failure,
+            _ => return,
+        };
+        self.fail_queued_request(id, failure);
+    }
+
+    /// Enable the request journal. The journal is loaded to find the requests that were never answered by a former
+    /// running instance.
+    pub fn set_request_journal(&mut self, store: Box<dyn RequestJournal>) -> io::Result<()> {
+        let journal = Journal::open(store)?;
+        // Don't reuse the ids of unanswered requests for new requests.
+        if let Some(max_id) = journal.max_request_id() {
+            self.next_request_id.skip_to(max_id.value());
+        }
+        self.journal = Some(journal);
+        Ok(())
+    }
+
+    /// Requests that were accepted by a former running instance but never answered, according to the journal.
+    /// Requests that can not be deserialized anymore are skipped.
+    pub fn unanswered_requests(&self) -> Vec<UnansweredRequest<Rq>> {
+        let journal = match self.journal.as_ref() {
+            Some(journal) => journal,
+            None => return Vec::new(),
+        };
+        journal
+            .unanswered()
+            .filter_map(|(request_id, (peer, request))| {
+                let request = serde_json::from_str(request).ok()?;
+                Some(UnansweredRequest {
+                    request_id: *request_id,
+                    peer: *peer,
+                    request,
+                })
+            })
+            .collect()
+    }
+
+    /// Mark an unanswered request of a former running instance as reconciled, so that it is removed from the journal.
+    pub fn reconcile_request(&mut self, request_id: RequestId) -> io::Result<()> {
+        match self.journal.as_mut() {
+            Some(journal) => journal.on_completed(request_id, ResponseStatus::Reconciled),
+            None => Ok(()),
+        }
+    }
+
+    // Write-ahead the accepted request to the journal. Failures of the journal don't block the request.
+    fn journal_accepted(&mut self, request_id: RequestId, peer: PeerId, request: &Rq) {
+        let journal = match self.journal.as_mut() {
+            Some(journal) => journal,
+            None => return,
+        };
+        let request = serde_json::to_string(request).unwrap_or_default();
+        if let Err(e) = journal.on_accepted(request_id, peer, request) {
+            self.event_log.record(EventSource::Behaviour, || {
+                format!("Failed to journal request {}: {}", request_id, e)
+            });
+        }
+    }
+
+    fn journal_completed(&mut self, request_id: RequestId, status: ResponseStatus) {
+        let journal = match self.journal.as_mut() {
+            Some(journal) => journal,
+            None => return,
+        };
+        if let Err(e) = journal.on_completed(request_id, status) {
+            self.event_log.record(EventSource::Behaviour, || {
+                format!("Failed to journal response {}: {}", request_id, e)
+            });
+        }
+    }
+
+    // Log the failures of the thread that writes the journal.
+    fn log_journal_errors(&mut self) {
+        let errors = match self.journal.as_mut() {
+            Some(journal) => journal.take_errors(),
+            None => return,
+        };
+        for e in errors {
+            self.event_log.record(EventSource::Behaviour, || {
+                format!("Failed to write the request journal: {}", e)
+            });
+        }
+    }
+
+    /// Set the status that is sent to connected peers in heartbeats, and send it to them immediately.
+    /// Does nothing if presence is not enabled.
+    pub fn set_presence_status(&mut self, status: Vec<u8>) {
+        if let Some(presence) = self.presence.as_mut() {
+            presence.set_status(status);
+            for peer in self.request_manager.connected_peers() {
+                self.send_heartbeat(peer, NotifyHandler::Any);
+            }
+        }
+    }
+
+    /// Peers that are online, with their latest status.
+    pub fn online_peers(&self) -> HashMap<PeerId, Vec<u8>> {
+        self.presence.as_ref().map(|p| p.online_peers()).unwrap_or_default()
+    }
+
+    /// The hello that the peer sent after the connection was established, `None` if it did not send one (yet), or
+    /// hellos are not enabled.
+    pub fn peer_hello(&self, peer: &PeerId) -> Option<Hello> {
+        self.hellos.as_ref().and_then(|h| h.get(peer)).cloned()
+    }
+
+    fn send_serialized_request(
+        &mut self,
+        peer: PeerId,
+        request: Result<SerializedRequest, OutboundFailure>,
+    ) -> RequestId {
         let request_id = RequestId::next(&self.next_request_id);
-        self.request_manager.on_new_out_request(peer, request_id, request);
+        self.send_serialized_request_with_id(peer, request_id, request);
         request_id
     }
 
+    fn send_serialized_request_with_id(
+        &mut self,
+        peer: PeerId,
+        request_id: RequestId,
+        request: Result<SerializedRequest, OutboundFailure>,
+    ) {
+        match request {
+            Ok(request) => self.request_manager.on_new_out_request(peer, request_id, request),
+            Err(failure) => self.request_manager.on_res_for_outbound(peer, request_id, Err(failure)),
+        }
+    }
+
+    // Run the middleware on an outbound request before it is serialized.
+    fn intercept_outbound(&self, peer: &PeerId, mut request: Rq) -> Result<Rq, OutboundFailure> {
+        self.middleware
+            .on_outbound(peer, &mut request)
+            .map_err(OutboundFailure::Intercepted)?;
+        Ok(request)
+    }
+
+    /// Report the status changes of a sent outbound request through the `status_tx` channel.
+    /// The channel is closed once the request completed.
+    pub fn track_request(
+        &mut self,
+        peer: PeerId,
+        request_id: RequestId,
+        status_tx: mpsc::UnboundedSender<RequestStatus>,
+    ) {
+        self.tracked_requests
+            .insert(request_id, (peer, RequestStatus::Queued, status_tx));
+    }
+
+    // Report the new status of a tracked request. Final status remove the request from the tracked ones.
+    fn update_request_status(&mut self, request_id: &RequestId, status: RequestStatus) {
+        if status.is_final() {
+            if let Some((_, _, status_tx)) = self.tracked_requests.remove(request_id) {
+                let _ = status_tx.unbounded_send(status);
+            }
+        } else if let Some((_, current, status_tx)) = self.tracked_requests.get_mut(request_id) {
+            *current = status.clone();
+            let _ = status_tx.unbounded_send(status);
+        }
+    }
+
     /// Get the current default  for the firewall.
     pub fn get_firewall_config(&self) -> &FirewallRules<TRq> {
         &self.firewall
@@ -208,6 +1026,60 @@ where
         self.handle_updated_peer_rule(peer);
     }
 
+    /// Set the rule for peers with the tag.
+    /// It takes precedence over the default rule, but not over peer specific rules. If a peer has multiple tags with
+    /// a rule, the rule of the first tag in alphabetical order applies.
+    pub fn set_tag_rule(&mut self, tag: String, rule: Rule<TRq>) {
+        let peers = self.addresses.peers_with_tag(&tag);
+        self.firewall.set_tag_rule(tag, rule);
+        peers.into_iter().for_each(|peer| self.handle_updated_peer_rule(peer));
+    }
+
+    /// Remove the rule for peers with the tag.
+    pub fn remove_tag_rule(&mut self, tag: &str) {
+        self.firewall.remove_tag_rule(tag);
+        let peers = self.addresses.peers_with_tag(tag);
+        peers.into_iter().for_each(|peer| self.handle_updated_peer_rule(peer));
+    }
+
+    /// Set the rule for inbound requests of a priority class.
+    /// For these requests, it takes precedence over the peer specific and default rules.
+    pub fn set_priority_rule(&mut self, priority: RequestPriority, rule: Rule<TRq>) {
+        self.firewall.set_priority_rule(priority, rule);
+        self.update_inbound_support();
+    }
+
+    /// Remove the rule for a priority class, which will result in using the peer specific or default rules for
+    /// requests of that class.
+    pub fn remove_priority_rule(&mut self, priority: RequestPriority) {
+        self.firewall.remove_priority_rule(priority);
+        self.update_inbound_support();
+    }
+
+    /// Approve or reject all inbound requests from the peer that currently await an individual approval, and abort
+    /// the pending [`FirewallRequest::RequestApproval`]s for them.
+    ///
+    /// If a `rule` is given, it is set as peer specific rule first. Requests that awaited a rule for the peer are
+    /// handled according to the new rule, and the decision is applied to all requests that still await approval.
+    /// Returns the ids of the requests to which the decision was applied.
+    pub fn resolve_pending_approvals(
+        &mut self,
+        peer: PeerId,
+        is_allowed: bool,
+        rule: Option<Rule<TRq>>,
+    ) -> Vec<RequestId> {
+        if let Some(rule) = rule {
+            self.set_peer_rule(peer, rule);
+        }
+        let requests = self.request_manager.awaiting_approval_from(&peer);
+        for request_id in &requests {
+            // Abort firewall request for approval.
+            let _ = self.approval_rq_handles.remove(request_id);
+            self.request_manager.on_request_approval(*request_id, is_allowed);
+        }
+        requests
+    }
+
     /// Add an address for the remote peer.
     pub fn add_address(&mut self, peer: PeerId, address: Multiaddr) {
         self.addresses.add_addrs(peer, address);
@@ -218,73 +1090,635 @@ where
         self.addresses.remove_address(peer, address);
     }
 
+    /// Remove all known addresses of a remote peer.
+    pub fn remove_peer_addresses(&mut self, peer: &PeerId) {
+        self.addresses.remove_peer(peer);
+    }
+
+    /// Attach a tag to the peer, e.g. its role.
+    pub fn tag_peer(&mut self, peer: PeerId, tag: String) {
+        let has_rule = self.firewall.get_tag_rule(&tag).is_some();
+        if self.addresses.add_tag(peer, tag) && has_rule {
+            self.handle_updated_peer_rule(peer);
+        }
+    }
+
+    /// Remove a tag from the peer.
+    pub fn untag_peer(&mut self, peer: &PeerId, tag: &str) {
+        if self.addresses.remove_tag(peer, tag) && self.firewall.get_tag_rule(tag).is_some() {
+            self.handle_updated_peer_rule(*peer);
+        }
+    }
+
+    /// Tags of the peer in alphabetical order.
+    pub fn peer_tags(&self, peer: &PeerId) -> Vec<String> {
+        self.addresses.tags(peer).map(String::from).collect()
+    }
+
+    /// All peers that have the tag.
+    pub fn peers_with_tag(&self, tag: &str) -> Vec<PeerId> {
+        self.addresses.peers_with_tag(tag)
+    }
+
     /// Export collected info about known relays and peer addresses.
     pub fn export_address_info(&self) -> AddressInfo {
         self.addresses.clone()
     }
 
+    /// Announce to the connected peers that the local peer rotated its identity to `new_peer`, which can be reached
+    /// at the `addrs`. Peers whose firewall rule rejects all requests are skipped.
+    ///
+    /// [`BehaviourEvent::RotationAnnounced`] is emitted once the announcement was sent to all peers. A pending
+    /// announcement is replaced.
+    pub fn announce_rotation(&mut self, new_peer: PeerId, addrs: Vec<Multiaddr>) {
+        let peers: HashSet<PeerId> = self
+            .request_manager
+            .connected_peers()
+            .into_iter()
+            .filter(|peer| !matches!(self.effective_rule(peer), Some(Rule::RejectAll)))
+            .collect();
+        if peers.is_empty() {
+            self.rotation = None;
+            let event = BehaviourEvent::RotationAnnounced { peers: Vec::new() };
+            self.pending_actions
+                .push_back(NetworkBehaviourAction::GenerateEvent(event));
+            return;
+        }
+        for peer in peers.iter() {
+            let message = RotationMessage {
+                new_peer,
+                addrs: addrs.clone(),
+            };
+            let event = EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(
+                EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(
+                    EitherOutput::Second(EitherOutput::First(message)),
+                )))),
+            ))));
+            self.pending_actions.push_back(NetworkBehaviourAction::NotifyHandler {
+                peer_id: *peer,
+                handler: NotifyHandler::Any,
+                event,
+            });
+        }
+        self.rotation = Some(Announcement::new(peers));
+    }
+
     /// Get currently established connections.
     pub fn established_connections(&self) -> Vec<(PeerId, Vec<ConnectedPoint>)> {
         self.request_manager.established_connections()
     }
 
-    /// Whether the relay protocol is enabled.
-    pub fn is_relay_enabled(&self) -> bool {
-        self.relay.is_enabled()
+    /// Names of the protocols that the local peer supports.
+    /// Empty until the behaviour was polled for the first time.
+    pub fn supported_protocols(&self) -> &[String] {
+        &self.supported_protocols
     }
 
-    /// Add a relay to the list of relays that may be tried to use if a remote peer can not be reached directly.
-    pub fn add_dialing_relay(
-        &mut self,
-        peer: PeerId,
-        address: Option<Multiaddr>,
-    ) -> Result<Option<Multiaddr>, RelayNotSupported> {
-        if !self.is_relay_enabled() {
-            return Err(RelayNotSupported);
+    /// Set the limits for established connections.
+    ///
+    /// New connections that exceed the limits are closed right after they were established. Established connections
+    /// that exceed the new limits are handled according to the `policy`.
+    pub fn set_connection_limits(&mut self, limits: EstablishedLimits, policy: ExcessConnectionPolicy) {
+        self.connection_limits = limits;
+        let excess = self
+            .connection_limits
+            .excess(self.request_manager.connections(), policy);
+        for (peer, connection) in excess {
+            self.close_connection(peer, connection, CloseReason::ConnectionLimit);
         }
-        Ok(self.addresses.add_relay(peer, address))
     }
 
-    /// Remove a relay from the list of dialing relays.
-    /// Returns `false` if the peer was not among the known relays.
+    /// Set the priority of a peer for keeping its connections if a new connection exceeds the connection limits.
+    /// Peers without priority have the priority `0`.
     ///
-    /// **Note**: Known relayed addresses for remote peers using this relay will not be influenced by this.
-    pub fn remove_dialing_relay(&mut self, peer: &PeerId) -> bool {
-        self.addresses.remove_relay(peer)
+    /// See [`LimitPressurePolicy::EvictLowestPriority`].
+    pub fn set_peer_priority(&mut self, peer: PeerId, priority: u8) {
+        self.peer_priorities.insert(peer, priority);
     }
 
-    /// Configure whether it should be attempted to reach the remote via known relays, if it can not be reached via
-    /// known addresses.
-    pub fn set_relay_fallback(&mut self, peer: PeerId, use_relay_fallback: bool) -> Result<(), RelayNotSupported> {
-        if !self.is_relay_enabled() {
-            return Err(RelayNotSupported);
-        }
-        self.addresses.set_relay_fallback(peer, use_relay_fallback);
-        Ok(())
+    /// Remove the priority of a peer, which will result in the priority `0`.
+    pub fn remove_peer_priority(&mut self, peer: &PeerId) {
+        self.peer_priorities.remove(peer);
     }
 
-    /// Dial the target via the specified relay.
-    /// The `is_exclusive` parameter specifies whether other known relays should be used if using the set relay is not
-    /// successful.
-    ///
-    /// Returns the relayed address of the local peer (`<relay-addr>/<relay-id>/p2p-circuit/<local-id>),
-    /// if an address for the relay is known.
-    pub fn use_specific_relay(
-        &mut self,
-        target: PeerId,
-        relay: PeerId,
-        is_exclusive: bool,
+    /// Set the priority of peers with the tag for keeping their connections.
+    /// A peer has the highest of its own priority and the priorities of its tags.
+    pub fn set_tag_priority(&mut self, tag: String, priority: u8) {
+        self.tag_priorities.insert(tag, priority);
+    }
+
+    /// Remove the priority of peers with the tag.
+    pub fn remove_tag_priority(&mut self, tag: &str) {
+        self.tag_priorities.remove(tag);
+    }
+
+    // Priority of a peer for keeping its connections, which is the highest of its own and its tags' priorities.
+    fn peer_priority(&self, peer: &PeerId) -> u8 {
+        let tag_priorities = self
+            .addresses
+            .tags(peer)
+            .filter_map(|tag| self.tag_priorities.get(tag).copied());
+        self.peer_priorities
+            .get(peer)
+            .copied()
+            .into_iter()
+            .chain(tag_priorities)
+            .max()
+            .unwrap_or_default()
+    }
+
+    // Effective firewall rule for the peer, which considers the rules for its tags.
+    fn effective_rule(&self, peer: &PeerId) -> Option<&Rule<TRq>> {
+        self.firewall.get_effective_rule_tagged(peer, self.addresses.tags(peer))
+    }
+
+    // Established connections that are not being closed because of the connection limits.
+    fn counted_connections(&self) -> Vec<(PeerId, ConnectionId, bool)> {
+        let mut connections = self.request_manager.connections();
+        connections.retain(|(_, c, _)| !self.evicted_connections.contains(c));
+        connections
+    }
+
+    // Handle a new connection that exceeds the connection limits according to the `LimitPressurePolicy`.
+    // Returns `true` if an established connection of a lower priority was evicted in favor of the new connection.
+    fn evict_for(&mut self, peer: PeerId, is_incoming: bool) -> bool {
+        if self.config.limit_pressure_policy == LimitPressurePolicy::Reject {
+            return false;
+        }
+        let priority = |p: &PeerId| self.peer_priority(p);
+        let candidates = self
+            .counted_connections()
+            .into_iter()
+            .map(|(p, connection, is_incoming)| EvictionCandidate {
+                peer: p,
+                connection,
+                is_incoming,
+                priority: priority(&p),
+                success_rate: self.traffic.peer(&p).and_then(PeerStats::success_rate),
+                last_active: self.traffic.last_active(&connection).unwrap_or_else(Instant::now),
+            })
+            .collect();
+        let evicted = self
+            .connection_limits
+            .eviction_candidate(candidates, &peer, is_incoming, priority(&peer));
+        match evicted {
+            Some((evicted_peer, connection)) => {
+                self.close_connection(evicted_peer, connection, CloseReason::ConnectionLimit);
+                self.evicted_connections.insert(connection);
+                self.pending_actions.push_back(NetworkBehaviourAction::GenerateEvent(
+                    BehaviourEvent::ConnectionEvicted {
+                        peer: evicted_peer,
+                        in_favor_of: peer,
+                    },
+                ));
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Close a single connection to the peer.
+    fn close_connection(&mut self, peer: PeerId, connection: ConnectionId, reason: CloseReason) {
+        self.close_reasons.insert(connection, reason);
+        self.pending_actions.push_back(NetworkBehaviourAction::CloseConnection {
+            peer_id: peer,
+            connection: CloseConnection::One(connection),
+        });
+    }
+
+    // Count a missed keep-alive probe, and close all connections to the peer if it missed too many in a row.
+    fn on_missed_probe(&mut self, peer: PeerId) {
+        let max_missed = match self.config.keep_alive_probes {
+            Some(probes) => probes.max_missed.get(),
+            None => return,
+        };
+        let missed = self.missed_probes.entry(peer).or_default();
+        *missed += 1;
+        if *missed < max_missed {
+            return;
+        }
+        let missed = self.missed_probes.remove(&peer).unwrap_or_default();
+        self.pending_actions.push_back(NetworkBehaviourAction::GenerateEvent(
+            BehaviourEvent::PeerUnresponsive { peer, missed },
+        ));
+        self.disconnect_peer(peer, CloseReason::Unresponsive);
+    }
+
+    /// Close all connections to the peer.
+    ///
+    /// Pending requests on the connections fail with [`OutboundFailure::ConnectionClosed`] and
+    /// [`InboundFailure::ConnectionClosed`] with the `reason`.
+    pub fn disconnect_peer(&mut self, peer: PeerId, reason: CloseReason) {
+        self.set_close_reason(&peer, reason);
+        self.pending_actions.push_back(NetworkBehaviourAction::CloseConnection {
+            peer_id: peer,
+            connection: CloseConnection::All,
+        });
+    }
+
+    /// Set the reason for the connections to the peer that are about to be closed by the local peer outside of the
+    /// behaviour, e.g. because the peer is banned in the swarm.
+    pub fn set_close_reason(&mut self, peer: &PeerId, reason: CloseReason) {
+        for (_, connection, _) in self
+            .request_manager
+            .connections()
+            .into_iter()
+            .filter(|(p, ..)| p == peer)
+        {
+            self.close_reasons.entry(connection).or_insert_with(|| reason.clone());
+        }
+    }
+
+    /// Resolve the reason for the last closed connection, which is the connection of the
+    /// [`SwarmEvent::ConnectionClosed`][libp2p::swarm::SwarmEvent::ConnectionClosed] that the swarm emits right after
+    /// the connection was closed in the behaviour. The reason that was set by the local peer takes precedence over the
+    /// one derived from the `cause` of the event.
+    ///
+    /// The pending requests on the connection are failed with the resolved reason.
+    pub fn resolve_close_reason(
+        &mut self,
+        peer: &PeerId,
+        cause: Option<&ConnectionError<impl fmt::Display>>,
+    ) -> CloseReason {
+        let from_cause = CloseReason::from_cause(cause);
+        match self.closed_connection.take() {
+            Some(closed) if closed.peer == *peer => self.fail_closed_requests(closed, from_cause),
+            other => {
+                if let Some(closed) = other {
+                    self.fail_closed_requests(closed, CloseReason::Remote);
+                }
+                from_cause
+            }
+        }
+    }
+
+    // Fail the pending requests of the closed connection with the reason that was set by the local peer, or else the
+    // `fallback`.
+    fn fail_closed_requests(&mut self, closed: ClosedConnection, fallback: CloseReason) -> CloseReason {
+        let reason = closed.local_reason.unwrap_or(fallback);
+        self.request_manager
+            .on_connection_closed(closed.peer, &closed.connection, closed.remaining, reason.clone());
+        reason
+    }
+
+    /// Get the traffic statistics for each peer with which messages were exchanged.
+    /// The statistics of the peers that disconnected first are removed once too many peers are disconnected.
+    pub fn peer_stats(&self) -> HashMap<PeerId, PeerStats> {
+        self.traffic.export()
+    }
+
+    /// Get the usage statistics for each relay via which connections were established.
+    pub fn relay_stats(&self) -> HashMap<PeerId, RelayStats> {
+        self.traffic.export_relays()
+    }
+
+    /// Get a snapshot of the requests that are currently pending.
+    pub fn pending_requests(&self) -> PendingRequests {
+        self.request_manager.pending_requests()
+    }
+
+    /// Get the inbound requests that currently wait for a peer rule or an individual approval.
+    pub fn awaiting_firewall(&self) -> AwaitingFirewall {
+        self.request_manager.awaiting_firewall()
+    }
+
+    /// Fail all requests of the peer that wait for a connection, a firewall rule or an individual approval, or that
+    /// are held back for ordered delivery, with [`OutboundFailure::Aborted`] and [`InboundFailure::Aborted`].
+    /// Requests that were already sent or forwarded to the application are not affected.
+    ///
+    /// Returns the ids of the aborted requests.
+    pub fn abort_pending(&mut self, peer: PeerId) -> Vec<RequestId> {
+        let aborted = self.request_manager.abort_pending(peer);
+        for request_id in aborted.iter() {
+            // Abort firewall request for approval.
+            let _ = self.approval_rq_handles.remove(request_id);
+        }
+        aborted
+    }
+
+    /// Number of inbound requests that were received, but await a peer rule or an individual approval from the
+    /// firewall before they are forwarded to the application.
+    pub fn inbound_queue_depth(&self) -> usize {
+        self.request_manager.inbound_queue_depth()
+    }
+
+    // Emit an event if the depth of the inbound queue crossed one of the configured watermarks.
+    fn check_inbound_watermarks(&mut self) {
+        let watermarks = match self.config.inbound_watermarks {
+            Some(watermarks) => watermarks,
+            None => return,
+        };
+        let depth = self.inbound_queue_depth();
+        let event = if !self.inbound_queue_high && depth >= watermarks.high() {
+            BehaviourEvent::InboundQueueHigh { depth }
+        } else if self.inbound_queue_high && depth <= watermarks.low() {
+            BehaviourEvent::InboundQueueLow { depth }
+        } else {
+            return;
+        };
+        self.inbound_queue_high = !self.inbound_queue_high;
+        self.pending_actions
+            .push_back(NetworkBehaviourAction::GenerateEvent(event));
+    }
+
+    /// Get the aggregated statistics over all peers.
+    pub fn stats(&self) -> NetworkStats {
+        let mut stats = self.traffic.aggregate();
+        stats.active_connections = self.request_manager.connection_count();
+        stats.queued_actions = self.request_manager.queued_actions();
+        stats
+    }
+
+    /// Set the generator for the ids of new requests.
+    /// This has to be set before the outbound queue and the request journal are enabled, and before any connection
+    /// is established.
+    pub fn set_request_id_generator(&mut self, generator: Arc<dyn RequestIdGenerator>) {
+        self.next_request_id = generator;
+    }
+
+    /// Set the middleware that is invoked on outbound and inbound requests, in the order of the list.
+    pub fn set_middleware(&mut self, middleware: Vec<Arc<dyn RequestMiddleware<Rq>>>) {
+        self.middleware.set_middleware(middleware);
+    }
+
+    /// Set the converters for messages of earlier protocol versions. The protocol versions of the converters are
+    /// supported in addition to the configured ones.
+    ///
+    /// Only applies to connections that are established afterwards.
+    pub fn set_message_migrations(&mut self, migrations: MessageMigrations<Rq, Rs>) {
+        for protocol in migrations.protocols() {
+            let is_supported = self
+                .config
+                .supported_protocols
+                .iter()
+                .any(|p| p.base_version() == protocol.base_version());
+            if !is_supported {
+                self.config.supported_protocols.push(protocol.clone());
+            }
+        }
+        self.migrations = Some(Arc::new(migrations));
+    }
+
+    /// Set the codec for encoding requests and responses, instead of JSON.
+    ///
+    /// Only applies to connections that are established afterwards.
+    pub fn set_codec(&mut self, codec: Arc<dyn Codec<Rq, Rs>>) {
+        self.codec = Some(codec);
+    }
+
+    /// Record metrics for the events of the ping and identify protocol.
+    #[cfg(feature = "metrics")]
+    pub fn set_metrics(&mut self, metrics: Arc<Metrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    /// Set the hooks that are invoked on responses to outbound requests, in the order of the list.
+    pub fn set_response_hooks(&mut self, hooks: Vec<Arc<dyn ResponseHook<Rs>>>) {
+        self.middleware.set_response_hooks(hooks);
+    }
+
+    /// Set the verifier for capability tokens that are attached to inbound requests.
+    /// Requests with a token that is accepted by the verifier are approved without checking the firewall rules.
+    pub fn set_capability_verifier(&mut self, verifier: Option<Arc<dyn CapabilityVerifier<TRq>>>) {
+        self.capability_verifier = verifier;
+        let peers = self
+            .request_manager
+            .established_connections()
+            .into_iter()
+            .map(|(p, _)| p);
+        for peer in peers.collect::<Vec<_>>() {
+            let inbound_support = self.supports_inbound(&peer);
+            self.request_manager.set_inbound_support(peer, None, inbound_support);
+        }
+    }
+
+    /// Pause or resume forwarding inbound requests.
+    /// While paused, approved inbound requests are rejected with [`InboundFailure::Overloaded`].
+    pub fn set_inbound_paused(&mut self, paused: bool) {
+        self.inbound_paused = paused;
+    }
+
+    /// Record an event in the log of recent events.
+    /// The description is only created if the log is enabled.
+    pub fn log_event<F: FnOnce() -> String>(&mut self, source: EventSource, description: F) {
+        self.event_log.record(source, description)
+    }
+
+    /// Get the most recent actions and swarm events, ordered from oldest to newest.
+    pub fn recent_events(&self) -> Vec<RecentEvent> {
+        self.event_log.entries()
+    }
+
+    /// Whether the relay protocol is enabled.
+    pub fn is_relay_enabled(&self) -> bool {
+        self.relay.is_enabled()
+    }
+
+    /// Whether the ping protocol is enabled.
+    pub fn is_ping_enabled(&self) -> bool {
+        self.ping.is_enabled()
+    }
+
+    /// Round-trip time of the latest successful ping to a connected peer, and when it was measured.
+    /// The sample is removed once the peer disconnected.
+    pub fn latest_rtt(&self, peer: &PeerId) -> Option<RttSample> {
+        self.latest_rtt.get(peer).copied()
+    }
+
+    /// Order the candidates by their health, so that the healthiest peer comes first.
+    ///
+    /// Peers are ranked by the success rate of the outbound requests sent to them, ties are broken by the latest ping
+    /// round-trip time. Peers to which no request completed yet are assumed to be healthy, peers without measured
+    /// round-trip time are ranked after the ones with. The relative order of otherwise equal candidates is kept.
+    pub fn rank_peers(&self, candidates: Vec<PeerId>) -> Vec<PeerId> {
+        let mut ranked: Vec<_> = candidates
+            .into_iter()
+            .map(|peer| {
+                let success_rate = self
+                    .traffic
+                    .peer(&peer)
+                    .and_then(PeerStats::success_rate)
+                    .unwrap_or(1.0);
+                (peer, success_rate, self.latest_rtt(&peer).map(|sample| sample.rtt))
+            })
+            .collect();
+        ranked.sort_by(|(_, rate_a, rtt_a), (_, rate_b, rtt_b)| {
+            let by_rate = rate_b.partial_cmp(rate_a).unwrap_or(std::cmp::Ordering::Equal);
+            let by_rtt = match (rtt_a, rtt_b) {
+                (Some(a), Some(b)) => a.cmp(b),
+                (a, b) => a.is_none().cmp(&b.is_none()),
+            };
+            by_rate.then(by_rtt)
+        });
+        ranked.into_iter().map(|(peer, ..)| peer).collect()
+    }
+
+    /// Add a relay to the list of relays that may be tried to use if a remote peer can not be reached directly.
+    pub fn add_dialing_relay(
+        &mut self,
+        peer: PeerId,
+        address: Option<Multiaddr>,
+    ) -> Result<Option<Multiaddr>, RelayNotSupported> {
+        if !self.is_relay_enabled() {
+            return Err(RelayNotSupported);
+        }
+        Ok(self.addresses.add_relay(peer, address))
+    }
+
+    /// Get the current restrictions on the usage of relays.
+    pub fn relay_policy(&self) -> &RelayPolicy {
+        &self.config.relay_policy
+    }
+
+    /// Set the restrictions on the usage of relays.
+    ///
+    /// Already established relayed connections and listeners are not closed.
+    pub fn set_relay_policy(&mut self, policy: RelayPolicy) {
+        self.config.relay_policy = policy;
+    }
+
+    /// Whether the relay may be used for listening and dialing according to the [`RelayPolicy`].
+    pub fn is_relay_allowed(&self, relay: &PeerId) -> bool {
+        self.config.relay_policy.is_relay_allowed(relay)
+    }
+
+    /// Known dialing relays that are allowed by the [`RelayPolicy`], ordered according to the configured
+    /// [`RelaySelection`].
+    pub fn select_relays(&mut self) -> Vec<PeerId> {
+        let policy = &self.config.relay_policy;
+        let mut relays: Vec<PeerId> = self
+            .addresses
+            .relays()
+            .iter()
+            .filter(|r| policy.is_relay_allowed(r))
+            .copied()
+            .collect();
+        match self.config.relay_selection {
+            RelaySelection::FallbackOrder => {}
+            RelaySelection::RoundRobin => {
+                if !relays.is_empty() {
+                    relays.rotate_left(self.next_relay % relays.len());
+                    self.next_relay = self.next_relay.wrapping_add(1);
+                }
+            }
+            RelaySelection::LowestLatency => {
+                relays.sort_by_key(|r| self.relay_health(r).rtt.unwrap_or(Duration::MAX));
+            }
+        }
+        // Relays that failed recently are tried last.
+        relays.sort_by_key(|r| self.relay_health(r).failures);
+        relays
+    }
+
+    /// Known dialing relays with their health, ranked by the number of recent failures and the round-trip time.
+    pub fn relay_candidates(&self) -> Vec<RelayCandidate> {
+        let mut candidates: Vec<RelayCandidate> = self
+            .addresses
+            .relays()
+            .iter()
+            .map(|r| {
+                let health = self.relay_health(r);
+                RelayCandidate {
+                    peer: *r,
+                    address: self.addresses.get_relay_addr(r),
+                    rtt: health.rtt,
+                    failures: health.failures,
+                }
+            })
+            .collect();
+        candidates.sort_by_key(|c| (c.failures, c.rtt.unwrap_or(Duration::MAX)));
+        candidates
+    }
+
+    // Health of a relay, default if no probe completed yet.
+    fn relay_health(&self, relay: &PeerId) -> RelayHealth {
+        self.relay_health.get(relay).copied().unwrap_or_default()
+    }
+
+    // Record the outcome of a connection attempt or ping to a peer, if the peer is a dialing relay.
+    fn on_relay_probe(&mut self, peer: PeerId, rtt: Result<Option<Duration>, ()>) {
+        if !self.addresses.relays().contains(&peer) {
+            return;
+        }
+        let health = self.relay_health.entry(peer).or_default();
+        match rtt {
+            Ok(rtt) => {
+                health.failures = 0;
+                health.rtt = rtt.or(health.rtt);
+            }
+            Err(()) => health.failures += 1,
+        }
+    }
+
+    /// Select a known dialing relay for listening, according to the configured [`RelaySelection`].
+    /// Returns the relay and its address, or `None` if no address is known for any relay.
+    pub fn select_listening_relay(&mut self) -> Option<(PeerId, Multiaddr)> {
+        self.select_relays()
+            .into_iter()
+            .find_map(|r| self.addresses.get_relay_addr(&r).map(|a| (r, a)))
+    }
+
+    /// Remove a relay from the list of dialing relays.
+    /// Returns `false` if the peer was not among the known relays.
+    ///
+    /// **Note**: Known relayed addresses for remote peers using this relay will not be influenced by this.
+    pub fn remove_dialing_relay(&mut self, peer: &PeerId) -> bool {
+        self.relay_health.remove(peer);
+        self.addresses.remove_relay(peer)
+    }
+
+    /// Configure whether it should be attempted to reach the remote via known relays, if it can not be reached via
+    /// known addresses.
+    pub fn set_relay_fallback(&mut self, peer: PeerId, use_relay_fallback: bool) -> Result<(), RelayNotSupported> {
+        if !self.is_relay_enabled() {
+            return Err(RelayNotSupported);
+        }
+        self.addresses.set_relay_fallback(peer, use_relay_fallback);
+        Ok(())
+    }
+
+    /// Dial the target via the specified relay.
+    /// The `is_exclusive` parameter specifies whether other known relays should be used if using the set relay is not
+    /// successful.
+    ///
+    /// Returns the relayed address of the local peer (`<relay-addr>/<relay-id>/p2p-circuit/<local-id>),
+    /// if an address for the relay is known.
+    pub fn use_specific_relay(
+        &mut self,
+        target: PeerId,
+        relay: PeerId,
+        is_exclusive: bool,
     ) -> Result<Option<Multiaddr>, RelayNotSupported> {
         if !self.is_relay_enabled() {
             return Err(RelayNotSupported);
         }
+        if !self.is_relay_allowed(&relay) {
+            return Ok(None);
+        }
         Ok(self.addresses.use_relay(target, relay, is_exclusive))
     }
 
     // Check the approval status of the request and add queries to the firewall if necessary.
-    fn check_approval_status(&mut self, peer: PeerId, request_id: RequestId, request: &Rq) -> ApprovalStatus {
-        // Check the firewall rule for the target peer.
-        match self.firewall.get_effective_rule(&peer) {
+    fn check_approval_status(
+        &mut self,
+        peer: PeerId,
+        request_id: RequestId,
+        request: &Rq,
+        capability: Option<&CapabilityToken>,
+        priority: RequestPriority,
+    ) -> ApprovalStatus {
+        // Approve the request if the attached capability token is accepted.
+        if let (Some(token), Some(verifier)) = (capability, self.capability_verifier.as_ref()) {
+            if verifier.verify(&peer, &TRq::from_request(request), token) {
+                return ApprovalStatus::Approved;
+            }
+        }
+        // Check the firewall rule for the priority class, or else for the target peer.
+        let rule = self
+            .firewall
+            .get_priority_rule(priority)
+            .or_else(|| self.effective_rule(&peer));
+        match rule {
             None => {
                 // Query for a new peer specific rule.
                 self.query_peer_rule(peer);
@@ -295,86 +1729,827 @@ where
                 self.query_request_approval(peer, request_id, TRq::from_request(request));
                 ApprovalStatus::MissingApproval
             }
-            Some(Rule::AllowAll) => ApprovalStatus::Approved,
-            Some(Rule::RejectAll) => ApprovalStatus::Rejected,
-            Some(Rule::Restricted { restriction, .. }) => {
-                if restriction(&TRq::from_request(request)) {
-                    ApprovalStatus::Approved
-                } else {
-                    ApprovalStatus::Rejected
+            Some(Rule::AllowAll) => ApprovalStatus::Approved,
+            Some(Rule::RejectAll) => ApprovalStatus::Rejected,
+            Some(Rule::Restricted { restriction, .. }) => {
+                if restriction(&TRq::from_request(request)) {
+                    ApprovalStatus::Approved
+                } else {
+                    ApprovalStatus::Rejected
+                }
+            }
+        }
+    }
+
+    fn new_request_response_handler(&mut self, peer: Option<PeerId>) -> Handler<Rq, Rs> {
+        let inbound_support = match peer {
+            Some(peer) => self.supports_inbound(&peer),
+            None => true,
+        };
+        // Use full protocol support on init.
+        // Once the connection is established, this will be updated with the effective rule for the remote peer.
+        Handler::new(
+            self.config.supported_protocols.clone(),
+            inbound_support,
+            self.config.connection_timeout,
+            self.config.request_timeout,
+            self.next_request_id.clone(),
+            self.config.buffer_tuning,
+            self.config.request_batching,
+            self.decode_pool.clone(),
+            self.migrations.clone(),
+            self.codec.clone(),
+        )
+    }
+
+    fn new_handler_for_peer(&mut self, peer: Option<PeerId>) -> ProtoHandler<Rq, Rs, TCustom> {
+        let relay_handler = self.relay.new_handler();
+        let custom_handler = self.custom.new_handler();
+        self.compose_handler(peer, relay_handler, custom_handler)
+    }
+
+    // Compose the handler for a new connection from the handlers of the individual protocols.
+    // The handlers of relay and custom behaviour are passed explicitly, since they may be provided by the respective
+    // behaviour when it requests a dial.
+    fn compose_handler(
+        &mut self,
+        peer: Option<PeerId>,
+        relay_handler: <Toggle<Relay> as Libp2pNetworkBehaviour>::ConnectionHandler,
+        custom_handler: <Toggle<TCustom> as Libp2pNetworkBehaviour>::ConnectionHandler,
+    ) -> ProtoHandler<Rq, Rs, TCustom> {
+        let handler = self.new_request_response_handler(peer);
+        let mdns_handler = self.mdns.new_handler();
+        let message_handlers = IntoConnectionHandler::select(
+            MailboxHandler::default(),
+            IntoConnectionHandler::select(
+                TransferHandler::default(),
+                IntoConnectionHandler::select(
+                    PresenceHandler::default(),
+                    IntoConnectionHandler::select(
+                        BlockHandler::default(),
+                        IntoConnectionHandler::select(
+                            HelloHandler::default(),
+                            IntoConnectionHandler::select(RotationHandler::default(), custom_handler),
+                        ),
+                    ),
+                ),
+            ),
+        );
+        let info_handlers = IntoConnectionHandler::select(self.ping.new_handler(), self.identify.new_handler());
+        IntoConnectionHandler::select(
+            handler,
+            IntoConnectionHandler::select(
+                mdns_handler,
+                IntoConnectionHandler::select(
+                    relay_handler,
+                    IntoConnectionHandler::select(info_handlers, message_handlers),
+                ),
+            ),
+        )
+    }
+
+    // Handle new event emitted by the `Handler`.
+    fn handle_handler_event(&mut self, peer: PeerId, connection: ConnectionId, event: HandlerOutEvent<Rq, Rs>) {
+        match event {
+            HandlerOutEvent::ReceivedRequest {
+                request_id,
+                request,
+                capability,
+                idempotency_key,
+                metadata,
+                response_tx,
+                error_tx,
+                bytes_received,
+            } => {
+                let stats = self.traffic.peer_mut(peer);
+                stats.requests_received += 1;
+                stats.bytes_received += bytes_received as u64;
+                self.traffic.on_connection_bytes(&connection, 0, bytes_received);
+                let response_tx = match idempotency_key {
+                    Some(key) => match self.deduplicate_request(peer, key, response_tx) {
+                        Some(response_tx) => response_tx,
+                        None => return,
+                    },
+                    None => response_tx,
+                };
+                let deadline = Instant::now() + self.config.request_timeout;
+                self.remote_error_txs.insert(request_id, (error_tx, deadline));
+                let priority = metadata.as_ref().map(|(_, p, _)| *p).unwrap_or_default();
+                if let Some(metadata) = metadata {
+                    self.request_metadata.insert(request_id, metadata);
+                }
+                let approval_status =
+                    self.check_approval_status(peer, request_id, &request, capability.as_ref(), priority);
+                self.request_manager.on_new_in_request(
+                    peer,
+                    request_id,
+                    request,
+                    bytes_received,
+                    response_tx,
+                    connection,
+                    approval_status,
+                );
+            }
+            HandlerOutEvent::ReceivedResponse {
+                request_id,
+                response,
+                bytes_received,
+                rtt,
+            } => {
+                self.traffic.peer_mut(peer).bytes_received += bytes_received as u64;
+                self.traffic.on_connection_bytes(&connection, 0, bytes_received);
+                self.request_manager
+                    .on_res_for_outbound(peer, request_id, Ok((response, rtt)));
+            }
+            HandlerOutEvent::RequestWritten { request_id, bytes_sent } => {
+                self.traffic.peer_mut(peer).bytes_sent += bytes_sent as u64;
+                self.traffic.on_connection_bytes(&connection, bytes_sent, 0);
+                self.update_request_status(&request_id, RequestStatus::Written);
+                self.request_manager.on_request_written(peer, request_id);
+            }
+            HandlerOutEvent::OutboundTimeout(request_id) => {
+                // Abort firewall request for approval.
+                let _ = self.approval_rq_handles.remove(&request_id);
+                self.request_manager
+                    .on_res_for_outbound(peer, request_id, Err(OutboundFailure::Timeout));
+            }
+            HandlerOutEvent::OutboundRemoteError { request_id, error } => {
+                self.request_manager
+                    .on_res_for_outbound(peer, request_id, Err(OutboundFailure::Remote(error)));
+            }
+            HandlerOutEvent::OutboundUnsupportedProtocols(request_id) => {
+                // Abort firewall request for approval.
+                let _ = self.approval_rq_handles.remove(&request_id);
+                let failure = OutboundFailure::UnsupportedProtocols {
+                    remote_protocols: self.remote_protocols.get(&peer).cloned(),
+                };
+                self.request_manager.on_res_for_outbound(peer, request_id, Err(failure));
+            }
+            HandlerOutEvent::InboundTimeout(request_id) => {
+                // Abort firewall request for approval.
+                let _ = self.approval_rq_handles.remove(&request_id);
+                let err = InboundFailure::Timeout;
+                self.request_manager.on_res_for_inbound(peer, request_id, Err(err));
+            }
+            HandlerOutEvent::SentResponse { request_id, bytes_sent } => {
+                // Abort firewall request for approval.
+                let _ = self.approval_rq_handles.remove(&request_id);
+                self.awaiting_response.remove(&request_id);
+                let stats = self.traffic.peer_mut(peer);
+                stats.responses_sent += 1;
+                stats.bytes_sent += bytes_sent as u64;
+                self.traffic.on_connection_bytes(&connection, bytes_sent, 0);
+                self.journal_completed(request_id, ResponseStatus::Sent);
+                self.request_manager.on_res_for_inbound(peer, request_id, Ok(()));
+            }
+            HandlerOutEvent::InboundUnsupportedProtocols(request_id) => {
+                // Abort firewall request for approval.
+                let _ = self.approval_rq_handles.remove(&request_id);
+                self.journal_completed(request_id, ResponseStatus::Dropped);
+                self.request_manager.on_res_for_inbound(peer, request_id, Ok(()));
+            }
+            HandlerOutEvent::InboundUnknownRequest(request_id) => {
+                let err = InboundFailure::UnknownRequest;
+                self.request_manager.on_res_for_inbound(peer, request_id, Err(err));
+            }
+            HandlerOutEvent::SendResponseOmission(request_id) => {
+                let _ = self.approval_rq_handles.remove(&request_id);
+                // Requests that already failed locally, e.g. because the firewall rejected them, were reported before.
+                if !self.awaiting_response.remove(&request_id) {
+                    self.request_manager.on_res_for_inbound(peer, request_id, Ok(()));
+                    return;
+                }
+                self.journal_completed(request_id, ResponseStatus::Dropped);
+                let err = InboundFailure::ResponseOmitted;
+                self.request_manager.on_res_for_inbound(peer, request_id, Err(err));
+            }
+            HandlerOutEvent::ResponseRejected(request_id) => {
+                self.awaiting_response.remove(&request_id);
+                self.journal_completed(request_id, ResponseStatus::Rejected);
+                self.request_manager.on_res_for_inbound(peer, request_id, Ok(()));
+            }
+        }
+    }
+
+    // Send the reason for not answering an inbound request to the remote.
+    // Failures that the remote observes itself are not sent.
+    fn send_remote_error(&mut self, request_id: RequestId, failure: &InboundFailure) {
+        let error_tx = match self.remote_error_txs.remove(&request_id) {
+            Some((tx, _)) => tx,
+            None => return,
+        };
+        if let Some(error) = failure.remote_error(self.config.busy_retry_after) {
+            let _ = error_tx.send(error);
+        }
+    }
+
+    // Check if a request with the idempotency key was received before, in which case it is answered with the same
+    // response as the first request.
+    // For new requests a channel is returned that forwards the response to the remote, and caches it.
+    fn deduplicate_request(
+        &mut self,
+        peer: PeerId,
+        key: String,
+        response_tx: oneshot::Sender<Rs>,
+    ) -> Option<oneshot::Sender<Rs>> {
+        let cache = match self.idempotency.as_mut() {
+            Some(cache) => cache,
+            None => return Some(response_tx),
+        };
+        let response_tx = cache.on_request(peer, key.clone(), response_tx)?;
+        let (tx, rx) = oneshot::channel();
+        self.idempotent_responses
+            .push(rx.map(move |res| (peer, key, res, response_tx)).boxed());
+        Some(tx)
+    }
+
+    // Send a message on the mailbox protocol.
+    // If there is no connection to the peer, the peer is dialed if `dial` is true. Otherwise the message is stored in
+    // the local mailbox.
+    // Returns `false` if the message was dropped because it could neither be sent nor stored.
+    fn send_mailbox_message(&mut self, peer: PeerId, message: MailboxMessage, dial: bool) -> bool {
+        if self.request_manager.connected_peers().contains(&peer) {
+            let event = EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(
+                EitherOutput::First(message),
+            ))));
+            self.pending_actions.push_back(NetworkBehaviourAction::NotifyHandler {
+                peer_id: peer,
+                handler: NotifyHandler::Any,
+                event,
+            });
+        } else if dial {
+            self.dial_for_outbox(peer);
+            self.mailbox_outbox.entry(peer).or_default().push(message);
+        } else {
+            return self.store_in_mailbox(peer, message);
+        }
+        true
+    }
+
+    // Dial the peer to send the messages from the outboxes, unless there is already a pending dial for this.
+    fn dial_for_outbox(&mut self, peer: PeerId) {
+        if self.mailbox_outbox.contains_key(&peer) || self.transfer_outbox.contains_key(&peer) {
+            return;
+        }
+        self.pending_actions.push_back(NetworkBehaviourAction::Dial {
+            handler: self.new_handler_for_peer(Some(peer)),
+            opts: DialOpts::peer_id(peer).condition(PeerCondition::Disconnected).build(),
+        });
+    }
+
+    // Store a message in the local mailbox for an offline peer.
+    // Returns `false` if the local peer is not a mailbox, or the limit for this peer is reached.
+    fn store_in_mailbox(&mut self, peer: PeerId, message: MailboxMessage) -> bool {
+        let expires_at = match &message {
+            MailboxMessage::Forward { expires_at, .. } => Some(*expires_at),
+            _ => None,
+        };
+        match self.mailbox.as_mut() {
+            Some(mailbox) => mailbox.store(peer, message, expires_at),
+            None => false,
+        }
+    }
+
+    // Handle new event emitted by the `MailboxHandler`.
+    fn handle_mailbox_event(&mut self, peer: PeerId, event: MailboxHandlerEvent) {
+        match event {
+            MailboxHandlerEvent::Received(message) => self.on_mailbox_message(peer, message),
+            MailboxHandlerEvent::SendFailed(message) => self.on_mailbox_send_failed(peer, message),
+            MailboxHandlerEvent::Sent(_) => {}
+        }
+    }
+
+    fn on_mailbox_message(&mut self, peer: PeerId, message: MailboxMessage) {
+        match message {
+            MailboxMessage::Deposit {
+                id,
+                recipient,
+                expires_at,
+                request,
+            } => {
+                let forward = MailboxMessage::Forward {
+                    id,
+                    sender: peer,
+                    expires_at,
+                    request,
+                };
+                let is_accepted = self.mailbox.is_some() && self.send_mailbox_message(recipient, forward, false);
+                if !is_accepted {
+                    let receipt = MailboxMessage::Receipt {
+                        id,
+                        recipient,
+                        is_approved: false,
+                    };
+                    let _ = self.send_mailbox_message(peer, receipt, false);
+                }
+            }
+            MailboxMessage::Forward {
+                id,
+                sender,
+                expires_at,
+                request,
+            } => {
+                let is_approved = self.on_forwarded_request(peer, sender, expires_at, &request);
+                let handled = MailboxMessage::Handled {
+                    id,
+                    sender,
+                    is_approved,
+                };
+                let _ = self.send_mailbox_message(peer, handled, true);
+            }
+            MailboxMessage::Handled {
+                id,
+                sender,
+                is_approved,
+            } => {
+                let receipt = MailboxMessage::Receipt {
+                    id,
+                    recipient: peer,
+                    is_approved,
+                };
+                // The receipt is dropped if it can not be stored, the sender then considers the request as expired.
+                let _ = self.send_mailbox_message(sender, receipt, false);
+            }
+            MailboxMessage::Receipt {
+                id,
+                recipient,
+                is_approved,
+            } => {
+                let request_id = RequestId(id);
+                if self.pending_deposits.remove(&request_id).is_some() {
+                    let status = match is_approved {
+                        true => MailboxDelivery::Delivered,
+                        false => MailboxDelivery::Rejected,
+                    };
+                    let event = BehaviourEvent::MailboxReceipt {
+                        request_id,
+                        recipient,
+                        status,
+                    };
+                    self.pending_actions
+                        .push_back(NetworkBehaviourAction::GenerateEvent(event));
+                }
+            }
+        }
+    }
+
+    // Forward a request that was received via a mailbox, if the mailbox is trusted and the request is approved by the
+    // firewall rule for the original sender.
+    // Firewall rules that require to ask for a rule or approval are not supported for such requests, and reject them.
+    fn on_forwarded_request(&mut self, mailbox: PeerId, sender: PeerId, expires_at: u64, request: &str) -> bool {
+        if !self.config.trusted_mailboxes.contains(&mailbox) || expires_at <= unix_now() {
+            return false;
+        }
+        let request: Rq = match serde_json::from_str(request) {
+            Ok(r) => r,
+            Err(_) => return false,
+        };
+        let is_approved = self.is_approved_by_rule(&sender, &request);
+        if is_approved {
+            // The sender is not connected, so the response is dropped.
+            let (response_tx, _) = oneshot::channel();
+            let event = BehaviourEvent::ReceivedRequest {
+                request_id: RequestId::next(&self.next_request_id),
+                peer: sender,
+                request,
+                responder: Responder::new(response_tx, None, None),
+                connection: None,
+                metadata: RequestMetadata::new(),
+                priority: RequestPriority::Normal,
+                global_id: None,
+            };
+            self.pending_actions
+                .push_back(NetworkBehaviourAction::GenerateEvent(event));
+        }
+        is_approved
+    }
+
+    // Check whether the firewall rule for the peer approves the request, without asking for a rule or approval.
+    fn is_approved_by_rule(&self, peer: &PeerId, request: &Rq) -> bool {
+        match self.effective_rule(peer) {
+            Some(Rule::AllowAll) => true,
+            Some(Rule::Restricted { restriction, .. }) => restriction(&TRq::from_request(request)),
+            _ => false,
+        }
+    }
+
+    fn on_mailbox_send_failed(&mut self, peer: PeerId, message: MailboxMessage) {
+        match message {
+            MailboxMessage::Deposit { id, .. } => {
+                let request_id = RequestId(id);
+                if let Some(recipient) = self.pending_deposits.remove(&request_id) {
+                    let event = BehaviourEvent::MailboxReceipt {
+                        request_id,
+                        recipient,
+                        status: MailboxDelivery::MailboxUnreachable,
+                    };
+                    self.pending_actions
+                        .push_back(NetworkBehaviourAction::GenerateEvent(event));
+                }
+            }
+            MailboxMessage::Forward { .. } | MailboxMessage::Receipt { .. } => {
+                // Retry once the peer reconnects.
+                let _ = self.store_in_mailbox(peer, message);
+            }
+            MailboxMessage::Handled { .. } => {}
+        }
+    }
+
+    // Send the messages that were waiting for a connection to the peer.
+    fn flush_outboxes(&mut self, peer: PeerId) {
+        let mut messages = self.mailbox_outbox.remove(&peer).unwrap_or_default();
+        if let Some(mailbox) = self.mailbox.as_mut() {
+            messages.extend(mailbox.take(&peer));
+        }
+        for message in messages {
+            let _ = self.send_mailbox_message(peer, message, false);
+        }
+        for message in self.transfer_outbox.remove(&peer).into_iter().flatten() {
+            self.send_transfer_message(peer, message);
+        }
+    }
+
+    // Send a message on the transfer protocol, and dial the peer if it is not connected.
+    fn send_transfer_message(&mut self, peer: PeerId, message: TransferMessage) {
+        if self.request_manager.connected_peers().contains(&peer) {
+            let event = EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(
+                EitherOutput::Second(EitherOutput::First(message)),
+            ))));
+            self.pending_actions.push_back(NetworkBehaviourAction::NotifyHandler {
+                peer_id: peer,
+                handler: NotifyHandler::Any,
+                event,
+            });
+        } else {
+            self.dial_for_outbox(peer);
+            self.transfer_outbox.entry(peer).or_default().push(message);
+        }
+    }
+
+    // Send a heartbeat with the local status to the peer.
+    fn send_heartbeat(&mut self, peer: PeerId, handler: NotifyHandler) {
+        let heartbeat = match self.presence.as_ref() {
+            Some(presence) => presence.heartbeat(),
+            None => return,
+        };
+        let event = EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(
+            EitherOutput::Second(EitherOutput::Second(EitherOutput::First(heartbeat))),
+        ))));
+        self.pending_actions.push_back(NetworkBehaviourAction::NotifyHandler {
+            peer_id: peer,
+            handler,
+            event,
+        });
+    }
+
+    // Send the local hello to a newly connected peer if the firewall permits it, or hold it back until the rule for
+    // the peer is known.
+    fn send_hello(&mut self, peer: PeerId, handler: NotifyHandler) {
+        let is_permitted = self.is_hello_permitted(&peer);
+        let hellos = match self.hellos.as_mut() {
+            Some(hellos) => hellos,
+            None => return,
+        };
+        let message = match is_permitted {
+            Some(true) => hellos.message(),
+            Some(false) => return,
+            None => {
+                hellos.defer_send(peer);
+                self.query_peer_rule(peer);
+                return;
+            }
+        };
+        let event = EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(
+            EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(
+                EitherOutput::First(message),
+            )))),
+        ))));
+        self.pending_actions.push_back(NetworkBehaviourAction::NotifyHandler {
+            peer_id: peer,
+            handler,
+            event,
+        });
+    }
+
+    // Handle a hello from a peer. Hellos that could not be sent are not retried.
+    fn handle_hello_event(&mut self, peer: PeerId, event: HelloHandlerEvent) {
+        let message = match (self.hellos.is_some(), event) {
+            (true, HelloHandlerEvent::Received(message)) => message,
+            _ => return,
+        };
+        let hello = match Hellos::decode(message) {
+            Some(hello) => hello,
+            None => return,
+        };
+        match self.is_hello_permitted(&peer) {
+            Some(true) => self.accept_hello(peer, hello),
+            Some(false) => {}
+            None => {
+                if let Some(hellos) = self.hellos.as_mut() {
+                    hellos.defer_received(peer, hello);
+                }
+                self.query_peer_rule(peer);
+            }
+        }
+    }
+
+    fn accept_hello(&mut self, peer: PeerId, hello: Hello) {
+        if let Some(hellos) = self.hellos.as_mut() {
+            hellos.on_received(peer, hello.clone());
+            let event = BehaviourEvent::PeerHello { peer, hello };
+            self.pending_actions
+                .push_back(NetworkBehaviourAction::GenerateEvent(event));
+        }
+    }
+
+    // Whether hellos are exchanged with the peer, which is the case unless the firewall rejects all of its requests.
+    // `None` if there is no rule for the peer yet.
+    fn is_hello_permitted(&self, peer: &PeerId) -> Option<bool> {
+        self.effective_rule(peer).map(|rule| !matches!(rule, Rule::RejectAll))
+    }
+
+    // Send and accept the hellos that were held back until the firewall rule for the peer was known.
+    fn flush_hellos(&mut self, peer: PeerId) {
+        let is_permitted = match self.is_hello_permitted(&peer) {
+            Some(is_permitted) => is_permitted,
+            None => return,
+        };
+        let (send, received) = match self.hellos.as_mut() {
+            Some(hellos) => hellos.take_pending(&peer),
+            None => return,
+        };
+        if !is_permitted {
+            return;
+        }
+        if send {
+            self.send_hello(peer, NotifyHandler::Any);
+        }
+        if let Some(hello) = received {
+            self.accept_hello(peer, hello);
+        }
+    }
+
+    // Send the wants that were not sent to the peer yet.
+    fn send_want_list(&mut self, peer: PeerId) {
+        let hashes = match self.blocks.as_mut() {
+            Some(blocks) => blocks.wants_for(peer),
+            None => return,
+        };
+        if !hashes.is_empty() {
+            self.send_block_message(peer, BlockMessage::Want { hashes });
+        }
+    }
+
+    // Send a message on the block exchange protocol to a connected peer.
+    fn send_block_message(&mut self, peer: PeerId, message: BlockMessage) {
+        if !self.request_manager.connected_peers().contains(&peer) {
+            return;
+        }
+        let event = EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(
+            EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(EitherOutput::First(message)))),
+        ))));
+        self.pending_actions.push_back(NetworkBehaviourAction::NotifyHandler {
+            peer_id: peer,
+            handler: NotifyHandler::Any,
+            event,
+        });
+    }
+
+    // Handle a message of the block exchange protocol. Messages that could not be sent are not retried, wants are
+    // sent again once the peer reconnected.
+    fn handle_block_event(&mut self, peer: PeerId, event: BlockHandlerEvent) {
+        // Blocks are only provided to peers that are permitted to send any request.
+        let is_permitted = matches!(self.effective_rule(&peer), Some(Rule::AllowAll));
+        let (blocks, message) = match (self.blocks.as_mut(), event) {
+            (Some(blocks), BlockHandlerEvent::Received(message)) => (blocks, message),
+            _ => return,
+        };
+        match message {
+            BlockMessage::Want { hashes } => {
+                if !is_permitted {
+                    return;
+                }
+                for data in blocks.on_peer_want(peer, hashes) {
+                    let message = BlockMessage::Block {
+                        data: base64::encode(&data),
+                    };
+                    self.send_block_message(peer, message);
+                }
+            }
+            BlockMessage::Cancel { hashes } => blocks.on_peer_cancel(&peer, &hashes),
+            BlockMessage::Block { data } => {
+                let data = match base64::decode(data) {
+                    Ok(data) if data.len() <= MAX_BLOCK_SIZE => data,
+                    _ => return,
+                };
+                // Blocks that were not wanted are dropped.
+                let (hash, asked_peers) = match blocks.on_block(&data) {
+                    Some(wanted) => wanted,
+                    None => return,
+                };
+                let (_, wanting_peers) = blocks.insert(data.clone());
+                for other in asked_peers.into_iter().filter(|p| *p != peer) {
+                    let message = BlockMessage::Cancel {
+                        hashes: vec![hash.clone()],
+                    };
+                    self.send_block_message(other, message);
+                }
+                for other in wanting_peers {
+                    let message = BlockMessage::Block {
+                        data: base64::encode(&data),
+                    };
+                    self.send_block_message(other, message);
                 }
+                let event = BehaviourEvent::BlockReceived { hash, peer, data };
+                self.pending_actions
+                    .push_back(NetworkBehaviourAction::GenerateEvent(event));
             }
         }
     }
 
-    fn new_request_response_handler(&mut self, peer: Option<PeerId>) -> Handler<Rq, Rs> {
-        let inbound_support = match peer {
-            Some(peer) => !matches!(self.firewall.get_effective_rule(&peer), Some(Rule::RejectAll)),
-            None => true,
+    // Handle a heartbeat from a peer. Heartbeats that could not be sent are not retried.
+    fn handle_presence_event(&mut self, peer: PeerId, event: PresenceHandlerEvent) {
+        let (presence, heartbeat) = match (self.presence.as_mut(), event) {
+            (Some(presence), PresenceHandlerEvent::Received(heartbeat)) => (presence, heartbeat),
+            _ => return,
         };
-        // Use full protocol support on init.
-        // Once the connection is established, this will be updated with the effective rule for the remote peer.
-        Handler::new(
-            self.config.supported_protocols.clone(),
-            inbound_support,
-            self.config.connection_timeout,
-            self.config.request_timeout,
-            self.next_request_id.clone(),
-        )
+        let event = match presence.on_heartbeat(peer, heartbeat) {
+            PresenceChange::Online(status) => BehaviourEvent::PeerOnline { peer, status },
+            PresenceChange::StatusChanged(status) => BehaviourEvent::PeerStatusChanged { peer, status },
+            PresenceChange::Unchanged => return,
+        };
+        self.pending_actions
+            .push_back(NetworkBehaviourAction::GenerateEvent(event));
     }
 
-    fn new_handler_for_peer(&mut self, peer: Option<PeerId>) -> <Self as Libp2pNetworkBehaviour>::ConnectionHandler {
-        let handler = self.new_request_response_handler(peer);
-        let mdns_handler = self.mdns.new_handler();
-        let relay_handler = self.relay.new_handler();
-        IntoConnectionHandler::select(handler, IntoConnectionHandler::select(mdns_handler, relay_handler))
+    fn finish_transfer(&mut self, peer: PeerId, transfer_id: RequestId, result: Result<(), TransferFailure>) {
+        let event = BehaviourEvent::TransferFinished {
+            peer,
+            transfer_id,
+            result,
+        };
+        self.pending_actions
+            .push_back(NetworkBehaviourAction::GenerateEvent(event));
     }
 
-    // Handle new event emitted by the `Handler`.
-    fn handle_handler_event(&mut self, peer: PeerId, connection: ConnectionId, event: HandlerOutEvent<Rq, Rs>) {
+    fn on_transfer_progress(
+        &mut self,
+        peer: PeerId,
+        transfer_id: RequestId,
+        is_inbound: bool,
+        transferred: u64,
+        total: u64,
+    ) {
+        let event = BehaviourEvent::TransferProgress {
+            peer,
+            transfer_id,
+            is_inbound,
+            transferred,
+            total,
+        };
+        self.pending_actions
+            .push_back(NetworkBehaviourAction::GenerateEvent(event));
+    }
+
+    // Handle new event emitted by the `TransferHandler`.
+    fn handle_transfer_event(&mut self, peer: PeerId, event: TransferHandlerEvent) {
         match event {
-            HandlerOutEvent::ReceivedRequest {
-                request_id,
-                request,
-                response_tx,
-            } => {
-                let approval_status = self.check_approval_status(peer, request_id, &request);
-                self.request_manager.on_new_in_request(
-                    peer,
-                    request_id,
-                    request,
-                    response_tx,
-                    connection,
-                    approval_status,
-                );
+            TransferHandlerEvent::Received(message) => self.on_transfer_message(peer, message),
+            TransferHandlerEvent::SendFailed(message) => match message {
+                TransferMessage::Offer { id, .. } | TransferMessage::Chunk { id, .. } => {
+                    self.interrupt_transfer(RequestId(id))
+                }
+                // The sender resumes the transfer if it was interrupted.
+                _ => {}
+            },
+            TransferHandlerEvent::Sent(_) => {}
+        }
+    }
+
+    fn on_transfer_message(&mut self, peer: PeerId, message: TransferMessage) {
+        match message {
+            // Messages of the sender.
+            TransferMessage::Offer {
+                id,
+                size,
+                digest,
+                metadata,
+            } => match self.on_transfer_offer(peer, id, size, digest, &metadata) {
+                Some(offset) => {
+                    self.send_transfer_message(peer, TransferMessage::Accept { id, offset });
+                    self.on_transfer_progress(peer, RequestId(id), true, offset, size);
+                    if offset == size {
+                        self.complete_inbound_transfer(peer, id);
+                    }
+                }
+                None => self.send_transfer_message(peer, TransferMessage::Reject { id }),
+            },
+            TransferMessage::Chunk { id, offset, data } => {
+                let (received, size) = match self.inbound_transfers.get_mut(&(peer, id)) {
+                    Some(t) if t.append(offset, &data) => (t.data.len() as u64, t.size),
+                    _ => {
+                        let _ = self.inbound_transfers.remove(&(peer, id));
+                        self.send_transfer_message(peer, TransferMessage::Reject { id });
+                        return;
+                    }
+                };
+                self.on_transfer_progress(peer, RequestId(id), true, received, size);
+                if received < size {
+                    self.send_transfer_message(peer, TransferMessage::Ack { id, offset: received });
+                } else {
+                    self.complete_inbound_transfer(peer, id);
+                }
             }
-            HandlerOutEvent::ReceivedResponse { request_id, response } => {
-                self.request_manager.on_res_for_outbound(peer, request_id, Ok(response));
+            TransferMessage::Cancel { id } => {
+                let _ = self.inbound_transfers.remove(&(peer, id));
             }
-            HandlerOutEvent::OutboundTimeout(request_id) => {
-                // Abort firewall request for approval.
-                let _ = self.approval_rq_handles.remove(&request_id);
-                self.request_manager
-                    .on_res_for_outbound(peer, request_id, Err(OutboundFailure::Timeout));
+            // Messages of the recipient.
+            TransferMessage::Accept { id, offset } | TransferMessage::Ack { id, offset } => {
+                let transfer_id = RequestId(id);
+                let chunk_size = self.config.transfer.unwrap_or_default().chunk_size;
+                let (chunk, size) = match self.outbound_transfers.get(&transfer_id) {
+                    Some(t) if t.peer == peer && !t.is_interrupted => {
+                        (t.chunk(id, offset, chunk_size), t.data.len() as u64)
+                    }
+                    _ => return,
+                };
+                self.on_transfer_progress(peer, transfer_id, false, offset.min(size), size);
+                if let Some(chunk) = chunk {
+                    self.send_transfer_message(peer, chunk);
+                }
             }
-            HandlerOutEvent::OutboundUnsupportedProtocols(request_id) => {
-                // Abort firewall request for approval.
-                let _ = self.approval_rq_handles.remove(&request_id);
-                self.request_manager
-                    .on_res_for_outbound(peer, request_id, Err(OutboundFailure::UnsupportedProtocols));
+            TransferMessage::Reject { id } => {
+                let transfer_id = RequestId(id);
+                if matches!(self.outbound_transfers.get(&transfer_id), Some(t) if t.peer == peer) {
+                    let _ = self.outbound_transfers.remove(&transfer_id);
+                    self.finish_transfer(peer, transfer_id, Err(TransferFailure::Rejected));
+                }
             }
-            HandlerOutEvent::InboundTimeout(request_id) => {
-                // Abort firewall request for approval.
-                let _ = self.approval_rq_handles.remove(&request_id);
-                let err = InboundFailure::Timeout;
-                self.request_manager.on_res_for_inbound(peer, request_id, Err(err));
+            TransferMessage::Complete { id, is_valid } => {
+                let transfer_id = RequestId(id);
+                if matches!(self.outbound_transfers.get(&transfer_id), Some(t) if t.peer == peer) {
+                    let _ = self.outbound_transfers.remove(&transfer_id);
+                    let result = is_valid.then(|| ()).ok_or(TransferFailure::IntegrityCheckFailed);
+                    self.finish_transfer(peer, transfer_id, result);
+                }
             }
-            HandlerOutEvent::InboundUnsupportedProtocols(request_id)
-            | HandlerOutEvent::SendResponseOmission(request_id)
-            | HandlerOutEvent::SentResponse(request_id) => {
-                // Abort firewall request for approval.
-                let _ = self.approval_rq_handles.remove(&request_id);
-                self.request_manager.on_res_for_inbound(peer, request_id, Ok(()));
+        }
+    }
+
+    // Check the offer for an inbound transfer.
+    // Returns the offset from which on the data is expected, or `None` if the offer is rejected.
+    fn on_transfer_offer(&mut self, peer: PeerId, id: u64, size: u64, digest: Vec<u8>, metadata: &str) -> Option<u64> {
+        let config = self.config.transfer?;
+        if size > config.max_blob_size {
+            return None;
+        }
+        // Resume an interrupted transfer.
+        if let Some(transfer) = self.inbound_transfers.get(&(peer, id)) {
+            if transfer.size == size && transfer.digest == digest {
+                return Some(transfer.data.len() as u64);
+            }
+        }
+        let metadata: Rq = serde_json::from_str(metadata).ok()?;
+        if !self.is_approved_by_rule(&peer, &metadata) {
+            return None;
+        }
+        let transfer = InboundTransfer {
+            metadata,
+            size,
+            digest,
+            data: Vec::new(),
+        };
+        self.inbound_transfers.insert((peer, id), transfer);
+        Some(0)
+    }
+
+    // Verify the data of a completed inbound transfer, and forward it if it is valid.
+    fn complete_inbound_transfer(&mut self, peer: PeerId, id: u64) {
+        let transfer = match self.inbound_transfers.remove(&(peer, id)) {
+            Some(t) => t,
+            None => return,
+        };
+        let is_valid = transfer.is_valid();
+        self.send_transfer_message(peer, TransferMessage::Complete { id, is_valid });
+        if is_valid {
+            let event = BehaviourEvent::ReceivedBlob {
+                peer,
+                transfer_id: RequestId(id),
+                metadata: transfer.metadata,
+                data: transfer.data,
+            };
+            self.pending_actions
+                .push_back(NetworkBehaviourAction::GenerateEvent(event));
+        }
+    }
+
+    // Mark an outbound transfer as interrupted, so that it can be resumed.
+    fn interrupt_transfer(&mut self, transfer_id: RequestId) {
+        if let Some(transfer) = self.outbound_transfers.get_mut(&transfer_id) {
+            if !transfer.is_interrupted {
+                transfer.is_interrupted = true;
+                let peer = transfer.peer;
+                self.finish_transfer(peer, transfer_id, Err(TransferFailure::Interrupted));
             }
         }
     }
@@ -385,25 +2560,8 @@ where
         if self.request_manager.is_rule_request_pending(&peer) {
             return;
         }
-        let (rule_tx, rule_rx) = oneshot::channel();
-        let (abort_handle_tx, abort_handle_rx) = oneshot::channel();
-        let timeout = Delay::new(self.config.firewall_timeout);
-
-        let firewall_req = FirewallRequest::<TRq>::PeerSpecificRule { peer, rule_tx };
-        // Send request through the firewall channel, add to pending rule requests.
-        let send_firewall = Self::send_firewall(self.permission_req_channel.clone(), firewall_req).map_err(|_| ());
-
-        let future = send_firewall
-            .and_then(move |()| async {
-                select_biased! {
-                    res = rule_rx.fuse() => res.map_err(|_| ()),
-                    _ = timeout.fuse() => Err(()),
-                    _ = abort_handle_rx.fuse() => Err(())
-
-                }
-            })
-            .map_ok_or_else(move |()| (peer, None), move |rule| (peer, Some(rule)))
-            .boxed();
+        let channel = self.permission_req_channel.clone();
+        let (future, abort_handle_tx) = peer_rule_query(channel, peer, self.config.firewall_timeout);
         self.pending_rule_rqs.push(future);
         self.rule_rq_handles.insert(peer, abort_handle_tx);
         self.request_manager.add_pending_rule_request(peer);
@@ -415,64 +2573,200 @@ where
     // A clone of the response-sender is handed. It is polled while a response from the firewall
     // is awaited
     fn query_request_approval(&mut self, peer: PeerId, request_id: RequestId, rq: TRq) {
-        let (approval_tx, approval_rx) = oneshot::channel();
-        let (abort_handle_tx, abort_handle_rx) = oneshot::channel();
-        let timeout = Delay::new(self.config.firewall_timeout);
-
-        let firewall_req = FirewallRequest::RequestApproval {
-            peer,
-            request: rq,
-            approval_tx,
-        };
-        let send_firewall = Self::send_firewall(self.permission_req_channel.clone(), firewall_req).map_err(|_| ());
-        let future = send_firewall
-            .and_then(move |()| async {
-                select_biased! {
-                    res = approval_rx.fuse() => res.map_err(|_| ()),
-                    _ = timeout.fuse() => Err(()),
-                    _ = abort_handle_rx.fuse() => Err(())
+        let channel = self.permission_req_channel.clone();
+        let (future, abort_handle_tx) = approval_query(channel, peer, request_id, rq, self.config.firewall_timeout);
+        self.pending_approval_rqs.push(future);
+        self.approval_rq_handles.insert(request_id, abort_handle_tx);
+    }
 
+    // Update the traffic statistics and the status of tracked requests for an action that is emitted by the
+    // `RequestManager`.
+    fn record_action(&mut self, action: &BehaviourAction<Rq, Rs>) {
+        match action {
+            BehaviourAction::OutboundOk { peer, request_id, .. } => {
+                self.traffic.peer_mut(*peer).requests_sent += 1;
+                self.update_request_status(request_id, RequestStatus::Sent);
+            }
+            BehaviourAction::OutboundReceivedRes { peer, request_id, .. } => {
+                self.traffic.peer_mut(*peer).responses_received += 1;
+                self.update_request_status(request_id, RequestStatus::Responded);
+            }
+            BehaviourAction::OutboundFailure {
+                peer,
+                request_id,
+                failure,
+            } => {
+                self.traffic.on_outbound_failure(*peer, failure);
+                self.update_request_status(request_id, RequestStatus::Failed(failure.clone()));
+            }
+            BehaviourAction::InboundFailure { peer, failure, .. } => self.traffic.on_inbound_failure(*peer, failure),
+            BehaviourAction::RequireDialAttempt(peer) => {
+                let dialing: Vec<_> = self
+                    .tracked_requests
+                    .iter()
+                    .filter(|(_, (p, status, _))| p == peer && *status == RequestStatus::Queued)
+                    .map(|(id, _)| *id)
+                    .collect();
+                for request_id in dialing {
+                    self.update_request_status(&request_id, RequestStatus::Dialing);
                 }
-            })
-            .map_ok_or_else(move |()| (request_id, false), move |b| (request_id, b))
-            .boxed();
+            }
+            BehaviourAction::InboundOk { .. } | BehaviourAction::SetInboundSupport { .. } => {}
+        }
+    }
 
-        self.pending_approval_rqs.push(future);
-        self.approval_rq_handles.insert(request_id, abort_handle_tx);
+    // Whether inbound requests from the peer are supported.
+    // Requests are only rejected on the protocol level if the firewall rejects all of them, and they can not be
+    // authorized with a capability token either.
+    fn supports_inbound(&self, peer: &PeerId) -> bool {
+        self.capability_verifier.is_some()
+            || self.firewall.priority_rules().next().is_some()
+            || !matches!(self.effective_rule(peer), Some(Rule::RejectAll))
     }
 
-    // Send a request through the firewall channel.
-    async fn send_firewall(
-        mut channel: mpsc::Sender<FirewallRequest<TRq>>,
-        request: FirewallRequest<TRq>,
-    ) -> Result<(), SendError> {
-        poll_fn(|cx: &mut Context<'_>| channel.poll_ready(cx)).await?;
-        channel.start_send(request)
+    // Set inbound protocol support for the active handlers of all connected peers.
+    fn update_inbound_support(&mut self) {
+        for peer in self.request_manager.connected_peers() {
+            let inbound_support = self.supports_inbound(&peer);
+            self.request_manager.set_inbound_support(peer, None, inbound_support);
+        }
     }
 
     // Handle a changed firewall rule for a peer.
     fn handle_updated_peer_rule(&mut self, peer: PeerId) {
         // Set inbound protocol support for the active handlers according to the new rule.
-        let rule = self.firewall.get_effective_rule(&peer);
-        let inbound_support = !matches!(rule, Some(Rule::RejectAll));
+        let inbound_support = self.supports_inbound(&peer);
         self.request_manager.set_inbound_support(peer, None, inbound_support);
+        let rule = self.effective_rule(&peer).cloned();
         // Query for individual request approval due to `Rule::Ask`.
-        if let Some(ask_reqs) = self.request_manager.on_peer_rule(peer, rule.cloned()) {
+        if let Some(ask_reqs) = self.request_manager.on_peer_rule(peer, rule) {
             ask_reqs.into_iter().for_each(|(id, rq)| {
                 self.query_request_approval(peer, id, rq);
             })
         }
+        self.flush_hellos(peer);
+    }
+
+    // Handle the identity rotation that a peer announced, or the result of sending the local announcement.
+    fn handle_rotation_event(&mut self, peer: PeerId, event: RotationHandlerEvent) {
+        let message = match event {
+            RotationHandlerEvent::Received(message) => message,
+            RotationHandlerEvent::Sent(_) => {
+                self.on_rotation_announced(&peer, true);
+                return;
+            }
+            RotationHandlerEvent::SendFailed(_) => {
+                self.on_rotation_announced(&peer, false);
+                return;
+            }
+        };
+        let is_rejected = matches!(self.effective_rule(&peer), Some(Rule::RejectAll));
+        if message.new_peer == peer || is_rejected {
+            return;
+        }
+        for address in message.addrs.iter() {
+            self.add_address(message.new_peer, address.clone());
+        }
+        let event = BehaviourEvent::PeerIdentityRotated {
+            peer,
+            new_peer: message.new_peer,
+            addrs: message.addrs,
+        };
+        self.pending_actions
+            .push_back(NetworkBehaviourAction::GenerateEvent(event));
+    }
+
+    // Mark the announcement of the local rotation to the peer as finished.
+    fn on_rotation_announced(&mut self, peer: &PeerId, is_sent: bool) {
+        let peers = match self.rotation.as_mut().and_then(|r| r.on_finished(peer, is_sent)) {
+            Some(peers) => peers,
+            None => return,
+        };
+        self.rotation = None;
+        let event = BehaviourEvent::RotationAnnounced { peers };
+        self.pending_actions
+            .push_back(NetworkBehaviourAction::GenerateEvent(event));
     }
 }
 
-impl<Rq, Rs, TRq> Libp2pNetworkBehaviour for NetworkBehaviour<Rq, Rs, TRq>
+// Send a `FirewallRequest::PeerSpecificRule` through the firewall channel.
+// Returns the future for the response, which resolves to `None` on timeout, and the handle for aborting it.
+pub(crate) fn peer_rule_query<TRq: Send + 'static>(
+    channel: mpsc::Sender<FirewallRequest<TRq>>,
+    peer: PeerId,
+    timeout: Duration,
+) -> (PendingPeerRuleRequest<TRq>, oneshot::Sender<()>) {
+    let (rule_tx, rule_rx) = oneshot::channel();
+    let (abort_handle_tx, abort_handle_rx) = oneshot::channel();
+    let timeout = Delay::new(timeout);
+
+    let firewall_req = FirewallRequest::<TRq>::PeerSpecificRule { peer, rule_tx };
+    let future = send_firewall(channel, firewall_req)
+        .map_err(|_| ())
+        .and_then(move |()| async {
+            select_biased! {
+                res = rule_rx.fuse() => res.map_err(|_| ()),
+                _ = timeout.fuse() => Err(()),
+                _ = abort_handle_rx.fuse() => Err(())
+
+            }
+        })
+        .map_ok_or_else(move |()| (peer, None), move |rule| (peer, Some(rule)))
+        .boxed();
+    (future, abort_handle_tx)
+}
+
+// Send a `FirewallRequest::RequestApproval` through the firewall channel.
+// Returns the future for the response, which resolves to a rejection on timeout, and the handle for aborting it.
+pub(crate) fn approval_query<TRq: Send + 'static>(
+    channel: mpsc::Sender<FirewallRequest<TRq>>,
+    peer: PeerId,
+    request_id: RequestId,
+    rq: TRq,
+    timeout: Duration,
+) -> (PendingApprovalRequest, oneshot::Sender<()>) {
+    let (approval_tx, approval_rx) = oneshot::channel();
+    let (abort_handle_tx, abort_handle_rx) = oneshot::channel();
+    let timeout = Delay::new(timeout);
+
+    let firewall_req = FirewallRequest::RequestApproval {
+        peer,
+        request: rq,
+        approval_tx,
+    };
+    let future = send_firewall(channel, firewall_req)
+        .map_err(|_| ())
+        .and_then(move |()| async {
+            select_biased! {
+                res = approval_rx.fuse() => res.map_err(|_| ()),
+                _ = timeout.fuse() => Err(()),
+                _ = abort_handle_rx.fuse() => Err(())
+
+            }
+        })
+        .map_ok_or_else(move |()| (request_id, false), move |b| (request_id, b))
+        .boxed();
+    (future, abort_handle_tx)
+}
+
+// Send a request through the firewall channel.
+async fn send_firewall<TRq>(
+    mut channel: mpsc::Sender<FirewallRequest<TRq>>,
+    request: FirewallRequest<TRq>,
+) -> Result<(), SendError> {
+    poll_fn(|cx: &mut Context<'_>| channel.poll_ready(cx)).await?;
+    channel.start_send(request)
+}
+
+impl<Rq, Rs, TRq, TCustom> Libp2pNetworkBehaviour for NetworkBehaviour<Rq, Rs, TRq, TCustom>
 where
     Rq: RqRsMessage,
     Rs: RqRsMessage,
     TRq: FwRequest<Rq>,
+    TCustom: Libp2pNetworkBehaviour,
 {
-    type ConnectionHandler = ProtoHandler<Rq, Rs>;
-    type OutEvent = BehaviourEvent<Rq, Rs>;
+    type ConnectionHandler = ProtoHandler<Rq, Rs, TCustom>;
+    type OutEvent = BehaviourEvent<Rq, Rs, TCustom::OutEvent>;
 
     fn new_handler(&mut self) -> Self::ConnectionHandler {
         self.new_handler_for_peer(None)
@@ -487,7 +2781,48 @@ where
         match event {
             EitherOutput::First(ev) => self.handle_handler_event(peer, connection, ev),
             EitherOutput::Second(EitherOutput::First(ev)) => self.mdns.inject_event(peer, connection, ev),
-            EitherOutput::Second(EitherOutput::Second(ev)) => self.relay.inject_event(peer, connection, ev),
+            EitherOutput::Second(EitherOutput::Second(EitherOutput::First(ev))) => {
+                // The events of the relay handler are not public, so relay requests of a peer that is not allowed to
+                // relay through the local peer can only be rejected by dropping all relay events from that peer.
+                // This does not apply to relays that are used by the local peer.
+                let is_used_relay = self.addresses.relays().contains(&peer);
+                if is_used_relay || self.config.relay_policy.is_client_allowed(&peer) {
+                    self.relay.inject_event(peer, connection, ev)
+                }
+            }
+            EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(EitherOutput::First(
+                EitherOutput::First(ev),
+            )))) => self.ping.inject_event(peer, connection, ev),
+            EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(EitherOutput::First(
+                EitherOutput::Second(ev),
+            )))) => self.identify.inject_event(peer, connection, ev),
+            EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(
+                EitherOutput::First(ev),
+            )))) => self.handle_mailbox_event(peer, ev),
+            EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(
+                EitherOutput::Second(EitherOutput::First(ev)),
+            )))) => self.handle_transfer_event(peer, ev),
+            EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(
+                EitherOutput::Second(EitherOutput::Second(EitherOutput::First(ev))),
+            )))) => self.handle_presence_event(peer, ev),
+            EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(
+                EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(EitherOutput::First(ev)))),
+            )))) => self.handle_block_event(peer, ev),
+            EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(
+                EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(
+                    EitherOutput::First(ev),
+                )))),
+            )))) => self.handle_hello_event(peer, ev),
+            EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(
+                EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(
+                    EitherOutput::Second(EitherOutput::First(ev)),
+                )))),
+            )))) => self.handle_rotation_event(peer, ev),
+            EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(
+                EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(
+                    EitherOutput::Second(EitherOutput::Second(ev)),
+                )))),
+            )))) => self.custom.inject_event(peer, connection, ev),
         };
     }
 
@@ -496,9 +2831,24 @@ where
         cx: &mut Context<'_>,
         _params: &mut impl PollParameters,
     ) -> Poll<NetworkBehaviourAction<Self::OutEvent, Self::ConnectionHandler>> {
+        if self.supported_protocols.is_empty() {
+            self.supported_protocols = _params
+                .supported_protocols()
+                .map(|p| String::from_utf8_lossy(&p).into_owned())
+                .collect();
+        }
+
         // Drive mdns.
         let _ = self.mdns.poll(cx, _params);
 
+        // Fail the requests of a closed connection if the reason was not resolved with the swarm event.
+        if let Some(closed) = self.closed_connection.take() {
+            self.fail_closed_requests(closed, CloseReason::Remote);
+        }
+
+        self.check_inbound_watermarks();
+        self.log_journal_errors();
+
         // Update firewall rule if a peer specific rule was returned after a `FirewallRequest::PeerSpecificRule` query.
         while let Poll::Ready(Some((peer, rule))) = self.pending_rule_rqs.poll_next_unpin(cx) {
             if let Some(rule) = rule {
@@ -513,6 +2863,106 @@ where
             self.request_manager.on_request_approval(request_id, is_allowed);
         }
 
+        // Cache the responses to requests with an idempotency key, and forward them to the remote.
+        while let Poll::Ready(Some((peer, key, res, response_tx))) = self.idempotent_responses.poll_next_unpin(cx) {
+            if let Some(cache) = self.idempotency.as_mut() {
+                match res {
+                    Ok(response) => {
+                        cache.on_response(peer, key, &response);
+                        let _ = response_tx.send(response);
+                    }
+                    Err(_) => cache.on_omission(peer, key),
+                }
+            }
+        }
+
+        // Handle the results of the ping protocol.
+        while let Poll::Ready(action) = self.ping.poll(cx, _params) {
+            if let NetworkBehaviourAction::GenerateEvent(event) = action {
+                #[cfg(feature = "metrics")]
+                if let Some(metrics) = self.metrics.as_ref() {
+                    metrics.record(&event);
+                }
+                let PingEvent { peer, result } = event;
+                let result = match result {
+                    Ok(PingSuccess::Ping { rtt }) => {
+                        let measured_at = Instant::now();
+                        self.latest_rtt.insert(peer, RttSample { rtt, measured_at });
+                        self.missed_probes.remove(&peer);
+                        self.on_relay_probe(peer, Ok(Some(rtt)));
+                        Ok(rtt)
+                    }
+                    Ok(PingSuccess::Pong) => continue,
+                    Err(failure) => {
+                        self.on_relay_probe(peer, Err(()));
+                        if !matches!(failure, PingFailure::Unsupported) {
+                            self.on_missed_probe(peer);
+                        }
+                        Err(failure.into())
+                    }
+                };
+                return Poll::Ready(NetworkBehaviourAction::GenerateEvent(BehaviourEvent::Ping {
+                    peer,
+                    result,
+                }));
+            }
+        }
+
+        // Emit the observed address of the latest identify info, once the swarm processed it.
+        if self.observed_addr_report.as_ref().map_or(false, |r| r.is_reported) {
+            let report = self.observed_addr_report.take().unwrap();
+            let previous = self.observed_addrs.insert(report.peer, report.address.clone());
+            if previous.as_ref() != Some(&report.address) || !report.promoted.is_empty() {
+                return Poll::Ready(NetworkBehaviourAction::GenerateEvent(BehaviourEvent::ObservedAddr {
+                    peer: report.peer,
+                    address: report.address,
+                    promoted: report.promoted,
+                }));
+            }
+        }
+
+        // Handle the actions of the identify protocol.
+        while let Poll::Ready(action) = self.identify.poll(cx, _params) {
+            #[cfg(feature = "metrics")]
+            if let (Some(metrics), NetworkBehaviourAction::GenerateEvent(event)) = (self.metrics.as_ref(), &action) {
+                metrics.record(event);
+            }
+            match action {
+                NetworkBehaviourAction::GenerateEvent(IdentifyEvent::Received { peer_id, info }) => {
+                    self.remote_protocols.insert(peer_id, info.protocols);
+                    self.observed_addr_report = Some(ObservedAddrReport {
+                        peer: peer_id,
+                        address: info.observed_addr,
+                        is_reported: false,
+                        promoted: Vec::new(),
+                    });
+                }
+                // The swarm adds the address to the external addresses, which is recorded in
+                // `inject_new_external_addr`.
+                NetworkBehaviourAction::ReportObservedAddr { address, score } => {
+                    if let Some(report) = self.observed_addr_report.as_mut() {
+                        report.is_reported = true;
+                    }
+                    return Poll::Ready(NetworkBehaviourAction::ReportObservedAddr { address, score });
+                }
+                NetworkBehaviourAction::NotifyHandler {
+                    peer_id,
+                    handler,
+                    event,
+                } => {
+                    let event = EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(EitherOutput::First(
+                        EitherOutput::Second(event),
+                    ))));
+                    return Poll::Ready(NetworkBehaviourAction::NotifyHandler {
+                        peer_id,
+                        handler,
+                        event,
+                    });
+                }
+                _ => {}
+            }
+        }
+
         // Handle events from the relay protocol.
         if let Poll::Ready(action) = self.relay.poll(cx, _params) {
             match action {
@@ -520,12 +2970,8 @@ where
                     opts,
                     handler: relay_handler,
                 } => {
-                    let rq_rs_handler = self.new_request_response_handler(opts.get_peer_id());
-                    let mdns_handler = self.mdns.new_handler();
-                    let handler = IntoConnectionHandler::select(
-                        rq_rs_handler,
-                        IntoConnectionHandler::select(mdns_handler, relay_handler),
-                    );
+                    let custom_handler = self.custom.new_handler();
+                    let handler = self.compose_handler(opts.get_peer_id(), relay_handler, custom_handler);
                     return Poll::Ready(NetworkBehaviourAction::Dial { opts, handler });
                 }
                 NetworkBehaviourAction::NotifyHandler {
@@ -533,7 +2979,7 @@ where
                     handler,
                     event,
                 } => {
-                    let event = EitherOutput::Second(EitherOutput::Second(event));
+                    let event = EitherOutput::Second(EitherOutput::Second(EitherOutput::First(event)));
                     return Poll::Ready(NetworkBehaviourAction::NotifyHandler {
                         peer_id,
                         handler,
@@ -542,30 +2988,214 @@ where
                 }
                 _ => {}
             }
-        }
-        // Emit events for pending requests and required dial attempts.
-        if let Some(event) = self.request_manager.take_next_action() {
+        }
+
+        // Forward the actions of the custom behaviour.
+        if let Poll::Ready(action) = self.custom.poll(cx, _params) {
+            let action = match action {
+                NetworkBehaviourAction::GenerateEvent(event) => {
+                    NetworkBehaviourAction::GenerateEvent(BehaviourEvent::Custom(event))
+                }
+                NetworkBehaviourAction::Dial {
+                    opts,
+                    handler: custom_handler,
+                } => {
+                    let relay_handler = self.relay.new_handler();
+                    let handler = self.compose_handler(opts.get_peer_id(), relay_handler, custom_handler);
+                    NetworkBehaviourAction::Dial { opts, handler }
+                }
+                NetworkBehaviourAction::NotifyHandler {
+                    peer_id,
+                    handler,
+                    event,
+                } => {
+                    let event = EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(
+                        EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(
+                            EitherOutput::Second(EitherOutput::Second(event)),
+                        )))),
+                    ))));
+                    NetworkBehaviourAction::NotifyHandler {
+                        peer_id,
+                        handler,
+                        event,
+                    }
+                }
+                NetworkBehaviourAction::ReportObservedAddr { address, score } => {
+                    NetworkBehaviourAction::ReportObservedAddr { address, score }
+                }
+                NetworkBehaviourAction::CloseConnection { peer_id, connection } => {
+                    NetworkBehaviourAction::CloseConnection { peer_id, connection }
+                }
+            };
+            return Poll::Ready(action);
+        }
+
+        // Give up wanted blocks that no peer provided within the timeout.
+        while let Poll::Ready(Some(hash)) = self.block_timeouts.poll_next_unpin(cx) {
+            if let Some(peers) = self.blocks.as_mut().and_then(|b| b.cancel(&hash)) {
+                for peer in peers {
+                    let message = BlockMessage::Cancel {
+                        hashes: vec![hash.clone()],
+                    };
+                    self.send_block_message(peer, message);
+                }
+                self.pending_actions.push_back(NetworkBehaviourAction::GenerateEvent(
+                    BehaviourEvent::BlockWantExpired { hash },
+                ));
+            }
+        }
+
+        // Report the expiry of requests that were deposited in a mailbox.
+        while let Poll::Ready(Some(request_id)) = self.deposit_timeouts.poll_next_unpin(cx) {
+            if let Some(recipient) = self.pending_deposits.remove(&request_id) {
+                return Poll::Ready(NetworkBehaviourAction::GenerateEvent(BehaviourEvent::MailboxReceipt {
+                    request_id,
+                    recipient,
+                    status: MailboxDelivery::Expired,
+                }));
+            }
+        }
+        // Drop queued requests whose TTL expired, unless they are currently sent.
+        while let Poll::Ready(Some(id)) = self.queue_timeouts.poll_next_unpin(cx) {
+            if !self.outbound_queue.as_ref().map_or(true, |q| q.is_in_flight(&id)) {
+                self.fail_queued_request(id, OutboundFailure::Expired);
+            }
+        }
+        // Dial peers whose backoff elapsed, if requests still await a connection to them.
+        while let Poll::Ready(Some(peer)) = self.dial_timeouts.poll_next_unpin(cx) {
+            self.delayed_dials.remove(&peer);
+            if self.request_manager.is_awaiting_connection(&peer) {
+                self.record_action(&BehaviourAction::RequireDialAttempt(peer));
+                return Poll::Ready(NetworkBehaviourAction::Dial {
+                    handler: self.new_handler_for_peer(Some(peer)),
+                    opts: DialOpts::peer_id(peer).condition(PeerCondition::Disconnected).build(),
+                });
+            }
+        }
+        // Send heartbeats to the connected peers, and report peers that timed out.
+        while let Some(Poll::Ready(expired)) = self.presence.as_mut().map(|p| p.poll_interval(cx)) {
+            for peer in expired {
+                self.pending_actions
+                    .push_back(NetworkBehaviourAction::GenerateEvent(BehaviourEvent::PeerOffline {
+                        peer,
+                    }));
+            }
+            for peer in self.request_manager.connected_peers() {
+                self.send_heartbeat(peer, NotifyHandler::Any);
+            }
+        }
+        // Send mailbox, transfer, presence and block messages, and emit the related events.
+        if let Some(action) = self.pending_actions.pop_front() {
+            return Poll::Ready(action);
+        }
+        // Report peers that reconnected too often.
+        if let Some((peer, connects)) = self.churn.take_flapping() {
+            return Poll::Ready(NetworkBehaviourAction::GenerateEvent(BehaviourEvent::PeerFlapping {
+                peer,
+                connects,
+            }));
+        }
+
+        // Emit events for pending requests and required dial attempts.
+        if let Some(mut event) = self.request_manager.take_next_action() {
+            // Postpone the dial attempt if the peer is still backing off from previous failures.
+            if let BehaviourAction::RequireDialAttempt(peer) = event {
+                if let Some(remaining) = self.dial_backoffs.remaining(&peer) {
+                    if self.delayed_dials.insert(peer) {
+                        self.dial_timeouts
+                            .push(Delay::new(remaining).map(move |_| peer).boxed());
+                    }
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+            }
+            if self.inbound_paused {
+                // Drop the request and response channel, which closes the inbound substream.
+                if let BehaviourAction::InboundOk { request_id, peer, .. } = event {
+                    event = BehaviourAction::InboundFailure {
+                        request_id,
+                        peer,
+                        failure: InboundFailure::Overloaded,
+                    };
+                }
+            }
+            if let BehaviourAction::InboundOk {
+                request_id,
+                peer,
+                request,
+                ..
+            } = &mut event
+            {
+                if let Err(reason) = self.middleware.on_inbound(peer, request) {
+                    event = BehaviourAction::InboundFailure {
+                        request_id: *request_id,
+                        peer: *peer,
+                        failure: InboundFailure::Intercepted(reason),
+                    };
+                }
+            }
+            if let BehaviourAction::OutboundReceivedRes {
+                request_id,
+                peer,
+                response,
+                ..
+            } = &mut event
+            {
+                if let Err(reason) = self.middleware.on_response(peer, *request_id, response) {
+                    event = BehaviourAction::OutboundFailure {
+                        request_id: *request_id,
+                        peer: *peer,
+                        failure: OutboundFailure::Intercepted(reason),
+                    };
+                }
+            }
+            self.record_action(&event);
+            self.event_log.record(EventSource::Behaviour, || format!("{:?}", event));
             let action = match event {
                 BehaviourAction::InboundOk {
                     request_id,
                     peer,
                     request,
                     response_tx,
-                } => NetworkBehaviourAction::GenerateEvent(BehaviourEvent::ReceivedRequest {
-                    peer,
-                    request_id,
-                    request,
-                    response_tx,
-                }),
+                } => {
+                    let (error_tx, deadline) = match self.remote_error_txs.remove(&request_id) {
+                        Some((tx, deadline)) => (Some(tx), Some(deadline)),
+                        None => (None, None),
+                    };
+                    let responder = Responder::new(response_tx, error_tx, deadline);
+                    let (metadata, priority, global_id) = self.request_metadata.remove(&request_id).unwrap_or_default();
+                    self.journal_accepted(request_id, peer, &request);
+                    self.awaiting_response.insert(request_id);
+                    let connection = self
+                        .request_manager
+                        .inbound_connection(&peer, &request_id)
+                        .map(|(id, endpoint)| RequestConnection::new(id, endpoint));
+                    NetworkBehaviourAction::GenerateEvent(BehaviourEvent::ReceivedRequest {
+                        peer,
+                        request_id,
+                        request,
+                        responder,
+                        connection,
+                        metadata,
+                        priority,
+                        global_id,
+                    })
+                }
                 BehaviourAction::InboundFailure {
                     request_id,
                     peer,
                     failure,
-                } => NetworkBehaviourAction::GenerateEvent(BehaviourEvent::InboundFailure {
-                    peer,
-                    request_id,
-                    failure,
-                }),
+                } => {
+                    self.request_metadata.remove(&request_id);
+                    self.awaiting_response.remove(&request_id);
+                    self.send_remote_error(request_id, &failure);
+                    self.journal_completed(request_id, ResponseStatus::Failed);
+                    NetworkBehaviourAction::GenerateEvent(BehaviourEvent::InboundFailure {
+                        peer,
+                        request_id,
+                        failure,
+                    })
+                }
                 BehaviourAction::OutboundOk {
                     request_id,
                     peer,
@@ -583,20 +3213,42 @@ where
                     request_id,
                     peer,
                     failure,
-                } => NetworkBehaviourAction::GenerateEvent(BehaviourEvent::OutboundFailure {
-                    peer,
-                    request_id,
-                    failure,
-                }),
+                } => match self.outbound_queue.as_mut().and_then(|q| q.take_in_flight(&request_id)) {
+                    Some(id) => {
+                        // A final failure is reported through the pending actions, continue with the next action.
+                        self.on_queued_request_failure(id, failure);
+                        cx.waker().wake_by_ref();
+                        return Poll::Pending;
+                    }
+                    None => NetworkBehaviourAction::GenerateEvent(BehaviourEvent::OutboundFailure {
+                        peer,
+                        request_id,
+                        failure,
+                    }),
+                },
                 BehaviourAction::OutboundReceivedRes {
                     request_id,
                     peer,
                     response,
-                } => NetworkBehaviourAction::GenerateEvent(BehaviourEvent::ReceivedResponse {
-                    peer,
-                    request_id,
-                    response,
-                }),
+                    rtt,
+                } => match self.outbound_queue.as_mut().and_then(|q| q.take_in_flight(&request_id)) {
+                    Some(id) => {
+                        if let Some(queue) = self.outbound_queue.as_mut() {
+                            queue.remove(&id);
+                        }
+                        NetworkBehaviourAction::GenerateEvent(BehaviourEvent::QueuedResponse {
+                            request_id: id,
+                            peer,
+                            result: Ok(response),
+                        })
+                    }
+                    None => NetworkBehaviourAction::GenerateEvent(BehaviourEvent::ReceivedResponse {
+                        peer,
+                        request_id,
+                        response,
+                        rtt,
+                    }),
+                },
                 BehaviourAction::RequireDialAttempt(peer) => NetworkBehaviourAction::Dial {
                     handler: self.new_handler_for_peer(Some(peer)),
                     opts: DialOpts::peer_id(peer).condition(PeerCondition::Disconnected).build(),
@@ -620,13 +3272,24 @@ where
     }
 
     fn addresses_of_peer(&mut self, peer: &PeerId) -> Vec<Multiaddr> {
-        let mut addresses = self.addresses.get_addrs(peer);
+        let relays = self.select_relays();
+        let mut known = self.addresses.get_addrs_via(peer, &relays);
         if let Some(relay) = self.relay.as_mut() {
-            addresses.extend(relay.addresses_of_peer(peer));
+            known.extend(relay.addresses_of_peer(peer));
+        }
+        // Skip relayed addresses via relays that are not allowed, including the later relays of multi-hop addresses.
+        let policy = &self.config.relay_policy;
+        known.retain(|a| relay_chain(a).iter().all(|r| policy.is_relay_allowed(r)));
+        let mut addresses = Vec::with_capacity(known.len());
+        for addr in known.into_iter().flat_map(|a| self.addresses.dial_addrs(peer, a)) {
+            if !addresses.contains(&addr) {
+                addresses.push(addr);
+            }
         }
         if let Some(mdns) = self.mdns.as_mut() {
             addresses.extend(mdns.addresses_of_peer(peer));
         }
+        addresses.extend(self.custom.addresses_of_peer(peer));
         addresses
     }
 
@@ -638,32 +3301,75 @@ where
         failed_addresses: Option<&Vec<Multiaddr>>,
         _other_established: usize,
     ) {
+        if let Some(relay) = self.relay.as_mut() {
+            relay.inject_connection_established(peer, connection, endpoint, failed_addresses, _other_established);
+        }
+
+        if let Some(mdns) = self.mdns.as_mut() {
+            mdns.inject_connection_established(peer, connection, endpoint, failed_addresses, _other_established);
+        }
+
+        if let Some(ping) = self.ping.as_mut() {
+            ping.inject_connection_established(peer, connection, endpoint, failed_addresses, _other_established);
+        }
+
+        if let Some(identify) = self.identify.as_mut() {
+            identify.inject_connection_established(peer, connection, endpoint, failed_addresses, _other_established);
+        }
+
+        if let Some(custom) = self.custom.as_mut() {
+            custom.inject_connection_established(peer, connection, endpoint, failed_addresses, _other_established);
+        }
+
+        // Close new connections that exceed the connection limits, without using them for requests, unless a
+        // connection of a lower priority can be evicted instead.
+        let connections = self.counted_connections().into_iter().map(|(p, _, i)| (p, i));
+        let new_connection = iter::once((*peer, endpoint.is_listener()));
+        if self
+            .connection_limits
+            .is_exceeded_by(connections.chain(new_connection), peer, endpoint.is_listener())
+            && !self.evict_for(*peer, endpoint.is_listener())
+        {
+            self.close_connection(*peer, *connection, CloseReason::ConnectionLimit);
+            self.excess_connections.insert(*connection);
+            return;
+        }
+
         // If the remote connected to us and there is no rule for inbound requests yet, query firewall.
-        if endpoint.is_listener() && self.firewall.get_effective_rule(peer).is_none() {
+        if endpoint.is_listener() && self.effective_rule(peer).is_none() {
             self.query_peer_rule(*peer);
         }
         // Set the protocol support for the remote peer.
-        let support_inbound = !matches!(self.firewall.get_effective_rule(peer), Some(Rule::RejectAll));
+        let support_inbound = self.supports_inbound(peer);
         self.request_manager
             .set_inbound_support(*peer, Some(*connection), support_inbound);
 
         if let Some(addrs) = failed_addresses {
             for addr in addrs {
-                self.addresses.deprioritize_addr(*peer, addr.clone());
+                self.addresses
+                    .on_dial_failure(*peer, addr.clone(), self.config.max_address_failures);
             }
         }
 
         self.request_manager
             .on_connection_established(*peer, *connection, endpoint.clone());
         self.addresses
-            .prioritize_addr(*peer, endpoint.get_remote_address().clone());
-
-        if let Some(relay) = self.relay.as_mut() {
-            relay.inject_connection_established(peer, connection, endpoint, failed_addresses, _other_established);
+            .on_dial_success(*peer, endpoint.get_remote_address().clone());
+        self.traffic.peer_mut(*peer).connections_established += 1;
+        self.traffic.on_peer_connected(peer);
+        self.traffic.on_connection_established(*connection, endpoint);
+        self.on_relay_probe(*peer, Ok(None));
+        self.churn.on_connection_established(*peer);
+        self.dial_backoffs.on_connection_established(peer);
+        self.flush_outboxes(*peer);
+        let queued = self.outbound_queue.as_ref().map(|q| q.pending_for(peer));
+        for id in queued.into_iter().flatten() {
+            self.send_queued_request(id);
         }
-
-        if let Some(mdns) = self.mdns.as_mut() {
-            mdns.inject_connection_established(peer, connection, endpoint, failed_addresses, _other_established);
+        if _other_established == 0 {
+            self.send_heartbeat(*peer, NotifyHandler::One(*connection));
+            self.send_hello(*peer, NotifyHandler::One(*connection));
+            self.send_want_list(*peer);
         }
     }
 
@@ -675,18 +3381,74 @@ where
         _handler: <Self::ConnectionHandler as IntoConnectionHandler>::Handler,
         remaining_established: usize,
     ) {
-        self.request_manager
-            .on_connection_closed(*peer, connection, remaining_established);
+        // Without the swarm event, the connection was closed by the remote or because of an error.
+        if let Some(closed) = self.closed_connection.take() {
+            self.fail_closed_requests(closed, CloseReason::Remote);
+        }
+        self.closed_connection = Some(ClosedConnection {
+            peer: *peer,
+            connection: *connection,
+            remaining: remaining_established,
+            local_reason: self.close_reasons.remove(connection),
+        });
+        self.evicted_connections.remove(connection);
+        // Connections that exceeded the limits were never counted as established.
+        if !self.excess_connections.remove(connection) {
+            self.traffic.peer_mut(*peer).connections_closed += 1;
+        }
+        self.traffic.on_connection_closed(connection);
         // Abort pending requests for firewall rule, if the peer completely disconnected.
         if remaining_established == 0 {
             let _ = self.rule_rq_handles.remove(peer);
+            let _ = self.latest_rtt.remove(peer);
+            let _ = self.missed_probes.remove(peer);
+            let _ = self.observed_addrs.remove(peer);
+            let _ = self.remote_protocols.remove(peer);
+            let _ = self.envelope_unsupported.remove(peer);
+            self.traffic.on_peer_disconnected(*peer);
+            self.churn.prune();
+            let interrupted: Vec<_> = self
+                .outbound_transfers
+                .iter()
+                .filter_map(|(id, t)| (t.peer == *peer).then(|| *id))
+                .collect();
+            for transfer_id in interrupted {
+                self.interrupt_transfer(transfer_id);
+            }
+            if let Some(blocks) = self.blocks.as_mut() {
+                blocks.remove_peer(peer);
+            }
+            if let Some(hellos) = self.hellos.as_mut() {
+                hellos.remove(peer);
+            }
+            if self.presence.as_mut().map_or(false, |p| p.remove(peer)) {
+                let event = BehaviourEvent::PeerOffline { peer: *peer };
+                self.pending_actions
+                    .push_back(NetworkBehaviourAction::GenerateEvent(event));
+            }
+            self.on_rotation_announced(peer, false);
         }
         let (_, select) = _handler.into_inner();
-        let (mdns_handler, relay_handler) = select.into_inner();
+        let (mdns_handler, select) = select.into_inner();
+        let (relay_handler, select) = select.into_inner();
+        let (info_handlers, select) = select.into_inner();
+        let (ping_handler, identify_handler) = info_handlers.into_inner();
+        let (_, select) = select.into_inner();
+        let (_, select) = select.into_inner();
+        let (_, select) = select.into_inner();
+        let (_, select) = select.into_inner();
+        let (_, select) = select.into_inner();
+        let (_, custom_handler) = select.into_inner();
         self.mdns
             .inject_connection_closed(peer, connection, _endpoint, mdns_handler, remaining_established);
         self.relay
             .inject_connection_closed(peer, connection, _endpoint, relay_handler, remaining_established);
+        self.ping
+            .inject_connection_closed(peer, connection, _endpoint, ping_handler, remaining_established);
+        self.identify
+            .inject_connection_closed(peer, connection, _endpoint, identify_handler, remaining_established);
+        self.custom
+            .inject_connection_closed(peer, connection, _endpoint, custom_handler, remaining_established);
     }
 
     fn inject_address_change(
@@ -704,21 +3466,61 @@ where
         if let Some(mdns) = self.mdns.as_mut() {
             mdns.inject_address_change(peer, connection, _old, new);
         }
+
+        if let Some(ping) = self.ping.as_mut() {
+            ping.inject_address_change(peer, connection, _old, new);
+        }
+
+        if let Some(identify) = self.identify.as_mut() {
+            identify.inject_address_change(peer, connection, _old, new);
+        }
+
+        if let Some(custom) = self.custom.as_mut() {
+            custom.inject_address_change(peer, connection, _old, new);
+        }
     }
 
     fn inject_dial_failure(
         &mut self,
         peer_id: Option<PeerId>,
         _handler: Self::ConnectionHandler,
-        _error: &libp2p::swarm::DialError,
+        error: &libp2p::swarm::DialError,
     ) {
         if let Some(peer) = peer_id {
+            if let libp2p::swarm::DialError::Transport(errors) = error {
+                for (addr, _) in errors {
+                    self.addresses
+                        .on_dial_failure(peer, addr.clone(), self.config.max_address_failures);
+                }
+            }
+            self.dial_backoffs.on_dial_failure(peer);
             self.request_manager.on_dial_failure(peer);
+            self.on_relay_probe(peer, Err(()));
+            for message in self.mailbox_outbox.remove(&peer).into_iter().flatten() {
+                self.on_mailbox_send_failed(peer, message);
+            }
+            for message in self.transfer_outbox.remove(&peer).into_iter().flatten() {
+                if let TransferMessage::Offer { id, .. } | TransferMessage::Chunk { id, .. } = message {
+                    self.interrupt_transfer(RequestId(id));
+                }
+            }
         }
         let (_, select) = _handler.into_inner();
-        let (mdns_handler, relay_handler) = select.into_inner();
-        self.mdns.inject_dial_failure(peer_id, mdns_handler, _error);
-        self.relay.inject_dial_failure(peer_id, relay_handler, _error);
+        let (mdns_handler, select) = select.into_inner();
+        let (relay_handler, select) = select.into_inner();
+        let (info_handlers, select) = select.into_inner();
+        let (ping_handler, identify_handler) = info_handlers.into_inner();
+        let (_, select) = select.into_inner();
+        let (_, select) = select.into_inner();
+        let (_, select) = select.into_inner();
+        let (_, select) = select.into_inner();
+        let (_, select) = select.into_inner();
+        let (_, custom_handler) = select.into_inner();
+        self.mdns.inject_dial_failure(peer_id, mdns_handler, error);
+        self.relay.inject_dial_failure(peer_id, relay_handler, error);
+        self.ping.inject_dial_failure(peer_id, ping_handler, error);
+        self.identify.inject_dial_failure(peer_id, identify_handler, error);
+        self.custom.inject_dial_failure(peer_id, custom_handler, error);
     }
 
     fn inject_listen_failure(
@@ -728,16 +3530,34 @@ where
         _handler: Self::ConnectionHandler,
     ) {
         let (_, select) = _handler.into_inner();
-        let (mdns_handler, relay_handler) = select.into_inner();
+        let (mdns_handler, select) = select.into_inner();
+        let (relay_handler, select) = select.into_inner();
+        let (info_handlers, select) = select.into_inner();
+        let (ping_handler, identify_handler) = info_handlers.into_inner();
+        let (_, select) = select.into_inner();
+        let (_, select) = select.into_inner();
+        let (_, select) = select.into_inner();
+        let (_, select) = select.into_inner();
+        let (_, select) = select.into_inner();
+        let (_, custom_handler) = select.into_inner();
         self.mdns
             .inject_listen_failure(_local_addr, _send_back_addr, mdns_handler);
         self.relay
             .inject_listen_failure(_local_addr, _send_back_addr, relay_handler);
+        self.ping
+            .inject_listen_failure(_local_addr, _send_back_addr, ping_handler);
+        self.identify
+            .inject_listen_failure(_local_addr, _send_back_addr, identify_handler);
+        self.custom
+            .inject_listen_failure(_local_addr, _send_back_addr, custom_handler);
     }
 
     fn inject_new_listener(&mut self, id: ListenerId) {
         self.mdns.inject_new_listener(id);
         self.relay.inject_new_listener(id);
+        self.ping.inject_new_listener(id);
+        self.identify.inject_new_listener(id);
+        self.custom.inject_new_listener(id);
     }
 
     fn inject_new_listen_addr(&mut self, _id: ListenerId, _addr: &Multiaddr) {
@@ -747,6 +3567,15 @@ where
         if let Some(relay) = self.relay.as_mut() {
             relay.inject_new_listen_addr(_id, _addr);
         }
+        if let Some(ping) = self.ping.as_mut() {
+            ping.inject_new_listen_addr(_id, _addr);
+        }
+        if let Some(identify) = self.identify.as_mut() {
+            identify.inject_new_listen_addr(_id, _addr);
+        }
+        if let Some(custom) = self.custom.as_mut() {
+            custom.inject_new_listen_addr(_id, _addr);
+        }
     }
 
     fn inject_expired_listen_addr(&mut self, id: ListenerId, addr: &Multiaddr) {
@@ -756,6 +3585,15 @@ where
         if let Some(relay) = self.relay.as_mut() {
             relay.inject_expired_listen_addr(id, addr);
         }
+        if let Some(ping) = self.ping.as_mut() {
+            ping.inject_expired_listen_addr(id, addr);
+        }
+        if let Some(identify) = self.identify.as_mut() {
+            identify.inject_expired_listen_addr(id, addr);
+        }
+        if let Some(custom) = self.custom.as_mut() {
+            custom.inject_expired_listen_addr(id, addr);
+        }
     }
 
     fn inject_listener_error(&mut self, id: ListenerId, err: &(dyn std::error::Error + 'static)) {
@@ -765,6 +3603,15 @@ where
         if let Some(relay) = self.relay.as_mut() {
             relay.inject_listener_error(id, err);
         }
+        if let Some(ping) = self.ping.as_mut() {
+            ping.inject_listener_error(id, err);
+        }
+        if let Some(identify) = self.identify.as_mut() {
+            identify.inject_listener_error(id, err);
+        }
+        if let Some(custom) = self.custom.as_mut() {
+            custom.inject_listener_error(id, err);
+        }
     }
 
     fn inject_listener_closed(&mut self, id: ListenerId, reason: Result<(), &std::io::Error>) {
@@ -774,15 +3621,36 @@ where
         if let Some(relay) = self.relay.as_mut() {
             relay.inject_listener_closed(id, reason);
         }
+        if let Some(ping) = self.ping.as_mut() {
+            ping.inject_listener_closed(id, reason);
+        }
+        if let Some(identify) = self.identify.as_mut() {
+            identify.inject_listener_closed(id, reason);
+        }
+        if let Some(custom) = self.custom.as_mut() {
+            custom.inject_listener_closed(id, reason);
+        }
     }
 
     fn inject_new_external_addr(&mut self, addr: &Multiaddr) {
+        if let Some(report) = self.observed_addr_report.as_mut().filter(|r| r.is_reported) {
+            report.promoted.push(addr.clone());
+        }
         if let Some(mdns) = self.mdns.as_mut() {
             mdns.inject_new_external_addr(addr);
         }
         if let Some(relay) = self.relay.as_mut() {
             relay.inject_new_external_addr(addr);
         }
+        if let Some(ping) = self.ping.as_mut() {
+            ping.inject_new_external_addr(addr);
+        }
+        if let Some(identify) = self.identify.as_mut() {
+            identify.inject_new_external_addr(addr);
+        }
+        if let Some(custom) = self.custom.as_mut() {
+            custom.inject_new_external_addr(addr);
+        }
     }
 
     fn inject_expired_external_addr(&mut self, addr: &Multiaddr) {
@@ -792,6 +3660,15 @@ where
         if let Some(relay) = self.relay.as_mut() {
             relay.inject_expired_external_addr(addr);
         }
+        if let Some(ping) = self.ping.as_mut() {
+            ping.inject_expired_external_addr(addr);
+        }
+        if let Some(identify) = self.identify.as_mut() {
+            identify.inject_expired_external_addr(addr);
+        }
+        if let Some(custom) = self.custom.as_mut() {
+            custom.inject_expired_external_addr(addr);
+        }
     }
 }
 
@@ -800,14 +3677,15 @@ pub trait RqRsMessage: Serialize + DeserializeOwned + Send + Sync + fmt::Debug +
 impl<TRq: Serialize + DeserializeOwned + Send + Sync + fmt::Debug + 'static> RqRsMessage for TRq {}
 
 /// Unique Id for each request.
-/// **Note**: This ID is only local and does not match the request's ID at the remote peer.
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+/// **Note**: This ID is only local and does not match the request's ID at the remote peer. It is only unique within
+/// the running instance, see [`GlobalRequestId`] for an id that is unique across peers and restarts.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct RequestId(u64);
 
 impl RequestId {
-    // Adds to the given value, returning the previous value as RequestId.
-    pub(crate) fn next(value: &Arc<AtomicU64>) -> Self {
-        RequestId(value.fetch_add(1, Ordering::Relaxed))
+    // Generate a new id with the given generator.
+    pub(crate) fn next(generator: &dyn RequestIdGenerator) -> Self {
+        RequestId(generator.next_id())
     }
 
     pub(crate) fn value(&self) -> u64 {
@@ -821,6 +3699,210 @@ impl fmt::Display for RequestId {
     }
 }
 
+/// Metadata that is sent alongside a request, e.g. tracing ids, tenancy information or auth material.
+pub type RequestMetadata = HashMap<String, String>;
+
+/// Priority class of a request.
+///
+/// Outbound requests of a higher class are sent before queued requests of a lower class. The class is transmitted
+/// to the remote, so that it can prioritize the processing, and firewall rules can be set per class.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum RequestPriority {
+    /// Background requests, e.g. synchronization.
+    Low,
+    /// Default class of all requests.
+    #[default]
+    Normal,
+    /// Latency sensitive requests, e.g. interactive queries.
+    High,
+}
+
+/// Connection on which an inbound request was received.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestConnection {
+    /// Id of the connection.
+    pub id: ConnectionId,
+    /// Address of the remote on the connection.
+    pub remote_addr: Multiaddr,
+    /// Whether the connection is relayed via a relay peer.
+    pub is_relayed: bool,
+}
+
+impl RequestConnection {
+    pub(crate) fn new(id: ConnectionId, endpoint: &ConnectedPoint) -> Self {
+        let is_relayed = match endpoint {
+            ConnectedPoint::Dialer { address, .. } => relay_of(address).is_some(),
+            ConnectedPoint::Listener {
+                local_addr,
+                send_back_addr,
+            } => relay_of(local_addr).is_some() || relay_of(send_back_addr).is_some(),
+        };
+        RequestConnection {
+            id,
+            remote_addr: endpoint.get_remote_address().clone(),
+            is_relayed,
+        }
+    }
+}
+
+/// Configuration of the worker threads that decode large inbound messages and encode the responses to inbound
+/// requests.
+///
+/// Per default, messages are decoded and encoded on the task of the connection, which means that a huge message
+/// stalls all other substreams of that connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeWorkers {
+    /// Number of worker threads.
+    pub threads: usize,
+    /// Minimal size in bytes of a message for it to be decoded in a worker thread.
+    /// Smaller messages are decoded directly.
+    pub min_size: usize,
+}
+
+/// Sizing of the internal queues, to adjust the memory usage for memory-constrained devices or high-throughput
+/// servers.
+///
+/// **Note**: The inline capacities of the small vectors that are used for pending requests of a peer are fixed at
+/// compile time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferTuning {
+    /// Capacity above which an empty queue is shrunk to free its memory.
+    pub shrink_threshold: usize,
+    /// Capacity that is preallocated for the queue of actions in the `NetworkBehaviour`.
+    pub action_queue_capacity: usize,
+    /// Capacity that is preallocated for the queues of pending events and outbound requests in each connection
+    /// handler.
+    pub handler_queue_capacity: usize,
+}
+
+impl Default for BufferTuning {
+    fn default() -> Self {
+        BufferTuning {
+            shrink_threshold: EMPTY_QUEUE_SHRINK_THRESHOLD,
+            action_queue_capacity: 0,
+            handler_queue_capacity: 0,
+        }
+    }
+}
+
+/// Configuration for sending multiple outbound requests to the same peer as batch on one substream.
+///
+/// **Note**: Batches are only supported by remote peers that also use a version of this library with batch support.
+/// If the remote rejects a batch, the requests in it are sent one by one instead, and no more batches are sent on that
+/// connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestBatching {
+    /// Time window in which outbound requests are collected before they are sent.
+    pub window: Duration,
+    /// Maximum number of requests in a batch. The batch is sent immediately once this number is reached.
+    pub max_requests: usize,
+}
+
+/// Strategy for assigning an outbound request to one of multiple connections to the same peer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConnectionSelection {
+    /// Pseudo-random connection, derived from the request id.
+    #[default]
+    Random,
+    /// Cycle through the connections.
+    RoundRobin,
+    /// Connection with the fewest inbound and outbound requests in flight.
+    LeastLoaded,
+}
+
+/// Strategy for choosing among multiple relays, when dialing a peer via relays or when listening via a relay.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RelaySelection {
+    /// Try the relays in the order in which they were added.
+    #[default]
+    FallbackOrder,
+    /// Cycle through the relays, so that each dial attempt starts with a different relay.
+    RoundRobin,
+    /// Prefer the relay with the lowest round-trip time of the latest ping.
+    /// Relays without a measured round-trip time are tried last.
+    LowestLatency,
+}
+
+/// Dialing relay, ranked by its health.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayCandidate {
+    pub peer: PeerId,
+    /// Known address of the relay.
+    pub address: Option<Multiaddr>,
+    /// Round-trip time of the latest successful ping to the relay.
+    pub rtt: Option<Duration>,
+    /// Number of consecutive failed dial attempts and pings.
+    pub failures: u32,
+}
+
+/// Periodic probing of established connections with the ping protocol, to detect peers that stopped responding, e.g.
+/// because of a half-open TCP connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeepAliveProbes {
+    /// Interval in which each connection is probed.
+    pub interval: Duration,
+    /// Time after which a probe without response counts as missed.
+    pub timeout: Duration,
+    /// Number of consecutive missed probes after which the peer is considered unresponsive, and its connections are
+    /// closed.
+    pub max_missed: NonZeroU32,
+}
+
+impl Default for KeepAliveProbes {
+    fn default() -> Self {
+        KeepAliveProbes {
+            interval: Duration::from_secs(15),
+            timeout: Duration::from_secs(20),
+            max_missed: NonZeroU32::new(3).unwrap(),
+        }
+    }
+}
+
+/// Thresholds for the number of inbound requests that await a peer rule or an individual approval from the firewall,
+/// to signal the application that it should shed load before [`ConfigConfig::max_pending_inbound`] is exceeded.
+///
+/// An event is emitted once the number reaches `high`, and again once it fell to `low`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InboundWatermarks {
+    high: usize,
+    low: usize,
+}
+
+impl InboundWatermarks {
+    /// Create new watermarks. The `low` watermark has to be lower than the `high` one, otherwise [`None`] is returned.
+    pub fn new(high: usize, low: usize) -> Option<Self> {
+        (low < high).then(|| InboundWatermarks { high, low })
+    }
+
+    pub fn high(&self) -> usize {
+        self.high
+    }
+
+    pub fn low(&self) -> usize {
+        self.low
+    }
+}
+
+// Health of a dialing relay.
+#[derive(Debug, Clone, Copy, Default)]
+struct RelayHealth {
+    // Consecutive failed dial attempts and pings.
+    failures: u32,
+    // Round-trip time of the latest successful ping.
+    rtt: Option<Duration>,
+}
+
+// Address under which a remote observed the local peer, as reported via identify.
+#[derive(Debug)]
+struct ObservedAddrReport {
+    peer: PeerId,
+    address: Multiaddr,
+    // Whether the address was reported to the swarm.
+    is_reported: bool,
+    // New external addresses that the swarm added for the report.
+    promoted: Vec<Multiaddr>,
+}
+
 /// Configuration of the `NetworkBehaviour`.
 pub struct ConfigConfig {
     /// Supported versions of the `MessageProtocol`.
@@ -833,6 +3915,67 @@ pub struct ConfigConfig {
     ///
     /// See `Network` docs for more info.
     pub firewall_timeout: Duration,
+    /// Number of recent actions and swarm events that are kept for debugging.
+    /// The log is disabled if set to 0.
+    pub event_log_size: usize,
+    /// Optional detection of peers that reconnect too often.
+    pub flap_detection: Option<FlapDetection>,
+    /// Remove known addresses of a peer after this number of consecutive failed dial attempts on them.
+    pub max_address_failures: Option<u32>,
+    /// Optional backoff for dialing peers after failed dial attempts.
+    /// While a peer is backing off, outbound requests to it wait for the backoff to elapse before it is dialed again.
+    pub dial_backoff: Option<DialBackoff>,
+    /// Maximum number of inbound requests that await a firewall rule or approval.
+    /// If the limit is exceeded, the oldest request fails with [`InboundFailure::Evicted`].
+    pub max_pending_inbound: Option<usize>,
+    /// Maximum number of outbound requests that await a connection to the remote.
+    /// If the limit is exceeded, the oldest request fails with [`OutboundFailure::Evicted`].
+    pub max_pending_outbound: Option<usize>,
+    /// Sizing of internal buffers.
+    pub buffer_tuning: BufferTuning,
+    /// Strategy for assigning outbound requests to a connection, if there are multiple connections to a peer.
+    pub connection_selection: ConnectionSelection,
+    /// Strategy for choosing among the known relays.
+    pub relay_selection: RelaySelection,
+    /// Restrictions on the relays that are used, and on the peers that may relay through the local peer.
+    pub relay_policy: RelayPolicy,
+    /// Optionally send multiple outbound requests to the same peer as batch on one substream.
+    pub request_batching: Option<RequestBatching>,
+    /// Optional worker threads for decoding large inbound messages.
+    pub decode_workers: Option<DecodeWorkers>,
+    /// Maximum total size in bytes of the requests that are cached while they await approval or a connection.
+    /// If the limit would be exceeded, new requests fail with [`InboundFailure::MaxBuffered`] or
+    /// [`OutboundFailure::MaxBuffered`].
+    pub max_buffered_bytes: Option<usize>,
+    /// Duration after which remotes are asked to retry requests that were rejected because the local peer is
+    /// overloaded. If set, the remote receives [`RemoteError::Busy`] instead of [`RemoteError::Overloaded`].
+    pub busy_retry_after: Option<Duration>,
+    /// Write outbound requests to the same peer in the order in which they were submitted.
+    /// A request is held back until the previous request to that peer was written to its substream or failed.
+    ///
+    /// **Note**: Only the local writes are ordered, the remote may still receive the requests in a different order.
+    pub ordered_delivery: bool,
+    /// Store requests on behalf of other peers for offline recipients, i.e. act as mailbox.
+    pub mailbox: Option<MailboxConfig>,
+    /// Mailboxes from which forwarded requests are accepted.
+    pub trusted_mailboxes: HashSet<PeerId>,
+    /// Accept inbound blob transfers. The chunk size of this config is also used for outbound transfers.
+    pub transfer: Option<TransferConfig>,
+    /// Optionally detect inbound requests that were retransmitted with the same idempotency key, and answer them with
+    /// the cached response.
+    pub idempotency: Option<IdempotencyConfig>,
+    /// Handling of new connections that exceed the limits for established connections.
+    pub limit_pressure_policy: LimitPressurePolicy,
+    /// Optionally exchange heartbeats with connected peers, to track which peers are online.
+    pub presence: Option<PresenceConfig>,
+    /// Initial status that is sent in heartbeats.
+    pub presence_status: Vec<u8>,
+    /// Optionally exchange hellos with the given local capabilities with each new peer.
+    pub hello: Option<Hello>,
+    /// Optionally close the connections to peers that missed multiple keep-alive probes in a row.
+    pub keep_alive_probes: Option<KeepAliveProbes>,
+    /// Optionally emit events if the number of inbound requests that await the firewall crosses the watermarks.
+    pub inbound_watermarks: Option<InboundWatermarks>,
 }
 
 impl Default for ConfigConfig {
@@ -842,13 +3985,38 @@ impl Default for ConfigConfig {
             connection_timeout: Duration::from_secs(10),
             request_timeout: Duration::from_secs(10),
             firewall_timeout: Duration::from_secs(10),
+            event_log_size: 0,
+            flap_detection: None,
+            dial_backoff: None,
+            max_address_failures: None,
+            max_pending_inbound: None,
+            max_pending_outbound: None,
+            buffer_tuning: BufferTuning::default(),
+            connection_selection: ConnectionSelection::default(),
+            relay_selection: RelaySelection::default(),
+            relay_policy: RelayPolicy::default(),
+            request_batching: None,
+            max_buffered_bytes: None,
+            busy_retry_after: None,
+            decode_workers: None,
+            ordered_delivery: false,
+            mailbox: None,
+            trusted_mailboxes: HashSet::new(),
+            transfer: None,
+            idempotency: None,
+            limit_pressure_policy: LimitPressurePolicy::default(),
+            presence: None,
+            presence_status: Vec::new(),
+            hello: None,
+            keep_alive_probes: None,
+            inbound_watermarks: None,
         }
     }
 }
 
 /// Requests and failure events emitted by the `NetworkBehaviour`.
 #[derive(Debug)]
-pub enum BehaviourEvent<Rq, Rs> {
+pub enum BehaviourEvent<Rq, Rs, TCustomEv> {
     /// An inbound request was received from a remote peer.
     /// The request was checked and approved by the firewall.
     ReceivedRequest {
@@ -856,8 +4024,18 @@ pub enum BehaviourEvent<Rq, Rs> {
         peer: PeerId,
         /// Request from the remote peer.
         request: Rq,
-        /// Channel for returning the response
-        response_tx: oneshot::Sender<Rs>,
+        /// Handle for answering or rejecting the request.
+        responder: Responder<Rs>,
+        /// Connection on which the request was received.
+        /// `None` if the request was forwarded by a mailbox, or the connection closed in the meantime.
+        connection: Option<RequestConnection>,
+        /// Metadata that the remote attached to the request. Empty if no metadata was attached.
+        metadata: RequestMetadata,
+        /// Priority class that the remote attached to the request.
+        priority: RequestPriority,
+        /// Id that the remote assigned to the request, scoped to the remote.
+        /// `None` if the request was sent without metadata or priority.
+        global_id: Option<GlobalRequestId>,
     },
     /// A failure occurred in the context of receiving an inbound request and sending a response.
     InboundFailure {
@@ -871,6 +4049,9 @@ pub enum BehaviourEvent<Rq, Rs> {
         peer: PeerId,
         /// Response from the remote peer.
         response: Rs,
+        /// Time between writing the request to the substream and receiving the response.
+        /// This excludes the time that the request waited for a connection or approval.
+        rtt: Duration,
     },
     /// A failure occurred in the context of sending an outbound request and receiving a response.
     OutboundFailure {
@@ -878,12 +4059,195 @@ pub enum BehaviourEvent<Rq, Rs> {
         peer: PeerId,
         failure: OutboundFailure,
     },
+    /// An established connection to the peer was closed to make room for a connection of a peer with a higher
+    /// priority.
+    ConnectionEvicted { peer: PeerId, in_favor_of: PeerId },
+    /// The peer connected more often than allowed by the configured [`FlapDetection`].
+    PeerFlapping {
+        peer: PeerId,
+        /// Number of connects within the configured window.
+        connects: u32,
+    },
+    /// The peer missed the configured number of [`KeepAliveProbes`] in a row, and its connections are closed.
+    PeerUnresponsive {
+        peer: PeerId,
+        /// Number of consecutive missed probes.
+        missed: u32,
+    },
+    /// The number of inbound requests that await the firewall reached the high [`InboundWatermarks`].
+    InboundQueueHigh { depth: usize },
+    /// The number of inbound requests that await the firewall fell to the low [`InboundWatermarks`].
+    InboundQueueLow { depth: usize },
+    /// A connected peer reported a new address under which it observed the local peer.
+    ObservedAddr {
+        peer: PeerId,
+        address: Multiaddr,
+        /// New external addresses of the local peer that resulted from the report.
+        promoted: Vec<Multiaddr>,
+    },
+    /// Result of a ping to a connected peer.
+    Ping {
+        peer: PeerId,
+        /// Round-trip time of the ping.
+        result: Result<Duration, PingErr>,
+    },
+    /// A connected peer announced that it rotated its identity, and the firewall permits it.
+    PeerIdentityRotated {
+        /// Old peer id of the peer.
+        peer: PeerId,
+        new_peer: PeerId,
+        /// Addresses of the new identity, that were added to the known addresses.
+        addrs: Vec<Multiaddr>,
+    },
+    /// The announcement of the local identity rotation was sent to all connected peers.
+    RotationAnnounced {
+        /// Peers that received the announcement.
+        peers: Vec<PeerId>,
+    },
+    /// Delivery status of a request that was sent via a mailbox.
+    MailboxReceipt {
+        request_id: RequestId,
+        recipient: PeerId,
+        status: MailboxDelivery,
+    },
+    /// Progress of an inbound or outbound blob transfer.
+    TransferProgress {
+        peer: PeerId,
+        transfer_id: RequestId,
+        is_inbound: bool,
+        /// Number of bytes that were transferred so far.
+        transferred: u64,
+        /// Total size of the blob.
+        total: u64,
+    },
+    /// An outbound blob transfer finished or failed.
+    TransferFinished {
+        peer: PeerId,
+        transfer_id: RequestId,
+        result: Result<(), TransferFailure>,
+    },
+    /// A blob was received and verified.
+    ReceivedBlob {
+        peer: PeerId,
+        /// Id that the sender assigned to the transfer.
+        transfer_id: RequestId,
+        /// Metadata of the blob, approved by the firewall.
+        metadata: Rq,
+        data: Vec<u8>,
+    },
+    /// Response or final failure of a request from the outbound queue.
+    QueuedResponse {
+        /// Id that was returned when the request was enqueued.
+        request_id: RequestId,
+        peer: PeerId,
+        result: Result<Rs, OutboundFailure>,
+    },
+    /// A connected peer sent its first heartbeat.
+    PeerOnline {
+        peer: PeerId,
+        /// Status that the peer sent in the heartbeat.
+        status: Vec<u8>,
+    },
+    /// A peer that was online disconnected, or did not send a heartbeat within the timeout.
+    PeerOffline { peer: PeerId },
+    /// A peer that is online sent a heartbeat with a new status.
+    PeerStatusChanged { peer: PeerId, status: Vec<u8> },
+    /// A newly connected peer sent its hello, and the firewall permits it.
+    PeerHello { peer: PeerId, hello: Hello },
+    /// A wanted block was received from a remote peer, and stored.
+    BlockReceived {
+        /// Hash of the block.
+        hash: Vec<u8>,
+        /// Peer that provided the block.
+        peer: PeerId,
+        data: Vec<u8>,
+    },
+    /// No peer provided a wanted block within the want timeout.
+    BlockWantExpired { hash: Vec<u8> },
+    /// Event of the custom behaviour.
+    Custom(TCustomEv),
 }
 
 /// The Relay protocol is not supported.
 #[derive(Debug)]
 pub struct RelayNotSupported;
 
+/// Round-trip time of a successful ping to a remote peer, and when it was measured.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RttSample {
+    /// Round-trip time of the ping.
+    pub rtt: Duration,
+    /// Time at which the pong was received.
+    pub measured_at: Instant,
+}
+
+impl RttSample {
+    /// Time that passed since the round-trip time was measured.
+    pub fn age(&self) -> Duration {
+        self.measured_at.elapsed()
+    }
+}
+
+/// Possible failures when pinging a remote peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PingErr {
+    /// The ping timed out before a pong was received.
+    Timeout,
+    /// The remote does not support the ping protocol.
+    Unsupported,
+    /// The ping failed for another reason.
+    Other(String),
+}
+
+impl From<PingFailure> for PingErr {
+    fn from(failure: PingFailure) -> Self {
+        match failure {
+            PingFailure::Timeout => PingErr::Timeout,
+            PingFailure::Unsupported => PingErr::Unsupported,
+            PingFailure::Other { error } => PingErr::Other(error.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for PingErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PingErr::Timeout => write!(f, "Timeout while waiting for a pong"),
+            PingErr::Unsupported => write!(f, "The remote does not support the ping protocol"),
+            PingErr::Other(e) => write!(f, "Ping failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for PingErr {}
+
+/// Lifecycle status of an outbound request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RequestStatus {
+    /// The request waits for a connection to the remote, or for a previous request if ordered delivery is enabled.
+    Queued,
+    /// The remote is not connected, and a connection attempt was started.
+    Dialing,
+    /// The request was sent to the remote on a connection, and awaits the response.
+    Sent,
+    /// The request was written to the substream, and thus left the local peer.
+    ///
+    /// Together with the round-trip time of the response, this allows to distinguish the time that the request was
+    /// queued locally from the time in the network and at the remote.
+    Written,
+    /// The response was received.
+    Responded,
+    /// The request failed.
+    Failed(OutboundFailure),
+}
+
+impl RequestStatus {
+    /// Whether the request completed, and the status won't change anymore.
+    pub fn is_final(&self) -> bool {
+        matches!(self, RequestStatus::Responded | RequestStatus::Failed(_))
+    }
+}
+
 /// Possible failures occurring in the context of sending an outbound request and receiving the response.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum OutboundFailure {
@@ -894,33 +4258,200 @@ pub enum OutboundFailure {
     Timeout,
     /// The request could not be sent because a dialing attempt failed.
     DialFailure,
-    /// The connection closed before a response was received.
+    /// The connection closed before a response was received, for the given reason.
     ///
     /// It is not known whether the request may have been
     /// received (and processed) by the remote peer.
-    ConnectionClosed,
+    ConnectionClosed(CloseReason),
     /// The remote supports none of the requested protocols.
-    UnsupportedProtocols,
+    UnsupportedProtocols {
+        /// Protocols that the remote advertised via identify, to detect a version mismatch.
+        /// `None` if the identify protocol is disabled, or the remote did not send its info yet.
+        remote_protocols: Option<Vec<String>>,
+    },
     /// `Network` was shut down before a response was received.
     Shutdown,
+    /// The request was dropped before it was sent, because the limit for requests awaiting a connection was reached.
+    Evicted,
+    /// The request could not be serialized.
+    InvalidRequest,
+    /// The request could not be cached until the remote is connected, because the limit of buffered bytes was
+    /// reached.
+    MaxBuffered,
+    /// The request from the outbound queue could not be delivered before its TTL expired.
+    Expired,
+    /// The remote received the request, but did not answer it and sent the reason instead.
+    Remote(RemoteError),
+    /// A [`RequestMiddleware`] failed the request before it was sent, or a [`ResponseHook`] failed the response, with
+    /// the given reason.
+    Intercepted(String),
+    /// The pending request was aborted by the local application before it was sent, e.g. with
+    /// [`Network::abort_pending`][crate::Network::abort_pending].
+    Aborted,
 }
 
 impl fmt::Display for OutboundFailure {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             OutboundFailure::Timeout => write!(f, "Timeout while waiting for a response"),
-            OutboundFailure::ConnectionClosed => write!(f, "Connection was closed before a response was received"),
-            OutboundFailure::UnsupportedProtocols => {
+            OutboundFailure::ConnectionClosed(reason) => {
+                write!(f, "Connection was closed before a response was received: {}", reason)
+            }
+            OutboundFailure::UnsupportedProtocols { remote_protocols: None } => {
                 write!(f, "The remote supports none of the requested protocols")
             }
+            OutboundFailure::UnsupportedProtocols {
+                remote_protocols: Some(protocols),
+            } => write!(
+                f,
+                "The remote supports none of the requested protocols, it advertised: [{}]",
+                protocols.join(", ")
+            ),
             OutboundFailure::DialFailure => write!(f, "Failed to dial the requested peer"),
             OutboundFailure::Shutdown => write!(f, "The local peer was shut down before a response was received."),
+            OutboundFailure::Evicted => write!(f, "Too many pending outbound requests, the request was dropped"),
+            OutboundFailure::InvalidRequest => write!(f, "The request could not be serialized"),
+            OutboundFailure::MaxBuffered => write!(f, "The limit of buffered bytes for pending requests was reached"),
+            OutboundFailure::Expired => write!(f, "The TTL of the queued request expired before it was delivered"),
+            OutboundFailure::Remote(err) => write!(f, "The remote did not answer the request: {}", err),
+            OutboundFailure::Intercepted(reason) => write!(f, "The request was failed by a middleware: {}", reason),
+            OutboundFailure::Aborted => write!(f, "The pending request was aborted"),
         }
     }
 }
 
 impl std::error::Error for OutboundFailure {}
 
+impl OutboundFailure {
+    /// Duration after which the remote asked to retry the request, if it rejected it with [`RemoteError::Busy`].
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            OutboundFailure::Remote(RemoteError::Busy { retry_after }) => Some(*retry_after),
+            _ => None,
+        }
+    }
+}
+
+/// Reason why a connection was closed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CloseReason {
+    /// The local application closed the connection, e.g. with
+    /// [`Network::disconnect_peer`][crate::Network::disconnect_peer].
+    Disconnect,
+    /// The local peer closed the connection to stay within the connection limits, see [`EstablishedLimits`].
+    ConnectionLimit,
+    /// The local peer banned the remote.
+    Banned,
+    /// The remote did not answer the keep-alive probes, see [`KeepAliveProbes`].
+    Unresponsive,
+    /// The connection was idle and its keep-alive expired.
+    KeepAliveTimeout,
+    /// The remote closed the connection.
+    Remote,
+    /// The connection failed with the given error.
+    Error(String),
+}
+
+impl CloseReason {
+    /// Whether the connection was closed by the local peer.
+    pub fn is_local(&self) -> bool {
+        matches!(
+            self,
+            CloseReason::Disconnect | CloseReason::ConnectionLimit | CloseReason::Banned | CloseReason::Unresponsive
+        )
+    }
+
+    // Reason derived from the cause of a closed connection. Connections without cause were closed by the local peer.
+    //
+    // I/O errors that indicate that the remote closed the transport or the multiplexer are reported as
+    // `CloseReason::Remote`, all other errors as `CloseReason::Error`.
+    pub(crate) fn from_cause(cause: Option<&ConnectionError<impl fmt::Display>>) -> Self {
+        match cause {
+            None => CloseReason::Disconnect,
+            Some(ConnectionError::KeepAliveTimeout) => CloseReason::KeepAliveTimeout,
+            Some(ConnectionError::IO(e)) => match e.kind() {
+                io::ErrorKind::UnexpectedEof
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::BrokenPipe => CloseReason::Remote,
+                _ if e.to_string().contains("connection is closed") => CloseReason::Remote,
+                _ => CloseReason::Error(e.to_string()),
+            },
+            Some(ConnectionError::Handler(e)) => CloseReason::Error(e.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CloseReason::Disconnect => write!(f, "Closed by the local peer"),
+            CloseReason::ConnectionLimit => write!(f, "Closed to stay within the connection limits"),
+            CloseReason::Banned => write!(f, "The remote was banned"),
+            CloseReason::Unresponsive => write!(f, "The remote did not answer the keep-alive probes"),
+            CloseReason::KeepAliveTimeout => write!(f, "The keep-alive of the idle connection expired"),
+            CloseReason::Remote => write!(f, "Closed by the remote"),
+            CloseReason::Error(err) => write!(f, "Connection error: {}", err),
+        }
+    }
+}
+
+// Connection that was closed in the behaviour, until the reason for closing it is resolved.
+#[derive(Debug)]
+struct ClosedConnection {
+    peer: PeerId,
+    connection: ConnectionId,
+    // Number of remaining connections to the peer.
+    remaining: usize,
+    // Reason if the connection was closed by the local peer.
+    local_reason: Option<CloseReason>,
+}
+
+/// Reason sent by a remote peer for not answering a request.
+///
+/// This allows to distinguish a peer that refused a request from a failure of the network.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RemoteError {
+    /// The firewall of the remote rejected the request.
+    NotPermitted,
+    /// The remote dropped the request because it has too many pending requests.
+    Overloaded,
+    /// The remote dropped the request because it is overloaded, and asked to retry it after the given duration at the
+    /// earliest.
+    Busy { retry_after: Duration },
+    /// The request was forwarded to the application of the remote, but it dropped the response channel.
+    /// This is also the reason for requests of a batch that were not answered.
+    Dropped,
+    /// The application of the remote rejected the request with the given reason.
+    Rejected(String),
+    /// The request was sent with a protocol version that the remote deprecated.
+    UpgradeRequired {
+        /// Name of the oldest protocol version that the remote still supports, e.g. `/p2p/1.0.0`.
+        min_version: String,
+    },
+    /// The remote could not decode the request because it does not know its type, e.g. because it runs an older
+    /// version with fewer request variants.
+    UnknownRequest,
+}
+
+impl fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RemoteError::NotPermitted => write!(f, "The firewall of the remote rejected the request"),
+            RemoteError::Overloaded => write!(f, "The remote is overloaded"),
+            RemoteError::Busy { retry_after } => write!(f, "The remote is busy, retry after {:?}", retry_after),
+            RemoteError::Dropped => write!(f, "The remote dropped the request without responding"),
+            RemoteError::Rejected(reason) => write!(f, "The remote rejected the request: {}", reason),
+            RemoteError::UpgradeRequired { min_version } => {
+                write!(f, "The remote requires protocol version {} or later", min_version)
+            }
+            RemoteError::UnknownRequest => write!(f, "The remote does not know the type of the request"),
+        }
+    }
+}
+
+impl std::error::Error for RemoteError {}
+
 /// Possible failures occurring in the context of receiving an inbound request and sending a response.
 ///
 /// **Note**: If the firewall is configured to block per se all requests from the remote peer, the protocol for inbound
@@ -928,12 +4459,30 @@ impl std::error::Error for OutboundFailure {}
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum InboundFailure {
     /// The inbound request timed out, either while reading the
-    /// incoming request or before a response is sent through [`RequestMessage.response_tx`].
+    /// incoming request or before a response is sent through the [`Responder`].
     Timeout,
     /// The local firewall blocked the request.
     NotPermitted,
-    /// The connection closed before a response could be send.
-    ConnectionClosed,
+    /// The connection closed before a response could be send, for the given reason.
+    ConnectionClosed(CloseReason),
+    /// The response channel was dropped by the application without sending a response.
+    ResponseOmitted,
+    /// The request was dropped before it was approved, because the limit for requests awaiting a firewall rule or
+    /// approval was reached.
+    Evicted,
+    /// The request was rejected because the channel for inbound requests was full.
+    Overloaded,
+    /// The request could not be cached while it awaits approval, because the limit of buffered bytes was reached.
+    MaxBuffered,
+    /// A [`RequestMiddleware`] failed the request before it was forwarded, with the given reason.
+    Intercepted(String),
+    /// The request could not be decoded because its type is unknown, e.g. because the remote runs a newer version
+    /// with additional request variants. The remote is informed with [`RemoteError::UnknownRequest`], unless the
+    /// request was part of a batch.
+    UnknownRequest,
+    /// The pending request was aborted by the local application while it awaited the firewall, e.g. with
+    /// [`Network::abort_pending`][crate::Network::abort_pending].
+    Aborted,
 }
 
 impl fmt::Display for InboundFailure {
@@ -941,15 +4490,44 @@ impl fmt::Display for InboundFailure {
         match self {
             InboundFailure::Timeout => write!(f, "Timeout while receiving request"),
             InboundFailure::NotPermitted => write!(f, "The firewall blocked the inbound request"),
-            InboundFailure::ConnectionClosed => {
-                write!(f, "The connection closed directly after the request was received")
+            InboundFailure::ConnectionClosed(reason) => {
+                write!(f, "The connection closed before a response was sent: {}", reason)
             }
+            InboundFailure::ResponseOmitted => write!(f, "The response channel was dropped without sending a response"),
+            InboundFailure::Evicted => write!(f, "Too many pending inbound requests, the request was dropped"),
+            InboundFailure::Overloaded => write!(f, "The channel for inbound requests is full"),
+            InboundFailure::MaxBuffered => write!(f, "The limit of buffered bytes for pending requests was reached"),
+            InboundFailure::Intercepted(reason) => write!(f, "The request was failed by a middleware: {}", reason),
+            InboundFailure::UnknownRequest => write!(f, "The type of the request is unknown"),
+            InboundFailure::Aborted => write!(f, "The pending request was aborted"),
         }
     }
 }
 
 impl std::error::Error for InboundFailure {}
 
+impl InboundFailure {
+    // Reason that is sent to the remote if the request is not answered because of the failure, with the delay after
+    // which the remote may retry a request that was dropped because the local peer is busy.
+    // Failures that the remote observes itself are not sent, e.g. omitted responses are reported by the protocol.
+    pub(crate) fn remote_error(&self, busy_retry_after: Option<Duration>) -> Option<RemoteError> {
+        match self {
+            InboundFailure::NotPermitted | InboundFailure::Intercepted(_) => Some(RemoteError::NotPermitted),
+            InboundFailure::Evicted | InboundFailure::Overloaded | InboundFailure::MaxBuffered => {
+                match busy_retry_after {
+                    Some(retry_after) => Some(RemoteError::Busy { retry_after }),
+                    None => Some(RemoteError::Overloaded),
+                }
+            }
+            InboundFailure::Timeout
+            | InboundFailure::ConnectionClosed(_)
+            | InboundFailure::ResponseOmitted
+            | InboundFailure::UnknownRequest
+            | InboundFailure::Aborted => None,
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use core::panic;
@@ -959,9 +4537,7 @@ mod test {
     use futures::{channel::mpsc, StreamExt};
     use libp2p::{
         core::{identity, upgrade, PeerId, Transport},
-        mdns::{Mdns, MdnsConfig},
         noise::{Keypair as NoiseKeypair, NoiseConfig, X25519Spec},
-        relay::v1::{new_transport_and_behaviour, RelayConfig},
         swarm::{Swarm, SwarmBuilder, SwarmEvent},
         tcp::TokioTcpConfig,
         yamux::YamuxConfig,
@@ -997,13 +4573,16 @@ mod test {
                 event = swarm1.select_next_some() => match event {
                     SwarmEvent::Behaviour(BehaviourEvent::ReceivedRequest {
                         peer,
-                        response_tx,
+                        responder,
                         request,
+                        connection,
                         ..
                     }) => {
                         assert_eq!(&request, &expected_ping);
                         assert_eq!(&peer, &peer2_id);
-                        response_tx.send(pong.clone()).unwrap();
+                        let connection = connection.expect("Request was received on a connection.");
+                        assert!(!connection.is_relayed);
+                        responder.respond(pong.clone()).unwrap();
                     }
                     SwarmEvent::Behaviour(e) => panic!("Peer1: Unexpected event: {:?}", e),
                     _ => {}
@@ -1051,7 +4630,7 @@ mod test {
         swarm2.behaviour_mut().send_request(peer1_id, ping.clone());
 
         // Wait for swarm 1 to receive request by swarm 2.
-        let response_tx = loop {
+        let responder = loop {
             futures::select_biased!(
                 event = swarm2.select_next_some() => {
                     if let SwarmEvent::Behaviour(..) =  event {
@@ -1061,13 +4640,13 @@ mod test {
                 event = swarm1.select_next_some() => match event {
                     SwarmEvent::Behaviour(BehaviourEvent::ReceivedRequest{
                         peer,
-                        response_tx,
+                        responder,
                         request,
                         ..
                     }) => {
                     assert_eq!(&request, &ping);
                     assert_eq!(&peer, &peer2_id);
-                    break response_tx
+                    break responder
                     },
                     SwarmEvent::Behaviour(e) => panic!("Peer1: Unexpected event: {:?}", e),
                     _ => {}
@@ -1080,19 +4659,17 @@ mod test {
 
         match swarm1.select_next_some().await {
             SwarmEvent::ConnectionClosed { peer_id, .. } if peer_id == peer2_id => {
-                assert!(response_tx.send(pong).is_err());
+                assert!(responder.respond(pong).is_err());
             }
             e => panic!("Peer1: Unexpected event: {:?}", e),
         }
     }
 
-    /// We expect the substream to be properly closed when response channel is dropped.
-    /// Since the ping protocol used here expects a response, the sender considers this
-    /// early close as a protocol violation which results in the connection being closed.
-    /// If the substream were not properly closed when dropped, the sender would instead
-    /// run into a timeout waiting for the response.
+    /// We expect the remote error to be sent and the substream to be properly closed when the response channel is
+    /// dropped. If the substream were not properly closed when dropped, the sender would instead run into a timeout
+    /// waiting for the response. Locally, the omitted response is reported as inbound failure.
     #[tokio::test]
-    async fn emits_inbound_connection_closed_if_channel_is_dropped() {
+    async fn emits_remote_error_if_channel_is_dropped() {
         let ping = Ping("ping".to_string().into_bytes());
 
         let (peer1_id, mut swarm1) = init_swarm().await;
@@ -1110,7 +4687,9 @@ mod test {
         swarm2.behaviour_mut().add_address(peer1_id, addr1.clone());
         let request_id = swarm2.behaviour_mut().send_request(peer1_id, ping.clone());
 
-        loop {
+        let mut is_omitted = false;
+        let mut is_remote_failure = false;
+        while !is_omitted || !is_remote_failure {
             futures::select_biased!(
                 event = swarm2.select_next_some() => {
                     match event {
@@ -1121,8 +4700,8 @@ mod test {
                         }) => {
                             assert_eq!(request_id, rq_id);
                             assert_eq!(peer, peer1_id);
-                            assert_eq!(failure, OutboundFailure::ConnectionClosed);
-                            break;
+                            assert_eq!(failure, OutboundFailure::Remote(RemoteError::Dropped));
+                            is_remote_failure = true;
                         }
                         SwarmEvent::Behaviour(other) => panic!("Peer2: unexpected event: {:?}", other),
                         _ => {}
@@ -1131,15 +4710,19 @@ mod test {
                 event = swarm1.select_next_some() => match event {
                     SwarmEvent::Behaviour(BehaviourEvent::ReceivedRequest{
                         peer,
-                        response_tx,
+                        responder,
                         request,
                         ..
                     }) => {
                         assert_eq!(&request, &ping);
                         assert_eq!(&peer, &peer2_id);
-                        drop(response_tx);
-                        continue;
+                        drop(responder);
                     },
+                    SwarmEvent::Behaviour(BehaviourEvent::InboundFailure { peer, failure, .. }) => {
+                        assert_eq!(peer, peer2_id);
+                        assert_eq!(failure, InboundFailure::ResponseOmitted);
+                        is_omitted = true;
+                    }
                     SwarmEvent::Behaviour(e) => panic!("Peer1: Unexpected event: {:?}", e),
                     _ => {}
                 },
@@ -1151,23 +4734,20 @@ mod test {
         let id_keys = identity::Keypair::generate_ed25519();
         let peer = id_keys.public().to_peer_id();
         let noise_keys = NoiseKeypair::<X25519Spec>::new().into_authentic(&id_keys).unwrap();
-        let transport = TokioTcpConfig::new();
-
-        let (transport, relay_behaviour) = new_transport_and_behaviour(RelayConfig::default(), transport);
-        let transport = transport
+        let transport = TokioTcpConfig::new()
             .upgrade(upgrade::Version::V1)
             .authenticate(NoiseConfig::xx(noise_keys).into_authenticated())
             .multiplex(YamuxConfig::default())
             .boxed();
 
-        let mdns = Mdns::new(MdnsConfig::default())
-            .await
-            .expect("Failed to create mdns behaviour.");
         let (dummy_tx, _) = mpsc::channel(10);
         let behaviour = NetworkBehaviour::new(
             ConfigConfig::default(),
-            Some(mdns),
-            Some(relay_behaviour),
+            None,
+            None,
+            None,
+            None,
+            None,
             dummy_tx,
             FirewallRules::allow_all(),
             None,
@@ -1,12 +1,13 @@
 // Copyright 2020-2021 IOTA Stiftung
 // SPDX-License-Identifier: Apache-2.0
 
+use super::mailbox::unix_now;
 use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
 use serde::{Deserialize, Serialize};
 use smallvec::SmallVec;
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 
-// Known addresses and relay config of a remote peer
+// Known addresses, relay config and tags of a remote peer
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PeerAddress {
     // Known addresses e.g. that have been explicitly added or already connected.
@@ -14,6 +15,14 @@ pub struct PeerAddress {
 
     // Try relay peer if a target can not be reached directly.
     use_relay_fallback: bool,
+
+    // Dial statistics of the known addresses.
+    #[serde(default)]
+    scores: HashMap<Multiaddr, AddressScore>,
+
+    // Application-defined labels of the peer, e.g. its role.
+    #[serde(default)]
+    tags: BTreeSet<String>,
 }
 
 impl Default for PeerAddress {
@@ -22,10 +31,25 @@ impl Default for PeerAddress {
             known: VecDeque::new(),
 
             use_relay_fallback: true,
+
+            scores: HashMap::new(),
+
+            tags: BTreeSet::new(),
         }
     }
 }
 
+/// Dial statistics of a known address of a peer.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AddressScore {
+    /// Number of connections that were established on the address.
+    pub successes: u32,
+    /// Number of failed dial attempts since the last connection was established on the address.
+    pub consecutive_failures: u32,
+    /// Unix timestamp in seconds of the last connection that was established on the address.
+    pub last_success: Option<u64>,
+}
+
 // Known relays and peer addresses.
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct AddressInfo {
@@ -40,13 +64,20 @@ impl AddressInfo {
     /// Known addresses of a peer ordered based on likeliness to be reachable.
     /// Optionally includes a relayed target address for each known dialing relay.
     pub fn get_addrs(&self, target: &PeerId) -> Vec<Multiaddr> {
+        self.get_addrs_via(target, &self.relays)
+    }
+
+    /// Known addresses of a peer, like [`AddressInfo::get_addrs`], but with the relayed addresses in the order of the
+    /// given relays.
+    /// Relays that are not among the known dialing relays are ignored.
+    pub fn get_addrs_via(&self, target: &PeerId, relays: &[PeerId]) -> Vec<Multiaddr> {
         let addrs = self.peers.get(target).cloned().unwrap_or_default();
-        #[allow(unused_mut)]
         let mut peer_addrs: Vec<Multiaddr> = addrs.known.into();
+        // Try addresses that failed repeatedly last, otherwise keep the order.
+        peer_addrs.sort_by_key(|a| addrs.scores.get(a).map_or(0, |s| s.consecutive_failures));
 
         if addrs.use_relay_fallback {
-            let relayed = self
-                .relays
+            let relayed = relays
                 .iter()
                 .filter_map(|r| self.get_relay_addr(r).map(|a| assemble_relayed_addr(*target, *r, a)));
             peer_addrs.extend(relayed);
@@ -54,6 +85,34 @@ impl AddressInfo {
         peer_addrs
     }
 
+    /// Addresses that are dialed for a known address of the target.
+    ///
+    /// The relay transport only supports a single circuit per address, hence a multi-hop address is dialed via its
+    /// first relay, which then forwards the connection with its own dialer. To handle failures of intermediate relays,
+    /// the target is additionally dialed directly via each later relay of the chain of which an address is known.
+    pub fn dial_addrs(&self, target: &PeerId, addr: Multiaddr) -> Vec<Multiaddr> {
+        let chain = relay_chain(&addr);
+        if chain.len() < 2 {
+            return vec![addr];
+        }
+        let mut addrs = vec![relay_prefix(&addr, 1).with(Protocol::P2p((*target).into()))];
+        for relay in &chain[1..] {
+            let relay_addr = self
+                .peers
+                .get(relay)
+                .and_then(|a| a.known.iter().find(|a| relay_of(a).is_none()).cloned());
+            if let Some(relay_addr) = relay_addr {
+                addrs.push(assemble_relayed_addr(*target, *relay, relay_addr));
+            }
+        }
+        addrs
+    }
+
+    /// Known dialing relays, in the order in which they were added.
+    pub fn relays(&self) -> &[PeerId] {
+        &self.relays
+    }
+
     /// Add address from the list of addresses that are tried when dialing the remote.
     pub fn add_addrs(&mut self, peer: PeerId, addr: Multiaddr) {
         let addrs = self.peers.entry(peer).or_default();
@@ -64,11 +123,100 @@ impl AddressInfo {
 
     /// Remove address from the list of addresses that are tried when dialing the remote.
     pub fn remove_address(&mut self, peer: &PeerId, addrs: &Multiaddr) {
-        if let Some(PeerAddress { known, .. }) = self.peers.get_mut(peer) {
+        if let Some(PeerAddress { known, scores, .. }) = self.peers.get_mut(peer) {
             known.retain(|a| a != addrs);
+            scores.remove(addrs);
+        }
+    }
+
+    /// Dial statistics of a known address of the peer.
+    pub fn address_score(&self, peer: &PeerId, addr: &Multiaddr) -> Option<&AddressScore> {
+        self.peers.get(peer)?.scores.get(addr)
+    }
+
+    /// Record that a connection was established on the address, and try it first when dialing the peer again.
+    pub fn on_dial_success(&mut self, peer: PeerId, addr: Multiaddr) {
+        let addr = strip_peer_id(peer, addr);
+        let score = self
+            .peers
+            .entry(peer)
+            .or_default()
+            .scores
+            .entry(addr.clone())
+            .or_default();
+        score.successes += 1;
+        score.consecutive_failures = 0;
+        score.last_success = Some(unix_now());
+        self.prioritize_addr(peer, addr);
+    }
+
+    /// Record a failed dial attempt on the address, and try it last when dialing the peer again.
+    /// The address is removed if it failed `max_failures` times in a row.
+    pub fn on_dial_failure(&mut self, peer: PeerId, addr: Multiaddr, max_failures: Option<u32>) {
+        let addr = strip_peer_id(peer, addr);
+        let score = self
+            .peers
+            .entry(peer)
+            .or_default()
+            .scores
+            .entry(addr.clone())
+            .or_default();
+        score.consecutive_failures += 1;
+        if max_failures.map_or(false, |max| score.consecutive_failures >= max) {
+            self.remove_address(&peer, &addr);
+        } else {
+            self.deprioritize_addr(peer, addr);
+        }
+    }
+
+    /// Remove all known addresses and the relay config of a peer.
+    /// The tags of the peer are kept.
+    pub fn remove_peer(&mut self, peer: &PeerId) {
+        if let Some(PeerAddress { tags, .. }) = self.peers.remove(peer) {
+            if !tags.is_empty() {
+                self.peers.insert(
+                    *peer,
+                    PeerAddress {
+                        tags,
+                        ..Default::default()
+                    },
+                );
+            }
         }
     }
 
+    /// Attach a tag to the peer. Returns `false` if the peer already had the tag.
+    pub fn add_tag(&mut self, peer: PeerId, tag: String) -> bool {
+        self.peers.entry(peer).or_default().tags.insert(tag)
+    }
+
+    /// Remove a tag from the peer. Returns `false` if the peer did not have the tag.
+    pub fn remove_tag(&mut self, peer: &PeerId, tag: &str) -> bool {
+        self.peers.get_mut(peer).map_or(false, |a| a.tags.remove(tag))
+    }
+
+    /// Tags of the peer in alphabetical order.
+    pub fn tags(&self, peer: &PeerId) -> impl Iterator<Item = &str> {
+        self.peers
+            .get(peer)
+            .into_iter()
+            .flat_map(|a| a.tags.iter().map(String::as_str))
+    }
+
+    /// Whether the peer has the tag.
+    pub fn has_tag(&self, peer: &PeerId, tag: &str) -> bool {
+        self.peers.get(peer).map_or(false, |a| a.tags.contains(tag))
+    }
+
+    /// All peers that have the tag.
+    pub fn peers_with_tag(&self, tag: &str) -> Vec<PeerId> {
+        self.peers
+            .iter()
+            .filter(|(_, a)| a.tags.contains(tag))
+            .map(|(peer, _)| *peer)
+            .collect()
+    }
+
     /// Configure whether to try reaching the target via a relay if no known address can be reached.
     pub fn set_relay_fallback(&mut self, peer: PeerId, use_relay_fallback: bool) {
         let addrs = self.peers.entry(peer).or_default();
@@ -138,7 +286,7 @@ impl AddressInfo {
     /// **Note**: Known relayed addresses for remote peers using this relay will not be influenced by this.
     pub fn remove_relay(&mut self, peer: &PeerId) -> bool {
         if self.relays.contains(peer) {
-            self.relays.retain(|p| p == peer);
+            self.relays.retain(|p| p != peer);
             true
         } else {
             false
@@ -146,17 +294,235 @@ impl AddressInfo {
     }
 }
 
+/// Restrictions on which relays may be used by the local peer, and which peers may use the local peer as relay.
+///
+/// Per default there are no restrictions.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayPolicy {
+    /// If set, only these relays are used for listening and dialing.
+    pub allowed_relays: Option<HashSet<PeerId>>,
+    /// Relays that are never used for listening and dialing.
+    pub denied_relays: HashSet<PeerId>,
+    /// If set, only these peers may relay through the local peer.
+    pub allowed_clients: Option<HashSet<PeerId>>,
+    /// Peers that may not relay through the local peer.
+    pub denied_clients: HashSet<PeerId>,
+}
+
+impl RelayPolicy {
+    /// Whether the relay may be used for listening and dialing.
+    pub fn is_relay_allowed(&self, relay: &PeerId) -> bool {
+        !self.denied_relays.contains(relay) && self.allowed_relays.as_ref().map_or(true, |a| a.contains(relay))
+    }
+
+    /// Whether the peer may relay through the local peer.
+    pub fn is_client_allowed(&self, peer: &PeerId) -> bool {
+        !self.denied_clients.contains(peer) && self.allowed_clients.as_ref().map_or(true, |a| a.contains(peer))
+    }
+}
+
+// Relay of a relayed address `<relay-addr>/p2p/<relay-id>/p2p-circuit[/p2p/<target-id>]`.
+pub fn relay_of(addr: &Multiaddr) -> Option<PeerId> {
+    let mut relay = None;
+    for protocol in addr.iter() {
+        match protocol {
+            Protocol::P2p(hash) => relay = PeerId::from_multihash(hash).ok(),
+            Protocol::P2pCircuit => return relay,
+            _ => {}
+        }
+    }
+    None
+}
+
+// Relays of a (multi-hop) relayed address, in the order of the hops.
+pub fn relay_chain(addr: &Multiaddr) -> Vec<PeerId> {
+    let mut chain = Vec::new();
+    let mut last_peer = None;
+    for protocol in addr.iter() {
+        match protocol {
+            Protocol::P2p(hash) => last_peer = PeerId::from_multihash(hash).ok(),
+            Protocol::P2pCircuit => chain.extend(last_peer.take()),
+            _ => {}
+        }
+    }
+    chain
+}
+
+// Prefix of a relayed address up to and including the `/p2p-circuit` of the given number of hops.
+fn relay_prefix(addr: &Multiaddr, hops: usize) -> Multiaddr {
+    let mut prefix = Multiaddr::empty();
+    let mut circuits = 0;
+    for protocol in addr.iter() {
+        if circuits == hops {
+            break;
+        }
+        if protocol == Protocol::P2pCircuit {
+            circuits += 1;
+        }
+        prefix.push(protocol);
+    }
+    prefix
+}
+
+// Remove the `/p2p/<peer-id>` suffix that the swarm appends to dialed addresses.
+// Relayed addresses always end with the peer id of the target.
+fn strip_peer_id(peer: PeerId, mut addr: Multiaddr) -> Multiaddr {
+    if relay_of(&addr).is_none() && addr.iter().last() == Some(Protocol::P2p(peer.into())) {
+        addr.pop();
+    }
+    addr
+}
+
 /// Assemble a relayed address for the target following the syntax
 /// `<relay-addr>/p2p/<relay-id>/p2p-circuit/p2p/<target-id>`.
 /// The address can be used to reach the target peer if they are listening on that relay.
-pub fn assemble_relayed_addr(target: PeerId, relay: PeerId, mut relay_addr: Multiaddr) -> Multiaddr {
-    let relay_proto = Multiaddr::empty()
-        .with(Protocol::P2p(relay.into()))
-        .with(Protocol::P2pCircuit);
+pub fn assemble_relayed_addr(target: PeerId, relay: PeerId, relay_addr: Multiaddr) -> Multiaddr {
+    assemble_relay_chain_addr(target, &[relay], relay_addr)
+}
 
-    if !relay_addr.ends_with(&relay_proto) {
-        relay_proto.into_iter().for_each(|p| relay_addr.push(p));
+/// Assemble a multi-hop relayed address for reaching the target through a chain of relays, following the syntax
+/// `<relay-addr>/p2p/<relay-id>/p2p-circuit/p2p/<next-relay-id>/p2p-circuit/../p2p/<target-id>`.
+/// The `relay_addr` is the address of the first relay in the chain.
+///
+/// Each relay forwards the connection to the next hop with its own dialer, so it has to be able to reach it, e.g.
+/// because the next relay is configured as dialing relay of the relay peer. If the first relays can not be reached,
+/// the target is dialed directly via the later relays of which an address is known.
+pub fn assemble_relay_chain_addr(target: PeerId, relays: &[PeerId], mut relay_addr: Multiaddr) -> Multiaddr {
+    if let Some((first, hops)) = relays.split_first() {
+        let relay_proto = Multiaddr::empty()
+            .with(Protocol::P2p((*first).into()))
+            .with(Protocol::P2pCircuit);
+        if !relay_addr.ends_with(&relay_proto) {
+            relay_proto.into_iter().for_each(|p| relay_addr.push(p));
+        }
+        for hop in hops {
+            relay_addr.push(Protocol::P2p((*hop).into()));
+            relay_addr.push(Protocol::P2pCircuit);
+        }
     }
     relay_addr.push(Protocol::P2p(target.into()));
     relay_addr
 }
+
+/// Complete an address of the peer to a dialable address that includes its peer id, e.g. for sharing it with other
+/// peers out-of-band.
+///
+/// For a relayed address the relayed form `<relay-addr>/p2p/<relay-id>/p2p-circuit/p2p/<peer-id>` is assembled, see
+/// [`assemble_relayed_addr`]. Otherwise `/p2p/<peer-id>` is appended if the address does not end with it already.
+pub fn dialable_addr(peer: PeerId, addr: Multiaddr) -> Multiaddr {
+    let hops = relay_chain(&addr).len();
+    if hops > 0 {
+        return relay_prefix(&addr, hops).with(Protocol::P2p(peer.into()));
+    }
+    if addr.iter().last() == Some(Protocol::P2p(peer.into())) {
+        addr
+    } else {
+        addr.with(Protocol::P2p(peer.into()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn address_scores() {
+        let peer = PeerId::random();
+        let addr_a: Multiaddr = "/ip4/10.0.0.1/tcp/4001".parse().unwrap();
+        let addr_b: Multiaddr = "/ip4/10.0.0.2/tcp/4001".parse().unwrap();
+        let mut info = AddressInfo::default();
+        info.set_relay_fallback(peer, false);
+        info.add_addrs(peer, addr_a.clone());
+        info.add_addrs(peer, addr_b.clone());
+
+        // Failures are recorded without the peer id suffix of dialed addresses.
+        let dialed = addr_a.clone().with(Protocol::P2p(peer.into()));
+        info.on_dial_failure(peer, dialed.clone(), Some(3));
+        info.on_dial_failure(peer, dialed.clone(), Some(3));
+        info.on_dial_failure(peer, addr_b.clone(), Some(3));
+        assert_eq!(info.get_addrs(&peer), vec![addr_b.clone(), addr_a.clone()]);
+        assert_eq!(info.address_score(&peer, &addr_a).unwrap().consecutive_failures, 2);
+
+        info.on_dial_success(peer, addr_b.clone());
+        let score = info.address_score(&peer, &addr_b).unwrap();
+        assert_eq!((score.successes, score.consecutive_failures), (1, 0));
+
+        // The address is dropped after failing three times in a row.
+        info.on_dial_failure(peer, dialed, Some(3));
+        assert_eq!(info.get_addrs(&peer), vec![addr_b]);
+        assert!(info.address_score(&peer, &addr_a).is_none());
+    }
+
+    #[test]
+    fn peer_tags() {
+        let peer_a = PeerId::random();
+        let peer_b = PeerId::random();
+        let addr: Multiaddr = "/ip4/10.0.0.1/tcp/4001".parse().unwrap();
+        let mut info = AddressInfo::default();
+        info.add_addrs(peer_a, addr);
+        assert!(info.add_tag(peer_a, "validator".into()));
+        assert!(info.add_tag(peer_a, "backup".into()));
+        assert!(!info.add_tag(peer_a, "validator".into()));
+        assert!(info.add_tag(peer_b, "validator".into()));
+        assert_eq!(info.tags(&peer_a).collect::<Vec<_>>(), vec!["backup", "validator"]);
+
+        let mut validators = info.peers_with_tag("validator");
+        validators.sort();
+        let mut expected = vec![peer_a, peer_b];
+        expected.sort();
+        assert_eq!(validators, expected);
+
+        // Tags are kept when the addresses of a peer are removed.
+        info.remove_peer(&peer_a);
+        assert!(info.get_addrs(&peer_a).is_empty());
+        assert!(info.has_tag(&peer_a, "backup"));
+
+        assert!(info.remove_tag(&peer_b, "validator"));
+        assert!(!info.remove_tag(&peer_b, "validator"));
+        assert_eq!(info.peers_with_tag("validator"), vec![peer_a]);
+    }
+
+    #[test]
+    fn complete_dialable_addr() {
+        let peer = PeerId::random();
+        let relay = PeerId::random();
+        let addr: Multiaddr = "/ip4/10.0.0.1/tcp/4001".parse().unwrap();
+        let expected = addr.clone().with(Protocol::P2p(peer.into()));
+        assert_eq!(dialable_addr(peer, addr.clone()), expected);
+        assert_eq!(dialable_addr(peer, expected.clone()), expected);
+
+        let expected = assemble_relayed_addr(peer, relay, addr.clone());
+        let listen_addr = addr.with(Protocol::P2p(relay.into())).with(Protocol::P2pCircuit);
+        assert_eq!(dialable_addr(peer, listen_addr), expected);
+        assert_eq!(dialable_addr(peer, expected.clone()), expected);
+    }
+
+    #[test]
+    fn relay_chain_addrs() {
+        let target = PeerId::random();
+        let relays = [PeerId::random(), PeerId::random(), PeerId::random()];
+        let relay_addr: Multiaddr = "/ip4/10.0.0.1/tcp/4001".parse().unwrap();
+        let chain_addr = assemble_relay_chain_addr(target, &relays, relay_addr.clone());
+        assert_eq!(relay_chain(&chain_addr), relays);
+        assert_eq!(dialable_addr(target, chain_addr.clone()), chain_addr);
+        assert_eq!(
+            assemble_relay_chain_addr(target, &relays[..1], relay_addr.clone()),
+            assemble_relayed_addr(target, relays[0], relay_addr.clone())
+        );
+
+        // The chain is dialed via the first relay, and directly via later relays with known addresses.
+        let mut info = AddressInfo::default();
+        let third_addr: Multiaddr = "/ip4/10.0.0.3/tcp/4001".parse().unwrap();
+        info.add_addrs(relays[2], third_addr.clone());
+        let dial_addrs = info.dial_addrs(&target, chain_addr);
+        assert_eq!(
+            dial_addrs,
+            vec![
+                assemble_relayed_addr(target, relays[0], relay_addr.clone()),
+                assemble_relayed_addr(target, relays[2], third_addr),
+            ]
+        );
+        let direct = relay_addr.with(Protocol::P2p(target.into()));
+        assert_eq!(info.dial_addrs(&target, direct.clone()), vec![direct]);
+    }
+}
@@ -0,0 +1,109 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::timer::Instant;
+use libp2p::PeerId;
+use rand::Rng;
+use std::{collections::HashMap, time::Duration};
+
+/// Configuration of the exponential backoff for dialing a peer after failed dial attempts.
+///
+/// The backoff after the first failure is `initial`, and it doubles with each further failure up to `max`. A random
+/// jitter of up to half the backoff is subtracted, so that peers that failed at the same time are not re-dialed
+/// simultaneously.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DialBackoff {
+    /// Backoff after the first failed dial attempt.
+    pub initial: Duration,
+    /// Maximum backoff.
+    pub max: Duration,
+}
+
+impl Default for DialBackoff {
+    fn default() -> Self {
+        DialBackoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(60),
+        }
+    }
+}
+
+impl DialBackoff {
+    // Backoff after the number of consecutive failures, without jitter.
+    fn backoff(&self, failures: u32) -> Duration {
+        let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+        self.initial.saturating_mul(factor).min(self.max)
+    }
+}
+
+// Recent dial failures of each peer, and the time until which it should not be dialed again.
+#[derive(Debug)]
+pub struct DialBackoffs {
+    config: Option<DialBackoff>,
+    // Number of consecutive failures and the end of the current backoff.
+    peers: HashMap<PeerId, (u32, Instant)>,
+}
+
+impl DialBackoffs {
+    pub fn new(config: Option<DialBackoff>) -> Self {
+        DialBackoffs {
+            config,
+            peers: HashMap::new(),
+        }
+    }
+
+    // Record a failed dial attempt, and start a new backoff for the peer.
+    pub fn on_dial_failure(&mut self, peer: PeerId) {
+        let config = match self.config {
+            Some(c) => c,
+            None => return,
+        };
+        let failures = self.peers.get(&peer).map_or(0, |(f, _)| *f) + 1;
+        let backoff = config.backoff(failures);
+        let jitter = rand::thread_rng().gen_range(Duration::ZERO..=backoff / 2);
+        self.peers.insert(peer, (failures, Instant::now() + (backoff - jitter)));
+    }
+
+    // Reset the backoff after a connection to the peer was established.
+    pub fn on_connection_established(&mut self, peer: &PeerId) {
+        self.peers.remove(peer);
+    }
+
+    // Remaining backoff for the peer, `None` if it may be dialed right away.
+    pub fn remaining(&self, peer: &PeerId) -> Option<Duration> {
+        let (_, until) = self.peers.get(peer)?;
+        let now = Instant::now();
+        (*until > now).then(|| *until - now)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn exponential_backoff() {
+        let config = DialBackoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(5),
+        };
+        assert_eq!(config.backoff(1), Duration::from_secs(1));
+        assert_eq!(config.backoff(3), Duration::from_secs(4));
+        assert_eq!(config.backoff(4), Duration::from_secs(5));
+        assert_eq!(config.backoff(100), Duration::from_secs(5));
+
+        let mut backoffs = DialBackoffs::new(Some(config));
+        let peer = PeerId::random();
+        assert!(backoffs.remaining(&peer).is_none());
+        backoffs.on_dial_failure(peer);
+        backoffs.on_dial_failure(peer);
+        let remaining = backoffs.remaining(&peer).unwrap();
+        assert!(remaining <= Duration::from_secs(2) && remaining >= Duration::from_millis(900));
+        backoffs.on_connection_established(&peer);
+        assert!(backoffs.remaining(&peer).is_none());
+
+        let mut disabled = DialBackoffs::new(None);
+        disabled.on_dial_failure(peer);
+        assert!(disabled.remaining(&peer).is_none());
+    }
+}
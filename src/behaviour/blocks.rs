@@ -0,0 +1,273 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use super::{
+    oneshot::{MessageHandler, MessageHandlerEvent, OneShotMessage},
+    transfer::digest,
+};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    time::Duration,
+};
+
+/// Maximum size of a single block.
+pub const MAX_BLOCK_SIZE: usize = 1024 * 1024;
+
+// Maximum number of wants of a remote peer that are kept until the blocks are stored. Further wants are ignored.
+const MAX_PEER_WANTS: usize = 1024;
+
+// Maximum length of a hash in a want, a SHA2-256 multihash has 34 bytes. Longer hashes are ignored.
+const MAX_HASH_LEN: usize = 64;
+
+/// Configuration of the block exchange.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockExchangeConfig {
+    /// Duration after which a wanted block that no connected peer provided is given up.
+    pub want_timeout: Duration,
+}
+
+impl Default for BlockExchangeConfig {
+    fn default() -> Self {
+        BlockExchangeConfig {
+            want_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Storage of the blocks that the local peer provides to other peers.
+///
+/// Blocks are identified by their hash, see [`block_hash`].
+pub trait BlockStore: Send + 'static {
+    /// Get the block with this hash, `None` if it is not stored.
+    fn get(&self, hash: &[u8]) -> Option<Vec<u8>>;
+
+    /// Store a new block.
+    fn insert(&mut self, hash: Vec<u8>, data: Vec<u8>);
+}
+
+/// [`BlockStore`] that keeps all blocks in memory.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryBlockStore {
+    blocks: HashMap<Vec<u8>, Vec<u8>>,
+}
+
+impl BlockStore for MemoryBlockStore {
+    fn get(&self, hash: &[u8]) -> Option<Vec<u8>> {
+        self.blocks.get(hash).cloned()
+    }
+
+    fn insert(&mut self, hash: Vec<u8>, data: Vec<u8>) {
+        self.blocks.insert(hash, data);
+    }
+}
+
+/// Hash that identifies a block: the SHA2-256 multihash of its data.
+pub fn block_hash(data: &[u8]) -> Vec<u8> {
+    digest(data)
+}
+
+/// Possible failures of the block exchange.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockErr {
+    /// The block exchange was not enabled.
+    NotEnabled,
+    /// The block exceeds [`MAX_BLOCK_SIZE`].
+    TooLarge,
+    /// No connected peer provided the block within the want timeout.
+    NotFound,
+}
+
+impl fmt::Display for BlockErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BlockErr::NotEnabled => write!(f, "The block exchange is not enabled"),
+            BlockErr::TooLarge => write!(f, "The block exceeds the maximum block size"),
+            BlockErr::NotFound => write!(f, "No peer provided the block within the timeout"),
+        }
+    }
+}
+
+impl std::error::Error for BlockErr {}
+
+// Message of the block exchange protocol.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum BlockMessage {
+    // The sender wants the blocks with these hashes.
+    Want { hashes: Vec<Vec<u8>> },
+    // The sender no longer wants the blocks.
+    Cancel { hashes: Vec<Vec<u8>> },
+    // Base64 encoded block that the recipient wanted.
+    Block { data: String },
+}
+
+impl OneShotMessage for BlockMessage {
+    const PROTOCOL_NAME: &'static [u8] = b"/p2p/blocks/1.0.0";
+    const MAX_SIZE: usize = 2 * MAX_BLOCK_SIZE;
+}
+
+// Handler for exchanging block messages on a connection.
+pub type BlockHandler = MessageHandler<BlockMessage>;
+
+// Events emitted by the `BlockHandler`.
+pub type BlockHandlerEvent = MessageHandlerEvent<BlockMessage>;
+
+// Blocks that the local peer wants from others, and the wants of remote peers that could not be served yet.
+pub struct BlockExchange {
+    config: BlockExchangeConfig,
+    store: Box<dyn BlockStore>,
+    // Wanted blocks, with the peers to which the want was sent.
+    wants: HashMap<Vec<u8>, HashSet<PeerId>>,
+    // Blocks that remote peers want, but that are not in the store yet.
+    peer_wants: HashMap<PeerId, HashSet<Vec<u8>>>,
+}
+
+impl BlockExchange {
+    pub fn new(config: BlockExchangeConfig, store: Box<dyn BlockStore>) -> Self {
+        BlockExchange {
+            config,
+            store,
+            wants: HashMap::new(),
+            peer_wants: HashMap::new(),
+        }
+    }
+
+    pub fn want_timeout(&self) -> Duration {
+        self.config.want_timeout
+    }
+
+    pub fn get(&self, hash: &[u8]) -> Option<Vec<u8>> {
+        self.store.get(hash)
+    }
+
+    // Add a wanted block. Returns `false` if it is already wanted.
+    pub fn want(&mut self, hash: Vec<u8>) -> bool {
+        if self.wants.contains_key(&hash) {
+            return false;
+        }
+        self.wants.insert(hash, HashSet::new());
+        true
+    }
+
+    // Record that the want list was sent to the peer, and return the wants that were not sent to it yet.
+    pub fn wants_for(&mut self, peer: PeerId) -> Vec<Vec<u8>> {
+        self.wants
+            .iter_mut()
+            .filter_map(|(hash, peers)| peers.insert(peer).then(|| hash.clone()))
+            .collect()
+    }
+
+    // Give up a wanted block. Returns the peers to which the want was sent, `None` if the block was not wanted.
+    pub fn cancel(&mut self, hash: &[u8]) -> Option<HashSet<PeerId>> {
+        self.wants.remove(hash)
+    }
+
+    // Store a block, and return its hash and the peers that wanted it.
+    pub fn insert(&mut self, data: Vec<u8>) -> (Vec<u8>, Vec<PeerId>) {
+        let hash = block_hash(&data);
+        self.store.insert(hash.clone(), data);
+        let mut peers = Vec::new();
+        for (peer, wants) in self.peer_wants.iter_mut() {
+            if wants.remove(&hash) {
+                peers.push(*peer);
+            }
+        }
+        self.peer_wants.retain(|_, w| !w.is_empty());
+        (hash, peers)
+    }
+
+    // Handle the wants of a peer. Returns the blocks that are available, the other wants are served once the blocks
+    // are stored, up to `MAX_PEER_WANTS` per peer.
+    pub fn on_peer_want(&mut self, peer: PeerId, hashes: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+        let mut available = Vec::new();
+        for hash in hashes.into_iter().filter(|h| h.len() <= MAX_HASH_LEN) {
+            match self.store.get(&hash) {
+                Some(data) => available.push(data),
+                None => {
+                    let wants = self.peer_wants.entry(peer).or_default();
+                    if wants.len() < MAX_PEER_WANTS {
+                        wants.insert(hash);
+                    }
+                }
+            }
+        }
+        available
+    }
+
+    pub fn on_peer_cancel(&mut self, peer: &PeerId, hashes: &[Vec<u8>]) {
+        if let Some(wants) = self.peer_wants.get_mut(peer) {
+            hashes.iter().for_each(|h| {
+                wants.remove(h);
+            });
+            if wants.is_empty() {
+                self.peer_wants.remove(peer);
+            }
+        }
+    }
+
+    // Remove the wants of a disconnected peer, and forget that our wants were sent to it.
+    pub fn remove_peer(&mut self, peer: &PeerId) {
+        self.peer_wants.remove(peer);
+        for peers in self.wants.values_mut() {
+            peers.remove(peer);
+        }
+    }
+
+    // Handle a received block. Returns the hash and the other peers to which the want was sent, `None` if the block
+    // was not wanted.
+    pub fn on_block(&mut self, data: &[u8]) -> Option<(Vec<u8>, HashSet<PeerId>)> {
+        let hash = block_hash(data);
+        let peers = self.wants.remove(&hash)?;
+        Some((hash, peers))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn block_wants() {
+        let peer_a = PeerId::random();
+        let peer_b = PeerId::random();
+        let mut exchange = BlockExchange::new(BlockExchangeConfig::default(), Box::new(MemoryBlockStore::default()));
+
+        let (stored, _) = exchange.insert(b"stored".to_vec());
+        let missing = block_hash(b"missing");
+        let available = exchange.on_peer_want(peer_a, vec![stored.clone(), missing.clone()]);
+        assert_eq!(available, vec![b"stored".to_vec()]);
+
+        // The remaining want is served once the block is stored.
+        let (hash, peers) = exchange.insert(b"missing".to_vec());
+        assert_eq!(hash, missing);
+        assert_eq!(peers, vec![peer_a]);
+
+        let wanted = block_hash(b"wanted");
+        assert!(exchange.want(wanted.clone()));
+        assert!(!exchange.want(wanted.clone()));
+        assert_eq!(exchange.wants_for(peer_a), vec![wanted.clone()]);
+        assert!(exchange.wants_for(peer_a).is_empty());
+        assert_eq!(exchange.wants_for(peer_b), vec![wanted]);
+
+        assert!(exchange.on_block(b"unwanted").is_none());
+        let (_, peers) = exchange.on_block(b"wanted").unwrap();
+        assert_eq!(peers, [peer_a, peer_b].into_iter().collect());
+    }
+
+    #[test]
+    fn bounded_peer_wants() {
+        let peer = PeerId::random();
+        let mut exchange = BlockExchange::new(BlockExchangeConfig::default(), Box::new(MemoryBlockStore::default()));
+
+        exchange.on_peer_want(peer, vec![vec![0; MAX_HASH_LEN + 1]]);
+        assert!(exchange.peer_wants.is_empty());
+
+        let hashes = (0..MAX_PEER_WANTS as u32 + 10)
+            .map(|i| i.to_be_bytes().to_vec())
+            .collect();
+        exchange.on_peer_want(peer, hashes);
+        assert_eq!(exchange.peer_wants[&peer].len(), MAX_PEER_WANTS);
+    }
+}
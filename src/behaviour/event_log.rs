@@ -0,0 +1,88 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::timer::Instant;
+use std::collections::VecDeque;
+
+/// Origin of a [`RecentEvent`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventSource {
+    /// Action that was emitted by the `NetworkBehaviour`, e.g. forwarding a request to a connection or a failure.
+    Behaviour,
+    /// Event that was emitted by the `Swarm` on connections, dial attempts and listeners.
+    Swarm,
+}
+
+/// Entry in the log of recent internal events.
+///
+/// The description never contains the content of requests and responses.
+#[derive(Debug, Clone)]
+pub struct RecentEvent {
+    /// Point in time at which the event was recorded.
+    pub time: Instant,
+    /// Origin of the event.
+    pub source: EventSource,
+    /// Debug representation of the event.
+    pub description: String,
+}
+
+// Bounded log of the most recent events.
+// If the capacity is reached, older events are dropped in favor of new ones.
+#[derive(Debug)]
+pub struct EventLog {
+    entries: VecDeque<RecentEvent>,
+    capacity: usize,
+}
+
+impl EventLog {
+    // Create a new log for at most `capacity` events. The log is disabled if the capacity is 0.
+    pub fn new(capacity: usize) -> Self {
+        EventLog {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    // Record a new event.
+    // The description is only created if the log is enabled.
+    pub fn record<F: FnOnce() -> String>(&mut self, source: EventSource, description: F) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(RecentEvent {
+            time: Instant::now(),
+            source,
+            description: description(),
+        });
+    }
+
+    // Recorded events, ordered from oldest to newest.
+    pub fn entries(&self) -> Vec<RecentEvent> {
+        self.entries.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn keeps_latest_events() {
+        let mut log = EventLog::new(3);
+        for i in 0..5 {
+            log.record(EventSource::Swarm, || i.to_string());
+        }
+        let descriptions: Vec<String> = log.entries().into_iter().map(|e| e.description).collect();
+        assert_eq!(descriptions, vec!["2", "3", "4"]);
+    }
+
+    #[test]
+    fn disabled_log() {
+        let mut log = EventLog::new(0);
+        log.record(EventSource::Behaviour, || panic!("Description should not be created."));
+        assert!(log.entries().is_empty());
+    }
+}
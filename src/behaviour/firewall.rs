@@ -3,10 +3,15 @@
 
 //! Firewall in [`Network`][`crate::Network`] for filtering inbound requests.
 
+pub mod capability;
 pub mod permissions;
+#[cfg(feature = "proptest")]
+pub mod testing;
+use crate::behaviour::RequestPriority;
 use core::fmt;
 use futures::channel::oneshot;
 use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
 use std::{borrow::Borrow, collections::HashMap, fmt::Debug, marker::PhantomData, sync::Arc};
 
 /// Derive new type from the received request, that only contains firewall-relevant information.
@@ -85,6 +90,52 @@ pub enum Rule<TRq> {
     Ask,
 }
 
+impl<TRq> Rule<TRq> {
+    /// Variant of the rule, without the restriction function of a [`Rule::Restricted`].
+    pub fn kind(&self) -> RuleKind {
+        match self {
+            Rule::AllowAll => RuleKind::AllowAll,
+            Rule::RejectAll => RuleKind::RejectAll,
+            Rule::Restricted { .. } => RuleKind::Restricted,
+            Rule::Ask => RuleKind::Ask,
+        }
+    }
+}
+
+/// Serializable variant of a [`Rule`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RuleKind {
+    AllowAll,
+    RejectAll,
+    Restricted,
+    Ask,
+}
+
+impl RuleKind {
+    /// The rule of this variant, or `None` for [`RuleKind::Restricted`] since it lacks the restriction function.
+    pub fn into_rule<TRq>(self) -> Option<Rule<TRq>> {
+        match self {
+            RuleKind::AllowAll => Some(Rule::AllowAll),
+            RuleKind::RejectAll => Some(Rule::RejectAll),
+            RuleKind::Ask => Some(Rule::Ask),
+            RuleKind::Restricted => None,
+        }
+    }
+}
+
+/// Serializable firewall rules, e.g. for loading them from a config file.
+///
+/// See [`NetworkBuilder::with_firewall_config_file`][crate::NetworkBuilder::with_firewall_config_file].
+/// [`RuleKind::Restricted`] can not be represented without the restriction function, and is treated like no rule.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FirewallConfig {
+    /// Default rule that is used if there is no peer-specific one for a peer.
+    pub default: Option<RuleKind>,
+    /// Peer specific rules.
+    pub peer_rules: HashMap<PeerId, RuleKind>,
+}
+
 impl<TRq> fmt::Debug for Rule<TRq> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -123,6 +174,10 @@ pub struct FirewallRules<TRq> {
     default: Option<Rule<TRq>>,
     /// Peer specific rules.
     peer_rules: HashMap<PeerId, Rule<TRq>>,
+    /// Rules for requests of a priority class, that take precedence over the peer specific and default rules.
+    priority_rules: HashMap<RequestPriority, Rule<TRq>>,
+    /// Rules for peers with a tag, that take precedence over the default rule.
+    tag_rules: HashMap<String, Rule<TRq>>,
 }
 
 impl<TRq> Default for FirewallRules<TRq> {
@@ -130,6 +185,8 @@ impl<TRq> Default for FirewallRules<TRq> {
         FirewallRules {
             default: None,
             peer_rules: HashMap::new(),
+            priority_rules: HashMap::new(),
+            tag_rules: HashMap::new(),
         }
     }
 }
@@ -139,6 +196,8 @@ impl<TRq> Clone for FirewallRules<TRq> {
         FirewallRules {
             default: self.default.clone(),
             peer_rules: self.peer_rules.clone(),
+            priority_rules: self.priority_rules.clone(),
+            tag_rules: self.tag_rules.clone(),
         }
     }
 }
@@ -148,7 +207,12 @@ impl<TRq> FirewallRules<TRq> {
     /// If no  is set, a a [`FirewallRequest::PeerSpecificRule`] will be sent through the firewall-channel on
     /// inbound requests.
     pub fn new(default: Option<Rule<TRq>>, peer_rules: HashMap<PeerId, Rule<TRq>>) -> Self {
-        FirewallRules { default, peer_rules }
+        FirewallRules {
+            default,
+            peer_rules,
+            priority_rules: HashMap::new(),
+            tag_rules: HashMap::new(),
+        }
     }
 
     /// Don't set any rules.
@@ -158,6 +222,8 @@ impl<TRq> FirewallRules<TRq> {
         FirewallRules {
             default: None,
             peer_rules: HashMap::new(),
+            priority_rules: HashMap::new(),
+            tag_rules: HashMap::new(),
         }
     }
 
@@ -166,6 +232,8 @@ impl<TRq> FirewallRules<TRq> {
         FirewallRules {
             default: Some(Rule::AllowAll),
             peer_rules: HashMap::new(),
+            priority_rules: HashMap::new(),
+            tag_rules: HashMap::new(),
         }
     }
 
@@ -174,6 +242,8 @@ impl<TRq> FirewallRules<TRq> {
         FirewallRules {
             default: Some(Rule::RejectAll),
             peer_rules: HashMap::new(),
+            priority_rules: HashMap::new(),
+            tag_rules: HashMap::new(),
         }
     }
 
@@ -197,6 +267,19 @@ impl<TRq> FirewallRules<TRq> {
         self.peer_rules.get(peer).or(self.default.as_ref())
     }
 
+    /// Get the effective rule for a peer with the given tags, which is the peer-specific rule, or else the rule of the
+    /// first tag for which a rule is set, or else the default rule.
+    pub fn get_effective_rule_tagged<'a>(
+        &self,
+        peer: &PeerId,
+        mut tags: impl Iterator<Item = &'a str>,
+    ) -> Option<&Rule<TRq>> {
+        self.peer_rules
+            .get(peer)
+            .or_else(|| tags.find_map(|tag| self.tag_rules.get(tag)))
+            .or(self.default.as_ref())
+    }
+
     /// Set the rule for a specific peer.
     pub fn set_rule(&mut self, peer: PeerId, rule: Rule<TRq>) {
         self.peer_rules.insert(peer, rule);
@@ -206,4 +289,51 @@ impl<TRq> FirewallRules<TRq> {
     pub fn remove_rule(&mut self, peer: &PeerId) {
         self.peer_rules.remove(peer);
     }
+
+    /// Iterate over all peer specific rules.
+    pub fn peer_rules(&self) -> impl Iterator<Item = (&PeerId, &Rule<TRq>)> {
+        self.peer_rules.iter()
+    }
+
+    /// Get the rule for requests of a priority class.
+    pub fn get_priority_rule(&self, priority: RequestPriority) -> Option<&Rule<TRq>> {
+        self.priority_rules.get(&priority)
+    }
+
+    /// Set the rule for requests of a priority class.
+    /// For these requests, it takes precedence over the peer specific and default rules.
+    pub fn set_priority_rule(&mut self, priority: RequestPriority, rule: Rule<TRq>) {
+        self.priority_rules.insert(priority, rule);
+    }
+
+    /// Remove the rule for a priority class.
+    pub fn remove_priority_rule(&mut self, priority: RequestPriority) {
+        self.priority_rules.remove(&priority);
+    }
+
+    /// Iterate over all rules for priority classes.
+    pub fn priority_rules(&self) -> impl Iterator<Item = (&RequestPriority, &Rule<TRq>)> {
+        self.priority_rules.iter()
+    }
+
+    /// Get the rule for peers with the tag.
+    pub fn get_tag_rule(&self, tag: &str) -> Option<&Rule<TRq>> {
+        self.tag_rules.get(tag)
+    }
+
+    /// Set the rule for peers with the tag.
+    /// For these peers, it takes precedence over the default rule, but not over peer specific rules.
+    pub fn set_tag_rule(&mut self, tag: String, rule: Rule<TRq>) {
+        self.tag_rules.insert(tag, rule);
+    }
+
+    /// Remove the rule for peers with the tag.
+    pub fn remove_tag_rule(&mut self, tag: &str) {
+        self.tag_rules.remove(tag);
+    }
+
+    /// Iterate over all rules for tags.
+    pub fn tag_rules(&self) -> impl Iterator<Item = (&String, &Rule<TRq>)> {
+        self.tag_rules.iter()
+    }
 }
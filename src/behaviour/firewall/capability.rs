@@ -0,0 +1,179 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+//! Capability tokens for authorizing inbound requests without a per-peer firewall rule.
+//!
+//! A [`CapabilityToken`] is issued and signed by a peer (the issuer) for another peer (the subject), and states that
+//! the subject may send requests within a certain scope until the token expires. The subject attaches the token to
+//! its outbound requests with [`Network::send_request_with_capability`][crate::Network::send_request_with_capability].
+//! On the receiving side, the configured [`CapabilityVerifier`] is evaluated before the firewall rules: if it accepts
+//! the token, the request is approved, otherwise the firewall rules apply as usual.
+//!
+//! ```
+//! # use p2p::{firewall::capability::{CapabilityToken, TrustedIssuers}, identity::Keypair, PeerId};
+//! # use std::time::{Duration, SystemTime};
+//! #
+//! # #[derive(Debug, Clone, PartialEq)]
+//! # enum RequestType {
+//! #     Read,
+//! #     Write,
+//! # }
+//! #
+//! let local_keys = Keypair::generate_ed25519();
+//! let remote = PeerId::random();
+//! // Allow the remote peer to perform `Read` requests on our node for the next hour.
+//! let expires_at = SystemTime::now() + Duration::from_secs(3600);
+//! let token = CapabilityToken::issue(&local_keys, remote, "read", expires_at).unwrap();
+//!
+//! // Verifier that accepts tokens issued by the local peer.
+//! let verifier = TrustedIssuers::new([local_keys.public().to_peer_id()], |scope, rq: &RequestType| {
+//!     scope == "read" && *rq == RequestType::Read
+//! });
+//! ```
+
+use libp2p::{
+    identity::{error::SigningError, Keypair, PublicKey},
+    PeerId,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fmt,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Token that authorizes the subject peer to send requests within a scope to peers that trust the issuer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CapabilityToken {
+    // Protobuf encoding of the public key of the issuer.
+    issuer: Vec<u8>,
+    subject: PeerId,
+    scope: String,
+    // Unix timestamp in seconds.
+    expires_at: u64,
+    signature: Vec<u8>,
+}
+
+impl CapabilityToken {
+    /// Issue a new token for the `subject`, signed with the identity keys of the issuer.
+    ///
+    /// The `scope` is application-defined and interpreted by the [`CapabilityVerifier`] of the receiving peer.
+    pub fn issue(
+        issuer_keys: &Keypair,
+        subject: PeerId,
+        scope: impl Into<String>,
+        expires_at: SystemTime,
+    ) -> Result<Self, SigningError> {
+        let issuer = issuer_keys.public().to_protobuf_encoding();
+        let scope = scope.into();
+        let expires_at = expires_at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let signature = issuer_keys.sign(&Self::signing_payload(&issuer, &subject, &scope, expires_at))?;
+        Ok(CapabilityToken {
+            issuer,
+            subject,
+            scope,
+            expires_at,
+            signature,
+        })
+    }
+
+    /// Peer id of the issuer, `None` if the issuer key could not be decoded.
+    pub fn issuer(&self) -> Option<PeerId> {
+        self.issuer_key().map(|key| key.to_peer_id())
+    }
+
+    /// Peer that is authorized by the token.
+    pub fn subject(&self) -> PeerId {
+        self.subject
+    }
+
+    /// Application-defined scope of the token.
+    pub fn scope(&self) -> &str {
+        &self.scope
+    }
+
+    /// Time until which the token is valid.
+    pub fn expires_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.expires_at)
+    }
+
+    /// Check that the token is signed by its issuer, authorizes the given peer and is not expired.
+    ///
+    /// This does not check whether the issuer is trusted.
+    pub fn is_valid_for(&self, peer: &PeerId) -> bool {
+        let is_signed = self.issuer_key().map_or(false, |key| {
+            let payload = Self::signing_payload(&self.issuer, &self.subject, &self.scope, self.expires_at);
+            key.verify(&payload, &self.signature)
+        });
+        is_signed && self.subject == *peer && self.expires_at() > SystemTime::now()
+    }
+
+    fn issuer_key(&self) -> Option<PublicKey> {
+        PublicKey::from_protobuf_encoding(&self.issuer).ok()
+    }
+
+    // Bytes that are signed by the issuer.
+    fn signing_payload(issuer: &[u8], subject: &PeerId, scope: &str, expires_at: u64) -> Vec<u8> {
+        let subject = subject.to_bytes();
+        let mut payload = Vec::with_capacity(issuer.len() + subject.len() + scope.len() + 32);
+        for field in [issuer, &subject, scope.as_bytes()] {
+            payload.extend_from_slice(&(field.len() as u64).to_be_bytes());
+            payload.extend_from_slice(field);
+        }
+        payload.extend_from_slice(&expires_at.to_be_bytes());
+        payload
+    }
+}
+
+/// Verifier for the capability token attached to an inbound request.
+///
+/// It is evaluated before the firewall rules. If it returns `true` the request is approved, otherwise the firewall
+/// rules for the peer apply.
+pub trait CapabilityVerifier<TRq>: Send + Sync {
+    fn verify(&self, peer: &PeerId, request: &TRq, token: &CapabilityToken) -> bool;
+}
+
+impl<TRq, F> CapabilityVerifier<TRq> for F
+where
+    F: Fn(&PeerId, &TRq, &CapabilityToken) -> bool + Send + Sync,
+{
+    fn verify(&self, peer: &PeerId, request: &TRq, token: &CapabilityToken) -> bool {
+        self(peer, request, token)
+    }
+}
+
+/// Verifier that accepts valid tokens of a set of trusted issuers, if the scope of the token permits the request.
+pub struct TrustedIssuers<TRq> {
+    issuers: HashSet<PeerId>,
+    permits: Arc<dyn Fn(&str, &TRq) -> bool + Send + Sync>,
+}
+
+impl<TRq> TrustedIssuers<TRq> {
+    /// Create a new verifier that trusts the given issuers.
+    /// The `permits` function checks if the request is within the scope of the token.
+    pub fn new<F>(issuers: impl IntoIterator<Item = PeerId>, permits: F) -> Self
+    where
+        F: Fn(&str, &TRq) -> bool + Send + Sync + 'static,
+    {
+        TrustedIssuers {
+            issuers: issuers.into_iter().collect(),
+            permits: Arc::new(permits),
+        }
+    }
+}
+
+impl<TRq> fmt::Debug for TrustedIssuers<TRq> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TrustedIssuers")
+            .field("issuers", &self.issuers)
+            .finish()
+    }
+}
+
+impl<TRq> CapabilityVerifier<TRq> for TrustedIssuers<TRq> {
+    fn verify(&self, peer: &PeerId, request: &TRq, token: &CapabilityToken) -> bool {
+        let is_trusted = token.issuer().map_or(false, |issuer| self.issuers.contains(&issuer));
+        is_trusted && token.is_valid_for(peer) && (self.permits)(token.scope(), request)
+    }
+}
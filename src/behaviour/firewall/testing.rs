@@ -0,0 +1,215 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+//! Utilities for property-testing firewall configurations with [`proptest`].
+//!
+//! The strategies generate arbitrary [`PermissionValue`]s, [`FirewallPermission`]s, [`Rule`]s and
+//! [`FirewallRules`]. The [`FirewallModel`] is an executable model of how `Network` approves inbound requests, against
+//! which the expected outcome of a sequence of requests and rule changes can be checked.
+//!
+//! **Note**: Firewall rules only apply to inbound requests, hence there is no strategy for a rule direction.
+//!
+//! Requires feature **proptest**.
+
+use super::{
+    permissions::{FirewallPermission, PermissionValue, VariantPermission},
+    FirewallRules, Rule,
+};
+use libp2p::{identity, PeerId};
+use proptest::{collection, option, prelude::*};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    marker::PhantomData,
+    sync::Arc,
+};
+
+/// Strategy for a single [`PermissionValue`].
+pub fn permission_value() -> impl Strategy<Value = PermissionValue> {
+    (0u8..32).prop_map(|index| PermissionValue::new(index).expect("index < 32"))
+}
+
+/// Strategy for an arbitrary set of permissions.
+pub fn firewall_permission() -> impl Strategy<Value = FirewallPermission> {
+    any::<u32>().prop_map(FirewallPermission::from)
+}
+
+/// Strategy for peer ids, derived from ed25519 keys.
+pub fn peer_id() -> impl Strategy<Value = PeerId> {
+    any::<[u8; 32]>().prop_map(|mut bytes| {
+        let secret = identity::ed25519::SecretKey::from_bytes(&mut bytes).expect("32 bytes are a valid secret key");
+        identity::Keypair::Ed25519(secret.into()).public().to_peer_id()
+    })
+}
+
+/// Strategy for all variants of [`Rule`]. A [`Rule::Restricted`] permits the requests whose
+/// [`VariantPermission`] is contained in a random [`FirewallPermission`].
+pub fn rule<TRq: VariantPermission + Debug>() -> impl Strategy<Value = Rule<TRq>> {
+    prop_oneof![
+        Just(Rule::AllowAll),
+        Just(Rule::RejectAll),
+        Just(Rule::Ask),
+        firewall_permission().prop_map(restricted_rule),
+    ]
+}
+
+/// [`Rule::Restricted`] that permits the requests whose [`VariantPermission`] is contained in the `permission`.
+pub fn restricted_rule<TRq: VariantPermission>(permission: FirewallPermission) -> Rule<TRq> {
+    Rule::Restricted {
+        restriction: Arc::new(move |rq: &TRq| permission.permits(&rq.permission())),
+        _maker: PhantomData,
+    }
+}
+
+/// Strategy for [`FirewallRules`] with an optional default rule and up to `max_peers` peer specific rules.
+pub fn firewall_rules<TRq: VariantPermission + Debug>(max_peers: usize) -> impl Strategy<Value = FirewallRules<TRq>> {
+    (
+        option::of(rule()),
+        collection::hash_map(peer_id(), rule(), 0..=max_peers),
+    )
+        .prop_map(|(default, peer_rules)| FirewallRules::new(default, peer_rules))
+}
+
+/// Approval status of an inbound request in the [`FirewallModel`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Approval {
+    /// The request is forwarded to the application.
+    Approved,
+    /// The request is rejected.
+    Rejected,
+    /// There is no rule for the peer, the request awaits the answer to a `FirewallRequest::PeerSpecificRule`.
+    AwaitingRule,
+    /// The rule for the peer is [`Rule::Ask`], the request awaits the answer to a `FirewallRequest::RequestApproval`.
+    AwaitingApproval,
+}
+
+/// Executable model of how the firewall of `Network` approves inbound requests.
+///
+/// A request is checked against the effective rule for the peer. If there is none, a rule is queried once per peer
+/// and all requests from the peer await it. On [`Rule::Ask`] each request awaits an individual approval.
+/// Queries that are not answered, e.g. because the channel was dropped or the firewall timeout elapsed, reject the
+/// affected requests.
+///
+/// **Note**: Capability tokens are not modeled.
+#[derive(Debug)]
+pub struct FirewallModel<TRq> {
+    rules: FirewallRules<TRq>,
+    // Requests that await a rule for the peer.
+    awaiting_rule: HashMap<PeerId, Vec<(u64, TRq)>>,
+    // Requests that await individual approval.
+    awaiting_approval: HashSet<u64>,
+}
+
+impl<TRq: Clone> FirewallModel<TRq> {
+    pub fn new(rules: FirewallRules<TRq>) -> Self {
+        FirewallModel {
+            rules,
+            awaiting_rule: HashMap::new(),
+            awaiting_approval: HashSet::new(),
+        }
+    }
+
+    pub fn rules(&self) -> &FirewallRules<TRq> {
+        &self.rules
+    }
+
+    /// Receive a request from the peer, with an id that is unique within the model.
+    pub fn on_request(&mut self, peer: PeerId, id: u64, request: TRq) -> Approval {
+        if let Some(requests) = self.awaiting_rule.get_mut(&peer) {
+            requests.push((id, request));
+            return Approval::AwaitingRule;
+        }
+        match self.rules.get_effective_rule(&peer).cloned() {
+            Some(rule) => self.evaluate(&rule, id, &request),
+            None => {
+                self.awaiting_rule.insert(peer, vec![(id, request)]);
+                Approval::AwaitingRule
+            }
+        }
+    }
+
+    /// Whether a rule was queried for the peer and is still awaited.
+    pub fn is_awaiting_rule(&self, peer: &PeerId) -> bool {
+        self.awaiting_rule.contains_key(peer)
+    }
+
+    /// The query for a peer rule was answered, or failed if `rule` is `None`.
+    /// Returns the new status of the requests that awaited the rule.
+    pub fn on_rule_response(&mut self, peer: PeerId, rule: Option<Rule<TRq>>) -> Vec<(u64, Approval)> {
+        if let Some(rule) = rule {
+            self.rules.set_rule(peer, rule);
+        }
+        self.on_updated_rule(peer)
+    }
+
+    /// The query for the approval of a request was answered, or failed if `is_allowed` is `None`.
+    /// Returns `None` if the request did not await an approval.
+    pub fn on_approval_response(&mut self, id: u64, is_allowed: Option<bool>) -> Option<Approval> {
+        if !self.awaiting_approval.remove(&id) {
+            return None;
+        }
+        match is_allowed {
+            Some(true) => Some(Approval::Approved),
+            _ => Some(Approval::Rejected),
+        }
+    }
+
+    /// Set the default rule. Returns the new status of the requests from peers without a peer specific rule that
+    /// awaited a rule.
+    pub fn set_default(&mut self, default: Option<Rule<TRq>>) -> Vec<(u64, Approval)> {
+        self.rules.set_default(default);
+        let peers: Vec<_> = self
+            .awaiting_rule
+            .keys()
+            .filter(|p| self.rules.get_rule(p).is_none())
+            .copied()
+            .collect();
+        peers.into_iter().flat_map(|peer| self.on_updated_rule(peer)).collect()
+    }
+
+    /// Set a peer specific rule. Returns the new status of the requests that awaited a rule for the peer.
+    pub fn set_peer_rule(&mut self, peer: PeerId, rule: Rule<TRq>) -> Vec<(u64, Approval)> {
+        self.rules.set_rule(peer, rule);
+        self.on_updated_rule(peer)
+    }
+
+    /// Remove a peer specific rule. Returns the new status of the requests that awaited a rule for the peer.
+    pub fn remove_peer_rule(&mut self, peer: PeerId) -> Vec<(u64, Approval)> {
+        self.rules.remove_rule(&peer);
+        self.on_updated_rule(peer)
+    }
+
+    // Handle the requests that awaited a rule according to the effective rule, or reject them if there is none.
+    fn on_updated_rule(&mut self, peer: PeerId) -> Vec<(u64, Approval)> {
+        let requests = self.awaiting_rule.remove(&peer).unwrap_or_default();
+        let rule = self.rules.get_effective_rule(&peer).cloned();
+        requests
+            .into_iter()
+            .map(|(id, request)| {
+                let approval = match rule.as_ref() {
+                    Some(rule) => self.evaluate(rule, id, &request),
+                    None => Approval::Rejected,
+                };
+                (id, approval)
+            })
+            .collect()
+    }
+
+    fn evaluate(&mut self, rule: &Rule<TRq>, id: u64, request: &TRq) -> Approval {
+        match rule {
+            Rule::AllowAll => Approval::Approved,
+            Rule::RejectAll => Approval::Rejected,
+            Rule::Restricted { restriction, .. } => {
+                if restriction(request) {
+                    Approval::Approved
+                } else {
+                    Approval::Rejected
+                }
+            }
+            Rule::Ask => {
+                self.awaiting_approval.insert(id);
+                Approval::AwaitingApproval
+            }
+        }
+    }
+}
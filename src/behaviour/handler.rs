@@ -13,42 +13,77 @@
 // The above copyright notice and this permission notice shall be included in
 // all copies or substantial portions of the Software.
 
+mod codec;
+mod decode_pool;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
+mod migration;
 mod protocol;
-use crate::{behaviour::EMPTY_QUEUE_SHRINK_THRESHOLD, RequestId, RqRsMessage};
-use futures::{channel::oneshot, future::BoxFuture, prelude::*, stream::FuturesUnordered};
+use crate::{
+    behaviour::{BufferTuning, RequestBatching, RequestIdGenerator},
+    firewall::capability::CapabilityToken,
+    timer::{Delay, Instant},
+    RemoteError, RequestId, RqRsMessage,
+};
+pub use codec::Codec;
+pub use decode_pool::DecodePool;
+use futures::{channel::oneshot, prelude::*, stream::FuturesUnordered};
 use libp2p::{
     core::upgrade::{NegotiationError, UpgradeError},
     swarm::{ConnectionHandler, ConnectionHandlerEvent, ConnectionHandlerUpgrErr, KeepAlive, SubstreamProtocol},
 };
-pub use protocol::{MessageProtocol, RequestProtocol, ResponseProtocol};
-use smallvec::SmallVec;
+pub use migration::MessageMigrations;
+use protocol::is_unknown_request;
+pub use protocol::{
+    InboundRequest, MessageProtocol, RequestHeaders, RequestOutcome, RequestProtocol, ResponseOutcome,
+    ResponseProtocol, SerializedRequest, UnknownRequestType,
+};
+use smallvec::{smallvec, SmallVec};
 use std::{
-    collections::VecDeque,
+    collections::{HashMap, VecDeque},
     io,
     marker::PhantomData,
-    sync::{atomic::AtomicU64, Arc},
+    pin::Pin,
+    sync::Arc,
     task::{Context, Poll},
     time::Duration,
 };
-use wasm_timer::Instant;
 
 type ConnectionHandlerEventType<Rq, Rs> = ConnectionHandlerEvent<
     RequestProtocol<Rq, Rs>,
-    RequestId,
+    OutboundIds,
     <Handler<Rq, Rs> as ConnectionHandler>::OutEvent,
     <Handler<Rq, Rs> as ConnectionHandler>::Error,
 >;
 
-type PendingInboundFuture<Rq, Rs> = BoxFuture<'static, Result<(RequestId, Rq, oneshot::Sender<Rs>), oneshot::Canceled>>;
+// Pending inbound requests of a substream, together with the id assigned to the substream.
+//
+// Named future instead of a boxed `map_ok` combinator, to avoid an additional allocation for each inbound substream.
+struct PendingInboundFuture<Rq, Rs> {
+    request_id: RequestId,
+    request_rx: oneshot::Receiver<SmallVec<[InboundRequest<Rq, Rs>; 1]>>,
+}
+
+impl<Rq, Rs> Future for PendingInboundFuture<Rq, Rs> {
+    type Output = Result<(RequestId, SmallVec<[InboundRequest<Rq, Rs>; 1]>), oneshot::Canceled>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let request_id = self.request_id;
+        self.request_rx.poll_unpin(cx).map_ok(|requests| (request_id, requests))
+    }
+}
+
+// Ids of the requests that are sent on one outbound substream.
+type OutboundIds = SmallVec<[RequestId; 1]>;
 
 // Events emitted in `NetworkBehaviour::poll` and injected to `Handler::inject_event`.
 #[derive(Debug)]
-pub enum HandlerInEvent<Rq>
-where
-    Rq: RqRsMessage,
-{
+pub enum HandlerInEvent {
     // Send an outbound request.
-    SendRequest { request_id: RequestId, request: Rq },
+    SendRequest {
+        request_id: RequestId,
+        request: SerializedRequest,
+    },
     // Set the protocol support for inbound requests.
     // This will be sent to the handler when the connection is first established,
     // and each time the effective firewall rule for the remote changes.
@@ -66,22 +101,56 @@ where
     ReceivedRequest {
         request_id: RequestId,
         request: Rq,
+        // Capability token that the remote attached to the request.
+        capability: Option<CapabilityToken>,
+        // Idempotency key that the remote attached to the request.
+        idempotency_key: Option<String>,
+        // Metadata, priority class and global id that the remote attached to the request.
+        metadata: Option<RequestHeaders>,
         response_tx: oneshot::Sender<Rs>,
+        // Channel for sending the reason to the remote if the request is not answered.
+        error_tx: oneshot::Sender<RemoteError>,
+        // Size of the request in bytes.
+        bytes_received: usize,
     },
     // A response for an outbound request.
     ReceivedResponse {
         request_id: RequestId,
         response: Rs,
+        // Size of the response in bytes.
+        bytes_received: usize,
+        // Time between writing the request to the substream and receiving the response.
+        rtt: Duration,
     },
     // A response for an inbound requests was successfully sent.
-    SentResponse(RequestId),
+    SentResponse {
+        request_id: RequestId,
+        // Size of the response in bytes.
+        bytes_sent: usize,
+    },
     // The response channel closed from the sender side before a response was sent.
     SendResponseOmission(RequestId),
+    // The request was rejected with a reason, instead of sending a response.
+    ResponseRejected(RequestId),
     // Timeout on sending a response.
     InboundTimeout(RequestId),
     // The inbound request was rejected because the local peer does not support any of the requested protocols.
     // This could be either because the protocols differ, or because the local firewall rejects all inbound requests.
     InboundUnsupportedProtocols(RequestId),
+    // The inbound request could not be decoded because its type is unknown.
+    InboundUnknownRequest(RequestId),
+    // The remote sent the reason why it did not answer the request, or dropped a request of a batch without
+    // responding.
+    OutboundRemoteError {
+        request_id: RequestId,
+        error: RemoteError,
+    },
+    // The outbound request was written to the substream.
+    RequestWritten {
+        request_id: RequestId,
+        // Size of the request in bytes.
+        bytes_sent: usize,
+    },
     // Timeout on receiving a response.
     OutboundTimeout(RequestId),
     // The outbound request was rejected because the remote peer does not support any of the requested protocols.
@@ -109,8 +178,32 @@ where
     // Current setting whether the connection to the remote should be kept alive.
     // This is set according to timeout configuration and pending requests.
     keep_alive: KeepAlive,
-    // Request id assigned to the next request.
-    next_request_id: Arc<AtomicU64>,
+    // Generator for the ids of inbound requests.
+    next_request_id: Arc<dyn RequestIdGenerator>,
+    // Capacity above which the empty queues are shrunk.
+    shrink_threshold: usize,
+
+    // Optional configuration for sending multiple outbound requests as batch.
+    batching: Option<RequestBatching>,
+    // Batch variants of the supported protocols.
+    batch_protocols: SmallVec<[MessageProtocol; 2]>,
+    // Whether the remote supports batches. Set to false once the remote rejected the batch protocols.
+    remote_supports_batch: bool,
+    // Delay until the pending outbound requests are sent as batch.
+    batch_delay: Option<Delay>,
+    // Requests of the outbound batches that are in flight, indexed by the id of their first request, so that they can
+    // be sent one by one if the remote does not support batches.
+    outbound_batches: HashMap<RequestId, SmallVec<[(RequestId, SerializedRequest); 1]>>,
+    // Envelope variants of the supported protocols, for requests with a capability token, idempotency key or metadata.
+    envelope_protocols: SmallVec<[MessageProtocol; 2]>,
+    // Ids of the requests of inbound batches, indexed by the id that was assigned to their substream.
+    inbound_batches: HashMap<RequestId, SmallVec<[RequestId; 1]>>,
+    // Optional pool for decoding large messages outside of the connection task.
+    decode_pool: Option<DecodePool>,
+    // Optional converters for messages of earlier protocol versions.
+    migrations: Option<Arc<MessageMigrations<Rq, Rs>>>,
+    // Optional codec for requests and responses, instead of JSON.
+    codec: Option<Arc<dyn Codec<Rq, Rs>>>,
 
     // Fatal error in connection.
     pending_error: Option<ConnectionHandlerUpgrErr<io::Error>>,
@@ -118,9 +211,11 @@ where
     // Pending events to emit to the `NetworkBehaviour`
     pending_events: VecDeque<HandlerOutEvent<Rq, Rs>>,
     // Pending outbound request that require a new `ConnectionHandlerEvent::OutboundSubstreamRequest`.
-    pending_out_req: VecDeque<(RequestId, Rq)>,
+    pending_out_req: VecDeque<(RequestId, SerializedRequest)>,
     // Pending inbound requests for which a `ResponseProtocol` was created, but no request message was received yet.
     pending_in_req: FuturesUnordered<PendingInboundFuture<Rq, Rs>>,
+    // Outbound substreams whose requests were not written yet, indexed by the id of their first request.
+    pending_out_written: HashMap<RequestId, (OutboundIds, oneshot::Receiver<Vec<usize>>)>,
 }
 
 impl<Rq, Rs> Handler<Rq, Rs>
@@ -128,39 +223,115 @@ where
     Rq: RqRsMessage,
     Rs: RqRsMessage,
 {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         supported_protocols: SmallVec<[MessageProtocol; 2]>,
         support_inbound: bool,
         keep_alive_timeout: Duration,
         request_timeout: Duration,
-        next_request_id: Arc<AtomicU64>,
+        next_request_id: Arc<dyn RequestIdGenerator>,
+        tuning: BufferTuning,
+        batching: Option<RequestBatching>,
+        decode_pool: Option<DecodePool>,
+        migrations: Option<Arc<MessageMigrations<Rq, Rs>>>,
+        codec: Option<Arc<dyn Codec<Rq, Rs>>>,
     ) -> Self {
+        // The codec is part of the protocol names, so that peers with different codecs fail to negotiate a protocol.
+        let supported_protocols: SmallVec<[MessageProtocol; 2]> = match codec.as_ref() {
+            Some(codec) => supported_protocols.iter().map(|p| p.with_codec(codec.name())).collect(),
+            None => supported_protocols,
+        };
+        let batch_protocols = supported_protocols.iter().map(|p| p.batched()).collect();
+        let envelope_protocols = supported_protocols.iter().map(|p| p.with_envelope()).collect();
         Self {
+            batching,
+            batch_protocols,
+            envelope_protocols,
+            remote_supports_batch: true,
+            batch_delay: None,
+            outbound_batches: HashMap::new(),
+            inbound_batches: HashMap::new(),
+            decode_pool,
+            migrations,
+            codec,
             supported_protocols,
             support_inbound,
             request_timeout,
             keep_alive_timeout,
             keep_alive: KeepAlive::Yes,
             next_request_id,
+            shrink_threshold: tuning.shrink_threshold,
             pending_error: None,
-            pending_events: VecDeque::new(),
-            pending_out_req: VecDeque::new(),
+            pending_events: VecDeque::with_capacity(tuning.handler_queue_capacity),
+            pending_out_req: VecDeque::with_capacity(tuning.handler_queue_capacity),
             pending_in_req: FuturesUnordered::new(),
+            pending_out_written: HashMap::new(),
         }
     }
 
-    // Create a new `RequestProtocol` for an outbound request.
+    // Create a new `RequestProtocol` for outbound requests.
+    // Multiple requests are sent as batch.
     fn new_outbound_protocol(
         &mut self,
-        request_id: RequestId,
-        request: Rq,
-    ) -> SubstreamProtocol<RequestProtocol<Rq, Rs>, RequestId> {
+        requests: SmallVec<[(RequestId, SerializedRequest); 1]>,
+    ) -> SubstreamProtocol<RequestProtocol<Rq, Rs>, OutboundIds> {
+        let protocols = if requests.len() > 1 {
+            if let Some((id, _)) = requests.first() {
+                self.outbound_batches.insert(*id, requests.clone());
+            }
+            self.batch_protocols.clone()
+        } else if requests.iter().any(|(_, rq)| rq.has_envelope()) {
+            self.envelope_protocols.clone()
+        } else {
+            self.supported_protocols.clone()
+        };
+        let (ids, requests): (OutboundIds, _) = requests.into_iter().unzip();
+        let (written_tx, written_rx) = oneshot::channel();
+        if let Some(id) = ids.first() {
+            self.pending_out_written.insert(*id, (ids.clone(), written_rx));
+        }
         let proto = RequestProtocol {
-            protocols: self.supported_protocols.clone(),
-            request,
+            protocols,
+            requests,
+            decode_pool: self.decode_pool.clone(),
+            migrations: self.migrations.clone(),
+            codec: self.codec.clone(),
+            written_tx: Some(written_tx),
             _marker: PhantomData,
         };
-        SubstreamProtocol::new(proto, request_id).with_timeout(self.request_timeout)
+        SubstreamProtocol::new(proto, ids).with_timeout(self.request_timeout)
+    }
+
+    // Take the next pending outbound requests for which a substream should be opened.
+    //
+    // If batching is enabled, requests are held back until either the batch window elapsed or the maximum number of
+    // requests for a batch is reached. Requests with a capability token, idempotency key or metadata are never
+    // batched.
+    fn next_outbound_requests(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Option<SmallVec<[(RequestId, SerializedRequest); 1]>> {
+        let batching = match self.batching {
+            Some(b) if self.remote_supports_batch => b,
+            _ => return self.pending_out_req.pop_front().map(|r| smallvec![r]),
+        };
+        if !self.pending_out_req.front()?.1.is_batchable() {
+            return self.pending_out_req.pop_front().map(|r| smallvec![r]);
+        }
+        if self.pending_out_req.len() < batching.max_requests {
+            let delay = self.batch_delay.get_or_insert_with(|| Delay::new(batching.window));
+            if delay.poll_unpin(cx).is_pending() {
+                return None;
+            }
+        }
+        self.batch_delay = None;
+        let count = self
+            .pending_out_req
+            .iter()
+            .take(batching.max_requests.max(1))
+            .take_while(|(_, rq)| rq.is_batchable())
+            .count();
+        Some(self.pending_out_req.drain(..count).collect())
     }
 
     // Create a new `ResponseProtocol` for an inbound request.
@@ -173,19 +344,41 @@ where
 
         let protocols = self
             .support_inbound
-            .then(|| self.supported_protocols.clone())
+            .then(|| {
+                let protocols = self.supported_protocols.iter().chain(self.batch_protocols.iter());
+                let protocols = protocols.chain(self.envelope_protocols.iter()).cloned();
+                // Requests of deprecated versions are accepted in all variants, so that they can be rejected with the
+                // minimum supported version.
+                let deprecated = self.migrations.iter().flat_map(|m| m.deprecated_protocols());
+                let deprecated = deprecated.map(|p| match self.codec.as_ref() {
+                    Some(codec) => p.with_codec(codec.name()),
+                    None => p.clone(),
+                });
+                let deprecated = deprecated.flat_map(|p| [p.clone(), p.batched(), p.with_envelope()]);
+                protocols.chain(deprecated).collect()
+            })
             .unwrap_or_default();
 
-        let proto = ResponseProtocol { protocols, request_tx };
+        let proto = ResponseProtocol {
+            protocols,
+            request_tx,
+            decode_pool: self.decode_pool.clone(),
+            migrations: self.migrations.clone(),
+            codec: self.codec.clone(),
+        };
 
-        self.pending_in_req.push(
-            request_rx
-                .map_ok(move |(request, tx)| (request_id, request, tx))
-                .boxed(),
-        );
+        self.pending_in_req
+            .push(PendingInboundFuture { request_id, request_rx });
 
         SubstreamProtocol::new(proto, request_id).with_timeout(self.request_timeout)
     }
+
+    // Ids of the requests that were received on an inbound substream.
+    fn take_inbound_ids(&mut self, substream_id: RequestId) -> SmallVec<[RequestId; 1]> {
+        self.inbound_batches
+            .remove(&substream_id)
+            .unwrap_or_else(|| smallvec![substream_id])
+    }
 }
 
 impl<Rq, Rs> ConnectionHandler for Handler<Rq, Rs>
@@ -193,40 +386,72 @@ where
     Rq: RqRsMessage,
     Rs: RqRsMessage,
 {
-    type InEvent = HandlerInEvent<Rq>;
+    type InEvent = HandlerInEvent;
     type OutEvent = HandlerOutEvent<Rq, Rs>;
     type Error = ConnectionHandlerUpgrErr<io::Error>;
     type InboundProtocol = ResponseProtocol<Rq, Rs>;
     type OutboundProtocol = RequestProtocol<Rq, Rs>;
     type InboundOpenInfo = RequestId;
-    type OutboundOpenInfo = RequestId;
+    type OutboundOpenInfo = OutboundIds;
 
     // Protocol and info for upgrading new inbound substreams.
     fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol, Self::InboundOpenInfo> {
         self.new_inbound_protocol()
     }
 
-    // Successfully received a requests and potentially sent a response.
-    fn inject_fully_negotiated_inbound(&mut self, send_response: bool, request_id: RequestId) {
-        let event = if send_response {
-            HandlerOutEvent::SentResponse(request_id)
-        } else {
-            HandlerOutEvent::SendResponseOmission(request_id)
-        };
-        self.pending_events.push_back(event);
+    // Successfully received requests and potentially sent responses.
+    fn inject_fully_negotiated_inbound(&mut self, outcomes: SmallVec<[ResponseOutcome; 1]>, request_id: RequestId) {
+        let request_ids = self.take_inbound_ids(request_id);
+        for (request_id, outcome) in request_ids.into_iter().zip(outcomes) {
+            let event = match outcome {
+                ResponseOutcome::Sent(bytes_sent) => HandlerOutEvent::SentResponse { request_id, bytes_sent },
+                ResponseOutcome::Rejected => HandlerOutEvent::ResponseRejected(request_id),
+                ResponseOutcome::Omitted => HandlerOutEvent::SendResponseOmission(request_id),
+            };
+            self.pending_events.push_back(event);
+        }
     }
 
-    // Successfully sent a requests and received a response.
-    fn inject_fully_negotiated_outbound(&mut self, response: Rs, request_id: RequestId) {
-        let event = HandlerOutEvent::ReceivedResponse { request_id, response };
-        self.pending_events.push_back(event);
+    // Successfully sent requests and received responses.
+    fn inject_fully_negotiated_outbound(&mut self, outcomes: SmallVec<[RequestOutcome<Rs>; 1]>, ids: OutboundIds) {
+        if let Some(id) = ids.first() {
+            self.outbound_batches.remove(id);
+        }
+        // The requests were written before the responses were read, even if the notification was not polled yet.
+        if let Some((written, _)) = ids.first().and_then(|id| self.pending_out_written.remove(id)) {
+            let bytes_sent = outcomes.iter().map(|(_, bytes_sent, _, _)| *bytes_sent);
+            let events = written
+                .into_iter()
+                .zip(bytes_sent)
+                .map(|(request_id, bytes_sent)| HandlerOutEvent::RequestWritten { request_id, bytes_sent });
+            self.pending_events.extend(events);
+        }
+        for (request_id, (response, _, bytes_received, rtt)) in ids.into_iter().zip(outcomes) {
+            let event = match response {
+                Ok(response) => HandlerOutEvent::ReceivedResponse {
+                    request_id,
+                    response,
+                    bytes_received,
+                    rtt,
+                },
+                Err(error) => HandlerOutEvent::OutboundRemoteError { request_id, error },
+            };
+            self.pending_events.push_back(event);
+        }
     }
 
     // New event emitted by the `NetworkBehaviour`.
     fn inject_event(&mut self, event: Self::InEvent) {
         match event {
             HandlerInEvent::SendRequest { request_id, request } => {
-                self.pending_out_req.push_back((request_id, request));
+                // Requests are queued behind all requests of the same or a higher priority class.
+                let priority = request.priority();
+                let index = self
+                    .pending_out_req
+                    .iter()
+                    .position(|(_, r)| r.priority() < priority)
+                    .unwrap_or(self.pending_out_req.len());
+                self.pending_out_req.insert(index, (request_id, request));
                 self.keep_alive = KeepAlive::Yes;
             }
             HandlerInEvent::SetInboundSupport(b) => {
@@ -236,15 +461,25 @@ where
     }
 
     // Upgrading the outbound substream with the `RequestProtocol` failed.
-    fn inject_dial_upgrade_error(&mut self, request_id: RequestId, error: ConnectionHandlerUpgrErr<io::Error>) {
+    fn inject_dial_upgrade_error(&mut self, ids: OutboundIds, error: ConnectionHandlerUpgrErr<io::Error>) {
+        let batch = ids.first().and_then(|id| self.outbound_batches.remove(id));
         match error {
             ConnectionHandlerUpgrErr::Timeout => {
-                self.pending_events
-                    .push_back(HandlerOutEvent::OutboundTimeout(request_id));
+                let events = ids.into_iter().map(HandlerOutEvent::OutboundTimeout);
+                self.pending_events.extend(events);
             }
             ConnectionHandlerUpgrErr::Upgrade(UpgradeError::Select(NegotiationError::Failed)) => {
-                self.pending_events
-                    .push_back(HandlerOutEvent::OutboundUnsupportedProtocols(request_id));
+                // Don't use batches anymore if the remote does not support them, and send the requests of the batch
+                // one by one instead.
+                if let Some(batch) = batch {
+                    self.remote_supports_batch = false;
+                    for request in batch.into_iter().rev() {
+                        self.pending_out_req.push_front(request);
+                    }
+                    return;
+                }
+                let events = ids.into_iter().map(HandlerOutEvent::OutboundUnsupportedProtocols);
+                self.pending_events.extend(events);
             }
             _ => {
                 // Fatal error
@@ -257,15 +492,30 @@ where
     fn inject_listen_upgrade_error(&mut self, request_id: RequestId, error: ConnectionHandlerUpgrErr<io::Error>) {
         match error {
             ConnectionHandlerUpgrErr::Timeout => {
-                self.pending_events
-                    .push_back(HandlerOutEvent::InboundTimeout(request_id));
+                let events = self
+                    .take_inbound_ids(request_id)
+                    .into_iter()
+                    .map(HandlerOutEvent::InboundTimeout);
+                self.pending_events.extend(events);
             }
             ConnectionHandlerUpgrErr::Upgrade(UpgradeError::Select(NegotiationError::Failed)) => {
-                self.pending_events
-                    .push_back(HandlerOutEvent::InboundUnsupportedProtocols(request_id));
+                let events = self
+                    .take_inbound_ids(request_id)
+                    .into_iter()
+                    .map(HandlerOutEvent::InboundUnsupportedProtocols);
+                self.pending_events.extend(events);
+            }
+            // The substream is intact, an unknown request type does not justify closing the connection.
+            ConnectionHandlerUpgrErr::Upgrade(UpgradeError::Apply(e)) if is_unknown_request(&e) => {
+                let events = self
+                    .take_inbound_ids(request_id)
+                    .into_iter()
+                    .map(HandlerOutEvent::InboundUnknownRequest);
+                self.pending_events.extend(events);
             }
             _ => {
                 // Fatal error
+                self.inbound_batches.remove(&request_id);
                 self.pending_error = Some(error);
             }
         }
@@ -281,31 +531,70 @@ where
         if let Some(err) = self.pending_error.take() {
             return Poll::Ready(ConnectionHandlerEvent::Close(err));
         }
+        // Report outbound requests that were written to their substream. Substreams that failed are removed.
+        let pending_events = &mut self.pending_events;
+        self.pending_out_written
+            .retain(|_, (ids, written_rx)| match written_rx.poll_unpin(cx) {
+                Poll::Ready(Ok(lens)) => {
+                    let events = ids
+                        .drain(..)
+                        .zip(lens)
+                        .map(|(request_id, bytes_sent)| HandlerOutEvent::RequestWritten { request_id, bytes_sent });
+                    pending_events.extend(events);
+                    false
+                }
+                Poll::Ready(Err(_)) => false,
+                Poll::Pending => true,
+            });
         // Emit events to `NetworkBehaviour`.
         if let Some(event) = self.pending_events.pop_front() {
             return Poll::Ready(ConnectionHandlerEvent::Custom(event));
         }
-        if self.pending_events.capacity() > EMPTY_QUEUE_SHRINK_THRESHOLD {
+        if self.pending_events.capacity() > self.shrink_threshold {
             self.pending_events.shrink_to_fit();
         }
         // Forward inbound requests to `NetworkBehaviour` once the request was read from the substream.
         while let Poll::Ready(Some(result)) = self.pending_in_req.poll_next_unpin(cx) {
-            if let Ok((request_id, request, response_tx)) = result {
+            if let Ok((substream_id, requests)) = result {
+                if requests.is_empty() {
+                    continue;
+                }
                 self.keep_alive = KeepAlive::Yes;
-                return Poll::Ready(ConnectionHandlerEvent::Custom(HandlerOutEvent::ReceivedRequest {
-                    request_id,
-                    request,
-                    response_tx,
-                }));
+                // The first request of a batch uses the id of the substream, the others are assigned new ids.
+                let mut request_ids = SmallVec::<[RequestId; 1]>::new();
+                for (request, capability, idempotency_key, metadata, bytes_received, response_tx, error_tx) in requests
+                {
+                    let request_id = match request_ids.is_empty() {
+                        true => substream_id,
+                        false => RequestId::next(&self.next_request_id),
+                    };
+                    request_ids.push(request_id);
+                    self.pending_events.push_back(HandlerOutEvent::ReceivedRequest {
+                        request_id,
+                        request,
+                        capability,
+                        idempotency_key,
+                        metadata,
+                        response_tx,
+                        error_tx,
+                        bytes_received,
+                    });
+                }
+                if request_ids.len() > 1 {
+                    self.inbound_batches.insert(substream_id, request_ids);
+                }
+                if let Some(event) = self.pending_events.pop_front() {
+                    return Poll::Ready(ConnectionHandlerEvent::Custom(event));
+                }
             }
         }
         // Create new outbound substream with `RequestProtocol` for outbound requests.
-        if let Some((request_id, request)) = self.pending_out_req.pop_front() {
+        if let Some(requests) = self.next_outbound_requests(cx) {
             self.keep_alive = KeepAlive::Yes;
-            let protocol = self.new_outbound_protocol(request_id, request);
+            let protocol = self.new_outbound_protocol(requests);
             return Poll::Ready(ConnectionHandlerEvent::OutboundSubstreamRequest { protocol });
         }
-        if self.pending_out_req.capacity() > EMPTY_QUEUE_SHRINK_THRESHOLD {
+        if self.pending_out_req.capacity() > self.shrink_threshold {
             self.pending_out_req.shrink_to_fit();
         }
         // Set timeout for keeping the connection alive.
@@ -0,0 +1,50 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use super::protocol::UnknownRequestType;
+use std::{fmt, io};
+
+/// Encoding of requests and responses on the wire, e.g. to use a binary format instead of the default JSON.
+///
+/// The codec is used for all requests and responses, including batches. The capability token, idempotency key or
+/// metadata of a request are still sent in a JSON header in front of the encoded request. Messages of earlier
+/// protocol versions are decoded with [`Codec::decode_value`] before the
+/// [`MessageMigrations`][crate::MessageMigrations] convert them.
+///
+/// The name of the codec is part of the protocol names, so that requests to peers with a different codec fail with
+/// [`OutboundFailure::UnsupportedProtocols`][crate::OutboundFailure::UnsupportedProtocols]. If an inbound request is
+/// well-formed but of an unknown type, [`Codec::decode_request`] should return an [`UnknownRequestType`] error, so
+/// that the remote is informed with [`RemoteError::UnknownRequest`][crate::RemoteError::UnknownRequest]. Other
+/// errors when decoding a message are handled like a malformed message, i.e. the connection is closed.
+pub trait Codec<Rq, Rs>: Send + Sync + 'static {
+    /// Name of the codec, e.g. `cbor`, that is appended to the protocol names.
+    fn name(&self) -> &str;
+
+    /// Encode an outbound request.
+    fn encode_request(&self, request: &Rq) -> Result<Vec<u8>, io::Error>;
+
+    /// Decode an inbound request.
+    ///
+    /// Return an [`UnknownRequestType`] error, converted into an [`io::Error`], if the type of the request is unknown.
+    fn decode_request(&self, bytes: &[u8]) -> Result<Rq, io::Error>;
+
+    /// Encode the response to an inbound request.
+    fn encode_response(&self, response: &Rs) -> Result<Vec<u8>, io::Error>;
+
+    /// Decode the response to an outbound request.
+    fn decode_response(&self, bytes: &[u8]) -> Result<Rs, io::Error>;
+
+    /// Decode a request or response of an earlier protocol version into a generic value, which is then converted to
+    /// the current type by the [`MessageMigrations`][crate::MessageMigrations].
+    ///
+    /// Per default the message is decoded as JSON.
+    fn decode_value(&self, bytes: &[u8]) -> Result<serde_json::Value, io::Error> {
+        serde_json::from_slice(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl<Rq, Rs> fmt::Debug for dyn Codec<Rq, Rs> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Codec")
+    }
+}
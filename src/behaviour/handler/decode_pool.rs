@@ -0,0 +1,121 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::behaviour::DecodeWorkers;
+use futures::channel::oneshot;
+use serde::de::DeserializeOwned;
+use std::{
+    io,
+    panic::{self, AssertUnwindSafe},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+// Pool of worker threads for deserializing large messages and serializing responses, so that this does not stall the
+// other substreams of a connection.
+//
+// A panicking job fails only its own message, the worker thread keeps running. The worker threads shut down once all
+// clones of the pool are dropped.
+#[derive(Debug, Clone)]
+pub struct DecodePool {
+    // Messages with fewer bytes are decoded directly.
+    min_size: usize,
+    // Channel for sending jobs to the workers.
+    job_tx: mpsc::Sender<Job>,
+}
+
+impl DecodePool {
+    pub fn new(config: DecodeWorkers) -> Self {
+        let (job_tx, job_rx) = mpsc::channel::<Job>();
+        let job_rx = Arc::new(Mutex::new(job_rx));
+        for _ in 0..config.threads.max(1) {
+            let job_rx = job_rx.clone();
+            thread::spawn(move || loop {
+                let job = match job_rx.lock() {
+                    Ok(rx) => rx.recv(),
+                    Err(_) => return,
+                };
+                match job {
+                    // The result channel of the job is dropped if it panics.
+                    Ok(job) => {
+                        let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                    }
+                    Err(_) => return,
+                }
+            });
+        }
+        DecodePool {
+            min_size: config.min_size,
+            job_tx,
+        }
+    }
+
+    // Decode the bytes with the `decode` function, in a worker thread if the message is large.
+    pub async fn decode<T, F>(&self, bytes: Vec<u8>, decode: F) -> Result<T, io::Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&[u8]) -> Result<T, io::Error> + Send + 'static,
+    {
+        if bytes.len() < self.min_size {
+            return decode(&bytes);
+        }
+        self.run(move || decode(&bytes)).await
+    }
+
+    // Encode a message with the `encode` function in a worker thread.
+    // The size of a message is not known before it is encoded, so all messages are encoded in the workers.
+    pub async fn encode<T, F>(&self, encode: F) -> Result<T, io::Error>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T, io::Error> + Send + 'static,
+    {
+        self.run(encode).await
+    }
+
+    // Run the function in a worker thread, or directly if the workers are gone.
+    async fn run<T, F>(&self, f: F) -> Result<T, io::Error>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T, io::Error> + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let job: Job = Box::new(move || {
+            let _ = tx.send(f());
+        });
+        if let Err(mpsc::SendError(job)) = self.job_tx.send(job) {
+            job();
+        }
+        rx.await.map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::Other,
+                "Worker dropped the message, it may have panicked.",
+            )
+        })?
+    }
+}
+
+// Deserialize the bytes of a message.
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, io::Error> {
+    serde_json::from_slice(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::executor::block_on;
+
+    #[test]
+    fn panicking_job() {
+        let pool = DecodePool::new(DecodeWorkers {
+            threads: 1,
+            min_size: 0,
+        });
+        let res = block_on(pool.decode(b"1".to_vec(), |_| -> Result<u8, io::Error> { panic!("decode") }));
+        assert!(res.is_err());
+        // The worker is still running.
+        assert_eq!(block_on(pool.decode(b"1".to_vec(), decode::<u8>)).unwrap(), 1);
+        assert_eq!(block_on(pool.encode(|| Ok(vec![1]))).unwrap(), vec![1]);
+    }
+}
@@ -0,0 +1,103 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+//! Entry points for fuzzing the wire format of the request-response protocol.
+//!
+//! The functions parse the bytes that a remote writes to a substream, the same way the inbound and outbound upgrades
+//! of the protocol do it, but synchronously and from a byte slice. This makes them suitable as `cargo-fuzz` targets:
+//!
+//! ```ignore
+//! #![no_main]
+//! use libfuzzer_sys::fuzz_target;
+//! use p2p::fuzzing::{parse_request_frame, RequestFormat};
+//!
+//! fuzz_target!(|data: &[u8]| {
+//!     let _ = parse_request_frame::<serde_json::Value>(RequestFormat::Batch, data);
+//! });
+//! ```
+//!
+//! Requires feature **fuzzing**.
+
+use super::{
+    decode_pool::decode,
+    protocol::{RequestEnvelope, ERROR_FRAME, RESPONSE_FRAME},
+};
+use crate::{behaviour::RequestMetadata, firewall::capability::CapabilityToken, RemoteError};
+use serde::de::DeserializeOwned;
+use std::io;
+
+pub use super::protocol::MAX_MESSAGE_SIZE;
+
+/// Format of an inbound request, depending on the negotiated variant of the protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestFormat {
+    /// A single request.
+    Single,
+    /// Multiple requests as JSON array.
+    Batch,
+    /// A single request in an envelope, together with a capability token, idempotency key or metadata.
+    Envelope,
+}
+
+/// Split a length-prefixed frame into its payload and the remaining bytes.
+///
+/// The length is encoded as unsigned varint. Frames with a malformed prefix or a length larger than
+/// [`MAX_MESSAGE_SIZE`] are rejected before the payload is inspected.
+pub fn decode_frame(data: &[u8]) -> Result<(&[u8], &[u8]), io::Error> {
+    let mut len: u64 = 0;
+    // A varint for a 64bit integer has at most 10 bytes.
+    for (i, byte) in data.iter().take(10).enumerate() {
+        len |= u64::from(byte & 0x7f) << (7 * i);
+        if len > MAX_MESSAGE_SIZE as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Message exceeds the maximum size.",
+            ));
+        }
+        if byte & 0x80 == 0 {
+            let payload = &data[i + 1..];
+            let len = len as usize;
+            if payload.len() < len {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Incomplete message."));
+            }
+            return Ok(payload.split_at(len));
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid length prefix."))
+}
+
+/// Parse the requests in a length-prefixed frame, as it is read by the inbound upgrade.
+pub fn parse_request_frame<Rq: DeserializeOwned>(format: RequestFormat, data: &[u8]) -> Result<Vec<Rq>, io::Error> {
+    let (payload, _) = decode_frame(data)?;
+    let requests = match format {
+        RequestFormat::Single => vec![decode(payload)?],
+        RequestFormat::Batch => decode(payload)?,
+        RequestFormat::Envelope => {
+            let envelope: RequestEnvelope<CapabilityToken, String, RequestMetadata, Rq> = decode(payload)?;
+            vec![envelope.request]
+        }
+    };
+    Ok(requests)
+}
+
+/// Parse the responses in a length-prefixed frame, as it is read by the outbound upgrade.
+/// A single response is preceded by a tag byte, which marks whether the frame contains the response or the error that
+/// the remote sent instead.
+pub fn parse_response_frame<Rs: DeserializeOwned>(
+    is_batch: bool,
+    data: &[u8],
+) -> Result<Vec<Result<Rs, RemoteError>>, io::Error> {
+    let (payload, _) = decode_frame(data)?;
+    if is_batch {
+        let responses: Vec<Option<Rs>> = decode(payload)?;
+        return Ok(responses.into_iter().map(|r| r.ok_or(RemoteError::Dropped)).collect());
+    }
+    match payload.split_first() {
+        Some((&RESPONSE_FRAME, response)) => decode(response).map(|response| vec![Ok(response)]),
+        Some((&ERROR_FRAME, error)) => decode(error).map(|error| vec![Err(error)]),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Missing or unknown frame tag.",
+        )),
+    }
+}
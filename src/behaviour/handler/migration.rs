@@ -0,0 +1,205 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use super::protocol::MessageProtocol;
+use serde::de::DeserializeOwned;
+use std::{collections::HashMap, fmt, io, sync::Arc};
+
+// Conversion of a message that was decoded in the shape of an earlier version.
+pub(super) type Converter<T> = Arc<dyn Fn(serde_json::Value) -> Result<T, io::Error> + Send + Sync>;
+
+/// Converters for messages that peers of earlier versions send in a different shape than the current request and
+/// response types, so that the peers of a network can be upgraded one after another.
+///
+/// Converters are registered for a version of the [`MessageProtocol`]. Each of these versions is supported in
+/// addition to the current version, which is still preferred when a protocol is negotiated with a remote. A message
+/// that is received on a substream for which an earlier version was negotiated is decoded as the earlier type, and
+/// converted to the current type.
+///
+/// **Note**: Messages are always sent in the shape of the current type, also to peers of earlier versions.
+///
+/// Versions that should not be used anymore can be deprecated instead, so that requests of them are answered with
+/// [`RemoteError::UpgradeRequired`][crate::RemoteError::UpgradeRequired] rather than failing the negotiation.
+///
+/// ```
+/// # use p2p::{MessageMigrations, MessageProtocol};
+/// # use serde::Deserialize;
+/// #[derive(Deserialize)]
+/// struct RequestV0 {
+///     name: String,
+/// }
+///
+/// struct Request {
+///     name: String,
+///     tags: Vec<String>,
+/// }
+///
+/// let migrations = MessageMigrations::<Request, ()>::new().with_request_converter(
+///     MessageProtocol::new_version(0, 9, 0),
+///     |old: RequestV0| Request {
+///         name: old.name,
+///         tags: Vec::new(),
+///     },
+/// );
+/// ```
+pub struct MessageMigrations<Rq, Rs> {
+    // Earlier protocol versions, in the order in which they were registered.
+    protocols: Vec<MessageProtocol>,
+    // Converters for requests and responses, keyed by the name of the protocol version.
+    requests: HashMap<String, Converter<Rq>>,
+    responses: HashMap<String, Converter<Rs>>,
+    // Deprecated protocol versions, with the oldest version that is still supported.
+    deprecated: Vec<(MessageProtocol, MessageProtocol)>,
+}
+
+impl<Rq, Rs> MessageMigrations<Rq, Rs> {
+    pub fn new() -> Self {
+        MessageMigrations {
+            protocols: Vec::new(),
+            requests: HashMap::new(),
+            responses: HashMap::new(),
+            deprecated: Vec::new(),
+        }
+    }
+
+    /// Decode requests that are received with the `protocol` version as `Old`, and convert them to the current
+    /// request type.
+    pub fn with_request_converter<Old, F>(mut self, protocol: MessageProtocol, convert: F) -> Self
+    where
+        Old: DeserializeOwned,
+        F: Fn(Old) -> Rq + Send + Sync + 'static,
+    {
+        let converter = Arc::new(move |value: serde_json::Value| decode_value::<Old>(value).map(&convert));
+        self.requests.insert(protocol.base_version().to_string(), converter);
+        self.add_protocol(protocol);
+        self
+    }
+
+    /// Decode responses that are received with the `protocol` version as `Old`, and convert them to the current
+    /// response type.
+    pub fn with_response_converter<Old, F>(mut self, protocol: MessageProtocol, convert: F) -> Self
+    where
+        Old: DeserializeOwned,
+        F: Fn(Old) -> Rs + Send + Sync + 'static,
+    {
+        let converter = Arc::new(move |value: serde_json::Value| decode_value::<Old>(value).map(&convert));
+        self.responses.insert(protocol.base_version().to_string(), converter);
+        self.add_protocol(protocol);
+        self
+    }
+
+    /// Answer inbound requests of the deprecated `protocol` version with
+    /// [`RemoteError::UpgradeRequired`][crate::RemoteError::UpgradeRequired], that names `min_version` as the oldest
+    /// version that is still supported. Requests of a batch are reported as dropped instead.
+    ///
+    /// The deprecated version is only supported for inbound requests, and takes precedence over converters of that
+    /// version.
+    pub fn with_deprecated_protocol(mut self, protocol: MessageProtocol, min_version: MessageProtocol) -> Self {
+        self.deprecated
+            .retain(|(p, _)| p.base_version() != protocol.base_version());
+        self.deprecated.push((protocol, min_version));
+        self
+    }
+
+    // Earlier protocol versions for which converters are registered.
+    pub(crate) fn protocols(&self) -> &[MessageProtocol] {
+        &self.protocols
+    }
+
+    // Deprecated protocol versions, that are only supported for rejecting inbound requests.
+    pub(super) fn deprecated_protocols(&self) -> impl Iterator<Item = &MessageProtocol> {
+        self.deprecated.iter().map(|(p, _)| p)
+    }
+
+    // Name of the oldest supported version if the negotiated protocol is deprecated.
+    pub(super) fn deprecation(&self, protocol: &MessageProtocol) -> Option<String> {
+        self.deprecated
+            .iter()
+            .find(|(p, _)| p.base_version() == protocol.base_version())
+            .map(|(_, min_version)| min_version.base_version().to_string())
+    }
+
+    // Converter for requests that were received with the negotiated protocol, `None` if they have the current shape.
+    pub(super) fn request_converter(&self, protocol: &MessageProtocol) -> Option<Converter<Rq>> {
+        self.requests.get(protocol.base_version()).cloned()
+    }
+
+    // Converter for responses that were received with the negotiated protocol, `None` if they have the current shape.
+    pub(super) fn response_converter(&self, protocol: &MessageProtocol) -> Option<Converter<Rs>> {
+        self.responses.get(protocol.base_version()).cloned()
+    }
+
+    fn add_protocol(&mut self, protocol: MessageProtocol) {
+        if !self
+            .protocols
+            .iter()
+            .any(|p| p.base_version() == protocol.base_version())
+        {
+            self.protocols.push(protocol);
+        }
+    }
+}
+
+impl<Rq, Rs> Default for MessageMigrations<Rq, Rs> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Rq, Rs> fmt::Debug for MessageMigrations<Rq, Rs> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MessageMigrations")
+            .field("protocols", &self.protocols)
+            .field("deprecated", &self.deprecated)
+            .finish()
+    }
+}
+
+fn decode_value<T: DeserializeOwned>(value: serde_json::Value) -> Result<T, io::Error> {
+    serde_json::from_value(value).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    struct OldRequest {
+        value: u8,
+    }
+
+    #[test]
+    fn convert_by_protocol_version() {
+        let old_protocol = MessageProtocol::new_version(0, 9, 0);
+        let migrations = MessageMigrations::<u32, ()>::new()
+            .with_request_converter(old_protocol.clone(), |old: OldRequest| old.value as u32 * 2);
+        assert_eq!(migrations.protocols().len(), 1);
+        assert!(migrations
+            .request_converter(&MessageProtocol::new_version(1, 0, 0))
+            .is_none());
+        assert!(migrations.response_converter(&old_protocol).is_none());
+
+        // Variants of the protocol, also with a codec, use the converter of their version.
+        assert!(migrations
+            .request_converter(&old_protocol.with_codec("cbor").batched())
+            .is_some());
+        let convert = migrations.request_converter(&old_protocol.with_envelope()).unwrap();
+        assert_eq!(convert(serde_json::json!({ "value": 21 })).unwrap(), 42);
+        assert!(convert(serde_json::json!(21)).is_err());
+    }
+
+    #[test]
+    fn deprecate_protocol_version() {
+        let deprecated = MessageProtocol::new_version(0, 8, 0);
+        let migrations = MessageMigrations::<u32, ()>::new()
+            .with_deprecated_protocol(deprecated.clone(), MessageProtocol::new_version(0, 9, 0));
+        assert!(migrations.protocols().is_empty());
+        assert_eq!(migrations.deprecated_protocols().count(), 1);
+        assert_eq!(
+            migrations.deprecation(&deprecated.batched()),
+            Some("/p2p/0.9.0".to_string())
+        );
+        assert!(migrations.deprecation(&MessageProtocol::new_version(0, 9, 0)).is_none());
+    }
+}
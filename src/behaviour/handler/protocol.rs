@@ -13,7 +13,17 @@
 // The above copyright notice and this permission notice shall be included in
 // all copies or substantial portions of the Software.
 
-use crate::RqRsMessage;
+use super::{
+    codec::Codec,
+    decode_pool::{decode, DecodePool},
+    migration::MessageMigrations,
+};
+use crate::{
+    behaviour::{GlobalRequestId, RequestMetadata, RequestPriority},
+    firewall::capability::CapabilityToken,
+    timer::Instant,
+    RemoteError, RqRsMessage,
+};
 use futures::{channel::oneshot, future::BoxFuture, prelude::*};
 use libp2p::{
     core::{
@@ -22,21 +32,117 @@ use libp2p::{
     },
     swarm::NegotiatedSubstream,
 };
-use serde::{de::DeserializeOwned, Serialize};
-use smallvec::SmallVec;
-use std::{fmt::Debug, io, marker::PhantomData};
+use serde::{
+    de::{self, DeserializeOwned, Deserializer, Visitor},
+    Deserialize, Serialize,
+};
+use serde_json::{error::Category, value::RawValue};
+use smallvec::{smallvec, SmallVec};
+use std::{
+    fmt::{self, Debug},
+    io,
+    marker::PhantomData,
+    sync::Arc,
+    time::Duration,
+};
+
+// Length prefix of a dropped item in a batch of items that were encoded with a codec.
+const DROPPED_ITEM: u32 = u32::MAX;
+
+// Tags in front of the message that is sent for a single request: the response, or the error if the request is not
+// answered.
+pub(super) const RESPONSE_FRAME: u8 = 0;
+pub(super) const ERROR_FRAME: u8 = 1;
+
+/// Maximum size in bytes of a single request or response message, including batches.
+/// Messages with a larger length prefix are rejected before their content is read.
+pub const MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
 
 /// Protocol Name.
 /// A Request-Response messages will only be successful if both peers support the [`MessageProtocol`].
+///
+/// The name is reference-counted, so that the protocol list can be cheaply cloned for each new substream.
 #[derive(Debug, Clone)]
 pub struct MessageProtocol {
-    version: String,
+    version: Arc<str>,
+    // Name of the protocol version without the suffixes of the codec and the variant.
+    base_version: Arc<str>,
+    // Whether multiple requests are sent as batch on one substream.
+    is_batch: bool,
+    // Whether the request is sent in an envelope together with a capability token, idempotency key or metadata.
+    has_envelope: bool,
 }
 
 impl MessageProtocol {
     pub fn new_version(major: u8, minor: u8, patch: u8) -> Self {
-        let version = format!("/p2p/{}.{}.{}", major, minor, patch);
-        MessageProtocol { version }
+        let version: Arc<str> = format!("/p2p/{}.{}.{}", major, minor, patch).into();
+        MessageProtocol {
+            version: version.clone(),
+            base_version: version,
+            is_batch: false,
+            has_envelope: false,
+        }
+    }
+
+    /// Version of the protocol in a separate namespace, i.e. `/p2p/<namespace>/<major>.<minor>.<patch>`.
+    /// Peers only exchange requests within the same namespace.
+    pub fn namespaced(namespace: &str, major: u8, minor: u8, patch: u8) -> Self {
+        let version: Arc<str> = format!("/p2p/{}/{}.{}.{}", namespace, major, minor, patch).into();
+        MessageProtocol {
+            version: version.clone(),
+            base_version: version,
+            is_batch: false,
+            has_envelope: false,
+        }
+    }
+
+    // Version of the protocol whose messages are encoded with the codec of the given name, i.e.
+    // `<version>/<codec>`. Peers with different codecs don't negotiate a common protocol.
+    pub(crate) fn with_codec(&self, codec: &str) -> Self {
+        let version = format!("{}/{}", self.version, codec);
+        MessageProtocol {
+            version: version.into(),
+            base_version: self.base_version.clone(),
+            is_batch: false,
+            has_envelope: false,
+        }
+    }
+
+    // Variant of the protocol for sending multiple requests as batch on one substream.
+    pub(crate) fn batched(&self) -> Self {
+        let version = format!("{}/batch", self.version);
+        MessageProtocol {
+            version: version.into(),
+            base_version: self.base_version.clone(),
+            is_batch: true,
+            has_envelope: false,
+        }
+    }
+
+    // Variant of the protocol for sending a single request in an envelope, together with any combination of a
+    // capability token, an idempotency key and metadata.
+    pub(crate) fn with_envelope(&self) -> Self {
+        let version = format!("{}/env", self.version);
+        MessageProtocol {
+            version: version.into(),
+            base_version: self.base_version.clone(),
+            is_batch: false,
+            has_envelope: true,
+        }
+    }
+
+    // Name of the protocol version, which is shared by all its variants.
+    pub(crate) fn base_version(&self) -> &str {
+        &self.base_version
+    }
+
+    pub(crate) fn is_batch(&self) -> bool {
+        self.is_batch
+    }
+
+    // Whether the request is sent in an envelope together with a capability token, idempotency key or metadata.
+    pub(crate) fn has_envelope(&self) -> bool {
+        self.has_envelope
     }
 }
 
@@ -55,11 +161,168 @@ where
     Rq: RqRsMessage,
     Rs: RqRsMessage,
 {
-    /// Supported protocols for inbound requests.
+    /// Supported protocols for inbound requests, including their batch and envelope variants.
     /// Rejects all inbound requests if empty.
-    pub protocols: SmallVec<[MessageProtocol; 2]>,
-    /// Channel for forwarding the inbound request.
-    pub request_tx: oneshot::Sender<(Rq, oneshot::Sender<Rs>)>,
+    pub protocols: SmallVec<[MessageProtocol; 8]>,
+    /// Channel for forwarding the inbound requests and their size in bytes.
+    /// Contains multiple requests if the remote sent a batch.
+    pub request_tx: oneshot::Sender<SmallVec<[InboundRequest<Rq, Rs>; 1]>>,
+    /// Optional pool for decoding large requests.
+    pub decode_pool: Option<DecodePool>,
+    /// Optional converters for requests of earlier protocol versions.
+    pub migrations: Option<Arc<MessageMigrations<Rq, Rs>>>,
+    /// Optional codec for requests and responses, instead of JSON.
+    pub codec: Option<Arc<dyn Codec<Rq, Rs>>>,
+}
+
+/// Metadata, priority class and the id that the sender assigned to the request, that were attached to a request.
+pub type RequestHeaders = (RequestMetadata, RequestPriority, Option<GlobalRequestId>);
+
+/// Inbound request, the capability token, idempotency key, and headers that were attached to it, its size in bytes,
+/// the channel for sending back the response, and the channel for sending back the reason if the request is not
+/// answered.
+pub type InboundRequest<Rq, Rs> = (
+    Rq,
+    Option<CapabilityToken>,
+    Option<String>,
+    Option<RequestHeaders>,
+    usize,
+    oneshot::Sender<Rs>,
+    oneshot::Sender<RemoteError>,
+);
+
+// Request that was read from a substream, together with the capability token, idempotency key and headers that were
+// attached to it, and its size in bytes.
+type RequestFrame<T> = (
+    T,
+    Option<CapabilityToken>,
+    Option<String>,
+    Option<RequestHeaders>,
+    usize,
+);
+
+// Request together with the capability token that authorizes it, the key that identifies retransmissions of it, and
+// metadata with the priority class of the sender and the id that the sender assigned to it.
+// Fields that are not set are omitted.
+#[derive(Serialize, Deserialize)]
+pub(super) struct RequestEnvelope<C, K, M, T> {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) capability: Option<C>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) key: Option<K>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) metadata: Option<M>,
+    #[serde(default)]
+    pub(super) priority: RequestPriority,
+    // Missing if the sender uses a version of this library that does not send the id.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) origin: Option<GlobalRequestId>,
+    pub(super) request: T,
+}
+
+/// Error for an inbound request that could not be decoded because its type is unknown, e.g. because the remote uses a
+/// newer version of the request enum that has additional variants.
+///
+/// A [`Codec`] returns this error wrapped in an [`io::Error`], so that the remote is informed with
+/// [`RemoteError::UnknownRequest`] instead of the connection being closed.
+#[derive(Debug)]
+pub struct UnknownRequestType(String);
+
+impl UnknownRequestType {
+    /// Error with a description of the unknown type.
+    pub fn new(description: impl Into<String>) -> Self {
+        UnknownRequestType(description.into())
+    }
+}
+
+impl fmt::Display for UnknownRequestType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unknown request type: {}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownRequestType {}
+
+impl From<UnknownRequestType> for io::Error {
+    fn from(e: UnknownRequestType) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+// Whether the upgrade failed because the type of the inbound request is unknown.
+pub(super) fn is_unknown_request(error: &io::Error) -> bool {
+    error.get_ref().map_or(false, |e| e.is::<UnknownRequestType>())
+}
+
+// Deserialize the bytes of a request. If the request is an enum and its variant is unknown, the error is an
+// `UnknownRequest`.
+pub(super) fn decode_request<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, io::Error> {
+    serde_json::from_slice(bytes).map_err(|e| {
+        if e.classify() == Category::Data && has_unknown_variant::<T>(bytes) {
+            UnknownRequestType::new(e.to_string()).into()
+        } else {
+            io::Error::new(io::ErrorKind::InvalidData, e)
+        }
+    })
+}
+
+// Whether the bytes are an externally tagged enum variant that is not one of the variants of `T`.
+// Only the outermost enum is checked, an unknown variant of a nested enum makes the request malformed.
+fn has_unknown_variant<T: DeserializeOwned>(bytes: &[u8]) -> bool {
+    let variants = match T::deserialize(VariantProbe) {
+        Err(ProbeError(Some(variants))) => variants,
+        _ => return false,
+    };
+    let tag = match serde_json::from_slice(bytes) {
+        Ok(serde_json::Value::String(tag)) => tag,
+        Ok(serde_json::Value::Object(map)) if map.len() == 1 => {
+            map.into_iter().next().map(|(tag, _)| tag).unwrap_or_default()
+        }
+        _ => return false,
+    };
+    !variants.contains(&tag.as_str())
+}
+
+// Deserializer that only collects the variant names if the deserialized type is an enum.
+struct VariantProbe;
+
+#[derive(Debug)]
+struct ProbeError(Option<&'static [&'static str]>);
+
+impl fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Probed variants: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for ProbeError {}
+
+impl de::Error for ProbeError {
+    fn custom<E: fmt::Display>(_: E) -> Self {
+        ProbeError(None)
+    }
+}
+
+impl<'de> Deserializer<'de> for VariantProbe {
+    type Error = ProbeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+        Err(ProbeError(None))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        variants: &'static [&'static str],
+        _: V,
+    ) -> Result<V::Value, Self::Error> {
+        Err(ProbeError(Some(variants)))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option unit unit_struct
+        newtype_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
 }
 
 impl<Rq, Rs> UpgradeInfo for ResponseProtocol<Rq, Rs>
@@ -68,7 +331,7 @@ where
     Rs: RqRsMessage,
 {
     type Info = MessageProtocol;
-    type InfoIter = smallvec::IntoIter<[Self::Info; 2]>;
+    type InfoIter = smallvec::IntoIter<[Self::Info; 8]>;
 
     fn protocol_info(&self) -> Self::InfoIter {
         self.protocols.clone().into_iter()
@@ -80,32 +343,286 @@ where
     Rq: RqRsMessage,
     Rs: RqRsMessage,
 {
-    // If a response was send back to remote.
-    // False if the response channel was dropped on a higher level before a response was sent.
-    type Output = bool;
+    // Outcome for each received request, in the order of the requests.
+    type Output = SmallVec<[ResponseOutcome; 1]>;
     type Error = io::Error;
     type Future = BoxFuture<'static, Result<Self::Output, Self::Error>>;
 
-    fn upgrade_inbound(self, mut io: NegotiatedSubstream, _: Self::Info) -> Self::Future {
+    fn upgrade_inbound(self, mut io: NegotiatedSubstream, info: Self::Info) -> Self::Future {
         async move {
+            // Requests of a deprecated protocol version are not forwarded, the remote is asked to upgrade instead.
+            if let Some(min_version) = self.migrations.as_ref().and_then(|m| m.deprecation(&info)) {
+                if info.is_batch() {
+                    // The requests of a batch can not be answered with an error, they are reported as dropped.
+                    let is_encoded = self.codec.is_some();
+                    let count_requests = move |bytes: &[u8]| match is_encoded {
+                        true => split_batch(bytes).map(|items| items.len()),
+                        false => decode::<Vec<serde_json::Value>>(bytes).map(|values| values.len()),
+                    };
+                    let (count, _) = read_and_decode(&mut io, None, count_requests).await?;
+                    let buf = match is_encoded {
+                        true => join_encoded_batch((0..count).map(|_| None)).0,
+                        false => {
+                            let responses: Vec<Option<Rs>> = (0..count).map(|_| None).collect();
+                            serialize_batch(&responses)?.0
+                        }
+                    };
+                    write_length_prefixed(&mut io, buf).await?;
+                } else {
+                    read_length_prefixed(&mut io, MAX_MESSAGE_SIZE)
+                        .await
+                        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                    write_error(&mut io, &RemoteError::UpgradeRequired { min_version }).await?;
+                }
+                io.close().await?;
+                return Ok(SmallVec::new());
+            }
+            // Requests of an earlier protocol version are converted to the current type.
+            let converter = self.migrations.as_ref().and_then(|m| m.request_converter(&info));
+            if info.is_batch() {
+                // Read the batch of requests, forward them to the handler.
+                // A batch with a request of an unknown type is dropped as a whole, since the number of requests is not
+                // known, the remote can not be informed about it.
+                let codec = self.codec.clone();
+                let decode_requests = move |bytes: &[u8]| {
+                    // Requests of an earlier version are converted individually, after each of them was decoded.
+                    let decode_item = |item: &[u8]| match (&converter, &codec) {
+                        (Some(convert), Some(codec)) => codec.decode_value(item).and_then(&**convert),
+                        (Some(convert), None) => decode(item).and_then(&**convert),
+                        (None, Some(codec)) => codec.decode_request(item),
+                        (None, None) => decode_request::<Rq>(item),
+                    };
+                    match codec.is_some() {
+                        true => split_batch(bytes)?
+                            .into_iter()
+                            .map(|item| {
+                                let item = item.ok_or_else(|| invalid_data("Missing request in batch."))?;
+                                decode_item(item).map(|request| (request, item.len()))
+                            })
+                            .collect(),
+                        false => decode_batch(bytes, decode_item),
+                    }
+                };
+                let (requests, _) = read_and_decode(&mut io, self.decode_pool.as_ref(), decode_requests).await?;
+                let mut response_rxs = Vec::with_capacity(requests.len());
+                let mut forward = SmallVec::with_capacity(requests.len());
+                for (request, request_len) in requests {
+                    let (tx, rx) = oneshot::channel();
+                    // Requests of a batch are not answered with an error, they are reported as dropped.
+                    let (error_tx, _) = oneshot::channel();
+                    forward.push((request, None, None, None, request_len, tx, error_tx));
+                    response_rxs.push(rx);
+                }
+                let _ = self.request_tx.send(forward);
+
+                // Receive all responses, write them back as batch.
+                let responses: Vec<Option<Rs>> = future::join_all(response_rxs)
+                    .await
+                    .into_iter()
+                    .map(Result::ok)
+                    .collect();
+                let is_answered: Vec<bool> = responses.iter().map(Option::is_some).collect();
+                let codec = self.codec.clone();
+                let encode_responses = move || match codec {
+                    Some(codec) => {
+                        let items = responses
+                            .iter()
+                            .map(|r| r.as_ref().map(|r| codec.encode_response(r)).transpose())
+                            .collect::<Result<Vec<_>, _>>()?;
+                        Ok(join_encoded_batch(items.iter().map(|item| item.as_deref())))
+                    }
+                    None => serialize_batch(&responses),
+                };
+                let (buf, lens) = encode(self.decode_pool.as_ref(), encode_responses).await?;
+                write_length_prefixed(&mut io, buf).await?;
+                io.close().await?;
+                let res = is_answered
+                    .into_iter()
+                    .zip(lens)
+                    .map(|(is_answered, len)| match is_answered {
+                        true => ResponseOutcome::Sent(len),
+                        false => ResponseOutcome::Omitted,
+                    });
+                return Ok(res.collect());
+            }
             // Read a request form the substream, forward it to the handler.
-            let request = read_and_parse(&mut io).await?;
-            // Create channel to receive the response.
+            let pool = self.decode_pool.as_ref();
+            let frame = match (converter, self.codec.clone()) {
+                (Some(convert), codec) => {
+                    let is_encoded = codec.is_some();
+                    let decode_value = move |bytes: &[u8]| match codec.as_ref() {
+                        Some(codec) => codec.decode_value(bytes),
+                        None => decode(bytes),
+                    };
+                    let (value, capability, key, metadata, request_len) =
+                        read_request(&mut io, &info, pool, is_encoded, decode_value).await?;
+                    convert(value).map(|request| (request, capability, key, metadata, request_len))
+                }
+                (None, Some(codec)) => {
+                    read_request(&mut io, &info, pool, true, move |bytes| codec.decode_request(bytes)).await
+                }
+                (None, None) => read_request(&mut io, &info, pool, false, decode_request::<Rq>).await,
+            };
+            let (request, capability, key, metadata, request_len) = match frame {
+                Ok(frame) => frame,
+                Err(e) if is_unknown_request(&e) => {
+                    // Tell the remote that the request type is not known, instead of just closing the substream.
+                    let _ = write_error(&mut io, &RemoteError::UnknownRequest).await;
+                    let _ = io.close().await;
+                    return Err(e);
+                }
+                Err(e) => return Err(e),
+            };
+            // Create channels to receive the response, or the reason why the request is not answered.
             let (tx, rx) = oneshot::channel();
-            let _ = self.request_tx.send((request, tx));
+            let (error_tx, error_rx) = oneshot::channel();
+            let _ = self.request_tx.send(smallvec![(
+                request,
+                capability,
+                key,
+                metadata,
+                request_len,
+                tx,
+                error_tx
+            )]);
 
             // Receive the response, write it back to the substream.
             let res = match rx.await {
-                Ok(response) => parse_and_write(&mut io, &response).await.map(|_| true)?,
-                Err(_) => false,
+                Ok(response) => write_response(&mut io, response, self.codec.clone(), self.decode_pool.as_ref())
+                    .await
+                    .map(ResponseOutcome::Sent)?,
+                Err(_) => {
+                    let error = error_rx.await.unwrap_or(RemoteError::Dropped);
+                    let outcome = match error {
+                        RemoteError::Rejected(_) => ResponseOutcome::Rejected,
+                        _ => ResponseOutcome::Omitted,
+                    };
+                    let _ = write_error(&mut io, &error).await;
+                    outcome
+                }
             };
             io.close().await?;
-            Ok(res)
+            Ok(smallvec![res])
         }
         .boxed()
     }
 }
 
+/// Outbound request that was serialized once, so that the bytes can be shared if the same request is sent to
+/// multiple peers.
+#[derive(Debug, Clone)]
+pub struct SerializedRequest {
+    bytes: Arc<[u8]>,
+    // Whether the bytes contain an envelope with a capability token, idempotency key or metadata, together with the
+    // request.
+    has_envelope: bool,
+    // Whether the request was encoded with a custom codec instead of JSON.
+    is_encoded: bool,
+    // Priority class of the request, used for ordering the requests that are queued for a connection.
+    priority: RequestPriority,
+}
+
+impl SerializedRequest {
+    pub fn new<Rq: Serialize>(request: &Rq) -> Result<Self, serde_json::Error> {
+        let bytes = serde_json::to_vec(request)?;
+        Ok(SerializedRequest {
+            bytes: bytes.into(),
+            has_envelope: false,
+            is_encoded: false,
+            priority: RequestPriority::Normal,
+        })
+    }
+
+    // Request that was encoded with a custom codec.
+    pub fn encoded(bytes: Vec<u8>) -> Self {
+        SerializedRequest {
+            bytes: bytes.into(),
+            has_envelope: false,
+            is_encoded: true,
+            priority: RequestPriority::Normal,
+        }
+    }
+
+    // Serialize the request in an envelope together with the capability token, idempotency key and headers that are
+    // set, and encode the request with the codec if one is set.
+    // Such a request is sent on its own substream with the envelope variant of the protocol.
+    pub fn with_envelope<Rq: Serialize, Rs>(
+        request: &Rq,
+        capability: Option<&CapabilityToken>,
+        key: Option<&str>,
+        headers: Option<(&RequestMetadata, RequestPriority, Option<GlobalRequestId>)>,
+        codec: Option<&dyn Codec<Rq, Rs>>,
+    ) -> Result<Self, io::Error> {
+        let priority = headers
+            .map(|(_, priority, _)| priority)
+            .unwrap_or(RequestPriority::Normal);
+        let bytes = serialize_envelope(request, codec, |request| RequestEnvelope {
+            capability,
+            key,
+            metadata: headers.map(|(metadata, _, _)| metadata),
+            priority,
+            origin: headers.and_then(|(_, _, origin)| origin),
+            request,
+        })?;
+        Ok(SerializedRequest {
+            bytes: bytes.into(),
+            has_envelope: true,
+            is_encoded: codec.is_some(),
+            priority,
+        })
+    }
+
+    pub fn has_envelope(&self) -> bool {
+        self.has_envelope
+    }
+
+    pub fn priority(&self) -> RequestPriority {
+        self.priority
+    }
+
+    // Whether the request can be sent as part of a batch.
+    pub fn is_batchable(&self) -> bool {
+        !self.has_envelope
+    }
+
+    // Whether the request was encoded with a custom codec instead of JSON.
+    pub fn is_encoded(&self) -> bool {
+        self.is_encoded
+    }
+}
+
+// Request in an envelope: serialized as part of the JSON envelope, or encoded with a codec after the JSON header of
+// the envelope, in which case it is `null` in the header.
+#[derive(Serialize)]
+#[serde(untagged)]
+enum EnvelopePayload<'a, Rq> {
+    Json(&'a Rq),
+    Encoded,
+}
+
+// Serialize the envelope that the `envelope` function creates for the request.
+// If a codec is set, the envelope is sent as length-prefixed JSON header followed by the encoded request.
+fn serialize_envelope<'a, Rq: Serialize, Rs, E: Serialize>(
+    request: &'a Rq,
+    codec: Option<&dyn Codec<Rq, Rs>>,
+    envelope: impl Fn(EnvelopePayload<'a, Rq>) -> E,
+) -> Result<Vec<u8>, io::Error> {
+    let codec = match codec {
+        Some(codec) => codec,
+        None => return serde_json::to_vec(&envelope(EnvelopePayload::Json(request))).map_err(invalid_data),
+    };
+    let header = serde_json::to_vec(&envelope(EnvelopePayload::Encoded)).map_err(invalid_data)?;
+    let (mut buf, _) = join_encoded_batch([Some(header.as_slice())].into_iter());
+    buf.extend(codec.encode_request(request)?);
+    Ok(buf)
+}
+
+impl AsRef<[u8]> for SerializedRequest {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
 /// Request substream upgrade protocol.
 ///
 /// Sends a request and receives a response.
@@ -116,11 +633,23 @@ where
     Rs: RqRsMessage,
 {
     /// Supported protocols for outbound requests.
+    /// For a batch of requests, these have to be the batch variants of the protocols, for a request with capability
+    /// token, idempotency key or metadata the envelope variants.
     pub protocols: SmallVec<[MessageProtocol; 2]>,
-    /// Outbound request.
-    pub request: Rq,
+    /// Serialized outbound requests.
+    /// Multiple requests are sent as batch.
+    pub requests: SmallVec<[SerializedRequest; 1]>,
+    /// Optional pool for decoding large responses.
+    pub decode_pool: Option<DecodePool>,
+    /// Optional converters for responses of earlier protocol versions.
+    pub migrations: Option<Arc<MessageMigrations<Rq, Rs>>>,
+    /// Optional codec for responses, instead of JSON. The requests were already encoded with it.
+    pub codec: Option<Arc<dyn Codec<Rq, Rs>>>,
+    /// Channel for reporting that the requests were written to the substream, with the number of bytes written for
+    /// each request.
+    pub written_tx: Option<oneshot::Sender<Vec<usize>>>,
 
-    pub _marker: PhantomData<Rs>,
+    pub _marker: PhantomData<(Rq, Rs)>,
 }
 
 impl<Rq, Rs> UpgradeInfo for RequestProtocol<Rq, Rs>
@@ -136,43 +665,443 @@ where
     }
 }
 
+/// Outcome of an inbound request on the substream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseOutcome {
+    /// The response was sent, with the number of bytes written for it.
+    Sent(usize),
+    /// The request was rejected with a reason that was sent to the remote.
+    Rejected,
+    /// The response channel was dropped without a response, either by the application or because the request failed
+    /// locally.
+    Omitted,
+}
+
+/// Response from the remote for a sent request, the number of bytes written for the request, the number of bytes
+/// read for the response, and the time between writing the request and reading the response.
+/// Instead of the response, the remote may send the reason why it did not answer the request. A request of a batch
+/// that the remote dropped without responding is reported as [`RemoteError::Dropped`].
+pub type RequestOutcome<Rs> = (Result<Rs, RemoteError>, usize, usize, Duration);
+
 impl<Rq, Rs> OutboundUpgrade<NegotiatedSubstream> for RequestProtocol<Rq, Rs>
 where
     Rq: RqRsMessage,
     Rs: RqRsMessage,
 {
-    // Response from the remote for the sent request.
-    type Output = Rs;
+    // Outcome for each sent request, in the order of the requests.
+    type Output = SmallVec<[RequestOutcome<Rs>; 1]>;
     type Error = io::Error;
     type Future = BoxFuture<'static, Result<Self::Output, Self::Error>>;
 
-    fn upgrade_outbound(self, mut io: NegotiatedSubstream, _: Self::Info) -> Self::Future {
+    fn upgrade_outbound(self, mut io: NegotiatedSubstream, info: Self::Info) -> Self::Future {
         async move {
+            // Responses of an earlier protocol version are converted to the current type.
+            let converter = self.migrations.as_ref().and_then(|m| m.response_converter(&info));
+            let written_tx = self.written_tx;
+            if info.is_batch() {
+                // Write the batch of requests to the substream.
+                let (buf, request_lens) = match self.codec.is_some() {
+                    true => join_encoded_batch(self.requests.iter().map(|r| Some(r.as_ref()))),
+                    false => join_batch(self.requests.iter().map(AsRef::as_ref)),
+                };
+                write_length_prefixed(&mut io, buf).await?;
+                let sent_at = Instant::now();
+                if let Some(tx) = written_tx {
+                    let _ = tx.send(request_lens.clone());
+                }
+                // Read the batch of responses, a dropped request is answered with `null`.
+                let codec = self.codec.clone();
+                let decode_responses = move |bytes: &[u8]| match (converter, codec) {
+                    // Responses of an earlier version are converted individually, after each of them was decoded.
+                    (Some(convert), Some(codec)) => split_batch(bytes)?
+                        .into_iter()
+                        .map(|item| {
+                            let response = item.map(|item| codec.decode_value(item).and_then(&*convert));
+                            Ok((response.transpose()?, item.map_or(0, <[u8]>::len)))
+                        })
+                        .collect(),
+                    (Some(convert), None) => decode_batch(bytes, |item| {
+                        decode::<Option<serde_json::Value>>(item).and_then(|v| v.map(&*convert).transpose())
+                    }),
+                    (None, Some(codec)) => split_batch(bytes)?
+                        .into_iter()
+                        .map(|item| {
+                            let response = item.map(|item| codec.decode_response(item)).transpose()?;
+                            Ok((response, item.map_or(0, <[u8]>::len)))
+                        })
+                        .collect(),
+                    (None, None) => decode_batch(bytes, decode::<Option<Rs>>),
+                };
+                let (responses, _) = read_and_decode(&mut io, self.decode_pool.as_ref(), decode_responses).await?;
+                let rtt = sent_at.elapsed();
+                io.close().await?;
+                if responses.len() != request_lens.len() {
+                    let err = "Number of responses does not match the number of requests.";
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+                }
+                let res = responses
+                    .into_iter()
+                    .zip(request_lens)
+                    .map(|((r, response_len), request_len)| {
+                        let r = r.ok_or(RemoteError::Dropped);
+                        (r, request_len, response_len, rtt)
+                    });
+                return Ok(res.collect());
+            }
+            let request = self
+                .requests
+                .into_iter()
+                .next()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Missing request."))?;
             // Write outbound request to the substream.
-            parse_and_write(&mut io, &self.request).await?;
+            let request_len = request.as_ref().len();
+            write_length_prefixed(&mut io, request).await?;
+            let sent_at = Instant::now();
+            if let Some(tx) = written_tx {
+                let _ = tx.send(vec![request_len]);
+            }
             // Read inbound response and return it.
-            let response = read_and_parse(&mut io).await?;
+            let (response, response_len) = match (converter, self.codec) {
+                (Some(convert), codec) => {
+                    let decode_value = move |bytes: &[u8]| match codec {
+                        Some(codec) => codec.decode_value(bytes),
+                        None => decode(bytes),
+                    };
+                    let (response, response_len) =
+                        read_response(&mut io, self.decode_pool.as_ref(), decode_value).await?;
+                    let response = match response {
+                        Ok(value) => Ok(convert(value)?),
+                        Err(error) => Err(error),
+                    };
+                    (response, response_len)
+                }
+                (None, Some(codec)) => {
+                    let decode = move |bytes: &[u8]| codec.decode_response(bytes);
+                    read_response(&mut io, self.decode_pool.as_ref(), decode).await?
+                }
+                (None, None) => read_response(&mut io, self.decode_pool.as_ref(), decode).await?,
+            };
+            let rtt = sent_at.elapsed();
             io.close().await?;
-            Ok(response)
+            Ok(smallvec![(response, request_len, response_len, rtt)])
         }
         .boxed()
     }
 }
 
-// Read from substream and deserialize the received bytes.
-async fn read_and_parse<TRq: DeserializeOwned>(io: &mut NegotiatedSubstream) -> Result<TRq, io::Error> {
-    read_length_prefixed(io, usize::MAX)
-        .map(|res| match res {
-            Ok(bytes) => {
-                serde_json::from_slice(bytes.as_slice()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
-            }
-            Err(e) => Err(io::Error::new(io::ErrorKind::Other, e)),
-        })
+// Read from substream and decode the received bytes with the `decode` function, e.g. of a codec.
+// Large messages are decoded in the pool, if there is one.
+//
+// The size of the message is bounded by `MAX_MESSAGE_SIZE`, and the nesting depth of JSON by the recursion limit of
+// `serde_json`.
+async fn read_and_decode<T, F>(
+    io: &mut NegotiatedSubstream,
+    decode_pool: Option<&DecodePool>,
+    decode: F,
+) -> Result<(T, usize), io::Error>
+where
+    T: Send + 'static,
+    F: FnOnce(&[u8]) -> Result<T, io::Error> + Send + 'static,
+{
+    let bytes = read_length_prefixed(io, MAX_MESSAGE_SIZE)
         .await
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    let len = bytes.len();
+    let data = match decode_pool {
+        Some(pool) => pool.decode(bytes, decode).await?,
+        None => decode(&bytes)?,
+    };
+    Ok((data, len))
+}
+
+// Read a single request from the substream, in the envelope of the negotiated protocol variant.
+// The request itself is decoded with the `decode` function, `is_encoded` is whether it was encoded with a codec.
+async fn read_request<T, F>(
+    io: &mut NegotiatedSubstream,
+    info: &MessageProtocol,
+    decode_pool: Option<&DecodePool>,
+    is_encoded: bool,
+    decode: F,
+) -> Result<RequestFrame<T>, io::Error>
+where
+    T: Send + 'static,
+    F: Fn(&[u8]) -> Result<T, io::Error> + Send + 'static,
+{
+    let info = info.clone();
+    let decode_frame = move |bytes: &[u8]| decode_request_frame(bytes, &info, is_encoded, decode);
+    let (frame, _) = read_and_decode(io, decode_pool, decode_frame).await?;
+    Ok(frame)
+}
+
+// Deserialize the envelope of a request, the request itself may borrow from the bytes.
+fn decode_envelope<'a, E: Deserialize<'a>>(bytes: &'a [u8]) -> Result<E, io::Error> {
+    serde_json::from_slice(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// Envelope of an inbound request, whose request is decoded separately.
+type ReceivedEnvelope<'a> = RequestEnvelope<CapabilityToken, String, RequestMetadata, &'a RawValue>;
+
+// Decode the envelope, and return it together with the bytes of the request.
+// If the request was encoded with a codec, it follows the length-prefixed JSON header of the envelope.
+fn open_envelope(bytes: &[u8], is_encoded: bool) -> Result<(ReceivedEnvelope<'_>, &[u8]), io::Error> {
+    if !is_encoded {
+        let envelope: ReceivedEnvelope = decode_envelope(bytes)?;
+        let request = envelope.request.get().as_bytes();
+        return Ok((envelope, request));
+    }
+    let mut request = bytes;
+    let header = split_item(&mut request)?.ok_or_else(|| invalid_data("Missing envelope header."))?;
+    Ok((decode_envelope(header)?, request))
 }
 
-// Serialize the data and write bytes to substream.
-async fn parse_and_write<TRq: Serialize>(io: &mut NegotiatedSubstream, data: &TRq) -> Result<(), io::Error> {
-    let buf = serde_json::to_vec(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-    write_length_prefixed(io, buf).await
+// Decode a single request, in an envelope if the protocol variant has one, the request itself with the `decode`
+// function.
+// The envelope is decoded first, so that an unknown request type can be told apart from a malformed envelope.
+fn decode_request_frame<T>(
+    bytes: &[u8],
+    info: &MessageProtocol,
+    is_encoded: bool,
+    decode: impl Fn(&[u8]) -> Result<T, io::Error>,
+) -> Result<RequestFrame<T>, io::Error> {
+    if !info.has_envelope() {
+        return Ok((decode(bytes)?, None, None, None, bytes.len()));
+    }
+    let (envelope, request) = open_envelope(bytes, is_encoded)?;
+    let headers = envelope
+        .metadata
+        .map(|metadata| (metadata, envelope.priority, envelope.origin));
+    Ok((
+        decode(request)?,
+        envelope.capability,
+        envelope.key,
+        headers,
+        bytes.len(),
+    ))
+}
+
+// Read a response from the substream and decode it with the `decode` function, or the error that the remote sent
+// instead, as marked by the tag in front of the message.
+async fn read_response<Rs, F>(
+    io: &mut NegotiatedSubstream,
+    decode_pool: Option<&DecodePool>,
+    decode: F,
+) -> Result<(Result<Rs, RemoteError>, usize), io::Error>
+where
+    Rs: Send + 'static,
+    F: FnOnce(&[u8]) -> Result<Rs, io::Error> + Send + 'static,
+{
+    let bytes = read_length_prefixed(io, MAX_MESSAGE_SIZE)
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    let (tag, payload) = bytes.split_first().ok_or_else(|| invalid_data("Missing frame tag."))?;
+    let len = payload.len();
+    match *tag {
+        RESPONSE_FRAME => {}
+        ERROR_FRAME => {
+            let error = serde_json::from_slice(payload).map_err(invalid_data)?;
+            return Ok((Err(error), len));
+        }
+        _ => return Err(invalid_data("Unknown frame tag.")),
+    }
+    let data = match decode_pool {
+        Some(pool) => pool.decode(bytes, move |bytes: &[u8]| decode(&bytes[1..])).await?,
+        None => decode(payload)?,
+    };
+    Ok((Ok(data), len))
+}
+
+// Serialize the error that is sent instead of the response to a single request, and write it to the substream.
+// Returns the number of bytes that were written.
+async fn write_error(io: &mut NegotiatedSubstream, error: &RemoteError) -> Result<usize, io::Error> {
+    let mut buf = vec![ERROR_FRAME];
+    serde_json::to_writer(&mut buf, error).map_err(invalid_data)?;
+    let len = buf.len() - 1;
+    write_length_prefixed(io, buf).await?;
+    Ok(len)
+}
+
+// Encode the response with the codec, or serialize it as JSON if there is none, and write the bytes to the substream.
+// The response is encoded in the pool, if there is one.
+// Returns the number of bytes that were written.
+async fn write_response<Rq: RqRsMessage, Rs: RqRsMessage>(
+    io: &mut NegotiatedSubstream,
+    response: Rs,
+    codec: Option<Arc<dyn Codec<Rq, Rs>>>,
+    pool: Option<&DecodePool>,
+) -> Result<usize, io::Error> {
+    let encode_response = move || {
+        let mut buf = vec![RESPONSE_FRAME];
+        match codec {
+            Some(codec) => buf.extend(codec.encode_response(&response)?),
+            None => serde_json::to_writer(&mut buf, &response).map_err(invalid_data)?,
+        }
+        Ok(buf)
+    };
+    let buf = encode(pool, encode_response).await?;
+    let len = buf.len() - 1;
+    write_length_prefixed(io, buf).await?;
+    Ok(len)
+}
+
+// Encode a message with the `encode` function, in the pool if there is one.
+async fn encode<T, F>(pool: Option<&DecodePool>, encode: F) -> Result<T, io::Error>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, io::Error> + Send + 'static,
+{
+    match pool {
+        Some(pool) => pool.encode(encode).await,
+        None => encode(),
+    }
+}
+
+// Serialize each item individually and join them to a JSON array.
+// Returns the bytes and the number of bytes of each item.
+fn serialize_batch<T: Serialize>(items: &[T]) -> Result<(Vec<u8>, Vec<usize>), io::Error> {
+    let items = items
+        .iter()
+        .map(|item| serde_json::to_vec(item).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(join_batch(items.iter().map(Vec::as_slice)))
+}
+
+// Decode each item of a JSON array with the `decode_item` function.
+// Returns the items together with their size in bytes.
+fn decode_batch<T>(
+    bytes: &[u8],
+    decode_item: impl Fn(&[u8]) -> Result<T, io::Error>,
+) -> Result<Vec<(T, usize)>, io::Error> {
+    let items: Vec<&RawValue> =
+        serde_json::from_slice(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    items
+        .into_iter()
+        .map(|item| {
+            let item = item.get().as_bytes();
+            decode_item(item).map(|data| (data, item.len()))
+        })
+        .collect()
+}
+
+// Join items that were encoded with a codec to a batch: each item is prefixed with its length as 4 bytes big-endian,
+// a dropped item is only the length `DROPPED_ITEM`.
+// Returns the bytes and the number of bytes of each item.
+fn join_encoded_batch<'a>(items: impl ExactSizeIterator<Item = Option<&'a [u8]>>) -> (Vec<u8>, Vec<usize>) {
+    let mut buf = Vec::new();
+    let mut lens = Vec::with_capacity(items.len());
+    for item in items {
+        match item {
+            Some(item) => {
+                buf.extend_from_slice(&(item.len() as u32).to_be_bytes());
+                buf.extend_from_slice(item);
+                lens.push(item.len());
+            }
+            None => {
+                buf.extend_from_slice(&DROPPED_ITEM.to_be_bytes());
+                lens.push(0);
+            }
+        }
+    }
+    (buf, lens)
+}
+
+// Split a batch of items that were encoded with a codec, `None` for dropped items.
+fn split_batch(mut bytes: &[u8]) -> Result<Vec<Option<&[u8]>>, io::Error> {
+    let mut items = Vec::new();
+    while !bytes.is_empty() {
+        items.push(split_item(&mut bytes)?);
+    }
+    Ok(items)
+}
+
+// Split the next length-prefixed item from the bytes, `None` if the item was dropped.
+fn split_item<'a>(bytes: &mut &'a [u8]) -> Result<Option<&'a [u8]>, io::Error> {
+    if bytes.len() < 4 {
+        return Err(invalid_data("Truncated item length."));
+    }
+    let (len, rest) = bytes.split_at(4);
+    let len = u32::from_be_bytes([len[0], len[1], len[2], len[3]]);
+    if len == DROPPED_ITEM {
+        *bytes = rest;
+        return Ok(None);
+    }
+    if rest.len() < len as usize {
+        return Err(invalid_data("Truncated item."));
+    }
+    let (item, rest) = rest.split_at(len as usize);
+    *bytes = rest;
+    Ok(Some(item))
+}
+
+// Error for a malformed message.
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+// Join already serialized items to a JSON array.
+// Returns the bytes and the number of bytes of each item.
+fn join_batch<'a>(items: impl ExactSizeIterator<Item = &'a [u8]>) -> (Vec<u8>, Vec<usize>) {
+    let mut buf = vec![b'['];
+    let mut lens = Vec::with_capacity(items.len());
+    for (i, item) in items.enumerate() {
+        if i > 0 {
+            buf.push(b',');
+        }
+        lens.push(item.len());
+        buf.extend_from_slice(item);
+    }
+    buf.push(b']');
+    (buf, lens)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    enum Inner {
+        A,
+    }
+
+    #[derive(Debug, Deserialize)]
+    enum Request {
+        Ping,
+        Nested(Inner),
+    }
+
+    #[test]
+    fn unknown_request_variant() {
+        assert!(decode_request::<Request>(br#""Ping""#).is_ok());
+        assert!(decode_request::<Request>(br#"{"Nested":"A"}"#).is_ok());
+
+        let err = decode_request::<Request>(br#""Pong""#).unwrap_err();
+        assert!(is_unknown_request(&err));
+        let err = decode_request::<Request>(br#"{"Pong":1}"#).unwrap_err();
+        assert!(is_unknown_request(&err));
+
+        // An unknown variant of a nested enum is a malformed request.
+        let err = decode_request::<Request>(br#"{"Nested":"B"}"#).unwrap_err();
+        assert!(!is_unknown_request(&err));
+        let err = decode_request::<Request>(b"[1]").unwrap_err();
+        assert!(!is_unknown_request(&err));
+    }
+
+    #[test]
+    fn envelope_with_multiple_fields() {
+        let protocol = MessageProtocol::new_version(1, 0, 0).with_envelope();
+        let metadata: RequestMetadata = [("tenant".to_string(), "a".to_string())].into_iter().collect();
+        let headers = Some((&metadata, RequestPriority::High, None));
+        let request = SerializedRequest::with_envelope::<u32, ()>(&7, None, Some("key"), headers, None).unwrap();
+        let (request, capability, key, headers, _) =
+            decode_request_frame(request.as_ref(), &protocol, false, decode_request::<u32>).unwrap();
+        assert_eq!(request, 7);
+        assert!(capability.is_none());
+        assert_eq!(key.as_deref(), Some("key"));
+        assert_eq!(headers, Some((metadata, RequestPriority::High, None)));
+
+        // Fields that are not set are omitted from the envelope.
+        let request = SerializedRequest::with_envelope::<u32, ()>(&7, None, Some("key"), None, None).unwrap();
+        let (_, _, _, headers, _) =
+            decode_request_frame(request.as_ref(), &protocol, false, decode_request::<u32>).unwrap();
+        assert!(headers.is_none());
+    }
 }
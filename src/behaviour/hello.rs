@@ -0,0 +1,155 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use super::oneshot::{MessageHandler, MessageHandlerEvent, OneShotMessage};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Capabilities that are advertised to each new peer in the hello exchange after a connection was established.
+///
+/// The encoded hello must not exceed 16 KiB, larger hellos are dropped by the remote.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Hello {
+    /// Names of the application features that the peer supports.
+    pub features: Vec<String>,
+    /// Additional application-defined payload.
+    pub data: Vec<u8>,
+}
+
+impl Hello {
+    pub fn new(features: Vec<String>) -> Self {
+        Hello {
+            features,
+            data: Vec::new(),
+        }
+    }
+
+    /// Send the application-defined `data` with the features.
+    pub fn with_data(mut self, data: Vec<u8>) -> Self {
+        self.data = data;
+        self
+    }
+
+    /// Whether the peer advertised the feature.
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+}
+
+// Hello with the base64 encoded data of the sender.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HelloMessage {
+    features: Vec<String>,
+    data: String,
+}
+
+impl OneShotMessage for HelloMessage {
+    const PROTOCOL_NAME: &'static [u8] = b"/p2p/hello/1.0.0";
+    const MAX_SIZE: usize = 16 * 1024;
+}
+
+// Handler for exchanging hellos on a connection.
+pub type HelloHandler = MessageHandler<HelloMessage>;
+
+// Events emitted by the `HelloHandler`.
+pub type HelloHandlerEvent = MessageHandlerEvent<HelloMessage>;
+
+// Local hello, and the hellos of the connected peers.
+//
+// Hellos are only exchanged with peers whose firewall rule does not reject all requests. If the rule for a peer is
+// not known yet, the hellos in both directions are held back until it was set.
+#[derive(Debug)]
+pub struct Hellos {
+    local: Hello,
+    received: HashMap<PeerId, Hello>,
+    // Peers to which the local hello is sent once their firewall rule is known.
+    pending_out: HashSet<PeerId>,
+    // Hellos that were received before the firewall rule of the peer was known.
+    pending_in: HashMap<PeerId, Hello>,
+}
+
+impl Hellos {
+    pub fn new(local: Hello) -> Self {
+        Hellos {
+            local,
+            received: HashMap::new(),
+            pending_out: HashSet::new(),
+            pending_in: HashMap::new(),
+        }
+    }
+
+    pub fn message(&self) -> HelloMessage {
+        HelloMessage {
+            features: self.local.features.clone(),
+            data: base64::encode(&self.local.data),
+        }
+    }
+
+    // Decode a hello from a peer, `None` if the data is invalid.
+    pub fn decode(message: HelloMessage) -> Option<Hello> {
+        let data = base64::decode(message.data).ok()?;
+        Some(Hello {
+            features: message.features,
+            data,
+        })
+    }
+
+    pub fn defer_send(&mut self, peer: PeerId) {
+        self.pending_out.insert(peer);
+    }
+
+    pub fn defer_received(&mut self, peer: PeerId, hello: Hello) {
+        self.pending_in.insert(peer, hello);
+    }
+
+    // Take the held back hellos of a peer. Returns whether the local hello should be sent, and the hello that was
+    // received from it.
+    pub fn take_pending(&mut self, peer: &PeerId) -> (bool, Option<Hello>) {
+        (self.pending_out.remove(peer), self.pending_in.remove(peer))
+    }
+
+    pub fn on_received(&mut self, peer: PeerId, hello: Hello) {
+        self.received.insert(peer, hello);
+    }
+
+    pub fn get(&self, peer: &PeerId) -> Option<&Hello> {
+        self.received.get(peer)
+    }
+
+    pub fn remove(&mut self, peer: &PeerId) {
+        self.received.remove(peer);
+        self.pending_out.remove(peer);
+        self.pending_in.remove(peer);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encode_and_hold_back() {
+        let local = Hello::new(vec!["blocks".into()]).with_data(vec![1, 2, 3]);
+        let mut hellos = Hellos::new(local.clone());
+        assert_eq!(Hellos::decode(hellos.message()), Some(local));
+        let invalid = HelloMessage {
+            features: Vec::new(),
+            data: "not base64!".into(),
+        };
+        assert!(Hellos::decode(invalid).is_none());
+
+        let peer = PeerId::random();
+        let remote = Hello::new(vec!["transfer".into()]);
+        hellos.defer_send(peer);
+        hellos.defer_received(peer, remote.clone());
+        assert!(hellos.get(&peer).is_none());
+        assert_eq!(hellos.take_pending(&peer), (true, Some(remote.clone())));
+        assert_eq!(hellos.take_pending(&peer), (false, None));
+
+        hellos.on_received(peer, remote);
+        assert!(hellos.get(&peer).unwrap().supports("transfer"));
+        hellos.remove(&peer);
+        assert!(hellos.get(&peer).is_none());
+    }
+}
@@ -0,0 +1,124 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{timer::Instant, RqRsMessage};
+use futures::channel::oneshot;
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+/// Configuration for detecting inbound requests that were retransmitted with the same idempotency key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdempotencyConfig {
+    /// Duration for which the response to a request is cached after it was sent.
+    /// Retransmissions within this window are answered with the cached response.
+    pub window: Duration,
+    /// Maximum number of keys that are tracked at the same time.
+    /// If the limit is reached, requests with new keys are forwarded without deduplication.
+    pub max_entries: usize,
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        IdempotencyConfig {
+            window: Duration::from_secs(5 * 60),
+            max_entries: 1000,
+        }
+    }
+}
+
+// Outcome of a request with an idempotency key.
+enum Entry<Rs> {
+    // The request was forwarded and awaits the response. Contains the response channels of the duplicates.
+    Pending(Vec<oneshot::Sender<Rs>>),
+    // The serialized response, and the time at which it was sent.
+    Done(String, Instant),
+}
+
+// Cache of the responses to inbound requests with an idempotency key, per remote peer and key.
+pub struct IdempotencyCache<Rs> {
+    config: IdempotencyConfig,
+    entries: HashMap<(PeerId, String), Entry<Rs>>,
+    // Keys of the completed entries, in the order in which they completed.
+    completed: VecDeque<(PeerId, String)>,
+}
+
+impl<Rs: RqRsMessage> IdempotencyCache<Rs> {
+    pub fn new(config: IdempotencyConfig) -> Self {
+        IdempotencyCache {
+            config,
+            entries: HashMap::new(),
+            completed: VecDeque::new(),
+        }
+    }
+
+    // Check if a request with the key was received before.
+    // Duplicates are answered with the cached response, or once the response to the first request is available, and
+    // `None` is returned. For new requests the `response_tx` is returned.
+    pub fn on_request(
+        &mut self,
+        peer: PeerId,
+        key: String,
+        response_tx: oneshot::Sender<Rs>,
+    ) -> Option<oneshot::Sender<Rs>> {
+        self.prune();
+        let key = (peer, key);
+        match self.entries.get_mut(&key) {
+            Some(Entry::Pending(duplicates)) => duplicates.push(response_tx),
+            Some(Entry::Done(response, _)) => {
+                if let Ok(response) = serde_json::from_str(response) {
+                    let _ = response_tx.send(response);
+                }
+            }
+            None if self.entries.len() >= self.config.max_entries => return Some(response_tx),
+            None => {
+                self.entries.insert(key, Entry::Pending(Vec::new()));
+                return Some(response_tx);
+            }
+        }
+        None
+    }
+
+    // Cache the response to the first request, and send it to the duplicates that waited for it.
+    pub fn on_response(&mut self, peer: PeerId, key: String, response: &Rs) {
+        let key = (peer, key);
+        let response = match serde_json::to_string(response) {
+            Ok(response) => response,
+            Err(_) => {
+                self.entries.remove(&key);
+                return;
+            }
+        };
+        let entry = Entry::Done(response.clone(), Instant::now());
+        if let Some(Entry::Pending(duplicates)) = self.entries.insert(key.clone(), entry) {
+            for response_tx in duplicates {
+                if let Ok(response) = serde_json::from_str(&response) {
+                    let _ = response_tx.send(response);
+                }
+            }
+        }
+        self.completed.push_back(key);
+    }
+
+    // The first request was not answered. Drop the duplicates, so that a later retransmission is forwarded again.
+    pub fn on_omission(&mut self, peer: PeerId, key: String) {
+        self.entries.remove(&(peer, key));
+    }
+
+    // Remove the cached responses that are older than the window.
+    fn prune(&mut self) {
+        let now = Instant::now();
+        while let Some(key) = self.completed.front() {
+            if let Some(Entry::Done(_, sent_at)) = self.entries.get(key) {
+                if now.duration_since(*sent_at) <= self.config.window {
+                    break;
+                }
+                self.entries.remove(key);
+            }
+            self.completed.pop_front();
+        }
+    }
+}
@@ -0,0 +1,286 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use super::{queue::write_atomic, RequestId};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+};
+
+// Number of records that are appended before the journal is compacted, in addition to the records of the requests
+// that are still open.
+const COMPACT_THRESHOLD: usize = 1024;
+
+/// How an inbound request that was forwarded to the application was completed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ResponseStatus {
+    /// The response was sent to the remote.
+    Sent,
+    /// The response channel was dropped without sending a response.
+    Dropped,
+    /// The request was rejected with a reason that was sent to the remote.
+    Rejected,
+    /// Sending the response failed, e.g. because the connection closed.
+    Failed,
+    /// The request remained unanswered in a former running instance, and the application reconciled it.
+    Reconciled,
+}
+
+/// Record in the [`RequestJournal`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JournalRecord {
+    /// An inbound request was approved and forwarded to the application.
+    /// The record is queued for writing before the request is forwarded.
+    Accepted {
+        request_id: RequestId,
+        peer: PeerId,
+        /// The serialized request.
+        request: String,
+    },
+    /// The accepted request was completed.
+    Completed {
+        request_id: RequestId,
+        status: ResponseStatus,
+    },
+}
+
+/// Write-ahead journal of the inbound requests that were forwarded to the application, and their response status.
+///
+/// When the `Network` is built, the journal is loaded to find the requests that were accepted but never completed
+/// by a former running instance, and then compacted. Afterwards the records are written by a separate thread, so that
+/// disk I/O does not block the network. The journal is compacted again once enough records were appended.
+pub trait RequestJournal: Send + 'static {
+    /// Append a new record.
+    fn append(&mut self, record: &JournalRecord) -> io::Result<()>;
+
+    /// Persist the appended records, e.g. by syncing them to disk. This is called after each batch of appended
+    /// records.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Load all records, in the order in which they were appended.
+    fn load(&mut self) -> io::Result<Vec<JournalRecord>>;
+
+    /// Replace the content of the journal with the `records`.
+    fn compact(&mut self, records: &[JournalRecord]) -> io::Result<()>;
+}
+
+/// [`RequestJournal`] that appends the records as JSON lines to a file.
+#[derive(Debug)]
+pub struct FileJournal {
+    path: PathBuf,
+    // File that records are appended to, opened on the first record after creating or compacting the journal.
+    file: Option<File>,
+}
+
+impl FileJournal {
+    /// Journal that is written to the file at `path`. The file is created on the first record.
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        FileJournal {
+            path: path.as_ref().to_path_buf(),
+            file: None,
+        }
+    }
+}
+
+impl Clone for FileJournal {
+    fn clone(&self) -> Self {
+        FileJournal::new(&self.path)
+    }
+}
+
+impl RequestJournal for FileJournal {
+    fn append(&mut self, record: &JournalRecord) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        line.push(b'\n');
+        let file = match self.file.as_mut() {
+            Some(file) => file,
+            None => self
+                .file
+                .insert(OpenOptions::new().create(true).append(true).open(&self.path)?),
+        };
+        file.write_all(&line)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.sync_data(),
+            None => Ok(()),
+        }
+    }
+
+    fn load(&mut self) -> io::Result<Vec<JournalRecord>> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        // A partially written last line after a crash is skipped.
+        let records = content.lines().filter_map(|l| serde_json::from_str(l).ok()).collect();
+        Ok(records)
+    }
+
+    fn compact(&mut self, records: &[JournalRecord]) -> io::Result<()> {
+        let mut content = Vec::new();
+        for record in records {
+            serde_json::to_writer(&mut content, record).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            content.push(b'\n');
+        }
+        // The file is replaced, records are appended to the new one.
+        self.file = None;
+        write_atomic(&self.path, &content)
+    }
+}
+
+// Operation of the thread that writes the journal.
+enum WriteOp {
+    Append(JournalRecord),
+    Compact(Vec<JournalRecord>),
+}
+
+// Write the records in batches, and flush the journal after each batch.
+// The thread stops once the `Journal` was dropped and all pending records were written.
+fn run_writer(mut store: Box<dyn RequestJournal>, op_rx: mpsc::Receiver<WriteOp>, error_tx: mpsc::Sender<io::Error>) {
+    while let Ok(op) = op_rx.recv() {
+        for op in std::iter::once(op).chain(op_rx.try_iter()) {
+            let res = match op {
+                WriteOp::Append(record) => store.append(&record),
+                WriteOp::Compact(records) => store.compact(&records),
+            };
+            if let Err(e) = res {
+                let _ = error_tx.send(e);
+            }
+        }
+        if let Err(e) = store.flush() {
+            let _ = error_tx.send(e);
+        }
+    }
+}
+
+// Journal of the inbound requests, and the requests that were not completed by a former running instance.
+pub struct Journal {
+    // Channel to the thread that writes the records.
+    op_tx: mpsc::Sender<WriteOp>,
+    // Failures of the thread that writes the records.
+    error_rx: mpsc::Receiver<io::Error>,
+    // Requests of this instance that were accepted and not completed yet, with the peer and the serialized request.
+    accepted: HashMap<RequestId, (PeerId, String)>,
+    // Requests of a former running instance that were never completed, with the peer and the serialized request.
+    unanswered: HashMap<RequestId, (PeerId, String)>,
+    // Number of records that were appended since the journal was compacted.
+    appended: usize,
+}
+
+impl Journal {
+    // Load the journal and keep only the records of the requests that were never completed.
+    pub fn open(mut store: Box<dyn RequestJournal>) -> io::Result<Self> {
+        let mut unanswered = HashMap::new();
+        for record in store.load()? {
+            match record {
+                JournalRecord::Accepted {
+                    request_id,
+                    peer,
+                    request,
+                } => {
+                    unanswered.insert(request_id, (peer, request));
+                }
+                JournalRecord::Completed { request_id, .. } => {
+                    unanswered.remove(&request_id);
+                }
+            }
+        }
+        store.compact(&accepted_records(&unanswered).collect::<Vec<_>>())?;
+        let (op_tx, op_rx) = mpsc::channel();
+        let (error_tx, error_rx) = mpsc::channel();
+        thread::spawn(move || run_writer(store, op_rx, error_tx));
+        Ok(Journal {
+            op_tx,
+            error_rx,
+            accepted: HashMap::new(),
+            unanswered,
+            appended: 0,
+        })
+    }
+
+    // Highest request id in the journal.
+    pub fn max_request_id(&self) -> Option<RequestId> {
+        self.unanswered.keys().max().copied()
+    }
+
+    // Write-ahead the record for an accepted request.
+    pub fn on_accepted(&mut self, request_id: RequestId, peer: PeerId, request: String) -> io::Result<()> {
+        self.accepted.insert(request_id, (peer, request.clone()));
+        self.append(JournalRecord::Accepted {
+            request_id,
+            peer,
+            request,
+        })
+    }
+
+    // Record the completion of an accepted request. Requests that were not accepted are ignored.
+    pub fn on_completed(&mut self, request_id: RequestId, status: ResponseStatus) -> io::Result<()> {
+        let is_known = match status {
+            ResponseStatus::Reconciled => self.unanswered.remove(&request_id).is_some(),
+            _ => self.accepted.remove(&request_id).is_some(),
+        };
+        if !is_known {
+            return Ok(());
+        }
+        self.append(JournalRecord::Completed { request_id, status })
+    }
+
+    // Failures of writing the journal since the last call.
+    pub fn take_errors(&mut self) -> Vec<io::Error> {
+        self.error_rx.try_iter().collect()
+    }
+
+    // Queue the record for writing, and compact the journal once enough records were appended.
+    fn append(&mut self, record: JournalRecord) -> io::Result<()> {
+        self.send(WriteOp::Append(record))?;
+        self.appended += 1;
+        if self.appended >= COMPACT_THRESHOLD + self.accepted.len() + self.unanswered.len() {
+            let records = accepted_records(&self.unanswered)
+                .chain(accepted_records(&self.accepted))
+                .collect();
+            self.send(WriteOp::Compact(records))?;
+            self.appended = 0;
+        }
+        Ok(())
+    }
+
+    fn send(&self, op: WriteOp) -> io::Result<()> {
+        self.op_tx
+            .send(op)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "The journal writer stopped."))
+    }
+
+    pub fn unanswered(&self) -> impl Iterator<Item = (&RequestId, &(PeerId, String))> {
+        self.unanswered.iter()
+    }
+}
+
+// Records for the accepted requests.
+fn accepted_records(requests: &HashMap<RequestId, (PeerId, String)>) -> impl Iterator<Item = JournalRecord> + '_ {
+    requests
+        .iter()
+        .map(|(request_id, (peer, request))| JournalRecord::Accepted {
+            request_id: *request_id,
+            peer: *peer,
+            request: request.clone(),
+        })
+}
+
+/// Inbound request that was accepted by a former running instance, but never answered.
+#[derive(Debug, Clone)]
+pub struct UnansweredRequest<Rq> {
+    pub request_id: RequestId,
+    pub peer: PeerId,
+    pub request: Rq,
+}
@@ -0,0 +1,263 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::timer::Instant;
+use libp2p::core::{connection::ConnectionId, PeerId};
+use serde::{Deserialize, Serialize};
+use std::{cmp::Ordering, collections::HashMap};
+
+/// Handling of the established connections that exceed the connection limits after the limits were lowered at
+/// runtime.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExcessConnectionPolicy {
+    /// Keep the established connections. Only new connections are subject to the new limits.
+    #[default]
+    Keep,
+    /// Close the most recently established connections until the limits are met.
+    CloseNewest,
+    /// Close the longest established connections until the limits are met.
+    CloseOldest,
+}
+
+/// Handling of a new connection that exceeds the limits for established connections.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LimitPressurePolicy {
+    /// Close the new connection.
+    #[default]
+    Reject,
+    /// Close an established connection of a peer with a lower priority than the peer of the new connection instead,
+    /// if that makes room for the new connection.
+    ///
+    /// The connection of the lowest peer priority is evicted first. Among peers of the same priority, the peer with
+    /// the lowest success rate of outbound requests is evicted first, and after that the connection that was idle
+    /// for the longest time.
+    EvictLowestPriority,
+}
+
+// Established connection that could be evicted in favor of a new connection.
+#[derive(Debug, Clone)]
+pub struct EvictionCandidate {
+    pub peer: PeerId,
+    pub connection: ConnectionId,
+    pub is_incoming: bool,
+    // Priority that was set for the peer.
+    pub priority: u8,
+    // Share of the outbound requests to the peer that received a response.
+    pub success_rate: Option<f64>,
+    // Last time that data was exchanged on the connection.
+    pub last_active: Instant,
+}
+
+impl EvictionCandidate {
+    // Order in which connections are evicted. Peers without completed requests are treated as fully reliable.
+    fn cmp_eviction(&self, other: &Self) -> Ordering {
+        let success_rate = |c: &Self| c.success_rate.unwrap_or(1.0);
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| {
+                success_rate(self)
+                    .partial_cmp(&success_rate(other))
+                    .unwrap_or(Ordering::Equal)
+            })
+            .then_with(|| self.last_active.cmp(&other.last_active))
+    }
+}
+
+// Limits for established connections.
+// They are enforced by the behaviour instead of the swarm, so that they can be changed at runtime.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EstablishedLimits {
+    pub incoming: Option<u32>,
+    pub outgoing: Option<u32>,
+    pub per_peer: Option<u32>,
+    pub total: Option<u32>,
+}
+
+// Number of established connections per direction and peer.
+#[derive(Default)]
+struct Counts {
+    incoming: u32,
+    outgoing: u32,
+    per_peer: HashMap<PeerId, u32>,
+}
+
+impl Counts {
+    fn add(&mut self, peer: PeerId, is_incoming: bool) {
+        match is_incoming {
+            true => self.incoming += 1,
+            false => self.outgoing += 1,
+        }
+        *self.per_peer.entry(peer).or_default() += 1;
+    }
+
+    fn remove(&mut self, peer: &PeerId, is_incoming: bool) {
+        match is_incoming {
+            true => self.incoming -= 1,
+            false => self.outgoing -= 1,
+        }
+        if let Some(count) = self.per_peer.get_mut(peer) {
+            *count -= 1;
+        }
+    }
+}
+
+impl EstablishedLimits {
+    // Whether a newly established connection exceeds the limits.
+    // The `connections` are all established connections with their direction, including the new one.
+    pub fn is_exceeded_by(
+        &self,
+        connections: impl Iterator<Item = (PeerId, bool)>,
+        peer: &PeerId,
+        is_incoming: bool,
+    ) -> bool {
+        let mut counts = Counts::default();
+        connections.for_each(|(p, i)| counts.add(p, i));
+        self.is_exceeded(&counts, peer, is_incoming)
+    }
+
+    // Established connections that have to be closed according to the policy so that the limits are met.
+    pub fn excess(
+        &self,
+        mut connections: Vec<(PeerId, ConnectionId, bool)>,
+        policy: ExcessConnectionPolicy,
+    ) -> Vec<(PeerId, ConnectionId)> {
+        // Connection ids are assigned in ascending order, so they reflect the age of the connections.
+        match policy {
+            ExcessConnectionPolicy::Keep => return Vec::new(),
+            ExcessConnectionPolicy::CloseNewest => connections.sort_by(|a, b| b.1.cmp(&a.1)),
+            ExcessConnectionPolicy::CloseOldest => connections.sort_by(|a, b| a.1.cmp(&b.1)),
+        }
+        let mut counts = Counts::default();
+        connections.iter().for_each(|(p, _, i)| counts.add(*p, *i));
+        let mut excess = Vec::new();
+        for (peer, connection, is_incoming) in connections {
+            if self.is_exceeded(&counts, &peer, is_incoming) {
+                counts.remove(&peer, is_incoming);
+                excess.push((peer, connection));
+            }
+        }
+        excess
+    }
+
+    // Established connection that has to be evicted so that a new connection of a peer with the `priority` is within
+    // the limits. `None` if no connection of a lower priority makes room for it.
+    // The `candidates` are all established connections, excluding the new one.
+    pub fn eviction_candidate(
+        &self,
+        mut candidates: Vec<EvictionCandidate>,
+        peer: &PeerId,
+        is_incoming: bool,
+        priority: u8,
+    ) -> Option<(PeerId, ConnectionId)> {
+        candidates.sort_by(EvictionCandidate::cmp_eviction);
+        let mut counts = Counts::default();
+        candidates.iter().for_each(|c| counts.add(c.peer, c.is_incoming));
+        counts.add(*peer, is_incoming);
+        for candidate in candidates.into_iter().take_while(|c| c.priority < priority) {
+            counts.remove(&candidate.peer, candidate.is_incoming);
+            if !self.is_exceeded(&counts, peer, is_incoming) {
+                return Some((candidate.peer, candidate.connection));
+            }
+            counts.add(candidate.peer, candidate.is_incoming);
+        }
+        None
+    }
+
+    // Whether a connection to the peer in the direction exceeds any of the limits.
+    fn is_exceeded(&self, counts: &Counts, peer: &PeerId, is_incoming: bool) -> bool {
+        let exceeds = |count: u32, limit: Option<u32>| limit.map_or(false, |l| count > l);
+        let directed = match is_incoming {
+            true => exceeds(counts.incoming, self.incoming),
+            false => exceeds(counts.outgoing, self.outgoing),
+        };
+        let per_peer = counts.per_peer.get(peer).copied().unwrap_or_default();
+        directed || exceeds(counts.incoming + counts.outgoing, self.total) || exceeds(per_peer, self.per_peer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn excess_connections() {
+        let peer_a = PeerId::random();
+        let peer_b = PeerId::random();
+        let connections = vec![
+            (peer_a, ConnectionId::new(1), true),
+            (peer_b, ConnectionId::new(2), false),
+            (peer_a, ConnectionId::new(3), false),
+            (peer_a, ConnectionId::new(4), true),
+        ];
+        let limits = EstablishedLimits {
+            per_peer: Some(2),
+            total: Some(2),
+            ..Default::default()
+        };
+        assert!(limits
+            .excess(connections.clone(), ExcessConnectionPolicy::Keep)
+            .is_empty());
+        assert_eq!(
+            limits.excess(connections.clone(), ExcessConnectionPolicy::CloseNewest),
+            vec![(peer_a, ConnectionId::new(4)), (peer_a, ConnectionId::new(3))]
+        );
+        assert_eq!(
+            limits.excess(connections.clone(), ExcessConnectionPolicy::CloseOldest),
+            vec![(peer_a, ConnectionId::new(1)), (peer_b, ConnectionId::new(2))]
+        );
+
+        let limits = EstablishedLimits {
+            incoming: Some(1),
+            ..Default::default()
+        };
+        let established = connections.iter().map(|(p, _, i)| (*p, *i));
+        assert!(limits.is_exceeded_by(established.clone(), &peer_a, true));
+        assert!(!limits.is_exceeded_by(established, &peer_b, false));
+    }
+
+    #[test]
+    fn evict_lowest_priority() {
+        let peer_a = PeerId::random();
+        let peer_b = PeerId::random();
+        let peer_c = PeerId::random();
+        let now = Instant::now();
+        let candidate = |peer, id, is_incoming, priority, success_rate, idle_secs| EvictionCandidate {
+            peer,
+            connection: ConnectionId::new(id),
+            is_incoming,
+            priority,
+            success_rate,
+            last_active: now - Duration::from_secs(idle_secs),
+        };
+        let candidates = vec![
+            candidate(peer_a, 1, true, 1, None, 10),
+            candidate(peer_b, 2, true, 1, Some(0.5), 0),
+            candidate(peer_b, 3, false, 0, None, 0),
+            candidate(peer_c, 4, true, 0, None, 20),
+            candidate(peer_c, 5, true, 0, Some(1.0), 30),
+        ];
+        let new_peer = PeerId::random();
+        let limits = EstablishedLimits {
+            incoming: Some(4),
+            ..Default::default()
+        };
+        // Lowest priority, then the longest idle connection, since the peer's success rate is the same.
+        assert_eq!(
+            limits.eviction_candidate(candidates.clone(), &new_peer, true, 1),
+            Some((peer_c, ConnectionId::new(5)))
+        );
+        // Outgoing connections don't make room for a new incoming one.
+        let without_c: Vec<_> = candidates.iter().filter(|c| c.peer != peer_c).cloned().collect();
+        let limits = EstablishedLimits {
+            incoming: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(limits.eviction_candidate(without_c.clone(), &new_peer, true, 1), None);
+        // Among the same priority, the peer with the lower success rate is evicted.
+        assert_eq!(
+            limits.eviction_candidate(without_c, &new_peer, true, 2),
+            Some((peer_b, ConnectionId::new(2)))
+        );
+    }
+}
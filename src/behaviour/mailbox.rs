@@ -0,0 +1,137 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use super::oneshot::{MessageHandler, MessageHandlerEvent, OneShotMessage};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Configuration for storing requests on behalf of other peers, if the local peer acts as mailbox.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MailboxConfig {
+    /// Maximum number of requests and receipts that are stored for a single offline peer.
+    pub max_messages_per_peer: usize,
+    /// Maximum duration for which a message is stored, independently of the TTL set by the sender.
+    pub max_ttl: Duration,
+}
+
+impl Default for MailboxConfig {
+    fn default() -> Self {
+        MailboxConfig {
+            max_messages_per_peer: 100,
+            max_ttl: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+/// Delivery status of a request that was sent via a mailbox.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MailboxDelivery {
+    /// The request was forwarded to the recipient and approved by its firewall.
+    Delivered,
+    /// The request was rejected, either by the mailbox or by the recipient.
+    Rejected,
+    /// The mailbox could not be reached.
+    MailboxUnreachable,
+    /// No receipt was received within the TTL of the request.
+    Expired,
+}
+
+// Message of the mailbox protocol.
+// Requests are transmitted in their serialized form, so that the mailbox does not have to know their type.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum MailboxMessage {
+    // Request that the sender deposits in the mailbox for the recipient.
+    Deposit {
+        id: u64,
+        recipient: PeerId,
+        // Unix timestamp in seconds.
+        expires_at: u64,
+        request: String,
+    },
+    // Deposited request that the mailbox forwards to the recipient.
+    Forward {
+        id: u64,
+        sender: PeerId,
+        expires_at: u64,
+        request: String,
+    },
+    // Notification from the recipient to the mailbox whether a forwarded request was approved.
+    Handled {
+        id: u64,
+        sender: PeerId,
+        is_approved: bool,
+    },
+    // Delivery receipt from the mailbox to the sender.
+    Receipt {
+        id: u64,
+        recipient: PeerId,
+        is_approved: bool,
+    },
+}
+
+impl OneShotMessage for MailboxMessage {
+    const PROTOCOL_NAME: &'static [u8] = b"/p2p/mailbox/1.0.0";
+    const MAX_SIZE: usize = 16 * 1024 * 1024;
+}
+
+// Handler for exchanging mailbox messages on a connection.
+pub type MailboxHandler = MessageHandler<MailboxMessage>;
+
+// Events emitted by the `MailboxHandler`.
+pub type MailboxHandlerEvent = MessageHandlerEvent<MailboxMessage>;
+
+// Messages that are stored for offline peers, if the local peer acts as mailbox.
+#[derive(Debug)]
+pub struct Mailbox {
+    config: MailboxConfig,
+    // Stored messages for each peer, together with the unix timestamp at which they expire.
+    stored: HashMap<PeerId, VecDeque<(MailboxMessage, u64)>>,
+}
+
+impl Mailbox {
+    pub fn new(config: MailboxConfig) -> Self {
+        Mailbox {
+            config,
+            stored: HashMap::new(),
+        }
+    }
+
+    // Store a message for the peer. The expiry is capped to the configured max TTL.
+    // Returns `false` if the limit of stored messages for that peer is reached.
+    pub fn store(&mut self, peer: PeerId, message: MailboxMessage, expires_at: Option<u64>) -> bool {
+        let now = unix_now();
+        let max_expiry = now + self.config.max_ttl.as_secs();
+        let queue = self.stored.entry(peer).or_default();
+        queue.retain(|(_, expires_at)| *expires_at > now);
+        if queue.len() >= self.config.max_messages_per_peer {
+            return false;
+        }
+        let expires_at = expires_at.map_or(max_expiry, |e| e.min(max_expiry));
+        queue.push_back((message, expires_at));
+        true
+    }
+
+    // Take all messages for the peer that did not expire yet.
+    pub fn take(&mut self, peer: &PeerId) -> Vec<MailboxMessage> {
+        let now = unix_now();
+        self.stored
+            .remove(peer)
+            .into_iter()
+            .flatten()
+            .filter(|(_, expires_at)| *expires_at > now)
+            .map(|(message, _)| message)
+            .collect()
+    }
+}
+
+// Current unix timestamp in seconds.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
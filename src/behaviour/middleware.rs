@@ -0,0 +1,85 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use super::RequestId;
+use libp2p::PeerId;
+use std::sync::Arc;
+
+/// Middleware that is invoked on each request that is sent or received via the request-response protocol, e.g. to
+/// attach or check auth material, validate requests against a schema, or record metrics in one place.
+///
+/// Multiple middleware are invoked in the order in which they were registered. If one of them fails the request, the
+/// remaining ones are skipped.
+pub trait RequestMiddleware<Rq>: Send + Sync + 'static {
+    /// Invoked on an outbound request before it is serialized and sent. The request may be modified.
+    ///
+    /// Returning an error fails the request with [`OutboundFailure::Intercepted`][crate::OutboundFailure].
+    fn on_outbound(&self, _peer: &PeerId, _request: &mut Rq) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Invoked on an inbound request after it was approved by the firewall, before it is forwarded to the
+    /// application. The request may be modified.
+    ///
+    /// Returning an error fails the request with [`InboundFailure::Intercepted`][crate::InboundFailure], and the
+    /// remote is notified that the request was not permitted.
+    fn on_inbound(&self, _peer: &PeerId, _request: &mut Rq) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Hook that is invoked on each response to an outbound request before it is forwarded to the requester, e.g. to
+/// decompress the response, verify its signature, or record metrics.
+///
+/// Multiple hooks are invoked in the order in which they were registered. If one of them fails the response, the
+/// remaining ones are skipped.
+pub trait ResponseHook<Rs>: Send + Sync + 'static {
+    /// Invoked on the response that was received from the `peer`. The response may be modified.
+    ///
+    /// Returning an error fails the request with [`OutboundFailure::Intercepted`][crate::OutboundFailure].
+    fn on_response(&self, peer: &PeerId, request_id: RequestId, response: &mut Rs) -> Result<(), String>;
+}
+
+// Ordered lists of the registered request middleware and response hooks.
+pub struct MiddlewareChain<Rq, Rs> {
+    middleware: Vec<Arc<dyn RequestMiddleware<Rq>>>,
+    response_hooks: Vec<Arc<dyn ResponseHook<Rs>>>,
+}
+
+impl<Rq, Rs> Default for MiddlewareChain<Rq, Rs> {
+    fn default() -> Self {
+        MiddlewareChain {
+            middleware: Vec::new(),
+            response_hooks: Vec::new(),
+        }
+    }
+}
+
+impl<Rq, Rs> MiddlewareChain<Rq, Rs> {
+    pub fn set_middleware(&mut self, middleware: Vec<Arc<dyn RequestMiddleware<Rq>>>) {
+        self.middleware = middleware;
+    }
+
+    pub fn set_response_hooks(&mut self, hooks: Vec<Arc<dyn ResponseHook<Rs>>>) {
+        self.response_hooks = hooks;
+    }
+
+    // Whether there is no request middleware.
+    pub fn is_empty(&self) -> bool {
+        self.middleware.is_empty()
+    }
+
+    pub fn on_outbound(&self, peer: &PeerId, request: &mut Rq) -> Result<(), String> {
+        self.middleware.iter().try_for_each(|m| m.on_outbound(peer, request))
+    }
+
+    pub fn on_inbound(&self, peer: &PeerId, request: &mut Rq) -> Result<(), String> {
+        self.middleware.iter().try_for_each(|m| m.on_inbound(peer, request))
+    }
+
+    pub fn on_response(&self, peer: &PeerId, request_id: RequestId, response: &mut Rs) -> Result<(), String> {
+        self.response_hooks
+            .iter()
+            .try_for_each(|h| h.on_response(peer, request_id, response))
+    }
+}
@@ -0,0 +1,172 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use futures::{future::BoxFuture, prelude::*};
+use libp2p::{
+    core::upgrade::{read_length_prefixed, write_length_prefixed, InboundUpgrade, OutboundUpgrade, UpgradeInfo},
+    swarm::{
+        ConnectionHandler, ConnectionHandlerEvent, ConnectionHandlerUpgrErr, KeepAlive, NegotiatedSubstream,
+        SubstreamProtocol,
+    },
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    collections::VecDeque,
+    fmt::Debug,
+    io, iter,
+    marker::PhantomData,
+    task::{Context, Poll},
+};
+
+// Message that is sent on its own substream, without a response.
+pub trait OneShotMessage: Serialize + DeserializeOwned + Clone + Debug + Send + 'static {
+    const PROTOCOL_NAME: &'static [u8];
+    // Maximum size of a single message.
+    const MAX_SIZE: usize;
+}
+
+// Upgrade for sending a single message.
+#[derive(Debug, Clone)]
+pub struct SendMessage<M>(M);
+
+impl<M: OneShotMessage> UpgradeInfo for SendMessage<M> {
+    type Info = &'static [u8];
+    type InfoIter = iter::Once<Self::Info>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        iter::once(M::PROTOCOL_NAME)
+    }
+}
+
+impl<M: OneShotMessage> OutboundUpgrade<NegotiatedSubstream> for SendMessage<M> {
+    type Output = ();
+    type Error = io::Error;
+    type Future = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+    fn upgrade_outbound(self, mut io: NegotiatedSubstream, _: Self::Info) -> Self::Future {
+        async move {
+            let buf = serde_json::to_vec(&self.0).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            write_length_prefixed(&mut io, buf).await?;
+            io.close().await
+        }
+        .boxed()
+    }
+}
+
+// Upgrade for receiving a single message.
+#[derive(Debug, Clone, Copy)]
+pub struct ReceiveMessage<M>(PhantomData<M>);
+
+impl<M: OneShotMessage> UpgradeInfo for ReceiveMessage<M> {
+    type Info = &'static [u8];
+    type InfoIter = iter::Once<Self::Info>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        iter::once(M::PROTOCOL_NAME)
+    }
+}
+
+impl<M: OneShotMessage> InboundUpgrade<NegotiatedSubstream> for ReceiveMessage<M> {
+    type Output = M;
+    type Error = io::Error;
+    type Future = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+    fn upgrade_inbound(self, mut io: NegotiatedSubstream, _: Self::Info) -> Self::Future {
+        async move {
+            let bytes = read_length_prefixed(&mut io, M::MAX_SIZE)
+                .await
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            io.close().await?;
+            serde_json::from_slice(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        }
+        .boxed()
+    }
+}
+
+// Events emitted by the `MessageHandler`.
+#[derive(Debug)]
+pub enum MessageHandlerEvent<M> {
+    // A message was received from the remote.
+    Received(M),
+    // The message was sent to the remote.
+    Sent(M),
+    // Sending the message to the remote failed.
+    SendFailed(M),
+}
+
+// Handler for exchanging one-shot messages on a connection.
+//
+// Failures don't close the connection, and the handler only keeps the connection alive while it is sending messages.
+#[derive(Debug)]
+pub struct MessageHandler<M> {
+    pending_out: VecDeque<M>,
+    pending_events: VecDeque<MessageHandlerEvent<M>>,
+    // Number of outbound substreams that were requested but did not complete yet.
+    pending_substreams: usize,
+}
+
+impl<M> Default for MessageHandler<M> {
+    fn default() -> Self {
+        MessageHandler {
+            pending_out: VecDeque::new(),
+            pending_events: VecDeque::new(),
+            pending_substreams: 0,
+        }
+    }
+}
+
+impl<M: OneShotMessage> ConnectionHandler for MessageHandler<M> {
+    type InEvent = M;
+    type OutEvent = MessageHandlerEvent<M>;
+    type Error = io::Error;
+    type InboundProtocol = ReceiveMessage<M>;
+    type OutboundProtocol = SendMessage<M>;
+    type InboundOpenInfo = ();
+    // Copy of the sent message, so that it can be returned if sending fails.
+    type OutboundOpenInfo = M;
+
+    fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol, Self::InboundOpenInfo> {
+        SubstreamProtocol::new(ReceiveMessage(PhantomData), ())
+    }
+
+    fn inject_fully_negotiated_inbound(&mut self, message: M, _: ()) {
+        self.pending_events.push_back(MessageHandlerEvent::Received(message));
+    }
+
+    fn inject_fully_negotiated_outbound(&mut self, _: (), message: M) {
+        self.pending_substreams = self.pending_substreams.saturating_sub(1);
+        self.pending_events.push_back(MessageHandlerEvent::Sent(message));
+    }
+
+    fn inject_event(&mut self, message: M) {
+        self.pending_out.push_back(message);
+    }
+
+    fn inject_dial_upgrade_error(&mut self, message: M, _: ConnectionHandlerUpgrErr<io::Error>) {
+        self.pending_substreams = self.pending_substreams.saturating_sub(1);
+        self.pending_events.push_back(MessageHandlerEvent::SendFailed(message));
+    }
+
+    fn connection_keep_alive(&self) -> KeepAlive {
+        if self.pending_out.is_empty() && self.pending_substreams == 0 {
+            KeepAlive::No
+        } else {
+            KeepAlive::Yes
+        }
+    }
+
+    fn poll(
+        &mut self,
+        _: &mut Context<'_>,
+    ) -> Poll<ConnectionHandlerEvent<SendMessage<M>, M, MessageHandlerEvent<M>, io::Error>> {
+        if let Some(event) = self.pending_events.pop_front() {
+            return Poll::Ready(ConnectionHandlerEvent::Custom(event));
+        }
+        if let Some(message) = self.pending_out.pop_front() {
+            self.pending_substreams += 1;
+            let protocol = SubstreamProtocol::new(SendMessage(message.clone()), message);
+            return Poll::Ready(ConnectionHandlerEvent::OutboundSubstreamRequest { protocol });
+        }
+        Poll::Pending
+    }
+}
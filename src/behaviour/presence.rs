@@ -0,0 +1,144 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use super::oneshot::{MessageHandler, MessageHandlerEvent, OneShotMessage};
+use crate::timer::{Delay, Instant};
+use futures::{task::Context, FutureExt};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, task::Poll, time::Duration};
+
+/// Configuration for exchanging heartbeats with connected peers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PresenceConfig {
+    /// Interval in which a heartbeat with the local status is sent to all connected peers.
+    pub heartbeat_interval: Duration,
+    /// Duration without heartbeat after which a peer is considered offline, even if it is still connected.
+    pub timeout: Duration,
+}
+
+impl Default for PresenceConfig {
+    fn default() -> Self {
+        PresenceConfig {
+            heartbeat_interval: Duration::from_secs(30),
+            timeout: Duration::from_secs(90),
+        }
+    }
+}
+
+// Heartbeat with the base64 encoded status of the sender.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Heartbeat {
+    status: String,
+}
+
+impl OneShotMessage for Heartbeat {
+    const PROTOCOL_NAME: &'static [u8] = b"/p2p/presence/1.0.0";
+    const MAX_SIZE: usize = 64 * 1024;
+}
+
+// Handler for exchanging heartbeats on a connection.
+pub type PresenceHandler = MessageHandler<Heartbeat>;
+
+// Events emitted by the `PresenceHandler`.
+pub type PresenceHandlerEvent = MessageHandlerEvent<Heartbeat>;
+
+// Change of the presence of a peer after it sent a heartbeat.
+#[derive(Debug)]
+pub enum PresenceChange {
+    Online(Vec<u8>),
+    StatusChanged(Vec<u8>),
+    Unchanged,
+}
+
+#[derive(Debug)]
+struct PeerPresence {
+    status: Vec<u8>,
+    last_seen: Instant,
+}
+
+// Local status, and the status of the peers that are online.
+#[derive(Debug)]
+pub struct Presence {
+    config: PresenceConfig,
+    status: Vec<u8>,
+    peers: HashMap<PeerId, PeerPresence>,
+    // Delay until the next heartbeat is sent.
+    heartbeat_delay: Delay,
+}
+
+impl Presence {
+    pub fn new(config: PresenceConfig, status: Vec<u8>) -> Self {
+        Presence {
+            config,
+            status,
+            peers: HashMap::new(),
+            heartbeat_delay: Delay::new(config.heartbeat_interval),
+        }
+    }
+
+    pub fn set_status(&mut self, status: Vec<u8>) {
+        self.status = status;
+    }
+
+    pub fn heartbeat(&self) -> Heartbeat {
+        Heartbeat {
+            status: base64::encode(&self.status),
+        }
+    }
+
+    // Record a heartbeat from the peer. Heartbeats with an invalid status are ignored.
+    pub fn on_heartbeat(&mut self, peer: PeerId, heartbeat: Heartbeat) -> PresenceChange {
+        let status = match base64::decode(heartbeat.status) {
+            Ok(status) => status,
+            Err(_) => return PresenceChange::Unchanged,
+        };
+        let last_seen = Instant::now();
+        match self.peers.get_mut(&peer) {
+            Some(presence) => {
+                presence.last_seen = last_seen;
+                if presence.status == status {
+                    return PresenceChange::Unchanged;
+                }
+                presence.status = status.clone();
+                PresenceChange::StatusChanged(status)
+            }
+            None => {
+                let presence = PeerPresence {
+                    status: status.clone(),
+                    last_seen,
+                };
+                self.peers.insert(peer, presence);
+                PresenceChange::Online(status)
+            }
+        }
+    }
+
+    // Remove the peer, returns `false` if it was not online.
+    pub fn remove(&mut self, peer: &PeerId) -> bool {
+        self.peers.remove(peer).is_some()
+    }
+
+    pub fn online_peers(&self) -> HashMap<PeerId, Vec<u8>> {
+        self.peers.iter().map(|(p, s)| (*p, s.status.clone())).collect()
+    }
+
+    // Poll the heartbeat interval. Once it elapsed, the peers that timed out are removed and returned.
+    pub fn poll_interval(&mut self, cx: &mut Context<'_>) -> Poll<Vec<PeerId>> {
+        if self.heartbeat_delay.poll_unpin(cx).is_pending() {
+            return Poll::Pending;
+        }
+        self.heartbeat_delay = Delay::new(self.config.heartbeat_interval);
+        let now = Instant::now();
+        let timeout = self.config.timeout;
+        let expired: Vec<_> = self
+            .peers
+            .iter()
+            .filter_map(|(peer, p)| (now.duration_since(p.last_seen) > timeout).then(|| *peer))
+            .collect();
+        for peer in &expired {
+            self.peers.remove(peer);
+        }
+        Poll::Ready(expired)
+    }
+}
@@ -0,0 +1,214 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use super::RequestId;
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Configuration of the queue for outbound requests to unreachable peers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutboundQueueConfig {
+    /// Maximum number of requests in the queue. New requests are rejected if the limit is reached.
+    pub max_requests: usize,
+    /// Maximum duration for which a request is kept in the queue, independently of the TTL of the request.
+    pub max_ttl: Duration,
+}
+
+impl Default for OutboundQueueConfig {
+    fn default() -> Self {
+        OutboundQueueConfig {
+            max_requests: 1000,
+            max_ttl: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+/// Request in the outbound queue, as it is persisted by a [`QueueStore`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueuedRequest {
+    /// Id that was returned when the request was enqueued.
+    pub id: RequestId,
+    /// Recipient of the request.
+    pub peer: PeerId,
+    /// The serialized request.
+    pub request: String,
+    /// Unix timestamp in seconds at which the request expires.
+    pub expires_at: u64,
+}
+
+/// Persistent storage of the outbound queue.
+///
+/// The store is loaded once when the `Network` is built, and afterwards updated on each change of the queue.
+pub trait QueueStore: Send + 'static {
+    /// Load all requests that are currently stored.
+    fn load(&mut self) -> io::Result<Vec<QueuedRequest>>;
+
+    /// Store a new request.
+    fn insert(&mut self, request: &QueuedRequest) -> io::Result<()>;
+
+    /// Remove the request with this id, because it was delivered, failed or expired.
+    fn remove(&mut self, id: RequestId) -> io::Result<()>;
+}
+
+/// [`QueueStore`] that keeps the queue as JSON in a single file, and rewrites the file on each change.
+#[derive(Debug, Clone)]
+pub struct FileQueueStore {
+    path: PathBuf,
+    requests: Vec<QueuedRequest>,
+}
+
+impl FileQueueStore {
+    /// Store that is persisted in the file at `path`. The file is created on the first change.
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        FileQueueStore {
+            path: path.as_ref().to_path_buf(),
+            requests: Vec::new(),
+        }
+    }
+
+    fn write(&self) -> io::Result<()> {
+        let json = serde_json::to_vec(&self.requests).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        write_atomic(&self.path, &json)
+    }
+}
+
+// Replace the content of the file at `path`. The content is written to a temporary file that is then renamed, so that
+// the file contains either the old or the new content if the process crashes while writing.
+pub(crate) fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+impl QueueStore for FileQueueStore {
+    fn load(&mut self) -> io::Result<Vec<QueuedRequest>> {
+        self.requests = match fs::read(&self.path) {
+            Ok(json) => serde_json::from_slice(&json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(self.requests.clone())
+    }
+
+    fn insert(&mut self, request: &QueuedRequest) -> io::Result<()> {
+        self.requests.push(request.clone());
+        self.write()
+    }
+
+    fn remove(&mut self, id: RequestId) -> io::Result<()> {
+        self.requests.retain(|r| r.id != id);
+        self.write()
+    }
+}
+
+/// Possible failures when adding a request to the outbound queue.
+#[derive(Debug)]
+pub enum EnqueueErr {
+    /// The outbound queue was not enabled.
+    NotEnabled,
+    /// The maximum number of queued requests is reached.
+    Full,
+    /// The request could not be serialized.
+    InvalidRequest,
+    /// The request could not be persisted.
+    Storage(io::Error),
+}
+
+impl fmt::Display for EnqueueErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EnqueueErr::NotEnabled => write!(f, "The outbound queue is not enabled"),
+            EnqueueErr::Full => write!(f, "The outbound queue is full"),
+            EnqueueErr::InvalidRequest => write!(f, "The request could not be serialized"),
+            EnqueueErr::Storage(e) => write!(f, "The request could not be persisted: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for EnqueueErr {}
+
+// Queue of outbound requests that are kept until they were delivered to the remote.
+pub struct OutboundQueue {
+    config: OutboundQueueConfig,
+    store: Box<dyn QueueStore>,
+    requests: HashMap<RequestId, QueuedRequest>,
+    // Queued requests that are currently sent, with the id assigned to the sent request.
+    in_flight: HashMap<RequestId, RequestId>,
+}
+
+impl OutboundQueue {
+    pub fn new(config: OutboundQueueConfig, store: Box<dyn QueueStore>) -> Self {
+        OutboundQueue {
+            config,
+            store,
+            requests: HashMap::new(),
+            in_flight: HashMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &OutboundQueueConfig {
+        &self.config
+    }
+
+    // Load the requests from the store.
+    pub fn load(&mut self) -> io::Result<Vec<QueuedRequest>> {
+        let requests = self.store.load()?;
+        self.requests = requests.iter().map(|r| (r.id, r.clone())).collect();
+        Ok(requests)
+    }
+
+    pub fn insert(&mut self, request: QueuedRequest) -> Result<(), EnqueueErr> {
+        if self.requests.len() >= self.config.max_requests {
+            return Err(EnqueueErr::Full);
+        }
+        self.store.insert(&request).map_err(EnqueueErr::Storage)?;
+        self.requests.insert(request.id, request);
+        Ok(())
+    }
+
+    // Remove the request from the queue and the store.
+    // Failures of the store are ignored, the request is then sent again after a restart.
+    pub fn remove(&mut self, id: &RequestId) -> Option<QueuedRequest> {
+        let request = self.requests.remove(id)?;
+        let _ = self.store.remove(*id);
+        Some(request)
+    }
+
+    pub fn get(&self, id: &RequestId) -> Option<&QueuedRequest> {
+        self.requests.get(id)
+    }
+
+    // Queued requests to the peer that are not sent currently.
+    pub fn pending_for(&self, peer: &PeerId) -> Vec<RequestId> {
+        let in_flight: HashSet<_> = self.in_flight.values().collect();
+        self.requests
+            .values()
+            .filter(|r| r.peer == *peer && !in_flight.contains(&r.id))
+            .map(|r| r.id)
+            .collect()
+    }
+
+    pub fn set_in_flight(&mut self, sent_id: RequestId, id: RequestId) {
+        self.in_flight.insert(sent_id, id);
+    }
+
+    pub fn is_in_flight(&self, id: &RequestId) -> bool {
+        self.in_flight.values().any(|i| i == id)
+    }
+
+    // Id of the queued request that was sent with the `sent_id`.
+    pub fn take_in_flight(&mut self, sent_id: &RequestId) -> Option<RequestId> {
+        self.in_flight.remove(sent_id)
+    }
+}
@@ -0,0 +1,142 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use super::RequestId;
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Generator for the ids of outbound and inbound requests.
+///
+/// The generator is shared by the behaviour and all connection handlers, and may be called concurrently.
+pub trait RequestIdGenerator: Send + Sync + 'static {
+    /// Generate a new id. The generator must not return the same id twice within a running instance.
+    fn next_id(&self) -> u64;
+
+    /// Ids up to `id` were used by a former running instance and are still referenced in the outbound queue or the
+    /// request journal. Generators that could produce these ids again should skip them.
+    fn skip_to(&self, _id: u64) {}
+}
+
+/// Default [`RequestIdGenerator`] that counts up from 1.
+#[derive(Debug)]
+pub struct CounterIds(AtomicU64);
+
+impl Default for CounterIds {
+    fn default() -> Self {
+        CounterIds(AtomicU64::new(1))
+    }
+}
+
+impl RequestIdGenerator for CounterIds {
+    fn next_id(&self) -> u64 {
+        self.0.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn skip_to(&self, id: u64) {
+        self.0.fetch_max(id + 1, Ordering::Relaxed);
+    }
+}
+
+/// [`RequestIdGenerator`] that prefixes a counter with a namespace derived from the local peer id, so that the ids
+/// of different nodes don't collide.
+///
+/// The upper 32 bits of each id are taken from the peer id, the lower 32 bits are a counter.
+#[derive(Debug)]
+pub struct NamespacedIds {
+    namespace: u64,
+    counter: AtomicU64,
+}
+
+impl NamespacedIds {
+    /// Generator with a namespace that is derived from the `local_peer`.
+    pub fn new(local_peer: &PeerId) -> Self {
+        let bytes = local_peer.to_bytes();
+        let mut prefix = [0u8; 4];
+        let len = bytes.len().min(4);
+        prefix[..len].copy_from_slice(&bytes[bytes.len() - len..]);
+        NamespacedIds {
+            namespace: (u32::from_be_bytes(prefix) as u64) << 32,
+            counter: AtomicU64::new(1),
+        }
+    }
+}
+
+impl RequestIdGenerator for NamespacedIds {
+    fn next_id(&self) -> u64 {
+        let count = self.counter.fetch_add(1, Ordering::Relaxed);
+        self.namespace | (count & u32::MAX as u64)
+    }
+
+    fn skip_to(&self, id: u64) {
+        if id & !(u32::MAX as u64) == self.namespace {
+            self.counter.fetch_max((id & u32::MAX as u64) + 1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// [`RequestId`] that is scoped to the node that generated it, and to the running instance of that node.
+///
+/// Request ids are only unique within a running instance. The global id additionally contains the peer id of the
+/// originating node and a random session id that is chosen when the [`Network`][crate::Network] is built, so that it
+/// can be used e.g. as correlation key in distributed logs or as idempotency key without colliding between peers or
+/// across restarts. Use [`Network::global_request_id`][crate::Network::global_request_id] to scope an id.
+///
+/// Requests that are sent with metadata carry the global id of the sender, so that the receiver can correlate them
+/// with the logs of the sender, see [`ReceiveRequest::global_id`][crate::ReceiveRequest::global_id].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct GlobalRequestId {
+    /// Node that generated the id, i.e. the sender of an outbound request, or the receiver of an inbound request.
+    pub origin: PeerId,
+    /// Random id of the running instance of the origin.
+    pub session: u64,
+    pub id: RequestId,
+}
+
+impl fmt::Display for GlobalRequestId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{:016x}/{}", self.origin, self.session, self.id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn namespaced_ids() {
+        let peer = PeerId::random();
+        let generator = NamespacedIds::new(&peer);
+        let other = NamespacedIds::new(&PeerId::random());
+        let id = generator.next_id();
+        assert_eq!(id & u32::MAX as u64, 1);
+        assert_ne!(id >> 32, other.next_id() >> 32);
+
+        // Ids of a former instance with the same namespace are skipped, others are ignored.
+        generator.skip_to(id + 10);
+        generator.skip_to(other.next_id() + 100);
+        assert_eq!(generator.next_id(), id + 11);
+        assert_eq!(NamespacedIds::new(&peer).next_id(), id);
+    }
+
+    #[test]
+    fn global_ids() {
+        let origin = PeerId::random();
+        let id = RequestId::next(&CounterIds::default());
+        let global = GlobalRequestId { origin, session: 1, id };
+        assert_eq!(global.to_string(), format!("{}/0000000000000001/1", origin));
+
+        // The same local id of another peer or of a restarted instance does not collide.
+        let other_peer = GlobalRequestId {
+            origin: PeerId::random(),
+            ..global
+        };
+        let restarted = GlobalRequestId { session: 2, ..global };
+        assert_ne!(global, other_peer);
+        assert_ne!(global, restarted);
+        assert_ne!(global.to_string(), restarted.to_string());
+    }
+}
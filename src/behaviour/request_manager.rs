@@ -2,7 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
-    behaviour::EMPTY_QUEUE_SHRINK_THRESHOLD,
+    behaviour::{handler::SerializedRequest, CloseReason, ConfigConfig, ConnectionSelection},
     firewall::{FwRequest, Rule},
     unwrap_or_return, InboundFailure, OutboundFailure, RequestId,
 };
@@ -10,8 +10,13 @@ use crate::{
 use futures::channel::oneshot;
 pub use libp2p::core::{connection::ConnectionId, ConnectedPoint};
 use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
 use smallvec::SmallVec;
-use std::collections::{HashMap, VecDeque};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt,
+    time::Duration,
+};
 
 // Actions for the behaviour so that it emits the appropriate `NetworkBehaviourAction`.
 pub enum BehaviourAction<Rq, Rs> {
@@ -32,7 +37,7 @@ pub enum BehaviourAction<Rq, Rs> {
     OutboundOk {
         request_id: RequestId,
         peer: PeerId,
-        request: Rq,
+        request: SerializedRequest,
         // The connection and handler that this request was assigned to.
         connection: ConnectionId,
     },
@@ -47,6 +52,7 @@ pub enum BehaviourAction<Rq, Rs> {
         request_id: RequestId,
         peer: PeerId,
         response: Rs,
+        rtt: Duration,
     },
     // Required dial attempt to connect a peer where at least one request is pending.
     RequireDialAttempt(PeerId),
@@ -60,6 +66,66 @@ pub enum BehaviourAction<Rq, Rs> {
     },
 }
 
+// Debug representation that omits the content of requests and responses.
+impl<Rq, Rs> fmt::Debug for BehaviourAction<Rq, Rs> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BehaviourAction::InboundOk { request_id, peer, .. } => f
+                .debug_struct("InboundOk")
+                .field("request_id", request_id)
+                .field("peer", peer)
+                .finish(),
+            BehaviourAction::InboundFailure {
+                request_id,
+                peer,
+                failure,
+            } => f
+                .debug_struct("InboundFailure")
+                .field("request_id", request_id)
+                .field("peer", peer)
+                .field("failure", failure)
+                .finish(),
+            BehaviourAction::OutboundOk {
+                request_id,
+                peer,
+                connection,
+                ..
+            } => f
+                .debug_struct("OutboundOk")
+                .field("request_id", request_id)
+                .field("peer", peer)
+                .field("connection", connection)
+                .finish(),
+            BehaviourAction::OutboundFailure {
+                request_id,
+                peer,
+                failure,
+            } => f
+                .debug_struct("OutboundFailure")
+                .field("request_id", request_id)
+                .field("peer", peer)
+                .field("failure", failure)
+                .finish(),
+            BehaviourAction::OutboundReceivedRes { request_id, peer, .. } => f
+                .debug_struct("OutboundReceivedRes")
+                .field("request_id", request_id)
+                .field("peer", peer)
+                .finish(),
+            BehaviourAction::RequireDialAttempt(peer) => f.debug_tuple("RequireDialAttempt").field(peer).finish(),
+            BehaviourAction::SetInboundSupport {
+                peer,
+                connection,
+                support,
+            } => f
+                .debug_struct("SetInboundSupport")
+                .field("peer", peer)
+                .field("connection", connection)
+                .field("support", support)
+                .finish(),
+        }
+    }
+}
+
 // The status of a new request according to the firewall rule of the associated peer.
 #[derive(Debug)]
 pub enum ApprovalStatus {
@@ -76,12 +142,51 @@ pub enum ApprovalStatus {
 }
 
 // Direction of a request.
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 enum RequestDirection {
     Inbound,
     Outbound,
 }
 
+/// Snapshot of the requests that are currently pending in the `NetworkBehaviour`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PendingRequests {
+    /// Inbound requests that have not been approved by the firewall yet.
+    pub inbound_unapproved: Vec<RequestId>,
+    /// Outbound requests that have not been sent yet because the remote is not connected.
+    pub outbound_unsent: Vec<RequestId>,
+    /// Inbound requests that were forwarded to the user, but no response was sent yet.
+    pub inbound_awaiting_response: Vec<RequestId>,
+    /// Outbound requests that were sent, but no response was received yet.
+    pub outbound_awaiting_response: Vec<RequestId>,
+    /// Outbound requests that are held back until the previous request to the same peer was written or failed.
+    /// Only used if ordered delivery is enabled.
+    pub outbound_held_back: HashMap<PeerId, Vec<RequestId>>,
+    /// Outbound requests that wait for a connection to the remote peer.
+    pub awaiting_connection: HashMap<PeerId, Vec<RequestId>>,
+    /// Peers for which a [`FirewallRequest::PeerSpecificRule`][crate::firewall::FirewallRequest] was sent, with the
+    /// inbound requests that wait for the response.
+    pub awaiting_peer_rule: HashMap<PeerId, Vec<RequestId>>,
+    /// Inbound requests that wait for a [`FirewallRequest::RequestApproval`][crate::firewall::FirewallRequest]
+    /// response.
+    pub awaiting_approval: Vec<RequestId>,
+    /// Number of actions that are queued to be emitted by the `NetworkBehaviour`.
+    pub queued_actions: usize,
+    /// Approximate number of bytes of the requests that are cached while they await approval or a connection.
+    pub buffered_bytes: usize,
+}
+
+/// Inbound requests that currently wait for input through the firewall channel.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AwaitingFirewall {
+    /// Peers for which a [`FirewallRequest::PeerSpecificRule`][crate::firewall::FirewallRequest] was sent, with the
+    /// requests that wait for the rule.
+    pub peer_rule: HashMap<PeerId, Vec<RequestId>>,
+    /// Requests for which a [`FirewallRequest::RequestApproval`][crate::firewall::FirewallRequest] was sent, per
+    /// peer and ordered by request id.
+    pub approval: HashMap<PeerId, Vec<RequestId>>,
+}
+
 // Manager for pending requests that are awaiting a peer rule, individual approval, or a connection to the remote.
 //
 // Stores pending requests, manages rule, approval and connection changes, and queues required `BehaviourAction`s for
@@ -92,9 +197,33 @@ pub struct RequestManager<Rq, Rs> {
     established_connections: HashMap<PeerId, HashMap<ConnectionId, ConnectedPoint>>,
 
     // Cache of inbound requests that have not been approved yet.
-    inbound_requests_cache: HashMap<RequestId, (PeerId, Rq, oneshot::Sender<Rs>)>,
+    // Ordered by request id, so that the oldest request is the first entry.
+    inbound_requests_cache: BTreeMap<RequestId, (PeerId, Rq, oneshot::Sender<Rs>)>,
     // Cache of outbound requests where the target peer is not connected yet.
-    outbound_requests_cache: HashMap<RequestId, (PeerId, Rq)>,
+    // Ordered by request id, so that the oldest request is the first entry.
+    outbound_requests_cache: BTreeMap<RequestId, (PeerId, SerializedRequest)>,
+    // Limit for cached inbound requests.
+    max_pending_inbound: Option<usize>,
+    // Limit for cached outbound requests.
+    max_pending_outbound: Option<usize>,
+    // Size in bytes of each cached inbound and outbound request.
+    buffered_sizes: HashMap<RequestId, usize>,
+    // Total size of the cached requests.
+    buffered_bytes: usize,
+    // Limit for the total size of cached requests.
+    max_buffered_bytes: Option<usize>,
+    // Capacity above which the empty action queue is shrunk.
+    shrink_threshold: usize,
+    // Strategy for assigning outbound requests to one of multiple connections of a peer.
+    connection_selection: ConnectionSelection,
+    // Counter for `ConnectionSelection::RoundRobin`.
+    round_robin_counter: usize,
+    // Only have one outbound request per peer in flight at a time, so that requests are delivered in order.
+    ordered_delivery: bool,
+    // For ordered delivery: the outbound request to each peer that is currently in flight.
+    ordered_in_flight: HashMap<PeerId, RequestId>,
+    // For ordered delivery: outbound requests that wait for the in-flight request to the same peer to complete.
+    ordered_held_back: HashMap<PeerId, VecDeque<(RequestId, SerializedRequest)>>,
 
     /// Inbound requests received on each connection, where no response was sent yet.
     inbound_requests_on_connection: HashMap<ConnectionId, Vec<RequestId>>,
@@ -107,26 +236,38 @@ pub struct RequestManager<Rq, Rs> {
     // Pending inbound requests for peers that don't have any a firewall rule and currently await the response for a
     // `FirewallRequest::PeerSpecificRule` that has been sent.
     awaiting_peer_rule: HashMap<PeerId, SmallVec<[RequestId; 10]>>,
-    // Pending inbound requests that require explicit approval due to Rule::Ask, and currently await the response for a
+    // Pending requests that require explicit approval due to Rule::Ask, and currently await the response for a
     // `FirewallRequest::RequestApproval` that has been sent.
-    awaiting_approval: SmallVec<[RequestId; 10]>,
+    awaiting_approval: HashMap<RequestId, RequestDirection>,
 
     // Actions that should be emitted by the `NetworkBehaviour` as `NetworkBehaviourAction`.
     actions: VecDeque<BehaviourAction<Rq, Rs>>,
 }
 
 impl<Rq, Rs> RequestManager<Rq, Rs> {
-    pub fn new() -> Self {
+    pub fn new(config: &ConfigConfig) -> Self {
+        let tuning = config.buffer_tuning;
         RequestManager {
-            inbound_requests_cache: HashMap::new(),
-            outbound_requests_cache: HashMap::new(),
+            shrink_threshold: tuning.shrink_threshold,
+            connection_selection: config.connection_selection,
+            round_robin_counter: 0,
+            ordered_delivery: config.ordered_delivery,
+            ordered_in_flight: HashMap::new(),
+            ordered_held_back: HashMap::new(),
+            inbound_requests_cache: BTreeMap::new(),
+            outbound_requests_cache: BTreeMap::new(),
+            max_pending_inbound: config.max_pending_inbound,
+            max_pending_outbound: config.max_pending_outbound,
+            buffered_sizes: HashMap::new(),
+            buffered_bytes: 0,
+            max_buffered_bytes: config.max_buffered_bytes,
             established_connections: HashMap::new(),
             inbound_requests_on_connection: HashMap::new(),
             outbound_requests_on_connection: HashMap::new(),
             awaiting_connection: HashMap::new(),
             awaiting_peer_rule: HashMap::new(),
-            awaiting_approval: SmallVec::new(),
-            actions: VecDeque::new(),
+            awaiting_approval: HashMap::new(),
+            actions: VecDeque::with_capacity(tuning.action_queue_capacity),
         }
     }
 
@@ -135,6 +276,19 @@ impl<Rq, Rs> RequestManager<Rq, Rs> {
         self.established_connections.keys().copied().collect()
     }
 
+    // Whether outbound requests to the peer await a connection.
+    pub fn is_awaiting_connection(&self, peer: &PeerId) -> bool {
+        self.awaiting_connection.contains_key(peer)
+    }
+
+    // Ids of the currently established connections, and whether the remote dialed them.
+    pub fn connections(&self) -> Vec<(PeerId, ConnectionId, bool)> {
+        self.established_connections
+            .iter()
+            .flat_map(|(p, c)| c.iter().map(|(id, point)| (*p, *id, point.is_listener())))
+            .collect()
+    }
+
     // Currently established connections.
     pub fn established_connections(&self) -> Vec<(PeerId, Vec<ConnectedPoint>)> {
         self.established_connections
@@ -143,15 +297,134 @@ impl<Rq, Rs> RequestManager<Rq, Rs> {
             .collect()
     }
 
+    // Connection on which the inbound request was received, if it is still established.
+    pub fn inbound_connection(&self, peer: &PeerId, request_id: &RequestId) -> Option<(ConnectionId, &ConnectedPoint)> {
+        let (connection, _) = self
+            .inbound_requests_on_connection
+            .iter()
+            .find(|(_, pending)| pending.contains(request_id))?;
+        let endpoint = self.established_connections.get(peer)?.get(connection)?;
+        Some((*connection, endpoint))
+    }
+
+    // Total number of currently established connections.
+    pub fn connection_count(&self) -> usize {
+        self.established_connections.values().map(|c| c.len()).sum()
+    }
+
+    // Number of actions that are queued to be emitted.
+    pub fn queued_actions(&self) -> usize {
+        self.actions.len()
+    }
+
+    // Snapshot of all pending requests.
+    pub fn pending_requests(&self) -> PendingRequests {
+        PendingRequests {
+            inbound_unapproved: self.inbound_requests_cache.keys().copied().collect(),
+            outbound_unsent: self.outbound_requests_cache.keys().copied().collect(),
+            inbound_awaiting_response: self
+                .inbound_requests_on_connection
+                .values()
+                .flatten()
+                .copied()
+                .collect(),
+            outbound_awaiting_response: self
+                .outbound_requests_on_connection
+                .values()
+                .flatten()
+                .copied()
+                .collect(),
+            awaiting_connection: self
+                .awaiting_connection
+                .iter()
+                .map(|(p, rqs)| (*p, rqs.to_vec()))
+                .collect(),
+            outbound_held_back: self
+                .ordered_held_back
+                .iter()
+                .map(|(p, rqs)| (*p, rqs.iter().map(|(id, _)| *id).collect()))
+                .collect(),
+            awaiting_peer_rule: self
+                .awaiting_peer_rule
+                .iter()
+                .map(|(p, rqs)| (*p, rqs.to_vec()))
+                .collect(),
+            awaiting_approval: self.awaiting_approval.keys().copied().collect(),
+            queued_actions: self.queued_actions(),
+            buffered_bytes: self.buffered_bytes,
+        }
+    }
+
+    // Number of inbound requests that are cached while they await the firewall.
+    pub fn inbound_queue_depth(&self) -> usize {
+        self.inbound_requests_cache.len()
+    }
+
+    // Inbound requests that wait for a peer rule or an individual approval.
+    pub fn awaiting_firewall(&self) -> AwaitingFirewall {
+        let peer_rule = self
+            .awaiting_peer_rule
+            .iter()
+            .map(|(p, rqs)| (*p, rqs.to_vec()))
+            .collect();
+        let mut approval: HashMap<PeerId, Vec<RequestId>> = HashMap::new();
+        for (request_id, (peer, ..)) in &self.inbound_requests_cache {
+            if self.awaiting_approval.contains_key(request_id) {
+                approval.entry(*peer).or_default().push(*request_id);
+            }
+        }
+        AwaitingFirewall { peer_rule, approval }
+    }
+
+    // Inbound requests from the peer that wait for an individual approval, ordered by request id.
+    pub fn awaiting_approval_from(&self, peer: &PeerId) -> Vec<RequestId> {
+        self.inbound_requests_cache
+            .iter()
+            .filter(|(id, (p, ..))| p == peer && self.awaiting_approval.contains_key(id))
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
     // New outbound request that should be sent.
     // If the remote is connected the request is assigned to a connection, else it is cached and a
     // new connection attempt is issued.
-    pub fn on_new_out_request(&mut self, peer: PeerId, request_id: RequestId, request: Rq) {
+    // In case of ordered delivery, the request is held back while another request to the peer is in flight.
+    // Fails with `OutboundFailure::MaxBuffered` if the request would have to be cached, but the limit of buffered bytes
+    // is reached.
+    pub fn on_new_out_request(&mut self, peer: PeerId, request_id: RequestId, request: SerializedRequest) {
+        let is_held_back = self.ordered_delivery && self.ordered_in_flight.contains_key(&peer);
+        let is_connected = self
+            .established_connections
+            .get(&peer)
+            .map(|connections| !connections.is_empty())
+            .unwrap_or(false);
+        if (is_held_back || !is_connected) && !self.try_buffer(request_id, request.as_ref().len()) {
+            self.actions.push_back(BehaviourAction::OutboundFailure {
+                request_id,
+                peer,
+                failure: OutboundFailure::MaxBuffered,
+            });
+            return;
+        }
+        if self.ordered_delivery {
+            if self.ordered_in_flight.contains_key(&peer) {
+                let held_back = self.ordered_held_back.entry(peer).or_default();
+                held_back.push_back((request_id, request));
+                return;
+            }
+            self.ordered_in_flight.insert(peer, request_id);
+        }
+        self.send_out_request(peer, request_id, request)
+    }
+
+    // Assign the outbound request to a connection, or cache it and issue a new connection attempt.
+    fn send_out_request(&mut self, peer: PeerId, request_id: RequestId, request: SerializedRequest) {
         // If no connection to the peer exists, add dial attempt.
         if let Some(connection) =
             self.assign_request_to_connection(&peer, request_id, None, &RequestDirection::Outbound)
         {
             // Request is approved and assigned to an existing connection.
+            self.unbuffer(&request_id);
             let action = BehaviourAction::OutboundOk {
                 request_id,
                 peer,
@@ -164,31 +437,50 @@ impl<Rq, Rs> RequestManager<Rq, Rs> {
             let reqs = self.awaiting_connection.entry(peer).or_default();
             reqs.push(request_id);
             self.actions.push_back(BehaviourAction::RequireDialAttempt(peer));
+            self.evict_outbound_requests();
         }
     }
 
     // New inbound request was received.
     // Depending on the approval status it is either directly approved/ rejected, or cached
     // while it is waiting for peer rules or individual approval of the request.
+    // Fails with `InboundFailure::MaxBuffered` if the request would have to be cached, but the limit of buffered bytes
+    // is reached.
+    #[allow(clippy::too_many_arguments)]
     pub fn on_new_in_request(
         &mut self,
         peer: PeerId,
         request_id: RequestId,
         request: Rq,
+        request_len: usize,
         response_tx: oneshot::Sender<Rs>,
         connection: ConnectionId,
         approval_status: ApprovalStatus,
     ) {
+        let is_cached = matches!(
+            approval_status,
+            ApprovalStatus::MissingRule | ApprovalStatus::MissingApproval
+        );
+        if is_cached && !self.try_buffer(request_id, request_len) {
+            self.actions.push_back(BehaviourAction::InboundFailure {
+                request_id,
+                peer,
+                failure: InboundFailure::MaxBuffered,
+            });
+            return;
+        }
         if !matches!(approval_status, ApprovalStatus::Rejected) {
             // Add request to the requests of the associated connection.
             // Return if the connection closed.
             let conn =
                 self.assign_request_to_connection(&peer, request_id, Some(connection), &RequestDirection::Inbound);
             if conn.is_none() {
+                self.unbuffer(&request_id);
+                // Only connections that exceeded the limits are not tracked while they are open.
                 let action = BehaviourAction::InboundFailure {
                     request_id,
                     peer,
-                    failure: InboundFailure::ConnectionClosed,
+                    failure: InboundFailure::ConnectionClosed(CloseReason::ConnectionLimit),
                 };
                 self.actions.push_back(action);
                 return;
@@ -200,12 +492,14 @@ impl<Rq, Rs> RequestManager<Rq, Rs> {
                 self.inbound_requests_cache
                     .insert(request_id, (peer, request, response_tx));
                 self.awaiting_peer_rule.entry(peer).or_default().push(request_id);
+                self.evict_inbound_requests();
             }
             ApprovalStatus::MissingApproval => {
                 // Add request to the list of requests that are awaiting individual approval.
                 self.inbound_requests_cache
                     .insert(request_id, (peer, request, response_tx));
-                self.awaiting_approval.push(request_id);
+                self.awaiting_approval.insert(request_id, RequestDirection::Inbound);
+                self.evict_inbound_requests();
             }
             ApprovalStatus::Approved => {
                 let action = BehaviourAction::InboundOk {
@@ -243,6 +537,7 @@ impl<Rq, Rs> RequestManager<Rq, Rs> {
         if let Some(requests) = self.awaiting_connection.remove(&peer) {
             requests.into_iter().for_each(|request_id| {
                 let (peer, request) = unwrap_or_return!(self.outbound_requests_cache.remove(&request_id));
+                self.unbuffer(&request_id);
                 let connection = self
                     .assign_request_to_connection(&peer, request_id, Some(id), &RequestDirection::Outbound)
                     .expect("Peer is connected");
@@ -259,7 +554,13 @@ impl<Rq, Rs> RequestManager<Rq, Rs> {
 
     // Handle an individual connection closing.
     // Emit failures for the pending requests on that connection.
-    pub fn on_connection_closed(&mut self, peer: PeerId, connection: &ConnectionId, remaining_established: usize) {
+    pub fn on_connection_closed(
+        &mut self,
+        peer: PeerId,
+        connection: &ConnectionId,
+        remaining_established: usize,
+        reason: CloseReason,
+    ) {
         if remaining_established == 0 {
             self.established_connections.remove(&peer);
         } else {
@@ -276,8 +577,9 @@ impl<Rq, Rs> RequestManager<Rq, Rs> {
             self.actions.push_back(BehaviourAction::OutboundFailure {
                 request_id,
                 peer,
-                failure: OutboundFailure::ConnectionClosed,
-            })
+                failure: OutboundFailure::ConnectionClosed(reason.clone()),
+            });
+            self.on_outbound_completed(peer, request_id);
         }
 
         for request_id in self
@@ -286,14 +588,16 @@ impl<Rq, Rs> RequestManager<Rq, Rs> {
             .unwrap_or_default()
         {
             // Remove request from all queues and lists.
-            self.awaiting_approval.retain(|r| r != &request_id);
+            self.awaiting_approval.remove(&request_id);
             if let Some(requests) = self.awaiting_peer_rule.get_mut(&peer) {
                 requests.retain(|r| r != &request_id)
             }
+            self.inbound_requests_cache.remove(&request_id);
+            self.unbuffer(&request_id);
             self.actions.push_back(BehaviourAction::InboundFailure {
                 request_id,
                 peer,
-                failure: InboundFailure::ConnectionClosed,
+                failure: InboundFailure::ConnectionClosed(reason.clone()),
             })
         }
     }
@@ -304,12 +608,14 @@ impl<Rq, Rs> RequestManager<Rq, Rs> {
         let requests = unwrap_or_return!(self.awaiting_connection.remove(&peer));
         requests.into_iter().for_each(|request_id| {
             unwrap_or_return!(self.outbound_requests_cache.remove(&request_id));
+            self.unbuffer(&request_id);
             let action = BehaviourAction::OutboundFailure {
                 request_id,
                 peer,
                 failure: OutboundFailure::DialFailure,
             };
             self.actions.push_back(action);
+            self.on_outbound_completed(peer, request_id);
         });
     }
 
@@ -346,7 +652,7 @@ impl<Rq, Rs> RequestManager<Rq, Rs> {
                             .inbound_requests_cache
                             .get(&request_id)
                             .map(|(_, rq, _)| TRq::from_request(rq))?;
-                        self.awaiting_approval.push(request_id);
+                        self.awaiting_approval.insert(request_id, RequestDirection::Inbound);
                         Some((request_id, rq))
                     }
                     Some(Rule::AllowAll) => {
@@ -380,10 +686,63 @@ impl<Rq, Rs> RequestManager<Rq, Rs> {
         Some(require_ask)
     }
 
+    // Fail all requests of the peer that await a connection, a peer rule or an individual approval, or are held back
+    // for ordered delivery.
+    // Returns the ids of the aborted requests.
+    pub fn abort_pending(&mut self, peer: PeerId) -> Vec<RequestId> {
+        let mut aborted = Vec::new();
+        // Held back requests are removed first, so that they are not sent once the in-flight request is aborted.
+        for (request_id, _) in self.ordered_held_back.remove(&peer).unwrap_or_default() {
+            self.unbuffer(&request_id);
+            self.actions.push_back(BehaviourAction::OutboundFailure {
+                request_id,
+                peer,
+                failure: OutboundFailure::Aborted,
+            });
+            aborted.push(request_id);
+        }
+        for request_id in self.awaiting_connection.remove(&peer).unwrap_or_default() {
+            if self.outbound_requests_cache.remove(&request_id).is_none() {
+                continue;
+            }
+            self.unbuffer(&request_id);
+            self.actions.push_back(BehaviourAction::OutboundFailure {
+                request_id,
+                peer,
+                failure: OutboundFailure::Aborted,
+            });
+            self.on_outbound_completed(peer, request_id);
+            aborted.push(request_id);
+        }
+        // All cached inbound requests await either a peer rule or an individual approval.
+        self.awaiting_peer_rule.remove(&peer);
+        let inbound: Vec<_> = self
+            .inbound_requests_cache
+            .iter()
+            .filter_map(|(id, (p, ..))| (*p == peer).then(|| *id))
+            .collect();
+        for request_id in inbound {
+            self.inbound_requests_cache.remove(&request_id);
+            self.unbuffer(&request_id);
+            self.awaiting_approval.remove(&request_id);
+            self.inbound_requests_on_connection
+                .values_mut()
+                .for_each(|pending| pending.retain(|r| r != &request_id));
+            self.actions.push_back(BehaviourAction::InboundFailure {
+                request_id,
+                peer,
+                failure: InboundFailure::Aborted,
+            });
+            aborted.push(request_id);
+        }
+        aborted
+    }
+
     // Handle the approval of an individual request.
     pub fn on_request_approval(&mut self, request_id: RequestId, is_allowed: bool) {
-        self.awaiting_approval.retain(|r| r != &request_id);
+        self.awaiting_approval.remove(&request_id);
         let (peer, request, response_tx) = unwrap_or_return!(self.inbound_requests_cache.remove(&request_id));
+        self.unbuffer(&request_id);
         let action = if is_allowed {
             BehaviourAction::InboundOk {
                 request_id,
@@ -421,16 +780,23 @@ impl<Rq, Rs> RequestManager<Rq, Rs> {
     }
 
     // Handle response / failure for a previously sent request.
-    pub fn on_res_for_outbound(&mut self, peer: PeerId, request_id: RequestId, result: Result<Rs, OutboundFailure>) {
+    // The round-trip time is measured from writing the request to the substream until the response was received.
+    pub fn on_res_for_outbound(
+        &mut self,
+        peer: PeerId,
+        request_id: RequestId,
+        result: Result<(Rs, Duration), OutboundFailure>,
+    ) {
         self.outbound_requests_on_connection
             .values_mut()
             .for_each(|pending| pending.retain(|id| id != &request_id));
 
         let action = match result {
-            Ok(response) => BehaviourAction::OutboundReceivedRes {
+            Ok((response, rtt)) => BehaviourAction::OutboundReceivedRes {
                 request_id,
                 peer,
                 response,
+                rtt,
             },
             Err(failure) => BehaviourAction::OutboundFailure {
                 request_id,
@@ -438,7 +804,33 @@ impl<Rq, Rs> RequestManager<Rq, Rs> {
                 failure,
             },
         };
-        self.actions.push_back(action)
+        self.actions.push_back(action);
+        self.on_outbound_completed(peer, request_id);
+    }
+
+    // Handle an outbound request that was written to the substream.
+    pub fn on_request_written(&mut self, peer: PeerId, request_id: RequestId) {
+        self.on_outbound_completed(peer, request_id);
+    }
+
+    // For ordered delivery: send the next held back request to the peer once the in-flight one was written or failed.
+    fn on_outbound_completed(&mut self, peer: PeerId, request_id: RequestId) {
+        if self.ordered_in_flight.get(&peer) != Some(&request_id) {
+            return;
+        }
+        let next = self.ordered_held_back.get_mut(&peer).and_then(|q| q.pop_front());
+        match next {
+            Some((next_id, request)) => {
+                if self.ordered_held_back.get(&peer).map(|q| q.is_empty()).unwrap_or(false) {
+                    self.ordered_held_back.remove(&peer);
+                }
+                self.ordered_in_flight.insert(peer, next_id);
+                self.send_out_request(peer, next_id, request);
+            }
+            None => {
+                self.ordered_in_flight.remove(&peer);
+            }
+        }
     }
 
     // Check if there are pending requests for rule for a specific peer.
@@ -474,12 +866,73 @@ impl<Rq, Rs> RequestManager<Rq, Rs> {
     // Remove the next `BehaviourAction` from the queue and return it.
     pub fn take_next_action(&mut self) -> Option<BehaviourAction<Rq, Rs>> {
         let next = self.actions.pop_front();
-        if self.actions.capacity() > EMPTY_QUEUE_SHRINK_THRESHOLD {
+        if self.actions.capacity() > self.shrink_threshold {
             self.actions.shrink_to_fit();
         }
         next
     }
 
+    // Fail the oldest cached inbound requests while the limit is exceeded.
+    fn evict_inbound_requests(&mut self) {
+        let max = unwrap_or_return!(self.max_pending_inbound);
+        while self.inbound_requests_cache.len() > max {
+            let request_id = unwrap_or_return!(self.inbound_requests_cache.keys().next().copied());
+            let (peer, ..) = unwrap_or_return!(self.inbound_requests_cache.remove(&request_id));
+            self.unbuffer(&request_id);
+            self.awaiting_approval.remove(&request_id);
+            if let Some(requests) = self.awaiting_peer_rule.get_mut(&peer) {
+                requests.retain(|r| r != &request_id)
+            }
+            self.inbound_requests_on_connection
+                .values_mut()
+                .for_each(|pending| pending.retain(|r| r != &request_id));
+            self.actions.push_back(BehaviourAction::InboundFailure {
+                request_id,
+                peer,
+                failure: InboundFailure::Evicted,
+            });
+        }
+    }
+
+    // Fail the oldest cached outbound requests while the limit is exceeded.
+    fn evict_outbound_requests(&mut self) {
+        let max = unwrap_or_return!(self.max_pending_outbound);
+        while self.outbound_requests_cache.len() > max {
+            let request_id = unwrap_or_return!(self.outbound_requests_cache.keys().next().copied());
+            let (peer, _) = unwrap_or_return!(self.outbound_requests_cache.remove(&request_id));
+            self.unbuffer(&request_id);
+            if let Some(requests) = self.awaiting_connection.get_mut(&peer) {
+                requests.retain(|r| r != &request_id)
+            }
+            self.actions.push_back(BehaviourAction::OutboundFailure {
+                request_id,
+                peer,
+                failure: OutboundFailure::Evicted,
+            });
+            self.on_outbound_completed(peer, request_id);
+        }
+    }
+
+    // Account the size of a request that is cached.
+    // Returns `false` if the limit of buffered bytes would be exceeded.
+    fn try_buffer(&mut self, request_id: RequestId, len: usize) -> bool {
+        if let Some(max) = self.max_buffered_bytes {
+            if self.buffered_bytes + len > max {
+                return false;
+            }
+        }
+        self.buffered_bytes += len;
+        self.buffered_sizes.insert(request_id, len);
+        true
+    }
+
+    // Remove a request from the buffer accounting, once it left the caches.
+    fn unbuffer(&mut self, request_id: &RequestId) {
+        if let Some(len) = self.buffered_sizes.remove(request_id) {
+            self.buffered_bytes -= len;
+        }
+    }
+
     // New request that has been sent/ received, but with no response yet.
     // Assign the request to the given connection or else to a random established one.
     // Return `None` if there are no connections.
@@ -497,11 +950,27 @@ impl<Rq, Rs> RequestManager<Rq, Rs> {
                 connections.into_iter().find(|&c| c == &conn)?;
                 conn
             }
-            None => {
-                // Assign request to a rather random connection.
-                let index = (request_id.value() as usize) % connections.len();
-                connections.nth(index).cloned()?
-            }
+            None => match self.connection_selection {
+                ConnectionSelection::Random => {
+                    // Assign request to a rather random connection.
+                    let index = (request_id.value() as usize) % connections.len();
+                    connections.nth(index).cloned()?
+                }
+                ConnectionSelection::RoundRobin => {
+                    let index = self.round_robin_counter % connections.len();
+                    self.round_robin_counter = self.round_robin_counter.wrapping_add(1);
+                    connections.nth(index).cloned()?
+                }
+                ConnectionSelection::LeastLoaded => {
+                    // Assign request to the connection with the fewest requests in flight.
+                    let in_flight = |c: &ConnectionId| {
+                        let inbound = self.inbound_requests_on_connection.get(c).map(|r| r.len());
+                        let outbound = self.outbound_requests_on_connection.get(c).map(|r| r.len());
+                        inbound.unwrap_or(0) + outbound.unwrap_or(0)
+                    };
+                    connections.min_by_key(|c| in_flight(c)).cloned()?
+                }
+            },
         };
         let map = match direction {
             RequestDirection::Inbound => &mut self.inbound_requests_on_connection,
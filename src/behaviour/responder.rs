@@ -0,0 +1,116 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use super::RemoteError;
+use crate::timer::Instant;
+use futures::channel::oneshot;
+use std::{fmt, time::Duration};
+
+/// Handle for answering an inbound request.
+///
+/// The request is answered either with [`Responder::respond`], or rejected with a reason that is sent to the remote
+/// with [`Responder::reject`]. If the responder is dropped without doing either, the remote fails the request with
+/// [`RemoteError::Dropped`], and an [`InboundFailure::ResponseOmitted`][crate::InboundFailure::ResponseOmitted] is
+/// reported locally.
+pub struct Responder<Rs> {
+    response_tx: oneshot::Sender<Rs>,
+    // Channel for sending the reason for rejecting the request, `None` if the request can not be rejected, e.g.
+    // because it was forwarded by a mailbox.
+    error_tx: Option<oneshot::Sender<RemoteError>>,
+    // Time at which the inbound substream times out.
+    deadline: Option<Instant>,
+}
+
+impl<Rs> Responder<Rs> {
+    pub(crate) fn new(
+        response_tx: oneshot::Sender<Rs>,
+        error_tx: Option<oneshot::Sender<RemoteError>>,
+        deadline: Option<Instant>,
+    ) -> Self {
+        Responder {
+            response_tx,
+            error_tx,
+            deadline,
+        }
+    }
+
+    /// Send the response to the remote.
+    ///
+    /// Returns the response back if the requester is no longer waiting for it, e.g. because the request timed out or
+    /// the connection closed.
+    pub fn respond(self, response: Rs) -> Result<(), Rs> {
+        self.response_tx.send(response)
+    }
+
+    /// Reject the request, and send the reason to the remote, for which the request fails with
+    /// [`RemoteError::Rejected`]. Locally, a rejected request is not reported as inbound failure.
+    pub fn reject(self, reason: impl Into<String>) {
+        let Responder {
+            response_tx, error_tx, ..
+        } = self;
+        // The reason is only sent once the response channel is closed.
+        drop(response_tx);
+        if let Some(error_tx) = error_tx {
+            let _ = error_tx.send(RemoteError::Rejected(reason.into()));
+        }
+    }
+
+    /// Remaining time until the request times out on the local side, after which a response can not be sent anymore.
+    /// The remote uses its own timeout, which may be shorter.
+    ///
+    /// `None` if there is no deadline, e.g. for requests that were forwarded by a mailbox.
+    pub fn time_left(&self) -> Option<Duration> {
+        let now = Instant::now();
+        self.deadline
+            .map(|deadline| if deadline > now { deadline - now } else { Duration::ZERO })
+    }
+
+    /// Whether the requester is still waiting for the response.
+    ///
+    /// This is `false` once the request timed out, or the connection on which it was received closed.
+    pub fn is_waiting(&self) -> bool {
+        !self.response_tx.is_canceled()
+    }
+
+    /// Wait until the requester is no longer waiting for the response, e.g. to abort the processing of a request.
+    pub async fn closed(&mut self) {
+        self.response_tx.cancellation().await
+    }
+}
+
+impl<Rs> fmt::Debug for Responder<Rs> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Responder")
+            .field("is_waiting", &self.is_waiting())
+            .field("time_left", &self.time_left())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn respond_or_reject() {
+        let (response_tx, mut response_rx) = oneshot::channel::<u8>();
+        let (error_tx, mut error_rx) = oneshot::channel();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let responder = Responder::new(response_tx, Some(error_tx), Some(deadline));
+        assert!(responder.is_waiting());
+        assert!(responder.time_left().unwrap() > Duration::from_secs(9));
+        responder.reject("unknown resource");
+        assert!(response_rx.try_recv().is_err());
+        assert_eq!(
+            error_rx.try_recv().unwrap(),
+            Some(RemoteError::Rejected("unknown resource".into()))
+        );
+
+        let (response_tx, response_rx) = oneshot::channel::<u8>();
+        let responder = Responder::new(response_tx, None, None);
+        assert!(responder.time_left().is_none());
+        drop(response_rx);
+        assert!(!responder.is_waiting());
+        assert_eq!(responder.respond(1), Err(1));
+    }
+}
@@ -0,0 +1,68 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use super::oneshot::{MessageHandler, MessageHandlerEvent, OneShotMessage};
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+// Announcement that the sender rotated its identity to a new peer id.
+//
+// The message is sent on the connections of the old identity, hence the remote can trust it without further proof.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RotationMessage {
+    pub new_peer: PeerId,
+    // Addresses under which the new identity can be reached.
+    pub addrs: Vec<Multiaddr>,
+}
+
+impl OneShotMessage for RotationMessage {
+    const PROTOCOL_NAME: &'static [u8] = b"/p2p/rotation/1.0.0";
+    const MAX_SIZE: usize = 16 * 1024;
+}
+
+// Handler for announcing identity rotations on a connection.
+pub type RotationHandler = MessageHandler<RotationMessage>;
+
+// Events emitted by the `RotationHandler`.
+pub type RotationHandlerEvent = MessageHandlerEvent<RotationMessage>;
+
+// Announcement of a rotation that is being sent to the connected peers.
+#[derive(Debug)]
+pub struct Announcement {
+    pending: HashSet<PeerId>,
+    announced: Vec<PeerId>,
+}
+
+impl Announcement {
+    pub fn new(peers: HashSet<PeerId>) -> Self {
+        Announcement {
+            pending: peers,
+            announced: Vec::new(),
+        }
+    }
+
+    // Mark the announcement to the peer as finished.
+    // Returns the peers that received the announcement once it finished for all of them.
+    pub fn on_finished(&mut self, peer: &PeerId, is_sent: bool) -> Option<Vec<PeerId>> {
+        if self.pending.remove(peer) && is_sent {
+            self.announced.push(*peer);
+        }
+        self.pending.is_empty().then(|| std::mem::take(&mut self.announced))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn announcement_finishes_for_all_peers() {
+        let (a, b) = (PeerId::random(), PeerId::random());
+        let mut announcement = Announcement::new([a, b].into_iter().collect());
+        assert_eq!(announcement.on_finished(&a, true), None);
+        // Unknown peers are ignored.
+        assert_eq!(announcement.on_finished(&PeerId::random(), true), None);
+        assert_eq!(announcement.on_finished(&b, false), Some(vec![a]));
+    }
+}
@@ -0,0 +1,387 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use super::addresses::relay_of;
+use crate::{timer::Instant, InboundFailure, OutboundFailure};
+use libp2p::{
+    core::{connection::ConnectionId, ConnectedPoint},
+    PeerId,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+/// Traffic counters for the request-response messages exchanged with a single remote peer.
+///
+/// **Note**: Bytes are only counted for the serialized messages of completed request-response exchanges, the overhead
+/// of the transport and protocol negotiation is not included.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerStats {
+    /// Outbound requests that were dispatched to a connection handler.
+    pub requests_sent: u64,
+    /// Inbound requests that were read from a substream, before they were checked by the firewall.
+    pub requests_received: u64,
+    /// Responses that were sent back for inbound requests.
+    pub responses_sent: u64,
+    /// Responses that were received for outbound requests.
+    pub responses_received: u64,
+    /// Failures in the context of outbound requests.
+    pub outbound_failures: u64,
+    /// Failures in the context of inbound requests.
+    pub inbound_failures: u64,
+    /// Serialized bytes of sent requests and responses.
+    pub bytes_sent: u64,
+    /// Serialized bytes of received requests and responses.
+    pub bytes_received: u64,
+    /// Connections that were established to the peer.
+    pub connections_established: u64,
+    /// Connections to the peer that were closed.
+    pub connections_closed: u64,
+}
+
+impl PeerStats {
+    /// Share of the completed outbound requests that received a response, `None` if no request completed yet.
+    pub fn success_rate(&self) -> Option<f64> {
+        let completed = self.responses_received + self.outbound_failures;
+        (completed > 0).then(|| self.responses_received as f64 / completed as f64)
+    }
+}
+
+/// Usage counters of a relay, via which the local peer connected to other peers or was connected by them.
+///
+/// **Note**: Only the usage of the relay as client is tracked. If the local peer itself acts as relay for others, the
+/// relayed circuits are handled within the libp2p relay protocol and are not exposed, so they can not be counted.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayStats {
+    /// Relayed connections that were established via the relay.
+    pub circuits_established: u64,
+    /// Relayed connections via the relay that were closed.
+    pub circuits_closed: u64,
+    /// Serialized bytes of requests and responses that were sent via the relay.
+    pub bytes_sent: u64,
+    /// Serialized bytes of requests and responses that were received via the relay.
+    pub bytes_received: u64,
+}
+
+/// Number of failures for each [`InboundFailure`] variant.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InboundFailureCounts {
+    pub timeout: u64,
+    pub not_permitted: u64,
+    pub connection_closed: u64,
+    pub response_omitted: u64,
+    pub evicted: u64,
+    pub overloaded: u64,
+    pub max_buffered: u64,
+    pub intercepted: u64,
+    pub unknown_request: u64,
+    pub aborted: u64,
+}
+
+/// Number of failures for each [`OutboundFailure`] variant.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutboundFailureCounts {
+    pub timeout: u64,
+    pub dial_failure: u64,
+    pub connection_closed: u64,
+    pub unsupported_protocols: u64,
+    pub shutdown: u64,
+    pub evicted: u64,
+    pub invalid_request: u64,
+    pub max_buffered: u64,
+    pub expired: u64,
+    pub remote_error: u64,
+    pub intercepted: u64,
+    pub aborted: u64,
+}
+
+/// Aggregated statistics over all peers.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkStats {
+    /// Total number of outbound requests that were dispatched to a connection handler.
+    pub requests_sent: u64,
+    /// Total number of inbound requests that were read from a substream.
+    pub requests_received: u64,
+    /// Total number of responses that were sent back for inbound requests.
+    pub responses_sent: u64,
+    /// Total number of responses that were received for outbound requests.
+    pub responses_received: u64,
+    /// Failures in the context of inbound requests.
+    pub inbound_failures: InboundFailureCounts,
+    /// Failures in the context of outbound requests.
+    pub outbound_failures: OutboundFailureCounts,
+    /// Number of currently established connections.
+    pub active_connections: usize,
+    /// Number of actions that are queued to be emitted by the `NetworkBehaviour`.
+    pub queued_actions: usize,
+}
+
+// Maximum number of peers without established connection whose counters are kept. The counters of the peers that
+// disconnected first are removed beyond it.
+const MAX_INACTIVE_PEERS: usize = 1024;
+
+// Traffic statistics for each peer with which messages were exchanged.
+#[derive(Debug, Default)]
+pub struct TrafficStats {
+    peers: HashMap<PeerId, PeerStats>,
+    // Peers with counters but without established connection, in the order in which they became inactive.
+    inactive: VecDeque<PeerId>,
+    inbound_failures: InboundFailureCounts,
+    outbound_failures: OutboundFailureCounts,
+    // Usage counters for each relay.
+    relays: HashMap<PeerId, RelayStats>,
+    // Relay of each established relayed connection.
+    relayed_connections: HashMap<ConnectionId, PeerId>,
+    // Last time that messages were exchanged on each established connection.
+    last_active: HashMap<ConnectionId, Instant>,
+}
+
+impl TrafficStats {
+    // Get mutable access to the counters of a peer, create new ones if none exist yet.
+    // New counters are inactive until the peer connects.
+    pub fn peer_mut(&mut self, peer: PeerId) -> &mut PeerStats {
+        if !self.peers.contains_key(&peer) {
+            self.on_peer_disconnected(peer);
+        }
+        self.peers.entry(peer).or_default()
+    }
+
+    // Keep the counters of the peer while it is connected.
+    pub fn on_peer_connected(&mut self, peer: &PeerId) {
+        self.inactive.retain(|p| p != peer);
+    }
+
+    // Mark the counters of the peer as inactive, and remove the counters of the peers that are inactive the longest if
+    // there are too many.
+    pub fn on_peer_disconnected(&mut self, peer: PeerId) {
+        self.inactive.push_back(peer);
+        while self.inactive.len() > MAX_INACTIVE_PEERS {
+            if let Some(peer) = self.inactive.pop_front() {
+                self.peers.remove(&peer);
+            }
+        }
+    }
+
+    pub fn peer(&self, peer: &PeerId) -> Option<&PeerStats> {
+        self.peers.get(peer)
+    }
+
+    // Count a failure on an inbound request.
+    pub fn on_inbound_failure(&mut self, peer: PeerId, failure: &InboundFailure) {
+        self.peer_mut(peer).inbound_failures += 1;
+        let counts = &mut self.inbound_failures;
+        match failure {
+            InboundFailure::Timeout => counts.timeout += 1,
+            InboundFailure::NotPermitted => counts.not_permitted += 1,
+            InboundFailure::ConnectionClosed(_) => counts.connection_closed += 1,
+            InboundFailure::ResponseOmitted => counts.response_omitted += 1,
+            InboundFailure::Evicted => counts.evicted += 1,
+            InboundFailure::Overloaded => counts.overloaded += 1,
+            InboundFailure::MaxBuffered => counts.max_buffered += 1,
+            InboundFailure::Intercepted(_) => counts.intercepted += 1,
+            InboundFailure::UnknownRequest => counts.unknown_request += 1,
+            InboundFailure::Aborted => counts.aborted += 1,
+        }
+    }
+
+    // Count a failure on an outbound request.
+    pub fn on_outbound_failure(&mut self, peer: PeerId, failure: &OutboundFailure) {
+        self.peer_mut(peer).outbound_failures += 1;
+        let counts = &mut self.outbound_failures;
+        match failure {
+            OutboundFailure::Timeout => counts.timeout += 1,
+            OutboundFailure::DialFailure => counts.dial_failure += 1,
+            OutboundFailure::ConnectionClosed(_) => counts.connection_closed += 1,
+            OutboundFailure::UnsupportedProtocols { .. } => counts.unsupported_protocols += 1,
+            OutboundFailure::Shutdown => counts.shutdown += 1,
+            OutboundFailure::Evicted => counts.evicted += 1,
+            OutboundFailure::InvalidRequest => counts.invalid_request += 1,
+            OutboundFailure::MaxBuffered => counts.max_buffered += 1,
+            OutboundFailure::Expired => counts.expired += 1,
+            OutboundFailure::Remote(_) => counts.remote_error += 1,
+            OutboundFailure::Intercepted(_) => counts.intercepted += 1,
+            OutboundFailure::Aborted => counts.aborted += 1,
+        }
+    }
+
+    // Count a new connection as circuit of the relay, if it is a relayed connection.
+    pub fn on_connection_established(&mut self, connection: ConnectionId, endpoint: &ConnectedPoint) {
+        self.last_active.insert(connection, Instant::now());
+        let addr = match endpoint {
+            ConnectedPoint::Dialer { address, .. } => address,
+            ConnectedPoint::Listener { local_addr, .. } => local_addr,
+        };
+        if let Some(relay) = relay_of(addr) {
+            self.relays.entry(relay).or_default().circuits_established += 1;
+            self.relayed_connections.insert(connection, relay);
+        }
+    }
+
+    // Count a closed relayed connection.
+    pub fn on_connection_closed(&mut self, connection: &ConnectionId) {
+        self.last_active.remove(connection);
+        if let Some(relay) = self.relayed_connections.remove(connection) {
+            self.relays.entry(relay).or_default().circuits_closed += 1;
+        }
+    }
+
+    // Record that messages were exchanged on a connection, and add the bytes to the counters of its relay, if it is
+    // relayed.
+    pub fn on_connection_bytes(&mut self, connection: &ConnectionId, bytes_sent: usize, bytes_received: usize) {
+        if let Some(last_active) = self.last_active.get_mut(connection) {
+            *last_active = Instant::now();
+        }
+        if let Some(relay) = self.relayed_connections.get(connection) {
+            let stats = self.relays.entry(*relay).or_default();
+            stats.bytes_sent += bytes_sent as u64;
+            stats.bytes_received += bytes_received as u64;
+        }
+    }
+
+    // Last time that messages were exchanged on the connection, or when it was established.
+    pub fn last_active(&self, connection: &ConnectionId) -> Option<Instant> {
+        self.last_active.get(connection).copied()
+    }
+
+    // Snapshot of the counters of all relays.
+    pub fn export_relays(&self) -> HashMap<PeerId, RelayStats> {
+        self.relays.clone()
+    }
+
+    // Sum up the counters of all peers.
+    // The number of connections and queued actions are not tracked here and have to be set by the caller.
+    pub fn aggregate(&self) -> NetworkStats {
+        let mut stats = NetworkStats {
+            inbound_failures: self.inbound_failures.clone(),
+            outbound_failures: self.outbound_failures.clone(),
+            ..Default::default()
+        };
+        for peer in self.peers.values() {
+            stats.requests_sent += peer.requests_sent;
+            stats.requests_received += peer.requests_received;
+            stats.responses_sent += peer.responses_sent;
+            stats.responses_received += peer.responses_received;
+        }
+        stats
+    }
+
+    // Snapshot of the counters of all peers.
+    pub fn export(&self) -> HashMap<PeerId, PeerStats> {
+        self.peers.clone()
+    }
+}
+
+/// Configuration for detecting peers that reconnect too frequently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlapDetection {
+    /// Maximum number of connections that may be established to a peer within the `window`.
+    pub max_connects: u32,
+    /// Time window in which connects to the same peer are counted.
+    pub window: Duration,
+}
+
+// Detect peers that connect more often than allowed by the `FlapDetection` config.
+#[derive(Debug)]
+pub struct ChurnTracker {
+    config: Option<FlapDetection>,
+    // Timestamps of the recent connects of each peer within the window.
+    connects: HashMap<PeerId, VecDeque<Instant>>,
+    // Flapping peers with their number of connects in the window, that have not been reported yet.
+    flapping: VecDeque<(PeerId, u32)>,
+}
+
+impl ChurnTracker {
+    pub fn new(config: Option<FlapDetection>) -> Self {
+        ChurnTracker {
+            config,
+            connects: HashMap::new(),
+            flapping: VecDeque::new(),
+        }
+    }
+
+    // Record a new connection to the peer, and check if the peer exceeded the allowed number of connects.
+    // After a peer was reported as flapping, its connects are counted anew.
+    pub fn on_connection_established(&mut self, peer: PeerId) {
+        let config = match self.config {
+            Some(c) => c,
+            None => return,
+        };
+        let now = Instant::now();
+        let connects = self.connects.entry(peer).or_default();
+        while matches!(connects.front(), Some(t) if now.duration_since(*t) > config.window) {
+            connects.pop_front();
+        }
+        connects.push_back(now);
+        if connects.len() as u32 > config.max_connects {
+            self.flapping.push_back((peer, connects.len() as u32));
+            self.connects.remove(&peer);
+        }
+    }
+
+    // Remove the connects of peers that have not been connected within the window.
+    pub fn prune(&mut self) {
+        let window = match self.config {
+            Some(c) => c.window,
+            None => return,
+        };
+        let now = Instant::now();
+        self.connects
+            .retain(|_, connects| matches!(connects.back(), Some(t) if now.duration_since(*t) <= window));
+    }
+
+    // Take the next flapping peer that should be reported.
+    pub fn take_flapping(&mut self) -> Option<(PeerId, u32)> {
+        self.flapping.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn detect_flapping_peer() {
+        let config = FlapDetection {
+            max_connects: 2,
+            window: Duration::from_secs(60),
+        };
+        let mut tracker = ChurnTracker::new(Some(config));
+        let peer = PeerId::random();
+        tracker.on_connection_established(peer);
+        tracker.on_connection_established(peer);
+        assert!(tracker.take_flapping().is_none());
+        tracker.on_connection_established(peer);
+        assert_eq!(tracker.take_flapping(), Some((peer, 3)));
+        tracker.on_connection_established(peer);
+        assert!(tracker.take_flapping().is_none());
+    }
+
+    #[test]
+    fn prune_inactive_peers() {
+        let mut stats = TrafficStats::default();
+        let connected = PeerId::random();
+        stats.peer_mut(connected).connections_established += 1;
+        stats.on_peer_connected(&connected);
+        let first = PeerId::random();
+        stats.peer_mut(first).outbound_failures += 1;
+        for _ in 0..MAX_INACTIVE_PEERS {
+            stats.peer_mut(PeerId::random()).outbound_failures += 1;
+        }
+        assert!(stats.peer(&first).is_none());
+        assert!(stats.peer(&connected).is_some());
+        assert_eq!(stats.export().len(), MAX_INACTIVE_PEERS + 1);
+    }
+
+    #[test]
+    fn success_rate_of_outbound_requests() {
+        let mut stats = PeerStats::default();
+        assert_eq!(stats.success_rate(), None);
+        stats.responses_received = 3;
+        stats.outbound_failures = 1;
+        assert_eq!(stats.success_rate(), Some(0.75));
+    }
+}
@@ -0,0 +1,195 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use super::oneshot::{MessageHandler, MessageHandlerEvent, OneShotMessage};
+use libp2p::{
+    multihash::{Code, MultihashDigest},
+    PeerId,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// Maximum size of a chunk, so that the encoded chunk does not exceed the max message size.
+const MAX_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Configuration for blob transfers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransferConfig {
+    /// Size of the chunks in which outbound blobs are sent.
+    /// Each chunk has to be acknowledged by the recipient before the next one is sent.
+    pub chunk_size: usize,
+    /// Maximum size of an inbound blob. Larger transfers are rejected.
+    pub max_blob_size: u64,
+}
+
+impl Default for TransferConfig {
+    fn default() -> Self {
+        TransferConfig {
+            chunk_size: 256 * 1024,
+            max_blob_size: 64 * 1024 * 1024,
+        }
+    }
+}
+
+/// Possible failures of an outbound blob transfer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransferFailure {
+    /// The recipient rejected the transfer, e.g. because of its firewall rules, or aborted it.
+    Rejected,
+    /// The connection to the recipient closed, or it could not be reached.
+    /// The transfer can be resumed.
+    Interrupted,
+    /// The data received by the recipient did not match the digest of the blob.
+    IntegrityCheckFailed,
+    /// The metadata of the blob could not be serialized.
+    InvalidRequest,
+}
+
+impl fmt::Display for TransferFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransferFailure::Rejected => write!(f, "The recipient rejected or aborted the transfer"),
+            TransferFailure::Interrupted => write!(f, "The transfer was interrupted"),
+            TransferFailure::IntegrityCheckFailed => write!(f, "The integrity check of the received data failed"),
+            TransferFailure::InvalidRequest => write!(f, "The metadata could not be serialized"),
+        }
+    }
+}
+
+impl std::error::Error for TransferFailure {}
+
+// Message of the transfer protocol.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TransferMessage {
+    // Offer to transfer a blob, described by the serialized metadata request.
+    // Sent again by the sender to resume an interrupted transfer.
+    Offer {
+        id: u64,
+        size: u64,
+        digest: Vec<u8>,
+        metadata: String,
+    },
+    // The recipient accepted the offer and expects the data from the offset on.
+    Accept {
+        id: u64,
+        offset: u64,
+    },
+    // The recipient rejected the offer, or aborted the transfer.
+    Reject {
+        id: u64,
+    },
+    // The sender aborted the transfer.
+    Cancel {
+        id: u64,
+    },
+    // Base64 encoded chunk of the blob, starting at the offset.
+    Chunk {
+        id: u64,
+        offset: u64,
+        data: String,
+    },
+    // The recipient received the data up to the offset.
+    Ack {
+        id: u64,
+        offset: u64,
+    },
+    // The recipient received all data, and verified it against the digest.
+    Complete {
+        id: u64,
+        is_valid: bool,
+    },
+}
+
+impl OneShotMessage for TransferMessage {
+    const PROTOCOL_NAME: &'static [u8] = b"/p2p/transfer/1.0.0";
+    const MAX_SIZE: usize = 16 * 1024 * 1024;
+}
+
+// Handler for exchanging transfer messages on a connection.
+pub type TransferHandler = MessageHandler<TransferMessage>;
+
+// Events emitted by the `TransferHandler`.
+pub type TransferHandlerEvent = MessageHandlerEvent<TransferMessage>;
+
+// State of an outbound transfer.
+#[derive(Debug)]
+pub struct OutboundTransfer {
+    pub peer: PeerId,
+    pub metadata: String,
+    pub data: Vec<u8>,
+    pub digest: Vec<u8>,
+    // Whether the transfer was interrupted and has to be resumed.
+    pub is_interrupted: bool,
+}
+
+impl OutboundTransfer {
+    pub fn new(peer: PeerId, metadata: String, data: Vec<u8>) -> Self {
+        OutboundTransfer {
+            peer,
+            metadata,
+            digest: digest(&data),
+            data,
+            is_interrupted: false,
+        }
+    }
+
+    pub fn offer(&self, id: u64) -> TransferMessage {
+        TransferMessage::Offer {
+            id,
+            size: self.data.len() as u64,
+            digest: self.digest.clone(),
+            metadata: self.metadata.clone(),
+        }
+    }
+
+    // Next chunk starting at the offset, `None` if all data was sent.
+    pub fn chunk(&self, id: u64, offset: u64, chunk_size: usize) -> Option<TransferMessage> {
+        let start = usize::try_from(offset).ok().filter(|o| *o < self.data.len())?;
+        let end = self.data.len().min(start + chunk_size.clamp(1, MAX_CHUNK_SIZE));
+        Some(TransferMessage::Chunk {
+            id,
+            offset,
+            data: base64::encode(&self.data[start..end]),
+        })
+    }
+}
+
+// State of an inbound transfer.
+// The received data is kept if the transfer is interrupted, so that it can be resumed.
+#[derive(Debug)]
+pub struct InboundTransfer<Rq> {
+    pub metadata: Rq,
+    pub size: u64,
+    pub digest: Vec<u8>,
+    pub data: Vec<u8>,
+}
+
+impl<Rq> InboundTransfer<Rq> {
+    // Append a received chunk. Chunks that don't continue the received data are ignored.
+    // Returns `false` if the chunk is invalid or exceeds the size of the blob.
+    pub fn append(&mut self, offset: u64, chunk: &str) -> bool {
+        if offset != self.data.len() as u64 {
+            return true;
+        }
+        match base64::decode(chunk) {
+            Ok(bytes) if self.data.len() as u64 + bytes.len() as u64 <= self.size => {
+                self.data.extend(bytes);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.data.len() as u64 == self.size
+    }
+
+    pub fn is_valid(&self) -> bool {
+        digest(&self.data) == self.digest
+    }
+}
+
+// SHA2-256 multihash of the data.
+pub fn digest(data: &[u8]) -> Vec<u8> {
+    Code::Sha2_256.digest(data).to_bytes()
+}
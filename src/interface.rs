@@ -1,36 +1,96 @@
 // Copyright 2020-2021 IOTA Stiftung
 // SPDX-License-Identifier: Apache-2.0
 
+mod api;
+#[cfg(feature = "tcp-transport")]
+mod bind;
+mod config;
 mod event_channel;
 mod event_loop;
-
+mod firewall_watcher;
+#[cfg(feature = "keystore")]
+mod keystore;
+#[cfg(feature = "metrics")]
+mod metrics;
+#[cfg(feature = "mock")]
+mod mock;
+mod namespace;
+mod noise;
+mod sharded;
+mod stagger;
+#[cfg(feature = "otel")]
+mod telemetry;
+
+pub use api::NetworkApi;
+#[cfg(feature = "tcp-transport")]
+use bind::BoundTcpTransport;
+#[cfg(feature = "tcp-transport")]
+pub use bind::OutboundBinding;
+pub use config::NetworkConfig;
 pub use event_channel::{ChannelSinkConfig, EventChannel};
+pub use event_loop::EventLoopDriver;
 use event_loop::{EventLoop, SwarmCommand};
+use firewall_watcher::FirewallWatcher;
+#[cfg(feature = "keystore")]
+pub use keystore::{Keystore, KeystoreErr};
+#[cfg(feature = "metrics")]
+pub use metrics::NetworkMetrics;
+#[cfg(feature = "mock")]
+pub use mock::{MockController, MockNetwork, OutboundRequest};
+pub use namespace::{NamespaceBehaviour, NamespaceEvent, NamespacedNetwork};
+use noise::{BannedPeers, ExpectedPeer, NoiseUpgrade};
+pub use noise::{HandshakeFailure, NoiseKeyRotation};
+use sharded::ShardRouting;
+pub use sharded::{Shard, ShardedNetwork};
 use smallvec::SmallVec;
+use stagger::StaggeredDial;
+#[cfg(feature = "otel")]
+pub use telemetry::Telemetry;
 
 use crate::{
     behaviour::{
-        BehaviourEvent, ConfigConfig, InboundFailure, NetworkBehaviour, OutboundFailure, RequestId, RqRsMessage,
+        AwaitingFirewall, BehaviourEvent, BlockErr, BlockExchangeConfig, BlockStore, BufferTuning, CloseReason, Codec,
+        ConfigConfig, ConnectionSelection, DecodeWorkers, DialBackoff, EnqueueErr, EstablishedLimits,
+        ExcessConnectionPolicy, FlapDetection, GlobalRequestId, Hello, IdempotencyConfig, InboundFailure,
+        InboundWatermarks, KeepAliveProbes, LimitPressurePolicy, MailboxConfig, MailboxDelivery, MessageMigrations,
+        NetworkBehaviour, OutboundFailure, OutboundQueueConfig, PendingRequests, PingErr, PresenceConfig, QueueStore,
+        RelayCandidate, RelayPolicy, RelaySelection, RequestBatching, RequestConnection, RequestId, RequestIdGenerator,
+        RequestJournal, RequestMetadata, RequestMiddleware, RequestPriority, RequestStatus, Responder, ResponseHook,
+        RqRsMessage, RttSample, TransferConfig, TransferFailure, UnansweredRequest,
     },
-    firewall::{FirewallRequest, FirewallRules, FwRequest, Rule},
-    AddressInfo, RelayNotSupported,
+    firewall::{
+        capability::{CapabilityToken, CapabilityVerifier},
+        FirewallRequest, FirewallRules, FwRequest, Rule, RuleKind,
+    },
+    AddressInfo, NetworkStats, PeerStats, RecentEvent, RelayNotSupported, RelayStats,
 };
 
 use futures::{
     channel::{mpsc, oneshot},
-    future::poll_fn,
-    AsyncRead, AsyncWrite, FutureExt,
+    future::{join_all, poll_fn},
+    AsyncRead, AsyncWrite, FutureExt, StreamExt,
 };
+#[cfg(feature = "mdns")]
+use libp2p::mdns::{Mdns, MdnsConfig};
+#[cfg(feature = "relay")]
+use libp2p::relay::v1::{new_transport_and_behaviour, RelayConfig};
 use libp2p::{
-    core::{transport::Transport, upgrade, ConnectedPoint, Executor, Multiaddr, PeerId},
-    identity::Keypair,
-    mdns::{Mdns, MdnsConfig},
+    core::{
+        muxing::StreamMuxerBox,
+        transport::{
+            timeout::{TransportTimeout, TransportTimeoutError},
+            Boxed, Transport,
+        },
+        upgrade, ConnectedPoint, Executor, Multiaddr, PeerId,
+    },
+    identify::{Identify, IdentifyConfig},
+    identity::{self, error::DecodingError, Keypair},
     multihash::Multihash,
-    noise::{AuthenticKeypair, Keypair as NoiseKeypair, NoiseConfig, X25519Spec},
-    relay::v1::{new_transport_and_behaviour, RelayConfig},
+    noise::{AuthenticKeypair, Keypair as NoiseKeypair, X25519Spec},
+    ping::{Ping, PingConfig},
     swarm::{
-        ConnectionError, ConnectionLimit, ConnectionLimits as Libp2pConnectionLimits, DialError,
-        PendingConnectionError, SwarmBuilder, SwarmEvent,
+        ConnectionError, ConnectionLimit, ConnectionLimits as Libp2pConnectionLimits, DialError, DummyBehaviour,
+        NetworkBehaviour as Libp2pNetworkBehaviour, PendingConnectionError, SwarmBuilder, SwarmEvent,
     },
     yamux::YamuxConfig,
     TransportError,
@@ -38,7 +98,14 @@ use libp2p::{
 #[cfg(feature = "tcp-transport")]
 use libp2p::{dns::TokioDnsConfig, tcp::TokioTcpConfig, websocket::WsConfig};
 use serde::{Deserialize, Serialize};
-use std::{io, num::NonZeroU32, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs, io,
+    num::{NonZeroU32, NonZeroU8, NonZeroUsize},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 use thiserror::Error;
 
 /// Central interface for listening to the network, establishing connection to remote peers, sending requests `Rq`
@@ -63,6 +130,14 @@ use thiserror::Error;
 ///    individual approval. If the user does not response in time or the receiving side of the channel was dropped, the
 /// request is rejected.
 ///
+/// ## Identity key rotation
+///
+/// The local [`PeerId`] and the keys of the transport are fixed for the lifetime of the swarm. To rotate the identity,
+/// build a new `Network` with the new keys via [`NetworkBuilder::with_keys`], and hand over to it with
+/// [`Network::rotate_identity`]. This re-establishes the connections under the new `PeerId`, optionally announces
+/// the rotation to the connected peers, which report it with [`NetworkEvent::PeerIdentityRotated`], and drops the
+/// old `Network`.
+///
 /// ## Example
 ///
 /// ```
@@ -135,6 +210,8 @@ where
 {
     // Id of the local peer.
     local_peer_id: PeerId,
+    // Random id of this running instance, for scoping request ids.
+    session: u64,
     // Channel for sending `SwarmCommand` to the `EventLoop`.
     // The `SwarmCommand`s trigger according operations on the Swarm.
     // The result of an operation is received via the oneshot Receiver that is included in each type.
@@ -166,11 +243,31 @@ where
         self.local_peer_id
     }
 
+    /// Scope the id of an inbound or outbound request of this network to the local peer and running instance, so that
+    /// it is unique across peers and restarts.
+    pub fn global_request_id(&self, request_id: RequestId) -> GlobalRequestId {
+        GlobalRequestId {
+            origin: self.local_peer_id,
+            session: self.session,
+            id: request_id,
+        }
+    }
+
     /// Send a new request to a remote peer.
     ///
     /// This will attempt to establish a connection to the remote via one of the known addresses if there is no active
     /// connection.
     pub async fn send_request(&mut self, peer: PeerId, request: Rq) -> Result<Rs, OutboundFailure> {
+        self.send_request_timed(peer, request).await.map(|r| r.response)
+    }
+
+    /// Send a new request to a remote peer, like [`Network::send_request`], and return the response together with the
+    /// measured round-trip time of the request.
+    pub async fn send_request_timed(
+        &mut self,
+        peer: PeerId,
+        request: Rq,
+    ) -> Result<TimedResponse<Rs>, OutboundFailure> {
         let (return_tx, rx_yield) = oneshot::channel();
         let command = SwarmCommand::SendRequest {
             peer,
@@ -181,6 +278,291 @@ where
         rx_yield.await.unwrap()
     }
 
+    /// Send a new request to a remote peer, like [`Network::send_request`], and return a handle for observing the
+    /// status of the request while it is pending, e.g. while the remote is dialed.
+    pub async fn send_request_tracked(&mut self, peer: PeerId, request: Rq) -> RequestHandle<Rs> {
+        let (status_tx, status_rx) = mpsc::unbounded();
+        let (return_tx, response_rx) = oneshot::channel();
+        let (id_tx, id_rx) = oneshot::channel();
+        let command = SwarmCommand::SendRequestTracked {
+            peer,
+            request,
+            status_tx,
+            return_tx,
+            id_tx,
+        };
+        self.send_command(command).await;
+        RequestHandle {
+            request_id: id_rx.await.unwrap(),
+            status: RequestStatus::Queued,
+            status_rx,
+            response_rx,
+        }
+    }
+
+    /// Deposit a request for a peer that is currently offline in a mailbox. The mailbox stores the request, and
+    /// forwards it once the recipient connects to it.
+    ///
+    /// The mailbox has to be configured with [`NetworkBuilder::with_mailbox`], and the recipient has to trust it
+    /// via [`NetworkBuilder::with_trusted_mailboxes`].
+    /// The delivery status is reported with a [`NetworkEvent::MailboxReceipt`] for the returned request id. If no
+    /// receipt is received within the `ttl`, the request is reported as [`MailboxDelivery::Expired`].
+    ///
+    /// **Note**: The recipient can not respond to the request, a response has to be sent as new request.
+    pub async fn send_via_mailbox(
+        &mut self,
+        mailbox: PeerId,
+        recipient: PeerId,
+        request: Rq,
+        ttl: Duration,
+    ) -> RequestId {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::SendViaMailbox {
+            mailbox,
+            recipient,
+            request,
+            ttl,
+            return_tx,
+        };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Add a request to the persistent outbound queue, for a peer that is possibly not reachable right now.
+    ///
+    /// The request is sent immediately, and if that fails, again each time the peer connects, until a response was
+    /// received or the `ttl` expired. Queued requests survive restarts if the queue is backed by a persistent
+    /// [`QueueStore`]. The response or final failure is forwarded through the channel that was set in
+    /// [`NetworkBuilder::with_outbound_queue`].
+    ///
+    /// The request is sent with an idempotency key, so that a remote with [`NetworkBuilder::with_idempotency`] can
+    /// detect retransmissions.
+    pub async fn enqueue_request(&mut self, peer: PeerId, request: Rq, ttl: Duration) -> Result<RequestId, EnqueueErr> {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::EnqueueRequest {
+            peer,
+            request,
+            ttl,
+            return_tx,
+        };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Get the inbound requests that a former running instance accepted but never answered, according to the request
+    /// journal set with [`NetworkBuilder::with_request_journal`].
+    ///
+    /// The requests stay in the journal until they are reconciled with [`Network::reconcile_request`].
+    pub async fn unanswered_requests(&mut self) -> Vec<UnansweredRequest<Rq>> {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::GetUnansweredRequests { return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Remove an unanswered request of a former running instance from the journal, once the application handled it.
+    pub async fn reconcile_request(&mut self, request_id: RequestId) -> io::Result<()> {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::ReconcileRequest { request_id, return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Send a blob to a remote peer. The blob is sent in chunks, and verified by the recipient against its digest.
+    ///
+    /// The `metadata` describes the blob, e.g. its file name, and is checked by the firewall of the recipient like
+    /// any request. Rules that require asking for approval reject the transfer.
+    /// The recipient has to accept transfers with [`NetworkBuilder::with_blob_transfer`].
+    ///
+    /// Returns the id of the transfer. The progress and the outcome are reported with
+    /// [`NetworkEvent::TransferProgress`] and [`NetworkEvent::TransferFinished`].
+    pub async fn send_blob(&mut self, peer: PeerId, metadata: Rq, data: Vec<u8>) -> RequestId {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::SendBlob {
+            peer,
+            metadata,
+            data,
+            return_tx,
+        };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Read a file and send it as blob to a remote peer, see [`Network::send_blob`].
+    ///
+    /// **Note**: The whole file is kept in memory until the transfer finished.
+    pub async fn send_file<P: AsRef<Path>>(&mut self, peer: PeerId, metadata: Rq, path: P) -> io::Result<RequestId> {
+        let data = fs::read(path)?;
+        Ok(self.send_blob(peer, metadata, data).await)
+    }
+
+    /// Resume an outbound transfer that failed with [`TransferFailure::Interrupted`].
+    /// The transfer continues from the data that the recipient already received.
+    ///
+    /// Returns `false` if there is no interrupted transfer with this id.
+    pub async fn resume_transfer(&mut self, transfer_id: RequestId) -> bool {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::ResumeTransfer { transfer_id, return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Abort an outbound transfer, or drop an interrupted transfer that should not be resumed.
+    pub async fn cancel_transfer(&mut self, transfer_id: RequestId) {
+        let command = SwarmCommand::CancelTransfer { transfer_id };
+        self.send_command(command).await;
+    }
+
+    /// Store a block in the block store, and provide it to connected peers that want it.
+    /// The block exchange has to be enabled with [`NetworkBuilder::with_block_exchange`].
+    ///
+    /// Returns the hash of the block, with which other peers can fetch it.
+    pub async fn put_block(&mut self, data: Vec<u8>) -> Result<Vec<u8>, BlockErr> {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::PutBlock { data, return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Get the block with this hash from the local block store, or fetch it from whichever connected peer has it.
+    /// A fetched block is verified against the hash, and stored in the local block store.
+    ///
+    /// Peers that connect while the block is wanted are asked as well. Fails with [`BlockErr::NotFound`] if no peer
+    /// provided the block within the want timeout of the [`BlockExchangeConfig`].
+    pub async fn get_block(&mut self, hash: Vec<u8>) -> Result<Vec<u8>, BlockErr> {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::GetBlock { hash, return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Send a new request to a remote peer, together with a capability token that was issued for the local peer.
+    ///
+    /// If the remote accepts the token, the request is approved without checking its firewall rules for the local
+    /// peer. Refer to the [`capability`][crate::firewall::capability] module for more information.
+    pub async fn send_request_with_capability(
+        &mut self,
+        peer: PeerId,
+        request: Rq,
+        capability: CapabilityToken,
+    ) -> Result<Rs, OutboundFailure> {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::SendRequestWithCapability {
+            peer,
+            request,
+            capability,
+            return_tx,
+        };
+        self.send_command(command).await;
+        rx_yield.await.unwrap().map(|r| r.response)
+    }
+
+    /// Send a new request to a remote peer, together with an idempotency key.
+    ///
+    /// If the remote enabled [`NetworkBuilder::with_idempotency`], it forwards only the first request with this key
+    /// from the local peer to its application, and answers retransmissions within its window with the same response.
+    /// This makes it safe to retry non-idempotent requests after a failure. The remote has to support idempotency
+    /// keys, otherwise the request fails with [`OutboundFailure::UnsupportedProtocols`].
+    pub async fn send_request_with_idempotency_key(
+        &mut self,
+        peer: PeerId,
+        request: Rq,
+        key: String,
+    ) -> Result<Rs, OutboundFailure> {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::SendRequestWithIdempotencyKey {
+            peer,
+            request,
+            key,
+            return_tx,
+        };
+        self.send_command(command).await;
+        rx_yield.await.unwrap().map(|r| r.response)
+    }
+
+    /// Send a new request to a remote peer, together with string key-value metadata, e.g. tracing ids, tenancy
+    /// information or auth material that should not be part of the request type.
+    ///
+    /// The metadata is exposed to the remote application in [`ReceiveRequest::metadata`], and the
+    /// [`Network::global_request_id`] of the request in [`ReceiveRequest::global_id`]. The remote has to support
+    /// request metadata, otherwise the request fails with [`OutboundFailure::UnsupportedProtocols`]. Metadata can not
+    /// be combined with a capability token or idempotency key.
+    pub async fn send_request_with_metadata(
+        &mut self,
+        peer: PeerId,
+        request: Rq,
+        metadata: RequestMetadata,
+    ) -> Result<Rs, OutboundFailure> {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::SendRequestWithMetadata {
+            peer,
+            request,
+            metadata,
+            priority: RequestPriority::Normal,
+            return_tx,
+        };
+        self.send_command(command).await;
+        rx_yield.await.unwrap().map(|r| r.response)
+    }
+
+    /// Send a new request to a remote peer with a priority class.
+    ///
+    /// The request is sent before requests of a lower class that are queued for the same connection. The class is
+    /// exposed to the remote in [`ReceiveRequest::priority`], so that it can prioritize the processing, and the remote
+    /// firewall may apply a rule for the class (see [`Network::set_priority_rule`]). The priority is transmitted with
+    /// the envelope variant of the protocol, which the remote has to support.
+    pub async fn send_request_with_priority(
+        &mut self,
+        peer: PeerId,
+        request: Rq,
+        priority: RequestPriority,
+    ) -> Result<Rs, OutboundFailure> {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::SendRequestWithMetadata {
+            peer,
+            request,
+            metadata: RequestMetadata::new(),
+            priority,
+            return_tx,
+        };
+        self.send_command(command).await;
+        rx_yield.await.unwrap().map(|r| r.response)
+    }
+
+    /// Send the same request to multiple remote peers, e.g. for broadcasting a message.
+    ///
+    /// Contrary to calling [`Network::send_request`] for each peer, the request is only serialized once.
+    /// Returns the outcome for each peer once all requests completed.
+    pub async fn send_request_to_peers(
+        &mut self,
+        peers: Vec<PeerId>,
+        request: Rq,
+    ) -> Vec<(PeerId, Result<Rs, OutboundFailure>)> {
+        let (targets, rxs): (Vec<_>, Vec<_>) = peers
+            .into_iter()
+            .map(|peer| {
+                let (return_tx, rx_yield) = oneshot::channel();
+                ((peer, return_tx), rx_yield)
+            })
+            .unzip();
+        let peers = targets.iter().map(|(peer, _)| *peer).collect::<Vec<_>>();
+        let command = SwarmCommand::SendRequestToPeers { request, targets };
+        self.send_command(command).await;
+        let results = join_all(rxs).await;
+        let results = results.into_iter().map(|r| r.unwrap().map(|r| r.response));
+        peers.into_iter().zip(results).collect()
+    }
+
+    /// Send the same request to all peers with the tag, see [`Network::send_request_to_peers`].
+    pub async fn send_request_to_tagged(
+        &mut self,
+        tag: String,
+        request: Rq,
+    ) -> Vec<(PeerId, Result<Rs, OutboundFailure>)> {
+        let peers = self.peers_with_tag(tag).await;
+        self.send_request_to_peers(peers, request).await
+    }
+
     /// Start listening on the network on the given address.
     /// In case of a tcp-transport, the address `/ip4/0.0.0.0/tcp/0` can be set if an OS-assigned address should be
     /// used.
@@ -198,6 +580,9 @@ where
     /// Start listening via a relay peer. This will establish a keep-alive connection to the relay,
     /// the relay will forward all requests to the local peer.
     /// The returned address will follow the scheme `<relay-addr>/<relay-id>/p2p-circuit/<local-id>`.
+    ///
+    /// If the listener or the connection to the relay is lost later on, the reservation on the relay is renewed
+    /// automatically, see [`NetworkBuilder::with_relay_renewal`].
     pub async fn start_relayed_listening(
         &mut self,
         relay: PeerId,
@@ -213,6 +598,56 @@ where
         rx_yield.await.unwrap()
     }
 
+    /// Start listening via one of the known dialing relays, chosen according to the [`RelaySelection`] that was
+    /// configured with [`NetworkBuilder::with_relay_selection`].
+    ///
+    /// Relays can be added with [`Network::add_dialing_relay`]. Fails with [`DialErr::NoAddresses`] if no address is
+    /// known for any of the relays.
+    pub async fn start_selected_relayed_listening(&mut self) -> Result<Multiaddr, ListenRelayErr> {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::StartSelectedRelayedListening { return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Known dialing relays, ranked by their health.
+    ///
+    /// Relays with fewer consecutive failures and a lower round-trip time are ranked first.
+    pub async fn relay_candidates(&mut self) -> Vec<RelayCandidate> {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::GetRelayCandidates { return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Get the current restrictions on the usage of relays.
+    pub async fn relay_policy(&mut self) -> RelayPolicy {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::GetRelayPolicy { return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Set the restrictions on which relays are used for listening and dialing, and which peers may relay through
+    /// the local peer.
+    ///
+    /// Already established relayed connections and listeners are not closed.
+    pub async fn set_relay_policy(&mut self, policy: RelayPolicy) {
+        let command = SwarmCommand::SetRelayPolicy { policy };
+        self.send_command(command).await;
+    }
+
+    /// Status of the reservations on relays via which the local peer is listening.
+    ///
+    /// **Note**: The relay v1 protocol has no expiry for reservations. A reservation is held as long as the
+    /// connection to the relay is kept alive.
+    pub async fn relay_reservations(&mut self) -> Vec<RelayReservation> {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::GetRelayReservations { return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
     //// Currently active listeners.
     pub async fn listeners(&mut self) -> Vec<Listener> {
         let (return_tx, rx_yield) = oneshot::channel();
@@ -299,6 +734,111 @@ where
         rx_yield.await.unwrap()
     }
 
+    /// Set the rule for inbound requests of a priority class, e.g. to only accept [`RequestPriority::High`]
+    /// requests from trusted peers. For requests of the class, it takes precedence over the peer specific and
+    /// default rules.
+    pub async fn set_priority_rule(&mut self, priority: RequestPriority, rule: Rule<TRq>) {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::SetPriorityRule {
+            priority,
+            rule,
+            return_tx,
+        };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Remove the rule for a priority class, which will result in using the peer specific or default rules.
+    pub async fn remove_priority_rule(&mut self, priority: RequestPriority) {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::RemovePriorityRule { priority, return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Set the rule for peers with the tag, e.g. to allow all requests from peers that are tagged as "validator".
+    /// It takes precedence over the default rule, but not over peer specific rules. If a peer has multiple tags with
+    /// a rule, the rule of the first tag in alphabetical order applies.
+    ///
+    /// See [`Network::tag_peer`].
+    pub async fn set_tag_rule(&mut self, tag: String, rule: Rule<TRq>) {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::SetTagRule { tag, rule, return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Remove the rule for peers with the tag, which will result in using the rules of their other tags or the
+    /// default rule.
+    pub async fn remove_tag_rule(&mut self, tag: String) {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::RemoveTagRule { tag, return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Get the inbound requests that currently wait for a response to a [`FirewallRequest::PeerSpecificRule`] or
+    /// [`FirewallRequest::RequestApproval`] that was sent through the firewall channel.
+    ///
+    /// This can be used to render the pending approvals independently of the firewall channel, e.g. after a
+    /// firewall request was missed.
+    pub async fn awaiting_firewall(&mut self) -> AwaitingFirewall {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::GetAwaitingFirewall { return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Give up on all requests of the peer that are still pending in the network: outbound requests that wait for a
+    /// connection to the peer or are held back for ordered delivery fail with [`OutboundFailure::Aborted`], inbound
+    /// requests that wait for a firewall rule or approval fail with [`InboundFailure::Aborted`].
+    ///
+    /// Requests that were already sent to the peer or forwarded to the application are not affected. Returns the ids
+    /// of the aborted requests.
+    pub async fn abort_pending(&mut self, peer: PeerId) -> Vec<RequestId> {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::AbortPending { peer, return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Get the number of inbound requests that were received, but await a response to a
+    /// [`FirewallRequest::PeerSpecificRule`] or [`FirewallRequest::RequestApproval`] before they are forwarded to the
+    /// application.
+    ///
+    /// See [`NetworkBuilder::with_inbound_watermarks`] for events if the number crosses a threshold.
+    pub async fn inbound_queue_depth(&mut self) -> usize {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::GetInboundQueueDepth { return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Approve or reject all inbound requests from a peer that currently await a response to a
+    /// [`FirewallRequest::RequestApproval`], instead of answering each of them individually.
+    /// Only inbound requests are subject to the firewall, outbound requests are not affected.
+    ///
+    /// Optionally a peer specific `rule` is set before the decision is applied. Requests that awaited a
+    /// [`FirewallRequest::PeerSpecificRule`] are then handled according to the new rule, and future requests from the
+    /// peer don't require individual approval anymore unless the rule is [`Rule::Ask`].
+    /// Returns the ids of the requests to which the decision was applied.
+    pub async fn resolve_pending_approvals(
+        &mut self,
+        peer: PeerId,
+        is_allowed: bool,
+        rule: Option<Rule<TRq>>,
+    ) -> Vec<RequestId> {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::ResolvePendingApprovals {
+            peer,
+            is_allowed,
+            rule,
+            return_tx,
+        };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
     /// Get the known addresses for a remote peer.
     pub async fn get_addrs(&mut self, peer: PeerId) -> Vec<Multiaddr> {
         let (return_tx, rx_yield) = oneshot::channel();
@@ -331,54 +871,134 @@ where
         rx_yield.await.unwrap()
     }
 
-    /// Export address info of remote peers and relays.
-    pub async fn export_address_info(&mut self) -> AddressInfo {
+    /// Attach a tag to the remote peer, e.g. its role like "validator" or "backup".
+    ///
+    /// Tags are kept in the peer store together with the known addresses, and are included in the exported
+    /// [`AddressInfo`]. They can be used to select peers for firewall rules, broadcasts and connection priorities.
+    pub async fn tag_peer(&mut self, peer: PeerId, tag: String) {
         let (return_tx, rx_yield) = oneshot::channel();
-        let command = SwarmCommand::ExportAddressInfo { return_tx };
+        let command = SwarmCommand::TagPeer { peer, tag, return_tx };
         self.send_command(command).await;
         rx_yield.await.unwrap()
     }
 
-    /// Add a relay to the list of relays that may be tried to use if a remote peer can not be reached directly.
-    pub async fn add_dialing_relay(
-        &mut self,
-        peer: PeerId,
-        address: Option<Multiaddr>,
-    ) -> Result<Option<Multiaddr>, RelayNotSupported> {
+    /// Remove a tag from the remote peer.
+    pub async fn untag_peer(&mut self, peer: PeerId, tag: String) {
         let (return_tx, rx_yield) = oneshot::channel();
-        let command = SwarmCommand::AddDialingRelay {
-            peer,
-            address,
-            return_tx,
-        };
+        let command = SwarmCommand::UntagPeer { peer, tag, return_tx };
         self.send_command(command).await;
         rx_yield.await.unwrap()
     }
 
-    /// Remove a relay from the list of dialing relays.
-    /// Returns `false` if the peer was not among the known relays.
-    ///
-    /// **Note**: Known relayed addresses for remote peers using this relay will not be influenced by this.
-    pub async fn remove_dialing_relay(&mut self, peer: PeerId) -> bool {
+    /// Get the tags of the remote peer in alphabetical order.
+    pub async fn peer_tags(&mut self, peer: PeerId) -> Vec<String> {
         let (return_tx, rx_yield) = oneshot::channel();
-        let command = SwarmCommand::RemoveDialingRelay { peer, return_tx };
+        let command = SwarmCommand::GetPeerTags { peer, return_tx };
         self.send_command(command).await;
         rx_yield.await.unwrap()
     }
 
-    /// Configure whether it should be attempted to reach the remote via known relays, if it can not be reached via
-    /// known addresses.
-    pub async fn set_relay_fallback(
-        &mut self,
-        peer: PeerId,
-        use_relay_fallback: bool,
-    ) -> Result<(), RelayNotSupported> {
+    /// Get all peers that have the tag.
+    pub async fn peers_with_tag(&mut self, tag: String) -> Vec<PeerId> {
         let (return_tx, rx_yield) = oneshot::channel();
-        let command = SwarmCommand::SetRelayFallback {
-            peer,
-            use_relay_fallback,
-            return_tx,
-        };
+        let command = SwarmCommand::GetTaggedPeers { tag, return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Export address info of remote peers and relays.
+    pub async fn export_address_info(&mut self) -> AddressInfo {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::ExportAddressInfo { return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Announce to all connected peers that the local peer rotated its identity to `new_peer`, which can be reached at
+    /// the `addrs`. The announcement is sent on the existing connections, which authenticate the local peer, and is
+    /// reported by the remote with [`NetworkEvent::PeerIdentityRotated`].
+    ///
+    /// Peers whose firewall rule rejects all requests are skipped. Returns the peers that received the announcement,
+    /// once it was sent to all of them.
+    pub async fn announce_identity_rotation(&mut self, new_peer: PeerId, addrs: Vec<Multiaddr>) -> Vec<PeerId> {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::AnnounceRotation {
+            new_peer,
+            addrs,
+            return_tx,
+        };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Hand over to the network `new`, that was built with the new identity keys:
+    /// 1. The known addresses of all connected peers are added to `new`, and it connects to each of them, so that the
+    ///    connections are re-established under the new `PeerId`.
+    /// 2. If `announce` is true, the rotation is announced to the connected peers with the addresses of `new`, see
+    ///    [`Network::announce_identity_rotation`].
+    /// 3. This `Network` is dropped.
+    ///
+    /// Returns the peers to which `new` connected. Peers to which no address is known, e.g. because they only dialed
+    /// the local peer, can still connect to the new identity with the announced addresses.
+    ///
+    /// **Note**: The event loop of the old network only shuts down once all clones of it were dropped.
+    pub async fn rotate_identity(mut self, new: &mut Network<Rq, Rs, TRq>, announce: bool) -> Vec<PeerId> {
+        let mut reconnected = Vec::new();
+        for (peer, _) in self.established_connections().await {
+            for address in self.get_addrs(peer).await {
+                new.add_address(peer, address).await;
+            }
+            if new.connect_peer(peer).await.is_ok() {
+                reconnected.push(peer);
+            }
+        }
+        if announce {
+            let addrs = new.self_addrs().await;
+            self.announce_identity_rotation(new.peer_id(), addrs).await;
+        }
+        reconnected
+    }
+
+    /// Add a relay to the list of relays that may be tried to use if a remote peer can not be reached directly.
+    pub async fn add_dialing_relay(
+        &mut self,
+        peer: PeerId,
+        address: Option<Multiaddr>,
+    ) -> Result<Option<Multiaddr>, RelayNotSupported> {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::AddDialingRelay {
+            peer,
+            address,
+            return_tx,
+        };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Remove a relay from the list of dialing relays.
+    /// Returns `false` if the peer was not among the known relays.
+    ///
+    /// **Note**: Known relayed addresses for remote peers using this relay will not be influenced by this.
+    pub async fn remove_dialing_relay(&mut self, peer: PeerId) -> bool {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::RemoveDialingRelay { peer, return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Configure whether it should be attempted to reach the remote via known relays, if it can not be reached via
+    /// known addresses.
+    pub async fn set_relay_fallback(
+        &mut self,
+        peer: PeerId,
+        use_relay_fallback: bool,
+    ) -> Result<(), RelayNotSupported> {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::SetRelayFallback {
+            peer,
+            use_relay_fallback,
+            return_tx,
+        };
         self.send_command(command).await;
         rx_yield.await.unwrap()
     }
@@ -408,7 +1028,9 @@ where
 
     /// Bans a peer by its peer ID.
     ///
-    /// Any incoming connection and any dialing attempt will immediately be rejected.
+    /// Any incoming connection and any dialing attempt will immediately be rejected, and the known addresses of the
+    /// peer are removed. Connections are rejected right after the security handshake, before any substream is opened.
+    /// Contrary to a firewall rule that rejects requests, a banned peer can not keep connections open.
     /// This function has no effect if the peer is already banned.
     pub async fn ban_peer(&mut self, peer: PeerId) {
         let (return_tx, rx_yield) = oneshot::channel();
@@ -417,6 +1039,18 @@ where
         rx_yield.await.unwrap()
     }
 
+    /// Close all connections to the peer.
+    ///
+    /// Pending requests on the connections fail with [`OutboundFailure::ConnectionClosed`] and
+    /// [`InboundFailure::ConnectionClosed`] with [`CloseReason::Disconnect`]. The peer is not prevented from
+    /// reconnecting, use [`Network::ban_peer`] for that.
+    pub async fn disconnect_peer(&mut self, peer: PeerId) {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::DisconnectPeer { peer, return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
     /// Unbans a peer.
     pub async fn unban_peer(&mut self, peer: PeerId) {
         let (return_tx, rx_yield) = oneshot::channel();
@@ -425,6 +1059,22 @@ where
         rx_yield.await.unwrap()
     }
 
+    /// Check whether a peer is currently banned.
+    pub async fn is_banned(&mut self, peer: PeerId) -> bool {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::GetIsBanned { peer, return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Get the list of currently banned peers.
+    pub async fn get_banned_peers(&mut self) -> Vec<PeerId> {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::GetBannedPeers { return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
     /// Check whether the Network has an established connection to a peer.
     pub async fn is_connected(&mut self, peer: PeerId) -> bool {
         let (return_tx, rx_yield) = oneshot::channel();
@@ -440,6 +1090,214 @@ where
         self.send_command(command).await;
         rx_yield.await.unwrap()
     }
+
+    /// Change the limits for established connections at runtime, without rebuilding the swarm.
+    ///
+    /// New connections that exceed the limits are closed right after they were established. Established connections
+    /// that exceed the new limits are handled according to the `policy`.
+    ///
+    /// The limits that were set with [`NetworkBuilder::with_connections_limit`] are enforced by the swarm before a
+    /// connection is established, and still apply. Hence the limits can only be lowered below them at runtime, and
+    /// the limits for pending connections are not changed.
+    pub async fn set_connection_limits(&mut self, limits: ConnectionLimits, policy: ExcessConnectionPolicy) {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::SetConnectionLimits {
+            limits,
+            policy,
+            return_tx,
+        };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Set the priority of a peer for keeping its connections if a new connection exceeds the limits for established
+    /// connections. Peers without priority have the priority `0`.
+    ///
+    /// Only applies if [`LimitPressurePolicy::EvictLowestPriority`] was set with
+    /// [`NetworkBuilder::with_limit_pressure_policy`].
+    pub async fn set_peer_priority(&mut self, peer: PeerId, priority: u8) {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::SetPeerPriority {
+            peer,
+            priority,
+            return_tx,
+        };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Remove the priority of a peer, which will result in the priority `0`.
+    pub async fn remove_peer_priority(&mut self, peer: PeerId) {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::RemovePeerPriority { peer, return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Set the priority of all peers with the tag for keeping their connections if the limits for established
+    /// connections are exceeded. A peer has the highest of its own priority and the priorities of its tags.
+    ///
+    /// See [`Network::set_peer_priority`].
+    pub async fn set_tag_priority(&mut self, tag: String, priority: u8) {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::SetTagPriority {
+            tag,
+            priority,
+            return_tx,
+        };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Remove the priority of peers with the tag.
+    pub async fn remove_tag_priority(&mut self, tag: String) {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::RemoveTagPriority { tag, return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Add a peer to which connections are re-established when the local listening addresses changed.
+    ///
+    /// See [`NetworkBuilder::with_persistent_peers`].
+    pub async fn add_persistent_peer(&mut self, peer: PeerId) {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::AddPersistentPeer { peer, return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Remove a persistent peer. Established connections to it are not closed.
+    pub async fn remove_persistent_peer(&mut self, peer: PeerId) {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::RemovePersistentPeer { peer, return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Get the libp2p-level view on the [`Swarm`][libp2p::Swarm], i.e. its connection counters, addresses and
+    /// supported protocols.
+    pub async fn network_info(&mut self) -> NetworkInfo {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::GetNetworkInfo { return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Get the dialable addresses of the local peer, e.g. for handing them to other peers out-of-band.
+    ///
+    /// These are the listening and external addresses with the local peer id appended, and the relayed addresses of
+    /// relay listeners in the form `<relay-addr>/p2p/<relay-id>/p2p-circuit/p2p/<local-id>`, see
+    /// [`dialable_addr`][crate::dialable_addr].
+    pub async fn self_addrs(&mut self) -> Vec<Multiaddr> {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::GetSelfAddrs { return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Get a snapshot of the internal state, e.g. for debugging requests that appear to be stuck.
+    pub async fn dump_state(&mut self) -> StateDump {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::DumpState { return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Check whether the remote peer is reachable and return the round-trip time of a ping.
+    ///
+    /// A connection to the peer is established first if there is none yet. If the peer was already pinged on the
+    /// current connections, the sample of the latest successful ping is returned, and [`RttSample::measured_at`] tells
+    /// when it was measured. Otherwise the next ping is awaited.
+    ///
+    /// Fails with [`ProbeErr::ProtocolNotSupported`] if the ping protocol is not enabled, see
+    /// [`NetworkBuilder::with_ping_support`].
+    pub async fn probe(&mut self, peer: PeerId) -> Result<RttSample, ProbeErr> {
+        if !self.is_connected(peer).await {
+            self.connect_peer(peer).await?;
+        }
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::Probe { peer, return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Get the aggregated request statistics over all peers, and the number of active connections.
+    pub async fn stats(&mut self) -> NetworkStats {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::GetStats { return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Get the traffic statistics for all peers with which messages were exchanged.
+    /// The statistics of the peers that disconnected first are removed once too many peers are disconnected.
+    pub async fn peer_stats(&mut self) -> HashMap<PeerId, PeerStats> {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::GetPeerStats { return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Get the usage statistics for each relay via which the local peer connected to other peers, or was connected by
+    /// them.
+    pub async fn relay_stats(&mut self) -> HashMap<PeerId, RelayStats> {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::GetRelayStats { return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Set the status that is sent to connected peers in heartbeats. The new status is sent to them immediately.
+    ///
+    /// Does nothing if presence was not enabled with [`NetworkBuilder::with_presence`].
+    pub async fn set_presence_status(&mut self, status: Vec<u8>) {
+        let command = SwarmCommand::SetPresenceStatus { status };
+        self.send_command(command).await;
+    }
+
+    /// Get the peers that are online, with the latest status that they sent in their heartbeats.
+    pub async fn online_peers(&mut self) -> HashMap<PeerId, Vec<u8>> {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::GetOnlinePeers { return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Get the hello that the peer sent after the connection was established.
+    ///
+    /// `None` if the peer is not connected, did not send a hello (yet), or the hello was not permitted by the firewall.
+    pub async fn peer_hello(&mut self, peer: PeerId) -> Option<Hello> {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::GetPeerHello { peer, return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Order the candidates by their health, e.g. to select the peer to which a request should be sent when the
+    /// same request could be served by any of them.
+    ///
+    /// Peers are ranked by the share of outbound requests that received a response, and then by the latest ping
+    /// round-trip time. Peers without any completed request or measured round-trip time are treated optimistically
+    /// respectively ranked last, so that new peers are still selected.
+    pub async fn rank_peers(&mut self, candidates: Vec<PeerId>) -> Vec<PeerId> {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::RankPeers { candidates, return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
+    /// Get the most recent internal actions and swarm events, ordered from oldest to newest.
+    ///
+    /// The number of kept events is configured with [`NetworkBuilder::with_event_log_size`]. Per default no events are
+    /// recorded.
+    pub async fn recent_events(&mut self) -> Vec<RecentEvent> {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = SwarmCommand::GetRecentEvents { return_tx };
+        self.send_command(command).await;
+        rx_yield.await.unwrap()
+    }
+
     async fn send_command(&mut self, command: SwarmCommand<Rq, Rs, TRq>) {
         let _ = poll_fn(|cx| self.command_tx.poll_ready(cx)).await;
         let _ = self.command_tx.start_send(command);
@@ -449,6 +1307,8 @@ where
 /// Use existing keypair for authentication on the transport layer.
 ///
 /// The local [`PeerId`] is derived from public key of the IdKeys.
+/// Apart from ed25519 keys, secp256k1 (requires feature **secp256k1**) and RSA identity keys are supported, so that
+/// the [`PeerId`] can match an identity that is used elsewhere.
 pub enum InitKeypair {
     /// Identity Keys that are used to derive the noise keypair and peer id.
     IdKeys(Keypair),
@@ -464,6 +1324,128 @@ pub enum InitKeypair {
     },
 }
 
+impl InitKeypair {
+    /// Generate new ed25519 identity keys.
+    pub fn generate_ed25519() -> Self {
+        InitKeypair::IdKeys(Keypair::generate_ed25519())
+    }
+
+    /// Generate new secp256k1 identity keys.
+    #[cfg(feature = "secp256k1")]
+    pub fn generate_secp256k1() -> Self {
+        InitKeypair::IdKeys(Keypair::generate_secp256k1())
+    }
+
+    /// Import ed25519 identity keys from the 64 bytes of the secret and public key.
+    ///
+    /// The input is zeroed after the import.
+    pub fn ed25519_from_bytes(keypair: &mut [u8]) -> Result<Self, DecodingError> {
+        let keypair = identity::ed25519::Keypair::decode(keypair)?;
+        Ok(InitKeypair::IdKeys(Keypair::Ed25519(keypair)))
+    }
+
+    /// Import secp256k1 identity keys from the raw 32 bytes of the secret key.
+    ///
+    /// The input is zeroed after the import.
+    #[cfg(feature = "secp256k1")]
+    pub fn secp256k1_from_bytes(secret: &mut [u8]) -> Result<Self, DecodingError> {
+        let secret = identity::secp256k1::SecretKey::from_bytes(secret)?;
+        Ok(InitKeypair::IdKeys(Keypair::Secp256k1(secret.into())))
+    }
+
+    /// Import secp256k1 identity keys from a DER-encoded secret key.
+    ///
+    /// The input is zeroed after the import.
+    #[cfg(feature = "secp256k1")]
+    pub fn secp256k1_from_der(der: &mut [u8]) -> Result<Self, DecodingError> {
+        Keypair::secp256k1_from_der(der).map(InitKeypair::IdKeys)
+    }
+
+    /// Import identity keys from the libp2p protobuf encoding, as created by [`InitKeypair::to_protobuf_encoding`].
+    pub fn from_protobuf_encoding(bytes: &[u8]) -> Result<Self, KeyErr> {
+        let keypair = Keypair::from_protobuf_encoding(bytes)?;
+        Ok(InitKeypair::IdKeys(keypair))
+    }
+
+    /// Encode the identity keys in the libp2p protobuf format.
+    ///
+    /// Fails with [`KeyErr::NoIdentityKeys`] for [`InitKeypair::Authenticated`], and with [`KeyErr::Decoding`] if the
+    /// key type does not support the encoding.
+    pub fn to_protobuf_encoding(&self) -> Result<Vec<u8>, KeyErr> {
+        match self {
+            InitKeypair::IdKeys(keypair) => Ok(keypair.to_protobuf_encoding()?),
+            InitKeypair::Authenticated { .. } => Err(KeyErr::NoIdentityKeys),
+        }
+    }
+
+    /// Import identity keys from a PEM document, as created by [`InitKeypair::to_pem`].
+    pub fn from_pem(pem: &str) -> Result<Self, KeyErr> {
+        let body = pem
+            .trim()
+            .strip_prefix(PEM_BEGIN)
+            .and_then(|s| s.strip_suffix(PEM_END))
+            .ok_or(KeyErr::InvalidPem)?;
+        let body: String = body.split_whitespace().collect();
+        let bytes = base64::decode(body).map_err(|_| KeyErr::InvalidPem)?;
+        Self::from_protobuf_encoding(&bytes)
+    }
+
+    /// Encode the identity keys as PEM document, that contains the libp2p protobuf encoding of the keys.
+    pub fn to_pem(&self) -> Result<String, KeyErr> {
+        let encoded = base64::encode(self.to_protobuf_encoding()?);
+        let mut pem = String::from(PEM_BEGIN);
+        pem.push('\n');
+        for line in encoded.as_bytes().chunks(64) {
+            // Base64 only contains ASCII characters.
+            pem.push_str(std::str::from_utf8(line).expect("Valid base64."));
+            pem.push('\n');
+        }
+        pem.push_str(PEM_END);
+        pem.push('\n');
+        Ok(pem)
+    }
+
+    /// Load the identity keys from a PEM file at `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, KeyErr> {
+        let pem = fs::read_to_string(path)?;
+        Self::from_pem(&pem)
+    }
+
+    /// Write the identity keys as PEM file to `path`.
+    ///
+    /// **Note**: The keys are stored unencrypted.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), KeyErr> {
+        fs::write(path, self.to_pem()?)?;
+        Ok(())
+    }
+
+    /// Load the identity keys from the PEM file at `path`, or generate new ed25519 keys and write them to the file if
+    /// it does not exist yet.
+    ///
+    /// This allows a node to keep a stable [`PeerId`] across restarts.
+    pub fn load_or_generate(path: impl AsRef<Path>) -> Result<Self, KeyErr> {
+        let path = path.as_ref();
+        if path.exists() {
+            return Self::load(path);
+        }
+        let keys = Self::generate_ed25519();
+        keys.save(path)?;
+        Ok(keys)
+    }
+
+    /// Import RSA identity keys from a PKCS#8 DER-encoded private key.
+    /// Generating new RSA keys is not supported.
+    ///
+    /// The input is zeroed after the import.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn rsa_from_pkcs8(der: &mut [u8]) -> Result<Self, DecodingError> {
+        Keypair::rsa_from_pkcs8(der).map(InitKeypair::IdKeys)
+    }
+}
+
+const PEM_BEGIN: &str = "-----BEGIN LIBP2P PRIVATE KEY-----";
+const PEM_END: &str = "-----END LIBP2P PRIVATE KEY-----";
+
 /// Builder for new `Network`.
 ///
 /// Default behaviour:
@@ -481,37 +1463,122 @@ pub enum InitKeypair {
 /// When building a new `Network` a new [`Swarm`][libp2p::Swarm] is created and continuously polled for events.
 /// Inbound requests are forwarded through a `mpsc::channel<ReceiveRequest<Rq, Rs>>`    .
 /// Optionally all events regarding connections and listeners are forwarded as [`NetworkEvent`].
-pub struct NetworkBuilder<Rq, Rs, TRq = Rq>
+///
+/// An additional custom [`NetworkBehaviour`][libp2p::swarm::NetworkBehaviour] can be composed into the swarm with
+/// [`NetworkBuilder::with_custom_behaviour`].
+pub struct NetworkBuilder<Rq, Rs, TRq = Rq, TCustom = DummyBehaviour>
 where
     Rq: RqRsMessage,
     Rs: RqRsMessage,
     TRq: FwRequest<Rq>,
+    TCustom: Libp2pNetworkBehaviour,
 {
     firewall_channel: mpsc::Sender<FirewallRequest<TRq>>,
     requests_channel: EventChannel<ReceiveRequest<Rq, Rs>>,
     events_channel: Option<EventChannel<NetworkEvent>>,
 
+    // Optional channel for forwarding a mirror of all raw swarm events.
+    swarm_debug_channel: Option<EventChannel<SwarmDebugEvent>>,
+    // Callback that is invoked on each failure.
+    error_hook: Option<ErrorHook>,
+    // Optional channel for forwarding received blobs.
+    blob_channel: Option<EventChannel<ReceivedBlob<Rq>>>,
+    // Optional channel for forwarding the responses to queued requests.
+    queued_response_channel: Option<EventChannel<QueuedResponse<Rs>>>,
+    // Configuration and storage of the outbound queue.
+    outbound_queue: Option<(OutboundQueueConfig, Box<dyn QueueStore>)>,
+    // Configuration and storage of the block exchange.
+    block_exchange: Option<(BlockExchangeConfig, Box<dyn BlockStore>)>,
+    // Write-ahead journal of the inbound requests.
+    request_journal: Option<Box<dyn RequestJournal>>,
+
+    // Reject inbound requests while the requests channel is full.
+    inbound_backpressure: bool,
+
+    // Delay for renewing lost relay reservations.
+    relay_renewal: Option<Duration>,
+    // Candidates that are added as dialing relays.
+    relay_candidates: Vec<(PeerId, Multiaddr)>,
+    // Peers that are dialed once the network is built.
+    bootstrap_peers: Vec<(PeerId, Multiaddr)>,
+    // Peers that are re-dialed when the local addresses changed.
+    persistent_peers: HashSet<PeerId>,
+    // Interval for probing the dialing relays.
+    relay_probe_interval: Option<Duration>,
+    // Number of addresses of a peer that are dialed concurrently.
+    dial_concurrency_factor: Option<NonZeroU8>,
+    // Delay between starting the concurrent dials to the addresses of a peer.
+    dial_stagger: Duration,
+    // Timeout for establishing a connection, including the security and muxer upgrade.
+    handshake_timeout: Option<Duration>,
+
     // Use an existing keypair instead of creating a new one.
     ident: Option<(AuthenticKeypair<X25519Spec>, PeerId)>,
 
+    // Identity keys, if known, for creating new noise keypairs.
+    id_keys: Option<Keypair>,
+    // Policy for reusing the noise keypair.
+    noise_key_rotation: NoiseKeyRotation,
+    // Predicate for the remote peer id of new connections.
+    expected_peer: Option<ExpectedPeer>,
+
     // Configuration of the underlying `NetworkBehaviour`.
     behaviour_config: ConfigConfig,
 
     // Limit of simultaneous connections.
     connections_limit: Option<ConnectionLimits>,
+    // Capacities of the internal channels.
+    channel_capacities: ChannelCapacities,
 
     // List of known addresses that were persisted from a former running instance.
     address_info: Option<AddressInfo>,
 
     // Firewall rules.
     firewall_rules: FirewallRules<TRq>,
+    // Config file with firewall rules that is reloaded on change, and the interval for checking it.
+    firewall_config_file: Option<(PathBuf, Duration)>,
+    // Verifier for capability tokens of inbound requests.
+    capability_verifier: Option<Arc<dyn CapabilityVerifier<TRq>>>,
+    // Custom generator for request ids.
+    request_id_generator: Option<Arc<dyn RequestIdGenerator>>,
+    // Middleware for outbound and inbound requests, in the order in which it was registered.
+    middleware: Vec<Arc<dyn RequestMiddleware<Rq>>>,
+    // Hooks for responses to outbound requests, in the order in which they were registered.
+    response_hooks: Vec<Arc<dyn ResponseHook<Rs>>>,
+    // Converters for messages of earlier protocol versions.
+    message_migrations: Option<MessageMigrations<Rq, Rs>>,
+    // Codec for requests and responses, instead of JSON.
+    codec: Option<Arc<dyn Codec<Rq, Rs>>>,
 
     // Use Mdns protocol for peer discovery in the local network.
     //
     // Note: This also broadcasts our own address and id to the local network.
+    #[cfg_attr(not(feature = "mdns"), allow(dead_code))]
     support_mdns: bool,
 
+    #[cfg_attr(not(feature = "relay"), allow(dead_code))]
     support_relay: bool,
+
+    // Use the Ping protocol to check the liveness of connections and measure the round-trip time.
+    support_ping: bool,
+
+    // Use the Identify protocol to learn the addresses under which remote peers observe the local peer.
+    support_identify: bool,
+
+    // Additional behaviour of the application, with the channel for forwarding its events.
+    custom_behaviour: Option<(TCustom, EventChannel<TCustom::OutEvent>)>,
+
+    // Recorder for metrics of the swarm and the enabled protocols.
+    #[cfg(feature = "metrics")]
+    metrics: Option<NetworkMetrics>,
+    // Exporter of request spans and metrics to OpenTelemetry.
+    #[cfg(feature = "otel")]
+    telemetry: Option<Telemetry>,
+    // Local addresses to which outgoing connections of the default transport are bound.
+    #[cfg(feature = "tcp-transport")]
+    outbound_binding: Option<OutboundBinding>,
+    // Routing of inbound connections if the network is a shard of a `ShardedNetwork`.
+    shard_routing: Option<ShardRouting>,
 }
 
 impl<Rq, Rs, TRq> NetworkBuilder<Rq, Rs, TRq>
@@ -535,33 +1602,299 @@ where
             firewall_channel,
             requests_channel,
             events_channel,
+            swarm_debug_channel: None,
+            error_hook: None,
+            blob_channel: None,
+            queued_response_channel: None,
+            outbound_queue: None,
+            block_exchange: None,
+            request_journal: None,
+            inbound_backpressure: false,
+            relay_renewal: Some(Duration::from_secs(10)),
+            relay_candidates: Vec::new(),
+            bootstrap_peers: Vec::new(),
+            persistent_peers: HashSet::new(),
+            relay_probe_interval: None,
+            dial_concurrency_factor: None,
+            dial_stagger: Duration::ZERO,
+            handshake_timeout: None,
             ident: None,
+            id_keys: None,
+            noise_key_rotation: NoiseKeyRotation::default(),
+            expected_peer: None,
             behaviour_config: Default::default(),
             connections_limit: None,
+            channel_capacities: ChannelCapacities::default(),
             firewall_rules,
+            firewall_config_file: None,
+            capability_verifier: None,
+            request_id_generator: None,
+            middleware: Vec::new(),
+            response_hooks: Vec::new(),
+            message_migrations: None,
+            codec: None,
             support_mdns: true,
             support_relay: true,
+            support_ping: false,
+            support_identify: false,
             address_info: None,
+            custom_behaviour: None,
+            #[cfg(feature = "metrics")]
+            metrics: None,
+            #[cfg(feature = "otel")]
+            telemetry: None,
+            #[cfg(feature = "tcp-transport")]
+            outbound_binding: None,
+            shard_routing: None,
         }
     }
 
-    /// Set the keypair that is used for authenticating the communication on the transport layer.
-    /// The local [`PeerId`] is derived from the keypair.
-    pub fn with_keys(mut self, keys: InitKeypair) -> Self {
-        let (keypair, id) = match keys {
-            InitKeypair::IdKeys(keypair) => {
-                let noise_keypair = NoiseKeypair::<X25519Spec>::new().into_authentic(&keypair).unwrap();
-                let id = keypair.public().to_peer_id();
-                (noise_keypair, id)
-            }
-            InitKeypair::Authenticated { peer_id, noise_keypair } => (noise_keypair, peer_id),
-        };
-        self.ident = Some((keypair, id));
-        self
+    /// Create a new builder like [`NetworkBuilder::new`], with the settings and firewall default rule of the
+    /// `config`, e.g. loaded from a config file.
+    ///
+    /// Settings that are not part of the config, e.g. the keys or custom firewall rules, can be set with the other
+    /// methods of the builder afterwards.
+    pub fn from_config(
+        config: &NetworkConfig,
+        firewall_channel: mpsc::Sender<FirewallRequest<TRq>>,
+        requests_channel: EventChannel<ReceiveRequest<Rq, Rs>>,
+        events_channel: Option<EventChannel<NetworkEvent>>,
+    ) -> Self {
+        let mut builder = Self::new(
+            firewall_channel,
+            requests_channel,
+            events_channel,
+            config.firewall_rules(),
+        )
+        .with_mdns_support(config.support_mdns)
+        .with_relay_support(config.support_relay)
+        .with_ping_support(config.support_ping)
+        .with_relay_candidates(config.relays.clone())
+        .with_bootstrap_peers(config.bootstrap_peers.clone());
+        if let Some(t) = config.request_timeout {
+            builder = builder.with_request_timeout(t);
+        }
+        if let Some(t) = config.connection_timeout {
+            builder = builder.with_connection_timeout(t);
+        }
+        if let Some(t) = config.firewall_timeout {
+            builder = builder.with_firewall_timeout(t);
+        }
+        if let Some(limit) = config.connections_limit.clone() {
+            builder = builder.with_connections_limit(limit);
+        }
+        builder
+    }
+}
+
+impl<Rq, Rs, TRq, TCustom> NetworkBuilder<Rq, Rs, TRq, TCustom>
+where
+    Rq: RqRsMessage,
+    Rs: RqRsMessage,
+    TRq: FwRequest<Rq>,
+    TCustom: Libp2pNetworkBehaviour + Send,
+    TCustom::OutEvent: fmt::Debug,
+{
+    /// Compose an additional custom [`NetworkBehaviour`][libp2p::swarm::NetworkBehaviour] into the swarm, e.g. to
+    /// integrate a protocol that is not shipped with this crate. The behaviour shares the connections and listeners
+    /// of the swarm, and the events that it emits are forwarded through the `events_channel`.
+    ///
+    /// The behaviour is owned by the event loop, hence interaction with it after the network was built has to happen
+    /// through channels that are integrated into the behaviour itself.
+    pub fn with_custom_behaviour<B>(
+        self,
+        behaviour: B,
+        events_channel: EventChannel<B::OutEvent>,
+    ) -> NetworkBuilder<Rq, Rs, TRq, B>
+    where
+        B: Libp2pNetworkBehaviour + Send,
+        B::OutEvent: fmt::Debug,
+    {
+        NetworkBuilder {
+            firewall_channel: self.firewall_channel,
+            requests_channel: self.requests_channel,
+            events_channel: self.events_channel,
+            swarm_debug_channel: self.swarm_debug_channel,
+            error_hook: self.error_hook,
+            blob_channel: self.blob_channel,
+            queued_response_channel: self.queued_response_channel,
+            outbound_queue: self.outbound_queue,
+            block_exchange: self.block_exchange,
+            request_journal: self.request_journal,
+            inbound_backpressure: self.inbound_backpressure,
+            relay_renewal: self.relay_renewal,
+            relay_candidates: self.relay_candidates,
+            bootstrap_peers: self.bootstrap_peers,
+            persistent_peers: self.persistent_peers,
+            relay_probe_interval: self.relay_probe_interval,
+            dial_concurrency_factor: self.dial_concurrency_factor,
+            dial_stagger: self.dial_stagger,
+            handshake_timeout: self.handshake_timeout,
+            ident: self.ident,
+            id_keys: self.id_keys,
+            noise_key_rotation: self.noise_key_rotation,
+            expected_peer: self.expected_peer,
+            behaviour_config: self.behaviour_config,
+            connections_limit: self.connections_limit,
+            channel_capacities: self.channel_capacities,
+            address_info: self.address_info,
+            firewall_rules: self.firewall_rules,
+            firewall_config_file: self.firewall_config_file,
+            capability_verifier: self.capability_verifier,
+            request_id_generator: self.request_id_generator,
+            middleware: self.middleware,
+            response_hooks: self.response_hooks,
+            message_migrations: self.message_migrations,
+            codec: self.codec,
+            support_mdns: self.support_mdns,
+            support_relay: self.support_relay,
+            support_ping: self.support_ping,
+            support_identify: self.support_identify,
+            custom_behaviour: Some((behaviour, events_channel)),
+            #[cfg(feature = "metrics")]
+            metrics: self.metrics,
+            #[cfg(feature = "otel")]
+            telemetry: self.telemetry,
+            #[cfg(feature = "tcp-transport")]
+            outbound_binding: self.outbound_binding,
+            shard_routing: self.shard_routing,
+        }
+    }
+
+    /// Set the keypair that is used for authenticating the communication on the transport layer.
+    /// The local [`PeerId`] is derived from the keypair.
+    pub fn with_keys(mut self, keys: InitKeypair) -> Self {
+        let (keypair, id) = match keys {
+            InitKeypair::IdKeys(keypair) => {
+                let noise_keypair = NoiseKeypair::<X25519Spec>::new().into_authentic(&keypair).unwrap();
+                let id = keypair.public().to_peer_id();
+                self.id_keys = Some(keypair);
+                (noise_keypair, id)
+            }
+            InitKeypair::Authenticated { peer_id, noise_keypair } => {
+                self.id_keys = None;
+                (noise_keypair, peer_id)
+            }
+        };
+        self.ident = Some((keypair, id));
+        self
+    }
+
+    /// Set a verifier for capability tokens that remote peers attach to their requests.
+    ///
+    /// The verifier is evaluated before the firewall rules. Requests with an accepted token are approved, for all
+    /// other requests the firewall rules apply. Refer to the [`capability`][crate::firewall::capability] module for
+    /// more information.
+    pub fn with_capability_verifier<V>(mut self, verifier: V) -> Self
+    where
+        V: CapabilityVerifier<TRq> + 'static,
+    {
+        self.capability_verifier = Some(Arc::new(verifier));
+        self
+    }
+
+    /// Register a middleware that is invoked on each outbound request before it is sent, and on each inbound request
+    /// before it is forwarded to the application. The middleware can modify, observe or fail the requests.
+    ///
+    /// Multiple middleware are invoked in the order in which they were registered.
+    pub fn with_middleware<M: RequestMiddleware<Rq>>(mut self, middleware: M) -> Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Register a hook that is invoked on each response to an outbound request before it is returned to the
+    /// requester. The hook can modify, observe or fail the responses.
+    ///
+    /// Multiple hooks are invoked in the order in which they were registered.
+    pub fn with_response_hook<H: ResponseHook<Rs>>(mut self, hook: H) -> Self {
+        self.response_hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// Set converters for requests and responses that peers of earlier versions send in a different shape, so that
+    /// the peers of a network can be upgraded one after another.
+    ///
+    /// The protocol versions of the converters are supported in addition to the current version, see
+    /// [`MessageMigrations`]. Requests of deprecated versions are answered with
+    /// [`RemoteError::UpgradeRequired`][crate::RemoteError::UpgradeRequired].
+    pub fn with_message_migrations(mut self, migrations: MessageMigrations<Rq, Rs>) -> Self {
+        self.message_migrations = Some(migrations);
+        self
+    }
+
+    /// Set the codec for encoding requests and responses on the wire, e.g. to use a binary format instead of JSON.
+    ///
+    /// All peers of the network have to use the same codec, see [`Codec`] for the messages to which it applies.
+    pub fn with_codec<C: Codec<Rq, Rs>>(mut self, codec: C) -> Self {
+        self.codec = Some(Arc::new(codec));
+        self
+    }
+
+    /// Set the generator for the ids of inbound and outbound requests, instead of the default counter.
+    ///
+    /// Use e.g. [`NamespacedIds`][crate::NamespacedIds] to generate ids that are unique across nodes, so that they
+    /// can be used as correlation keys in distributed logs. Ids are 64-bit values, generators that derive them from
+    /// larger identifiers (e.g. UUIDs) have to take care of collisions themselves. Alternatively, ids of the default
+    /// counter can be scoped with [`Network::global_request_id`].
+    pub fn with_request_id_generator<G: RequestIdGenerator>(mut self, generator: G) -> Self {
+        self.request_id_generator = Some(Arc::new(generator));
+        self
+    }
+
+    /// Set whether the static keypair of the noise protocol is reused for all sessions, or rotated per session or
+    /// after an interval, independently of the identity keys.
+    ///
+    /// Per default the same noise keypair is used for all sessions. See [`NoiseKeyRotation`] for the restrictions.
+    pub fn with_noise_key_rotation(mut self, rotation: NoiseKeyRotation) -> Self {
+        self.noise_key_rotation = rotation;
+        self
+    }
+
+    /// Set a predicate that is evaluated for the remote [`PeerId`] of each inbound and outbound connection right
+    /// after the security handshake.
+    ///
+    /// Connections to peers that don't fulfill the predicate are closed before any substream is opened. This does not
+    /// replace the firewall rules for requests, but prevents that unexpected peers are connected at all.
+    pub fn with_expected_peers<F>(mut self, is_expected: F) -> Self
+    where
+        F: Fn(&PeerId) -> bool + Send + Sync + 'static,
+    {
+        self.expected_peer = Some(Arc::new(is_expected));
+        self
+    }
+
+    /// Use the identity keys from the encrypted [`Keystore`], unlocked with `password`.
+    /// If the keystore file does not exist yet, new keys are generated and stored.
+    ///
+    /// Requires feature **keystore**.
+    #[cfg(feature = "keystore")]
+    pub fn with_keystore(self, keystore: &Keystore, password: &[u8]) -> Result<Self, KeystoreErr> {
+        let keys = keystore.unlock_or_generate(password)?;
+        Ok(self.with_keys(keys))
+    }
+
+    /// Load firewall rules from a JSON file in the format of [`FirewallConfig`][crate::firewall::FirewallConfig], and
+    /// reload the file whenever it was modified, so that policy updates don't require a restart.
+    ///
+    /// The file is checked for modifications in the `poll_interval`. The rules of the file take precedence over the
+    /// rules that were passed to [`NetworkBuilder::new`]. On reload, only the rules that differ from the previously
+    /// loaded file are changed, all at once, and a [`NetworkEvent::FirewallRuleChanged`] is emitted for each of them.
+    /// If the file can not be parsed, no rule is changed and [`NetworkEvent::FirewallReloadFailed`] is emitted.
+    ///
+    /// Building the `Network` fails if the file can not be loaded initially.
+    pub fn with_firewall_config_file(mut self, path: impl AsRef<Path>, poll_interval: Duration) -> Self {
+        self.firewall_config_file = Some((path.as_ref().to_path_buf(), poll_interval));
+        self
     }
 
     /// Set the limit for simultaneous connections.
     /// By default no connection limits apply.
+    ///
+    /// Connections that exceed the limits are denied before they are established. The limits for established
+    /// connections can be lowered at runtime with [`Network::set_connection_limits`].
+    ///
+    /// If [`LimitPressurePolicy::EvictLowestPriority`] is set, the limits for established connections are instead
+    /// enforced once a new connection was established, so that another connection can be evicted in its favor.
     pub fn with_connections_limit(mut self, limit: ConnectionLimits) -> Self {
         self.connections_limit = Some(limit);
         self
@@ -589,6 +1922,291 @@ where
         self
     }
 
+    /// Set the strategy for assigning outbound requests to a connection, if multiple connections to the remote peer
+    /// are established.
+    ///
+    /// Per default a pseudo-random connection is used.
+    pub fn with_connection_selection(mut self, selection: ConnectionSelection) -> Self {
+        self.behaviour_config.connection_selection = selection;
+        self
+    }
+
+    /// Set the handling of new connections that exceed the limits for established connections, e.g. to evict the
+    /// connections of less important peers instead of rejecting the new one. The priority of peers is set with
+    /// [`Network::set_peer_priority`].
+    ///
+    /// Per default new connections that exceed the limits are closed.
+    pub fn with_limit_pressure_policy(mut self, policy: LimitPressurePolicy) -> Self {
+        self.behaviour_config.limit_pressure_policy = policy;
+        self
+    }
+
+    /// Set the strategy for choosing among the known dialing relays, when a peer is dialed via relays or when
+    /// listening via [`Network::start_selected_relayed_listening`].
+    ///
+    /// Per default the relays are tried in the order in which they were added.
+    pub fn with_relay_selection(mut self, selection: RelaySelection) -> Self {
+        self.behaviour_config.relay_selection = selection;
+        self
+    }
+
+    /// Restrict which relays are used for listening and dialing, and which peers may relay through the local peer.
+    ///
+    /// **Note**: A peer that is not allowed to relay through the local peer can not interact with the local relay
+    /// protocol at all, unless it is one of the local dialing relays.
+    ///
+    /// Per default there are no restrictions. The policy can be changed at runtime with
+    /// [`Network::set_relay_policy`].
+    pub fn with_relay_policy(mut self, policy: RelayPolicy) -> Self {
+        self.behaviour_config.relay_policy = policy;
+        self
+    }
+
+    /// Act as mailbox for other peers: store requests for offline recipients, and forward them once the recipient
+    /// connects.
+    ///
+    /// Per default the local peer is no mailbox.
+    pub fn with_mailbox(mut self, config: MailboxConfig) -> Self {
+        self.behaviour_config.mailbox = Some(config);
+        self
+    }
+
+    /// Detect inbound requests that a remote retransmitted with the same idempotency key. Only the first request is
+    /// forwarded, retransmissions are answered with its response once it is available.
+    ///
+    /// Per default idempotency keys are ignored, and each request is forwarded.
+    pub fn with_idempotency(mut self, config: IdempotencyConfig) -> Self {
+        self.behaviour_config.idempotency = Some(config);
+        self
+    }
+
+    /// Exchange heartbeats with all connected peers, and report with [`NetworkEvent::PeerOnline`],
+    /// [`NetworkEvent::PeerOffline`] and [`NetworkEvent::PeerStatusChanged`] which peers are online.
+    ///
+    /// The `status` is an application-defined payload that is sent in each heartbeat, it can be changed later with
+    /// [`Network::set_presence_status`]. Heartbeats don't keep connections alive.
+    ///
+    /// Per default presence is disabled.
+    pub fn with_presence(mut self, config: PresenceConfig, status: Vec<u8>) -> Self {
+        self.behaviour_config.presence = Some(config);
+        self.behaviour_config.presence_status = status;
+        self
+    }
+
+    /// Exchange hellos with each peer right after the first connection to it was established, so that peers can
+    /// advertise their capabilities without a separate request.
+    ///
+    /// The `hello` of the local peer is sent to all peers whose firewall rule does not reject all requests, and only
+    /// hellos of such peers are accepted. If no rule for the peer is known yet, it is queried with a
+    /// [`FirewallRequest::PeerSpecificRule`], and the hellos are held back until the rule was set. The hello of a
+    /// peer is reported with [`NetworkEvent::PeerHello`] after the [`NetworkEvent::ConnectionEstablished`] event, and
+    /// can be queried with [`Network::peer_hello`] while the peer is connected.
+    ///
+    /// Per default hellos are not exchanged.
+    pub fn with_hello(mut self, hello: Hello) -> Self {
+        self.behaviour_config.hello = Some(hello);
+        self
+    }
+
+    /// Accept requests that are forwarded by these mailboxes on behalf of other peers.
+    ///
+    /// The mailbox is trusted to correctly state the original sender of a request. Forwarded requests are only
+    /// accepted if the firewall rule for the original sender is [`Rule::AllowAll`] or a [`Rule::Restricted`] that
+    /// permits the request; rules that require asking for approval reject them.
+    pub fn with_trusted_mailboxes(mut self, mailboxes: impl IntoIterator<Item = PeerId>) -> Self {
+        self.behaviour_config.trusted_mailboxes.extend(mailboxes);
+        self
+    }
+
+    /// Collect outbound requests to the same peer within the `window`, and send them as batch on a single substream.
+    /// This reduces the overhead per request for chatty workloads, at the cost of latency.
+    ///
+    /// See [`RequestBatching`] for the restrictions. Per default no batching is done.
+    pub fn with_request_batching(mut self, window: Duration, max_requests: usize) -> Self {
+        self.behaviour_config.request_batching = Some(RequestBatching { window, max_requests });
+        self
+    }
+
+    /// Whether outbound requests to the same peer should be sent in the order in which they were submitted, for
+    /// applications where the order of requests matters.
+    ///
+    /// If enabled, later requests are held back until the previous request to the same peer was written to its
+    /// substream or failed. Responses are not awaited, and may arrive in a different order. Per default requests are
+    /// sent concurrently.
+    ///
+    /// **Note**: This only orders the local writes. Each request is still sent on its own substream, which the remote
+    /// reads concurrently, and e.g. pending firewall approvals or decoding in a worker pool may reorder them before
+    /// they are forwarded at the remote. Applications that require the remote to process requests in order have to
+    /// add a sequence number to their requests, or await each response before sending the next request.
+    pub fn with_ordered_delivery(mut self, ordered: bool) -> Self {
+        self.behaviour_config.ordered_delivery = ordered;
+        self
+    }
+
+    /// Decode inbound requests and responses with at least `min_size` bytes in a pool of `threads` worker threads,
+    /// instead of on the task of the connection. Responses to inbound requests are always encoded in the pool, since
+    /// their size is not known before.
+    ///
+    /// Per default all messages are decoded and encoded directly.
+    pub fn with_decode_workers(mut self, threads: usize, min_size: usize) -> Self {
+        self.behaviour_config.decode_workers = Some(DecodeWorkers { threads, min_size });
+        self
+    }
+
+    /// Adjust the sizing of internal buffers, see [`BufferTuning`].
+    pub fn with_buffer_tuning(mut self, tuning: BufferTuning) -> Self {
+        self.behaviour_config.buffer_tuning = tuning;
+        self
+    }
+
+    /// Set the maximum number of inbound requests that are cached while they await a firewall rule or individual
+    /// approval. If the limit is exceeded, the oldest request fails with [`InboundFailure::Evicted`].
+    ///
+    /// Per default the number is not limited.
+    pub fn with_max_pending_inbound(mut self, max: usize) -> Self {
+        self.behaviour_config.max_pending_inbound = Some(max);
+        self
+    }
+
+    /// Emit a [`NetworkEvent::InboundQueueHigh`] once the number of inbound requests that await a firewall rule or
+    /// individual approval reaches the high watermark, and a [`NetworkEvent::InboundQueueLow`] once it fell to the low
+    /// watermark again. It is an early signal to shed load, before requests are evicted because of
+    /// [`NetworkBuilder::with_max_pending_inbound`].
+    ///
+    /// The current number can be obtained with [`Network::inbound_queue_depth`].
+    pub fn with_inbound_watermarks(mut self, watermarks: InboundWatermarks) -> Self {
+        self.behaviour_config.inbound_watermarks = Some(watermarks);
+        self
+    }
+
+    /// Set a budget for the total size in bytes of the requests that are cached while they await a firewall rule,
+    /// individual approval, or a connection to the remote peer.
+    /// If the budget would be exceeded, new requests fail with [`InboundFailure::MaxBuffered`] or
+    /// [`OutboundFailure::MaxBuffered`].
+    ///
+    /// Per default the size is not limited.
+    pub fn with_max_buffered_bytes(mut self, max: usize) -> Self {
+        self.behaviour_config.max_buffered_bytes = Some(max);
+        self
+    }
+
+    /// Set the maximum number of outbound requests that are cached while they await a connection to the remote peer.
+    /// If the limit is exceeded, the oldest request fails with [`OutboundFailure::Evicted`].
+    ///
+    /// Per default the number is not limited.
+    pub fn with_max_pending_outbound(mut self, max: usize) -> Self {
+        self.behaviour_config.max_pending_outbound = Some(max);
+        self
+    }
+
+    /// Detect peers that connect more than `max_connects` times within the `window`, and emit a
+    /// [`NetworkEvent::PeerFlapping`] for them, e.g. to back off or ban unstable peers.
+    ///
+    /// Per default no flap detection is done.
+    pub fn with_flap_detection(mut self, max_connects: u32, window: Duration) -> Self {
+        self.behaviour_config.flap_detection = Some(FlapDetection { max_connects, window });
+        self
+    }
+
+    /// Back off from dialing a peer after failed dial attempts, so that a popular but unreachable peer is not dialed
+    /// anew for each outbound request. While the peer is backing off, outbound requests to it wait until the backoff
+    /// elapsed, and then a single dial attempt is made for all of them.
+    ///
+    /// Per default peers are dialed for each new outbound request without backoff.
+    pub fn with_dial_backoff(mut self, backoff: DialBackoff) -> Self {
+        self.behaviour_config.dial_backoff = Some(backoff);
+        self
+    }
+
+    /// Remove a known address of a peer after `max_failures` consecutive failed dial attempts on it, so that stale
+    /// addresses do not accumulate. Addresses that failed are always tried after the ones that did not.
+    ///
+    /// Per default known addresses are only removed explicitly via [`Network::remove_address`].
+    pub fn with_address_gc(mut self, max_failures: u32) -> Self {
+        self.behaviour_config.max_address_failures = Some(max_failures);
+        self
+    }
+
+    /// Set the number of recent internal actions and swarm events that are kept for debugging, and can be retrieved
+    /// via [`Network::recent_events`].
+    ///
+    /// Per default this log is disabled.
+    pub fn with_event_log_size(mut self, size: usize) -> Self {
+        self.behaviour_config.event_log_size = size;
+        self
+    }
+
+    /// Set a callback that is invoked on each inbound and outbound failure, failed dial and connection error, with
+    /// the peer and the full error.
+    ///
+    /// The callback is invoked from within the event loop, and thus should not block.
+    pub fn with_error_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(NetworkFailure<'_>) + Send + Sync + 'static,
+    {
+        self.error_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Set a channel for forwarding a [`SwarmDebugEvent`] for each raw event of the swarm.
+    ///
+    /// Contrary to the events channel of [`NetworkEvent`]s, this includes events like dial attempts and failures with
+    /// their full error, which is useful for debugging lower-level issues in the transport.
+    pub fn with_swarm_debug_channel(mut self, channel: EventChannel<SwarmDebugEvent>) -> Self {
+        self.swarm_debug_channel = Some(channel);
+        self
+    }
+
+    /// Accept blob transfers from remote peers, and forward the received blobs through the `channel`.
+    ///
+    /// A transfer is only accepted if the firewall rule for the sender is [`Rule::AllowAll`] or a
+    /// [`Rule::Restricted`] that permits the metadata of the blob. The chunk size of the `config` is also used for
+    /// outbound transfers.
+    ///
+    /// Per default inbound transfers are rejected.
+    pub fn with_blob_transfer(mut self, channel: EventChannel<ReceivedBlob<Rq>>, config: TransferConfig) -> Self {
+        self.blob_channel = Some(channel);
+        self.behaviour_config.transfer = Some(config);
+        self
+    }
+
+    /// Enable the outbound queue for [`Network::enqueue_request`], persisted in the `store`.
+    /// Requests that remained in the store from a former running instance are sent again once the network is built.
+    ///
+    /// The responses and final failures of queued requests are forwarded through the `channel`.
+    pub fn with_outbound_queue<S: QueueStore>(
+        mut self,
+        channel: EventChannel<QueuedResponse<Rs>>,
+        config: OutboundQueueConfig,
+        store: S,
+    ) -> Self {
+        self.queued_response_channel = Some(channel);
+        self.outbound_queue = Some((config, Box::new(store)));
+        self
+    }
+
+    /// Enable the exchange of blocks with other peers, see [`Network::get_block`] and [`Network::put_block`].
+    ///
+    /// The blocks in the `store` are provided to connected peers that want them, if the firewall rule for the peer is
+    /// [`Rule::AllowAll`]. Fetched blocks are added to the store.
+    ///
+    /// Per default the block exchange is disabled and wants of other peers are ignored.
+    pub fn with_block_exchange<S: BlockStore>(mut self, config: BlockExchangeConfig, store: S) -> Self {
+        self.block_exchange = Some((config, Box::new(store)));
+        self
+    }
+
+    /// Write each inbound request that is forwarded to the application to the `journal` before forwarding it, and
+    /// record when it was answered. The records are written in batches by a separate thread, and the journal is
+    /// compacted periodically to the requests that are still open.
+    ///
+    /// After a restart, the requests that were received but never answered can be retrieved with
+    /// [`Network::unanswered_requests`].
+    pub fn with_request_journal<J: RequestJournal>(mut self, journal: J) -> Self {
+        self.request_journal = Some(Box::new(journal));
+        self
+    }
+
     /// Load the behaviour state from a former running instance.
     /// The state contains default and peer-specific rules, and the list of known addresses for remote peers.
     pub fn load_addresses(mut self, address_info: AddressInfo) -> Self {
@@ -596,33 +2214,228 @@ where
         self
     }
 
+    /// Set the capacities of the internal channels, see [`ChannelCapacities`] for the backpressure that is applied if
+    /// they are full.
+    pub fn with_channel_capacities(mut self, capacities: ChannelCapacities) -> Self {
+        self.channel_capacities = capacities;
+        self
+    }
+
+    /// Reject new inbound requests with [`InboundFailure::Overloaded`] while the channel for inbound requests is full,
+    /// instead of buffering, dropping or blocking according to the [`ChannelSinkConfig`] of the channel.
+    ///
+    /// The substreams of rejected requests are closed without a response, so that the backpressure is propagated to
+    /// the remote peers. Inbound requests are accepted again once the receiver read from the channel.
+    pub fn with_inbound_backpressure(mut self, enable: bool) -> Self {
+        self.inbound_backpressure = enable;
+        self
+    }
+
+    /// Ask remotes to retry requests after `retry_after`, if they are rejected because the local peer is overloaded,
+    /// e.g. due to [`NetworkBuilder::with_inbound_backpressure`] or the limits for pending requests. The remote
+    /// receives [`RemoteError::Busy`][crate::RemoteError::Busy] with the hint, which can be obtained via
+    /// [`OutboundFailure::retry_after`].
+    ///
+    /// **Note**: Remotes of earlier versions fail to decode the hint. Per default
+    /// [`RemoteError::Overloaded`][crate::RemoteError::Overloaded] is sent without a hint.
+    pub fn with_busy_retry_after(mut self, retry_after: Duration) -> Self {
+        self.behaviour_config.busy_retry_after = Some(retry_after);
+        self
+    }
+
     /// Whether the peer should support the [`Mdns`][libp2p::mdns] protocol for peer discovery in a local network.
     ///
     /// **Note**: Enabling Mdns broadcasts our own address and id to the local network.
+    ///
+    /// Requires the `mdns` feature, otherwise the protocol is never supported.
     pub fn with_mdns_support(mut self, support_mdns_protocol: bool) -> Self {
         self.support_mdns = support_mdns_protocol;
         self
     }
 
+    /// Set the delay after which a lost reservation on a relay is renewed, i.e. after which the local peer starts
+    /// listening via the relay again. Failed renewals are retried after the same delay.
+    /// If set to `None`, lost reservations are not renewed.
+    ///
+    /// A [`NetworkEvent::RelayReservationLost`] and [`NetworkEvent::RelayReservationRenewed`] is emitted when a
+    /// reservation is lost or renewed.
+    ///
+    /// Per default lost reservations are renewed after 10s.
+    pub fn with_relay_renewal(mut self, delay: Option<Duration>) -> Self {
+        self.relay_renewal = delay;
+        self
+    }
+
+    /// Add candidates for relay nodes, that are used as dialing relays and for listening via
+    /// [`Network::start_selected_relayed_listening`].
+    ///
+    /// The candidates are ranked by their health, see [`NetworkBuilder::with_relay_probing`]. Candidates are only
+    /// added if the relay protocol is supported.
+    ///
+    /// **Note**: Relays are not discovered automatically from the network, since neither the identify- nor a DHT
+    /// protocol is integrated.
+    pub fn with_relay_candidates(mut self, candidates: Vec<(PeerId, Multiaddr)>) -> Self {
+        self.relay_candidates.extend(candidates);
+        self
+    }
+
+    /// Add the addresses of the `peers`, and dial them once the network is built, e.g. to join the network via
+    /// well-known nodes.
+    ///
+    /// Failed dial attempts are not retried.
+    pub fn with_bootstrap_peers(mut self, peers: Vec<(PeerId, Multiaddr)>) -> Self {
+        self.bootstrap_peers.extend(peers);
+        self
+    }
+
+    /// Set peers to which connections are re-established when the local listening addresses changed, e.g. after
+    /// switching the network interface. Persistent peers can also be changed at runtime with
+    /// [`Network::add_persistent_peer`].
+    ///
+    /// After such a change, the persistent peers are dialed anew even if they are still connected, and active
+    /// reservations on relays are refreshed, see [`NetworkEvent::LocalAddressesChanged`].
+    pub fn with_persistent_peers(mut self, peers: Vec<PeerId>) -> Self {
+        self.persistent_peers.extend(peers);
+        self
+    }
+
+    /// Periodically dial the dialing relays that are not connected, to probe their health.
+    /// Connected relays are probed with the ping protocol, if it is enabled.
+    ///
+    /// Relays with consecutive failed dial attempts or pings are ranked last, see [`Network::relay_candidates`].
+    /// Per default relays are not probed.
+    pub fn with_relay_probing(mut self, interval: Option<Duration>) -> Self {
+        self.relay_probe_interval = interval;
+        self
+    }
+
+    /// Dial up to `factor` known addresses of a peer concurrently, and keep the first connection that is established.
+    ///
+    /// The concurrent dials are started `stagger` apart in the order of the addresses, so that the preferred address
+    /// gets a head start ("happy eyeballs"), but a slow or dead address doesn't delay the connection by its full
+    /// timeout. Once a dial failed, the next address is dialed in its place.
+    pub fn with_dial_concurrency(mut self, factor: NonZeroU8, stagger: Duration) -> Self {
+        self.dial_concurrency_factor = Some(factor);
+        self.dial_stagger = stagger;
+        self
+    }
+
+    /// Close new connections that did not complete the security and muxer upgrade within the `timeout`, so that
+    /// remotes that stall in the middle of the handshake don't occupy resources indefinitely.
+    ///
+    /// Inbound connections that time out are reported as [`NetworkEvent::IncomingConnectionError`] with
+    /// [`ConnectionErr::HandshakeTimeout`]. For outbound connections the timeout also covers establishing the
+    /// transport connection, a dial that times out fails with an I/O error.
+    ///
+    /// Per default there is no timeout.
+    pub fn with_handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = Some(timeout);
+        self
+    }
+
     /// Whether the peer should support the [`Relay`][libp2p::relay] protocol that allows dialing and listening via a
     /// relay peer.
     ///
     /// **Note:** enabling this protocol also means that other peers can use our peer as relay.
+    ///
+    /// Requires the `relay` feature, otherwise the protocol is never supported.
     pub fn with_relay_support(mut self, support_relay_protocol: bool) -> Self {
         self.support_relay = support_relay_protocol;
         self
     }
 
+    /// Whether the peer should support the [`Ping`][libp2p::ping] protocol, which periodically pings connected peers
+    /// to measure the round-trip time. This is required for [`Network::probe`].
+    ///
+    /// Per default the ping protocol is disabled.
+    pub fn with_ping_support(mut self, support_ping_protocol: bool) -> Self {
+        self.support_ping = support_ping_protocol;
+        self
+    }
+
+    /// Probe each established connection in the configured interval with the [`Ping`][libp2p::ping] protocol, and close
+    /// all connections to a peer once it missed `max_missed` probes in a row. A [`NetworkEvent::PeerUnresponsive`] is
+    /// emitted for the peer, and its pending requests fail with [`OutboundFailure::ConnectionClosed`] instead of
+    /// waiting for the request timeout, e.g. if the connection is half-open.
+    ///
+    /// This enables the ping protocol. Peers that don't support it are never considered unresponsive.
+    ///
+    /// Per default the ping protocol only measures round-trip times, and connections are not closed on failed pings.
+    pub fn with_keep_alive_probes(mut self, probes: KeepAliveProbes) -> Self {
+        self.support_ping = true;
+        self.behaviour_config.keep_alive_probes = Some(probes);
+        self
+    }
+
+    /// Whether the peer should support the [`Identify`][libp2p::identify] protocol, with which connected peers
+    /// report the address under which they observe the local peer. Observed addresses are added to the external
+    /// addresses of the local peer, and reported as [`NetworkEvent::ObservedAddr`], e.g. to detect changes of a NAT
+    /// mapping.
+    ///
+    /// Requires the identity keys of the local peer, see [`InitKeypair::IdKeys`]. If the network is built with only an
+    /// authenticated noise keypair, the protocol is not supported.
+    ///
+    /// Per default the protocol is not supported.
+    pub fn with_identify_support(mut self, support_identify_protocol: bool) -> Self {
+        self.support_identify = support_identify_protocol;
+        self
+    }
+
+    /// Record metrics for the events of the swarm, e.g. connections, dial attempts and listeners, and for the events
+    /// of the enabled ping, identify and relay protocols.
+    ///
+    /// The [`NetworkMetrics`] are registered in a `prometheus_client` registry on creation, which can then be used to
+    /// encode the current metrics.
+    #[cfg(feature = "metrics")]
+    pub fn with_metrics(mut self, metrics: NetworkMetrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Export spans for the lifecycle of requests, and request metrics to OpenTelemetry.
+    ///
+    /// The trace context of requests that are sent with [`Network::send_request_with_metadata`] is propagated to the
+    /// remote in the metadata. See [`Telemetry`] for details.
+    #[cfg(feature = "otel")]
+    pub fn with_telemetry(mut self, telemetry: Telemetry) -> Self {
+        self.telemetry = Some(telemetry);
+        self
+    }
+
+    /// Bind outgoing TCP connections to local addresses, e.g. to dial via a specific interface on a multi-homed host,
+    /// or to only allow connections via a VPN.
+    ///
+    /// Only applies to the transport of [`NetworkBuilder::build`]. Relayed connections are bound via the connection
+    /// to the relay.
+    #[cfg(feature = "tcp-transport")]
+    pub fn with_outbound_binding(mut self, binding: OutboundBinding) -> Self {
+        self.outbound_binding = Some(binding);
+        self
+    }
+
     #[cfg(feature = "tcp-transport")]
     /// [`Self::build_with_transport`] with a [`Transport`] based on TCP/IP that supports dns resolution and websockets.
     /// It uses [`tokio::spawn`] as executor, hence this method has to be called in the context of a tokio.rs runtime.
-    pub async fn build(self) -> Result<Network<Rq, Rs, TRq>, io::Error> {
-        let dns_transport = TokioDnsConfig::system(TokioTcpConfig::new())?;
-        let transport = dns_transport.clone().or_transport(WsConfig::new(dns_transport));
+    ///
+    /// Outgoing connections are bound to the local addresses that were set with
+    /// [`NetworkBuilder::with_outbound_binding`].
+    pub async fn build(mut self) -> Result<Network<Rq, Rs, TRq>, io::Error> {
         let executor = |fut| {
             tokio::spawn(fut);
         };
-        self.build_with_transport(transport, executor).await
+        match self.outbound_binding.take() {
+            Some(binding) => {
+                let tcp = BoundTcpTransport::new(TokioTcpConfig::new(), binding);
+                let dns_transport = TokioDnsConfig::system(tcp)?;
+                let transport = dns_transport.clone().or_transport(WsConfig::new(dns_transport));
+                self.build_with_transport(transport, executor).await
+            }
+            None => {
+                let dns_transport = TokioDnsConfig::system(TokioTcpConfig::new())?;
+                let transport = dns_transport.clone().or_transport(WsConfig::new(dns_transport));
+                self.build_with_transport(transport, executor).await
+            }
+        }
     }
 
     /// Create a new [`Network`] instance with an underlying [`Swarm`][libp2p::Swarm] that uses the provided
@@ -674,73 +2487,276 @@ where
         Tp::ListenerUpgrade: Send + 'static,
         Tp::Error: Send + Sync,
         E: Executor + Send + 'static + Clone,
+    {
+        let (network, event_loop) = self
+            .build_event_loop(transport, Some(Box::new(executor.clone())))
+            .await?;
+        executor.exec(event_loop.run().boxed());
+        Ok(network)
+    }
+
+    /// Create a new [`Network`] instance like [`Self::build_with_transport`], but instead of spawning the event loop
+    /// in a new task, return an [`EventLoopDriver`] with which the caller drives it.
+    ///
+    /// No executor is used at all: the tasks of the individual connections are polled as part of the event loop.
+    /// This allows tests and simulators to control the interleaving of all network activity, e.g. by calling
+    /// [`EventLoopDriver::tick`] between polling the futures returned by the methods of [`Network`].
+    ///
+    /// **Note**: Timeouts and intervals still use the system clock.
+    pub async fn build_with_driver<Tp>(
+        self,
+        transport: Tp,
+    ) -> Result<(Network<Rq, Rs, TRq>, EventLoopDriver), io::Error>
+    where
+        Tp: Transport + Sized + Clone + Send + Sync + 'static,
+        Tp::Output: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+        Tp::Dial: Send + 'static,
+        Tp::Listener: Send + 'static,
+        Tp::ListenerUpgrade: Send + 'static,
+        Tp::Error: Send + Sync,
+    {
+        let (network, event_loop) = self.build_event_loop(transport, None).await?;
+        Ok((network, EventLoopDriver::new(event_loop)))
+    }
+
+    // Create the swarm and the event loop that drives it.
+    // If no executor is provided, the connection tasks are polled within the swarm.
+    async fn build_event_loop<Tp>(
+        self,
+        transport: Tp,
+        executor: Option<Box<dyn Executor + Send>>,
+    ) -> Result<(Network<Rq, Rs, TRq>, EventLoop<Rq, Rs, TRq, TCustom>), io::Error>
+    where
+        Tp: Transport + Sized + Clone + Send + Sync + 'static,
+        Tp::Output: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+        Tp::Dial: Send + 'static,
+        Tp::Listener: Send + 'static,
+        Tp::ListenerUpgrade: Send + 'static,
+        Tp::Error: Send + Sync,
     {
         // Use the configured keypair or create a new one.
+        let mut id_keys = self.id_keys;
         let (noise_keypair, peer_id) = self.ident.unwrap_or_else(|| {
             let keypair = Keypair::generate_ed25519();
             // Can never fail for `identity::Keypair::Ed25519` and `X25519Spec` protocol.
             let noise_keypair = NoiseKeypair::<X25519Spec>::new().into_authentic(&keypair).unwrap();
             let peer_id = keypair.public().to_peer_id();
+            id_keys = Some(keypair);
             (noise_keypair, peer_id)
         });
-        let relay;
-        let boxed_transport;
-        if self.support_relay {
+        let identify_key = id_keys.as_ref().map(Keypair::public);
+        let banned_peers = BannedPeers::default();
+        let noise_upgrade = NoiseUpgrade::new(noise_keypair, id_keys, self.noise_key_rotation)
+            .with_expected_peer(self.expected_peer)
+            .with_banned_peers(banned_peers.clone());
+        let transport = StaggeredDial::new(transport, self.dial_stagger);
+        #[cfg(feature = "relay")]
+        let (boxed_transport, relay) = if self.support_relay {
             let (relay_transport, relay_behaviour) = new_transport_and_behaviour(RelayConfig::default(), transport);
-            boxed_transport = relay_transport
+            let boxed_transport = relay_transport
                 .upgrade(upgrade::Version::V1)
-                .authenticate(NoiseConfig::xx(noise_keypair).into_authenticated())
+                .authenticate(noise_upgrade)
                 .multiplex(YamuxConfig::default())
                 .boxed();
-            relay = Some(relay_behaviour)
+            (boxed_transport, Some(relay_behaviour))
         } else {
-            boxed_transport = transport
+            let boxed_transport = transport
                 .upgrade(upgrade::Version::V1)
-                .authenticate(NoiseConfig::xx(noise_keypair).into_authenticated())
+                .authenticate(noise_upgrade)
                 .multiplex(YamuxConfig::default())
                 .boxed();
-            relay = None;
-        }
+            (boxed_transport, None)
+        };
+        #[cfg(not(feature = "relay"))]
+        let (boxed_transport, relay) = {
+            let boxed_transport = transport
+                .upgrade(upgrade::Version::V1)
+                .authenticate(noise_upgrade)
+                .multiplex(YamuxConfig::default())
+                .boxed();
+            (boxed_transport, None)
+        };
+        let boxed_transport = match self.handshake_timeout {
+            Some(timeout) => with_handshake_timeout(boxed_transport, timeout),
+            None => boxed_transport,
+        };
+        let is_shard = self.shard_routing.is_some();
+        let boxed_transport = match self.shard_routing {
+            Some(routing) => routing.wrap_transport(boxed_transport),
+            None => boxed_transport,
+        };
+        #[cfg(feature = "mdns")]
         let mdns = if self.support_mdns {
             Some(Mdns::new(MdnsConfig::default()).await?)
         } else {
             None
         };
+        #[cfg(not(feature = "mdns"))]
+        let mdns = None;
+        let keep_alive_probes = self.behaviour_config.keep_alive_probes;
+        let limit_pressure_policy = self.behaviour_config.limit_pressure_policy;
+        let ping = self.support_ping.then(|| {
+            let config = match keep_alive_probes {
+                // Unresponsive peers are detected by the behaviour, the ping handler should never close the connection.
+                Some(probes) => PingConfig::new()
+                    .with_interval(probes.interval)
+                    .with_timeout(probes.timeout)
+                    .with_max_failures(NonZeroU32::new(u32::MAX).unwrap()),
+                None => PingConfig::new(),
+            };
+            Ping::new(config)
+        });
+        let identify = match identify_key {
+            Some(key) if self.support_identify => {
+                let protocol_version = format!("/p2p/{}", env!("CARGO_PKG_VERSION"));
+                Some(Identify::new(IdentifyConfig::new(protocol_version, key)))
+            }
+            _ => None,
+        };
+        let (custom, custom_channel) = match self.custom_behaviour {
+            Some((behaviour, channel)) => (Some(behaviour), Some(channel)),
+            None => (None, None),
+        };
+
+        let mut firewall_rules = self.firewall_rules;
+        let firewall_watcher = match self.firewall_config_file {
+            Some((path, interval)) => {
+                let (watcher, config) = FirewallWatcher::new(path, interval)?;
+                if let Some(default) = config.default {
+                    firewall_rules.set_default(default.into_rule());
+                }
+                for (peer, rule) in config.peer_rules {
+                    match rule.into_rule() {
+                        Some(rule) => firewall_rules.set_rule(peer, rule),
+                        None => firewall_rules.remove_rule(&peer),
+                    }
+                }
+                Some(watcher)
+            }
+            None => None,
+        };
 
-        let behaviour = NetworkBehaviour::new(
+        let mut behaviour = NetworkBehaviour::new(
             self.behaviour_config,
             mdns,
             relay,
+            ping,
+            identify,
+            custom,
             self.firewall_channel,
-            self.firewall_rules,
+            firewall_rules,
             self.address_info,
         );
+        let session = rand::random();
+        behaviour.set_session(peer_id, session);
+        behaviour.set_capability_verifier(self.capability_verifier);
+        if let Some(generator) = self.request_id_generator {
+            behaviour.set_request_id_generator(generator);
+        }
+        behaviour.set_middleware(self.middleware);
+        behaviour.set_response_hooks(self.response_hooks);
+        if let Some(migrations) = self.message_migrations {
+            behaviour.set_message_migrations(migrations);
+        }
+        if let Some(codec) = self.codec {
+            behaviour.set_codec(codec);
+        }
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = self.metrics.as_ref() {
+            behaviour.set_metrics(metrics.libp2p());
+        }
+        if let Some((config, store)) = self.outbound_queue {
+            behaviour.set_outbound_queue(config, store)?;
+        }
+        if let Some((config, store)) = self.block_exchange {
+            behaviour.set_block_exchange(config, store);
+        }
+        if let Some(journal) = self.request_journal {
+            behaviour.set_request_journal(journal)?;
+        }
+        for (relay, address) in self.relay_candidates {
+            let _ = behaviour.add_dialing_relay(relay, Some(address));
+        }
+        for (peer, address) in &self.bootstrap_peers {
+            behaviour.add_address(*peer, address.clone());
+        }
+        // Connections can only be evicted in favor of a new one once it is established, hence the behaviour has to
+        // enforce the limits for established connections.
+        let is_evicting = matches!(limit_pressure_policy, LimitPressurePolicy::EvictLowestPriority);
+        if let Some(limit) = self.connections_limit.as_ref().filter(|_| is_evicting) {
+            behaviour.set_connection_limits(limit.established(), ExcessConnectionPolicy::Keep);
+        }
 
-        let mut swarm_builder =
-            SwarmBuilder::new(boxed_transport, behaviour, peer_id).executor(Box::new(executor.clone()));
+        let mut swarm_builder = SwarmBuilder::new(boxed_transport, behaviour, peer_id);
+        if let Some(executor) = executor {
+            swarm_builder = swarm_builder.executor(executor);
+        }
+        if let Some(factor) = self.dial_concurrency_factor {
+            swarm_builder = swarm_builder.dial_concurrency_factor(factor);
+        }
+        swarm_builder = swarm_builder
+            .notify_handler_buffer_size(self.channel_capacities.notify_handler)
+            .connection_event_buffer_size(self.channel_capacities.connection_events);
         if let Some(limit) = self.connections_limit {
-            swarm_builder = swarm_builder.connection_limits(limit.into());
+            let swarm_limits = if is_evicting {
+                Libp2pConnectionLimits::default()
+                    .with_max_pending_incoming(limit.max_pending_incoming)
+                    .with_max_pending_outgoing(limit.max_pending_outgoing)
+            } else {
+                limit.into()
+            };
+            swarm_builder = swarm_builder.connection_limits(swarm_limits);
         }
-        let swarm = swarm_builder.build();
+        let mut swarm = swarm_builder.build();
         let local_peer_id = *swarm.local_peer_id();
+        if is_shard {
+            // Accept the inbound connections that the listening shard hands to this shard.
+            swarm
+                .listen_on(ShardRouting::accept_address())
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        }
+        for (peer, _) in self.bootstrap_peers {
+            let _ = swarm.dial(peer);
+        }
 
         // Channel for sending `SwarmCommand`s.
-        let (command_tx, command_rx) = mpsc::channel(10);
-
-        // Spawn an event-loop for all Swarm interaction in new task.
-        let event_loop = EventLoop::new(swarm, command_rx, self.requests_channel, self.events_channel);
-        executor.exec(event_loop.run().boxed());
+        let (command_tx, command_rx) = mpsc::channel(self.channel_capacities.commands);
+
+        // Event-loop for all Swarm interaction.
+        let event_loop = EventLoop::new(
+            swarm,
+            command_rx,
+            self.requests_channel,
+            self.inbound_backpressure,
+            self.events_channel,
+            self.swarm_debug_channel,
+            self.error_hook,
+            self.blob_channel,
+            self.queued_response_channel,
+            custom_channel,
+            self.relay_renewal,
+            self.relay_probe_interval,
+            self.persistent_peers,
+            firewall_watcher,
+            banned_peers,
+            #[cfg(feature = "metrics")]
+            self.metrics,
+            #[cfg(feature = "otel")]
+            self.telemetry,
+        );
 
-        Ok(Network {
+        let network = Network {
             local_peer_id,
+            session,
             command_tx,
-        })
+        };
+        Ok((network, event_loop))
     }
 }
 
 /// Inbound Request from a remote peer.
-/// It is expected that a response will be returned through the `response_rx` channel,
-/// otherwise an [`OutboundFailure`] will occur at the remote peer.
+/// It is expected that the request is answered or rejected through the [`Responder`], otherwise an
+/// [`OutboundFailure`] will occur at the remote peer.
 #[derive(Debug)]
 pub struct ReceiveRequest<Rq, Rs> {
     /// ID of the request.
@@ -749,15 +2765,96 @@ pub struct ReceiveRequest<Rq, Rs> {
     pub peer: PeerId,
     /// Request from the remote peer.
     pub request: Rq,
-    /// Channel for returning the response.
+    /// Handle for answering or rejecting the request.
     ///
-    /// **Note:** If an [`InboundFailure`] occurs before a response was sent, the Receiver side of this channel is
-    /// dropped.
-    pub response_tx: oneshot::Sender<Rs>,
+    /// **Note:** If an [`InboundFailure`] occurs before a response was sent, the requester is no longer waiting, see
+    /// [`Responder::is_waiting`].
+    pub responder: Responder<Rs>,
+    /// Connection on which the request was received, with the address of the remote.
+    /// `None` if the request was forwarded by a mailbox, or the connection closed before the request was approved.
+    pub connection: Option<RequestConnection>,
+    /// Metadata that the remote attached with [`Network::send_request_with_metadata`].
+    /// Empty if no metadata was attached.
+    pub metadata: RequestMetadata,
+    /// Priority class that the remote attached with [`Network::send_request_with_priority`].
+    /// [`RequestPriority::Normal`] if no priority was attached.
+    pub priority: RequestPriority,
+    /// Id that the remote assigned to the request, scoped to the remote and its running instance, i.e. the
+    /// [`Network::global_request_id`] of the request at the remote.
+    /// `None` if the request was sent without metadata or priority.
+    pub global_id: Option<GlobalRequestId>,
 }
 
-/// Active Listener of the local peer.
+/// Handle for an outbound request that was sent with [`Network::send_request_tracked`].
+pub struct RequestHandle<Rs> {
+    request_id: RequestId,
+    // Latest known status.
+    status: RequestStatus,
+    status_rx: mpsc::UnboundedReceiver<RequestStatus>,
+    response_rx: oneshot::Receiver<Result<TimedResponse<Rs>, OutboundFailure>>,
+}
+
+impl<Rs> RequestHandle<Rs> {
+    pub fn request_id(&self) -> RequestId {
+        self.request_id
+    }
+
+    /// Current status of the request.
+    pub fn status(&mut self) -> &RequestStatus {
+        while let Ok(Some(status)) = self.status_rx.try_next() {
+            self.status = status;
+        }
+        &self.status
+    }
+
+    /// Wait for the next change of the status.
+    /// Returns `None` if the request already reached a final status.
+    pub async fn status_changed(&mut self) -> Option<RequestStatus> {
+        let status = self.status_rx.next().await?;
+        self.status = status.clone();
+        Some(status)
+    }
+
+    /// Wait for the response of the remote.
+    pub async fn response(self) -> Result<TimedResponse<Rs>, OutboundFailure> {
+        self.response_rx.await.unwrap_or(Err(OutboundFailure::Shutdown))
+    }
+}
+
+/// Response to an outbound request, with the measured round-trip time.
 #[derive(Debug, Clone)]
+pub struct TimedResponse<Rs> {
+    /// Response from the remote peer.
+    pub response: Rs,
+    /// Time between writing the request to the substream and receiving the response.
+    /// This excludes the time that the request was queued, e.g. while waiting for a connection.
+    pub rtt: Duration,
+}
+
+/// Response or final failure of a request that was sent with [`Network::enqueue_request`].
+#[derive(Debug)]
+pub struct QueuedResponse<Rs> {
+    /// Id that was returned when the request was enqueued.
+    pub request_id: RequestId,
+    /// ID of the remote peer to which the request was sent.
+    pub peer: PeerId,
+    pub result: Result<Rs, OutboundFailure>,
+}
+
+/// Blob that was received from a remote peer and verified against its digest.
+#[derive(Debug)]
+pub struct ReceivedBlob<Rq> {
+    /// Id that the sender assigned to the transfer.
+    pub transfer_id: RequestId,
+    /// ID of the remote peer that sent the blob.
+    pub peer: PeerId,
+    /// Metadata that describes the blob.
+    pub metadata: Rq,
+    pub data: Vec<u8>,
+}
+
+/// Active Listener of the local peer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Listener {
     /// The addresses associated with this listener.
     pub addrs: SmallVec<[Multiaddr; 6]>,
@@ -765,6 +2862,57 @@ pub struct Listener {
     pub uses_relay: Option<PeerId>,
 }
 
+/// Status of the reservation on a relay, via which the local peer is listening.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayReservation {
+    /// The relay peer.
+    pub relay: PeerId,
+    /// Whether the local peer is currently reachable via the relay.
+    pub is_active: bool,
+    /// The relayed listening address, if the reservation is active.
+    pub address: Option<Multiaddr>,
+    /// Number of times the reservation was renewed after it was lost.
+    pub renewals: u32,
+}
+
+/// Snapshot of the internal state of the [`Network`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateDump {
+    /// Requests that are pending in the `NetworkBehaviour`.
+    pub requests: PendingRequests,
+    /// Remote addresses of the currently established connections.
+    pub connections: HashMap<PeerId, Vec<Multiaddr>>,
+    /// Peers to which a connection attempt was started via [`Network::connect_peer`], and is still ongoing.
+    pub pending_dials: Vec<PeerId>,
+    /// Default firewall rule.
+    pub firewall_default: Option<RuleKind>,
+    /// Peer specific firewall rules.
+    pub firewall_peer_rules: HashMap<PeerId, RuleKind>,
+    /// Currently active listeners.
+    pub listeners: Vec<Listener>,
+}
+
+/// Information about the [`Swarm`][libp2p::Swarm] of the [`Network`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkInfo {
+    /// Number of connected peers.
+    pub num_peers: usize,
+    /// Number of incoming connections that are still being negotiated.
+    pub num_pending_incoming: u32,
+    /// Number of outgoing connections that are still being dialed or negotiated.
+    pub num_pending_outgoing: u32,
+    /// Number of established incoming connections.
+    pub num_established_incoming: u32,
+    /// Number of established outgoing connections.
+    pub num_established_outgoing: u32,
+    /// Addresses on which the swarm is listening.
+    pub listen_addrs: Vec<Multiaddr>,
+    /// External addresses of the local peer, as reported by the protocols.
+    pub external_addrs: Vec<Multiaddr>,
+    /// Names of the protocols that the local peer supports.
+    pub supported_protocols: Vec<String>,
+}
+
 /// Events happening in the Network.
 /// Includes events about connection and listener status as well as potential failures when receiving
 /// request-response messages.
@@ -797,6 +2945,8 @@ pub enum NetworkEvent {
         num_established: u32,
         /// Potential Error that resulted in the disconnection.
         cause: Option<io::Error>,
+        /// Whether the connection was closed by the local peer, the remote, or because of an error.
+        reason: CloseReason,
     },
     /// An error happened on a connection during its initial handshake.
     ///
@@ -830,13 +2980,153 @@ pub enum NetworkEvent {
         /// The listener error.
         error: io::Error,
     },
+    /// The reservation on a relay was lost, the local peer is not reachable via the relay anymore.
+    ///
+    /// Unless disabled with [`NetworkBuilder::with_relay_renewal`], the reservation will be renewed.
+    RelayReservationLost { relay: PeerId },
+    /// A lost reservation on a relay was renewed.
+    RelayReservationRenewed {
+        relay: PeerId,
+        /// The relayed listening address.
+        address: Multiaddr,
+    },
+    /// The local listening addresses changed, e.g. because the network interface was switched.
+    ///
+    /// To recover connectivity, the persistent peers were dialed anew, and active relay reservations are refreshed.
+    /// A [`NetworkEvent::RelayReservationRenewed`] is emitted once a reservation was refreshed.
+    ///
+    /// See [`NetworkBuilder::with_persistent_peers`].
+    LocalAddressesChanged {
+        /// The current listening addresses.
+        listen_addrs: Vec<Multiaddr>,
+        /// Persistent peers that were dialed.
+        redialed_peers: Vec<PeerId>,
+        /// Relays on which the reservation is refreshed.
+        refreshed_relays: Vec<PeerId>,
+    },
+    /// A connected peer reported a new address under which it observes the local peer, e.g. because the mapping of a
+    /// NAT changed.
+    ///
+    /// See [`NetworkBuilder::with_identify_support`].
+    ObservedAddr {
+        /// The peer that reported the address.
+        peer: PeerId,
+        address: Multiaddr,
+        /// New external addresses of the local peer that the address was promoted to, after translating it to the
+        /// local listening addresses. Empty if the address was already known as external address.
+        promoted: Vec<Multiaddr>,
+    },
+    /// An established connection to the peer was closed to make room for a connection of a peer with a higher
+    /// priority.
+    ///
+    /// See [`LimitPressurePolicy::EvictLowestPriority`].
+    ConnectionEvicted {
+        peer: PeerId,
+        /// Peer of the new connection.
+        in_favor_of: PeerId,
+    },
+    /// A peer connected more often than allowed by the configured [`FlapDetection`].
+    ///
+    /// See [`NetworkBuilder::with_flap_detection`].
+    PeerFlapping {
+        peer: PeerId,
+        /// Number of connects within the configured window.
+        connects: u32,
+    },
+    /// A connected peer announced that it rotated its identity to `new_peer`, e.g. with
+    /// [`Network::rotate_identity`].
+    ///
+    /// The addresses of the new identity were added to the known addresses. Firewall rules, tags and other state
+    /// that refer to the old peer id are not transferred, this is up to the application.
+    PeerIdentityRotated {
+        /// Old peer id of the peer.
+        peer: PeerId,
+        new_peer: PeerId,
+        /// Addresses of the new identity.
+        addrs: Vec<Multiaddr>,
+    },
+    /// A peer missed the configured number of keep-alive probes in a row, and its connections are closed.
+    ///
+    /// See [`NetworkBuilder::with_keep_alive_probes`].
+    PeerUnresponsive {
+        peer: PeerId,
+        /// Number of consecutive missed probes.
+        missed: u32,
+    },
+    /// The number of inbound requests that await the firewall reached the high watermark.
+    ///
+    /// See [`NetworkBuilder::with_inbound_watermarks`].
+    InboundQueueHigh {
+        /// Current number of inbound requests that await the firewall.
+        depth: usize,
+    },
+    /// The number of inbound requests that await the firewall fell to the low watermark, after it reached the high
+    /// watermark.
+    ///
+    /// See [`NetworkBuilder::with_inbound_watermarks`].
+    InboundQueueLow {
+        /// Current number of inbound requests that await the firewall.
+        depth: usize,
+    },
+    /// Delivery status of a request that was sent with [`Network::send_via_mailbox`].
+    MailboxReceipt {
+        request_id: RequestId,
+        recipient: PeerId,
+        status: MailboxDelivery,
+    },
+    /// Progress of an inbound or outbound blob transfer.
+    TransferProgress {
+        peer: PeerId,
+        /// Id of the transfer. For inbound transfers this is the id assigned by the sender.
+        transfer_id: RequestId,
+        is_inbound: bool,
+        /// Number of bytes that were transferred so far.
+        transferred: u64,
+        /// Total size of the blob.
+        total: u64,
+    },
+    /// An outbound blob transfer that was started with [`Network::send_blob`] finished or failed.
+    TransferFinished {
+        peer: PeerId,
+        transfer_id: RequestId,
+        result: Result<(), TransferFailure>,
+    },
+    /// A connected peer sent its first heartbeat.
+    ///
+    /// See [`NetworkBuilder::with_presence`].
+    PeerOnline {
+        peer: PeerId,
+        /// Status that the peer sent in the heartbeat.
+        status: Vec<u8>,
+    },
+    /// A peer that was online disconnected, or did not send a heartbeat within the configured timeout.
+    PeerOffline { peer: PeerId },
+    /// A peer that is online sent a heartbeat with a new status.
+    PeerStatusChanged { peer: PeerId, status: Vec<u8> },
+    /// A newly connected peer sent its hello.
+    ///
+    /// See [`NetworkBuilder::with_hello`].
+    PeerHello { peer: PeerId, hello: Hello },
+    /// A firewall rule was changed by reloading the firewall config file.
+    ///
+    /// See [`NetworkBuilder::with_firewall_config_file`].
+    FirewallRuleChanged {
+        /// The peer of the rule, `None` for the default rule.
+        peer: Option<PeerId>,
+        /// The new rule, `None` if the rule was removed.
+        rule: Option<RuleKind>,
+    },
+    /// The modified firewall config file could not be loaded. The firewall rules were not changed.
+    FirewallReloadFailed { error: io::Error },
 }
 
-type SwarmEv<Rq, Rs, THandleErr> = SwarmEvent<BehaviourEvent<Rq, Rs>, THandleErr>;
+type SwarmEv<Rq, Rs, TCustomEv, THandleErr> = SwarmEvent<BehaviourEvent<Rq, Rs, TCustomEv>, THandleErr>;
 
-impl<Rq: RqRsMessage, Rs: RqRsMessage, THandleErr> TryFrom<SwarmEv<Rq, Rs, THandleErr>> for NetworkEvent {
+impl<Rq: RqRsMessage, Rs: RqRsMessage, TCustomEv, THandleErr> TryFrom<SwarmEv<Rq, Rs, TCustomEv, THandleErr>>
+    for NetworkEvent
+{
     type Error = ();
-    fn try_from(value: SwarmEv<Rq, Rs, THandleErr>) -> Result<Self, Self::Error> {
+    fn try_from(value: SwarmEv<Rq, Rs, TCustomEv, THandleErr>) -> Result<Self, Self::Error> {
         match value {
             SwarmEvent::Behaviour(BehaviourEvent::InboundFailure {
                 request_id,
@@ -847,6 +3137,74 @@ impl<Rq: RqRsMessage, Rs: RqRsMessage, THandleErr> TryFrom<SwarmEv<Rq, Rs, THand
                 peer,
                 failure,
             }),
+            SwarmEvent::Behaviour(BehaviourEvent::ObservedAddr {
+                peer,
+                address,
+                promoted,
+            }) => Ok(NetworkEvent::ObservedAddr {
+                peer,
+                address,
+                promoted,
+            }),
+            SwarmEvent::Behaviour(BehaviourEvent::ConnectionEvicted { peer, in_favor_of }) => {
+                Ok(NetworkEvent::ConnectionEvicted { peer, in_favor_of })
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::PeerFlapping { peer, connects }) => {
+                Ok(NetworkEvent::PeerFlapping { peer, connects })
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::PeerIdentityRotated { peer, new_peer, addrs }) => {
+                Ok(NetworkEvent::PeerIdentityRotated { peer, new_peer, addrs })
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::PeerUnresponsive { peer, missed }) => {
+                Ok(NetworkEvent::PeerUnresponsive { peer, missed })
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::InboundQueueHigh { depth }) => {
+                Ok(NetworkEvent::InboundQueueHigh { depth })
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::InboundQueueLow { depth }) => {
+                Ok(NetworkEvent::InboundQueueLow { depth })
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::MailboxReceipt {
+                request_id,
+                recipient,
+                status,
+            }) => Ok(NetworkEvent::MailboxReceipt {
+                request_id,
+                recipient,
+                status,
+            }),
+            SwarmEvent::Behaviour(BehaviourEvent::TransferProgress {
+                peer,
+                transfer_id,
+                is_inbound,
+                transferred,
+                total,
+            }) => Ok(NetworkEvent::TransferProgress {
+                peer,
+                transfer_id,
+                is_inbound,
+                transferred,
+                total,
+            }),
+            SwarmEvent::Behaviour(BehaviourEvent::TransferFinished {
+                peer,
+                transfer_id,
+                result,
+            }) => Ok(NetworkEvent::TransferFinished {
+                peer,
+                transfer_id,
+                result,
+            }),
+            SwarmEvent::Behaviour(BehaviourEvent::PeerOnline { peer, status }) => {
+                Ok(NetworkEvent::PeerOnline { peer, status })
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::PeerOffline { peer }) => Ok(NetworkEvent::PeerOffline { peer }),
+            SwarmEvent::Behaviour(BehaviourEvent::PeerStatusChanged { peer, status }) => {
+                Ok(NetworkEvent::PeerStatusChanged { peer, status })
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::PeerHello { peer, hello }) => {
+                Ok(NetworkEvent::PeerHello { peer, hello })
+            }
             SwarmEvent::ConnectionEstablished {
                 peer_id,
                 endpoint,
@@ -863,6 +3221,9 @@ impl<Rq: RqRsMessage, Rs: RqRsMessage, THandleErr> TryFrom<SwarmEv<Rq, Rs, THand
                 num_established,
                 cause,
             } => {
+                // Reasons that were set by the local peer are only known to the behaviour, and are set by the
+                // event loop.
+                let reason = CloseReason::from_cause(cause.as_ref());
                 let cause = match cause {
                     Some(ConnectionError::IO(e)) => Some(e),
                     _ => None,
@@ -872,6 +3233,7 @@ impl<Rq: RqRsMessage, Rs: RqRsMessage, THandleErr> TryFrom<SwarmEv<Rq, Rs, THand
                     num_established,
                     endpoint,
                     cause,
+                    reason,
                 })
             }
             SwarmEvent::IncomingConnectionError {
@@ -898,7 +3260,228 @@ impl<Rq: RqRsMessage, Rs: RqRsMessage, THandleErr> TryFrom<SwarmEv<Rq, Rs, THand
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Callback for failures, set with [`NetworkBuilder::with_error_hook`].
+pub(crate) type ErrorHook = Arc<dyn Fn(NetworkFailure<'_>) + Send + Sync>;
+
+/// Failure that is reported to the hook set with [`NetworkBuilder::with_error_hook`].
+///
+/// The failures are reported independently of whether they are also returned to the caller or forwarded as
+/// [`NetworkEvent`].
+#[derive(Debug)]
+pub enum NetworkFailure<'a> {
+    /// Receiving an inbound request or sending the response failed.
+    Inbound {
+        peer: PeerId,
+        request_id: RequestId,
+        failure: &'a InboundFailure,
+    },
+    /// Sending an outbound request or receiving the response failed.
+    Outbound {
+        peer: PeerId,
+        request_id: RequestId,
+        failure: &'a OutboundFailure,
+    },
+    /// Dialing a peer failed.
+    Dial { peer: Option<PeerId>, error: &'a DialErr },
+    /// An inbound connection failed during its initial handshake.
+    IncomingConnection {
+        local_addr: &'a Multiaddr,
+        send_back_addr: &'a Multiaddr,
+        error: &'a ConnectionErr,
+    },
+    /// An established connection was closed because of an I/O error.
+    ConnectionClosed {
+        peer: PeerId,
+        endpoint: &'a ConnectedPoint,
+        error: &'a io::Error,
+    },
+}
+
+/// Mirror of a raw [`SwarmEvent`][libp2p::swarm::SwarmEvent] for debugging.
+///
+/// Errors are included with their debug representation. Events of the `NetworkBehaviour` are not mirrored, since they
+/// contain the content of requests and responses.
+#[derive(Debug, Clone)]
+pub enum SwarmDebugEvent {
+    /// A connection to the given peer has been opened.
+    ConnectionEstablished {
+        peer: PeerId,
+        endpoint: ConnectedPoint,
+        num_established: NonZeroU32,
+        /// Errors of concurrent dial attempts to other addresses of the peer.
+        concurrent_dial_errors: Vec<(Multiaddr, String)>,
+    },
+    /// A connection with the given peer has been closed.
+    ConnectionClosed {
+        peer: PeerId,
+        endpoint: ConnectedPoint,
+        num_established: u32,
+        cause: Option<String>,
+    },
+    /// A new connection arrived on a listener and is in the process of protocol negotiation.
+    IncomingConnection {
+        local_addr: Multiaddr,
+        send_back_addr: Multiaddr,
+    },
+    /// An error happened on an inbound connection during its initial handshake.
+    IncomingConnectionError {
+        local_addr: Multiaddr,
+        send_back_addr: Multiaddr,
+        error: String,
+    },
+    /// An error happened on an outbound connection.
+    OutgoingConnectionError { peer: Option<PeerId>, error: String },
+    /// A connection to a banned peer was closed.
+    BannedPeer { peer: PeerId, endpoint: ConnectedPoint },
+    /// A listener reported a new listening address.
+    NewListenAddr(Multiaddr),
+    /// A listening address expired.
+    ExpiredListenAddr(Multiaddr),
+    /// A listener closed.
+    ListenerClosed {
+        addresses: Vec<Multiaddr>,
+        cause: Option<String>,
+    },
+    /// A listener reported a non-fatal error.
+    ListenerError { error: String },
+    /// A new dialing attempt was initiated.
+    Dialing(PeerId),
+}
+
+impl SwarmDebugEvent {
+    // Create a mirror of the swarm event. Returns `None` for events of the `NetworkBehaviour`.
+    fn from_swarm_event<Rq, Rs, TCustomEv, THandleErr: fmt::Debug>(
+        event: &SwarmEv<Rq, Rs, TCustomEv, THandleErr>,
+    ) -> Option<Self> {
+        let ev = match event {
+            SwarmEvent::Behaviour(_) => return None,
+            SwarmEvent::ConnectionEstablished {
+                peer_id,
+                endpoint,
+                num_established,
+                concurrent_dial_errors,
+            } => SwarmDebugEvent::ConnectionEstablished {
+                peer: *peer_id,
+                endpoint: endpoint.clone(),
+                num_established: *num_established,
+                concurrent_dial_errors: concurrent_dial_errors
+                    .iter()
+                    .flatten()
+                    .map(|(addr, e)| (addr.clone(), format!("{:?}", e)))
+                    .collect(),
+            },
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                endpoint,
+                num_established,
+                cause,
+            } => SwarmDebugEvent::ConnectionClosed {
+                peer: *peer_id,
+                endpoint: endpoint.clone(),
+                num_established: *num_established,
+                cause: cause.as_ref().map(|e| format!("{:?}", e)),
+            },
+            SwarmEvent::IncomingConnection {
+                local_addr,
+                send_back_addr,
+            } => SwarmDebugEvent::IncomingConnection {
+                local_addr: local_addr.clone(),
+                send_back_addr: send_back_addr.clone(),
+            },
+            SwarmEvent::IncomingConnectionError {
+                local_addr,
+                send_back_addr,
+                error,
+            } => SwarmDebugEvent::IncomingConnectionError {
+                local_addr: local_addr.clone(),
+                send_back_addr: send_back_addr.clone(),
+                error: format!("{:?}", error),
+            },
+            SwarmEvent::OutgoingConnectionError { peer_id, error } => SwarmDebugEvent::OutgoingConnectionError {
+                peer: *peer_id,
+                error: format!("{:?}", error),
+            },
+            SwarmEvent::BannedPeer { peer_id, endpoint } => SwarmDebugEvent::BannedPeer {
+                peer: *peer_id,
+                endpoint: endpoint.clone(),
+            },
+            SwarmEvent::NewListenAddr { address, .. } => SwarmDebugEvent::NewListenAddr(address.clone()),
+            SwarmEvent::ExpiredListenAddr { address, .. } => SwarmDebugEvent::ExpiredListenAddr(address.clone()),
+            SwarmEvent::ListenerClosed { addresses, reason, .. } => SwarmDebugEvent::ListenerClosed {
+                addresses: addresses.clone(),
+                cause: reason.as_ref().err().map(|e| format!("{:?}", e)),
+            },
+            SwarmEvent::ListenerError { error, .. } => SwarmDebugEvent::ListenerError {
+                error: format!("{:?}", error),
+            },
+            SwarmEvent::Dialing(peer) => SwarmDebugEvent::Dialing(*peer),
+        };
+        Some(ev)
+    }
+}
+
+/// Capacities of the bounded channels between the components of the [`Network`].
+///
+/// None of the channels drops data or buffers unboundedly if it is full, instead the sending side waits until there
+/// is capacity again, which propagates the backpressure:
+/// - `commands`: [`Network`] methods wait until the event loop processed earlier commands.
+/// - `notify_handler`: The swarm stops polling the behaviour until the connection handler took the pending events,
+///   which delays new requests and events on all connections.
+/// - `connection_events`: A connection stops polling its handler, and thus reading from its substreams, until the
+///   behaviour processed the pending events of that connection.
+/// - `firewall`: Requests that need a rule or approval wait until the firewall request could be sent, at the latest
+///   until the firewall timeout.
+/// - `requests`: Inbound requests are delivered according to the [`ChannelSinkConfig`] of the channel, or rejected with
+///   [`NetworkBuilder::with_inbound_backpressure`].
+///
+/// The firewall and request channels are passed to [`NetworkBuilder::new`], use
+/// [`ChannelCapacities::firewall_channel`] and [`ChannelCapacities::requests_channel`] to create them with the
+/// configured capacities.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelCapacities {
+    /// Capacity of the channel for commands from the [`Network`] to the event loop.
+    pub commands: usize,
+    /// Number of events from the behaviour that are buffered for each connection handler.
+    pub notify_handler: NonZeroUsize,
+    /// Number of events from each connection handler that are buffered for the behaviour.
+    pub connection_events: usize,
+    /// Capacity of the channel for firewall requests.
+    pub firewall: usize,
+    /// Capacity of the channel for inbound requests.
+    pub requests: usize,
+}
+
+impl Default for ChannelCapacities {
+    fn default() -> Self {
+        ChannelCapacities {
+            commands: 10,
+            notify_handler: NonZeroUsize::new(8).expect("Capacity is not zero."),
+            connection_events: 7,
+            firewall: 10,
+            requests: 10,
+        }
+    }
+}
+
+impl ChannelCapacities {
+    /// Create the channel for firewall requests with the configured capacity.
+    pub fn firewall_channel<TRq>(&self) -> (mpsc::Sender<FirewallRequest<TRq>>, mpsc::Receiver<FirewallRequest<TRq>>) {
+        mpsc::channel(self.firewall)
+    }
+
+    /// Create the channel for inbound requests with the configured capacity.
+    pub fn requests_channel<Rq, Rs>(
+        &self,
+        config: ChannelSinkConfig,
+    ) -> (
+        EventChannel<ReceiveRequest<Rq, Rs>>,
+        mpsc::Receiver<ReceiveRequest<Rq, Rs>>,
+    ) {
+        EventChannel::new(self.requests, config)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ConnectionLimits {
     max_pending_incoming: Option<u32>,
     max_pending_outgoing: Option<u32>,
@@ -934,6 +3517,16 @@ impl From<ConnectionLimits> for Libp2pConnectionLimits {
 }
 
 impl ConnectionLimits {
+    // Limits for established connections, which are enforced by the behaviour.
+    fn established(&self) -> EstablishedLimits {
+        EstablishedLimits {
+            incoming: self.max_established_incoming,
+            outgoing: self.max_established_outgoing,
+            per_peer: self.max_established_per_peer,
+            total: self.max_established_total,
+        }
+    }
+
     /// Configures the maximum number of concurrently incoming connections being established.
     pub fn with_max_pending_incoming(mut self, limit: Option<u32>) -> Self {
         self.max_pending_incoming = limit;
@@ -998,22 +3591,92 @@ pub enum DialErr {
     Aborted,
     /// The peer identity obtained on the connection did not
     /// match the one that was expected.
-    #[error("Wrong peer ID, obtained: {obtained:?}")]
-    WrongPeerId { obtained: PeerId },
+    #[error("Wrong peer ID on {address}, obtained: {obtained:?}")]
+    WrongPeerId { obtained: PeerId, address: Multiaddr },
     /// The provided peer identity is invalid.
     #[error("Invalid peer ID: {0:?}")]
     InvalidPeerId(Multihash),
     /// An I/O error occurred on the connection.
     #[error("An I/O error occurred on the connection: {0}.")]
     ConnectionIo(io::Error),
-    /// An error occurred while negotiating the transport protocol(s) on a connection.
-    #[error("An error occurred while negotiating the transport protocol(s) on a connection: `{0:?}`.")]
-    Transport(Vec<(Multiaddr, TransportError<io::Error>)>),
+    /// Dialing failed on all attempted addresses, with the failure reason for each address.
+    #[error("Dialing failed on all addresses: `{0:?}`.")]
+    Transport(Vec<(Multiaddr, AddressFailure)>),
     /// The communication system was shut down before the dialing attempt resolved.
     #[error("The network event-loop was shut down.")]
     Shutdown,
 }
 
+impl DialErr {
+    /// The attempted addresses and the reason why dialing each of them failed.
+    ///
+    /// Empty if the dial failed before any address was attempted.
+    pub fn attempts(&self) -> Vec<(&Multiaddr, &AddressFailure)> {
+        match self {
+            DialErr::Transport(attempts) => attempts.iter().map(|(a, f)| (a, f)).collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Reason why dialing a single address failed.
+#[derive(Error, Debug)]
+pub enum AddressFailure {
+    /// The connection attempt timed out.
+    #[error("Connection attempt timed out.")]
+    Timeout,
+    /// The remote refused the connection.
+    #[error("Connection refused.")]
+    Refused,
+    /// The network or host is not reachable.
+    #[error("Address is unreachable.")]
+    Unreachable,
+    /// The address is not supported by the transport.
+    #[error("Multiaddress not supported.")]
+    NotSupported,
+    /// The connection was established, but the security or muxer upgrade failed.
+    #[error("Connection handshake failed: {0}")]
+    Handshake(HandshakeFailure),
+    /// Another I/O error occurred.
+    #[error("I/O error: {0}")]
+    Io(io::Error),
+}
+
+impl From<TransportError<io::Error>> for AddressFailure {
+    fn from(err: TransportError<io::Error>) -> Self {
+        match err {
+            TransportError::MultiaddrNotSupported(_) => AddressFailure::NotSupported,
+            TransportError::Other(e) => match e.kind() {
+                io::ErrorKind::TimedOut => AddressFailure::Timeout,
+                io::ErrorKind::ConnectionRefused => AddressFailure::Refused,
+                io::ErrorKind::AddrNotAvailable => AddressFailure::Unreachable,
+                _ if is_unreachable(&e) => AddressFailure::Unreachable,
+                _ => match HandshakeFailure::find(&e) {
+                    Some(failure) => AddressFailure::Handshake(failure),
+                    None => AddressFailure::Io(e),
+                },
+            },
+        }
+    }
+}
+
+// `ErrorKind::NetworkUnreachable` and `ErrorKind::HostUnreachable` are not stable yet, check the raw os error
+// (ENETUNREACH, EHOSTUNREACH) instead.
+#[cfg(target_os = "linux")]
+fn is_unreachable(e: &io::Error) -> bool {
+    matches!(e.raw_os_error(), Some(101) | Some(113))
+}
+
+#[cfg(target_os = "macos")]
+fn is_unreachable(e: &io::Error) -> bool {
+    matches!(e.raw_os_error(), Some(51) | Some(65))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn is_unreachable(_: &io::Error) -> bool {
+    false
+}
+
 impl TryFrom<DialError> for DialErr {
     type Error = ();
     fn try_from(err: DialError) -> Result<Self, Self::Error> {
@@ -1023,12 +3686,17 @@ impl TryFrom<DialError> for DialErr {
                 DialErr::ConnectionLimit { limit, current }
             }
             DialError::LocalPeerId => DialErr::LocalPeerId,
-            DialError::WrongPeerId { obtained, .. } => DialErr::WrongPeerId { obtained },
+            DialError::WrongPeerId { obtained, endpoint } => DialErr::WrongPeerId {
+                obtained,
+                address: endpoint.get_remote_address().clone(),
+            },
             DialError::InvalidPeerId(hash) => DialErr::InvalidPeerId(hash),
             DialError::DialPeerConditionFalse(_) => return Err(()),
             DialError::Aborted => DialErr::Aborted,
             DialError::ConnectionIo(e) => DialErr::ConnectionIo(e),
-            DialError::Transport(addrs) => DialErr::Transport(addrs),
+            DialError::Transport(attempts) => {
+                DialErr::Transport(attempts.into_iter().map(|(a, e)| (a, e.into())).collect())
+            }
             DialError::NoAddresses => DialErr::NoAddresses,
         };
         Ok(e)
@@ -1056,13 +3724,29 @@ pub enum ConnectionErr {
     /// Pending connection attempt has been aborted.
     #[error("Pending connection attempt has been aborted.")]
     Aborted,
+    /// The security and muxer upgrade did not complete within the configured timeout.
+    ///
+    /// See [`NetworkBuilder::with_handshake_timeout`].
+    #[error("Connection handshake timed out.")]
+    HandshakeTimeout,
+    /// The security or muxer upgrade failed.
+    #[error("Connection handshake failed: {0}")]
+    Handshake(HandshakeFailure),
 }
 
 impl From<PendingConnectionError<TransportError<io::Error>>> for ConnectionErr {
     fn from(value: PendingConnectionError<TransportError<io::Error>>) -> Self {
         match value {
+            PendingConnectionError::Transport(TransportError::Other(e)) | PendingConnectionError::IO(e)
+                if is_handshake_timeout(&e) =>
+            {
+                ConnectionErr::HandshakeTimeout
+            }
             PendingConnectionError::Transport(TransportError::Other(e)) | PendingConnectionError::IO(e) => {
-                ConnectionErr::Io(e)
+                match HandshakeFailure::find(&e) {
+                    Some(failure) => ConnectionErr::Handshake(failure),
+                    None => ConnectionErr::Io(e),
+                }
             }
             PendingConnectionError::WrongPeerId { obtained, .. } => ConnectionErr::WrongPeerId { obtained },
             PendingConnectionError::ConnectionLimit(ConnectionLimit { limit, current }) => {
@@ -1074,6 +3758,41 @@ impl From<PendingConnectionError<TransportError<io::Error>>> for ConnectionErr {
     }
 }
 
+// Marker for the I/O error of a connection that did not complete the handshake within the timeout.
+#[derive(Debug)]
+struct HandshakeTimeout;
+
+impl fmt::Display for HandshakeTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Connection handshake timed out")
+    }
+}
+
+impl std::error::Error for HandshakeTimeout {}
+
+// Close connections whose establishment, including the security and muxer upgrade, did not complete within the
+// timeout.
+fn with_handshake_timeout(
+    transport: Boxed<(PeerId, StreamMuxerBox)>,
+    timeout: Duration,
+) -> Boxed<(PeerId, StreamMuxerBox)> {
+    TransportTimeout::new(transport, timeout)
+        .map_err(|e| match e {
+            TransportTimeoutError::Timeout => io::Error::new(io::ErrorKind::TimedOut, HandshakeTimeout),
+            TransportTimeoutError::TimerError(e) | TransportTimeoutError::Other(e) => e,
+        })
+        .boxed()
+}
+
+// Whether the error, or any I/O error that it wraps, was caused by the handshake timeout.
+fn is_handshake_timeout(error: &io::Error) -> bool {
+    match error.get_ref() {
+        Some(inner) if inner.is::<HandshakeTimeout>() => true,
+        Some(inner) => inner.downcast_ref::<io::Error>().map_or(false, is_handshake_timeout),
+        None => false,
+    }
+}
+
 /// Error on the [Transport][libp2p::Transport].
 #[derive(Error, Debug)]
 pub enum TransportErr {
@@ -1085,6 +3804,18 @@ pub enum TransportErr {
     Io(io::Error),
 }
 
+impl TransportErr {
+    /// Kind of the underlying I/O error, e.g. [`io::ErrorKind::AddrInUse`] or [`io::ErrorKind::PermissionDenied`].
+    ///
+    /// `None` if the address is not supported by the transport.
+    pub fn kind(&self) -> Option<io::ErrorKind> {
+        match self {
+            TransportErr::MultiaddrNotSupported(_) => None,
+            TransportErr::Io(e) => Some(e.kind()),
+        }
+    }
+}
+
 impl From<TransportError<io::Error>> for TransportErr {
     fn from(err: TransportError<io::Error>) -> Self {
         match err {
@@ -1098,16 +3829,40 @@ impl From<TransportError<io::Error>> for TransportErr {
 #[derive(Error, Debug)]
 pub enum ListenErr {
     /// Listening on the address failed on the transport layer.
-    #[error("Transport error: {0}")]
-    Transport(TransportErr),
+    #[error("Listening on {address} failed: {error}")]
+    Transport {
+        /// The address on which listening was attempted.
+        address: Multiaddr,
+        /// The error of the transport.
+        error: TransportErr,
+    },
     /// The communication system was shut down before the listening attempt resolved.
     #[error("The network event-loop was shut down.")]
     Shutdown,
 }
 
-impl From<TransportError<io::Error>> for ListenErr {
-    fn from(err: TransportError<io::Error>) -> Self {
-        ListenErr::Transport(err.into())
+impl ListenErr {
+    pub(crate) fn transport(address: Multiaddr, err: TransportError<io::Error>) -> Self {
+        ListenErr::Transport {
+            address,
+            error: err.into(),
+        }
+    }
+
+    /// The address on which listening failed.
+    pub fn address(&self) -> Option<&Multiaddr> {
+        match self {
+            ListenErr::Transport { address, .. } => Some(address),
+            ListenErr::Shutdown => None,
+        }
+    }
+
+    /// Kind of the underlying I/O error, e.g. [`io::ErrorKind::AddrInUse`] if the port is already in use.
+    pub fn kind(&self) -> Option<io::ErrorKind> {
+        match self {
+            ListenErr::Transport { error, .. } => error.kind(),
+            ListenErr::Shutdown => None,
+        }
     }
 }
 
@@ -1117,6 +3872,9 @@ pub enum ListenRelayErr {
     /// The relay protocol is not supported.
     #[error("Relay Protocol not enabled.")]
     ProtocolNotSupported,
+    /// The relay is not allowed by the [`RelayPolicy`].
+    #[error("Relay not allowed.")]
+    NotAllowed,
     /// Establishing a connection to the relay failed.
     #[error("Dial Relay Error: {0}")]
     DialRelay(#[from] DialErr),
@@ -1125,6 +3883,26 @@ pub enum ListenRelayErr {
     Listen(ListenErr),
 }
 
+/// Error on probing a remote peer.
+#[derive(Error, Debug)]
+pub enum ProbeErr {
+    /// The ping protocol is not supported.
+    #[error("Ping Protocol not enabled.")]
+    ProtocolNotSupported,
+    /// Establishing a connection to the peer failed.
+    #[error("Dial Error: {0}")]
+    Dial(#[from] DialErr),
+    /// The ping failed.
+    #[error("Ping Error: {0}")]
+    Ping(PingErr),
+    /// The connection to the peer was closed before the ping completed.
+    #[error("Connection closed.")]
+    ConnectionClosed,
+    /// The network task was shut down.
+    #[error("Network task was shut down.")]
+    Shutdown,
+}
+
 impl TryFrom<DialError> for ListenRelayErr {
     type Error = <DialErr as TryFrom<DialError>>::Error;
     fn try_from(err: DialError) -> Result<Self, Self::Error> {
@@ -1132,8 +3910,19 @@ impl TryFrom<DialError> for ListenRelayErr {
     }
 }
 
-impl From<TransportError<io::Error>> for ListenRelayErr {
-    fn from(err: TransportError<io::Error>) -> Self {
-        ListenRelayErr::Listen(err.into())
-    }
+/// Error on importing, exporting or persisting identity keys.
+#[derive(Error, Debug)]
+pub enum KeyErr {
+    /// Reading or writing the key file failed.
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    /// The keys could not be encoded or decoded.
+    #[error("Decoding error: {0}")]
+    Decoding(#[from] DecodingError),
+    /// The input is not a valid PEM document of libp2p keys.
+    #[error("Invalid PEM document.")]
+    InvalidPem,
+    /// Only an authenticated noise keypair without the identity keys is available.
+    #[error("No identity keys.")]
+    NoIdentityKeys,
 }
@@ -0,0 +1,44 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use super::Network;
+use crate::{
+    behaviour::{OutboundFailure, RqRsMessage},
+    firewall::FwRequest,
+};
+use futures::{future::BoxFuture, FutureExt};
+use libp2p::PeerId;
+
+/// Common interface of [`Network`] and the [`MockNetwork`][crate::MockNetwork], so that an application can be written
+/// against this trait and tested with the mock instead of real peers.
+///
+/// The methods are the same as the ones of [`Network`], but return boxed futures.
+pub trait NetworkApi<Rq, Rs>: Send {
+    /// Get the [`PeerId`] of the local peer.
+    fn peer_id(&self) -> PeerId;
+
+    /// Send a new request to a remote peer, see [`Network::send_request`].
+    fn send_request(&mut self, peer: PeerId, request: Rq) -> BoxFuture<'_, Result<Rs, OutboundFailure>>;
+
+    /// Check whether there is an established connection to the peer, see [`Network::is_connected`].
+    fn is_connected(&mut self, peer: PeerId) -> BoxFuture<'_, bool>;
+}
+
+impl<Rq, Rs, TRq> NetworkApi<Rq, Rs> for Network<Rq, Rs, TRq>
+where
+    Rq: RqRsMessage,
+    Rs: RqRsMessage,
+    TRq: FwRequest<Rq>,
+{
+    fn peer_id(&self) -> PeerId {
+        Network::peer_id(self)
+    }
+
+    fn send_request(&mut self, peer: PeerId, request: Rq) -> BoxFuture<'_, Result<Rs, OutboundFailure>> {
+        Network::send_request(self, peer, request).boxed()
+    }
+
+    fn is_connected(&mut self, peer: PeerId) -> BoxFuture<'_, bool> {
+        Network::is_connected(self, peer).boxed()
+    }
+}
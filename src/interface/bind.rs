@@ -0,0 +1,135 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use futures::{future::BoxFuture, FutureExt};
+use libp2p::{
+    core::{
+        multiaddr::{Multiaddr, Protocol},
+        transport::{Transport, TransportError},
+    },
+    tcp::{tokio::TcpStream, TokioTcpConfig},
+};
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+};
+use tokio::net::TcpSocket;
+
+/// Local addresses that outgoing TCP connections are bound to, e.g. to dial via a specific interface on a multi-homed
+/// host.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OutboundBinding {
+    /// IP addresses of the local interfaces. A dial is bound to the first address of the same IP version as the
+    /// remote address.
+    pub local_addrs: Vec<IpAddr>,
+    /// Fail dials to remote addresses of an IP version for which no local address is configured, e.g. to only allow
+    /// connections via a VPN. Otherwise the operating system chooses the interface for these dials.
+    pub strict: bool,
+}
+
+impl OutboundBinding {
+    // Local address to which a connection to the remote is bound.
+    fn local_addr_for(&self, remote: &SocketAddr) -> Option<IpAddr> {
+        self.local_addrs
+            .iter()
+            .find(|a| a.is_ipv4() == remote.is_ipv4())
+            .copied()
+    }
+}
+
+// TCP transport that binds outgoing connections to the local addresses of the `OutboundBinding`.
+// Listening is delegated to the inner transport.
+#[derive(Debug, Clone)]
+pub struct BoundTcpTransport {
+    inner: TokioTcpConfig,
+    binding: OutboundBinding,
+}
+
+impl BoundTcpTransport {
+    pub fn new(inner: TokioTcpConfig, binding: OutboundBinding) -> Self {
+        BoundTcpTransport { inner, binding }
+    }
+}
+
+impl Transport for BoundTcpTransport {
+    type Output = TcpStream;
+    type Error = io::Error;
+    type Listener = <TokioTcpConfig as Transport>::Listener;
+    type ListenerUpgrade = <TokioTcpConfig as Transport>::ListenerUpgrade;
+    type Dial = BoxFuture<'static, Result<TcpStream, io::Error>>;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<Self::Listener, TransportError<Self::Error>> {
+        self.inner.listen_on(addr)
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>> {
+        let remote = match socket_addr(&addr) {
+            Some(remote) => remote,
+            None => return Ok(self.inner.dial(addr)?.boxed()),
+        };
+        match self.binding.local_addr_for(&remote) {
+            Some(local) => Ok(connect(local, remote).boxed()),
+            None if self.binding.strict => Err(TransportError::Other(io::Error::new(
+                io::ErrorKind::AddrNotAvailable,
+                "no local address is configured for the IP version of the remote",
+            ))),
+            None => Ok(self.inner.dial(addr)?.boxed()),
+        }
+    }
+
+    fn dial_as_listener(self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>> {
+        self.dial(addr)
+    }
+
+    fn address_translation(&self, listen: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+        self.inner.address_translation(listen, observed)
+    }
+}
+
+// Connect to the remote from a socket that is bound to the local address.
+async fn connect(local: IpAddr, remote: SocketAddr) -> io::Result<TcpStream> {
+    let socket = match remote {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    socket.bind(SocketAddr::new(local, 0))?;
+    let stream = socket.connect(remote).await?;
+    Ok(TcpStream(stream))
+}
+
+// Parse a `/ip4/../tcp/..` or `/ip6/../tcp/..` address, optionally followed by the peer id.
+fn socket_addr(addr: &Multiaddr) -> Option<SocketAddr> {
+    let mut iter = addr.iter();
+    let ip = match iter.next()? {
+        Protocol::Ip4(ip) => IpAddr::from(ip),
+        Protocol::Ip6(ip) => IpAddr::from(ip),
+        _ => return None,
+    };
+    let port = match iter.next()? {
+        Protocol::Tcp(port) => port,
+        _ => return None,
+    };
+    match iter.next() {
+        None | Some(Protocol::P2p(_)) => Some(SocketAddr::new(ip, port)),
+        Some(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn select_local_addr() {
+        let binding = OutboundBinding {
+            local_addrs: vec!["::1".parse().unwrap(), "10.0.0.2".parse().unwrap()],
+            strict: true,
+        };
+        let remote = socket_addr(&"/ip4/10.0.0.1/tcp/4001".parse().unwrap()).unwrap();
+        assert_eq!(binding.local_addr_for(&remote), Some("10.0.0.2".parse().unwrap()));
+        let remote = socket_addr(&"/ip6/::2/tcp/4001".parse().unwrap()).unwrap();
+        assert_eq!(binding.local_addr_for(&remote), Some("::1".parse().unwrap()));
+        assert!(socket_addr(&"/dns/example.com/tcp/4001".parse().unwrap()).is_none());
+        assert!(socket_addr(&"/ip4/10.0.0.1/tcp/4001/ws".parse().unwrap()).is_none());
+    }
+}
@@ -0,0 +1,78 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use super::ConnectionLimits;
+use crate::firewall::{FirewallRules, RuleKind};
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Configuration of a [`NetworkBuilder`][super::NetworkBuilder] that can be loaded from a config file in any format
+/// that is supported by serde, e.g. TOML or YAML.
+///
+/// All fields are optional. Unset fields keep the defaults of the builder.
+///
+/// ```
+/// # use p2p::NetworkConfig;
+/// let config: NetworkConfig = serde_json::from_str(
+///     r#"{
+///         "request_timeout": { "secs": 30, "nanos": 0 },
+///         "firewall_default": "AllowAll",
+///         "support_mdns": false
+///     }"#,
+/// )
+/// .unwrap();
+/// assert!(!config.support_mdns);
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkConfig {
+    /// See [`NetworkBuilder::with_request_timeout`][super::NetworkBuilder::with_request_timeout].
+    pub request_timeout: Option<Duration>,
+    /// See [`NetworkBuilder::with_connection_timeout`][super::NetworkBuilder::with_connection_timeout].
+    pub connection_timeout: Option<Duration>,
+    /// See [`NetworkBuilder::with_firewall_timeout`][super::NetworkBuilder::with_firewall_timeout].
+    pub firewall_timeout: Option<Duration>,
+    /// See [`NetworkBuilder::with_connections_limit`][super::NetworkBuilder::with_connections_limit].
+    pub connections_limit: Option<ConnectionLimits>,
+    /// Default rule of the firewall.
+    ///
+    /// [`RuleKind::Restricted`] can not be configured from a file, and is treated like no default rule.
+    pub firewall_default: Option<RuleKind>,
+    /// Use the mDNS protocol for peer discovery in the local network.
+    pub support_mdns: bool,
+    /// Support the relay protocol, for dialing and listening via relays.
+    pub support_relay: bool,
+    /// Support the ping protocol, for measuring the round-trip time to connected peers.
+    pub support_ping: bool,
+    /// See [`NetworkBuilder::with_relay_candidates`][super::NetworkBuilder::with_relay_candidates].
+    pub relays: Vec<(PeerId, Multiaddr)>,
+    /// See [`NetworkBuilder::with_bootstrap_peers`][super::NetworkBuilder::with_bootstrap_peers].
+    pub bootstrap_peers: Vec<(PeerId, Multiaddr)>,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        NetworkConfig {
+            request_timeout: None,
+            connection_timeout: None,
+            firewall_timeout: None,
+            connections_limit: None,
+            firewall_default: None,
+            support_mdns: true,
+            support_relay: true,
+            support_ping: false,
+            relays: Vec::new(),
+            bootstrap_peers: Vec::new(),
+        }
+    }
+}
+
+impl NetworkConfig {
+    // Firewall rules with the configured default rule.
+    pub(super) fn firewall_rules<TRq>(&self) -> FirewallRules<TRq> {
+        let mut rules = FirewallRules::empty();
+        rules.set_default(self.firewall_default.and_then(RuleKind::into_rule));
+        rules
+    }
+}
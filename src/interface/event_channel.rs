@@ -94,6 +94,27 @@ impl<T> EventChannel<T> {
             }
         }
     }
+
+    /// Check if a new event can be sent directly through the channel, without being buffered, dropped, or blocking.
+    ///
+    /// If not, the waker of the current task is notified once the receiver read from the channel.
+    pub(crate) fn poll_capacity(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let has_buffered = self.buffer.as_ref().map(|(b, _)| !b.is_empty()).unwrap_or(false);
+        match self.inner.poll_ready(cx) {
+            Poll::Ready(_) if !has_buffered => Poll::Ready(()),
+            _ => Poll::Pending,
+        }
+    }
+
+    /// Create another sender for the same channel, with the same configuration but its own buffer.
+    pub(crate) fn split(&self) -> Self {
+        EventChannel {
+            inner: self.inner.clone(),
+            buffer: self.buffer.as_ref().map(|(_, cap)| (VecDeque::new(), *cap)),
+            use_inner: self.use_inner,
+            waker: None,
+        }
+    }
 }
 
 /// Implement [`Sink`] for sending events through the underlying channel.
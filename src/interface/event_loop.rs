@@ -3,33 +3,140 @@
 
 use crate::{
     assemble_relayed_addr,
-    behaviour::{BehaviourEvent, NetworkBehaviour},
-    firewall::{FirewallRules, FwRequest, Rule},
-    interface::NetworkEvent,
-    AddressInfo, DialErr, EventChannel, ListenErr, ListenRelayErr, Listener, OutboundFailure, ReceiveRequest,
-    RelayNotSupported, RequestId, RqRsMessage,
+    behaviour::{
+        AwaitingFirewall, BehaviourEvent, BlockErr, CloseReason, EnqueueErr, EventSource, ExcessConnectionPolicy,
+        Hello, NetworkBehaviour, RelayCandidate, RelayPolicy, RequestMetadata, RequestPriority, RequestStatus,
+        RttSample, UnansweredRequest,
+    },
+    dialable_addr,
+    firewall::{capability::CapabilityToken, FirewallRules, FwRequest, Rule, RuleKind},
+    interface::{
+        firewall_watcher::{FirewallWatcher, RuleChange},
+        noise::BannedPeers,
+        sharded::is_routed_to_shard,
+        ErrorHook, NetworkEvent, NetworkFailure, RelayReservation, SwarmDebugEvent,
+    },
+    timer::{Delay, Instant},
+    AddressInfo, ConnectionErr, ConnectionLimits, DialErr, EventChannel, ListenErr, ListenRelayErr, Listener,
+    NetworkInfo, NetworkStats, OutboundFailure, PeerStats, ProbeErr, QueuedResponse, ReceiveRequest, ReceivedBlob,
+    RecentEvent, RelayNotSupported, RelayStats, RequestId, RqRsMessage, StateDump, TimedResponse,
 };
 use futures::{
     channel::{mpsc, oneshot},
     prelude::*,
+    stream::FuturesUnordered,
 };
+
+#[cfg(feature = "metrics")]
+use super::NetworkMetrics;
+#[cfg(feature = "otel")]
+use super::Telemetry;
 use libp2p::{
     core::{connection::ListenerId, ConnectedPoint},
-    swarm::{NetworkBehaviour as Libp2pNetworkBehaviour, Swarm, SwarmEvent},
+    swarm::{
+        dial_opts::{DialOpts, PeerCondition},
+        ConnectionError, NetworkBehaviour as Libp2pNetworkBehaviour, Swarm, SwarmEvent,
+    },
     Multiaddr, PeerId,
 };
 use smallvec::SmallVec;
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, io,
+    pin::Pin,
+    sync::RwLockWriteGuard,
+    task::{Context, Poll},
+    time::Duration,
+};
 
 pub type Ack = ();
 
+// Time that changes of the local addresses are given to settle before connectivity is recovered.
+const ADDRESS_SETTLE_DELAY: Duration = Duration::from_secs(1);
+
 /// Perform actions on the Swarm.
 /// The return value is sent back through the `return_tx` oneshot channel.
 pub enum SwarmCommand<Rq, Rs, TRq> {
     SendRequest {
         peer: PeerId,
         request: Rq,
-        return_tx: oneshot::Sender<Result<Rs, OutboundFailure>>,
+        return_tx: oneshot::Sender<Result<TimedResponse<Rs>, OutboundFailure>>,
+    },
+
+    SendRequestTracked {
+        peer: PeerId,
+        request: Rq,
+        status_tx: mpsc::UnboundedSender<RequestStatus>,
+        return_tx: oneshot::Sender<Result<TimedResponse<Rs>, OutboundFailure>>,
+        id_tx: oneshot::Sender<RequestId>,
+    },
+
+    SendRequestWithCapability {
+        peer: PeerId,
+        request: Rq,
+        capability: CapabilityToken,
+        return_tx: oneshot::Sender<Result<TimedResponse<Rs>, OutboundFailure>>,
+    },
+    SendRequestWithIdempotencyKey {
+        peer: PeerId,
+        request: Rq,
+        key: String,
+        return_tx: oneshot::Sender<Result<TimedResponse<Rs>, OutboundFailure>>,
+    },
+    SendRequestWithMetadata {
+        peer: PeerId,
+        request: Rq,
+        metadata: RequestMetadata,
+        priority: RequestPriority,
+        return_tx: oneshot::Sender<Result<TimedResponse<Rs>, OutboundFailure>>,
+    },
+
+    SendViaMailbox {
+        mailbox: PeerId,
+        recipient: PeerId,
+        request: Rq,
+        ttl: Duration,
+        return_tx: oneshot::Sender<RequestId>,
+    },
+
+    GetUnansweredRequests {
+        return_tx: oneshot::Sender<Vec<UnansweredRequest<Rq>>>,
+    },
+    ReconcileRequest {
+        request_id: RequestId,
+        return_tx: oneshot::Sender<io::Result<()>>,
+    },
+    EnqueueRequest {
+        peer: PeerId,
+        request: Rq,
+        ttl: Duration,
+        return_tx: oneshot::Sender<Result<RequestId, EnqueueErr>>,
+    },
+    SendBlob {
+        peer: PeerId,
+        metadata: Rq,
+        data: Vec<u8>,
+        return_tx: oneshot::Sender<RequestId>,
+    },
+    ResumeTransfer {
+        transfer_id: RequestId,
+        return_tx: oneshot::Sender<bool>,
+    },
+    CancelTransfer {
+        transfer_id: RequestId,
+    },
+    PutBlock {
+        data: Vec<u8>,
+        return_tx: oneshot::Sender<Result<Vec<u8>, BlockErr>>,
+    },
+    GetBlock {
+        hash: Vec<u8>,
+        return_tx: oneshot::Sender<Result<Vec<u8>, BlockErr>>,
+    },
+
+    SendRequestToPeers {
+        request: Rq,
+        targets: Vec<(PeerId, oneshot::Sender<Result<TimedResponse<Rs>, OutboundFailure>>)>,
     },
 
     ConnectPeer {
@@ -43,6 +150,38 @@ pub enum SwarmCommand<Rq, Rs, TRq> {
     GetConnections {
         return_tx: oneshot::Sender<Vec<(PeerId, Vec<ConnectedPoint>)>>,
     },
+    GetNetworkInfo {
+        return_tx: oneshot::Sender<NetworkInfo>,
+    },
+    GetSelfAddrs {
+        return_tx: oneshot::Sender<Vec<Multiaddr>>,
+    },
+    SetConnectionLimits {
+        limits: ConnectionLimits,
+        policy: ExcessConnectionPolicy,
+        return_tx: oneshot::Sender<Ack>,
+    },
+    SetPeerPriority {
+        peer: PeerId,
+        priority: u8,
+        return_tx: oneshot::Sender<Ack>,
+    },
+    AddPersistentPeer {
+        peer: PeerId,
+        return_tx: oneshot::Sender<Ack>,
+    },
+    RemovePersistentPeer {
+        peer: PeerId,
+        return_tx: oneshot::Sender<Ack>,
+    },
+    RemovePeerPriority {
+        peer: PeerId,
+        return_tx: oneshot::Sender<Ack>,
+    },
+    Probe {
+        peer: PeerId,
+        return_tx: oneshot::Sender<Result<RttSample, ProbeErr>>,
+    },
 
     StartListening {
         address: Multiaddr,
@@ -53,6 +192,21 @@ pub enum SwarmCommand<Rq, Rs, TRq> {
         relay_addr: Option<Multiaddr>,
         return_tx: oneshot::Sender<Result<Multiaddr, ListenRelayErr>>,
     },
+    StartSelectedRelayedListening {
+        return_tx: oneshot::Sender<Result<Multiaddr, ListenRelayErr>>,
+    },
+    GetRelayReservations {
+        return_tx: oneshot::Sender<Vec<RelayReservation>>,
+    },
+    GetRelayCandidates {
+        return_tx: oneshot::Sender<Vec<RelayCandidate>>,
+    },
+    GetRelayPolicy {
+        return_tx: oneshot::Sender<RelayPolicy>,
+    },
+    SetRelayPolicy {
+        policy: RelayPolicy,
+    },
     GetListeners {
         return_tx: oneshot::Sender<Vec<Listener>>,
     },
@@ -82,6 +236,42 @@ pub enum SwarmCommand<Rq, Rs, TRq> {
         address: Multiaddr,
         return_tx: oneshot::Sender<Ack>,
     },
+    TagPeer {
+        peer: PeerId,
+        tag: String,
+        return_tx: oneshot::Sender<Ack>,
+    },
+    UntagPeer {
+        peer: PeerId,
+        tag: String,
+        return_tx: oneshot::Sender<Ack>,
+    },
+    GetPeerTags {
+        peer: PeerId,
+        return_tx: oneshot::Sender<Vec<String>>,
+    },
+    GetTaggedPeers {
+        tag: String,
+        return_tx: oneshot::Sender<Vec<PeerId>>,
+    },
+    SetTagRule {
+        tag: String,
+        rule: Rule<TRq>,
+        return_tx: oneshot::Sender<Ack>,
+    },
+    RemoveTagRule {
+        tag: String,
+        return_tx: oneshot::Sender<Ack>,
+    },
+    SetTagPriority {
+        tag: String,
+        priority: u8,
+        return_tx: oneshot::Sender<Ack>,
+    },
+    RemoveTagPriority {
+        tag: String,
+        return_tx: oneshot::Sender<Ack>,
+    },
 
     AddDialingRelay {
         peer: PeerId,
@@ -123,7 +313,36 @@ pub enum SwarmCommand<Rq, Rs, TRq> {
         peer: PeerId,
         return_tx: oneshot::Sender<Ack>,
     },
+    SetPriorityRule {
+        priority: RequestPriority,
+        rule: Rule<TRq>,
+        return_tx: oneshot::Sender<Ack>,
+    },
+    RemovePriorityRule {
+        priority: RequestPriority,
+        return_tx: oneshot::Sender<Ack>,
+    },
+    GetAwaitingFirewall {
+        return_tx: oneshot::Sender<AwaitingFirewall>,
+    },
+    GetInboundQueueDepth {
+        return_tx: oneshot::Sender<usize>,
+    },
+    AbortPending {
+        peer: PeerId,
+        return_tx: oneshot::Sender<Vec<RequestId>>,
+    },
+    ResolvePendingApprovals {
+        peer: PeerId,
+        is_allowed: bool,
+        rule: Option<Rule<TRq>>,
+        return_tx: oneshot::Sender<Vec<RequestId>>,
+    },
 
+    DisconnectPeer {
+        peer: PeerId,
+        return_tx: oneshot::Sender<Ack>,
+    },
     BanPeer {
         peer: PeerId,
         return_tx: oneshot::Sender<Ack>,
@@ -132,10 +351,52 @@ pub enum SwarmCommand<Rq, Rs, TRq> {
         peer: PeerId,
         return_tx: oneshot::Sender<Ack>,
     },
+    GetIsBanned {
+        peer: PeerId,
+        return_tx: oneshot::Sender<bool>,
+    },
+    GetBannedPeers {
+        return_tx: oneshot::Sender<Vec<PeerId>>,
+    },
 
     ExportAddressInfo {
         return_tx: oneshot::Sender<AddressInfo>,
     },
+    AnnounceRotation {
+        new_peer: PeerId,
+        addrs: Vec<Multiaddr>,
+        return_tx: oneshot::Sender<Vec<PeerId>>,
+    },
+
+    GetStats {
+        return_tx: oneshot::Sender<NetworkStats>,
+    },
+    GetPeerStats {
+        return_tx: oneshot::Sender<HashMap<PeerId, PeerStats>>,
+    },
+    GetRelayStats {
+        return_tx: oneshot::Sender<HashMap<PeerId, RelayStats>>,
+    },
+    SetPresenceStatus {
+        status: Vec<u8>,
+    },
+    GetOnlinePeers {
+        return_tx: oneshot::Sender<HashMap<PeerId, Vec<u8>>>,
+    },
+    GetPeerHello {
+        peer: PeerId,
+        return_tx: oneshot::Sender<Option<Hello>>,
+    },
+    RankPeers {
+        candidates: Vec<PeerId>,
+        return_tx: oneshot::Sender<Vec<PeerId>>,
+    },
+    GetRecentEvents {
+        return_tx: oneshot::Sender<Vec<RecentEvent>>,
+    },
+    DumpState {
+        return_tx: oneshot::Sender<StateDump>,
+    },
 }
 
 /// Central loop that is responsible for all [`Swarm`] interaction.
@@ -144,14 +405,15 @@ pub enum SwarmCommand<Rq, Rs, TRq> {
 /// Operations on the Swarm are performed based on the [`SwarmCommand`]s that are received through the `command_rx`
 /// channel. The outcome for each operation is returned through the oneshot channel that is included in the
 /// [`SwarmCommand`]. No operation is blocking, instead the return-channel is cached until an outcome yields.
-pub struct EventLoop<Rq, Rs, TRq>
+pub struct EventLoop<Rq, Rs, TRq, TCustom>
 where
     Rq: RqRsMessage,
     Rs: RqRsMessage,
     TRq: FwRequest<Rq>,
+    TCustom: Libp2pNetworkBehaviour,
 {
     // libp2p `Swarm` that uses `NetworkBehaviour` as network behaviour protocol.
-    swarm: Swarm<NetworkBehaviour<Rq, Rs, TRq>>,
+    swarm: Swarm<NetworkBehaviour<Rq, Rs, TRq, TCustom>>,
 
     // Channel for to receiving `SwarmCommand`.
     // This will trigger an according action on the Swarm.
@@ -160,15 +422,57 @@ where
 
     // Channel for forwarding inbound requests.
     request_channel: EventChannel<ReceiveRequest<Rq, Rs>>,
+    // Pause inbound requests in the `NetworkBehaviour` while the request channel is full.
+    inbound_backpressure: bool,
+    // Whether inbound requests are currently paused due to backpressure.
+    inbound_paused: bool,
     // Optional channel for forwarding all events on the swarm on listeners and connections.
     event_channel: Option<EventChannel<NetworkEvent>>,
+    // Optional channel for forwarding a mirror of all raw swarm events.
+    swarm_debug_channel: Option<EventChannel<SwarmDebugEvent>>,
+    // Callback that is invoked on each failure.
+    error_hook: Option<ErrorHook>,
+    // Optional channel for forwarding received blobs.
+    blob_channel: Option<EventChannel<ReceivedBlob<Rq>>>,
+    // Optional channel for forwarding the responses to queued requests.
+    queued_response_channel: Option<EventChannel<QueuedResponse<Rs>>>,
+    // Channel for forwarding the events of the custom behaviour, if one was composed into the swarm.
+    custom_channel: Option<EventChannel<TCustom::OutEvent>>,
+
+    // Optional recorder of metrics for swarm events and relay reservations.
+    #[cfg(feature = "metrics")]
+    metrics: Option<NetworkMetrics>,
+    // Optional exporter of request spans and metrics to OpenTelemetry.
+    #[cfg(feature = "otel")]
+    telemetry: Option<Telemetry>,
 
     // Currently active listeners.
     listeners: HashMap<ListenerId, Listener>,
+    // State of the listeners via relays, that are renewed if they are lost.
+    relay_reservations: HashMap<PeerId, ReservationState>,
+    // Delay after which a lost relay reservation is renewed, `None` if lost reservations are not renewed.
+    relay_renewal: Option<Duration>,
+    // Timers for the pending renewals of relay reservations.
+    pending_renewals: FuturesUnordered<future::BoxFuture<'static, PeerId>>,
+    // Interval in which disconnected dialing relays are probed, `None` if relays are not probed.
+    relay_probe_interval: Option<Duration>,
+    // Delay until the next probe of the dialing relays.
+    relay_probe_delay: Option<Delay>,
+    // Peers that are re-dialed when the local listening addresses changed.
+    persistent_peers: HashSet<PeerId>,
+    // Time at which each listener that is not relayed reported its first address.
+    listening_since: HashMap<ListenerId, Instant>,
+    // Delay until connectivity is recovered after the local listening addresses changed.
+    // It is reset on each further change, so that a burst of changes is handled at once.
+    address_change_delay: Option<Delay>,
+    // Watcher of the firewall config file.
+    firewall_watcher: Option<FirewallWatcher>,
+    // Banned peers, shared with the noise upgrade that rejects their connections.
+    banned_peers: BannedPeers,
 
     // Response channels for sent outbound requests.
     // The channels are cached until a response was received or `OutboundFailure` occurred.
-    await_response: HashMap<RequestId, oneshot::Sender<Result<Rs, OutboundFailure>>>,
+    await_response: HashMap<RequestId, oneshot::Sender<Result<TimedResponse<Rs>, OutboundFailure>>>,
     // Response channels for the connection attempts to a remote peer.
     // A result if returned once the remote connected or the dial attempt failed.
     await_connection: HashMap<PeerId, oneshot::Sender<Result<Multiaddr, DialErr>>>,
@@ -180,31 +484,80 @@ where
     // A result is returned once the associated listener reported it's first new listening address, or a listener error
     // occurred. Additionally, an error will be returned if the relay could not be connected.
     await_relayed_listen: HashMap<ListenerId, (PeerId, oneshot::Sender<Result<Multiaddr, ListenRelayErr>>)>,
+    // Response channels for probing a connected peer.
+    // A result is returned once the next ping to the peer succeeded or failed, or if the connection was closed.
+    await_ping: HashMap<PeerId, Vec<oneshot::Sender<Result<RttSample, ProbeErr>>>>,
+    // Response channels for fetching a block from other peers.
+    // A result is returned once a peer provided the block, or the want expired.
+    await_block: HashMap<Vec<u8>, Vec<oneshot::Sender<Result<Vec<u8>, BlockErr>>>>,
+    // Response channel for announcing an identity rotation.
+    // The peers that received the announcement are returned once it was sent to all connected peers.
+    await_rotation: Option<oneshot::Sender<Vec<PeerId>>>,
 }
 
-impl<Rq, Rs, TRq> EventLoop<Rq, Rs, TRq>
+impl<Rq, Rs, TRq, TCustom> EventLoop<Rq, Rs, TRq, TCustom>
 where
     Rq: RqRsMessage,
     Rs: RqRsMessage,
     TRq: FwRequest<Rq>,
+    TCustom: Libp2pNetworkBehaviour,
+    TCustom::OutEvent: fmt::Debug,
 {
     /// Create new instance of en event-loop
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        swarm: Swarm<NetworkBehaviour<Rq, Rs, TRq>>,
+        swarm: Swarm<NetworkBehaviour<Rq, Rs, TRq, TCustom>>,
         command_rx: mpsc::Receiver<SwarmCommand<Rq, Rs, TRq>>,
         request_channel: EventChannel<ReceiveRequest<Rq, Rs>>,
+        inbound_backpressure: bool,
         event_channel: Option<EventChannel<NetworkEvent>>,
+        swarm_debug_channel: Option<EventChannel<SwarmDebugEvent>>,
+        error_hook: Option<ErrorHook>,
+        blob_channel: Option<EventChannel<ReceivedBlob<Rq>>>,
+        queued_response_channel: Option<EventChannel<QueuedResponse<Rs>>>,
+        custom_channel: Option<EventChannel<TCustom::OutEvent>>,
+        relay_renewal: Option<Duration>,
+        relay_probe_interval: Option<Duration>,
+        persistent_peers: HashSet<PeerId>,
+        firewall_watcher: Option<FirewallWatcher>,
+        banned_peers: BannedPeers,
+        #[cfg(feature = "metrics")] metrics: Option<NetworkMetrics>,
+        #[cfg(feature = "otel")] telemetry: Option<Telemetry>,
     ) -> Self {
         EventLoop {
             swarm,
             command_rx,
             request_channel,
+            inbound_backpressure,
+            inbound_paused: false,
             event_channel,
+            swarm_debug_channel,
+            error_hook,
+            blob_channel,
+            queued_response_channel,
+            custom_channel,
+            #[cfg(feature = "metrics")]
+            metrics,
+            #[cfg(feature = "otel")]
+            telemetry,
             listeners: HashMap::new(),
+            relay_reservations: HashMap::new(),
+            relay_renewal,
+            pending_renewals: FuturesUnordered::new(),
+            relay_probe_interval,
+            relay_probe_delay: relay_probe_interval.map(|_| Delay::new(Duration::ZERO)),
+            persistent_peers,
+            listening_since: HashMap::new(),
+            address_change_delay: None,
+            firewall_watcher,
+            banned_peers,
             await_response: HashMap::new(),
             await_connection: HashMap::new(),
             await_listen: HashMap::new(),
             await_relayed_listen: HashMap::new(),
+            await_ping: HashMap::new(),
+            await_block: HashMap::new(),
+            await_rotation: None,
         }
     }
 
@@ -216,75 +569,242 @@ where
     /// down.
     pub async fn run(mut self) {
         loop {
-            if let Some(event_channel) = self.event_channel.as_mut() {
-                futures::select_biased! {
-                    // Drive the swarm and handle events
-                    event = self.swarm.select_next_some() => self.handle_swarm_event(event).await,
-                   // Receive `SwarmCommand`s to initiate operations on the `Swarm`.
-                    command = self.command_rx.next().fuse() => {
-                        if let Some(c) = command {
-                            self.handle_command(c)
-                        } else {
-                            break;
-                        }
-                    },
-                    // Drive request channel to forward inbound requests.
-                    _ = self.request_channel.next().fuse() => {}
-                    // Drive events channel to forward network events.
-                    _ = event_channel.next().fuse() => {}
-                }
-            } else {
-                futures::select_biased! {
-                    event = self.swarm.select_next_some() => self.handle_swarm_event(event).await,
-                    command = self.command_rx.next().fuse() => {
-                        if let Some(c) = command {
-                            self.handle_command(c)
-                        } else {
-                            break;
-                        }
-                    },
-                    _ = self.request_channel.next().fuse() => {}
+            futures::select_biased! {
+                // Drive the swarm and handle events
+                event = self.swarm.select_next_some() => self.handle_swarm_event(event).await,
+                // Receive `SwarmCommand`s to initiate operations on the `Swarm`.
+                command = self.command_rx.next().fuse() => {
+                    if let Some(c) = command {
+                        self.handle_command(c)
+                    } else {
+                        break;
+                    }
+                },
+                // Drive request channel to forward inbound requests.
+                // Resolves to `true` if inbound requests are paused and the channel has capacity again.
+                has_capacity = drive_request_channel(&mut self.request_channel, self.inbound_paused).fuse() => {
+                    if has_capacity {
+                        self.set_inbound_paused(false);
+                    }
+                }
+                // Renew lost relay reservations once their delay elapsed.
+                relay = self.pending_renewals.select_next_some() => self.renew_relay_reservation(relay),
+                // Periodically probe the dialing relays.
+                _ = poll_delay(self.relay_probe_delay.as_mut()).fuse() => self.probe_relays(),
+                // Recover connectivity once changes of the local addresses settled.
+                _ = poll_delay(self.address_change_delay.as_mut()).fuse() => {
+                    let event = self.recover_connectivity();
+                    self.emit_event(event).await
                 }
+                // Periodically check the firewall config file for modifications.
+                _ = poll_delay(self.firewall_watcher.as_mut().map(|w| &mut w.delay)).fuse() => {
+                    self.reload_firewall_config().await
+                }
+                // Drive events channel to forward network events.
+                _ = drive_channel(self.event_channel.as_mut()).fuse() => {}
+                // Drive debug channel to forward raw swarm events.
+                _ = drive_channel(self.swarm_debug_channel.as_mut()).fuse() => {}
+                // Drive blob channel to forward received blobs.
+                _ = drive_channel(self.blob_channel.as_mut()).fuse() => {}
+                // Drive channel to forward the responses to queued requests.
+                _ = drive_channel(self.queued_response_channel.as_mut()).fuse() => {}
+                // Drive channel to forward the events of the custom behaviour.
+                _ = drive_channel(self.custom_channel.as_mut()).fuse() => {}
             }
         }
         self.shutdown();
     }
 
+    // Record the span of an outbound request that was sent without metadata.
+    #[cfg(feature = "otel")]
+    fn trace_outbound(&mut self, request_id: RequestId, peer: PeerId) {
+        if let Some(telemetry) = self.telemetry.as_mut() {
+            let cx = telemetry.start_outbound(peer, None);
+            telemetry.on_request_sent(request_id, cx);
+        }
+    }
+
+    // Pause or resume inbound requests in the `NetworkBehaviour`.
+    fn set_inbound_paused(&mut self, paused: bool) {
+        self.inbound_paused = paused;
+        self.swarm.behaviour_mut().set_inbound_paused(paused);
+    }
+
     // Check if the swarm event yields a result for a previously initiated operation.
     // Optionally forward a `NetworkEvent` for the event.
-    async fn handle_swarm_event<THandleErr>(&mut self, event: SwarmEvent<BehaviourEvent<Rq, Rs>, THandleErr>) {
+    async fn handle_swarm_event<THandleErr: fmt::Debug>(
+        &mut self,
+        event: SwarmEvent<BehaviourEvent<Rq, Rs, TCustom::OutEvent>, THandleErr>,
+    ) {
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.record(&event);
+        }
+        if let Some(debug_tx) = self.swarm_debug_channel.as_mut() {
+            if let Some(ev) = SwarmDebugEvent::from_swarm_event(&event) {
+                let _ = debug_tx.send(ev).await;
+            }
+        }
+        // Behaviour events are already logged by the `NetworkBehaviour` as actions.
+        if !matches!(event, SwarmEvent::Behaviour(..)) {
+            self.swarm
+                .behaviour_mut()
+                .log_event(EventSource::Swarm, || format!("{:?}", event));
+        }
+        // The swarm emits the event right after the connection was closed in the behaviour, which fails the pending
+        // requests of the connection once the reason is resolved.
+        let close_reason = match event {
+            SwarmEvent::ConnectionClosed {
+                ref peer_id, ref cause, ..
+            } => Some(self.swarm.behaviour_mut().resolve_close_reason(peer_id, cause.as_ref())),
+            _ => None,
+        };
+        if let SwarmEvent::ConnectionClosed {
+            peer_id,
+            ref endpoint,
+            cause: Some(ConnectionError::IO(ref error)),
+            ..
+        } = event
+        {
+            self.report_failure(NetworkFailure::ConnectionClosed {
+                peer: peer_id,
+                endpoint,
+                error,
+            });
+        }
         match event {
             SwarmEvent::Behaviour(BehaviourEvent::ReceivedRequest {
                 request_id,
                 peer,
                 request,
-                response_tx,
+                responder,
+                connection,
+                metadata,
+                priority,
+                global_id,
             }) => {
+                #[cfg(feature = "otel")]
+                if let Some(telemetry) = self.telemetry.as_ref() {
+                    telemetry.on_inbound_request(request_id, peer, &metadata);
+                }
                 let received_rq = ReceiveRequest {
                     request_id,
                     peer,
                     request,
-                    response_tx,
+                    responder,
+                    connection,
+                    metadata,
+                    priority,
+                    global_id,
                 };
                 let _ = self.request_channel.send(received_rq).await;
+                if self.inbound_backpressure && !self.inbound_paused {
+                    let channel = &mut self.request_channel;
+                    let is_full = future::poll_fn(|cx| Poll::Ready(channel.poll_capacity(cx).is_pending())).await;
+                    if is_full {
+                        self.set_inbound_paused(true);
+                    }
+                }
+                return;
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::ReceivedBlob {
+                peer,
+                transfer_id,
+                metadata,
+                data,
+            }) => {
+                if let Some(blob_tx) = self.blob_channel.as_mut() {
+                    let blob = ReceivedBlob {
+                        transfer_id,
+                        peer,
+                        metadata,
+                        data,
+                    };
+                    let _ = blob_tx.send(blob).await;
+                }
+                return;
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::QueuedResponse {
+                request_id,
+                peer,
+                result,
+            }) => {
+                if let Some(response_tx) = self.queued_response_channel.as_mut() {
+                    let response = QueuedResponse {
+                        request_id,
+                        peer,
+                        result,
+                    };
+                    let _ = response_tx.send(response).await;
+                }
+                return;
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::BlockReceived { hash, data, .. }) => {
+                for return_tx in self.await_block.remove(&hash).into_iter().flatten() {
+                    let _ = return_tx.send(Ok(data.clone()));
+                }
+                return;
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::BlockWantExpired { hash }) => {
+                for return_tx in self.await_block.remove(&hash).into_iter().flatten() {
+                    let _ = return_tx.send(Err(BlockErr::NotFound));
+                }
+                return;
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Custom(event)) => {
+                if let Some(custom_tx) = self.custom_channel.as_mut() {
+                    let _ = custom_tx.send(event).await;
+                }
                 return;
             }
             SwarmEvent::Behaviour(BehaviourEvent::ReceivedResponse {
-                request_id, response, ..
+                request_id,
+                response,
+                rtt,
+                ..
             }) => {
+                #[cfg(feature = "otel")]
+                if let Some(telemetry) = self.telemetry.as_mut() {
+                    telemetry.on_response(&request_id, rtt);
+                }
                 if let Some(result_tx) = self.await_response.remove(&request_id) {
-                    let _ = result_tx.send(Ok(response));
+                    let _ = result_tx.send(Ok(TimedResponse { response, rtt }));
                 }
                 return;
             }
             SwarmEvent::Behaviour(BehaviourEvent::OutboundFailure {
-                request_id, failure, ..
+                request_id,
+                peer,
+                failure,
             }) => {
+                self.report_failure(NetworkFailure::Outbound {
+                    peer,
+                    request_id,
+                    failure: &failure,
+                });
+                #[cfg(feature = "otel")]
+                if let Some(telemetry) = self.telemetry.as_mut() {
+                    telemetry.on_outbound_failure(&request_id, &failure);
+                }
                 if let Some(result_tx) = self.await_response.remove(&request_id) {
                     let _ = result_tx.send(Err(failure));
                 }
                 return;
             }
+            SwarmEvent::Behaviour(BehaviourEvent::Ping { peer, result }) => {
+                let measured_at = Instant::now();
+                let result = result.map(|rtt| RttSample { rtt, measured_at }).map_err(ProbeErr::Ping);
+                for result_tx in self.await_ping.remove(&peer).into_iter().flatten() {
+                    let _ = result_tx.send(result.clone());
+                }
+                return;
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::RotationAnnounced { peers }) => {
+                if let Some(return_tx) = self.await_rotation.take() {
+                    let _ = return_tx.send(peers);
+                }
+                return;
+            }
             SwarmEvent::ConnectionEstablished {
                 peer_id, ref endpoint, ..
             } => {
@@ -292,22 +812,66 @@ where
                     let _ = result_tx.send(Ok(endpoint.get_remote_address().clone()));
                 }
             }
-            SwarmEvent::OutgoingConnectionError { ref peer_id, error } => {
-                if let Some(peer) = peer_id {
-                    if let Ok(err) = DialErr::try_from(error) {
-                        if let Some(result_tx) = self.await_connection.remove(peer) {
-                            let _ = result_tx.send(Err(err));
-                        }
+            SwarmEvent::OutgoingConnectionError { peer_id, error } => {
+                if let Ok(err) = DialErr::try_from(error) {
+                    self.report_failure(NetworkFailure::Dial {
+                        peer: peer_id,
+                        error: &err,
+                    });
+                    if let Some(result_tx) = peer_id.and_then(|p| self.await_connection.remove(&p)) {
+                        let _ = result_tx.send(Err(err));
                     }
                 }
                 return;
             }
+            SwarmEvent::Behaviour(BehaviourEvent::InboundFailure {
+                request_id,
+                peer,
+                ref failure,
+            }) => {
+                self.report_failure(NetworkFailure::Inbound {
+                    peer,
+                    request_id,
+                    failure,
+                });
+                #[cfg(feature = "otel")]
+                if let Some(telemetry) = self.telemetry.as_ref() {
+                    telemetry.on_inbound_failure(failure);
+                }
+            }
+            // The connection was handed to the shard of the remote peer.
+            SwarmEvent::IncomingConnectionError { ref error, .. } if is_routed_to_shard(error) => {}
+            SwarmEvent::IncomingConnectionError {
+                local_addr,
+                send_back_addr,
+                error,
+            } => {
+                let error = ConnectionErr::from(error);
+                self.report_failure(NetworkFailure::IncomingConnection {
+                    local_addr: &local_addr,
+                    send_back_addr: &send_back_addr,
+                    error: &error,
+                });
+                let event = NetworkEvent::IncomingConnectionError {
+                    local_addr,
+                    send_back_addr,
+                    error,
+                };
+                self.emit_event(event).await;
+                return;
+            }
             SwarmEvent::NewListenAddr {
                 ref address,
                 ref listener_id,
             } => {
                 if let Some(listener) = self.listeners.get_mut(listener_id) {
                     listener.addrs.push(address.clone());
+                    if listener.uses_relay.is_none() {
+                        self.on_listen_addr_change(*listener_id, true);
+                    }
+                }
+                if let Some(event) = self.on_relay_listen_addr(*listener_id, address) {
+                    self.emit_event(event).await;
                 }
                 if let Some((_, result_tx)) = self.await_relayed_listen.remove(listener_id) {
                     let _ = result_tx.send(Ok(address.clone()));
@@ -318,9 +882,17 @@ where
             }
             SwarmEvent::ListenerClosed { ref listener_id, .. } => {
                 self.listeners.remove(listener_id);
+                self.listening_since.remove(listener_id);
+                if let Some(event) = self.on_relay_listener_lost(|s| s.listener_id == Some(*listener_id)) {
+                    self.emit_event(event).await;
+                }
             }
             SwarmEvent::ListenerError { ref listener_id, .. } => {
                 self.listeners.remove(listener_id);
+                self.listening_since.remove(listener_id);
+                if let Some(event) = self.on_relay_listener_lost(|s| s.listener_id == Some(*listener_id)) {
+                    self.emit_event(event).await;
+                }
             }
             SwarmEvent::ExpiredListenAddr {
                 ref listener_id,
@@ -328,6 +900,9 @@ where
             } => {
                 if let Some(listener) = self.listeners.get_mut(listener_id) {
                     listener.addrs.retain(|a| a != address);
+                    if listener.uses_relay.is_none() {
+                        self.on_listen_addr_change(*listener_id, false);
+                    }
                 }
             }
             SwarmEvent::BannedPeer { peer_id, .. } => {
@@ -335,15 +910,244 @@ where
                     let _ = result_tx.send(Err(DialErr::Banned));
                 }
             }
-            SwarmEvent::Behaviour(BehaviourEvent::InboundFailure { .. })
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                num_established: 0,
+                ..
+            } => {
+                for result_tx in self.await_ping.remove(&peer_id).into_iter().flatten() {
+                    let _ = result_tx.send(Err(ProbeErr::ConnectionClosed));
+                }
+                // The reservation is lost if the connection to the relay closed.
+                let lost = self.on_relay_listener_lost(|s| s.status.relay == peer_id && s.listener_id.is_some());
+                if let Some(event) = lost {
+                    self.emit_event(event).await;
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::ObservedAddr { .. })
+            | SwarmEvent::Behaviour(BehaviourEvent::PeerFlapping { .. })
+            | SwarmEvent::Behaviour(BehaviourEvent::PeerIdentityRotated { .. })
+            | SwarmEvent::Behaviour(BehaviourEvent::PeerUnresponsive { .. })
+            | SwarmEvent::Behaviour(BehaviourEvent::InboundQueueHigh { .. })
+            | SwarmEvent::Behaviour(BehaviourEvent::InboundQueueLow { .. })
+            | SwarmEvent::Behaviour(BehaviourEvent::ConnectionEvicted { .. })
+            | SwarmEvent::Behaviour(BehaviourEvent::MailboxReceipt { .. })
+            | SwarmEvent::Behaviour(BehaviourEvent::TransferProgress { .. })
+            | SwarmEvent::Behaviour(BehaviourEvent::TransferFinished { .. })
+            | SwarmEvent::Behaviour(BehaviourEvent::PeerOnline { .. })
+            | SwarmEvent::Behaviour(BehaviourEvent::PeerOffline { .. })
+            | SwarmEvent::Behaviour(BehaviourEvent::PeerStatusChanged { .. })
+            | SwarmEvent::Behaviour(BehaviourEvent::PeerHello { .. })
             | SwarmEvent::Dialing(..)
             | SwarmEvent::ConnectionClosed { .. }
-            | SwarmEvent::IncomingConnection { .. }
-            | SwarmEvent::IncomingConnectionError { .. } => {}
+            | SwarmEvent::IncomingConnection { .. } => {}
+        }
+        if let Ok(mut ev) = NetworkEvent::try_from(event) {
+            if let (NetworkEvent::ConnectionClosed { reason, .. }, Some(resolved)) = (&mut ev, close_reason) {
+                *reason = resolved;
+            }
+            self.emit_event(ev).await;
         }
+    }
+
+    // Lock the banned peers for modification.
+    fn write_banned_peers(&self) -> RwLockWriteGuard<'_, HashSet<PeerId>> {
+        self.banned_peers.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Reload the firewall config file if it was modified, and apply the changed rules.
+    // All changes are applied before any other event is handled, so that no request is checked against a partially
+    // applied config.
+    async fn reload_firewall_config(&mut self) {
+        let result = match self.firewall_watcher.as_mut() {
+            Some(watcher) => watcher.poll_changes(),
+            None => return,
+        };
+        let changes = match result {
+            Ok(changes) => changes,
+            Err(error) => {
+                self.emit_event(NetworkEvent::FirewallReloadFailed { error }).await;
+                return;
+            }
+        };
+        let behaviour = self.swarm.behaviour_mut();
+        for change in changes.iter() {
+            match (change.peer, change.rule.and_then(RuleKind::into_rule)) {
+                (None, rule) => behaviour.set_firewall_default(rule),
+                (Some(peer), Some(rule)) => behaviour.set_peer_rule(peer, rule),
+                (Some(peer), None) => behaviour.remove_peer_rule(peer),
+            }
+        }
+        for RuleChange { peer, rule } in changes {
+            self.emit_event(NetworkEvent::FirewallRuleChanged { peer, rule }).await;
+        }
+    }
+
+    // Invoke the error hook, if there is one.
+    fn report_failure(&self, failure: NetworkFailure<'_>) {
+        if let Some(hook) = self.error_hook.as_ref() {
+            hook(failure);
+        }
+    }
+
+    // Forward the event through the event channel, if there is one.
+    async fn emit_event(&mut self, event: NetworkEvent) {
         if let Some(event_tx) = self.event_channel.as_mut() {
-            if let Ok(ev) = NetworkEvent::try_from(event) {
-                let _ = event_tx.send(ev).await;
+            let _ = event_tx.send(event).await;
+        }
+    }
+
+    // Mark the reservation of a relayed listener as active once the listener reported its address.
+    // Returns `NetworkEvent::RelayReservationRenewed` if the reservation was renewed.
+    fn on_relay_listen_addr(&mut self, listener_id: ListenerId, address: &Multiaddr) -> Option<NetworkEvent> {
+        let state = self
+            .relay_reservations
+            .values_mut()
+            .find(|s| s.listener_id == Some(listener_id))?;
+        if state.status.is_active {
+            return None;
+        }
+        state.status.is_active = true;
+        state.status.address = Some(address.clone());
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.record_relay_reservation(if state.is_renewing { "renewed" } else { "accepted" });
+        }
+        if !state.is_renewing {
+            return None;
+        }
+        state.is_renewing = false;
+        state.status.renewals += 1;
+        Some(NetworkEvent::RelayReservationRenewed {
+            relay: state.status.relay,
+            address: address.clone(),
+        })
+    }
+
+    // Handle the loss of a relayed listener, and schedule its renewal.
+    // Returns `NetworkEvent::RelayReservationLost` if the reservation was active before.
+    fn on_relay_listener_lost<F: Fn(&ReservationState) -> bool>(&mut self, condition_fn: F) -> Option<NetworkEvent> {
+        let relay = self.relay_reservations.values().find(|s| condition_fn(s))?.status.relay;
+        let state = self.relay_reservations.get_mut(&relay)?;
+        if let Some(listener_id) = state.listener_id.take() {
+            self.listeners.remove(&listener_id);
+            let _ = self.swarm.remove_listener(listener_id);
+        }
+        let was_active = state.status.is_active;
+        state.status.is_active = false;
+        state.status.address = None;
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = self.metrics.as_ref().filter(|_| was_active) {
+            metrics.record_relay_reservation("lost");
+        }
+        match self.relay_renewal {
+            // Retry a failed renewal, or renew a reservation that was active before.
+            Some(delay) if was_active || state.is_renewing => {
+                state.is_renewing = true;
+                self.pending_renewals
+                    .push(Delay::new(delay).map(move |_| relay).boxed());
+            }
+            // The initial listening attempt failed, or renewals are disabled.
+            _ => {
+                self.relay_reservations.remove(&relay);
+            }
+        }
+        was_active.then(|| NetworkEvent::RelayReservationLost { relay })
+    }
+
+    // Dial the dialing relays that are not connected, to check that they are reachable.
+    // Connected relays are probed by the ping protocol.
+    fn probe_relays(&mut self) {
+        self.relay_probe_delay = self.relay_probe_interval.map(Delay::new);
+        let candidates = self.swarm.behaviour().relay_candidates();
+        for candidate in candidates {
+            if !self.swarm.is_connected(&candidate.peer) {
+                // Dial failures are recorded by the `NetworkBehaviour`.
+                let _ = self.swarm.dial(candidate.peer);
+            }
+        }
+    }
+
+    // Schedule the recovery of connectivity after an address of a listener that is not relayed changed.
+    // New addresses that a listener reports right after it started listening are not considered as change.
+    fn on_listen_addr_change(&mut self, listener_id: ListenerId, is_new: bool) {
+        let now = Instant::now();
+        let since = *self.listening_since.entry(listener_id).or_insert(now);
+        if is_new && now.duration_since(since) < ADDRESS_SETTLE_DELAY {
+            return;
+        }
+        self.address_change_delay = Some(Delay::new(ADDRESS_SETTLE_DELAY));
+    }
+
+    // Recover connectivity after the local listening addresses changed, since established connections may still be
+    // bound to an address that is gone: re-dial the persistent peers, and refresh the active relay reservations.
+    fn recover_connectivity(&mut self) -> NetworkEvent {
+        self.address_change_delay = None;
+        let mut redialed_peers = Vec::new();
+        for peer in self.persistent_peers.iter() {
+            // Establish a new connection even if the peer is connected, since the existing one might be broken.
+            let opts = DialOpts::peer_id(*peer).condition(PeerCondition::Always).build();
+            if self.swarm.dial(opts).is_ok() {
+                redialed_peers.push(*peer);
+            }
+        }
+        let refreshed_relays: Vec<_> = self
+            .relay_reservations
+            .values()
+            .filter(|s| s.status.is_active)
+            .map(|s| s.status.relay)
+            .collect();
+        for relay in refreshed_relays.iter() {
+            self.refresh_relay_reservation(*relay);
+        }
+        NetworkEvent::LocalAddressesChanged {
+            listen_addrs: self.swarm.listeners().cloned().collect(),
+            redialed_peers,
+            refreshed_relays,
+        }
+    }
+
+    // Start listening via the relay anew, and handle it like the renewal of a lost reservation.
+    fn refresh_relay_reservation(&mut self, relay: PeerId) {
+        let state = match self.relay_reservations.get_mut(&relay) {
+            Some(s) => s,
+            None => return,
+        };
+        if let Some(listener_id) = state.listener_id.take() {
+            self.listeners.remove(&listener_id);
+            let _ = self.swarm.remove_listener(listener_id);
+        }
+        state.status.is_active = false;
+        state.status.address = None;
+        state.is_renewing = true;
+        self.renew_relay_reservation(relay);
+    }
+
+    // Start listening via the relay again, if the reservation was not removed in the meantime.
+    fn renew_relay_reservation(&mut self, relay: PeerId) {
+        let state = match self.relay_reservations.get_mut(&relay) {
+            Some(s) if s.listener_id.is_none() => s,
+            _ => return,
+        };
+        // Don't renew reservations on relays that were denied in the meantime.
+        if !self.swarm.behaviour().is_relay_allowed(&relay) {
+            self.relay_reservations.remove(&relay);
+            return;
+        }
+        match self.swarm.listen_on(state.relayed_addr.clone()) {
+            Ok(listener_id) => {
+                state.listener_id = Some(listener_id);
+                let listener = Listener {
+                    addrs: SmallVec::new(),
+                    uses_relay: Some(relay),
+                };
+                self.listeners.insert(listener_id, listener);
+            }
+            Err(_) => {
+                if let Some(delay) = self.relay_renewal {
+                    self.pending_renewals
+                        .push(Delay::new(delay).map(move |_| relay).boxed());
+                }
             }
         }
     }
@@ -361,6 +1165,142 @@ where
             } => {
                 let request_id = self.swarm.behaviour_mut().send_request(peer, request);
                 self.await_response.insert(request_id, return_tx);
+                #[cfg(feature = "otel")]
+                self.trace_outbound(request_id, peer);
+            }
+            SwarmCommand::SendRequestTracked {
+                peer,
+                request,
+                status_tx,
+                return_tx,
+                id_tx,
+            } => {
+                let behaviour = self.swarm.behaviour_mut();
+                let request_id = behaviour.send_request(peer, request);
+                behaviour.track_request(peer, request_id, status_tx);
+                self.await_response.insert(request_id, return_tx);
+                #[cfg(feature = "otel")]
+                self.trace_outbound(request_id, peer);
+                let _ = id_tx.send(request_id);
+            }
+            SwarmCommand::SendRequestWithCapability {
+                peer,
+                request,
+                capability,
+                return_tx,
+            } => {
+                let request_id = self
+                    .swarm
+                    .behaviour_mut()
+                    .send_request_with_capability(peer, request, &capability);
+                self.await_response.insert(request_id, return_tx);
+                #[cfg(feature = "otel")]
+                self.trace_outbound(request_id, peer);
+            }
+            SwarmCommand::SendRequestWithIdempotencyKey {
+                peer,
+                request,
+                key,
+                return_tx,
+            } => {
+                let request_id = self
+                    .swarm
+                    .behaviour_mut()
+                    .send_request_with_idempotency_key(peer, request, &key);
+                self.await_response.insert(request_id, return_tx);
+                #[cfg(feature = "otel")]
+                self.trace_outbound(request_id, peer);
+            }
+            SwarmCommand::SendRequestWithMetadata {
+                peer,
+                request,
+                #[cfg_attr(not(feature = "otel"), allow(unused_mut))]
+                mut metadata,
+                priority,
+                return_tx,
+            } => {
+                // Propagate the trace context of the request to the remote.
+                #[cfg(feature = "otel")]
+                let span_cx = self
+                    .telemetry
+                    .as_ref()
+                    .map(|t| t.start_outbound(peer, Some(&mut metadata)));
+                let request_id = self
+                    .swarm
+                    .behaviour_mut()
+                    .send_request_with_metadata(peer, request, &metadata, priority);
+                self.await_response.insert(request_id, return_tx);
+                #[cfg(feature = "otel")]
+                if let (Some(telemetry), Some(cx)) = (self.telemetry.as_mut(), span_cx) {
+                    telemetry.on_request_sent(request_id, cx);
+                }
+            }
+            SwarmCommand::SendViaMailbox {
+                mailbox,
+                recipient,
+                request,
+                ttl,
+                return_tx,
+            } => {
+                let request_id = self
+                    .swarm
+                    .behaviour_mut()
+                    .send_via_mailbox(mailbox, recipient, request, ttl);
+                let _ = return_tx.send(request_id);
+            }
+            SwarmCommand::GetUnansweredRequests { return_tx } => {
+                let requests = self.swarm.behaviour().unanswered_requests();
+                let _ = return_tx.send(requests);
+            }
+            SwarmCommand::ReconcileRequest { request_id, return_tx } => {
+                let res = self.swarm.behaviour_mut().reconcile_request(request_id);
+                let _ = return_tx.send(res);
+            }
+            SwarmCommand::EnqueueRequest {
+                peer,
+                request,
+                ttl,
+                return_tx,
+            } => {
+                let res = self.swarm.behaviour_mut().enqueue_request(peer, request, ttl);
+                let _ = return_tx.send(res);
+            }
+            SwarmCommand::SendBlob {
+                peer,
+                metadata,
+                data,
+                return_tx,
+            } => {
+                let transfer_id = self.swarm.behaviour_mut().send_blob(peer, metadata, data);
+                let _ = return_tx.send(transfer_id);
+            }
+            SwarmCommand::ResumeTransfer { transfer_id, return_tx } => {
+                let is_resumed = self.swarm.behaviour_mut().resume_transfer(transfer_id);
+                let _ = return_tx.send(is_resumed);
+            }
+            SwarmCommand::CancelTransfer { transfer_id } => self.swarm.behaviour_mut().cancel_transfer(transfer_id),
+            SwarmCommand::PutBlock { data, return_tx } => {
+                let _ = return_tx.send(self.swarm.behaviour_mut().put_block(data));
+            }
+            SwarmCommand::GetBlock { hash, return_tx } => match self.swarm.behaviour_mut().want_block(hash.clone()) {
+                Ok(Some(data)) => {
+                    let _ = return_tx.send(Ok(data));
+                }
+                Ok(None) => self.await_block.entry(hash).or_default().push(return_tx),
+                Err(e) => {
+                    let _ = return_tx.send(Err(e));
+                }
+            },
+            SwarmCommand::SendRequestToPeers { request, targets } => {
+                let (peers, return_txs): (Vec<_>, Vec<_>) = targets.into_iter().unzip();
+                #[cfg(feature = "otel")]
+                let traced_peers = peers.clone();
+                let request_ids = self.swarm.behaviour_mut().send_request_to_peers(peers, request);
+                #[cfg(feature = "otel")]
+                for (request_id, peer) in request_ids.iter().zip(traced_peers) {
+                    self.trace_outbound(*request_id, peer);
+                }
+                self.await_response.extend(request_ids.into_iter().zip(return_txs));
             }
             SwarmCommand::ConnectPeer { peer, return_tx } => match self.swarm.dial(peer) {
                 Ok(_) => {
@@ -381,18 +1321,80 @@ where
                 let connections = self.swarm.behaviour().established_connections();
                 let _ = return_tx.send(connections);
             }
+            SwarmCommand::GetNetworkInfo { return_tx } => {
+                let _ = return_tx.send(self.network_info());
+            }
+            SwarmCommand::GetSelfAddrs { return_tx } => {
+                let _ = return_tx.send(self.self_addrs());
+            }
+            SwarmCommand::SetConnectionLimits {
+                limits,
+                policy,
+                return_tx,
+            } => {
+                self.swarm
+                    .behaviour_mut()
+                    .set_connection_limits(limits.established(), policy);
+                let _ = return_tx.send(());
+            }
+            SwarmCommand::SetPeerPriority {
+                peer,
+                priority,
+                return_tx,
+            } => {
+                self.swarm.behaviour_mut().set_peer_priority(peer, priority);
+                let _ = return_tx.send(());
+            }
+            SwarmCommand::RemovePeerPriority { peer, return_tx } => {
+                self.swarm.behaviour_mut().remove_peer_priority(&peer);
+                let _ = return_tx.send(());
+            }
+            SwarmCommand::AddPersistentPeer { peer, return_tx } => {
+                self.persistent_peers.insert(peer);
+                let _ = return_tx.send(());
+            }
+            SwarmCommand::RemovePersistentPeer { peer, return_tx } => {
+                self.persistent_peers.remove(&peer);
+                let _ = return_tx.send(());
+            }
+            SwarmCommand::Probe { peer, return_tx } => self.probe(peer, return_tx),
             SwarmCommand::StartListening { address, return_tx } => self.start_listening(address, return_tx),
             SwarmCommand::StartRelayedListening {
                 relay,
                 relay_addr,
                 return_tx,
             } => self.start_relayed_listening(relay, relay_addr, return_tx),
+            SwarmCommand::StartSelectedRelayedListening { return_tx } => {
+                match self.swarm.behaviour_mut().select_listening_relay() {
+                    Some((relay, relay_addr)) => self.start_relayed_listening(relay, Some(relay_addr), return_tx),
+                    None if !self.swarm.behaviour().is_relay_enabled() => {
+                        let _ = return_tx.send(Err(ListenRelayErr::ProtocolNotSupported));
+                    }
+                    None => {
+                        let _ = return_tx.send(Err(ListenRelayErr::DialRelay(DialErr::NoAddresses)));
+                    }
+                }
+            }
+            SwarmCommand::GetRelayCandidates { return_tx } => {
+                let candidates = self.swarm.behaviour().relay_candidates();
+                let _ = return_tx.send(candidates);
+            }
+            SwarmCommand::GetRelayPolicy { return_tx } => {
+                let policy = self.swarm.behaviour().relay_policy().clone();
+                let _ = return_tx.send(policy);
+            }
+            SwarmCommand::SetRelayPolicy { policy } => self.swarm.behaviour_mut().set_relay_policy(policy),
+            SwarmCommand::GetRelayReservations { return_tx } => {
+                let reservations = self.relay_reservations.values().map(|s| s.status.clone()).collect();
+                let _ = return_tx.send(reservations);
+            }
             SwarmCommand::GetListeners { return_tx } => {
                 let listeners = self.listeners.values().cloned().collect();
                 let _ = return_tx.send(listeners);
             }
             SwarmCommand::StopListening { return_tx } => {
                 self.remove_listener(|_| true);
+                self.relay_reservations.clear();
                 let _ = return_tx.send(());
             }
             SwarmCommand::StopListeningAddr { address, return_tx } => {
@@ -401,6 +1403,7 @@ where
             }
             SwarmCommand::StopListeningRelay { relay, return_tx } => {
                 let had_relay = self.remove_listener(|l: &Listener| l.uses_relay == Some(relay));
+                let had_relay = self.relay_reservations.remove(&relay).is_some() || had_relay;
                 let _ = return_tx.send(had_relay);
             }
             SwarmCommand::GetPeerAddrs { peer, return_tx } => {
@@ -423,6 +1426,40 @@ where
                 self.swarm.behaviour_mut().remove_address(&peer, &address);
                 let _ = return_tx.send(());
             }
+            SwarmCommand::TagPeer { peer, tag, return_tx } => {
+                self.swarm.behaviour_mut().tag_peer(peer, tag);
+                let _ = return_tx.send(());
+            }
+            SwarmCommand::UntagPeer { peer, tag, return_tx } => {
+                self.swarm.behaviour_mut().untag_peer(&peer, &tag);
+                let _ = return_tx.send(());
+            }
+            SwarmCommand::GetPeerTags { peer, return_tx } => {
+                let _ = return_tx.send(self.swarm.behaviour().peer_tags(&peer));
+            }
+            SwarmCommand::GetTaggedPeers { tag, return_tx } => {
+                let _ = return_tx.send(self.swarm.behaviour().peers_with_tag(&tag));
+            }
+            SwarmCommand::SetTagRule { tag, rule, return_tx } => {
+                self.swarm.behaviour_mut().set_tag_rule(tag, rule);
+                let _ = return_tx.send(());
+            }
+            SwarmCommand::RemoveTagRule { tag, return_tx } => {
+                self.swarm.behaviour_mut().remove_tag_rule(&tag);
+                let _ = return_tx.send(());
+            }
+            SwarmCommand::SetTagPriority {
+                tag,
+                priority,
+                return_tx,
+            } => {
+                self.swarm.behaviour_mut().set_tag_priority(tag, priority);
+                let _ = return_tx.send(());
+            }
+            SwarmCommand::RemoveTagPriority { tag, return_tx } => {
+                self.swarm.behaviour_mut().remove_tag_priority(&tag);
+                let _ = return_tx.send(());
+            }
             SwarmCommand::AddDialingRelay {
                 peer,
                 address,
@@ -475,23 +1512,119 @@ where
                 self.swarm.behaviour_mut().remove_peer_rule(peer);
                 let _ = return_tx.send(());
             }
+            SwarmCommand::SetPriorityRule {
+                priority,
+                rule,
+                return_tx,
+            } => {
+                self.swarm.behaviour_mut().set_priority_rule(priority, rule);
+                let _ = return_tx.send(());
+            }
+            SwarmCommand::RemovePriorityRule { priority, return_tx } => {
+                self.swarm.behaviour_mut().remove_priority_rule(priority);
+                let _ = return_tx.send(());
+            }
+            SwarmCommand::GetAwaitingFirewall { return_tx } => {
+                let _ = return_tx.send(self.swarm.behaviour().awaiting_firewall());
+            }
+            SwarmCommand::GetInboundQueueDepth { return_tx } => {
+                let _ = return_tx.send(self.swarm.behaviour().inbound_queue_depth());
+            }
+            SwarmCommand::AbortPending { peer, return_tx } => {
+                let aborted = self.swarm.behaviour_mut().abort_pending(peer);
+                let _ = return_tx.send(aborted);
+            }
+            SwarmCommand::ResolvePendingApprovals {
+                peer,
+                is_allowed,
+                rule,
+                return_tx,
+            } => {
+                let resolved = self
+                    .swarm
+                    .behaviour_mut()
+                    .resolve_pending_approvals(peer, is_allowed, rule);
+                let _ = return_tx.send(resolved);
+            }
+            SwarmCommand::DisconnectPeer { peer, return_tx } => {
+                self.swarm
+                    .behaviour_mut()
+                    .disconnect_peer(peer, CloseReason::Disconnect);
+                let _ = return_tx.send(());
+            }
             SwarmCommand::BanPeer { peer, return_tx } => {
+                self.write_banned_peers().insert(peer);
+                self.swarm.behaviour_mut().set_close_reason(&peer, CloseReason::Banned);
                 self.swarm.ban_peer_id(peer);
+                self.swarm.behaviour_mut().remove_peer_addresses(&peer);
                 let _ = return_tx.send(());
             }
             SwarmCommand::UnbanPeer { peer, return_tx } => {
+                self.write_banned_peers().remove(&peer);
                 self.swarm.unban_peer_id(peer);
                 let _ = return_tx.send(());
             }
+            SwarmCommand::GetIsBanned { peer, return_tx } => {
+                let is_banned = self
+                    .banned_peers
+                    .read()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .contains(&peer);
+                let _ = return_tx.send(is_banned);
+            }
+            SwarmCommand::GetBannedPeers { return_tx } => {
+                let banned = self.banned_peers.read().unwrap_or_else(|e| e.into_inner());
+                let _ = return_tx.send(banned.iter().copied().collect());
+            }
             SwarmCommand::ExportAddressInfo { return_tx } => {
                 let state = self.swarm.behaviour_mut().export_address_info();
                 let _ = return_tx.send(state);
             }
+            SwarmCommand::AnnounceRotation {
+                new_peer,
+                addrs,
+                return_tx,
+            } => {
+                self.swarm.behaviour_mut().announce_rotation(new_peer, addrs);
+                self.await_rotation = Some(return_tx);
+            }
+            SwarmCommand::GetStats { return_tx } => {
+                let stats = self.swarm.behaviour().stats();
+                let _ = return_tx.send(stats);
+            }
+            SwarmCommand::GetPeerStats { return_tx } => {
+                let stats = self.swarm.behaviour().peer_stats();
+                let _ = return_tx.send(stats);
+            }
+            SwarmCommand::GetRelayStats { return_tx } => {
+                let stats = self.swarm.behaviour().relay_stats();
+                let _ = return_tx.send(stats);
+            }
+            SwarmCommand::SetPresenceStatus { status } => self.swarm.behaviour_mut().set_presence_status(status),
+            SwarmCommand::GetOnlinePeers { return_tx } => {
+                let peers = self.swarm.behaviour().online_peers();
+                let _ = return_tx.send(peers);
+            }
+            SwarmCommand::GetPeerHello { peer, return_tx } => {
+                let hello = self.swarm.behaviour().peer_hello(&peer);
+                let _ = return_tx.send(hello);
+            }
+            SwarmCommand::RankPeers { candidates, return_tx } => {
+                let ranked = self.swarm.behaviour().rank_peers(candidates);
+                let _ = return_tx.send(ranked);
+            }
+            SwarmCommand::GetRecentEvents { return_tx } => {
+                let events = self.swarm.behaviour().recent_events();
+                let _ = return_tx.send(events);
+            }
+            SwarmCommand::DumpState { return_tx } => {
+                let _ = return_tx.send(self.dump_state());
+            }
         }
     }
 
     fn start_listening(&mut self, address: Multiaddr, return_tx: oneshot::Sender<Result<Multiaddr, ListenErr>>) {
-        match self.swarm.listen_on(address) {
+        match self.swarm.listen_on(address.clone()) {
             Ok(listener_id) => {
                 self.await_listen.insert(listener_id, return_tx);
                 let new_listener = Listener {
@@ -501,7 +1634,7 @@ where
                 self.listeners.insert(listener_id, new_listener);
             }
             Err(err) => {
-                let _ = return_tx.send(Err(ListenErr::from(err)));
+                let _ = return_tx.send(Err(ListenErr::transport(address, err)));
             }
         }
     }
@@ -518,6 +1651,10 @@ where
             let _ = return_tx.send(Err(err));
             return;
         }
+        if !self.swarm.behaviour().is_relay_allowed(&relay) {
+            let _ = return_tx.send(Err(ListenRelayErr::NotAllowed));
+            return;
+        }
 
         if let Some(addr) = relay_addr.as_ref() {
             self.swarm.behaviour_mut().add_address(relay, addr.clone());
@@ -531,10 +1668,25 @@ where
                 return;
             }
         };
-        let listen = self.swarm.listen_on(relayed_addr).map_err(ListenRelayErr::from);
+        let listen = self
+            .swarm
+            .listen_on(relayed_addr.clone())
+            .map_err(|err| ListenRelayErr::Listen(ListenErr::transport(relayed_addr.clone(), err)));
         match listen {
             Ok(listener_id) => {
                 self.await_relayed_listen.insert(listener_id, (relay, return_tx));
+                let state = ReservationState {
+                    listener_id: Some(listener_id),
+                    relayed_addr,
+                    is_renewing: false,
+                    status: RelayReservation {
+                        relay,
+                        is_active: false,
+                        address: None,
+                        renewals: 0,
+                    },
+                };
+                self.relay_reservations.insert(relay, state);
                 let new_listener = Listener {
                     addrs: SmallVec::new(),
                     uses_relay: Some(relay),
@@ -547,6 +1699,79 @@ where
         }
     }
 
+    // Get the round-trip time to a connected peer.
+    //
+    // Use the sample of the latest ping on the current connections, or wait for the next one if the peer has not been
+    // pinged yet.
+    fn probe(&mut self, peer: PeerId, return_tx: oneshot::Sender<Result<RttSample, ProbeErr>>) {
+        if !self.swarm.behaviour().is_ping_enabled() {
+            let _ = return_tx.send(Err(ProbeErr::ProtocolNotSupported));
+            return;
+        }
+        if let Some(rtt) = self.swarm.behaviour().latest_rtt(&peer) {
+            let _ = return_tx.send(Ok(rtt));
+            return;
+        }
+        if !self.swarm.is_connected(&peer) {
+            let _ = return_tx.send(Err(ProbeErr::ConnectionClosed));
+            return;
+        }
+        self.await_ping.entry(peer).or_default().push(return_tx);
+    }
+
+    // Collect the connection counters and addresses of the swarm.
+    fn network_info(&self) -> NetworkInfo {
+        let info = self.swarm.network_info();
+        let counters = info.connection_counters();
+        NetworkInfo {
+            num_peers: info.num_peers(),
+            num_pending_incoming: counters.num_pending_incoming(),
+            num_pending_outgoing: counters.num_pending_outgoing(),
+            num_established_incoming: counters.num_established_incoming(),
+            num_established_outgoing: counters.num_established_outgoing(),
+            listen_addrs: self.swarm.listeners().cloned().collect(),
+            external_addrs: self.swarm.external_addresses().map(|r| r.addr.clone()).collect(),
+            supported_protocols: self.swarm.behaviour().supported_protocols().to_vec(),
+        }
+    }
+
+    // Dialable addresses of the local peer, from the listening and external addresses of the swarm.
+    fn self_addrs(&self) -> Vec<Multiaddr> {
+        let local_peer = *self.swarm.local_peer_id();
+        let listen_addrs = self.swarm.listeners().cloned();
+        let external_addrs = self.swarm.external_addresses().map(|r| r.addr.clone());
+        let mut addrs = Vec::new();
+        for addr in listen_addrs.chain(external_addrs) {
+            let addr = dialable_addr(local_peer, addr);
+            if !addrs.contains(&addr) {
+                addrs.push(addr);
+            }
+        }
+        addrs
+    }
+
+    // Create a snapshot of the state of the behaviour and the event-loop.
+    fn dump_state(&self) -> StateDump {
+        let behaviour = self.swarm.behaviour();
+        let connections = behaviour
+            .established_connections()
+            .into_iter()
+            .map(|(peer, points)| {
+                let addrs = points.iter().map(|p| p.get_remote_address().clone()).collect();
+                (peer, addrs)
+            })
+            .collect();
+        let firewall = behaviour.get_firewall_config();
+        StateDump {
+            requests: behaviour.pending_requests(),
+            connections,
+            pending_dials: self.await_connection.keys().copied().collect(),
+            firewall_default: firewall.get_default_rule().map(|r| r.kind()),
+            firewall_peer_rules: firewall.peer_rules().map(|(p, r)| (*p, r.kind())).collect(),
+            listeners: self.listeners.values().cloned().collect(),
+        }
+    }
+
     // Remove listeners based on the given condition.
     //
     // Return whether there was at least one listener that matches the condition.
@@ -562,6 +1787,7 @@ where
         for id in remove_listeners {
             let _ = self.listeners.remove(&id);
             let _ = self.swarm.remove_listener(id);
+            self.relay_reservations.retain(|_, s| s.listener_id != Some(id));
         }
         removed_one
     }
@@ -580,5 +1806,112 @@ where
         for (_, (_, return_tx)) in self.await_relayed_listen.drain() {
             let _ = return_tx.send(Err(ListenRelayErr::Listen(ListenErr::Shutdown)));
         }
+        for (_, return_txs) in self.await_ping.drain() {
+            for return_tx in return_txs {
+                let _ = return_tx.send(Err(ProbeErr::Shutdown));
+            }
+        }
+    }
+}
+
+// State of a listener via a relay.
+struct ReservationState {
+    // Current listener, `None` while a renewal is pending.
+    listener_id: Option<ListenerId>,
+    // Address on which the listener was started.
+    relayed_addr: Multiaddr,
+    // Whether the reservation was lost and is renewed.
+    is_renewing: bool,
+    status: RelayReservation,
+}
+
+// Drive the request channel to forward buffered requests.
+// If `is_paused`, resolve to `true` once the channel has capacity for new requests.
+async fn drive_request_channel<T>(channel: &mut EventChannel<T>, is_paused: bool) -> bool {
+    future::poll_fn(|cx| {
+        if channel.poll_next_unpin(cx).is_ready() {
+            return Poll::Ready(false);
+        }
+        if is_paused && channel.poll_capacity(cx).is_ready() {
+            return Poll::Ready(true);
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+// Resolve once the optional delay elapsed.
+// Pends forever if there is no delay.
+async fn poll_delay(delay: Option<&mut Delay>) {
+    match delay {
+        Some(delay) => {
+            let _ = delay.await;
+        }
+        None => future::pending().await,
+    }
+}
+
+// Drive the optional channel to forward buffered events.
+// Pends forever if there is no channel.
+async fn drive_channel<T>(channel: Option<&mut EventChannel<T>>) {
+    match channel {
+        Some(channel) => {
+            let _ = channel.next().await;
+        }
+        None => future::pending().await,
+    }
+}
+
+/// Handle for driving the event loop of a [`Network`][crate::Network] manually, instead of running it in a spawned
+/// task. Returned by [`NetworkBuilder::build_with_driver`][crate::NetworkBuilder::build_with_driver].
+///
+/// The `Network` only makes progress while the driver is polled, either by awaiting it as a `Future`, which resolves
+/// once all `Network` clones were dropped, or step-wise with [`EventLoopDriver::tick`].
+pub struct EventLoopDriver {
+    event_loop: future::BoxFuture<'static, ()>,
+    is_terminated: bool,
+}
+
+impl EventLoopDriver {
+    pub(crate) fn new<Rq, Rs, TRq, TCustom>(event_loop: EventLoop<Rq, Rs, TRq, TCustom>) -> Self
+    where
+        Rq: RqRsMessage,
+        Rs: RqRsMessage,
+        TRq: FwRequest<Rq>,
+        TCustom: Libp2pNetworkBehaviour + Send,
+        TCustom::OutEvent: fmt::Debug,
+    {
+        EventLoopDriver {
+            event_loop: event_loop.run().boxed(),
+            is_terminated: false,
+        }
+    }
+
+    /// Poll the event loop once: all commands, swarm events and timers that are ready are handled, until no further
+    /// progress can be made without new input.
+    ///
+    /// Returns `false` once the event loop shut down because all `Network` clones were dropped.
+    pub fn tick(&mut self) -> bool {
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        let _ = self.poll_unpin(&mut cx);
+        !self.is_terminated
+    }
+
+    /// Whether the event loop shut down.
+    pub fn is_terminated(&self) -> bool {
+        self.is_terminated
+    }
+}
+
+impl Future for EventLoopDriver {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.is_terminated {
+            return Poll::Ready(());
+        }
+        let poll = self.event_loop.poll_unpin(cx);
+        self.is_terminated = poll.is_ready();
+        poll
     }
 }
@@ -0,0 +1,138 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    firewall::{FirewallConfig, RuleKind},
+    timer::Delay,
+};
+use libp2p::PeerId;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+// Change of a firewall rule in the config file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleChange {
+    // `None` for the default rule.
+    pub peer: Option<PeerId>,
+    // `None` if the rule was removed.
+    pub rule: Option<RuleKind>,
+}
+
+// Watcher that polls the modification time of a firewall config file, and reloads it on change.
+pub struct FirewallWatcher {
+    path: PathBuf,
+    interval: Duration,
+    // Delay until the next poll.
+    pub delay: Delay,
+    // Modification time of the file when it was last loaded.
+    modified: Option<SystemTime>,
+    // The config that was last applied.
+    applied: FirewallConfig,
+}
+
+impl FirewallWatcher {
+    // Load the config file initially.
+    pub fn new(path: PathBuf, interval: Duration) -> io::Result<(Self, FirewallConfig)> {
+        let modified = fs::metadata(&path)?.modified().ok();
+        let config = load(&path)?;
+        let watcher = FirewallWatcher {
+            path,
+            interval,
+            delay: Delay::new(interval),
+            modified,
+            applied: config.clone(),
+        };
+        Ok((watcher, config))
+    }
+
+    // Reload the file if it was modified since it was last loaded, and return the changed rules.
+    //
+    // If the file can not be parsed, no rules are changed. A missing file is treated as unchanged, since editors may
+    // replace the file in multiple steps.
+    pub fn poll_changes(&mut self) -> io::Result<Vec<RuleChange>> {
+        self.delay = Delay::new(self.interval);
+        let modified = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata.modified().ok(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        if modified.is_some() && modified == self.modified {
+            return Ok(Vec::new());
+        }
+        let config = load(&self.path)?;
+        self.modified = modified;
+        let changes = diff(&self.applied, &config);
+        self.applied = config;
+        Ok(changes)
+    }
+}
+
+fn load(path: &Path) -> io::Result<FirewallConfig> {
+    let content = fs::read(path)?;
+    serde_json::from_slice(&content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// Rules that differ between the old and the new config.
+fn diff(old: &FirewallConfig, new: &FirewallConfig) -> Vec<RuleChange> {
+    let mut changes = Vec::new();
+    if old.default != new.default {
+        changes.push(RuleChange {
+            peer: None,
+            rule: new.default,
+        });
+    }
+    for (peer, rule) in new.peer_rules.iter() {
+        if old.peer_rules.get(peer) != Some(rule) {
+            changes.push(RuleChange {
+                peer: Some(*peer),
+                rule: Some(*rule),
+            });
+        }
+    }
+    for peer in old.peer_rules.keys() {
+        if !new.peer_rules.contains_key(peer) {
+            changes.push(RuleChange {
+                peer: Some(*peer),
+                rule: None,
+            });
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn config_diff() {
+        let peer_a = PeerId::random();
+        let peer_b = PeerId::random();
+        let old = FirewallConfig {
+            default: Some(RuleKind::Ask),
+            peer_rules: [(peer_a, RuleKind::AllowAll), (peer_b, RuleKind::Ask)]
+                .into_iter()
+                .collect(),
+        };
+        assert!(diff(&old, &old).is_empty());
+
+        let new = FirewallConfig {
+            default: None,
+            peer_rules: [(peer_a, RuleKind::RejectAll)].into_iter().collect(),
+        };
+        let changes = diff(&old, &new);
+        assert_eq!(changes.len(), 3);
+        assert!(changes.contains(&RuleChange { peer: None, rule: None }));
+        assert!(changes.contains(&RuleChange {
+            peer: Some(peer_a),
+            rule: Some(RuleKind::RejectAll)
+        }));
+        assert!(changes.contains(&RuleChange {
+            peer: Some(peer_b),
+            rule: None
+        }));
+    }
+}
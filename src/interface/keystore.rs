@@ -0,0 +1,130 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use super::{InitKeypair, KeyErr};
+use argon2::Argon2;
+use chacha20poly1305::{
+    aead::{Aead, NewAead},
+    Key, XChaCha20Poly1305, XNonce,
+};
+use rand::{rngs::OsRng, RngCore};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+// Prefix of a keystore file, including the version of the format.
+const MAGIC: &[u8] = b"p2p-keystore-v1";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+/// Encrypted on-disk storage of the identity keys.
+///
+/// The keys are encrypted with XChaCha20-Poly1305, using a key that is derived from the password with Argon2id.
+/// Only the identity keys are stored: the noise keypair is generated on each start and authenticated with the
+/// identity keys, so the [`PeerId`][libp2p::PeerId] stays the same.
+///
+/// Requires feature **keystore**.
+#[derive(Debug, Clone)]
+pub struct Keystore {
+    path: PathBuf,
+}
+
+impl Keystore {
+    /// Keystore that is stored in the file at `path`.
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Keystore {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Path of the keystore file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the keystore file exists.
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    /// Encrypt the identity keys with the password and write them to the keystore file.
+    /// An existing file is overwritten.
+    pub fn store(&self, keys: &InitKeypair, password: &[u8]) -> Result<(), KeystoreErr> {
+        let plaintext = keys.to_protobuf_encoding()?;
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut salt);
+        OsRng.fill_bytes(&mut nonce);
+        let cipher = new_cipher(password, &salt)?;
+        let ciphertext = cipher
+            .encrypt(XNonce::from_slice(&nonce), plaintext.as_slice())
+            .map_err(|_| KeystoreErr::Encryption)?;
+        let mut file = Vec::with_capacity(MAGIC.len() + SALT_LEN + NONCE_LEN + ciphertext.len());
+        file.extend_from_slice(MAGIC);
+        file.extend_from_slice(&salt);
+        file.extend_from_slice(&nonce);
+        file.extend_from_slice(&ciphertext);
+        fs::write(&self.path, file)?;
+        Ok(())
+    }
+
+    /// Read the keystore file and decrypt the identity keys with the password.
+    pub fn unlock(&self, password: &[u8]) -> Result<InitKeypair, KeystoreErr> {
+        let file = fs::read(&self.path)?;
+        let data = file.strip_prefix(MAGIC).ok_or(KeystoreErr::InvalidFormat)?;
+        if data.len() < SALT_LEN + NONCE_LEN {
+            return Err(KeystoreErr::InvalidFormat);
+        }
+        let (salt, data) = data.split_at(SALT_LEN);
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        let cipher = new_cipher(password, salt)?;
+        let plaintext = cipher
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| KeystoreErr::WrongPassword)?;
+        Ok(InitKeypair::from_protobuf_encoding(&plaintext)?)
+    }
+
+    /// Unlock the identity keys, or generate new ed25519 keys and store them if the keystore file does not exist yet.
+    pub fn unlock_or_generate(&self, password: &[u8]) -> Result<InitKeypair, KeystoreErr> {
+        if self.exists() {
+            return self.unlock(password);
+        }
+        let keys = InitKeypair::generate_ed25519();
+        self.store(&keys, password)?;
+        Ok(keys)
+    }
+}
+
+// Derive the encryption key from the password and salt.
+fn new_cipher(password: &[u8], salt: &[u8]) -> Result<XChaCha20Poly1305, KeystoreErr> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(password, salt, &mut key)
+        .map_err(|e| KeystoreErr::KeyDerivation(e.to_string()))?;
+    Ok(XChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+/// Error on storing or unlocking a [`Keystore`].
+#[derive(Error, Debug)]
+pub enum KeystoreErr {
+    /// Reading or writing the keystore file failed.
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    /// The keys could not be encoded or decoded.
+    #[error("Key error: {0}")]
+    Key(#[from] KeyErr),
+    /// The file is not a keystore of a supported version.
+    #[error("Invalid keystore format.")]
+    InvalidFormat,
+    /// Deriving the encryption key from the password failed.
+    #[error("Key derivation error: {0}")]
+    KeyDerivation(String),
+    /// Encrypting the keys failed.
+    #[error("Encryption failed.")]
+    Encryption,
+    /// The keys could not be decrypted, either because the password is wrong or the file was modified.
+    #[error("Wrong password or corrupted keystore.")]
+    WrongPassword,
+}
@@ -0,0 +1,58 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use libp2p::metrics::{Metrics, Recorder};
+use prometheus_client::{
+    metrics::{counter::Counter, family::Family},
+    registry::Registry,
+};
+use std::sync::Arc;
+
+/// Recorder of [`libp2p-metrics`][libp2p::metrics] for the events of the swarm and of the enabled protocols, and of
+/// metrics for the relay reservations of the local peer.
+///
+/// The metrics are registered in a [`prometheus_client`] registry on creation, which can then be used to encode the
+/// current metrics:
+/// - Connections, dial attempts and listeners of the swarm.
+/// - Round-trip times and failures of the ping protocol, if it is enabled.
+/// - Received, sent and pushed infos of the identify protocol, if it is enabled.
+/// - Accepted, renewed and lost reservations at relays, if the relay protocol is enabled.
+pub struct NetworkMetrics {
+    // Shared with the `NetworkBehaviour`, which records the events of the ping and identify protocol.
+    libp2p: Arc<Metrics>,
+    relay_reservations: Family<Vec<(String, String)>, Counter>,
+}
+
+impl NetworkMetrics {
+    pub fn new(registry: &mut Registry) -> Self {
+        let libp2p = Arc::new(Metrics::new(registry));
+        let relay_reservations = Family::default();
+        registry.sub_registry_with_prefix("p2p").register(
+            "relay_reservations",
+            "Number of accepted, renewed and lost reservations at relays",
+            Box::new(relay_reservations.clone()),
+        );
+        NetworkMetrics {
+            libp2p,
+            relay_reservations,
+        }
+    }
+
+    pub(crate) fn libp2p(&self) -> Arc<Metrics> {
+        self.libp2p.clone()
+    }
+
+    pub(crate) fn record<E>(&self, event: &E)
+    where
+        Metrics: Recorder<E>,
+    {
+        self.libp2p.record(event)
+    }
+
+    // Record a change of the reservation at a relay, i.e. `accepted`, `renewed` or `lost`.
+    pub(crate) fn record_relay_reservation(&self, event: &str) {
+        self.relay_reservations
+            .get_or_create(&vec![("event".into(), event.into())])
+            .inc();
+    }
+}
@@ -0,0 +1,268 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use super::{EventChannel, NetworkApi, NetworkEvent, ReceiveRequest};
+use crate::behaviour::{
+    CounterIds, OutboundFailure, RequestId, RequestMetadata, RequestPriority, Responder, RqRsMessage,
+};
+use futures::{
+    channel::{mpsc, oneshot},
+    future::BoxFuture,
+    FutureExt, SinkExt, StreamExt,
+};
+use libp2p::{identity::Keypair, PeerId};
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+};
+
+/// Outbound request that was sent via the [`MockNetwork`].
+#[derive(Debug)]
+pub struct OutboundRequest<Rq, Rs> {
+    /// Recipient of the request.
+    pub peer: PeerId,
+    pub request: Rq,
+    /// Channel for returning the result of the request to the application.
+    ///
+    /// If it is dropped without sending a result, the request fails with [`OutboundFailure::Timeout`].
+    pub response_tx: oneshot::Sender<Result<Rs, OutboundFailure>>,
+}
+
+/// Stand-in for [`Network`][crate::Network] in unit tests of applications, that does not open any sockets.
+///
+/// It provides the same methods for sending requests and the same channels for inbound requests and events, but
+/// all interaction is scripted through the [`MockController`] instead of remote peers. Application code that is
+/// written against the [`NetworkApi`] trait works with both.
+///
+/// Requires feature **mock**.
+pub struct MockNetwork<Rq: RqRsMessage, Rs: RqRsMessage> {
+    local_peer_id: PeerId,
+    outbound_tx: mpsc::UnboundedSender<OutboundRequest<Rq, Rs>>,
+    connected: Arc<Mutex<HashSet<PeerId>>>,
+}
+
+impl<Rq: RqRsMessage, Rs: RqRsMessage> MockNetwork<Rq, Rs> {
+    /// Create a new mock network with a random local peer id.
+    ///
+    /// The channels are the same that would be passed to [`NetworkBuilder::new`][crate::NetworkBuilder::new], the
+    /// returned [`MockController`] sends the scripted requests and events into them.
+    pub fn new(
+        request_channel: EventChannel<ReceiveRequest<Rq, Rs>>,
+        event_channel: Option<EventChannel<NetworkEvent>>,
+    ) -> (Self, MockController<Rq, Rs>) {
+        let local_peer_id = Keypair::generate_ed25519().public().to_peer_id();
+        let (outbound_tx, outbound_rx) = mpsc::unbounded();
+        let connected = Arc::new(Mutex::new(HashSet::new()));
+        let network = MockNetwork {
+            local_peer_id,
+            outbound_tx,
+            connected: connected.clone(),
+        };
+        let controller = MockController {
+            request_channel,
+            event_channel,
+            outbound_rx,
+            connected,
+            next_request_id: Arc::new(CounterIds::default()),
+        };
+        (network, controller)
+    }
+
+    /// Get the peer id of the local peer.
+    pub fn peer_id(&self) -> PeerId {
+        self.local_peer_id
+    }
+
+    /// Send a new request to a remote peer.
+    ///
+    /// The request is forwarded to [`MockController::next_outbound`], and resolves with the result that is sent
+    /// back through [`OutboundRequest::response_tx`]. If the controller was dropped, the request fails with
+    /// [`OutboundFailure::Shutdown`].
+    pub async fn send_request(&mut self, peer: PeerId, request: Rq) -> Result<Rs, OutboundFailure> {
+        let (response_tx, response_rx) = oneshot::channel();
+        let request = OutboundRequest {
+            peer,
+            request,
+            response_tx,
+        };
+        if self.outbound_tx.unbounded_send(request).is_err() {
+            return Err(OutboundFailure::Shutdown);
+        }
+        response_rx.await.unwrap_or(Err(OutboundFailure::Timeout))
+    }
+
+    /// Check whether the controller marked the peer as connected.
+    pub async fn is_connected(&mut self, peer: PeerId) -> bool {
+        self.connected.lock().unwrap().contains(&peer)
+    }
+}
+
+/// Test side of a [`MockNetwork`], for scripting inbound requests and events and asserting on outbound requests.
+///
+/// Requires feature **mock**.
+pub struct MockController<Rq: RqRsMessage, Rs: RqRsMessage> {
+    request_channel: EventChannel<ReceiveRequest<Rq, Rs>>,
+    event_channel: Option<EventChannel<NetworkEvent>>,
+    outbound_rx: mpsc::UnboundedReceiver<OutboundRequest<Rq, Rs>>,
+    connected: Arc<Mutex<HashSet<PeerId>>>,
+    next_request_id: Arc<CounterIds>,
+}
+
+impl<Rq: RqRsMessage, Rs: RqRsMessage> MockController<Rq, Rs> {
+    /// Send an inbound request from the peer to the application, as if it was received and approved by the firewall.
+    ///
+    /// Returns the channel on which the response of the application is received. The sender side is dropped if the
+    /// application did not answer or rejected the request.
+    pub async fn inject_request(&mut self, peer: PeerId, request: Rq) -> oneshot::Receiver<Rs> {
+        let (response_tx, response_rx) = oneshot::channel();
+        let request = ReceiveRequest {
+            request_id: RequestId::next(&*self.next_request_id),
+            peer,
+            request,
+            responder: Responder::new(response_tx, None, None),
+            connection: None,
+            metadata: RequestMetadata::new(),
+            priority: RequestPriority::Normal,
+            global_id: None,
+        };
+        let _ = self.request_channel.send(request).await;
+        response_rx
+    }
+
+    /// Send an event to the application. Does nothing if no event channel was passed to [`MockNetwork::new`].
+    pub async fn inject_event(&mut self, event: NetworkEvent) {
+        if let Some(channel) = self.event_channel.as_mut() {
+            let _ = channel.send(event).await;
+        }
+    }
+
+    /// Wait for the next request that the application sent via [`MockNetwork::send_request`].
+    ///
+    /// Returns `None` if the [`MockNetwork`] was dropped.
+    pub async fn next_outbound(&mut self) -> Option<OutboundRequest<Rq, Rs>> {
+        self.outbound_rx.next().await
+    }
+
+    /// Get the next request that the application already sent, without waiting.
+    pub fn try_next_outbound(&mut self) -> Option<OutboundRequest<Rq, Rs>> {
+        self.outbound_rx.try_next().ok().flatten()
+    }
+
+    /// Mark the peer as connected, as reported by [`MockNetwork::is_connected`].
+    ///
+    /// **Note**: No [`NetworkEvent::ConnectionEstablished`] is emitted, it can be injected with
+    /// [`MockController::inject_event`] if the application relies on it.
+    pub fn connect(&mut self, peer: PeerId) {
+        self.connected.lock().unwrap().insert(peer);
+    }
+
+    /// Mark the peer as disconnected.
+    pub fn disconnect(&mut self, peer: PeerId) {
+        self.connected.lock().unwrap().remove(&peer);
+    }
+}
+
+impl<Rq: RqRsMessage, Rs: RqRsMessage> NetworkApi<Rq, Rs> for MockNetwork<Rq, Rs> {
+    fn peer_id(&self) -> PeerId {
+        MockNetwork::peer_id(self)
+    }
+
+    fn send_request(&mut self, peer: PeerId, request: Rq) -> BoxFuture<'_, Result<Rs, OutboundFailure>> {
+        MockNetwork::send_request(self, peer, request).boxed()
+    }
+
+    fn is_connected(&mut self, peer: PeerId) -> BoxFuture<'_, bool> {
+        MockNetwork::is_connected(self, peer).boxed()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{interface::ChannelSinkConfig, InboundFailure};
+
+    type Mock = (
+        MockNetwork<String, String>,
+        MockController<String, String>,
+        mpsc::Receiver<ReceiveRequest<String, String>>,
+        mpsc::Receiver<NetworkEvent>,
+    );
+
+    fn mock() -> Mock {
+        let (request_tx, request_rx) = EventChannel::new(1, ChannelSinkConfig::Block);
+        let (event_tx, event_rx) = EventChannel::new(1, ChannelSinkConfig::Block);
+        let (network, controller) = MockNetwork::new(request_tx, Some(event_tx));
+        (network, controller, request_rx, event_rx)
+    }
+
+    // Application code that only depends on the trait.
+    async fn ping<N: NetworkApi<String, String>>(network: &mut N, peer: PeerId) -> Result<String, OutboundFailure> {
+        if !network.is_connected(peer).await {
+            return Err(OutboundFailure::DialFailure);
+        }
+        network.send_request(peer, "ping".into()).await
+    }
+
+    #[tokio::test]
+    async fn outbound_round_trip() {
+        let (mut network, mut controller, ..) = mock();
+        let peer = PeerId::random();
+        assert_eq!(ping(&mut network, peer).await, Err(OutboundFailure::DialFailure));
+
+        controller.connect(peer);
+        let answer = async {
+            let outbound = controller.next_outbound().await.unwrap();
+            assert_eq!(outbound.peer, peer);
+            assert_eq!(outbound.request, "ping");
+            outbound.response_tx.send(Ok("pong".into())).unwrap();
+        };
+        let (res, ()) = futures::join!(ping(&mut network, peer), answer);
+        assert_eq!(res.unwrap(), "pong");
+        assert!(controller.try_next_outbound().is_none());
+
+        // A dropped response channel fails the request with a timeout.
+        let drop_response = async { drop(controller.next_outbound().await) };
+        let (res, ()) = futures::join!(ping(&mut network, peer), drop_response);
+        assert_eq!(res, Err(OutboundFailure::Timeout));
+
+        controller.disconnect(peer);
+        assert!(!NetworkApi::is_connected(&mut network, peer).await);
+
+        drop(controller);
+        let res = network.send_request(peer, "ping".into()).await;
+        assert_eq!(res, Err(OutboundFailure::Shutdown));
+    }
+
+    #[tokio::test]
+    async fn inbound_round_trip() {
+        let (_network, mut controller, mut request_rx, mut event_rx) = mock();
+        let peer = PeerId::random();
+
+        let response_rx = controller.inject_request(peer, "ping".into()).await;
+        let request = request_rx.next().await.unwrap();
+        assert_eq!(request.peer, peer);
+        assert_eq!(request.request, "ping");
+        request.responder.respond("pong".into()).unwrap();
+        assert_eq!(response_rx.await.unwrap(), "pong");
+
+        // The response channel closes if the application rejects the request.
+        let response_rx = controller.inject_request(peer, "ping".into()).await;
+        request_rx.next().await.unwrap().responder.reject("busy");
+        assert!(response_rx.await.is_err());
+
+        let event = NetworkEvent::InboundFailure {
+            request_id: RequestId::next(&CounterIds::default()),
+            peer,
+            failure: InboundFailure::Timeout,
+        };
+        controller.inject_event(event).await;
+        match event_rx.next().await.unwrap() {
+            NetworkEvent::InboundFailure {
+                peer: p,
+                failure: InboundFailure::Timeout,
+                ..
+            } => assert_eq!(p, peer),
+            other => panic!("Unexpected event {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,665 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use super::ReceiveRequest;
+use crate::{
+    behaviour::{
+        approval_query, peer_rule_query, ApprovalStatus, BehaviourAction, CloseReason, ConfigConfig, CounterIds,
+        Handler, HandlerInEvent, HandlerOutEvent, InboundFailure, MessageProtocol, OutboundFailure,
+        PendingApprovalRequest, PendingPeerRuleRequest, RemoteError, RequestConnection, RequestHeaders, RequestId,
+        RequestIdGenerator, RequestManager, RequestPriority, Responder, RqRsMessage, SerializedRequest,
+    },
+    firewall::{FirewallRequest, FirewallRules, FwRequest, Rule},
+    timer::Instant,
+};
+use futures::{
+    channel::{mpsc, oneshot},
+    future::poll_fn,
+    stream::FuturesUnordered,
+    task::{Context, Poll},
+    StreamExt,
+};
+use libp2p::{
+    core::{connection::ConnectionId, ConnectedPoint, Multiaddr, PeerId},
+    swarm::{
+        dial_opts::{DialOpts, PeerCondition},
+        ConnectionHandler, DialError, IntoConnectionHandler, NetworkBehaviour as Libp2pNetworkBehaviour,
+        NetworkBehaviourAction, NotifyHandler, PollParameters,
+    },
+};
+use smallvec::smallvec;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+// Capacity of the command channel of a namespace.
+const COMMAND_CHANNEL_CAPACITY: usize = 10;
+
+/// Events of a namespace, which are forwarded through the events channel of
+/// [`NetworkBuilder::with_custom_behaviour`][crate::NetworkBuilder::with_custom_behaviour].
+#[derive(Debug)]
+pub enum NamespaceEvent<Rq, Rs> {
+    /// An inbound request within the namespace was approved by the namespace's firewall.
+    Request(ReceiveRequest<Rq, Rs>),
+    /// A failure occurred in the context of receiving an inbound request and sending a response.
+    InboundFailure {
+        request_id: RequestId,
+        peer: PeerId,
+        failure: InboundFailure,
+    },
+}
+
+// Operations that are initiated through a `NamespacedNetwork` and executed in the `NamespaceBehaviour`.
+enum NamespaceCommand<Rq, Rs, TRq> {
+    SendRequest {
+        peer: PeerId,
+        request: Rq,
+        return_tx: oneshot::Sender<Result<Rs, OutboundFailure>>,
+    },
+    SetFirewallDefault {
+        default: Option<Rule<TRq>>,
+        return_tx: oneshot::Sender<()>,
+    },
+    SetPeerRule {
+        peer: PeerId,
+        rule: Rule<TRq>,
+        return_tx: oneshot::Sender<()>,
+    },
+    RemovePeerRule {
+        peer: PeerId,
+        return_tx: oneshot::Sender<()>,
+    },
+}
+
+/// Separate logical network on top of the swarm of a [`Network`][crate::Network], e.g. to run a control plane and a
+/// data plane without doubling the connections to each peer.
+///
+/// The namespace uses its own request protocol `/p2p/<namespace>/1.0.0`, firewall and request types, while it shares
+/// the keypair, transport, listeners and connections of the network. It is composed into the swarm with
+/// [`NetworkBuilder::with_custom_behaviour`][crate::NetworkBuilder::with_custom_behaviour], whose events channel
+/// receives the [`NamespaceEvent`]s. Requests within the namespace are sent via the [`NamespacedNetwork`] that is
+/// returned when creating the namespace.
+///
+/// The namespace only runs the request-response protocol on the connections, all other protocols, the connection
+/// limits and the bans are handled by the network. Addresses of remote peers are resolved by the network,
+/// connections are therefore established with the methods of [`Network`][crate::Network]. Further namespaces can be
+/// composed into the swarm by combining multiple `NamespaceBehaviour`s in one custom
+/// [`NetworkBehaviour`][libp2p::swarm::NetworkBehaviour].
+pub struct NamespaceBehaviour<Rq, Rs, TRq = Rq>
+where
+    Rq: RqRsMessage,
+    Rs: RqRsMessage,
+    TRq: FwRequest<Rq>,
+{
+    // Timeout and protocol configurations.
+    config: ConfigConfig,
+    // Generator for the ids of new requests.
+    next_request_id: Arc<dyn RequestIdGenerator>,
+    // Manager for pending requests, their state and necessary actions.
+    request_manager: RequestManager<Rq, Rs>,
+    // Firewall of the namespace, independent of the firewall of the network.
+    firewall: FirewallRules<TRq>,
+    // Channel for firewall requests of the namespace.
+    permission_req_channel: mpsc::Sender<FirewallRequest<TRq>>,
+    // Futures for pending responses to sent `FirewallRequest::PeerSpecificRule`s.
+    pending_rule_rqs: FuturesUnordered<PendingPeerRuleRequest<TRq>>,
+    // Futures for pending responses to sent `FirewallRequest::RequestApproval`s.
+    pending_approval_rqs: FuturesUnordered<PendingApprovalRequest>,
+    // Handles to pending firewall rule request. If the handle is dropped, the future is aborted.
+    rule_rq_handles: HashMap<PeerId, oneshot::Sender<()>>,
+    // Handles to pending approval requests. If the handle is dropped, the future is aborted.
+    approval_rq_handles: HashMap<RequestId, oneshot::Sender<()>>,
+    // Channels for sending the reason to the remote if a received request is not answered, and the time at which
+    // the inbound substream times out.
+    remote_error_txs: HashMap<RequestId, (oneshot::Sender<RemoteError>, Instant)>,
+    // Inbound requests that were forwarded to the application and were neither answered nor failed yet.
+    awaiting_response: HashSet<RequestId>,
+    // Metadata, priority class and global id that the remote attached to received requests, until they are forwarded
+    // to the application.
+    request_metadata: HashMap<RequestId, RequestHeaders>,
+    command_rx: mpsc::Receiver<NamespaceCommand<Rq, Rs, TRq>>,
+    // Response channels for sent outbound requests.
+    await_response: HashMap<RequestId, oneshot::Sender<Result<Rs, OutboundFailure>>>,
+}
+
+impl<Rq, Rs, TRq> NamespaceBehaviour<Rq, Rs, TRq>
+where
+    Rq: RqRsMessage,
+    Rs: RqRsMessage,
+    TRq: FwRequest<Rq>,
+{
+    /// Create a new namespace with the default config.
+    /// The firewall of the namespace uses the `rules`, and sends [`FirewallRequest`]s through the `firewall_channel`.
+    pub fn new(
+        namespace: &str,
+        firewall_channel: mpsc::Sender<FirewallRequest<TRq>>,
+        rules: FirewallRules<TRq>,
+    ) -> (Self, NamespacedNetwork<Rq, Rs, TRq>) {
+        Self::with_config(namespace, ConfigConfig::default(), firewall_channel, rules)
+    }
+
+    /// Create a new namespace with the `config`, whose supported protocols are replaced with the protocol of the
+    /// namespace.
+    ///
+    /// **Note**: Only the timeouts, the limits for pending requests, the buffer tuning, request batching, ordered
+    /// delivery and the connection selection apply within the namespace. Options that require other protocols than
+    /// the request protocol, e.g. mailboxes, hellos, presence and keep-alive probes, are handled by the network.
+    pub fn with_config(
+        namespace: &str,
+        mut config: ConfigConfig,
+        firewall_channel: mpsc::Sender<FirewallRequest<TRq>>,
+        rules: FirewallRules<TRq>,
+    ) -> (Self, NamespacedNetwork<Rq, Rs, TRq>) {
+        config.supported_protocols = smallvec![MessageProtocol::namespaced(namespace, 1, 0, 0)];
+        let (command_tx, command_rx) = mpsc::channel(COMMAND_CHANNEL_CAPACITY);
+        let behaviour = NamespaceBehaviour {
+            request_manager: RequestManager::new(&config),
+            config,
+            next_request_id: Arc::new(CounterIds::default()),
+            firewall: rules,
+            permission_req_channel: firewall_channel,
+            pending_rule_rqs: FuturesUnordered::default(),
+            pending_approval_rqs: FuturesUnordered::default(),
+            rule_rq_handles: HashMap::new(),
+            approval_rq_handles: HashMap::new(),
+            remote_error_txs: HashMap::new(),
+            awaiting_response: HashSet::new(),
+            request_metadata: HashMap::new(),
+            command_rx,
+            await_response: HashMap::new(),
+        };
+        let network = NamespacedNetwork {
+            namespace: namespace.into(),
+            command_tx,
+        };
+        (behaviour, network)
+    }
+
+    fn handle_command(&mut self, command: NamespaceCommand<Rq, Rs, TRq>) {
+        match command {
+            NamespaceCommand::SendRequest {
+                peer,
+                request,
+                return_tx,
+            } => {
+                let request_id = RequestId::next(&self.next_request_id);
+                self.await_response.insert(request_id, return_tx);
+                match SerializedRequest::new(&request) {
+                    Ok(request) => self.request_manager.on_new_out_request(peer, request_id, request),
+                    Err(_) => {
+                        let failure = Err(OutboundFailure::InvalidRequest);
+                        self.request_manager.on_res_for_outbound(peer, request_id, failure)
+                    }
+                }
+            }
+            NamespaceCommand::SetFirewallDefault { default, return_tx } => {
+                self.firewall.set_default(default);
+                for peer in self.request_manager.connected_peers() {
+                    if self.firewall.get_rule(&peer).is_none() {
+                        self.handle_updated_peer_rule(peer);
+                    }
+                }
+                let _ = return_tx.send(());
+            }
+            NamespaceCommand::SetPeerRule { peer, rule, return_tx } => {
+                self.firewall.set_rule(peer, rule);
+                self.handle_updated_peer_rule(peer);
+                let _ = return_tx.send(());
+            }
+            NamespaceCommand::RemovePeerRule { peer, return_tx } => {
+                self.firewall.remove_rule(&peer);
+                self.handle_updated_peer_rule(peer);
+                let _ = return_tx.send(());
+            }
+        }
+    }
+
+    // Handle new event emitted by the `Handler`.
+    fn handle_handler_event(&mut self, peer: PeerId, connection: ConnectionId, event: HandlerOutEvent<Rq, Rs>) {
+        match event {
+            // Capability tokens and idempotency keys are not supported within a namespace, the request is handled
+            // like one without them.
+            HandlerOutEvent::ReceivedRequest {
+                request_id,
+                request,
+                metadata,
+                response_tx,
+                error_tx,
+                bytes_received,
+                ..
+            } => {
+                let deadline = Instant::now() + self.config.request_timeout;
+                self.remote_error_txs.insert(request_id, (error_tx, deadline));
+                let priority = metadata.as_ref().map(|(_, p, _)| *p).unwrap_or_default();
+                if let Some(metadata) = metadata {
+                    self.request_metadata.insert(request_id, metadata);
+                }
+                let approval_status = self.check_approval_status(peer, request_id, &request, priority);
+                self.request_manager.on_new_in_request(
+                    peer,
+                    request_id,
+                    request,
+                    bytes_received,
+                    response_tx,
+                    connection,
+                    approval_status,
+                );
+            }
+            HandlerOutEvent::ReceivedResponse {
+                request_id,
+                response,
+                rtt,
+                ..
+            } => self
+                .request_manager
+                .on_res_for_outbound(peer, request_id, Ok((response, rtt))),
+            HandlerOutEvent::RequestWritten { request_id, .. } => {
+                self.request_manager.on_request_written(peer, request_id)
+            }
+            HandlerOutEvent::OutboundTimeout(request_id) => {
+                self.request_manager
+                    .on_res_for_outbound(peer, request_id, Err(OutboundFailure::Timeout));
+            }
+            HandlerOutEvent::OutboundRemoteError { request_id, error } => {
+                self.request_manager
+                    .on_res_for_outbound(peer, request_id, Err(OutboundFailure::Remote(error)));
+            }
+            HandlerOutEvent::OutboundUnsupportedProtocols(request_id) => {
+                let failure = OutboundFailure::UnsupportedProtocols { remote_protocols: None };
+                self.request_manager.on_res_for_outbound(peer, request_id, Err(failure));
+            }
+            HandlerOutEvent::InboundTimeout(request_id) => {
+                // Abort firewall request for approval.
+                let _ = self.approval_rq_handles.remove(&request_id);
+                self.awaiting_response.remove(&request_id);
+                let err = InboundFailure::Timeout;
+                self.request_manager.on_res_for_inbound(peer, request_id, Err(err));
+            }
+            HandlerOutEvent::SentResponse { request_id, .. } | HandlerOutEvent::ResponseRejected(request_id) => {
+                self.awaiting_response.remove(&request_id);
+                self.request_manager.on_res_for_inbound(peer, request_id, Ok(()));
+            }
+            HandlerOutEvent::InboundUnsupportedProtocols(request_id) => {
+                self.request_manager.on_res_for_inbound(peer, request_id, Ok(()));
+            }
+            HandlerOutEvent::InboundUnknownRequest(request_id) => {
+                let err = InboundFailure::UnknownRequest;
+                self.request_manager.on_res_for_inbound(peer, request_id, Err(err));
+            }
+            HandlerOutEvent::SendResponseOmission(request_id) => {
+                let _ = self.approval_rq_handles.remove(&request_id);
+                // Requests that already failed locally, e.g. because the firewall rejected them, were reported before.
+                let result = match self.awaiting_response.remove(&request_id) {
+                    true => Err(InboundFailure::ResponseOmitted),
+                    false => Ok(()),
+                };
+                self.request_manager.on_res_for_inbound(peer, request_id, result);
+            }
+        }
+    }
+
+    // Check the approval status of the request and add queries to the firewall if necessary.
+    fn check_approval_status(
+        &mut self,
+        peer: PeerId,
+        request_id: RequestId,
+        request: &Rq,
+        priority: RequestPriority,
+    ) -> ApprovalStatus {
+        let rule = self
+            .firewall
+            .get_priority_rule(priority)
+            .or_else(|| self.firewall.get_effective_rule(&peer));
+        match rule {
+            None => {
+                self.query_peer_rule(peer);
+                ApprovalStatus::MissingRule
+            }
+            Some(Rule::Ask) => {
+                self.query_request_approval(peer, request_id, TRq::from_request(request));
+                ApprovalStatus::MissingApproval
+            }
+            Some(Rule::AllowAll) => ApprovalStatus::Approved,
+            Some(Rule::RejectAll) => ApprovalStatus::Rejected,
+            Some(Rule::Restricted { restriction, .. }) => match restriction(&TRq::from_request(request)) {
+                true => ApprovalStatus::Approved,
+                false => ApprovalStatus::Rejected,
+            },
+        }
+    }
+
+    // Query for a new peer-specific firewall rule, if there is no pending request for this yet.
+    fn query_peer_rule(&mut self, peer: PeerId) {
+        if self.request_manager.is_rule_request_pending(&peer) {
+            return;
+        }
+        let channel = self.permission_req_channel.clone();
+        let (future, abort_handle_tx) = peer_rule_query(channel, peer, self.config.firewall_timeout);
+        self.pending_rule_rqs.push(future);
+        self.rule_rq_handles.insert(peer, abort_handle_tx);
+        self.request_manager.add_pending_rule_request(peer);
+    }
+
+    // Query for individual approval of a request, because of a `Rule::Ask`.
+    fn query_request_approval(&mut self, peer: PeerId, request_id: RequestId, rq: TRq) {
+        let channel = self.permission_req_channel.clone();
+        let (future, abort_handle_tx) = approval_query(channel, peer, request_id, rq, self.config.firewall_timeout);
+        self.pending_approval_rqs.push(future);
+        self.approval_rq_handles.insert(request_id, abort_handle_tx);
+    }
+
+    // Whether inbound requests from the peer are supported.
+    fn supports_inbound(&self, peer: &PeerId) -> bool {
+        self.firewall.priority_rules().next().is_some()
+            || !matches!(self.firewall.get_effective_rule(peer), Some(Rule::RejectAll))
+    }
+
+    // Handle a changed firewall rule for a peer.
+    fn handle_updated_peer_rule(&mut self, peer: PeerId) {
+        let inbound_support = self.supports_inbound(&peer);
+        self.request_manager.set_inbound_support(peer, None, inbound_support);
+        let rule = self.firewall.get_effective_rule(&peer).cloned();
+        for (request_id, rq) in self.request_manager.on_peer_rule(peer, rule).unwrap_or_default() {
+            self.query_request_approval(peer, request_id, rq);
+        }
+    }
+
+    fn new_request_response_handler(&self) -> Handler<Rq, Rs> {
+        Handler::new(
+            self.config.supported_protocols.clone(),
+            true,
+            self.config.connection_timeout,
+            self.config.request_timeout,
+            self.next_request_id.clone(),
+            self.config.buffer_tuning,
+            self.config.request_batching,
+            None,
+            None,
+            None,
+        )
+    }
+
+    // Map the next action of the `RequestManager` to a `NetworkBehaviourAction`, and resolve the pending outbound
+    // requests. Returns `None` if the action is handled within the namespace.
+    fn handle_action(
+        &mut self,
+        action: BehaviourAction<Rq, Rs>,
+    ) -> Option<NetworkBehaviourAction<NamespaceEvent<Rq, Rs>, Handler<Rq, Rs>>> {
+        let action = match action {
+            BehaviourAction::InboundOk {
+                request_id,
+                peer,
+                request,
+                response_tx,
+            } => {
+                let (error_tx, deadline) = match self.remote_error_txs.remove(&request_id) {
+                    Some((tx, deadline)) => (Some(tx), Some(deadline)),
+                    None => (None, None),
+                };
+                let responder = Responder::new(response_tx, error_tx, deadline);
+                let (metadata, priority, global_id) = self.request_metadata.remove(&request_id).unwrap_or_default();
+                self.awaiting_response.insert(request_id);
+                let connection = self
+                    .request_manager
+                    .inbound_connection(&peer, &request_id)
+                    .map(|(id, endpoint)| RequestConnection::new(id, endpoint));
+                NetworkBehaviourAction::GenerateEvent(NamespaceEvent::Request(ReceiveRequest {
+                    request_id,
+                    peer,
+                    request,
+                    responder,
+                    connection,
+                    metadata,
+                    priority,
+                    global_id,
+                }))
+            }
+            BehaviourAction::InboundFailure {
+                request_id,
+                peer,
+                failure,
+            } => {
+                self.request_metadata.remove(&request_id);
+                self.awaiting_response.remove(&request_id);
+                if let Some((error_tx, _)) = self.remote_error_txs.remove(&request_id) {
+                    if let Some(error) = failure.remote_error(self.config.busy_retry_after) {
+                        let _ = error_tx.send(error);
+                    }
+                }
+                NetworkBehaviourAction::GenerateEvent(NamespaceEvent::InboundFailure {
+                    request_id,
+                    peer,
+                    failure,
+                })
+            }
+            BehaviourAction::OutboundOk {
+                request_id,
+                peer,
+                request,
+                connection,
+            } => NetworkBehaviourAction::NotifyHandler {
+                peer_id: peer,
+                handler: NotifyHandler::One(connection),
+                event: HandlerInEvent::SendRequest { request_id, request },
+            },
+            BehaviourAction::OutboundFailure {
+                request_id, failure, ..
+            } => {
+                if let Some(return_tx) = self.await_response.remove(&request_id) {
+                    let _ = return_tx.send(Err(failure));
+                }
+                return None;
+            }
+            BehaviourAction::OutboundReceivedRes {
+                request_id, response, ..
+            } => {
+                if let Some(return_tx) = self.await_response.remove(&request_id) {
+                    let _ = return_tx.send(Ok(response));
+                }
+                return None;
+            }
+            BehaviourAction::RequireDialAttempt(peer) => NetworkBehaviourAction::Dial {
+                opts: DialOpts::peer_id(peer).condition(PeerCondition::Disconnected).build(),
+                handler: self.new_request_response_handler(),
+            },
+            BehaviourAction::SetInboundSupport {
+                peer,
+                connection,
+                support,
+            } => NetworkBehaviourAction::NotifyHandler {
+                peer_id: peer,
+                handler: NotifyHandler::One(connection),
+                event: HandlerInEvent::SetInboundSupport(support),
+            },
+        };
+        Some(action)
+    }
+}
+
+impl<Rq, Rs, TRq> Libp2pNetworkBehaviour for NamespaceBehaviour<Rq, Rs, TRq>
+where
+    Rq: RqRsMessage,
+    Rs: RqRsMessage,
+    TRq: FwRequest<Rq>,
+{
+    type ConnectionHandler = Handler<Rq, Rs>;
+    type OutEvent = NamespaceEvent<Rq, Rs>;
+
+    fn new_handler(&mut self) -> Self::ConnectionHandler {
+        self.new_request_response_handler()
+    }
+
+    fn inject_event(
+        &mut self,
+        peer: PeerId,
+        connection: ConnectionId,
+        event: <<Self::ConnectionHandler as IntoConnectionHandler>::Handler as ConnectionHandler>::OutEvent,
+    ) {
+        self.handle_handler_event(peer, connection, event)
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+        _params: &mut impl PollParameters,
+    ) -> Poll<NetworkBehaviourAction<Self::OutEvent, Self::ConnectionHandler>> {
+        while let Poll::Ready(Some(command)) = self.command_rx.poll_next_unpin(cx) {
+            self.handle_command(command);
+        }
+        // Update firewall rule if a peer specific rule was returned after a `FirewallRequest::PeerSpecificRule` query.
+        while let Poll::Ready(Some((peer, rule))) = self.pending_rule_rqs.poll_next_unpin(cx) {
+            if let Some(rule) = rule {
+                self.firewall.set_rule(peer, rule);
+            }
+            self.handle_updated_peer_rule(peer);
+        }
+        // Handle individual approvals for requests that were returned after a `FirewallRequest::RequestApproval`
+        // query.
+        while let Poll::Ready(Some((request_id, is_allowed))) = self.pending_approval_rqs.poll_next_unpin(cx) {
+            self.approval_rq_handles.remove(&request_id);
+            self.request_manager.on_request_approval(request_id, is_allowed);
+        }
+        while let Some(action) = self.request_manager.take_next_action() {
+            if let Some(action) = self.handle_action(action) {
+                return Poll::Ready(action);
+            }
+        }
+        Poll::Pending
+    }
+
+    fn inject_connection_established(
+        &mut self,
+        peer: &PeerId,
+        connection: &ConnectionId,
+        endpoint: &ConnectedPoint,
+        _failed_addresses: Option<&Vec<Multiaddr>>,
+        _other_established: usize,
+    ) {
+        // If the remote connected to us and there is no rule for inbound requests yet, query firewall.
+        if endpoint.is_listener() && self.firewall.get_effective_rule(peer).is_none() {
+            self.query_peer_rule(*peer);
+        }
+        let support_inbound = self.supports_inbound(peer);
+        self.request_manager
+            .set_inbound_support(*peer, Some(*connection), support_inbound);
+        self.request_manager
+            .on_connection_established(*peer, *connection, endpoint.clone());
+    }
+
+    fn inject_connection_closed(
+        &mut self,
+        peer: &PeerId,
+        connection: &ConnectionId,
+        _endpoint: &ConnectedPoint,
+        _handler: <Self::ConnectionHandler as IntoConnectionHandler>::Handler,
+        remaining_established: usize,
+    ) {
+        // Abort pending requests for firewall rule, if the peer completely disconnected.
+        if remaining_established == 0 {
+            let _ = self.rule_rq_handles.remove(peer);
+        }
+        // The reason why the connection closed is only known to the network.
+        self.request_manager
+            .on_connection_closed(*peer, connection, remaining_established, CloseReason::Remote);
+    }
+
+    fn inject_address_change(
+        &mut self,
+        peer: &PeerId,
+        connection: &ConnectionId,
+        _old: &ConnectedPoint,
+        new: &ConnectedPoint,
+    ) {
+        self.request_manager.on_address_change(*peer, *connection, new.clone());
+    }
+
+    fn inject_dial_failure(&mut self, peer_id: Option<PeerId>, _handler: Self::ConnectionHandler, _error: &DialError) {
+        if let Some(peer) = peer_id {
+            self.request_manager.on_dial_failure(peer);
+        }
+    }
+}
+
+/// Interface for sending requests and setting firewall rules within a namespace.
+///
+/// Like [`Network`][crate::Network] it is a wrapper for the sender side of a channel, and can be cloned while still
+/// operating on the same namespace.
+pub struct NamespacedNetwork<Rq, Rs, TRq = Rq>
+where
+    Rq: RqRsMessage,
+    Rs: RqRsMessage,
+    TRq: FwRequest<Rq>,
+{
+    namespace: String,
+    command_tx: mpsc::Sender<NamespaceCommand<Rq, Rs, TRq>>,
+}
+
+impl<Rq, Rs, TRq> Clone for NamespacedNetwork<Rq, Rs, TRq>
+where
+    Rq: RqRsMessage,
+    Rs: RqRsMessage,
+    TRq: FwRequest<Rq>,
+{
+    fn clone(&self) -> Self {
+        NamespacedNetwork {
+            namespace: self.namespace.clone(),
+            command_tx: self.command_tx.clone(),
+        }
+    }
+}
+
+impl<Rq, Rs, TRq> NamespacedNetwork<Rq, Rs, TRq>
+where
+    Rq: RqRsMessage,
+    Rs: RqRsMessage,
+    TRq: FwRequest<Rq>,
+{
+    /// Name of the namespace.
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    /// Send a new request to a remote peer within the namespace.
+    ///
+    /// Fails with [`OutboundFailure::Shutdown`] if the network was shut down.
+    pub async fn send_request(&mut self, peer: PeerId, request: Rq) -> Result<Rs, OutboundFailure> {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = NamespaceCommand::SendRequest {
+            peer,
+            request,
+            return_tx,
+        };
+        self.send_command(command).await;
+        rx_yield.await.unwrap_or(Err(OutboundFailure::Shutdown))
+    }
+
+    /// Set the default firewall rule of the namespace.
+    pub async fn set_firewall_default(&mut self, default: Option<Rule<TRq>>) {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = NamespaceCommand::SetFirewallDefault { default, return_tx };
+        self.send_command(command).await;
+        let _ = rx_yield.await;
+    }
+
+    /// Set a peer specific rule in the firewall of the namespace.
+    pub async fn set_peer_rule(&mut self, peer: PeerId, rule: Rule<TRq>) {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = NamespaceCommand::SetPeerRule { peer, rule, return_tx };
+        self.send_command(command).await;
+        let _ = rx_yield.await;
+    }
+
+    /// Remove a peer specific rule from the firewall of the namespace.
+    pub async fn remove_peer_rule(&mut self, peer: PeerId) {
+        let (return_tx, rx_yield) = oneshot::channel();
+        let command = NamespaceCommand::RemovePeerRule { peer, return_tx };
+        self.send_command(command).await;
+        let _ = rx_yield.await;
+    }
+
+    async fn send_command(&mut self, command: NamespaceCommand<Rq, Rs, TRq>) {
+        let _ = poll_fn(|cx| self.command_tx.poll_ready(cx)).await;
+        let _ = self.command_tx.start_send(command);
+    }
+}
@@ -0,0 +1,230 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::timer::Instant;
+use futures::{future::BoxFuture, AsyncRead, AsyncWrite, FutureExt, TryFutureExt};
+use libp2p::{
+    core::upgrade::{InboundUpgrade, NegotiationError, OutboundUpgrade, UpgradeInfo},
+    identity::Keypair,
+    noise::{
+        AuthenticKeypair, Keypair as NoiseKeypair, NoiseAuthenticated, NoiseConfig, NoiseError, NoiseOutput,
+        X25519Spec, XX,
+    },
+    PeerId,
+};
+use std::{
+    collections::HashSet,
+    error::Error as StdError,
+    io, iter,
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+use thiserror::Error;
+
+const PROTOCOL_NAME: &[u8] = b"/noise";
+
+/// Predicate for the remote [`PeerId`] of a new connection.
+pub type ExpectedPeer = Arc<dyn Fn(&PeerId) -> bool + Send + Sync>;
+
+// Peers that are banned on the swarm, shared between the event loop and the noise upgrade.
+pub type BannedPeers = Arc<RwLock<HashSet<PeerId>>>;
+
+/// Policy for reusing the static keypair of the noise protocol, independently of the identity keys.
+///
+/// A new noise keypair can only be created if the identity keys are known, i.e. if the keys were set via
+/// [`InitKeypair::IdKeys`][crate::InitKeypair::IdKeys] or generated. A keypair provided via
+/// [`InitKeypair::Authenticated`][crate::InitKeypair::Authenticated] is always pinned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NoiseKeyRotation {
+    /// Use the same static keypair for all sessions.
+    #[default]
+    Static,
+    /// Generate a new static keypair for each session.
+    PerSession,
+    /// Reuse the static keypair for the given duration, then generate a new one.
+    Interval(Duration),
+}
+
+/// Reason why the security handshake of a new connection failed.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum HandshakeFailure {
+    /// The remote peer is banned.
+    #[error("Remote peer {0} is banned.")]
+    Banned(PeerId),
+    /// The remote peer was rejected by the predicate set with
+    /// [`NetworkBuilder::with_expected_peers`][crate::NetworkBuilder::with_expected_peers].
+    #[error("Remote peer {0} is not expected.")]
+    UnexpectedPeer(PeerId),
+    /// The identity of the remote could not be verified, e.g. because its noise key is not signed with its identity
+    /// key.
+    #[error("Remote identity could not be verified.")]
+    InvalidIdentity,
+    /// A handshake message could not be decrypted, e.g. because the remote uses different keys than expected.
+    #[error("Decrypting a handshake message failed.")]
+    Decrypt,
+    /// The remote does not support the security or multiplexing protocol.
+    #[error("Remote does not support the protocol.")]
+    ProtocolMismatch,
+    /// Another error occurred during the handshake.
+    #[error("Handshake failed: {0}")]
+    Other(String),
+}
+
+impl HandshakeFailure {
+    // Find the reason for a failed handshake in the chain of errors of a failed connection.
+    // `None` if the connection did not fail during the handshake.
+    pub(crate) fn find(error: &io::Error) -> Option<Self> {
+        let mut next: Option<&(dyn StdError + 'static)> = Some(error);
+        while let Some(e) = next {
+            if let Some(noise) = e.downcast_ref::<NoiseError>() {
+                return Some(Self::from_noise(noise));
+            }
+            if let Some(NegotiationError::Failed) = e.downcast_ref::<NegotiationError>() {
+                return Some(HandshakeFailure::ProtocolMismatch);
+            }
+            // The source of an I/O error is the source of the wrapped error, hence the wrapped error is used instead.
+            next = match e.downcast_ref::<io::Error>() {
+                Some(io_err) => io_err.get_ref().map(|e| e as &(dyn StdError + 'static)),
+                None => e.source(),
+            };
+        }
+        None
+    }
+
+    fn from_noise(error: &NoiseError) -> Self {
+        match error {
+            NoiseError::Io(e) => match e.get_ref().and_then(|e| e.downcast_ref::<HandshakeFailure>()) {
+                Some(failure) => failure.clone(),
+                None => HandshakeFailure::Other(e.to_string()),
+            },
+            NoiseError::Noise(snow::Error::Decrypt) => HandshakeFailure::Decrypt,
+            NoiseError::InvalidKey | NoiseError::UnexpectedKey | NoiseError::AuthenticationFailed => {
+                HandshakeFailure::InvalidIdentity
+            }
+            e => HandshakeFailure::Other(e.to_string()),
+        }
+    }
+}
+
+// Noise upgrade for authenticating connections, that creates new static keypairs according to the rotation policy.
+#[derive(Clone)]
+pub struct NoiseUpgrade {
+    // Current noise keypair and the time at which it was created.
+    current: Arc<Mutex<(AuthenticKeypair<X25519Spec>, Instant)>>,
+    // Identity keys for authenticating new noise keypairs. `None` if the keypair is pinned.
+    id_keys: Option<Keypair>,
+    rotation: NoiseKeyRotation,
+    // Predicate that the remote peer id has to fulfill after the handshake.
+    expected_peer: Option<ExpectedPeer>,
+    // Banned peers, whose connections are rejected right after the handshake.
+    banned_peers: BannedPeers,
+}
+
+impl NoiseUpgrade {
+    pub fn new(keypair: AuthenticKeypair<X25519Spec>, id_keys: Option<Keypair>, rotation: NoiseKeyRotation) -> Self {
+        NoiseUpgrade {
+            current: Arc::new(Mutex::new((keypair, Instant::now()))),
+            id_keys,
+            rotation,
+            expected_peer: None,
+            banned_peers: BannedPeers::default(),
+        }
+    }
+
+    pub fn with_expected_peer(mut self, expected_peer: Option<ExpectedPeer>) -> Self {
+        self.expected_peer = expected_peer;
+        self
+    }
+
+    pub fn with_banned_peers(mut self, banned_peers: BannedPeers) -> Self {
+        self.banned_peers = banned_peers;
+        self
+    }
+
+    // Fail the upgrade if the remote peer is banned or does not fulfill the expected-peer predicate.
+    fn check_peer<T>(
+        expected_peer: Option<ExpectedPeer>,
+        banned_peers: BannedPeers,
+        (peer_id, output): (PeerId, NoiseOutput<T>),
+    ) -> Result<(PeerId, NoiseOutput<T>), NoiseError> {
+        if banned_peers
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .contains(&peer_id)
+        {
+            let err = HandshakeFailure::Banned(peer_id);
+            return Err(NoiseError::Io(io::Error::new(io::ErrorKind::PermissionDenied, err)));
+        }
+        match expected_peer {
+            Some(is_expected) if !is_expected(&peer_id) => {
+                let err = HandshakeFailure::UnexpectedPeer(peer_id);
+                Err(NoiseError::Io(io::Error::new(io::ErrorKind::PermissionDenied, err)))
+            }
+            _ => Ok((peer_id, output)),
+        }
+    }
+
+    // Noise config for a new session, with a new static keypair if required by the rotation policy.
+    fn session_config(&self) -> NoiseAuthenticated<XX, X25519Spec, ()> {
+        let mut current = self.current.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(id_keys) = self.id_keys.as_ref() {
+            let is_expired = match self.rotation {
+                NoiseKeyRotation::Static => false,
+                NoiseKeyRotation::PerSession => true,
+                NoiseKeyRotation::Interval(interval) => current.1.elapsed() >= interval,
+            };
+            if is_expired {
+                // Keep the current keypair if signing the new one with the identity keys fails.
+                if let Ok(keypair) = NoiseKeypair::<X25519Spec>::new().into_authentic(id_keys) {
+                    *current = (keypair, Instant::now());
+                }
+            }
+        }
+        NoiseConfig::xx(current.0.clone()).into_authenticated()
+    }
+}
+
+impl UpgradeInfo for NoiseUpgrade {
+    type Info = &'static [u8];
+    type InfoIter = iter::Once<Self::Info>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        iter::once(PROTOCOL_NAME)
+    }
+}
+
+impl<T> InboundUpgrade<T> for NoiseUpgrade
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Output = (PeerId, NoiseOutput<T>);
+    type Error = NoiseError;
+    type Future = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+    fn upgrade_inbound(self, socket: T, info: Self::Info) -> Self::Future {
+        let expected_peer = self.expected_peer.clone();
+        let banned_peers = self.banned_peers.clone();
+        self.session_config()
+            .upgrade_inbound(socket, info)
+            .and_then(|output| async move { Self::check_peer(expected_peer, banned_peers, output) })
+            .boxed()
+    }
+}
+
+impl<T> OutboundUpgrade<T> for NoiseUpgrade
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Output = (PeerId, NoiseOutput<T>);
+    type Error = NoiseError;
+    type Future = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+    fn upgrade_outbound(self, socket: T, info: Self::Info) -> Self::Future {
+        let expected_peer = self.expected_peer.clone();
+        let banned_peers = self.banned_peers.clone();
+        self.session_config()
+            .upgrade_outbound(socket, info)
+            .and_then(|output| async move { Self::check_peer(expected_peer, banned_peers, output) })
+            .boxed()
+    }
+}
@@ -0,0 +1,429 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use super::{DialErr, EventChannel, ListenErr, Network, NetworkBuilder, NetworkEvent, ReceiveRequest};
+use crate::{
+    firewall::{FirewallRequest, FirewallRules, FwRequest, Rule},
+    OutboundFailure, RqRsMessage,
+};
+use futures::{
+    channel::mpsc,
+    future::{self, BoxFuture},
+    stream::BoxStream,
+    Future, FutureExt, StreamExt, TryFutureExt, TryStreamExt,
+};
+use libp2p::{
+    core::{
+        muxing::StreamMuxerBox,
+        transport::{Boxed, ListenerEvent, Transport, TransportError},
+    },
+    swarm::PendingConnectionError,
+    Multiaddr, PeerId,
+};
+use std::{
+    collections::hash_map::DefaultHasher,
+    fmt,
+    hash::{Hash, Hasher},
+    io,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+};
+
+/// Multiple [`Network`] shards of the same local peer, each with its own swarm and event loop, so that the handling
+/// of connections is spread across multiple tasks when there are thousands of peers.
+///
+/// Each remote peer is assigned to one shard by the hash of its peer id, and all connections to that peer are
+/// handled by this shard:
+/// - Outbound operations for the peer are routed to its shard.
+/// - Only the first shard listens. Inbound connections are handed to the shard of the remote peer once the handshake
+///   completed, hence a peer never holds connections on two shards.
+///
+/// The inbound requests and events of all shards are sent through the same channels. Since each peer is only handled
+/// by its shard, a rule that is returned for a
+/// [`FirewallRequest::PeerSpecificRule`][crate::firewall::FirewallRequest::PeerSpecificRule] applies to all
+/// connections with the peer.
+///
+/// The shards are built by the caller from the builder of each [`Shard`], using the same identity keys for all of
+/// them, e.g. with [`NetworkBuilder::with_keys`]. The firewall channel can be shared by cloning the sender.
+/// Firewall rules and bans that are set via the [`ShardedNetwork`] are applied to all shards that handle the peer.
+///
+/// **Note**: Operations on a shard that is accessed directly, e.g. via [`ShardedNetwork::shards_mut`], are not
+/// routed. Connections that such a shard dials to a peer of another shard are not handed over.
+pub struct ShardedNetwork<Rq, Rs, TRq = Rq>
+where
+    Rq: RqRsMessage,
+    Rs: RqRsMessage,
+    TRq: FwRequest<Rq>,
+{
+    shards: Vec<Network<Rq, Rs, TRq>>,
+}
+
+impl<Rq, Rs, TRq> ShardedNetwork<Rq, Rs, TRq>
+where
+    Rq: RqRsMessage,
+    Rs: RqRsMessage,
+    TRq: FwRequest<Rq>,
+{
+    /// Build `num_shards` networks with `build_shard`, which is called with the [`Shard`] handle of each shard and
+    /// has to build it from [`Shard::builder`].
+    ///
+    /// Parameters:
+    /// - `requests_channel`: Channel for forwarding the inbound requests of all shards.
+    /// - `events_channel`: Optional channel for forwarding the events of all shards.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidInput`] if the shards don't share the same peer id, or a shard was not
+    /// built from the builder of its [`Shard`].
+    pub async fn build<F, Fut>(
+        num_shards: NonZeroUsize,
+        requests_channel: EventChannel<ReceiveRequest<Rq, Rs>>,
+        events_channel: Option<EventChannel<NetworkEvent>>,
+        mut build_shard: F,
+    ) -> Result<Self, io::Error>
+    where
+        F: FnMut(Shard<Rq, Rs>) -> Fut,
+        Fut: Future<Output = Result<Network<Rq, Rs, TRq>, io::Error>>,
+    {
+        let router = ShardRouter::new(num_shards);
+        let mut shards: Vec<Network<Rq, Rs, TRq>> = Vec::with_capacity(num_shards.get());
+        for index in 0..num_shards.get() {
+            let handle = Shard {
+                routing: ShardRouting {
+                    router: router.clone(),
+                    index,
+                },
+                requests_channel: requests_channel.split(),
+                events_channel: events_channel.as_ref().map(EventChannel::split),
+            };
+            let shard = build_shard(handle).await?;
+            if shards.first().map_or(false, |s| s.peer_id() != shard.peer_id()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "All shards need to use the same identity keys",
+                ));
+            }
+            if !router.is_accepting(index) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Shards need to be built from the builder of their `Shard`",
+                ));
+            }
+            shards.push(shard);
+        }
+        Ok(ShardedNetwork { shards })
+    }
+
+    /// Get the [`PeerId`] of the local peer.
+    pub fn peer_id(&self) -> PeerId {
+        self.shards[0].peer_id()
+    }
+
+    /// Number of shards.
+    pub fn num_shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Index of the shard that handles the peer.
+    pub fn shard_index(&self, peer: &PeerId) -> usize {
+        shard_index(peer, self.shards.len())
+    }
+
+    /// Get the shard that handles the peer, e.g. for operations that are not routed by the [`ShardedNetwork`].
+    pub fn shard(&mut self, peer: &PeerId) -> &mut Network<Rq, Rs, TRq> {
+        let index = self.shard_index(peer);
+        &mut self.shards[index]
+    }
+
+    /// All shards, in the order in which they were built.
+    pub fn shards_mut(&mut self) -> &mut [Network<Rq, Rs, TRq>] {
+        &mut self.shards
+    }
+
+    /// Start listening on the `address` with the first shard, which hands the inbound connections to the shard of
+    /// the remote peer once the handshake completed.
+    ///
+    /// Returns the address of the listener.
+    pub async fn start_listening(&mut self, address: Multiaddr) -> Result<Multiaddr, ListenErr> {
+        self.shards[0].start_listening(address).await
+    }
+
+    /// Send a request to a remote peer via its shard.
+    ///
+    /// See [`Network::send_request`].
+    pub async fn send_request(&mut self, peer: PeerId, request: Rq) -> Result<Rs, OutboundFailure> {
+        self.shard(&peer).send_request(peer, request).await
+    }
+
+    /// Establish a connection to the remote peer from its shard.
+    pub async fn connect_peer(&mut self, peer: PeerId) -> Result<Multiaddr, DialErr> {
+        self.shard(&peer).connect_peer(peer).await
+    }
+
+    /// Add an address of the remote peer to its shard.
+    pub async fn add_address(&mut self, peer: PeerId, address: Multiaddr) {
+        self.shard(&peer).add_address(peer, address).await
+    }
+
+    /// Check whether the shard of the peer has an established connection to it.
+    pub async fn is_connected(&mut self, peer: PeerId) -> bool {
+        self.shard(&peer).is_connected(peer).await
+    }
+
+    /// Set the default firewall rule of all shards.
+    pub async fn set_firewall_default(&mut self, default: Option<Rule<TRq>>) {
+        for shard in self.shards.iter_mut() {
+            shard.set_firewall_default(default.clone()).await;
+        }
+    }
+
+    /// Set a peer specific rule in the shard of the peer.
+    pub async fn set_peer_rule(&mut self, peer: PeerId, rule: Rule<TRq>) {
+        self.shard(&peer).set_peer_rule(peer, rule).await;
+    }
+
+    /// Remove a peer specific rule from the shard of the peer.
+    pub async fn remove_peer_rule(&mut self, peer: PeerId) {
+        self.shard(&peer).remove_peer_rule(peer).await;
+    }
+
+    /// Ban the peer in all shards, so that the listening shard rejects its inbound connections during the handshake.
+    pub async fn ban_peer(&mut self, peer: PeerId) {
+        for shard in self.shards.iter_mut() {
+            shard.ban_peer(peer).await;
+        }
+    }
+
+    /// Unban the peer in all shards.
+    pub async fn unban_peer(&mut self, peer: PeerId) {
+        for shard in self.shards.iter_mut() {
+            shard.unban_peer(peer).await;
+        }
+    }
+}
+
+/// Handle for building one shard of a [`ShardedNetwork`], that is passed to the `build_shard` function of
+/// [`ShardedNetwork::build`].
+pub struct Shard<Rq, Rs> {
+    routing: ShardRouting,
+    requests_channel: EventChannel<ReceiveRequest<Rq, Rs>>,
+    events_channel: Option<EventChannel<NetworkEvent>>,
+}
+
+impl<Rq, Rs> Shard<Rq, Rs>
+where
+    Rq: RqRsMessage,
+    Rs: RqRsMessage,
+{
+    /// Index of the shard.
+    pub fn index(&self) -> usize {
+        self.routing.index
+    }
+
+    /// Create the builder for the shard, see [`NetworkBuilder::new`]. The inbound requests and events of the shard are
+    /// forwarded through the channels of the [`ShardedNetwork`], and its transport accepts the inbound connections
+    /// that are handed to it by the listening shard.
+    pub fn builder<TRq: FwRequest<Rq>>(
+        self,
+        firewall_channel: mpsc::Sender<FirewallRequest<TRq>>,
+        firewall_rules: FirewallRules<TRq>,
+    ) -> NetworkBuilder<Rq, Rs, TRq> {
+        let mut builder = NetworkBuilder::new(
+            firewall_channel,
+            self.requests_channel,
+            self.events_channel,
+            firewall_rules,
+        );
+        builder.shard_routing = Some(self.routing);
+        builder
+    }
+}
+
+impl<Rq, Rs> fmt::Debug for Shard<Rq, Rs> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Shard").field("index", &self.routing.index).finish()
+    }
+}
+
+// Index of the shard that handles the peer.
+fn shard_index(peer: &PeerId, num_shards: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    peer.hash(&mut hasher);
+    (hasher.finish() % num_shards as u64) as usize
+}
+
+// Inbound connection that was handed to the shard of the remote peer.
+struct RoutedConnection {
+    peer: PeerId,
+    muxer: StreamMuxerBox,
+    local_addr: Multiaddr,
+    remote_addr: Multiaddr,
+}
+
+// Channels for handing inbound connections to the shard of the remote peer, shared by the transports of all shards.
+#[derive(Clone)]
+struct ShardRouter {
+    senders: Arc<Vec<mpsc::UnboundedSender<RoutedConnection>>>,
+    // Receiver of each shard, until its transport starts accepting the handed connections.
+    receivers: Arc<Mutex<Vec<Option<mpsc::UnboundedReceiver<RoutedConnection>>>>>,
+}
+
+impl ShardRouter {
+    fn new(num_shards: NonZeroUsize) -> Self {
+        let (senders, receivers) = (0..num_shards.get())
+            .map(|_| {
+                let (tx, rx) = mpsc::unbounded();
+                (tx, Some(rx))
+            })
+            .unzip();
+        ShardRouter {
+            senders: Arc::new(senders),
+            receivers: Arc::new(Mutex::new(receivers)),
+        }
+    }
+
+    // Take the receiver of the shard.
+    fn take_receiver(&self, index: usize) -> Option<mpsc::UnboundedReceiver<RoutedConnection>> {
+        self.receivers.lock().unwrap_or_else(|e| e.into_inner())[index].take()
+    }
+
+    // Whether the transport of the shard accepts the connections that are handed to it.
+    fn is_accepting(&self, index: usize) -> bool {
+        self.receivers.lock().unwrap_or_else(|e| e.into_inner())[index].is_none()
+    }
+
+    // Hand the connection to the shard of the remote peer, unless it belongs to the shard that accepted it.
+    fn route(&self, index: usize, connection: RoutedConnection) -> Result<(PeerId, StreamMuxerBox), io::Error> {
+        let target = shard_index(&connection.peer, self.senders.len());
+        if target == index {
+            return Ok((connection.peer, connection.muxer));
+        }
+        self.senders[target]
+            .unbounded_send(connection)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Shard of the remote peer was shut down"))?;
+        Err(io::Error::new(io::ErrorKind::Other, RoutedToShard))
+    }
+}
+
+/// Routing of the inbound connections of a shard, that is set on its builder by [`Shard::builder`].
+pub(crate) struct ShardRouting {
+    router: ShardRouter,
+    index: usize,
+}
+
+impl ShardRouting {
+    // Wrap the transport of the shard so that its inbound connections are handed to the shard of the remote peer.
+    pub(crate) fn wrap_transport(self, transport: Boxed<(PeerId, StreamMuxerBox)>) -> Boxed<(PeerId, StreamMuxerBox)> {
+        RoutedTransport {
+            inner: transport,
+            router: self.router,
+            index: self.index,
+        }
+        .boxed()
+    }
+
+    // Address on which the transport of the shard accepts the connections that are handed to it.
+    pub(crate) fn accept_address() -> Multiaddr {
+        Multiaddr::empty()
+    }
+}
+
+// Marker for the I/O error of an inbound connection that was handed to the shard of the remote peer.
+#[derive(Debug)]
+struct RoutedToShard;
+
+impl fmt::Display for RoutedToShard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Connection was handed to the shard of the remote peer")
+    }
+}
+
+impl std::error::Error for RoutedToShard {}
+
+// Whether the error of an inbound connection was caused by handing it to the shard of the remote peer.
+pub(crate) fn is_routed_to_shard(error: &PendingConnectionError<TransportError<io::Error>>) -> bool {
+    fn is_routed(error: &io::Error) -> bool {
+        match error.get_ref() {
+            Some(inner) if inner.is::<RoutedToShard>() => true,
+            Some(inner) => inner.downcast_ref::<io::Error>().map_or(false, is_routed),
+            None => false,
+        }
+    }
+    match error {
+        PendingConnectionError::Transport(TransportError::Other(e)) | PendingConnectionError::IO(e) => is_routed(e),
+        _ => false,
+    }
+}
+
+// Transport of a shard, that hands inbound connections to the shard of the remote peer once the handshake completed,
+// and accepts the connections that other shards hand to it when listening on the `ShardRouting::accept_address`.
+#[derive(Clone)]
+struct RoutedTransport {
+    inner: Boxed<(PeerId, StreamMuxerBox)>,
+    router: ShardRouter,
+    index: usize,
+}
+
+impl Transport for RoutedTransport {
+    type Output = (PeerId, StreamMuxerBox);
+    type Error = io::Error;
+    type Listener = BoxStream<'static, Result<ListenerEvent<Self::ListenerUpgrade, io::Error>, io::Error>>;
+    type ListenerUpgrade = BoxFuture<'static, Result<Self::Output, io::Error>>;
+    type Dial = <Boxed<(PeerId, StreamMuxerBox)> as Transport>::Dial;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<Self::Listener, TransportError<Self::Error>> {
+        let RoutedTransport { inner, router, index } = self;
+        if addr == ShardRouting::accept_address() {
+            let receiver = match router.take_receiver(index) {
+                Some(rx) => rx,
+                None => return Err(TransportError::MultiaddrNotSupported(addr)),
+            };
+            let listener = receiver.map(|connection| {
+                Ok(ListenerEvent::Upgrade {
+                    upgrade: future::ready(Ok((connection.peer, connection.muxer))).boxed(),
+                    local_addr: connection.local_addr,
+                    remote_addr: connection.remote_addr,
+                })
+            });
+            return Ok(listener.boxed());
+        }
+        let listener = inner.listen_on(addr)?.map_ok(move |event| match event {
+            ListenerEvent::Upgrade {
+                upgrade,
+                local_addr,
+                remote_addr,
+            } => {
+                let router = router.clone();
+                let addrs = (local_addr.clone(), remote_addr.clone());
+                let upgrade = upgrade.and_then(move |(peer, muxer)| {
+                    let connection = RoutedConnection {
+                        peer,
+                        muxer,
+                        local_addr: addrs.0,
+                        remote_addr: addrs.1,
+                    };
+                    future::ready(router.route(index, connection))
+                });
+                ListenerEvent::Upgrade {
+                    upgrade: upgrade.boxed(),
+                    local_addr,
+                    remote_addr,
+                }
+            }
+            ListenerEvent::NewAddress(addr) => ListenerEvent::NewAddress(addr),
+            ListenerEvent::AddressExpired(addr) => ListenerEvent::AddressExpired(addr),
+            ListenerEvent::Error(e) => ListenerEvent::Error(e),
+        });
+        Ok(listener.boxed())
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>> {
+        self.inner.dial(addr)
+    }
+
+    fn dial_as_listener(self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>> {
+        self.inner.dial_as_listener(addr)
+    }
+
+    fn address_translation(&self, listen: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+        self.inner.address_translation(listen, observed)
+    }
+}
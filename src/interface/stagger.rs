@@ -0,0 +1,120 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::timer::{Delay, Instant};
+use futures::{future::BoxFuture, FutureExt};
+use libp2p::{
+    core::{
+        multiaddr::{Multiaddr, Protocol},
+        transport::{Transport, TransportError},
+    },
+    PeerId,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+// Transport that staggers concurrent dials to the same peer, so that the first address is tried first, but the
+// next one is tried in parallel if the first one did not connect after the stagger delay.
+// Peers are identified by the `/p2p/<peer-id>` suffix that the swarm appends to each dialed address.
+#[derive(Debug, Clone)]
+pub struct StaggeredDial<T> {
+    inner: T,
+    stagger: Duration,
+    // Earliest time for the next dial to each peer.
+    slots: Arc<Mutex<HashMap<PeerId, Instant>>>,
+}
+
+impl<T> StaggeredDial<T> {
+    pub fn new(inner: T, stagger: Duration) -> Self {
+        StaggeredDial {
+            inner,
+            stagger,
+            slots: Arc::default(),
+        }
+    }
+
+    // Delay for a new dial to the address, and reserve the next slot for the peer.
+    fn next_delay(&self, addr: &Multiaddr) -> Option<Duration> {
+        if self.stagger.is_zero() {
+            return None;
+        }
+        let peer = match addr.iter().last()? {
+            Protocol::P2p(hash) => PeerId::from_multihash(hash).ok()?,
+            _ => return None,
+        };
+        let mut slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        slots.retain(|_, slot| *slot > now);
+        let slot = slots.get(&peer).copied().unwrap_or(now);
+        slots.insert(peer, slot + self.stagger);
+        (slot > now).then(|| slot - now)
+    }
+}
+
+impl<T> Transport for StaggeredDial<T>
+where
+    T: Transport,
+    T::Dial: Send + 'static,
+{
+    type Output = T::Output;
+    type Error = T::Error;
+    type Listener = T::Listener;
+    type ListenerUpgrade = T::ListenerUpgrade;
+    type Dial = BoxFuture<'static, Result<T::Output, T::Error>>;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<Self::Listener, TransportError<Self::Error>> {
+        self.inner.listen_on(addr)
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>> {
+        let delay = self.next_delay(&addr);
+        let dial = self.inner.dial(addr)?;
+        match delay {
+            Some(delay) => Ok(Delay::new(delay).then(|_| dial).boxed()),
+            None => Ok(dial.boxed()),
+        }
+    }
+
+    fn dial_as_listener(self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>> {
+        Ok(self.inner.dial_as_listener(addr)?.boxed())
+    }
+
+    fn address_translation(&self, listen: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+        self.inner.address_translation(listen, observed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use libp2p::core::transport::MemoryTransport;
+
+    #[test]
+    fn stagger_dials_per_peer() {
+        let transport = StaggeredDial::new(MemoryTransport::default(), Duration::from_secs(1));
+        let peer = PeerId::random();
+        let addr = |port: u64| {
+            Multiaddr::empty()
+                .with(Protocol::Memory(port))
+                .with(Protocol::P2p(peer.into()))
+        };
+
+        assert!(transport.next_delay(&addr(1)).is_none());
+        let second = transport.next_delay(&addr(2)).unwrap();
+        assert!(second <= Duration::from_secs(1) && second > Duration::from_millis(900));
+        let third = transport.next_delay(&addr(3)).unwrap();
+        assert!(third > Duration::from_millis(1900));
+
+        // Dials to other peers, or without peer id, are not delayed.
+        let other = Multiaddr::empty()
+            .with(Protocol::Memory(1))
+            .with(Protocol::P2p(PeerId::random().into()));
+        assert!(transport.next_delay(&other).is_none());
+        assert!(transport
+            .next_delay(&Multiaddr::empty().with(Protocol::Memory(1)))
+            .is_none());
+    }
+}
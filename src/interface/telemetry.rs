@@ -0,0 +1,176 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{InboundFailure, OutboundFailure, RequestId, RequestMetadata};
+use libp2p::PeerId;
+use opentelemetry::{
+    global,
+    metrics::{Counter, Meter, Unit, ValueRecorder},
+    propagation::TextMapPropagator,
+    sdk::{propagation::TraceContextPropagator, trace::Tracer},
+    trace::{SpanKind, Status, TraceContextExt, TraceError, Tracer as _},
+    Context, KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use std::{collections::HashMap, fmt, time::Duration};
+
+/// Export of request lifecycle spans and request metrics to [OpenTelemetry][opentelemetry].
+///
+/// A span is recorded for each outbound request from sending it until the response was received or the request
+/// failed, and for each inbound request that is forwarded to the local application. The trace context of an outbound
+/// request is propagated in the W3C `traceparent` / `tracestate` entries of the [`RequestMetadata`], so that the span
+/// of the inbound request on the remote is a child of it.
+///
+/// **Note**: The trace context can only be propagated for requests that are sent with
+/// [`Network::send_request_with_metadata`][crate::Network::send_request_with_metadata], since other requests are sent
+/// without metadata.
+///
+/// Additionally the number of requests and failures, and the round-trip time of outbound requests are recorded as
+/// metrics.
+pub struct Telemetry {
+    tracer: Tracer,
+    propagator: TraceContextPropagator,
+    requests: Counter<u64>,
+    failures: Counter<u64>,
+    rtt: ValueRecorder<f64>,
+    // Contexts with the span of each pending outbound request.
+    outbound: HashMap<RequestId, Context>,
+}
+
+impl Telemetry {
+    /// Record spans with the `tracer`, and metrics with the instruments of the `meter`.
+    pub fn new(tracer: Tracer, meter: Meter) -> Self {
+        Telemetry {
+            tracer,
+            propagator: TraceContextPropagator::new(),
+            requests: meter
+                .u64_counter("p2p.requests")
+                .with_description("Number of sent and received requests.")
+                .init(),
+            failures: meter
+                .u64_counter("p2p.request_failures")
+                .with_description("Number of failed inbound and outbound requests.")
+                .init(),
+            rtt: meter
+                .f64_value_recorder("p2p.request_rtt")
+                .with_description("Round-trip time of outbound requests.")
+                .with_unit(Unit::new("s"))
+                .init(),
+            outbound: HashMap::new(),
+        }
+    }
+
+    /// Export spans via OTLP to the collector at the `endpoint`, e.g. `http://localhost:4317`.
+    ///
+    /// The spans are exported in batches on the tokio runtime. Metrics are recorded with the meter of the global
+    /// meter provider, that can be set e.g. with the metrics pipeline of [`opentelemetry_otlp`].
+    pub fn with_otlp_exporter(endpoint: &str) -> Result<Self, TraceError> {
+        let tracer = opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_endpoint(endpoint))
+            .install_batch(opentelemetry::runtime::Tokio)?;
+        Ok(Self::new(tracer, global::meter("p2p")))
+    }
+
+    /// Extract the trace context that the remote propagated in the metadata of a request, e.g. to record the
+    /// handling of an inbound request as part of the remote's trace.
+    pub fn extract_context(metadata: &RequestMetadata) -> Context {
+        TraceContextPropagator::new().extract(metadata)
+    }
+
+    // Start the span of an outbound request, and inject its context into the metadata of the request.
+    pub(crate) fn start_outbound(&self, peer: PeerId, metadata: Option<&mut RequestMetadata>) -> Context {
+        let builder = self
+            .tracer
+            .span_builder("p2p.outbound_request")
+            .with_kind(SpanKind::Client)
+            .with_attributes(vec![KeyValue::new("p2p.peer", peer.to_string())]);
+        let cx = Context::new().with_span(self.tracer.build_with_context(builder, &Context::new()));
+        if let Some(metadata) = metadata {
+            self.propagator.inject_context(&cx, metadata);
+        }
+        self.requests.add(1, &[KeyValue::new("direction", "outbound")]);
+        cx
+    }
+
+    // Cache the span until the request is answered or failed.
+    pub(crate) fn on_request_sent(&mut self, request_id: RequestId, cx: Context) {
+        cx.span()
+            .set_attribute(KeyValue::new("p2p.request_id", request_id.to_string()));
+        self.outbound.insert(request_id, cx);
+    }
+
+    pub(crate) fn on_response(&mut self, request_id: &RequestId, rtt: Duration) {
+        if let Some(cx) = self.outbound.remove(request_id) {
+            self.rtt.record(rtt.as_secs_f64(), &[]);
+            let span = cx.span();
+            span.set_status(Status::Ok);
+            span.end();
+        }
+    }
+
+    pub(crate) fn on_outbound_failure(&mut self, request_id: &RequestId, failure: &OutboundFailure) {
+        self.failures.add(1, &[KeyValue::new("direction", "outbound")]);
+        if let Some(cx) = self.outbound.remove(request_id) {
+            let span = cx.span();
+            span.set_status(Status::error(failure.to_string()));
+            span.end();
+        }
+    }
+
+    // Record the span of an inbound request as child of the context that the remote propagated in the metadata.
+    pub(crate) fn on_inbound_request(&self, request_id: RequestId, peer: PeerId, metadata: &RequestMetadata) {
+        self.requests.add(1, &[KeyValue::new("direction", "inbound")]);
+        let parent = self.propagator.extract(metadata);
+        let builder = self
+            .tracer
+            .span_builder("p2p.inbound_request")
+            .with_kind(SpanKind::Server)
+            .with_attributes(vec![
+                KeyValue::new("p2p.peer", peer.to_string()),
+                KeyValue::new("p2p.request_id", request_id.to_string()),
+            ]);
+        let cx = parent.with_span(self.tracer.build_with_context(builder, &parent));
+        cx.span().end();
+    }
+
+    pub(crate) fn on_inbound_failure(&self, failure: &InboundFailure) {
+        self.failures.add(
+            1,
+            &[
+                KeyValue::new("direction", "inbound"),
+                KeyValue::new("failure", failure.to_string()),
+            ],
+        );
+    }
+}
+
+impl fmt::Debug for Telemetry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Telemetry")
+            .field("pending_spans", &self.outbound.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use opentelemetry::{sdk::trace::TracerProvider, trace::TracerProvider as _};
+
+    #[test]
+    fn propagate_trace_context() {
+        let provider = TracerProvider::builder().build();
+        let telemetry = Telemetry::new(provider.tracer("p2p-test"), global::meter("p2p-test"));
+        let mut metadata = RequestMetadata::new();
+        let cx = telemetry.start_outbound(PeerId::random(), Some(&mut metadata));
+        assert!(metadata.contains_key("traceparent"));
+
+        let remote_cx = Telemetry::extract_context(&metadata);
+        assert_eq!(
+            remote_cx.span().span_context().trace_id(),
+            cx.span().span_context().trace_id()
+        );
+        assert!(remote_cx.span().span_context().is_remote());
+    }
+}
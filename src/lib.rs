@@ -13,8 +13,10 @@
 
 mod behaviour;
 mod libp2p_reexport {
+    #[cfg(feature = "metrics")]
+    pub use libp2p::metrics;
     pub use libp2p::{
-        core::{ConnectedPoint, Executor},
+        core::{connection::ConnectionId, ConnectedPoint, Executor},
         identity,
         swarm::DialError,
         Multiaddr, PeerId,
@@ -23,15 +25,42 @@ mod libp2p_reexport {
     pub type NoiseKeypair = libp2p::noise::Keypair<libp2p::noise::X25519Spec>;
 }
 mod interface;
+#[cfg(feature = "testutils")]
+pub mod testutils;
+mod timer;
 
+#[cfg(feature = "fuzzing")]
+pub use behaviour::fuzzing;
 pub use behaviour::{
-    assemble_relayed_addr, firewall, AddressInfo, InboundFailure, OutboundFailure, PeerAddress, RelayNotSupported,
-    RequestId, RqRsMessage,
+    assemble_relay_chain_addr, assemble_relayed_addr, block_hash, dialable_addr, firewall, AddressInfo, AddressScore,
+    AwaitingFirewall, BlockErr, BlockExchangeConfig, BlockStore, BufferTuning, CloseReason, Codec, ConnectionSelection,
+    CounterIds, DecodeWorkers, DialBackoff, EnqueueErr, EventSource, ExcessConnectionPolicy, FileJournal,
+    FileQueueStore, FlapDetection, GlobalRequestId, Hello, IdempotencyConfig, InboundFailure, InboundFailureCounts,
+    InboundWatermarks, JournalRecord, KeepAliveProbes, LimitPressurePolicy, MailboxConfig, MailboxDelivery,
+    MemoryBlockStore, MessageMigrations, MessageProtocol, NamespacedIds, NetworkStats, OutboundFailure,
+    OutboundFailureCounts, OutboundQueueConfig, PeerAddress, PeerStats, PendingRequests, PingErr, PresenceConfig,
+    QueueStore, QueuedRequest, RecentEvent, RelayCandidate, RelayNotSupported, RelayPolicy, RelaySelection, RelayStats,
+    RemoteError, RequestBatching, RequestConnection, RequestId, RequestIdGenerator, RequestJournal, RequestMetadata,
+    RequestMiddleware, RequestPriority, RequestStatus, Responder, ResponseHook, ResponseStatus, RqRsMessage, RttSample,
+    TransferConfig, TransferFailure, UnansweredRequest, UnknownRequestType, MAX_BLOCK_SIZE,
 };
+#[cfg(feature = "metrics")]
+pub use interface::NetworkMetrics;
+#[cfg(feature = "tcp-transport")]
+pub use interface::OutboundBinding;
+#[cfg(feature = "otel")]
+pub use interface::Telemetry;
 pub use interface::{
-    ChannelSinkConfig, ConnectionErr, ConnectionLimits, DialErr, EventChannel, InitKeypair, ListenErr, ListenRelayErr,
-    Listener, Network, NetworkBuilder, NetworkEvent, ReceiveRequest, TransportErr,
+    AddressFailure, ChannelCapacities, ChannelSinkConfig, ConnectionErr, ConnectionLimits, DialErr, EventChannel,
+    EventLoopDriver, HandshakeFailure, InitKeypair, KeyErr, ListenErr, ListenRelayErr, Listener, NamespaceBehaviour,
+    NamespaceEvent, NamespacedNetwork, Network, NetworkApi, NetworkBuilder, NetworkConfig, NetworkEvent,
+    NetworkFailure, NetworkInfo, NoiseKeyRotation, ProbeErr, QueuedResponse, ReceiveRequest, ReceivedBlob,
+    RelayReservation, RequestHandle, Shard, ShardedNetwork, StateDump, SwarmDebugEvent, TimedResponse, TransportErr,
 };
+#[cfg(feature = "keystore")]
+pub use interface::{Keystore, KeystoreErr};
+#[cfg(feature = "mock")]
+pub use interface::{MockController, MockNetwork, OutboundRequest};
 pub use libp2p_reexport::*;
 
 #[macro_export(local_inner_macros)]
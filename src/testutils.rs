@@ -0,0 +1,223 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+//! Helpers for end-to-end tests with multiple [`Network`] instances in the same process.
+//!
+//! A [`TestNet`] spins up N nodes that communicate via the in-memory transport, each with a generated keypair and the
+//! same preset firewall rules. All nodes know the addresses of each other, so that they can be connected without
+//! further setup:
+//!
+//! ```
+//! # use p2p::{firewall::FirewallRules, testutils::TestNet};
+//! # async fn test() -> std::io::Result<()> {
+//! let mut net = TestNet::<String, String>::new(3, FirewallRules::allow_all()).await?;
+//! net.node_mut(1).serve(|_, request| request.to_uppercase());
+//! let response = net.request(0, 1, "ping".into()).await.unwrap();
+//! assert_eq!(response, "PING");
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Requires feature **testutils**, and a tokio runtime in which the event loops are spawned.
+
+use crate::{
+    firewall::{FirewallRequest, FirewallRules, FwRequest},
+    timer::Delay,
+    ChannelSinkConfig, DialErr, EventChannel, Network, NetworkBuilder, OutboundFailure, ReceiveRequest, RqRsMessage,
+};
+use futures::{channel::mpsc, future, FutureExt, StreamExt};
+use libp2p::{
+    core::{multiaddr::Protocol, transport::MemoryTransport},
+    Multiaddr, PeerId,
+};
+use std::{io, mem, time::Duration};
+
+// Capacity of the request- and firewall channels of each node.
+const CHANNEL_CAPACITY: usize = 64;
+
+// Interval in which the connection state is polled while awaiting connectivity.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Single node of a [`TestNet`].
+pub struct TestNode<Rq: RqRsMessage, Rs: RqRsMessage, TRq: FwRequest<Rq> = Rq> {
+    /// Interface of the node's network.
+    pub network: Network<Rq, Rs, TRq>,
+    /// Memory address on which the node is listening.
+    pub address: Multiaddr,
+    /// Inbound requests that were approved by the firewall.
+    pub request_rx: mpsc::Receiver<ReceiveRequest<Rq, Rs>>,
+    /// Requests of the firewall, if the preset rules ask for approval.
+    pub firewall_rx: mpsc::Receiver<FirewallRequest<TRq>>,
+}
+
+impl<Rq: RqRsMessage, Rs: RqRsMessage, TRq: FwRequest<Rq>> TestNode<Rq, Rs, TRq> {
+    /// Get the peer id of the node.
+    pub fn peer_id(&self) -> PeerId {
+        self.network.peer_id()
+    }
+
+    /// Wait for the next inbound request. Returns `None` if the requests are already served by [`TestNode::serve`].
+    pub async fn next_request(&mut self) -> Option<ReceiveRequest<Rq, Rs>> {
+        self.request_rx.next().await
+    }
+
+    /// Answer all inbound requests with the `handler` in a new task.
+    ///
+    /// The [`TestNode::request_rx`] channel is taken over by the task, and no longer yields any requests.
+    pub fn serve<F>(&mut self, mut handler: F)
+    where
+        F: FnMut(PeerId, Rq) -> Rs + Send + 'static,
+    {
+        let (_, closed_rx) = mpsc::channel(0);
+        let mut request_rx = mem::replace(&mut self.request_rx, closed_rx);
+        tokio::spawn(async move {
+            while let Some(rq) = request_rx.next().await {
+                let _ = rq.responder.respond(handler(rq.peer, rq.request));
+            }
+        });
+    }
+}
+
+/// Multiple interconnected [`Network`] instances that communicate via the in-memory transport.
+pub struct TestNet<Rq: RqRsMessage, Rs: RqRsMessage, TRq: FwRequest<Rq> = Rq> {
+    nodes: Vec<TestNode<Rq, Rs, TRq>>,
+}
+
+impl<Rq: RqRsMessage, Rs: RqRsMessage, TRq: FwRequest<Rq>> TestNet<Rq, Rs, TRq> {
+    /// Spin up `n` nodes with the default config and the firewall `rules`.
+    pub async fn new(n: usize, rules: FirewallRules<TRq>) -> io::Result<Self> {
+        Self::with_builder(n, rules, |_, builder| builder).await
+    }
+
+    /// Spin up `n` nodes, and adjust the builder of each node with `configure`, which is called with the index of the
+    /// node and its builder.
+    ///
+    /// Each node listens on a new memory address, and the addresses of all other nodes are added to it.
+    pub async fn with_builder<F>(n: usize, rules: FirewallRules<TRq>, mut configure: F) -> io::Result<Self>
+    where
+        F: FnMut(usize, NetworkBuilder<Rq, Rs, TRq>) -> NetworkBuilder<Rq, Rs, TRq>,
+    {
+        let mut nodes = Vec::with_capacity(n);
+        for i in 0..n {
+            let (firewall_tx, firewall_rx) = mpsc::channel(CHANNEL_CAPACITY);
+            let (request_channel, request_rx) = EventChannel::new(CHANNEL_CAPACITY, ChannelSinkConfig::Block);
+            let builder = NetworkBuilder::new(firewall_tx, request_channel, None, rules.clone());
+            let mut network = configure(i, builder)
+                .build_with_transport(MemoryTransport::default(), |fut| {
+                    tokio::spawn(fut);
+                })
+                .await?;
+            let address = network
+                .start_listening(Multiaddr::empty().with(Protocol::Memory(0)))
+                .await
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            nodes.push(TestNode {
+                network,
+                address,
+                request_rx,
+                firewall_rx,
+            });
+        }
+        let peers: Vec<_> = nodes.iter().map(|n| (n.peer_id(), n.address.clone())).collect();
+        for node in nodes.iter_mut() {
+            let local = node.peer_id();
+            for (peer, address) in peers.iter().filter(|(p, _)| *p != local) {
+                node.network.add_address(*peer, address.clone()).await;
+            }
+        }
+        Ok(TestNet { nodes })
+    }
+
+    /// Number of nodes.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Whether the net has no nodes.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Get the node at the index.
+    ///
+    /// Panics if the index is out of bounds.
+    pub fn node(&self, index: usize) -> &TestNode<Rq, Rs, TRq> {
+        &self.nodes[index]
+    }
+
+    /// Get the node at the index.
+    ///
+    /// Panics if the index is out of bounds.
+    pub fn node_mut(&mut self, index: usize) -> &mut TestNode<Rq, Rs, TRq> {
+        &mut self.nodes[index]
+    }
+
+    /// All nodes, in the order in which they were created.
+    pub fn nodes_mut(&mut self) -> &mut [TestNode<Rq, Rs, TRq>] {
+        &mut self.nodes
+    }
+
+    /// Peer ids of all nodes.
+    pub fn peer_ids(&self) -> Vec<PeerId> {
+        self.nodes.iter().map(TestNode::peer_id).collect()
+    }
+
+    /// Connect the node at index `a` to the node at index `b`.
+    pub async fn connect(&mut self, a: usize, b: usize) -> Result<Multiaddr, DialErr> {
+        let peer = self.nodes[b].peer_id();
+        self.nodes[a].network.connect_peer(peer).await
+    }
+
+    /// Connect each pair of nodes.
+    pub async fn connect_all(&mut self) -> Result<(), DialErr> {
+        for a in 0..self.nodes.len() {
+            for b in a + 1..self.nodes.len() {
+                self.connect(a, b).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait until the node at index `a` is connected to the node at index `b`.
+    /// Returns `false` if they were not connected within the `timeout`.
+    pub async fn await_connected(&mut self, a: usize, b: usize, timeout: Duration) -> bool {
+        let peer = self.nodes[b].peer_id();
+        let network = &mut self.nodes[a].network;
+        let poll_connected = async {
+            while !network.is_connected(peer).await {
+                Delay::new(POLL_INTERVAL).await;
+            }
+        };
+        let res = future::select(poll_connected.boxed(), Delay::new(timeout)).await;
+        matches!(res, future::Either::Left(_))
+    }
+
+    /// Send a request from the node at index `from` to the node at index `to`.
+    pub async fn request(&mut self, from: usize, to: usize, request: Rq) -> Result<Rs, OutboundFailure> {
+        let peer = self.nodes[to].peer_id();
+        self.nodes[from].network.send_request(peer, request).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn exchange_requests() {
+        let mut net = TestNet::<u32, u32>::new(3, FirewallRules::allow_all()).await.unwrap();
+        net.node_mut(2).serve(|_, request| request + 1);
+        assert!(!net.await_connected(0, 2, Duration::ZERO).await);
+
+        assert_eq!(net.request(0, 2, 41).await.unwrap(), 42);
+        assert!(net.await_connected(2, 0, Duration::from_secs(1)).await);
+        net.connect_all().await.unwrap();
+        assert!(net.await_connected(1, 2, Duration::from_secs(1)).await);
+
+        // Requests that are not answered fail on the sender's side.
+        let peer = net.node(0).peer_id();
+        let mut network = net.node(1).network.clone();
+        let (response, _) = future::join(network.send_request(peer, 1), net.node_mut(0).next_request().map(drop)).await;
+        assert!(response.is_err());
+    }
+}
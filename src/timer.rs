@@ -0,0 +1,65 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+// Timer abstraction for the internal timeouts and delays.
+//
+// The implementation is selected by feature:
+// - `tokio-timer`: the timer of the tokio runtime, hence the network has to run within a tokio runtime.
+// - `async-std-timer`: the timer of `async-io`, which is also used by async-std.
+// - otherwise: `wasm-timer`, which runs its own timer thread (or uses the browser APIs on wasm) and thus works with any
+//   executor.
+//
+// If multiple timer features are enabled, tokio takes precedence over async-std.
+
+use futures::FutureExt;
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+pub use wasm_timer::Instant;
+
+// Future that completes after a duration.
+pub struct Delay {
+    #[cfg(feature = "tokio-timer")]
+    inner: Pin<Box<tokio::time::Sleep>>,
+    #[cfg(all(feature = "async-std-timer", not(feature = "tokio-timer")))]
+    inner: async_io::Timer,
+    #[cfg(not(any(feature = "tokio-timer", feature = "async-std-timer")))]
+    inner: wasm_timer::Delay,
+}
+
+impl Delay {
+    pub fn new(duration: Duration) -> Self {
+        #[cfg(feature = "tokio-timer")]
+        let inner = Box::pin(tokio::time::sleep(duration));
+        #[cfg(all(feature = "async-std-timer", not(feature = "tokio-timer")))]
+        let inner = async_io::Timer::after(duration);
+        #[cfg(not(any(feature = "tokio-timer", feature = "async-std-timer")))]
+        let inner = wasm_timer::Delay::new(duration);
+        Delay { inner }
+    }
+}
+
+impl Future for Delay {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // The wasm timer only fails if its timer thread is gone. The delay is then treated as elapsed, so that
+        // timeouts are still triggered.
+        self.inner.poll_unpin(cx).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn delay_elapses() {
+        let start = Instant::now();
+        Delay::new(Duration::from_millis(50)).await;
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+}
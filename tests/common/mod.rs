@@ -0,0 +1,34 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+//! Shared fixture for building networks in the integration tests.
+
+#![allow(dead_code)]
+
+use p2p::{firewall::FirewallRules, ChannelSinkConfig, EventChannel, Network, NetworkBuilder, RqRsMessage};
+
+use futures::channel::mpsc;
+#[cfg(not(feature = "tcp-transport"))]
+use libp2p::tcp::TokioTcpConfig;
+
+pub fn builder() -> NetworkBuilder<(), ()> {
+    let (dummy_fw_tx, _) = mpsc::channel(10);
+    let (dummy_rq_channel, _) = EventChannel::new(10, ChannelSinkConfig::DropLatest);
+    NetworkBuilder::new(dummy_fw_tx, dummy_rq_channel, None, FirewallRules::allow_all())
+}
+
+pub async fn build<Rq: RqRsMessage, Rs: RqRsMessage>(builder: NetworkBuilder<Rq, Rs>) -> Network<Rq, Rs> {
+    #[cfg(not(feature = "tcp-transport"))]
+    let peer = {
+        let executor = |fut| {
+            tokio::spawn(fut);
+        };
+        builder
+            .build_with_transport(TokioTcpConfig::new(), executor)
+            .await
+            .unwrap()
+    };
+    #[cfg(feature = "tcp-transport")]
+    let peer = builder.build().await.unwrap();
+    peer
+}
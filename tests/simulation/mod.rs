@@ -0,0 +1,273 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+//! Simulated network for integration tests.
+//!
+//! Multiple [`Network`] instances are connected via the in-memory transport. The links between them can be configured
+//! with latency, jitter and loss, and the network can be split into partitions. All random decisions are made with a
+//! seeded RNG, so that a failing test case can be reproduced from its seed.
+
+#![allow(dead_code)]
+
+use futures::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    ready,
+};
+use libp2p::core::{
+    multiaddr::Protocol,
+    transport::{memory::Channel, MemoryTransport, Transport},
+    Multiaddr,
+};
+use p2p::{firewall::FwRequest, Network, NetworkBuilder, PeerId, RqRsMessage};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+use tokio::time::{sleep, Sleep};
+
+/// Identifier of a node in the simulation. It is equal to the port of the node's memory address.
+pub type NodeId = u64;
+
+// Memory ports are global per process, hence they are shared by all simulations that run in parallel.
+static NEXT_PORT: AtomicU64 = AtomicU64::new(1);
+
+static NEXT_CONNECTION: AtomicU64 = AtomicU64::new(0);
+
+/// Conditions of the link between two nodes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LinkConfig {
+    /// Delay of each write on the link.
+    pub latency: Duration,
+    /// Maximum random delay that is added to the latency.
+    pub jitter: Duration,
+    /// Probability in `[0, 1]` that a write is lost.
+    ///
+    /// The transport is stream-based and can not retransmit lost data, hence a lost write closes the connection the
+    /// same way a broken link would.
+    pub loss: f64,
+}
+
+struct SimState {
+    rng: StdRng,
+    default_link: LinkConfig,
+    links: HashMap<(NodeId, NodeId), LinkConfig>,
+    partitions: HashSet<(NodeId, NodeId)>,
+    // Wakers of the connections that wait for data, to notify them about new partitions.
+    readers: HashMap<u64, Waker>,
+}
+
+// Links are symmetric.
+fn link_key(a: NodeId, b: NodeId) -> (NodeId, NodeId) {
+    (a.min(b), a.max(b))
+}
+
+/// Simulated network that connects [`Network`] instances via the in-memory transport.
+#[derive(Clone)]
+pub struct SimNetwork {
+    state: Arc<Mutex<SimState>>,
+}
+
+impl SimNetwork {
+    /// New simulation without latency and loss, using the `seed` for all random decisions.
+    pub fn new(seed: u64) -> Self {
+        let state = SimState {
+            rng: StdRng::seed_from_u64(seed),
+            default_link: LinkConfig::default(),
+            links: HashMap::new(),
+            partitions: HashSet::new(),
+            readers: HashMap::new(),
+        };
+        SimNetwork {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Set the conditions of all links that were not configured individually.
+    pub fn set_default_link(&self, config: LinkConfig) {
+        self.state.lock().unwrap().default_link = config;
+    }
+
+    /// Set the conditions of the link between `a` and `b`, in both directions.
+    pub fn set_link(&self, a: NodeId, b: NodeId, config: LinkConfig) {
+        self.state.lock().unwrap().links.insert(link_key(a, b), config);
+    }
+
+    /// Split the network so that the nodes in `group_a` can not reach the nodes in `group_b` and vice versa.
+    /// Established connections between the groups fail on their next read or write, new dials are refused.
+    pub fn partition(&self, group_a: &[NodeId], group_b: &[NodeId]) {
+        let mut state = self.state.lock().unwrap();
+        for a in group_a {
+            for b in group_b {
+                state.partitions.insert(link_key(*a, *b));
+            }
+        }
+        state.readers.drain().for_each(|(_, waker)| waker.wake());
+    }
+
+    /// Remove all partitions.
+    pub fn heal(&self) {
+        self.state.lock().unwrap().partitions.clear();
+    }
+
+    pub fn is_partitioned(&self, a: NodeId, b: NodeId) -> bool {
+        self.state.lock().unwrap().partitions.contains(&link_key(a, b))
+    }
+
+    // Delay for the next write from `local` to `remote`, or the error if the write is lost.
+    fn sample_write(&self, local: NodeId, remote: NodeId) -> io::Result<Duration> {
+        let mut state = self.state.lock().unwrap();
+        let key = link_key(local, remote);
+        if state.partitions.contains(&key) {
+            return Err(partitioned());
+        }
+        let config = state.links.get(&key).copied().unwrap_or(state.default_link);
+        if config.loss > 0.0 && state.rng.gen_bool(config.loss.min(1.0)) {
+            return Err(io::Error::new(io::ErrorKind::ConnectionReset, "write lost"));
+        }
+        let jitter = match config.jitter.as_micros() as u64 {
+            0 => Duration::ZERO,
+            max => Duration::from_micros(state.rng.gen_range(0..=max)),
+        };
+        Ok(config.latency + jitter)
+    }
+
+    /// Build the network on the simulated transport and start listening on the memory address of the new node.
+    pub async fn spawn<Rq, Rs, TRq>(&self, builder: NetworkBuilder<Rq, Rs, TRq>) -> SimNode<Rq, Rs, TRq>
+    where
+        Rq: RqRsMessage,
+        Rs: RqRsMessage,
+        TRq: FwRequest<Rq>,
+    {
+        let id = NEXT_PORT.fetch_add(1, Ordering::Relaxed);
+        let sim = self.clone();
+        let transport =
+            MemoryTransport::default().and_then(move |conn, _| SimConnection::handshake(conn, id, sim.clone()));
+        let executor = |fut| {
+            tokio::spawn(fut);
+        };
+        let mut network = builder.build_with_transport(transport, executor).await.unwrap();
+        let addr = network
+            .start_listening(Multiaddr::empty().with(Protocol::Memory(id)))
+            .await
+            .unwrap();
+        SimNode { id, addr, network }
+    }
+}
+
+/// [`Network`] instance in the simulation.
+pub struct SimNode<Rq: RqRsMessage, Rs: RqRsMessage, TRq: FwRequest<Rq> = Rq> {
+    pub id: NodeId,
+    pub addr: Multiaddr,
+    pub network: Network<Rq, Rs, TRq>,
+}
+
+impl<Rq: RqRsMessage, Rs: RqRsMessage, TRq: FwRequest<Rq>> SimNode<Rq, Rs, TRq> {
+    pub fn peer_id(&self) -> PeerId {
+        self.network.peer_id()
+    }
+
+    /// Add the address of the other node, so that it can be dialed.
+    pub async fn add_peer<Rq2, Rs2, TRq2>(&mut self, other: &SimNode<Rq2, Rs2, TRq2>)
+    where
+        Rq2: RqRsMessage,
+        Rs2: RqRsMessage,
+        TRq2: FwRequest<Rq2>,
+    {
+        self.network.add_address(other.peer_id(), other.addr.clone()).await;
+    }
+}
+
+fn partitioned() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "network partitioned")
+}
+
+// Connection on the memory transport that applies the conditions of the link to the remote node.
+struct SimConnection {
+    inner: Channel<Vec<u8>>,
+    id: u64,
+    local: NodeId,
+    remote: NodeId,
+    sim: SimNetwork,
+    // Delay of the current write.
+    write_delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl SimConnection {
+    // Exchange the node ids with the remote, and refuse the connection if the nodes are partitioned.
+    async fn handshake(mut inner: Channel<Vec<u8>>, local: NodeId, sim: SimNetwork) -> io::Result<Self> {
+        inner.write_all(&local.to_be_bytes()).await?;
+        inner.flush().await?;
+        let mut remote = [0u8; 8];
+        inner.read_exact(&mut remote).await?;
+        let remote = NodeId::from_be_bytes(remote);
+        if sim.is_partitioned(local, remote) {
+            return Err(partitioned());
+        }
+        Ok(SimConnection {
+            inner,
+            id: NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed),
+            local,
+            remote,
+            sim,
+            write_delay: None,
+        })
+    }
+}
+
+impl AsyncRead for SimConnection {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        if this.sim.is_partitioned(this.local, this.remote) {
+            return Poll::Ready(Err(partitioned()));
+        }
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if poll.is_pending() {
+            let mut state = this.sim.state.lock().unwrap();
+            state.readers.insert(this.id, cx.waker().clone());
+        }
+        poll
+    }
+}
+
+impl AsyncWrite for SimConnection {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        if this.write_delay.is_none() {
+            match this.sim.sample_write(this.local, this.remote) {
+                Ok(duration) => this.write_delay = Some(Box::pin(sleep(duration))),
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+        }
+        if let Some(delay) = this.write_delay.as_mut() {
+            ready!(delay.as_mut().poll(cx));
+        }
+        let res = ready!(Pin::new(&mut this.inner).poll_write(cx, buf));
+        this.write_delay = None;
+        Poll::Ready(res)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+impl Drop for SimConnection {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.sim.state.lock() {
+            state.readers.remove(&self.id);
+        }
+    }
+}
@@ -0,0 +1,49 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+mod common;
+
+use common::{build, builder};
+
+use std::time::Duration;
+
+use p2p::{block_hash, BlockErr, BlockExchangeConfig, MemoryBlockStore};
+
+#[tokio::test]
+async fn block_exchange() {
+    let mut remote = build(
+        builder()
+            .with_mdns_support(false)
+            .with_block_exchange(BlockExchangeConfig::default(), MemoryBlockStore::default()),
+    )
+    .await;
+    let remote_id = remote.peer_id();
+    let remote_addr = remote
+        .start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+    let hash = remote.put_block(b"block data".to_vec()).await.unwrap();
+    assert_eq!(hash, block_hash(b"block data"));
+
+    let config = BlockExchangeConfig {
+        want_timeout: Duration::from_secs(2),
+    };
+    let mut peer = build(
+        builder()
+            .with_mdns_support(false)
+            .with_block_exchange(config, MemoryBlockStore::default()),
+    )
+    .await;
+    peer.add_address(remote_id, remote_addr).await;
+    peer.connect_peer(remote_id).await.unwrap();
+
+    let data = peer.get_block(hash).await.unwrap();
+    assert_eq!(data, b"block data".to_vec());
+
+    let missing = block_hash(b"missing");
+    assert_eq!(peer.get_block(missing).await, Err(BlockErr::NotFound));
+    assert_eq!(
+        build(builder()).await.put_block(Vec::new()).await,
+        Err(BlockErr::NotEnabled)
+    );
+}
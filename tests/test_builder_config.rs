@@ -1,39 +1,28 @@
 // Copyright 2020-2021 IOTA Stiftung
 // SPDX-License-Identifier: Apache-2.0
 
-use std::time::Duration;
+mod common;
 
-use p2p::{
-    assemble_relayed_addr, firewall::FirewallRules, ChannelSinkConfig, DialErr, EventChannel, ListenErr,
-    ListenRelayErr, Network, NetworkBuilder, PeerId, TransportErr,
-};
+use common::{build, builder};
 
-use futures::channel::mpsc;
-#[cfg(not(feature = "tcp-transport"))]
-use libp2p::tcp::TokioTcpConfig;
+use std::{num::NonZeroUsize, time::Duration};
 
-fn builder() -> NetworkBuilder<(), ()> {
-    let (dummy_fw_tx, _) = mpsc::channel(10);
-    let (dummy_rq_channel, _) = EventChannel::new(10, ChannelSinkConfig::DropLatest);
-    NetworkBuilder::new(dummy_fw_tx, dummy_rq_channel, None, FirewallRules::allow_all())
-}
+#[cfg(feature = "mdns")]
+use p2p::DialErr;
+#[cfg(feature = "relay")]
+use p2p::{assemble_relayed_addr, ListenErr, ListenRelayErr, PeerId, TransportErr};
+use p2p::{
+    firewall::FirewallRules, ChannelCapacities, ChannelSinkConfig, EventChannel, NetworkBuilder, NetworkConfig,
+    OutboundFailure, RemoteError,
+};
 
-async fn build(builder: NetworkBuilder<(), ()>) -> Network<(), ()> {
-    #[cfg(not(feature = "tcp-transport"))]
-    let peer = {
-        let executor = |fut| {
-            tokio::spawn(fut);
-        };
-        builder
-            .build_with_transport(TokioTcpConfig::new(), executor)
-            .await
-            .unwrap()
-    };
-    #[cfg(feature = "tcp-transport")]
-    let peer = builder.build().await.unwrap();
-    peer
-}
+use futures::{channel::mpsc, future, StreamExt};
+use libp2p::{
+    ping::{Ping, PingConfig, PingEvent},
+    tcp::TokioTcpConfig,
+};
 
+#[cfg(feature = "mdns")]
 #[tokio::test]
 async fn mdns_config() {
     // Test both peers mdns disabled.
@@ -72,6 +61,7 @@ async fn mdns_config() {
     assert!(matches!(err, DialErr::NoAddresses), "unexpected error: {}", err);
 }
 
+#[cfg(feature = "relay")]
 #[tokio::test]
 async fn relay_config() {
     let mut peer = build(builder().with_relay_support(false)).await;
@@ -100,7 +90,7 @@ async fn relay_config() {
     // Listening on a relayed address directly should fail.
     let err = peer.start_listening(relayed_address.clone()).await.unwrap_err();
     assert!(
-        matches!(err, ListenErr::Transport(TransportErr::MultiaddrNotSupported(ref addr)) if addr == &relayed_address),
+        matches!(err, ListenErr::Transport { error: TransportErr::MultiaddrNotSupported(ref addr), .. } if addr == &relayed_address),
         "unexpected error: {}",
         err
     );
@@ -110,3 +100,115 @@ async fn relay_config() {
     assert!(peer.set_relay_fallback(PeerId::random(), true).await.is_err());
     assert!(peer.use_specific_relay(PeerId::random(), relay_id, true).await.is_err());
 }
+
+#[tokio::test]
+async fn channel_capacities() {
+    let capacities = ChannelCapacities {
+        commands: 1,
+        notify_handler: NonZeroUsize::new(1).unwrap(),
+        connection_events: 1,
+        firewall: 1,
+        requests: 1,
+    };
+    let (dummy_fw_tx, _) = capacities.firewall_channel();
+    let (request_channel, rq_rx) = capacities.requests_channel(ChannelSinkConfig::Block);
+    let remote_builder = NetworkBuilder::new(dummy_fw_tx, request_channel, None, FirewallRules::allow_all());
+    let mut remote = build(remote_builder.with_channel_capacities(capacities)).await;
+    tokio::spawn(rq_rx.for_each(|rq| {
+        let _ = rq.responder.respond(());
+        future::ready(())
+    }));
+    let remote_id = remote.peer_id();
+    let remote_addr = remote
+        .start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let mut peer = build(builder().with_channel_capacities(capacities)).await;
+    peer.add_address(remote_id, remote_addr).await;
+    let requests = (0..10).map(|_| {
+        let mut peer = peer.clone();
+        async move { peer.send_request(remote_id, ()).await }
+    });
+    for res in future::join_all(requests).await {
+        assert!(res.is_ok());
+    }
+}
+
+#[tokio::test]
+async fn network_from_config() {
+    let mut remote = build(builder().with_mdns_support(false)).await;
+    let remote_id = remote.peer_id();
+    let remote_addr = remote
+        .start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let config: NetworkConfig = serde_json::from_value(serde_json::json!({
+        "request_timeout": { "secs": 5, "nanos": 0 },
+        "firewall_default": "RejectAll",
+        "support_mdns": false,
+        "bootstrap_peers": [[remote_id, remote_addr]],
+    }))
+    .unwrap();
+    let (dummy_fw_tx, _) = mpsc::channel(10);
+    let (dummy_rq_channel, _) = EventChannel::new(10, ChannelSinkConfig::DropLatest);
+    let mut peer = build(NetworkBuilder::from_config(
+        &config,
+        dummy_fw_tx,
+        dummy_rq_channel,
+        None,
+    ))
+    .await;
+
+    // The bootstrap peer is dialed without further interaction.
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(peer.is_connected(remote_id).await);
+
+    // The configured default rule rejects inbound requests.
+    let err = remote.send_request(peer.peer_id(), ()).await.unwrap_err();
+    assert!(matches!(
+        err,
+        OutboundFailure::UnsupportedProtocols { .. } | OutboundFailure::Remote(RemoteError::NotPermitted)
+    ));
+}
+
+#[tokio::test]
+async fn custom_behaviour() {
+    let executor = |fut| {
+        tokio::spawn(fut);
+    };
+    // Use the ping protocol as custom behaviour instead of the integrated one.
+    let new_builder = || {
+        let (ping_tx, ping_rx) = EventChannel::new(10, ChannelSinkConfig::BufferLatest);
+        let ping = Ping::new(PingConfig::new().with_interval(Duration::from_millis(100)));
+        let builder = builder()
+            .with_mdns_support(false)
+            .with_ping_support(false)
+            .with_custom_behaviour(ping, ping_tx);
+        (builder, ping_rx)
+    };
+
+    let (remote_builder, _) = new_builder();
+    let mut remote = remote_builder
+        .build_with_transport(TokioTcpConfig::new(), executor)
+        .await
+        .unwrap();
+    let remote_id = remote.peer_id();
+    let remote_addr = remote
+        .start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let (peer_builder, mut ping_rx) = new_builder();
+    let mut peer = peer_builder
+        .build_with_transport(TokioTcpConfig::new(), executor)
+        .await
+        .unwrap();
+    peer.add_address(remote_id, remote_addr).await;
+    peer.connect_peer(remote_id).await.unwrap();
+
+    let event: PingEvent = ping_rx.next().await.unwrap();
+    assert_eq!(event.peer, remote_id);
+    assert!(event.result.is_ok());
+}
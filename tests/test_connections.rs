@@ -0,0 +1,503 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+mod common;
+
+use common::{build, builder};
+
+use std::{num::NonZeroU32, time::Duration};
+
+#[cfg(feature = "metrics")]
+use p2p::NetworkMetrics;
+#[cfg(feature = "tcp-transport")]
+use p2p::OutboundBinding;
+use p2p::{
+    firewall::FirewallRules, AddressFailure, ChannelSinkConfig, CloseReason, ConnectionErr, ConnectionLimits, DialErr,
+    EventChannel, ExcessConnectionPolicy, HandshakeFailure, Hello, KeepAliveProbes, Network, NetworkBuilder,
+    NetworkEvent, NetworkFailure, OutboundFailure, PeerId,
+};
+
+use futures::{channel::mpsc, future, StreamExt};
+use libp2p::multiaddr::Protocol;
+#[cfg(feature = "metrics")]
+use prometheus_client::{encoding::text::encode, registry::Registry};
+
+#[tokio::test]
+async fn identity_rotation() {
+    let (dummy_fw_tx, _) = mpsc::channel(10);
+    let (dummy_rq_channel, _) = EventChannel::new(10, ChannelSinkConfig::DropLatest);
+    let (event_channel, event_rx) = EventChannel::new(10, ChannelSinkConfig::Block);
+    let remote_builder = NetworkBuilder::<(), ()>::new(
+        dummy_fw_tx,
+        dummy_rq_channel,
+        Some(event_channel),
+        FirewallRules::allow_all(),
+    );
+    let mut remote = build(remote_builder.with_mdns_support(false)).await;
+    let remote_id = remote.peer_id();
+    let remote_addr = remote
+        .start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let mut old = build(builder().with_mdns_support(false)).await;
+    let old_id = old.peer_id();
+    old.add_address(remote_id, remote_addr).await;
+    old.connect_peer(remote_id).await.unwrap();
+
+    let mut new = build(builder().with_mdns_support(false)).await;
+    let new_id = new.peer_id();
+    new.start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+
+    // The connection is re-established under the new peer id, and the rotation is announced to the remote.
+    let reconnected = old.rotate_identity(&mut new, true).await;
+    assert_eq!(reconnected, vec![remote_id]);
+    assert!(new.is_connected(remote_id).await);
+
+    let mut rotations = event_rx.filter(|e| future::ready(matches!(e, NetworkEvent::PeerIdentityRotated { .. })));
+    match rotations.next().await.unwrap() {
+        NetworkEvent::PeerIdentityRotated { peer, new_peer, addrs } => {
+            assert_eq!(peer, old_id);
+            assert_eq!(new_peer, new_id);
+            assert!(!addrs.is_empty());
+        }
+        _ => unreachable!(),
+    }
+    assert!(!remote.get_addrs(new_id).await.is_empty());
+}
+
+#[tokio::test]
+async fn listen_on_used_port() {
+    let mut peer = build(builder().with_mdns_support(false)).await;
+    let addr = peer
+        .start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let err = peer.start_listening(addr.clone()).await.unwrap_err();
+    assert_eq!(err.address(), Some(&addr));
+    assert_eq!(
+        err.kind(),
+        Some(std::io::ErrorKind::AddrInUse),
+        "unexpected error: {}",
+        err
+    );
+}
+
+#[tokio::test]
+async fn dial_failure_per_address() {
+    let mut peer = build(builder().with_mdns_support(false)).await;
+    let remote = PeerId::random();
+
+    // Port that nobody is listening on.
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let refused_addr: libp2p::Multiaddr = format!("/ip4/127.0.0.1/tcp/{}", port).parse().unwrap();
+    let unsupported_addr: libp2p::Multiaddr = "/memory/1234".parse().unwrap();
+    peer.add_address(remote, refused_addr.clone()).await;
+    peer.add_address(remote, unsupported_addr.clone()).await;
+
+    let err = peer.connect_peer(remote).await.unwrap_err();
+    let attempts = err.attempts();
+    assert_eq!(attempts.len(), 2, "unexpected error: {}", err);
+    for (addr, failure) in attempts {
+        if addr == &refused_addr {
+            assert!(
+                matches!(failure, AddressFailure::Refused),
+                "unexpected failure: {}",
+                failure
+            );
+        } else {
+            assert_eq!(addr, &unsupported_addr);
+            assert!(
+                matches!(failure, AddressFailure::NotSupported),
+                "unexpected failure: {}",
+                failure
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn error_hook_reports_dial_failure() {
+    let failed_dials = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let failed_dials_clone = failed_dials.clone();
+    let builder = builder().with_mdns_support(false).with_error_hook(move |failure| {
+        if let NetworkFailure::Dial { peer, .. } = failure {
+            failed_dials_clone.lock().unwrap().push(peer);
+        }
+    });
+    let mut peer = build(builder).await;
+
+    let remote = PeerId::random();
+    peer.add_address(remote, "/memory/1234".parse().unwrap()).await;
+    assert!(peer.connect_peer(remote).await.is_err());
+    assert_eq!(*failed_dials.lock().unwrap(), vec![Some(remote)]);
+}
+
+#[tokio::test]
+async fn ban_peer() {
+    let mut peer = build(builder().with_mdns_support(false)).await;
+    let mut remote = build(builder().with_mdns_support(false)).await;
+    let remote_id = remote.peer_id();
+    let remote_addr = remote
+        .start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+
+    peer.add_address(remote_id, remote_addr.clone()).await;
+    peer.ban_peer(remote_id).await;
+    assert!(peer.is_banned(remote_id).await);
+    assert_eq!(peer.get_banned_peers().await, vec![remote_id]);
+    assert!(peer.export_address_info().await.get_addrs(&remote_id).is_empty());
+    let err = peer.connect_peer(remote_id).await.unwrap_err();
+    assert!(matches!(err, DialErr::Banned), "unexpected error: {}", err);
+
+    // Inbound connections of the banned peer are rejected.
+    let local_addr = peer
+        .start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+    remote.add_address(peer.peer_id(), local_addr).await;
+    assert!(remote.connect_peer(peer.peer_id()).await.is_err());
+
+    peer.unban_peer(remote_id).await;
+    assert!(!peer.is_banned(remote_id).await);
+    peer.add_address(remote_id, remote_addr).await;
+    assert!(peer.connect_peer(remote_id).await.is_ok());
+}
+
+#[tokio::test]
+async fn keep_alive_probes() {
+    let probes = KeepAliveProbes {
+        interval: Duration::from_millis(50),
+        timeout: Duration::from_secs(1),
+        max_missed: NonZeroU32::new(1).unwrap(),
+    };
+    let mut remote = build(builder().with_keep_alive_probes(probes)).await;
+    let remote_id = remote.peer_id();
+    let remote_addr = remote
+        .start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let mut peer = build(builder().with_keep_alive_probes(probes)).await;
+    peer.add_address(remote_id, remote_addr).await;
+    peer.connect_peer(remote_id).await.unwrap();
+
+    // Responsive peers are probed without closing the connection.
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(peer.is_connected(remote_id).await);
+    assert!(peer.probe(remote_id).await.is_ok());
+}
+
+#[tokio::test]
+async fn close_reason() {
+    let (dummy_fw_tx, _) = mpsc::channel(10);
+    let (request_channel, mut rq_rx) = EventChannel::new(10, ChannelSinkConfig::Block);
+    let remote_builder = NetworkBuilder::new(dummy_fw_tx, request_channel, None, FirewallRules::allow_all());
+    let mut remote: Network<(), ()> = build(remote_builder).await;
+    let remote_id = remote.peer_id();
+    let remote_addr = remote
+        .start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let (dummy_fw_tx, _) = mpsc::channel(10);
+    let (dummy_rq_channel, _) = EventChannel::new(10, ChannelSinkConfig::DropLatest);
+    let (event_channel, mut event_rx) = EventChannel::new(10, ChannelSinkConfig::Block);
+    let builder = NetworkBuilder::new(
+        dummy_fw_tx,
+        dummy_rq_channel,
+        Some(event_channel),
+        FirewallRules::allow_all(),
+    );
+    let mut peer: Network<(), ()> = build(builder).await;
+    peer.add_address(remote_id, remote_addr).await;
+
+    let mut sender = peer.clone();
+    let request = tokio::spawn(async move { sender.send_request(remote_id, ()).await });
+    // Keep the request pending while the connection is closed.
+    let _pending = rq_rx.next().await.unwrap();
+    peer.disconnect_peer(remote_id).await;
+    assert_eq!(
+        request.await.unwrap(),
+        Err(OutboundFailure::ConnectionClosed(CloseReason::Disconnect))
+    );
+    loop {
+        if let NetworkEvent::ConnectionClosed { peer, reason, .. } = event_rx.next().await.unwrap() {
+            assert_eq!(peer, remote_id);
+            assert_eq!(reason, CloseReason::Disconnect);
+            assert!(reason.is_local());
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn handshake_timeout() {
+    let (dummy_fw_tx, _) = mpsc::channel(10);
+    let (dummy_rq_channel, _) = EventChannel::new(10, ChannelSinkConfig::DropLatest);
+    let (event_channel, event_rx) = EventChannel::new(10, ChannelSinkConfig::Block);
+    let builder = NetworkBuilder::<(), ()>::new(
+        dummy_fw_tx,
+        dummy_rq_channel,
+        Some(event_channel),
+        FirewallRules::allow_all(),
+    )
+    .with_handshake_timeout(Duration::from_millis(200));
+    let mut peer = build(builder).await;
+    let addr = peer
+        .start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+    let port = match addr.iter().nth(1) {
+        Some(Protocol::Tcp(port)) => port,
+        _ => panic!("Unexpected address {}", addr),
+    };
+
+    // Open a TCP connection, but never start the handshake.
+    let _stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+    let mut errors = event_rx.filter(|ev| future::ready(matches!(ev, NetworkEvent::IncomingConnectionError { .. })));
+    let event = tokio::time::timeout(Duration::from_secs(5), errors.next())
+        .await
+        .expect("Stalled handshake was not closed.");
+    match event.unwrap() {
+        NetworkEvent::IncomingConnectionError { error, .. } => {
+            assert!(matches!(error, ConnectionErr::HandshakeTimeout))
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[tokio::test]
+async fn handshake_failure_reason() {
+    let mut remote = build(builder()).await;
+    let remote_id = remote.peer_id();
+    let remote_addr = remote
+        .start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let mut peer = build(builder().with_expected_peers(|_| false)).await;
+    peer.add_address(remote_id, remote_addr).await;
+    let err = peer.connect_peer(remote_id).await.unwrap_err();
+    let attempts = err.attempts();
+    assert_eq!(attempts.len(), 1);
+    match attempts[0].1 {
+        AddressFailure::Handshake(failure) => assert_eq!(*failure, HandshakeFailure::UnexpectedPeer(remote_id)),
+        other => panic!("Unexpected failure {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn runtime_connection_limits() {
+    let mut remote = build(builder().with_mdns_support(false)).await;
+    let remote_id = remote.peer_id();
+    let remote_addr = remote
+        .start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let mut peers = Vec::new();
+    for _ in 0..3 {
+        let mut peer = build(builder().with_mdns_support(false)).await;
+        peer.add_address(remote_id, remote_addr.clone()).await;
+        peer.connect_peer(remote_id).await.unwrap();
+        peers.push(peer);
+    }
+    assert_eq!(remote.established_connections().await.len(), 3);
+
+    // Lowering the limit closes the most recent connections.
+    let limits = ConnectionLimits::default().with_max_established(Some(1));
+    remote
+        .set_connection_limits(limits, ExcessConnectionPolicy::CloseNewest)
+        .await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let connections = remote.established_connections().await;
+    assert_eq!(connections.len(), 1);
+    assert_eq!(connections[0].0, peers[0].peer_id());
+
+    // New connections that exceed the limit are closed.
+    let _ = peers[1].connect_peer(remote_id).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(!remote.is_connected(peers[1].peer_id()).await);
+
+    // Raising the limit accepts new connections again.
+    let limits = ConnectionLimits::default().with_max_established(Some(2));
+    remote.set_connection_limits(limits, ExcessConnectionPolicy::Keep).await;
+    peers[1].connect_peer(remote_id).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(remote.is_connected(peers[1].peer_id()).await);
+}
+
+#[tokio::test]
+async fn network_info() {
+    let mut remote = build(builder().with_mdns_support(false)).await;
+    let remote_id = remote.peer_id();
+    let remote_addr = remote
+        .start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let mut peer = build(builder().with_mdns_support(false)).await;
+    peer.add_address(remote_id, remote_addr.clone()).await;
+    peer.connect_peer(remote_id).await.unwrap();
+
+    let info = peer.network_info().await;
+    assert_eq!(info.num_peers, 1);
+    assert_eq!(info.num_established_outgoing, 1);
+    assert_eq!(info.num_established_incoming, 0);
+    assert!(!info.supported_protocols.is_empty());
+
+    let info = remote.network_info().await;
+    assert_eq!(info.num_established_incoming, 1);
+    assert_eq!(info.listen_addrs, vec![remote_addr.clone()]);
+
+    let self_addr = format!("{}/p2p/{}", remote_addr, remote_id).parse().unwrap();
+    assert_eq!(remote.self_addrs().await, vec![self_addr]);
+}
+
+#[tokio::test]
+async fn hello_exchange() {
+    let remote_hello = Hello::new(vec!["blocks".into()]).with_data(vec![1]);
+    let mut remote = build(builder().with_mdns_support(false).with_hello(remote_hello.clone())).await;
+    let remote_id = remote.peer_id();
+    let remote_addr = remote
+        .start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let (dummy_fw_tx, _) = mpsc::channel(10);
+    let (dummy_rq_channel, _) = EventChannel::new(10, ChannelSinkConfig::DropLatest);
+    let (event_channel, event_rx) = EventChannel::new(10, ChannelSinkConfig::Block);
+    let peer_builder = NetworkBuilder::<(), ()>::new(
+        dummy_fw_tx,
+        dummy_rq_channel,
+        Some(event_channel),
+        FirewallRules::allow_all(),
+    );
+    let peer_hello = Hello::new(vec!["transfer".into()]);
+    let mut peer = build(peer_builder.with_mdns_support(false).with_hello(peer_hello.clone())).await;
+    let peer_id = peer.peer_id();
+    peer.add_address(remote_id, remote_addr).await;
+    peer.connect_peer(remote_id).await.unwrap();
+
+    let mut hellos = event_rx.filter(|e| future::ready(matches!(e, NetworkEvent::PeerHello { .. })));
+    match hellos.next().await.unwrap() {
+        NetworkEvent::PeerHello { peer, hello } => {
+            assert_eq!(peer, remote_id);
+            assert_eq!(hello, remote_hello);
+        }
+        _ => unreachable!(),
+    }
+    assert_eq!(peer.peer_hello(remote_id).await, Some(remote_hello));
+    // The remote received the hello in the other direction.
+    while remote.peer_hello(peer_id).await.is_none() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(remote.peer_hello(peer_id).await, Some(peer_hello));
+}
+
+#[tokio::test]
+async fn identify_observed_addr() {
+    let (dummy_fw_tx, _) = mpsc::channel(10);
+    let (dummy_rq_channel, _) = EventChannel::new(10, ChannelSinkConfig::DropLatest);
+    let (event_channel, event_rx) = EventChannel::new(10, ChannelSinkConfig::Block);
+    let listener_builder = NetworkBuilder::<(), ()>::new(
+        dummy_fw_tx,
+        dummy_rq_channel,
+        Some(event_channel),
+        FirewallRules::allow_all(),
+    );
+    let mut listener = build(listener_builder.with_mdns_support(false).with_identify_support(true)).await;
+    let listener_addr = listener
+        .start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let mut dialer = build(builder().with_mdns_support(false).with_identify_support(true)).await;
+    let dialer_id = dialer.peer_id();
+    dialer.add_address(listener.peer_id(), listener_addr).await;
+    dialer.connect_peer(listener.peer_id()).await.unwrap();
+
+    // The dialer reports the address under which it observed the listener.
+    let mut observed = event_rx.filter(|e| future::ready(matches!(e, NetworkEvent::ObservedAddr { .. })));
+    match observed.next().await.unwrap() {
+        NetworkEvent::ObservedAddr { peer, .. } => assert_eq!(peer, dialer_id),
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(feature = "metrics")]
+#[tokio::test]
+async fn ping_identify_metrics() {
+    // Value of the metric in the text encoding of the registry, 0 if it was not recorded yet.
+    fn metric(registry: &Registry, name: &str) -> u64 {
+        let mut encoded = Vec::new();
+        encode(&mut encoded, registry).unwrap();
+        String::from_utf8(encoded)
+            .unwrap()
+            .lines()
+            .find_map(|l| l.strip_prefix(name)?.strip_prefix(' ')?.parse().ok())
+            .unwrap_or(0)
+    }
+
+    let mut remote = build(builder().with_ping_support(true).with_identify_support(true)).await;
+    let remote_id = remote.peer_id();
+    let remote_addr = remote
+        .start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let mut registry = Registry::default();
+    let metrics = NetworkMetrics::new(&mut registry);
+    let mut peer = build(
+        builder()
+            .with_ping_support(true)
+            .with_identify_support(true)
+            .with_metrics(metrics),
+    )
+    .await;
+    peer.add_address(remote_id, remote_addr).await;
+    peer.connect_peer(remote_id).await.unwrap();
+
+    // Both protocols run right after the connection was established.
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(metric(&registry, "libp2p_ping_rtt_seconds_count"), 1);
+    assert_eq!(metric(&registry, "libp2p_identify_received_total"), 1);
+}
+
+#[cfg(feature = "tcp-transport")]
+#[tokio::test]
+async fn outbound_binding() {
+    let mut remote = builder().with_mdns_support(false).build().await.unwrap();
+    let remote_id = remote.peer_id();
+    let remote_addr = remote
+        .start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let binding = OutboundBinding {
+        local_addrs: vec!["127.0.0.1".parse().unwrap()],
+        strict: true,
+    };
+    let mut peer = builder()
+        .with_mdns_support(false)
+        .with_outbound_binding(binding)
+        .build()
+        .await
+        .unwrap();
+    peer.add_address(remote_id, remote_addr).await;
+    peer.connect_peer(remote_id).await.unwrap();
+
+    // No local address for IPv6 is configured.
+    let other = PeerId::random();
+    peer.add_address(other, "/ip6/::1/tcp/4001".parse().unwrap()).await;
+    assert!(matches!(peer.connect_peer(other).await, Err(DialErr::Transport(_))));
+}
@@ -1,5 +1,9 @@
 // Copyright 2020-2021 IOTA Stiftung
 // SPDX-License-Identifier: Apache-2.0
+
+// All dialing scenarios go through a relay, the fixture is unused if the protocol is not compiled in.
+#![cfg_attr(not(feature = "relay"), allow(dead_code, unused_imports))]
+
 use core::fmt;
 use futures::{
     channel::mpsc::{self, Receiver},
@@ -320,6 +324,8 @@ impl TestConfig {
                 NetworkEvent::NewListenAddr(..)
                     | NetworkEvent::ConnectionClosed { .. }
                     | NetworkEvent::ListenerClosed { .. }
+                    | NetworkEvent::RelayReservationLost { .. }
+                    | NetworkEvent::RelayReservationRenewed { .. }
             ))
         });
         let event = filtered.next().await.unwrap();
@@ -332,6 +338,7 @@ impl TestConfig {
     }
 }
 
+#[cfg(feature = "relay")]
 #[tokio::test]
 async fn test_dialing() {
     let run_test = async {
@@ -12,10 +12,10 @@ use libp2p::tcp::TokioTcpConfig;
 use p2p::{
     firewall::{
         permissions::{FirewallPermission, PermissionValue, VariantPermission},
-        FirewallRequest, FirewallRules, Rule,
+        FirewallRequest, FirewallRules, Rule, RuleKind,
     },
-    ChannelSinkConfig, EventChannel, InboundFailure, Network, NetworkBuilder, NetworkEvent, OutboundFailure, PeerId,
-    ReceiveRequest,
+    ChannelSinkConfig, EventChannel, InboundFailure, InboundWatermarks, Network, NetworkBuilder, NetworkEvent,
+    OutboundFailure, PeerId, ReceiveRequest, RemoteError, RequestPriority,
 };
 use rand::random;
 use serde::{Deserialize, Serialize};
@@ -196,13 +196,13 @@ impl<'a> RulesTestConfig<'a> {
             match individual_permissions {
                 TestPermission::RejectAll => match res_future.await {
                     Ok(_) => panic!("Unexpected response; config {}", self),
-                    Err(OutboundFailure::UnsupportedProtocols) | Err(OutboundFailure::ConnectionClosed) => {}
+                    Err(OutboundFailure::UnsupportedProtocols { .. }) | Err(OutboundFailure::ConnectionClosed(_)) => {}
                     Err(e) => panic!("Unexpected Failure {:?}; config {}", e, self),
                 },
                 TestPermission::Restricted(_) => {
                     match res_future.await {
                         Ok(_) => panic!("Unexpected response; config {}", self),
-                        Err(OutboundFailure::Timeout) | Err(OutboundFailure::ConnectionClosed) => {}
+                        Err(OutboundFailure::Timeout) | Err(OutboundFailure::ConnectionClosed(_)) => {}
                         Err(e) => panic!("Unexpected Failure {:?}; config {}", e, self),
                     }
                     self.expect_b_inbound_reject(peer_a_id).await;
@@ -220,12 +220,12 @@ impl<'a> RulesTestConfig<'a> {
                 let ReceiveRequest {
                     peer,
                     request,
-                    response_tx,
+                    responder,
                     ..
                 } = self.b_request_rx.select_next_some().await;
                 assert_eq!(peer, peer_a_id);
                 assert_eq!(request, self.req);
-                response_tx.send(Response::Pong).unwrap();
+                responder.respond(Response::Pong).unwrap();
             };
             join(source_fut, dst_fut).await;
         }
@@ -420,18 +420,18 @@ impl<'a> AskTestConfig<'a> {
                 .await;
 
                 match res {
-                    OutboundFailure::Timeout | OutboundFailure::ConnectionClosed => {
+                    OutboundFailure::Timeout | OutboundFailure::ConnectionClosed(_) => {
                         self.expect_b_inbound_reject(peer_a_id).await;
                     }
-                    OutboundFailure::UnsupportedProtocols if matches!(self.rule, FwRuleRes::RejectAll) => {}
+                    OutboundFailure::UnsupportedProtocols { .. } if matches!(self.rule, FwRuleRes::RejectAll) => {}
                     other => panic!("Unexpected outbound failure {:?}; config: {}", other, self),
                 }
                 return;
             }
 
-            let ReceiveRequest { peer, response_tx, .. } = self.b_request_rx.select_next_some().await;
+            let ReceiveRequest { peer, responder, .. } = self.b_request_rx.select_next_some().await;
             assert_eq!(peer, peer_a_id);
-            response_tx.send(Response::Pong).unwrap();
+            responder.respond(Response::Pong).unwrap();
             rx_res
                 .await
                 .unwrap()
@@ -538,3 +538,409 @@ async fn firewall_ask() {
         _ = sleep(Duration::from_secs(iterations)).fuse() => panic!("Test timed out"),
     }
 }
+
+#[tokio::test]
+async fn awaiting_firewall() {
+    let (_, _, _, mut peer_a) = init_peer().await;
+    let (mut b_firewall_rx, mut b_rq_rx, _, mut peer_b) = init_peer().await;
+
+    let peer_a_id = peer_a.peer_id();
+    let peer_b_id = peer_b.peer_id();
+    let peer_b_addr = peer_b
+        .start_listening("/ip4/0.0.0.0/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+    peer_a.add_address(peer_b_id, peer_b_addr).await;
+
+    let request = peer_a.send_request(peer_b_id, Request::Ping);
+    let handle_firewall = async {
+        let rule_tx = match b_firewall_rx.next().await.unwrap() {
+            FirewallRequest::PeerSpecificRule { peer, rule_tx } if peer == peer_a_id => rule_tx,
+            _ => panic!("Expected PeerSpecificRule"),
+        };
+        let awaiting = peer_b.awaiting_firewall().await;
+        assert_eq!(awaiting.peer_rule.get(&peer_a_id).map(Vec::len), Some(1));
+        assert!(awaiting.approval.is_empty());
+
+        rule_tx.send(Rule::Ask).unwrap();
+        let approval_tx = match b_firewall_rx.next().await.unwrap() {
+            FirewallRequest::RequestApproval { approval_tx, .. } => approval_tx,
+            _ => panic!("Expected RequestApproval"),
+        };
+        let awaiting = peer_b.awaiting_firewall().await;
+        assert!(awaiting.peer_rule.is_empty());
+        assert_eq!(awaiting.approval.get(&peer_a_id).map(Vec::len), Some(1));
+
+        approval_tx.send(true).unwrap();
+        let rq = b_rq_rx.next().await.unwrap();
+        rq.responder.respond(Response::Pong).unwrap();
+        assert_eq!(peer_b.awaiting_firewall().await, Default::default());
+    };
+    let (res, ()) = join(request, handle_firewall).await;
+    assert_eq!(res.unwrap(), Response::Pong);
+}
+
+#[tokio::test]
+async fn resolve_pending_approvals() {
+    let (_, _, _, mut peer_a) = init_peer().await;
+    let (mut b_firewall_rx, mut b_rq_rx, _, mut peer_b) = init_peer().await;
+
+    let peer_a_id = peer_a.peer_id();
+    let peer_b_id = peer_b.peer_id();
+    let peer_b_addr = peer_b
+        .start_listening("/ip4/0.0.0.0/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+    peer_a.add_address(peer_b_id, peer_b_addr).await;
+    peer_b.set_peer_rule(peer_a_id, Rule::Ask).await;
+
+    let mut peer_a_clone = peer_a.clone();
+    let requests = join(
+        peer_a.send_request(peer_b_id, Request::Ping),
+        peer_a_clone.send_request(peer_b_id, Request::Other),
+    );
+    let handle_firewall = async {
+        // Keep the approval channels open without answering them.
+        let mut approvals = Vec::new();
+        while approvals.len() < 2 {
+            match b_firewall_rx.next().await.unwrap() {
+                FirewallRequest::RequestApproval { approval_tx, .. } => approvals.push(approval_tx),
+                _ => panic!("Expected RequestApproval"),
+            }
+        }
+        let resolved = peer_b
+            .resolve_pending_approvals(peer_a_id, true, Some(Rule::AllowAll))
+            .await;
+        assert_eq!(resolved.len(), 2);
+        assert_eq!(peer_b.awaiting_firewall().await, Default::default());
+        for _ in 0..2 {
+            let rq = b_rq_rx.next().await.unwrap();
+            rq.responder.respond(Response::Pong).unwrap();
+        }
+    };
+    let ((res_ping, res_other), ()) = join(requests, handle_firewall).await;
+    assert!(res_ping.is_ok());
+    assert!(res_other.is_ok());
+
+    // The installed rule approves new requests without asking.
+    let request = peer_a.send_request(peer_b_id, Request::Ping);
+    let respond = async {
+        let rq = b_rq_rx.next().await.unwrap();
+        rq.responder.respond(Response::Pong).unwrap();
+    };
+    let (res, ()) = join(request, respond).await;
+    assert!(res.is_ok());
+    assert!(b_firewall_rx.try_next().is_err());
+}
+
+#[tokio::test]
+async fn priority_rule() {
+    let (_, _, _, mut peer_a) = init_peer().await;
+    let (_, mut b_rq_rx, _, mut peer_b) = init_peer().await;
+
+    let peer_b_id = peer_b.peer_id();
+    let peer_b_addr = peer_b
+        .start_listening("/ip4/0.0.0.0/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+    peer_a.add_address(peer_b_id, peer_b_addr).await;
+    peer_b.set_firewall_default(Some(Rule::RejectAll)).await;
+    peer_b.set_priority_rule(RequestPriority::High, Rule::AllowAll).await;
+
+    // Only requests of the high priority class are permitted.
+    let err = peer_a
+        .send_request_with_priority(peer_b_id, Request::Ping, RequestPriority::Low)
+        .await
+        .unwrap_err();
+    assert_eq!(err, OutboundFailure::Remote(RemoteError::NotPermitted));
+
+    let request = peer_a.send_request_with_priority(peer_b_id, Request::Ping, RequestPriority::High);
+    let respond = async {
+        let rq = b_rq_rx.next().await.unwrap();
+        assert_eq!(rq.priority, RequestPriority::High);
+        rq.responder.respond(Response::Pong).unwrap();
+    };
+    let (res, ()) = join(request, respond).await;
+    assert_eq!(res.unwrap(), Response::Pong);
+
+    peer_b.remove_priority_rule(RequestPriority::High).await;
+    let err = peer_a
+        .send_request_with_priority(peer_b_id, Request::Ping, RequestPriority::High)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        OutboundFailure::Remote(_) | OutboundFailure::UnsupportedProtocols { .. }
+    ));
+}
+
+#[tokio::test]
+async fn tag_rule() {
+    let (_, _, _, mut peer_a) = init_peer().await;
+    let (_, mut b_rq_rx, _, mut peer_b) = init_peer().await;
+
+    let peer_a_id = peer_a.peer_id();
+    let peer_b_id = peer_b.peer_id();
+    let peer_b_addr = peer_b
+        .start_listening("/ip4/0.0.0.0/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+    peer_a.add_address(peer_b_id, peer_b_addr).await;
+    peer_b.set_firewall_default(Some(Rule::RejectAll)).await;
+    peer_b.set_tag_rule("validator".into(), Rule::AllowAll).await;
+    peer_b.tag_peer(peer_a_id, "validator".into()).await;
+    peer_b.tag_peer(peer_a_id, "backup".into()).await;
+    assert_eq!(peer_b.peer_tags(peer_a_id).await, vec!["backup", "validator"]);
+    assert_eq!(peer_b.peers_with_tag("validator".into()).await, vec![peer_a_id]);
+
+    // Requests from peers with the tag are permitted.
+    let request = peer_a.send_request(peer_b_id, Request::Ping);
+    let respond = async {
+        let rq = b_rq_rx.next().await.unwrap();
+        rq.responder.respond(Response::Pong).unwrap();
+    };
+    let (res, ()) = join(request, respond).await;
+    assert_eq!(res.unwrap(), Response::Pong);
+
+    // Peer specific rules take precedence over tag rules.
+    peer_b.set_peer_rule(peer_a_id, Rule::RejectAll).await;
+    let err = peer_a.send_request(peer_b_id, Request::Ping).await.unwrap_err();
+    assert!(matches!(
+        err,
+        OutboundFailure::Remote(_) | OutboundFailure::UnsupportedProtocols { .. }
+    ));
+    peer_b.remove_peer_rule(peer_a_id).await;
+
+    peer_b.untag_peer(peer_a_id, "validator".into()).await;
+    assert!(peer_b.peers_with_tag("validator".into()).await.is_empty());
+    let err = peer_a.send_request(peer_b_id, Request::Ping).await.unwrap_err();
+    assert!(matches!(
+        err,
+        OutboundFailure::Remote(_) | OutboundFailure::UnsupportedProtocols { .. }
+    ));
+}
+
+#[tokio::test]
+async fn firewall_config_reload() {
+    let path = std::env::temp_dir().join(format!("p2p-firewall-{}.json", random::<u64>()));
+    std::fs::write(&path, r#"{ "default": "RejectAll" }"#).unwrap();
+
+    let (_, _, _, mut peer_a) = init_peer().await;
+    let (firewall_tx, _) = mpsc::channel(10);
+    let (request_channel, mut b_rq_rx) = EventChannel::new(10, ChannelSinkConfig::Block);
+    let (event_channel, mut b_event_rx) = EventChannel::new(10, ChannelSinkConfig::Block);
+    let builder = NetworkBuilder::<Request, Response>::new(
+        firewall_tx,
+        request_channel,
+        Some(event_channel),
+        FirewallRules::default(),
+    )
+    .with_firewall_config_file(&path, Duration::from_millis(50));
+    #[cfg(not(feature = "tcp-transport"))]
+    let mut peer_b = builder
+        .build_with_transport(TokioTcpConfig::new(), |fut| {
+            tokio::spawn(fut);
+        })
+        .await
+        .unwrap();
+    #[cfg(feature = "tcp-transport")]
+    let mut peer_b = builder.build().await.unwrap();
+
+    let peer_a_id = peer_a.peer_id();
+    let peer_b_id = peer_b.peer_id();
+    let peer_b_addr = peer_b
+        .start_listening("/ip4/0.0.0.0/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+    peer_a.add_address(peer_b_id, peer_b_addr).await;
+    assert!(peer_a.send_request(peer_b_id, Request::Ping).await.is_err());
+
+    // Only the changed rule is applied and reported.
+    let config = format!(
+        r#"{{ "default": "RejectAll", "peer_rules": {{ "{}": "AllowAll" }} }}"#,
+        peer_a_id
+    );
+    std::fs::write(&path, config).unwrap();
+    loop {
+        match b_event_rx.next().await.unwrap() {
+            NetworkEvent::FirewallRuleChanged { peer, rule } => {
+                assert_eq!(peer, Some(peer_a_id));
+                assert_eq!(rule, Some(RuleKind::AllowAll));
+                break;
+            }
+            _ => continue,
+        }
+    }
+    let request = peer_a.send_request(peer_b_id, Request::Ping);
+    let respond = async {
+        let rq = b_rq_rx.next().await.unwrap();
+        rq.responder.respond(Response::Pong).unwrap();
+    };
+    let (res, ()) = join(request, respond).await;
+    assert_eq!(res.unwrap(), Response::Pong);
+
+    // Invalid files are not applied.
+    std::fs::write(&path, "{ invalid").unwrap();
+    loop {
+        if let NetworkEvent::FirewallReloadFailed { .. } = b_event_rx.next().await.unwrap() {
+            break;
+        }
+    }
+    assert_eq!(
+        peer_b.get_firewall_config().await.get_rule(&peer_a_id).map(Rule::kind),
+        Some(RuleKind::AllowAll)
+    );
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn inbound_watermarks() {
+    assert!(InboundWatermarks::new(1, 1).is_none());
+    let (_, _, _, mut peer_a) = init_peer().await;
+    let (firewall_tx, mut b_firewall_rx) = mpsc::channel(10);
+    let (request_channel, mut b_rq_rx) = EventChannel::new(10, ChannelSinkConfig::Block);
+    let (event_channel, mut b_event_rx) = EventChannel::new(10, ChannelSinkConfig::Block);
+    let builder = NetworkBuilder::<Request, Response>::new(
+        firewall_tx,
+        request_channel,
+        Some(event_channel),
+        FirewallRules::default(),
+    )
+    .with_inbound_watermarks(InboundWatermarks::new(2, 1).unwrap());
+    #[cfg(not(feature = "tcp-transport"))]
+    let mut peer_b = builder
+        .build_with_transport(TokioTcpConfig::new(), |fut| {
+            tokio::spawn(fut);
+        })
+        .await
+        .unwrap();
+    #[cfg(feature = "tcp-transport")]
+    let mut peer_b = builder.build().await.unwrap();
+
+    let peer_a_id = peer_a.peer_id();
+    let peer_b_id = peer_b.peer_id();
+    let peer_b_addr = peer_b
+        .start_listening("/ip4/0.0.0.0/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+    peer_a.add_address(peer_b_id, peer_b_addr).await;
+    peer_b.set_peer_rule(peer_a_id, Rule::Ask).await;
+
+    let mut peer_a_clone = peer_a.clone();
+    let requests = join(
+        peer_a.send_request(peer_b_id, Request::Ping),
+        peer_a_clone.send_request(peer_b_id, Request::Other),
+    );
+    let handle_firewall = async {
+        let mut approvals = Vec::new();
+        while approvals.len() < 2 {
+            match b_firewall_rx.next().await.unwrap() {
+                FirewallRequest::RequestApproval { approval_tx, .. } => approvals.push(approval_tx),
+                _ => panic!("Expected RequestApproval"),
+            }
+        }
+        loop {
+            if let NetworkEvent::InboundQueueHigh { depth } = b_event_rx.next().await.unwrap() {
+                assert_eq!(depth, 2);
+                break;
+            }
+        }
+        assert_eq!(peer_b.inbound_queue_depth().await, 2);
+
+        // Approved requests are no longer counted once they are forwarded to the application.
+        approvals.pop().unwrap().send(true).unwrap();
+        let rq = b_rq_rx.next().await.unwrap();
+        loop {
+            if let NetworkEvent::InboundQueueLow { depth } = b_event_rx.next().await.unwrap() {
+                assert_eq!(depth, 1);
+                break;
+            }
+        }
+        assert_eq!(peer_b.inbound_queue_depth().await, 1);
+        rq.responder.respond(Response::Pong).unwrap();
+        approvals.pop().unwrap().send(true).unwrap();
+        let rq = b_rq_rx.next().await.unwrap();
+        rq.responder.respond(Response::Pong).unwrap();
+        sleep(Duration::from_millis(200)).await;
+        assert_eq!(peer_b.inbound_queue_depth().await, 0);
+    };
+    let ((res_ping, res_other), ()) = join(requests, handle_firewall).await;
+    assert!(res_ping.is_ok());
+    assert!(res_other.is_ok());
+}
+
+#[tokio::test]
+async fn abort_pending() {
+    let (_, _, _, mut peer_a) = init_peer().await;
+    let (mut b_firewall_rx, _, mut b_event_rx, mut peer_b) = init_peer().await;
+
+    let peer_a_id = peer_a.peer_id();
+    let peer_b_id = peer_b.peer_id();
+    let peer_b_addr = peer_b
+        .start_listening("/ip4/0.0.0.0/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+    peer_a.add_address(peer_b_id, peer_b_addr).await;
+    peer_b.set_peer_rule(peer_a_id, Rule::Ask).await;
+
+    let request = peer_a.send_request(peer_b_id, Request::Ping);
+    let abort = async {
+        // Keep the approval channel open without answering it.
+        let _approval_tx = match b_firewall_rx.next().await.unwrap() {
+            FirewallRequest::RequestApproval { approval_tx, .. } => approval_tx,
+            _ => panic!("Expected RequestApproval"),
+        };
+        let aborted = peer_b.abort_pending(peer_a_id).await;
+        assert_eq!(aborted.len(), 1);
+        assert_eq!(peer_b.awaiting_firewall().await, Default::default());
+        loop {
+            if let NetworkEvent::InboundFailure {
+                request_id, failure, ..
+            } = b_event_rx.next().await.unwrap()
+            {
+                assert_eq!(request_id, aborted[0]);
+                assert_eq!(failure, InboundFailure::Aborted);
+                break;
+            }
+        }
+        assert!(peer_b.abort_pending(peer_a_id).await.is_empty());
+    };
+    let (res, ()) = join(request, abort).await;
+    assert!(res.is_err());
+}
+
+#[tokio::test]
+async fn connection_closed_while_awaiting_approval() {
+    let (_, _, _, mut peer_a) = init_peer().await;
+    let (mut b_firewall_rx, _, mut b_event_rx, mut peer_b) = init_peer().await;
+
+    let peer_a_id = peer_a.peer_id();
+    let peer_b_id = peer_b.peer_id();
+    let peer_b_addr = peer_b
+        .start_listening("/ip4/0.0.0.0/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+    peer_a.add_address(peer_b_id, peer_b_addr).await;
+    peer_b.set_peer_rule(peer_a_id, Rule::Ask).await;
+
+    let request = peer_a.send_request(peer_b_id, Request::Ping);
+    let disconnect = async {
+        let _approval_tx = match b_firewall_rx.next().await.unwrap() {
+            FirewallRequest::RequestApproval { approval_tx, .. } => approval_tx,
+            _ => panic!("Expected RequestApproval"),
+        };
+        assert_eq!(peer_b.inbound_queue_depth().await, 1);
+        peer_b.disconnect_peer(peer_a_id).await;
+        loop {
+            if let NetworkEvent::InboundFailure { failure, .. } = b_event_rx.next().await.unwrap() {
+                assert!(matches!(failure, InboundFailure::ConnectionClosed(_)));
+                break;
+            }
+        }
+        // The request is removed from the cache, and its size from the buffered bytes.
+        assert_eq!(peer_b.inbound_queue_depth().await, 0);
+        assert_eq!(peer_b.dump_state().await.requests.buffered_bytes, 0);
+    };
+    let (res, ()) = join(request, disconnect).await;
+    assert!(res.is_err());
+}
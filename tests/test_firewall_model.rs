@@ -0,0 +1,75 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use p2p::firewall::{
+    permissions::{PermissionValue, VariantPermission},
+    testing::{firewall_rules, peer_id, rule, Approval, FirewallModel},
+    RuleKind,
+};
+use proptest::prelude::*;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Request {
+    Ping,
+    Other,
+}
+
+impl VariantPermission for Request {
+    fn permission(&self) -> PermissionValue {
+        match self {
+            Request::Ping => PermissionValue::new(0).expect("0 < 32"),
+            Request::Other => PermissionValue::new(1).expect("1 < 32"),
+        }
+    }
+}
+
+fn request() -> impl Strategy<Value = Request> {
+    prop_oneof![Just(Request::Ping), Just(Request::Other)]
+}
+
+proptest! {
+    #[test]
+    fn effective_rule_decides(rules in firewall_rules::<Request>(4), peer in peer_id(), rq in request()) {
+        let kind = rules.get_effective_rule(&peer).map(|r| r.kind());
+        let mut model = FirewallModel::new(rules);
+        let approval = model.on_request(peer, 0, rq);
+        match kind {
+            None => prop_assert_eq!(approval, Approval::AwaitingRule),
+            Some(RuleKind::AllowAll) => prop_assert_eq!(approval, Approval::Approved),
+            Some(RuleKind::RejectAll) => prop_assert_eq!(approval, Approval::Rejected),
+            Some(RuleKind::Ask) => prop_assert_eq!(approval, Approval::AwaitingApproval),
+            Some(RuleKind::Restricted) => {
+                prop_assert!(matches!(approval, Approval::Approved | Approval::Rejected))
+            }
+        }
+    }
+
+    #[test]
+    fn peer_rule_resolves_awaiting_requests(
+        peer in peer_id(),
+        new_rule in rule::<Request>(),
+        rqs in proptest::collection::vec(request(), 1..8),
+    ) {
+        let mut model = FirewallModel::new(Default::default());
+        for (id, rq) in rqs.iter().enumerate() {
+            prop_assert_eq!(model.on_request(peer, id as u64, rq.clone()), Approval::AwaitingRule);
+        }
+        let is_ask = new_rule.kind() == RuleKind::Ask;
+        let resolved = model.set_peer_rule(peer, new_rule);
+        prop_assert_eq!(resolved.len(), rqs.len());
+        prop_assert!(!model.is_awaiting_rule(&peer));
+        for (id, approval) in resolved {
+            prop_assert_eq!(approval == Approval::AwaitingApproval, is_ask);
+            if is_ask {
+                prop_assert_eq!(model.on_approval_response(id, None), Some(Approval::Rejected));
+            }
+        }
+    }
+
+    #[test]
+    fn failed_rule_query_rejects(peer in peer_id(), rq in request()) {
+        let mut model = FirewallModel::new(Default::default());
+        prop_assert_eq!(model.on_request(peer, 0, rq), Approval::AwaitingRule);
+        prop_assert_eq!(model.on_rule_response(peer, None), vec![(0, Approval::Rejected)]);
+    }
+}
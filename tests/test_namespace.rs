@@ -0,0 +1,61 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+mod common;
+
+use common::builder;
+
+use p2p::{firewall::FirewallRules, ChannelSinkConfig, EventChannel, NamespaceBehaviour, NamespaceEvent};
+
+use futures::{channel::mpsc, StreamExt};
+use libp2p::tcp::TokioTcpConfig;
+
+#[tokio::test]
+async fn namespaced_network() {
+    let executor = |fut| {
+        tokio::spawn(fut);
+    };
+    let new_builder = || {
+        let (fw_tx, _) = mpsc::channel(10);
+        let (namespace, handle) = NamespaceBehaviour::<String, String>::new("data", fw_tx, FirewallRules::allow_all());
+        let (event_tx, event_rx) = EventChannel::new(10, ChannelSinkConfig::Block);
+        let builder = builder()
+            .with_mdns_support(false)
+            .with_custom_behaviour(namespace, event_tx);
+        (builder, handle, event_rx)
+    };
+
+    let (remote_builder, _, mut remote_events) = new_builder();
+    let mut remote = remote_builder
+        .build_with_transport(TokioTcpConfig::new(), executor)
+        .await
+        .unwrap();
+    let remote_id = remote.peer_id();
+    let remote_addr = remote
+        .start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+    tokio::spawn(async move {
+        while let Some(event) = remote_events.next().await {
+            if let NamespaceEvent::Request(rq) = event {
+                let _ = rq.responder.respond(rq.request.to_uppercase());
+            }
+        }
+    });
+
+    let (peer_builder, mut namespace, _) = new_builder();
+    let mut peer = peer_builder
+        .build_with_transport(TokioTcpConfig::new(), executor)
+        .await
+        .unwrap();
+    assert_eq!(namespace.namespace(), "data");
+    peer.add_address(remote_id, remote_addr).await;
+    peer.connect_peer(remote_id).await.unwrap();
+
+    // Requests within the namespace use the connection of the network.
+    let response = namespace.send_request(remote_id, "ping".into()).await.unwrap();
+    assert_eq!(response, "PING");
+    let connections = peer.established_connections().await;
+    assert_eq!(connections.len(), 1);
+    assert_eq!(connections[0].1.len(), 1);
+}
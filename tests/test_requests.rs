@@ -0,0 +1,374 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+mod common;
+
+use common::{build, builder};
+
+use std::{io, time::Duration};
+
+use p2p::{
+    firewall::FirewallRules, ChannelSinkConfig, Codec, EventChannel, InboundFailure, NetworkBuilder, NetworkEvent,
+    OutboundFailure, PeerId, RemoteError, RequestId, RequestMetadata, RequestMiddleware, ResponseHook,
+};
+
+use futures::{channel::mpsc, future, StreamExt};
+use serde::{Deserialize, Serialize};
+
+// Middleware that fails either all outbound or all inbound requests.
+struct FailRequests {
+    outbound: bool,
+}
+
+impl RequestMiddleware<()> for FailRequests {
+    fn on_outbound(&self, _: &PeerId, _: &mut ()) -> Result<(), String> {
+        match self.outbound {
+            true => Err("outbound".into()),
+            false => Ok(()),
+        }
+    }
+
+    fn on_inbound(&self, _: &PeerId, _: &mut ()) -> Result<(), String> {
+        match self.outbound {
+            true => Ok(()),
+            false => Err("inbound".into()),
+        }
+    }
+}
+
+#[tokio::test]
+async fn middleware_fails_requests() {
+    let mut peer = build(builder().with_middleware(FailRequests { outbound: true })).await;
+    let mut remote = build(builder().with_middleware(FailRequests { outbound: false })).await;
+    let remote_id = remote.peer_id();
+    let remote_addr = remote
+        .start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+    peer.add_address(remote_id, remote_addr.clone()).await;
+    let err = peer.send_request(remote_id, ()).await.unwrap_err();
+    assert_eq!(err, OutboundFailure::Intercepted("outbound".into()));
+
+    let mut other = build(builder()).await;
+    other.add_address(remote_id, remote_addr).await;
+    let err = other.send_request(remote_id, ()).await.unwrap_err();
+    assert_eq!(err, OutboundFailure::Remote(RemoteError::NotPermitted));
+}
+
+// Hook that fails all responses, e.g. because their signature is invalid.
+struct RejectResponses;
+
+impl ResponseHook<()> for RejectResponses {
+    fn on_response(&self, _: &PeerId, _: RequestId, _: &mut ()) -> Result<(), String> {
+        Err("invalid signature".into())
+    }
+}
+
+#[tokio::test]
+async fn response_hook_fails_response() {
+    let (dummy_fw_tx, _) = mpsc::channel(10);
+    let (request_channel, rq_rx) = EventChannel::new(10, ChannelSinkConfig::Block);
+    let mut remote = build(NetworkBuilder::new(
+        dummy_fw_tx,
+        request_channel,
+        None,
+        FirewallRules::allow_all(),
+    ))
+    .await;
+    tokio::spawn(rq_rx.for_each(|rq| {
+        let _ = rq.responder.respond(());
+        future::ready(())
+    }));
+    let remote_id = remote.peer_id();
+    let remote_addr = remote
+        .start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let mut peer = build(builder()).await;
+    peer.add_address(remote_id, remote_addr.clone()).await;
+    assert!(peer.send_request(remote_id, ()).await.is_ok());
+
+    let mut peer = build(builder().with_response_hook(RejectResponses)).await;
+    peer.add_address(remote_id, remote_addr).await;
+    let err = peer.send_request(remote_id, ()).await.unwrap_err();
+    assert_eq!(err, OutboundFailure::Intercepted("invalid signature".into()));
+}
+
+// Codec that sends strings as raw UTF-8 bytes instead of JSON strings.
+struct Utf8Codec;
+
+impl Codec<String, String> for Utf8Codec {
+    fn name(&self) -> &str {
+        "utf8"
+    }
+
+    fn encode_request(&self, request: &String) -> Result<Vec<u8>, io::Error> {
+        Ok(request.as_bytes().to_vec())
+    }
+
+    fn decode_request(&self, bytes: &[u8]) -> Result<String, io::Error> {
+        String::from_utf8(bytes.to_vec()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn encode_response(&self, response: &String) -> Result<Vec<u8>, io::Error> {
+        Ok(response.as_bytes().to_vec())
+    }
+
+    fn decode_response(&self, bytes: &[u8]) -> Result<String, io::Error> {
+        String::from_utf8(bytes.to_vec()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+#[tokio::test]
+async fn custom_codec() {
+    let new_builder = || {
+        let (dummy_fw_tx, _) = mpsc::channel(10);
+        let (request_channel, rq_rx) = EventChannel::new(10, ChannelSinkConfig::Block);
+        let builder =
+            NetworkBuilder::<String, String>::new(dummy_fw_tx, request_channel, None, FirewallRules::allow_all())
+                .with_mdns_support(false)
+                .with_codec(Utf8Codec);
+        (builder, rq_rx)
+    };
+    let (remote_builder, rq_rx) = new_builder();
+    let mut remote = build(remote_builder).await;
+    tokio::spawn(rq_rx.for_each(|rq| {
+        let _ = rq.responder.respond(rq.request.to_uppercase());
+        future::ready(())
+    }));
+    let remote_id = remote.peer_id();
+    let remote_addr = remote
+        .start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let (peer_builder, _) = new_builder();
+    let mut peer = build(peer_builder).await;
+    peer.add_address(remote_id, remote_addr.clone()).await;
+    // Neither the request nor the response are valid JSON.
+    let response = peer.send_request(remote_id, "ping".into()).await.unwrap();
+    assert_eq!(response, "PING");
+    // The request in an envelope is encoded with the codec as well.
+    let metadata: RequestMetadata = [("trace-id".to_string(), "abc".to_string())].into_iter().collect();
+    let response = peer
+        .send_request_with_metadata(remote_id, "meta".into(), metadata)
+        .await
+        .unwrap();
+    assert_eq!(response, "META");
+
+    // Peers without the codec fail to negotiate a protocol.
+    let (dummy_fw_tx, _) = mpsc::channel(10);
+    let (request_channel, _) = EventChannel::new(10, ChannelSinkConfig::Block);
+    let json_builder =
+        NetworkBuilder::<String, String>::new(dummy_fw_tx, request_channel, None, FirewallRules::allow_all())
+            .with_mdns_support(false);
+    let mut json_peer = build(json_builder).await;
+    json_peer.add_address(remote_id, remote_addr).await;
+    let err = json_peer.send_request(remote_id, "ping".into()).await.unwrap_err();
+    assert!(matches!(err, OutboundFailure::UnsupportedProtocols { .. }));
+}
+
+#[tokio::test]
+async fn reject_request() {
+    let (dummy_fw_tx, _) = mpsc::channel(10);
+    let (request_channel, rq_rx) = EventChannel::new(10, ChannelSinkConfig::Block);
+    let mut remote = build(NetworkBuilder::new(
+        dummy_fw_tx,
+        request_channel,
+        None,
+        FirewallRules::allow_all(),
+    ))
+    .await;
+    tokio::spawn(rq_rx.for_each(|rq| {
+        assert!(rq.responder.is_waiting());
+        assert!(rq.responder.time_left().is_some());
+        rq.responder.reject("unknown resource");
+        future::ready(())
+    }));
+    let remote_id = remote.peer_id();
+    let remote_addr = remote
+        .start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let mut peer = build(builder()).await;
+    peer.add_address(remote_id, remote_addr).await;
+    let err = peer.send_request(remote_id, ()).await.unwrap_err();
+    assert_eq!(
+        err,
+        OutboundFailure::Remote(RemoteError::Rejected("unknown resource".into()))
+    );
+    // Locally, the rejection is not reported as failure.
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let stats = remote.stats().await;
+    assert_eq!(stats.inbound_failures.response_omitted, 0);
+}
+
+#[tokio::test]
+async fn unknown_request_variant() {
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    enum RequestV1 {
+        Ping,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    enum RequestV2 {
+        Ping,
+        Status,
+    }
+
+    let (dummy_fw_tx, _) = mpsc::channel(10);
+    let (request_channel, rq_rx) = EventChannel::new(10, ChannelSinkConfig::Block);
+    let (event_channel, event_rx) = EventChannel::new(10, ChannelSinkConfig::Block);
+    let remote_builder = NetworkBuilder::<RequestV1, ()>::new(
+        dummy_fw_tx,
+        request_channel,
+        Some(event_channel),
+        FirewallRules::allow_all(),
+    );
+    let mut remote = build(remote_builder).await;
+    tokio::spawn(rq_rx.for_each(|rq| {
+        let _ = rq.responder.respond(());
+        future::ready(())
+    }));
+    let remote_id = remote.peer_id();
+    let remote_addr = remote
+        .start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let (dummy_fw_tx, _) = mpsc::channel(10);
+    let (dummy_rq_channel, _) = EventChannel::new(10, ChannelSinkConfig::DropLatest);
+    let builder = NetworkBuilder::<RequestV2, ()>::new(dummy_fw_tx, dummy_rq_channel, None, FirewallRules::allow_all());
+    let mut peer = build(builder).await;
+    let peer_id = peer.peer_id();
+    peer.add_address(remote_id, remote_addr).await;
+
+    let err = peer.send_request(remote_id, RequestV2::Status).await.unwrap_err();
+    assert_eq!(err, OutboundFailure::Remote(RemoteError::UnknownRequest));
+    let mut failures = event_rx.filter(|ev| future::ready(matches!(ev, NetworkEvent::InboundFailure { .. })));
+    match failures.next().await.unwrap() {
+        NetworkEvent::InboundFailure { peer, failure, .. } => {
+            assert_eq!(peer, peer_id);
+            assert_eq!(failure, InboundFailure::UnknownRequest);
+        }
+        _ => unreachable!(),
+    }
+
+    // The connection is kept for requests of known types.
+    assert!(peer.send_request(remote_id, RequestV2::Ping).await.is_ok());
+    assert!(peer.is_connected(remote_id).await);
+}
+
+#[tokio::test]
+async fn busy_retry_after() {
+    let (dummy_fw_tx, _) = mpsc::channel(10);
+    // The channel is full after the first request, since it is never read.
+    let (request_channel, _rq_rx) = EventChannel::new(0, ChannelSinkConfig::Block);
+    let retry_after = Duration::from_secs(5);
+    let builder = NetworkBuilder::new(dummy_fw_tx, request_channel, None, FirewallRules::allow_all())
+        .with_inbound_backpressure(true)
+        .with_busy_retry_after(retry_after);
+    let mut remote = build(builder).await;
+    let remote_id = remote.peer_id();
+    let remote_addr = remote
+        .start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let mut peer = build(builder().with_request_timeout(Duration::from_secs(10))).await;
+    peer.add_address(remote_id, remote_addr).await;
+    let mut first_peer = peer.clone();
+    let first = tokio::spawn(async move { first_peer.send_request(remote_id, ()).await });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let err = peer.send_request(remote_id, ()).await.unwrap_err();
+    assert_eq!(err, OutboundFailure::Remote(RemoteError::Busy { retry_after }));
+    assert_eq!(err.retry_after(), Some(retry_after));
+    first.abort();
+}
+
+#[tokio::test]
+async fn request_metadata() {
+    let (dummy_fw_tx, _) = mpsc::channel(10);
+    let (request_channel, mut rq_rx) = EventChannel::new(10, ChannelSinkConfig::Block);
+    let mut remote = build(NetworkBuilder::new(
+        dummy_fw_tx,
+        request_channel,
+        None,
+        FirewallRules::allow_all(),
+    ))
+    .await;
+    let remote_id = remote.peer_id();
+    let remote_addr = remote
+        .start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let mut peer = build(builder()).await;
+    let peer_id = peer.peer_id();
+    peer.add_address(remote_id, remote_addr).await;
+    let metadata: RequestMetadata = [("trace-id".to_string(), "abc".to_string())].into_iter().collect();
+    let mut peer_clone = peer.clone();
+    let request = peer.send_request_with_metadata(remote_id, (), metadata.clone());
+    let respond = async {
+        let rq = rq_rx.next().await.unwrap();
+        assert_eq!(rq.metadata, metadata);
+        // The id is scoped to the sender.
+        assert_eq!(rq.global_id.map(|id| id.origin), Some(peer_id));
+        rq.responder.respond(()).unwrap();
+
+        // Requests without metadata have empty metadata.
+        let rq = rq_rx.next().await.unwrap();
+        assert!(rq.metadata.is_empty());
+        assert!(rq.global_id.is_none());
+        rq.responder.respond(()).unwrap();
+    };
+    let requests = async {
+        assert!(request.await.is_ok());
+        assert!(peer_clone.send_request(remote_id, ()).await.is_ok());
+    };
+    future::join(requests, respond).await;
+}
+
+#[tokio::test]
+async fn ordered_delivery() {
+    let (dummy_fw_tx, _) = mpsc::channel(10);
+    let (request_channel, mut rq_rx) = EventChannel::new(10, ChannelSinkConfig::Block);
+    let remote_builder =
+        NetworkBuilder::<String, String>::new(dummy_fw_tx, request_channel, None, FirewallRules::allow_all());
+    let mut remote = build(remote_builder).await;
+    let remote_id = remote.peer_id();
+    let remote_addr = remote
+        .start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let (dummy_fw_tx, _) = mpsc::channel(10);
+    let (dummy_rq_channel, _) = EventChannel::new(10, ChannelSinkConfig::DropLatest);
+    let peer_builder =
+        NetworkBuilder::<String, String>::new(dummy_fw_tx, dummy_rq_channel, None, FirewallRules::allow_all())
+            .with_ordered_delivery(true);
+    let mut peer = build(peer_builder).await;
+    peer.add_address(remote_id, remote_addr).await;
+
+    let requests = future::join_all((0..3).map(|i| {
+        let mut peer = peer.clone();
+        async move { peer.send_request(remote_id, i.to_string()).await }
+    }));
+    // The requests arrive in order, without waiting for the responses to the previous ones.
+    let respond = async {
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            received.push(rq_rx.next().await.unwrap());
+        }
+        let order: Vec<_> = received.iter().map(|rq| rq.request.clone()).collect();
+        assert_eq!(order, ["0", "1", "2"]);
+        for rq in received {
+            let _ = rq.responder.respond(rq.request);
+        }
+    };
+    let (responses, ()) = future::join(requests, respond).await;
+    assert!(responses.into_iter().all(|r| r.is_ok()));
+}
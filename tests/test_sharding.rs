@@ -0,0 +1,101 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+mod common;
+
+use common::{build, builder};
+
+use std::{io, num::NonZeroUsize, time::Duration};
+
+use p2p::{firewall::FirewallRules, ChannelSinkConfig, EventChannel, InitKeypair, Shard, ShardedNetwork};
+
+use futures::{channel::mpsc, future, FutureExt, StreamExt};
+
+#[tokio::test]
+async fn sharded_network() {
+    let keys = libp2p::identity::Keypair::generate_ed25519();
+    let (rq_channel, mut rq_rx) = EventChannel::new(10, ChannelSinkConfig::Block);
+    let build_shard = |shard: Shard<(), ()>| {
+        let (dummy_fw_tx, _) = mpsc::channel(10);
+        let builder = shard
+            .builder(dummy_fw_tx, FirewallRules::allow_all())
+            .with_mdns_support(false)
+            .with_keys(InitKeypair::IdKeys(keys.clone()));
+        build(builder).map(Ok)
+    };
+    let mut sharded = ShardedNetwork::build(NonZeroUsize::new(2).unwrap(), rq_channel, None, build_shard)
+        .await
+        .unwrap();
+    assert_eq!(sharded.peer_id(), keys.public().to_peer_id());
+    let addr = sharded
+        .start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+
+    // Inbound connections are handed to the shard of the remote, and its requests are received on the shared channel.
+    let mut remotes = Vec::new();
+    while remotes.len() < 4 {
+        let mut remote = build(builder().with_mdns_support(false)).await;
+        remote.add_address(sharded.peer_id(), addr.clone()).await;
+        remote.connect_peer(sharded.peer_id()).await.unwrap();
+        remotes.push(remote);
+    }
+    for remote in remotes.iter_mut() {
+        let remote_id = remote.peer_id();
+        let index = sharded.shard_index(&remote_id);
+        while !sharded.is_connected(remote_id).await {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        for (i, shard) in sharded.shards_mut().iter_mut().enumerate() {
+            assert_eq!(shard.is_connected(remote_id).await, i == index);
+        }
+
+        let peer_id = sharded.peer_id();
+        let respond = async {
+            let rq = rq_rx.next().await.unwrap();
+            assert_eq!(rq.peer, remote_id);
+            let _ = rq.responder.respond(());
+        };
+        let (res, ()) = future::join(remote.send_request(peer_id, ()), respond).await;
+        assert!(res.is_ok());
+    }
+
+    // Outbound connections are established by the shard of the remote.
+    let mut peer = build(builder().with_mdns_support(false)).await;
+    let peer_id = peer.peer_id();
+    let peer_addr = peer
+        .start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+    sharded.add_address(peer_id, peer_addr).await;
+    sharded.connect_peer(peer_id).await.unwrap();
+    let index = sharded.shard_index(&peer_id);
+    assert!(sharded.shards_mut()[index].is_connected(peer_id).await);
+
+    // Shards with different identities are rejected.
+    let (rq_channel, _) = EventChannel::new(10, ChannelSinkConfig::Block);
+    let res = ShardedNetwork::build(
+        NonZeroUsize::new(2).unwrap(),
+        rq_channel,
+        None,
+        |shard: Shard<(), ()>| {
+            let (dummy_fw_tx, _) = mpsc::channel(10);
+            build(
+                shard
+                    .builder(dummy_fw_tx, FirewallRules::allow_all())
+                    .with_mdns_support(false),
+            )
+            .map(Ok)
+        },
+    )
+    .await;
+    assert!(res.is_err());
+
+    // Shards that are not built from their handle are rejected.
+    let (rq_channel, _) = EventChannel::new(10, ChannelSinkConfig::Block);
+    let res = ShardedNetwork::build(NonZeroUsize::new(1).unwrap(), rq_channel, None, |_: Shard<(), ()>| {
+        build(builder().with_mdns_support(false)).map(Ok)
+    })
+    .await;
+    assert_eq!(res.err().unwrap().kind(), io::ErrorKind::InvalidInput);
+}
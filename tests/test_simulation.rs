@@ -0,0 +1,137 @@
+// Copyright 2020-2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+mod simulation;
+
+use futures::{channel::mpsc, future, StreamExt};
+use p2p::{firewall::FirewallRules, ChannelSinkConfig, EventChannel, NetworkBuilder, RequestStatus};
+use serde::{Deserialize, Serialize};
+use simulation::{LinkConfig, SimNetwork, SimNode};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Request;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Response;
+
+type TestNode = SimNode<Request, Response>;
+
+// Spawn a node that answers all inbound requests.
+async fn spawn_node(sim: &SimNetwork) -> TestNode {
+    let (dummy_fw_tx, _) = mpsc::channel(10);
+    let (request_channel, rq_rx) = EventChannel::new(10, ChannelSinkConfig::Block);
+    let builder = NetworkBuilder::new(dummy_fw_tx, request_channel, None, FirewallRules::allow_all())
+        .with_mdns_support(false)
+        .with_request_timeout(Duration::from_secs(5));
+    tokio::spawn(rq_rx.for_each(|rq| {
+        let _ = rq.responder.respond(Response);
+        future::ready(())
+    }));
+    sim.spawn(builder).await
+}
+
+#[tokio::test]
+async fn request_with_latency() {
+    let sim = SimNetwork::new(1);
+    let latency = Duration::from_millis(20);
+    sim.set_default_link(LinkConfig {
+        latency,
+        jitter: Duration::from_millis(10),
+        loss: 0.0,
+    });
+    let mut a = spawn_node(&sim).await;
+    let b = spawn_node(&sim).await;
+    a.add_peer(&b).await;
+
+    let start = Instant::now();
+    let res = a.network.send_request(b.peer_id(), Request).await;
+    assert_eq!(res.unwrap(), Response);
+    // At least the request and the response were delayed.
+    assert!(start.elapsed() >= 2 * latency);
+}
+
+#[tokio::test]
+async fn measured_rtt() {
+    let sim = SimNetwork::new(4);
+    let latency = Duration::from_millis(20);
+    sim.set_default_link(LinkConfig {
+        latency,
+        jitter: Duration::ZERO,
+        loss: 0.0,
+    });
+    let mut a = spawn_node(&sim).await;
+    let b = spawn_node(&sim).await;
+    a.add_peer(&b).await;
+
+    // The first request includes dialing the remote, which is not part of the round-trip time.
+    let start = Instant::now();
+    let res = a.network.send_request_timed(b.peer_id(), Request).await.unwrap();
+    assert_eq!(res.response, Response);
+    assert!(res.rtt >= latency);
+    assert!(res.rtt < start.elapsed());
+}
+
+#[tokio::test]
+async fn request_status() {
+    let sim = SimNetwork::new(5);
+    let mut a = spawn_node(&sim).await;
+    let b = spawn_node(&sim).await;
+    a.add_peer(&b).await;
+
+    let mut handle = a.network.send_request_tracked(b.peer_id(), Request).await;
+    let mut statuses = Vec::new();
+    while let Some(status) = handle.status_changed().await {
+        statuses.push(status);
+    }
+    assert_eq!(
+        statuses,
+        vec![
+            RequestStatus::Dialing,
+            RequestStatus::Sent,
+            RequestStatus::Written,
+            RequestStatus::Responded
+        ]
+    );
+    assert_eq!(handle.status(), &RequestStatus::Responded);
+    assert_eq!(handle.response().await.unwrap().response, Response);
+}
+
+#[tokio::test]
+async fn partition_and_heal() {
+    let sim = SimNetwork::new(2);
+    let mut a = spawn_node(&sim).await;
+    let b = spawn_node(&sim).await;
+    a.add_peer(&b).await;
+    assert!(a.network.send_request(b.peer_id(), Request).await.is_ok());
+
+    sim.partition(&[a.id], &[b.id]);
+    assert!(a.network.send_request(b.peer_id(), Request).await.is_err());
+
+    sim.heal();
+    assert!(a.network.send_request(b.peer_id(), Request).await.is_ok());
+}
+
+#[tokio::test]
+async fn lossy_link() {
+    let sim = SimNetwork::new(3);
+    let mut a = spawn_node(&sim).await;
+    let b = spawn_node(&sim).await;
+    let c = spawn_node(&sim).await;
+    a.add_peer(&b).await;
+    a.add_peer(&c).await;
+    sim.set_link(
+        a.id,
+        b.id,
+        LinkConfig {
+            loss: 1.0,
+            ..Default::default()
+        },
+    );
+
+    assert!(a.network.send_request(b.peer_id(), Request).await.is_err());
+    assert!(a.network.send_request(c.peer_id(), Request).await.is_ok());
+
+    sim.set_link(a.id, b.id, LinkConfig::default());
+    assert!(a.network.send_request(b.peer_id(), Request).await.is_ok());
+}